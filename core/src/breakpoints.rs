@@ -32,16 +32,35 @@
 
 #[allow(dead_code)]
 pub enum BreakPointType {
-    StepOver(u32),       // Breakpoint on next decoded instruction
-    Execute(u16, u16),   // Breakpoint on CS:IP
-    ExecuteOffset(u16),  // Breakpoint on *::IP
-    ExecuteFlat(u32),    // Breakpoint on CS<<4+IP
-    MemAccess(u16, u16), // Breakpoint on memory access, seg::offset
-    MemAccessFlat(u32),  // Breakpoint on memory access, seg<<4+offset
-    Interrupt(u8),       // Breakpoint on interrupt #
-    IoAccess(u16),       // Breakpoint on I/O port access
-    StartWatch(u32),     // Start stopwatch at address
-    StopWatch(u32),      // Stop stopwatch at address
+    StepOver(u32),                     // Breakpoint on next decoded instruction
+    Execute(u16, u16),                 // Breakpoint on CS:IP
+    ExecuteOffset(u16),                // Breakpoint on *::IP
+    ExecuteFlat(u32),                  // Breakpoint on CS<<4+IP
+    MemAccess(u16, u16),               // Breakpoint on memory access, seg::offset
+    MemAccessFlat(u32),                // Breakpoint on memory access, seg<<4+offset
+    MemWatchFlat(u32, u32, WatchMode), // Watchpoint on flat address range start..end
+    Interrupt(u8),                     // Breakpoint on interrupt #
+    IoAccess(u16),                     // Breakpoint on I/O port access
+    StartWatch(u32),                   // Start stopwatch at address
+    StopWatch(u32),                    // Stop stopwatch at address
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum WatchMode {
+    Read,
+    Write,
+    #[default]
+    Access,
+}
+
+impl WatchMode {
+    pub fn matches(&self, write: bool) -> bool {
+        match self {
+            WatchMode::Read => !write,
+            WatchMode::Write => write,
+            WatchMode::Access => true,
+        }
+    }
 }
 
 pub enum StopWatchType {
@@ -36,8 +36,8 @@
 
 #![allow(dead_code)]
 
-use anyhow::Error;
-use fxhash::FxHashMap;
+use anyhow::{anyhow, Error};
+use fxhash::{FxHashMap, FxHashSet};
 use ringbuf::Producer;
 use std::{collections::VecDeque, fmt, io::Write, path::Path};
 
@@ -50,6 +50,7 @@ use crate::{
         VideoCardDispatch,
         VideoCardId,
         VideoCardInterface,
+        VideoOption,
         VideoType,
     },
     devices::{
@@ -67,8 +68,9 @@ use crate::{
     },
     machine::{KeybufferEntry, MachineCheckpoint, MachinePatch},
     machine_config::{normalize_conventional_memory, MachineConfiguration, MachineDescriptor},
-    machine_types::{HardDiskControllerType, SerialControllerType, SerialMouseType},
+    machine_types::{BusMouseType, HardDiskControllerType, LptDacType, SerialControllerType, SoundBlasterType},
     memerror::MemError,
+    network,
     syntax_token::SyntaxToken,
     tracelogger::TraceLogger,
     updatable::*,
@@ -79,18 +81,33 @@ use crate::devices::ega::{self, EGACard};
 #[cfg(feature = "vga")]
 use crate::devices::vga::{self, VGACard};
 use crate::{
+    cd_image::CD_AUDIO_RATE,
     cpu_common::{CpuDispatch, CpuType},
     device_traits::videocard::VideoCardSubType,
     devices::{
         a0::A0Register,
+        atapi_cdrom::AtapiCdrom,
         cartridge_slots::CartridgeSlot,
+        drive_sound::DRIVE_SOUND_RATE,
         game_port::GamePort,
+        inport_mouse::InportMouse,
         lotech_ems::LotechEmsCard,
         lpt_card::ParallelController,
+        lpt_dac::{LptDac, DAC_OUTPUT_RATE},
+        lpt_port::ParallelPort,
+        modem::VirtualModem,
+        mpu401::Mpu401,
+        ne2000::Ne2000,
+        printer::Printer,
+        sn76489::{Sn76489, SN76489_OUTPUT_RATE},
+        sound_blaster::{SoundBlaster, SB_OUTPUT_RATE},
         tga,
         tga::TGACard,
+        xtide::{IdeRegisterLayout, XtIdeController},
     },
     machine_types::{EmsType, EmsType::LoTech2MB, FdcType, MachineType},
+    savestate::{SaveStateError, StateReader, StateWriter},
+    sound_mixer::SoundMixer,
     syntax_token::SyntaxFormatType,
 };
 
@@ -126,7 +143,7 @@ pub struct TimingTableEntry {
     pub us: f64,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ClockFactor {
     Divisor(u8),
     Multiplier(u8),
@@ -280,6 +297,12 @@ pub enum IoDeviceType {
     FloppyController,
     HardDiskController,
     Mouse,
+    BusMouse,
+    SoundBlaster,
+    Sn76489,
+    Mpu401,
+    Ne2000,
+    XtIde,
     Ems,
     GamePort,
     Video(VideoCardId),
@@ -372,9 +395,13 @@ pub struct BusInterface {
     keyboard_type: KeyboardType,
     keyboard: Option<Keyboard>,
     conventional_size: usize,
+    conventional_wait_states: u32,
     memory: Vec<u8>,
     memory_mask: Vec<u8>,
     open_bus_byte: u8,
+    log_unmapped: bool,
+    unmapped_touched: FxHashSet<usize>, // Blocks of unmapped memory that have been read since reset
+    memory_holes: Vec<(usize, usize)>,
     desc_vec: Vec<MemRangeDescriptor>,
     mmio_map: Vec<(MemRangeDescriptor, MmioDeviceType)>,
     mmio_map_fast: [MmioDeviceType; MMIO_MAP_LEN],
@@ -400,6 +427,13 @@ pub struct BusInterface {
     fdc: Option<FloppyController>,
     hdc: Option<HardDiskController>,
     mouse: Option<Mouse>,
+    bus_mouse: Option<InportMouse>,
+    sound_blaster: Option<SoundBlaster>,
+    sn76489: Option<Sn76489>,
+    mpu401: Option<Mpu401>,
+    ne2000: Option<Ne2000>,
+    xtide: Option<XtIdeController>,
+    modem: Option<VirtualModem>,
     ems: Option<LotechEmsCard>,
     cart_slot: Option<CartridgeSlot>,
     game_port: Option<GamePort>,
@@ -420,6 +454,8 @@ pub struct BusInterface {
     refresh_active: bool,
 
     terminal_port: Option<u16>,
+    exit_port: Option<u16>,
+    exit_code: Option<u8>,
 }
 
 #[macro_export]
@@ -542,9 +578,13 @@ impl Default for BusInterface {
             keyboard_type: KeyboardType::ModelF,
             keyboard: None,
             conventional_size: ADDRESS_SPACE,
+            conventional_wait_states: DEFAULT_WAIT_STATES,
             memory: vec![0; ADDRESS_SPACE],
             memory_mask: vec![0; ADDRESS_SPACE],
             open_bus_byte: 0xFF,
+            log_unmapped: false,
+            unmapped_touched: FxHashSet::default(),
+            memory_holes: Vec::new(),
             desc_vec: Vec::new(),
             mmio_map: Vec::new(),
             mmio_map_fast: [MmioDeviceType::Memory; MMIO_MAP_LEN],
@@ -570,6 +610,13 @@ impl Default for BusInterface {
             fdc: None,
             hdc: None,
             mouse: None,
+            bus_mouse: None,
+            sound_blaster: None,
+            sn76489: None,
+            mpu401: None,
+            ne2000: None,
+            xtide: None,
+            modem: None,
             ems: None,
             cart_slot: None,
             game_port: None,
@@ -589,6 +636,8 @@ impl Default for BusInterface {
             refresh_active: false,
 
             terminal_port: None,
+            exit_port: None,
+            exit_code: None,
         }
     }
 }
@@ -781,6 +830,23 @@ impl BusInterface {
         Ok(())
     }
 
+    /// Set or clear write protection for the specified range of memory. Guest writes to a protected range are
+    /// silently ignored, the same as writes to ROM. patch_from() can still be used to modify protected memory.
+    pub fn set_write_protect(&mut self, location: usize, size: usize, protect: bool) -> Result<(), bool> {
+        if location + size > self.memory_mask.len() {
+            log::error!("write protect out of range: {} len: {}", location, size);
+            return Err(false);
+        }
+
+        for dst in self.memory_mask[location..location + size].iter_mut() {
+            match protect {
+                true => *dst |= MEM_ROM_BIT,
+                false => *dst &= !MEM_ROM_BIT,
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_slice_at(&self, start: usize, len: usize) -> &[u8] {
         &self.memory[start..start + len]
     }
@@ -816,9 +882,45 @@ impl BusInterface {
         for byte_ref in &mut self.memory[0..self.conventional_size] {
             *byte_ref = 0;
         }
+        // Memory holes read as open bus, even within conventional memory
+        for &(start, end) in &self.memory_holes {
+            self.memory[start..end].fill(self.open_bus_byte);
+        }
 
         // Reset IO statistics
         self.io_stats.clear();
+        self.unmapped_touched.clear();
+    }
+
+    /// Enable or disable logging of the first read from each unmapped memory block and IO port.
+    pub fn set_log_unmapped(&mut self, state: bool) {
+        self.log_unmapped = state;
+    }
+
+    /// Set the exit port. A write to this port requests that the machine stop, with the byte written
+    /// as the exit code.
+    pub fn set_exit_port(&mut self, port: Option<u16>) {
+        if let Some(port) = port {
+            log::debug!("Exit port set to: {:04X}", port);
+        }
+        self.exit_port = port;
+    }
+
+    /// Take the exit code written to the exit port, if a write has occurred since the last call.
+    pub fn take_exit_code(&mut self) -> Option<u8> {
+        self.exit_code.take()
+    }
+
+    /// Log the first read from an unmapped block of memory. Memory is tracked in blocks of the MMIO map size
+    /// to keep the log readable and the lookup cheap.
+    fn log_unmapped_read(&mut self, address: usize) {
+        if self.unmapped_touched.insert(address >> MMIO_MAP_SHIFT) {
+            log::warn!(
+                "Read from unmapped memory at [{:05X}], returning open bus value {:02X}",
+                address,
+                self.open_bus_byte
+            );
+        }
     }
 
     pub fn reset(&mut self) {
@@ -862,7 +964,10 @@ impl BusInterface {
     pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
-                // Address is not mapped.
+                // Address is not mapped. Apply the configured wait states for conventional memory.
+                if address < self.conventional_size {
+                    return Ok(self.conventional_wait_states);
+                }
                 return Ok(DEFAULT_WAIT_STATES);
             }
             else {
@@ -914,7 +1019,10 @@ impl BusInterface {
     pub fn get_write_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
-                // Address is not mapped.
+                // Address is not mapped. Apply the configured wait states for conventional memory.
+                if address < self.conventional_size {
+                    return Ok(self.conventional_wait_states);
+                }
                 return Ok(DEFAULT_WAIT_STATES);
             }
             else {
@@ -968,6 +1076,12 @@ impl BusInterface {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
+                if self.log_unmapped
+                    && address >= self.conventional_size
+                    && self.memory_mask[address] & MEM_ROM_BIT == 0
+                {
+                    self.log_unmapped_read(address);
+                }
                 let data: u8 = self.memory[address];
                 return Ok((data, 0));
             }
@@ -1029,7 +1143,11 @@ impl BusInterface {
                     }
                     _ => {}
                 }
-                return Err(MemError::MmioError);
+                // No device claimed this address. Return the open bus value.
+                if self.log_unmapped {
+                    self.log_unmapped_read(address);
+                }
+                return Ok((self.open_bus_byte, 0));
             }
         }
         Err(MemError::ReadOutOfBoundsError)
@@ -1044,6 +1162,23 @@ impl BusInterface {
         }
     }
 
+    /// Copy the entire address space into `buf` as it would be read by peek_u8(), including the
+    /// contents of memory-mapped devices.
+    pub fn peek_address_space(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.extend_from_slice(&self.memory);
+        for (page, device) in self.mmio_map_fast.iter().enumerate() {
+            if !matches!(device, MmioDeviceType::None | MmioDeviceType::Memory) {
+                let start = page << MMIO_MAP_SHIFT;
+                for (address, byte) in buf.iter_mut().enumerate().skip(start).take(MMIO_MAP_SIZE) {
+                    if let Ok(data) = self.peek_u8(address) {
+                        *byte = data;
+                    }
+                }
+            }
+        }
+    }
+
     pub fn peek_u8(&self, address: usize) -> Result<u8, MemError> {
         if address < self.memory.len() {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
@@ -1107,6 +1242,12 @@ impl BusInterface {
         if address < self.memory.len() - 1 {
             if self.memory_mask[address] & MEM_MMIO_BIT == 0 {
                 // Address is not mapped.
+                if self.log_unmapped
+                    && address >= self.conventional_size
+                    && self.memory_mask[address] & MEM_ROM_BIT == 0
+                {
+                    self.log_unmapped_read(address);
+                }
                 let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
                 return Ok((w, DEFAULT_WAIT_STATES));
             }
@@ -1156,8 +1297,11 @@ impl BusInterface {
                     }
                     _ => {}
                 }
-                return Ok((0xFFFF, 0));
-                //return Err(MemError::MmioError);
+                // No device claimed this address. Return the open bus value.
+                if self.log_unmapped {
+                    self.log_unmapped_read(address);
+                }
+                return Ok(((self.open_bus_byte as u16) << 8 | self.open_bus_byte as u16, 0));
             }
         }
         Err(MemError::ReadOutOfBoundsError)
@@ -1740,8 +1884,28 @@ impl BusInterface {
         // Get normalized conventional memory and set it.
         let conventional_memory = normalize_conventional_memory(machine_config)?;
         self.set_conventional_size(conventional_memory as usize);
+        self.conventional_wait_states = machine_config.memory.conventional.wait_states;
         self.open_bus_byte = machine_desc.open_bus_byte;
 
+        // Install memory holes. Holes are write protected so that writes are ignored.
+        self.memory_holes.clear();
+        for hole in machine_config.memory.holes.iter() {
+            let start = hole.address as usize;
+            let end = start + hole.size as usize;
+            if end > self.memory.len() {
+                return Err(anyhow!(
+                    "Memory hole out of range: {:05X} size: {:X}",
+                    hole.address,
+                    hole.size
+                ));
+            }
+            log::debug!("Installing memory hole at [{:05X}-{:05X})", start, end);
+            _ = self.set_write_protect(start, hole.size as usize, true);
+            let open_bus_byte = self.open_bus_byte;
+            self.memory[start..end].fill(open_bus_byte);
+            self.memory_holes.push((start, end));
+        }
+
         // Create the A0 register if specified.
         // TODO: Wrap this up in a motherboard device type?
         if let Some(a0_type) = machine_desc.a0 {
@@ -1765,6 +1929,7 @@ impl BusInterface {
                 false,
                 video_types,
                 num_floppies,
+                machine_config.cpu.as_ref().is_some_and(|cpu| cpu.fpu),
             ));
             // Add PPI ports to io_map
 
@@ -1829,7 +1994,10 @@ impl BusInterface {
             // Create the correct kind of FDC (currently only NEC supported)
             match fdc_type {
                 FdcType::IbmNec | FdcType::IbmPCJrNec => {
-                    let fdc = FloppyController::new(fdc_type, floppy_ct);
+                    let mut fdc = FloppyController::new(fdc_type, floppy_ct);
+                    for (i, drive) in fdc_config.drive.iter().enumerate() {
+                        fdc.set_drive_type(i, drive.fd_type);
+                    }
                     // Add FDC ports to io_map
                     add_io_device!(self, fdc, IoDeviceType::FloppyController);
                     self.fdc = Some(fdc);
@@ -1847,6 +2015,19 @@ impl BusInterface {
                     add_io_device!(self, hdc, IoDeviceType::HardDiskController);
                     self.hdc = Some(hdc);
                 }
+                HardDiskControllerType::XtIde | HardDiskControllerType::Ata8Bit => {
+                    let layout = match hdc_config.hdc_type {
+                        HardDiskControllerType::XtIde => IdeRegisterLayout::XtIde,
+                        _ => IdeRegisterLayout::Ata8Bit,
+                    };
+                    let mut xtide = XtIdeController::new(layout, hdc_config.io_base, hdc_config.irq);
+                    if let Some(device_id) = hdc_config.cdrom {
+                        log::debug!("Attaching ATAPI CD-ROM drive as IDE device {}...", device_id);
+                        xtide.set_cdrom(device_id as usize, AtapiCdrom::new())?;
+                    }
+                    add_io_device!(self, xtide, IoDeviceType::XtIde);
+                    self.xtide = Some(xtide);
+                }
             }
         }
 
@@ -1865,29 +2046,100 @@ impl BusInterface {
             if let MachineType::IbmPCJr = machine_desc.machine_type {
                 out2_suppresses_int = false;
             }
-            match serial_config.sc_type {
-                SerialControllerType::IbmAsync => {
-                    let serial = SerialPortController::new(out2_suppresses_int);
-                    // Add Serial Controller ports to io_map
-                    add_io_device!(self, serial, IoDeviceType::Serial);
-                    self.serial = Some(serial);
-                }
-            }
+            let fifo_capable = matches!(serial_config.sc_type, SerialControllerType::Ns16550);
+            let serial = SerialPortController::new(out2_suppresses_int, fifo_capable);
+            // Add Serial Controller ports to io_map
+            add_io_device!(self, serial, IoDeviceType::Serial);
+            self.serial = Some(serial);
         }
 
         // Create a Serial mouse if specified
         if let Some(serial_mouse_config) = &machine_config.serial_mouse {
             // Only create mouse if we have as serial card to plug it into!
             if self.serial.is_some() {
-                match serial_mouse_config.mouse_type {
-                    SerialMouseType::Microsoft => {
-                        let mouse = Mouse::new(serial_mouse_config.port as usize);
-                        self.mouse = Some(mouse);
-                    }
+                let mouse = Mouse::new(serial_mouse_config.mouse_type, serial_mouse_config.port as usize);
+                self.mouse = Some(mouse);
+            }
+        }
+
+        // Create a virtual modem if specified
+        if let Some(modem_config) = &machine_config.serial_modem {
+            if let Some(serial) = &mut self.serial {
+                let port = modem_config.port as usize;
+                if machine_config
+                    .serial_mouse
+                    .as_ref()
+                    .is_some_and(|m| m.port as usize == port)
+                {
+                    log::warn!(
+                        "Virtual modem and serial mouse are both attached to serial port {}",
+                        port
+                    );
+                }
+                serial.set_tx_capture(port, true);
+                self.modem = Some(VirtualModem::new(
+                    port,
+                    modem_config.listen_port,
+                    modem_config.listen_address.clone(),
+                ));
+            }
+        }
+
+        // Create a bus mouse if specified
+        if let Some(bus_mouse_config) = &machine_config.bus_mouse {
+            match bus_mouse_config.mouse_type {
+                BusMouseType::InPort => {
+                    let bus_mouse = InportMouse::new(bus_mouse_config.io_base, bus_mouse_config.irq);
+                    add_io_device!(self, bus_mouse, IoDeviceType::BusMouse);
+                    self.bus_mouse = Some(bus_mouse);
+                }
+            }
+        }
+
+        // Create a Sound Blaster if specified
+        if let Some(sb_config) = &machine_config.sound_blaster {
+            match sb_config.sb_type {
+                SoundBlasterType::SoundBlaster2 => {
+                    let sound_blaster = SoundBlaster::new(sb_config.io_base, sb_config.irq, sb_config.dma);
+                    add_io_device!(self, sound_blaster, IoDeviceType::SoundBlaster);
+                    self.sound_blaster = Some(sound_blaster);
                 }
             }
         }
 
+        // Create an MPU-401 if specified
+        if let Some(mpu_config) = &machine_config.mpu401 {
+            let mpu401 = Mpu401::new(mpu_config.io_base, mpu_config.irq);
+            add_io_device!(self, mpu401, IoDeviceType::Mpu401);
+            self.mpu401 = Some(mpu401);
+        }
+
+        // Create an NE2000 if specified
+        if let Some(ne_config) = &machine_config.ne2000 {
+            let mac = match ne_config
+                .mac_address
+                .as_deref()
+                .map(network::parse_mac_address)
+                .transpose()
+            {
+                Ok(mac) => mac,
+                Err(e) => {
+                    log::error!("NE2000: {}. Using the default address.", e);
+                    None
+                }
+            };
+            let backend = match network::create_backend(ne_config.backend, ne_config.pcap_interface.as_deref()) {
+                Ok(backend) => Some(backend),
+                Err(e) => {
+                    log::error!("NE2000: Failed to create network backend: {}", e);
+                    None
+                }
+            };
+            let ne2000 = Ne2000::new(ne_config.io_base, ne_config.irq, mac, backend);
+            add_io_device!(self, ne2000, IoDeviceType::Ne2000);
+            self.ne2000 = Some(ne2000);
+        }
+
         // Create an EMS board if specified
         if let Some(ems_config) = &machine_config.ems {
             if let EmsType::LoTech2MB = ems_config.ems_type {
@@ -1926,6 +2178,13 @@ impl BusInterface {
             self.game_port = Some(game_port);
         }
 
+        // Create the onboard sound chip, if present
+        if let Some(sn76489_addr) = machine_desc.sn76489 {
+            let sn76489 = Sn76489::new(Some(sn76489_addr));
+            add_io_device!(self, sn76489, IoDeviceType::Sn76489);
+            self.sn76489 = Some(sn76489);
+        }
+
         // Create video cards
         for (i, card) in machine_config.video.iter().enumerate() {
             let video_dispatch;
@@ -1937,19 +2196,26 @@ impl BusInterface {
             log::debug!("Creating video card of type: {:?}", card.video_type);
             match card.video_type {
                 VideoType::MDA => {
-                    let mda = MDACard::new(
+                    let mut mda = MDACard::new(
                         card.video_subtype.unwrap_or(VideoCardSubType::None),
                         TraceLogger::None,
                         clock_mode,
                         true,
                         video_frame_debug,
                     );
+                    // A Hercules card sharing the bus with a color card must leave B800 to the color card.
+                    if machine_config.video.iter().any(|c| c.video_type != VideoType::MDA) {
+                        mda.set_half_only(true);
+                    }
                     add_io_device!(self, mda, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, mda, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Mda(mda)
                 }
                 VideoType::CGA => {
-                    let cga = CGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
+                    let mut cga = CGACard::new(TraceLogger::None, clock_mode, video_frame_debug);
+                    if card.snow.unwrap_or(false) {
+                        cga.set_video_option(VideoOption::EnableSnow(true));
+                    }
                     add_io_device!(self, cga, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, cga, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Cga(cga)
@@ -1957,7 +2223,10 @@ impl BusInterface {
                 VideoType::TGA => {
                     // Subtype can be Tandy1000 or PCJr
                     let subtype = card.video_subtype.unwrap_or(VideoCardSubType::Tandy1000);
-                    let tga = TGACard::new(subtype, TraceLogger::None, clock_mode, video_frame_debug);
+                    let mut tga = TGACard::new(subtype, TraceLogger::None, clock_mode, video_frame_debug);
+                    if card.snow.unwrap_or(false) {
+                        tga.set_video_option(VideoOption::EnableSnow(true));
+                    }
                     add_io_device!(self, tga, IoDeviceType::Video(video_id));
                     add_mmio_device!(self, tga, MmioDeviceType::Video(video_id));
                     video_dispatch = VideoCardDispatch::Tga(tga)
@@ -1989,24 +2258,48 @@ impl BusInterface {
             self.videocard_ids.push(video_id);
         }
 
+        // Attach a parallel port DAC to the first available parallel port. A DAC takes the place of a printer.
+        if let Some(dac_config) = &machine_config.lpt_dac {
+            log::debug!("Attaching LPT DAC of type: {:?}", dac_config.dac_type);
+            if let Some(lpt) = self.first_lpt_mut() {
+                lpt.attach_dac(LptDac::new(dac_config.dac_type));
+            }
+            else {
+                log::warn!("LPT DAC configured, but machine has no parallel port.");
+            }
+        }
+
+        // Attach a virtual printer to the first available parallel port
+        if let Some(printer_config) = &machine_config.printer {
+            log::debug!("Attaching printer of type: {:?}", printer_config.printer_type);
+            if machine_config.lpt_dac.is_some() {
+                log::warn!("Printer configured, but parallel port is in use by LPT DAC.");
+            }
+            else if let Some(lpt) = self.first_lpt_mut() {
+                lpt.attach_printer(Printer::new(None));
+            }
+            else {
+                log::warn!("Printer configured, but machine has no parallel port.");
+            }
+        }
+
         self.machine_desc = Some(machine_desc.clone());
         Ok(())
     }
 
     /// Return whether NMI is enabled.
-    /// On the 5150 & 5160, NMI generation can be disabled via the PPI.
+    /// On the 5150 & 5160, NMI is masked by bit 7 of the A0 register.
     pub fn nmi_enabled(&self) -> bool {
         match self.machine_desc.unwrap().machine_type {
             // TODO: Add other types?
             MachineType::Ibm5150v64K | MachineType::Ibm5150v256K | MachineType::Ibm5160 => {
-                if let Some(ppi) = &self.ppi {
-                    ppi.nmi_enabled()
+                if let Some(a0) = &self.a0 {
+                    a0.is_nmi_enabled()
                 }
                 else {
                     true
                 }
             }
-            // Add other types that use A0 register?
             MachineType::IbmPCJr => {
                 if let Some(a0) = &self.a0 {
                     a0.is_nmi_enabled()
@@ -2019,6 +2312,20 @@ impl BusInterface {
         }
     }
 
+    /// Signal a RAM parity error. If parity checking is enabled via the PPI, this latches an NMI.
+    pub fn raise_parity_error(&mut self) {
+        if let Some(ppi) = &mut self.ppi {
+            ppi.set_parity_check();
+        }
+    }
+
+    /// Signal an I/O channel check from an expansion card. If enabled via the PPI, this latches an NMI.
+    pub fn raise_io_check(&mut self) {
+        if let Some(ppi) = &mut self.ppi {
+            ppi.set_io_check();
+        }
+    }
+
     // Schedule extra ticks for the PIT.
     pub fn adjust_pit(&mut self, ticks: u32) {
         log::debug!("Scheduling {} extra system ticks for PIT", ticks);
@@ -2041,16 +2348,28 @@ impl BusInterface {
                 //log::debug!("Got keyboard byte: {:02X}", kb_byte);
 
                 match kb_event.pressed {
-                    true => keyboard.key_down(kb_event.keycode, &kb_event.modifiers, Some(kb_buf)),
+                    true => keyboard.key_down(kb_event.keycode, &kb_event.modifiers, kb_event.translate, Some(kb_buf)),
                     false => keyboard.key_up(kb_event.keycode),
                 }
+            }
+
+            // Accumulate us and run the keyboard when scheduled.
+            self.kb_us_accum += us;
+            if self.kb_us_accum > KB_UPDATE_RATE {
+                keyboard.run(KB_UPDATE_RATE);
+                self.kb_us_accum -= KB_UPDATE_RATE;
+            }
 
-                // Read a byte from the keyboard
-                if let Some(kb_byte) = keyboard.recv_scancode() {
-                    //log::debug!("Received keyboard byte: {:02X}", kb_byte);
+            // Do we have a PPI? if so, send the next buffered scancode to the PPI once the
+            // previous one has been read and cleared.
+            if let Some(ppi) = &mut self.ppi {
+                if ppi.take_kb_reset() {
+                    keyboard.reset();
+                }
 
-                    // Do we have a PPI? if so, send the scancode to the PPI
-                    if let Some(ppi) = &mut self.ppi {
+                if ppi.kb_ready() {
+                    if let Some(kb_byte) = keyboard.recv_scancode() {
+                        //log::debug!("Received keyboard byte: {:02X}", kb_byte);
                         ppi.send_keyboard(kb_byte);
 
                         match self.machine_desc.unwrap().machine_type {
@@ -2073,29 +2392,6 @@ impl BusInterface {
                     }
                 }
             }
-
-            // Accumulate us and run the keyboard when scheduled.
-            self.kb_us_accum += us;
-            if self.kb_us_accum > KB_UPDATE_RATE {
-                keyboard.run(KB_UPDATE_RATE);
-                self.kb_us_accum -= KB_UPDATE_RATE;
-
-                // Read a byte from the keyboard
-                if let Some(kb_byte) = keyboard.recv_scancode() {
-                    // Do we have a PPI? if so, send the scancode to the PPI
-                    if let Some(ppi) = &mut self.ppi {
-                        ppi.send_keyboard(kb_byte);
-
-                        if ppi.kb_enabled() {
-                            if let Some(pic) = &mut self.pic1 {
-                                // TODO: Should we let the PPI do this directly?
-                                //log::warn!("sending kb interrupt for byte: {:02X}", kb_byte);
-                                pic.pulse_interrupt(1);
-                            }
-                        }
-                    }
-                }
-            }
         }
 
         // There will always be a PIC, so safe to unwrap.
@@ -2106,10 +2402,13 @@ impl BusInterface {
         // There will always be a PIT, so safe to unwrap.
         let mut pit = self.pit.take().unwrap();
 
+        // The PPI's parity and I/O channel check latches are also NMI sources.
+        let ppi_nmi_source = self.ppi.as_ref().is_some_and(|ppi| ppi.nmi_source());
+
         // Run the A0 register. It doesn't need a time delta.
         let mut ppi_nmi_latch = None;
         if let Some(a0) = &mut self.a0 {
-            let new_nmi_latch = a0.run(&mut pit, 0.0);
+            let new_nmi_latch = a0.run(&mut pit, 0.0) || ppi_nmi_source;
             self.a0_data = a0.read();
 
             if !self.nmi_latch && new_nmi_latch {
@@ -2190,6 +2489,12 @@ impl BusInterface {
             self.hdc = Some(hdc);
         }
 
+        // Run the Sound Blaster, passing it DMA controller while DMA is still unattached.
+        if let Some(mut sound_blaster) = self.sound_blaster.take() {
+            sound_blaster.run(&mut dma1, self, us);
+            self.sound_blaster = Some(sound_blaster);
+        }
+
         // Run the DMA controller.
         dma1.run(self);
 
@@ -2203,6 +2508,30 @@ impl BusInterface {
             if let Some(mouse) = &mut self.mouse {
                 mouse.run(serial, us);
             }
+
+            if let Some(modem) = &mut self.modem {
+                modem.run(serial, us);
+            }
+        }
+
+        // Run the bus mouse.
+        if let Some(bus_mouse) = &mut self.bus_mouse {
+            bus_mouse.run(self.pic1.as_mut().unwrap(), us);
+        }
+
+        // Run the MPU-401.
+        if let Some(mpu401) = &mut self.mpu401 {
+            mpu401.run(self.pic1.as_mut().unwrap());
+        }
+
+        // Run the NE2000.
+        if let Some(ne2000) = &mut self.ne2000 {
+            ne2000.run(self.pic1.as_mut().unwrap(), us);
+        }
+
+        // Run the IDE controller.
+        if let Some(xtide) = &mut self.xtide {
+            xtide.run(self.pic1.as_mut().unwrap(), us);
         }
 
         // Run the game port {
@@ -2210,6 +2539,16 @@ impl BusInterface {
             game_port.run(us);
         }
 
+        // Run the sound chip.
+        if let Some(sn76489) = &mut self.sn76489 {
+            sn76489.run(us);
+        }
+
+        // Run the parallel port.
+        if let Some(parallel) = &mut self.parallel {
+            parallel.run(us);
+        }
+
         let mut do_area5150_hack = false;
         let mut save_cga: VideoCardId = Default::default();
 
@@ -2218,6 +2557,9 @@ impl BusInterface {
             match video_dispatch {
                 VideoCardDispatch::Mda(mda) => {
                     mda.run(DeviceRunTimeUnit::Microseconds(us), &mut self.pic1, None);
+                    if let Some(lpt) = mda.lpt_mut() {
+                        lpt.run(us);
+                    }
                 }
                 VideoCardDispatch::Cga(cga) => {
                     self.cga_tick_accum += sys_ticks;
@@ -2404,6 +2746,31 @@ impl BusInterface {
             serial.reset();
         }
 
+        // Reset Sound Blaster
+        if let Some(sound_blaster) = self.sound_blaster.as_mut() {
+            sound_blaster.reset();
+        }
+
+        // Reset sound chip
+        if let Some(sn76489) = self.sn76489.as_mut() {
+            sn76489.reset();
+        }
+
+        // Reset MPU-401
+        if let Some(mpu401) = self.mpu401.as_mut() {
+            mpu401.reset();
+        }
+
+        // Reset NE2000
+        if let Some(ne2000) = self.ne2000.as_mut() {
+            ne2000.reset();
+        }
+
+        // Reset IDE controller
+        if let Some(xtide) = self.xtide.as_mut() {
+            xtide.reset();
+        }
+
         // Reset video cards
         let vids: Vec<_> = self.videocards.keys().cloned().collect();
         for vid in vids {
@@ -2417,6 +2784,180 @@ impl BusInterface {
         //self.pic1.as_mut().unwrap().reset();
     }
 
+    /// Return the name of the first installed device whose state cannot be saved, if any.
+    pub fn unsupported_state_device(&self) -> Option<String> {
+        let unsupported = [
+            ("hard disk controller", self.hdc.is_some()),
+            ("XT-IDE controller", self.xtide.is_some()),
+            ("Sound Blaster", self.sound_blaster.is_some()),
+            ("MPU-401", self.mpu401.is_some()),
+            ("NE2000", self.ne2000.is_some()),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, installed)| *installed) {
+            return Some(name.to_string());
+        }
+        self.videocard_ids.iter().find_map(|vid| match self.videocards.get(vid) {
+            Some(VideoCardDispatch::Mda(_)) | Some(VideoCardDispatch::Cga(_)) => None,
+            _ => Some(format!("{:?} video card", vid.vtype)),
+        })
+    }
+
+    /// Write save state sections for memory, bus timing state and each installed device. Returns an error if a
+    /// device whose state cannot be saved is installed, as restoring the rest of the machine around it would leave
+    /// the two out of step.
+    pub fn save_state(&self, w: &mut StateWriter) -> Result<(), SaveStateError> {
+        if let Some(device) = self.unsupported_state_device() {
+            return Err(SaveStateError::UnsupportedDevice(device));
+        }
+
+        w.write_section("memory", |w| w.write_bytes(&self.memory));
+        w.write_section("bus", |w| {
+            w.write_u8(self.open_bus_byte);
+            w.write_u8(self.a0_data);
+            w.write_bool(self.nmi_latch);
+            w.write_u16(self.dma_counter);
+            w.write_u32(self.pit_ticks_advance);
+            w.write_bool(self.timer_trigger1_armed);
+            w.write_bool(self.timer_trigger2_armed);
+            w.write_u32(self.cga_tick_accum);
+            w.write_u32(self.tga_tick_accum);
+            w.write_f64(self.kb_us_accum);
+            w.write_bool(self.refresh_active);
+        });
+
+        if let Some(keyboard) = &self.keyboard {
+            w.write_state("keyboard", keyboard);
+        }
+        if let Some(ppi) = &self.ppi {
+            w.write_state("ppi", ppi);
+        }
+        if let Some(a0) = &self.a0 {
+            w.write_state("a0", a0);
+        }
+        if let Some(pit) = &self.pit {
+            w.write_state("pit", pit);
+        }
+        if let Some(dma1) = &self.dma1 {
+            w.write_state("dma1", dma1);
+        }
+        if let Some(dma2) = &self.dma2 {
+            w.write_state("dma2", dma2);
+        }
+        if let Some(pic1) = &self.pic1 {
+            w.write_state("pic1", pic1);
+        }
+        if let Some(pic2) = &self.pic2 {
+            w.write_state("pic2", pic2);
+        }
+        if let Some(serial) = &self.serial {
+            w.write_state("serial", serial);
+        }
+        if let Some(fdc) = &self.fdc {
+            w.write_state("fdc", fdc);
+        }
+        if let Some(ems) = &self.ems {
+            w.write_state("ems", ems);
+        }
+        if let Some(sn76489) = &self.sn76489 {
+            w.write_state("sn76489", sn76489);
+        }
+
+        for vid in &self.videocard_ids {
+            let tag = format!("video{}", vid.idx);
+            match self.videocards.get(vid) {
+                Some(VideoCardDispatch::Mda(mda)) => w.write_state(&tag, mda),
+                Some(VideoCardDispatch::Cga(cga)) => w.write_state(&tag, cga),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore memory, bus timing state or a device from a save state section. Returns false if the section is not
+    /// recognized, or belongs to a device that is not installed.
+    pub fn load_state_section(&mut self, tag: &str, r: &mut StateReader) -> Result<bool, SaveStateError> {
+        match tag {
+            "memory" => r.read_bytes_into(&mut self.memory, "memory")?,
+            "bus" => {
+                self.open_bus_byte = r.read_u8()?;
+                self.a0_data = r.read_u8()?;
+                self.nmi_latch = r.read_bool()?;
+                self.dma_counter = r.read_u16()?;
+                self.pit_ticks_advance = r.read_u32()?;
+                self.timer_trigger1_armed = r.read_bool()?;
+                self.timer_trigger2_armed = r.read_bool()?;
+                self.cga_tick_accum = r.read_u32()?;
+                self.tga_tick_accum = r.read_u32()?;
+                self.kb_us_accum = r.read_f64()?;
+                self.refresh_active = r.read_bool()?;
+            }
+            "keyboard" => match &mut self.keyboard {
+                Some(keyboard) => r.read_state(keyboard, "keyboard")?,
+                None => return Ok(false),
+            },
+            "ppi" => match &mut self.ppi {
+                Some(ppi) => r.read_state(ppi, "PPI")?,
+                None => return Ok(false),
+            },
+            "a0" => match &mut self.a0 {
+                Some(a0) => r.read_state(a0, "A0 register")?,
+                None => return Ok(false),
+            },
+            "pit" => match &mut self.pit {
+                Some(pit) => r.read_state(pit, "PIT")?,
+                None => return Ok(false),
+            },
+            "dma1" => match &mut self.dma1 {
+                Some(dma1) => r.read_state(dma1, "DMA controller")?,
+                None => return Ok(false),
+            },
+            "dma2" => match &mut self.dma2 {
+                Some(dma2) => r.read_state(dma2, "DMA controller")?,
+                None => return Ok(false),
+            },
+            "pic1" => match &mut self.pic1 {
+                Some(pic1) => r.read_state(pic1, "PIC")?,
+                None => return Ok(false),
+            },
+            "pic2" => match &mut self.pic2 {
+                Some(pic2) => r.read_state(pic2, "PIC")?,
+                None => return Ok(false),
+            },
+            "serial" => match &mut self.serial {
+                Some(serial) => r.read_state(serial, "serial controller")?,
+                None => return Ok(false),
+            },
+            "fdc" => match &mut self.fdc {
+                Some(fdc) => r.read_state(fdc, "FDC")?,
+                None => return Ok(false),
+            },
+            "ems" => match &mut self.ems {
+                Some(ems) => r.read_state(ems, "EMS card")?,
+                None => return Ok(false),
+            },
+            "sn76489" => match &mut self.sn76489 {
+                Some(sn76489) => r.read_state(sn76489, "SN76489")?,
+                None => return Ok(false),
+            },
+            _ => {
+                let vid = match tag
+                    .strip_prefix("video")
+                    .and_then(|idx| idx.parse::<usize>().ok())
+                    .and_then(|idx| self.videocard_ids.iter().find(|vid| vid.idx == idx))
+                {
+                    Some(vid) => *vid,
+                    None => return Ok(false),
+                };
+                match self.videocards.get_mut(&vid) {
+                    Some(VideoCardDispatch::Mda(mda)) => r.read_state(mda, "MDA card")?,
+                    Some(VideoCardDispatch::Cga(cga)) => r.read_state(cga, "CGA card")?,
+                    _ => return Ok(false),
+                }
+            }
+        }
+        Ok(true)
+    }
+
     /// Read an 8-bit value from an IO port.
     ///
     /// We provide the elapsed cycle count for the current instruction. This allows a device
@@ -2499,6 +3040,36 @@ impl BusInterface {
                         byte = Some(ems.read_u8(port, nul_delta));
                     }
                 }
+                IoDeviceType::BusMouse => {
+                    if let Some(bus_mouse) = &mut self.bus_mouse {
+                        byte = Some(bus_mouse.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::SoundBlaster => {
+                    if let Some(sound_blaster) = &mut self.sound_blaster {
+                        byte = Some(sound_blaster.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Sn76489 => {
+                    if let Some(sn76489) = &mut self.sn76489 {
+                        byte = Some(sn76489.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Mpu401 => {
+                    if let Some(mpu401) = &mut self.mpu401 {
+                        byte = Some(mpu401.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::Ne2000 => {
+                    if let Some(ne2000) = &mut self.ne2000 {
+                        byte = Some(ne2000.read_u8(port, nul_delta));
+                    }
+                }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        byte = Some(xtide.read_u8(port, nul_delta));
+                    }
+                }
                 IoDeviceType::GamePort => {
                     if let Some(game_port) = &mut self.game_port {
                         byte = Some(game_port.read_u8(port, nul_delta));
@@ -2530,6 +3101,10 @@ impl BusInterface {
 
         let byte_val = byte.unwrap_or(NO_IO_BYTE);
 
+        if byte.is_none() && self.log_unmapped && !self.io_stats.contains_key(&port) {
+            log::warn!("Read from unmapped IO port {:04X}, returning {:02X}", port, byte_val);
+        }
+
         self.io_stats
             .entry(port)
             .and_modify(|e| {
@@ -2567,6 +3142,12 @@ impl BusInterface {
             }
         }
 
+        // Handle exit port
+        if self.exit_port == Some(port) {
+            log::debug!("Write to exit port: {:02X}", data);
+            self.exit_code = Some(data);
+        }
+
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
         let mut resolved = false;
@@ -2654,6 +3235,42 @@ impl BusInterface {
                         resolved = true;
                     }
                 }
+                IoDeviceType::BusMouse => {
+                    if let Some(bus_mouse) = &mut self.bus_mouse {
+                        bus_mouse.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::SoundBlaster => {
+                    if let Some(sound_blaster) = &mut self.sound_blaster {
+                        sound_blaster.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Sn76489 => {
+                    if let Some(sn76489) = &mut self.sn76489 {
+                        sn76489.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Mpu401 => {
+                    if let Some(mpu401) = &mut self.mpu401 {
+                        mpu401.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::Ne2000 => {
+                    if let Some(ne2000) = &mut self.ne2000 {
+                        ne2000.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        xtide.write_u8(port, data, None, nul_delta);
+                        resolved = true;
+                    }
+                }
                 IoDeviceType::GamePort => {
                     if let Some(game_port) = &mut self.game_port {
                         game_port.write_u8(port, data, None, nul_delta);
@@ -2754,6 +3371,81 @@ impl BusInterface {
         &mut self.mouse
     }
 
+    pub fn bus_mouse_mut(&mut self) -> &mut Option<InportMouse> {
+        &mut self.bus_mouse
+    }
+
+    pub fn sound_blaster_mut(&mut self) -> &mut Option<SoundBlaster> {
+        &mut self.sound_blaster
+    }
+
+    pub fn sn76489_mut(&mut self) -> &mut Option<Sn76489> {
+        &mut self.sn76489
+    }
+
+    pub fn mpu401_mut(&mut self) -> &mut Option<Mpu401> {
+        &mut self.mpu401
+    }
+
+    pub fn ne2000_mut(&mut self) -> &mut Option<Ne2000> {
+        &mut self.ne2000
+    }
+
+    pub fn xtide_mut(&mut self) -> &mut Option<XtIdeController> {
+        &mut self.xtide
+    }
+
+    /// Return the first parallel port in the machine, either on a parallel card or an MDA.
+    fn first_lpt_mut(&mut self) -> Option<&mut ParallelPort> {
+        if let Some(parallel) = &mut self.parallel {
+            return Some(parallel.lpt_mut());
+        }
+        self.videocards.values_mut().find_map(|card| match card {
+            VideoCardDispatch::Mda(mda) => mda.lpt_mut(),
+            _ => None,
+        })
+    }
+
+    pub fn lpt_dac_mut(&mut self) -> Option<&mut LptDac> {
+        self.first_lpt_mut().and_then(|lpt| lpt.dac_mut())
+    }
+
+    /// Register a mixer source for each installed sound device.
+    pub fn register_sound_sources(&mut self, mixer: &mut SoundMixer) {
+        if let Some(sound_blaster) = &mut self.sound_blaster {
+            sound_blaster.set_sound_source(mixer.add_source("Sound Blaster", SB_OUTPUT_RATE));
+        }
+        if let Some(sn76489) = &mut self.sn76489 {
+            sn76489.set_sound_source(mixer.add_source("SN76489", SN76489_OUTPUT_RATE));
+        }
+        if let Some(dac) = self.lpt_dac_mut() {
+            let name = match dac.dac_type() {
+                LptDacType::Covox => "Covox",
+                LptDacType::DisneySoundSource => "Disney Sound Source",
+            };
+            dac.set_sound_source(mixer.add_source(name, DAC_OUTPUT_RATE));
+        }
+        if let Some(fdc) = &mut self.fdc {
+            fdc.sound_mut()
+                .set_sound_source(mixer.add_source("Floppy Drives", DRIVE_SOUND_RATE));
+        }
+        if let Some(cdrom) = self.xtide.as_mut().and_then(|xtide| xtide.cdrom_mut()) {
+            cdrom.set_sound_source(mixer.add_source("CD Audio", CD_AUDIO_RATE));
+        }
+    }
+
+    pub fn printer_mut(&mut self) -> Option<&mut Printer> {
+        if let Some(parallel) = &mut self.parallel {
+            if let Some(printer) = parallel.lpt_mut().printer_mut() {
+                return Some(printer);
+            }
+        }
+        self.videocards.values_mut().find_map(|card| match card {
+            VideoCardDispatch::Mda(mda) => mda.lpt_mut().and_then(|lpt| lpt.printer_mut()),
+            _ => None,
+        })
+    }
+
     pub fn primary_video(&self) -> Option<Box<&dyn VideoCard>> {
         if self.videocard_ids.len() > 0 {
             self.video(&self.videocard_ids[0])
@@ -2868,6 +3560,9 @@ impl BusInterface {
         if let Some(hdc) = &self.hdc {
             hdc.drive_ct()
         }
+        else if let Some(xtide) = &self.xtide {
+            xtide.drive_ct()
+        }
         else {
             0
         }
@@ -0,0 +1,420 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    cd_image.rs
+
+    CD-ROM disc images. ISO images hold the 2048-byte user data of each
+    sector of a single data track. CUE sheets describe a disc made of one or
+    more BIN files holding data and audio tracks, which may be stored as
+    2048-byte user data or as 2352-byte raw sectors.
+
+    Sectors are addressed by logical block address (LBA). LBA 0 is at
+    00:02:00 in minutes, seconds and frames (MSF), after the 2 second lead
+    in, and there are 75 frames per second.
+
+*/
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, Error};
+
+pub const CD_SECTOR_SIZE: usize = 2048;
+pub const CD_RAW_SECTOR_SIZE: usize = 2352;
+pub const CD_FRAMES_PER_SECOND: u32 = 75;
+pub const CD_LEAD_IN_FRAMES: u32 = 150;
+/// Sample rate of CD audio. Each raw sector holds 588 stereo 16-bit samples.
+pub const CD_AUDIO_RATE: f64 = 44_100.0;
+
+// Offset of the user data in a raw sector, after the sync pattern and header.
+const MODE1_DATA_OFFSET: usize = 16;
+const MODE2_DATA_OFFSET: usize = 24;
+
+/// A readable, seekable source of image data.
+pub trait CdImageFile: Read + Seek + Send {}
+impl<T: Read + Seek + Send> CdImageFile for T {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CdTrackType {
+    Data,
+    Audio,
+}
+
+#[derive(Clone, Debug)]
+pub struct CdTrack {
+    pub number: u8,
+    pub track_type: CdTrackType,
+    /// LBA of the first sector of the track.
+    pub start: u32,
+    /// Length of the track in sectors.
+    pub length: u32,
+    file: usize,
+    offset: u64,
+    sector_size: usize,
+    data_offset: usize,
+}
+
+impl CdTrack {
+    pub fn end(&self) -> u32 {
+        self.start + self.length
+    }
+}
+
+pub struct CdImage {
+    files:  Vec<Box<dyn CdImageFile>>,
+    tracks: Vec<CdTrack>,
+}
+
+/// Convert a logical block address to minutes, seconds and frames.
+pub fn lba_to_msf(lba: u32) -> (u8, u8, u8) {
+    let frames = lba + CD_LEAD_IN_FRAMES;
+    let m = frames / (60 * CD_FRAMES_PER_SECOND);
+    let s = (frames / CD_FRAMES_PER_SECOND) % 60;
+    let f = frames % CD_FRAMES_PER_SECOND;
+    (m as u8, s as u8, f as u8)
+}
+
+/// Convert minutes, seconds and frames to a logical block address. Addresses in the lead in return 0.
+pub fn msf_to_lba(m: u8, s: u8, f: u8) -> u32 {
+    let frames = (m as u32 * 60 + s as u32) * CD_FRAMES_PER_SECOND + f as u32;
+    frames.saturating_sub(CD_LEAD_IN_FRAMES)
+}
+
+/// Parse a CUE sheet "mm:ss:ff" position into a frame count.
+fn parse_cue_msf(s: &str) -> Result<u32, Error> {
+    let parts: Vec<u32> = s
+        .split(':')
+        .map(|p| p.parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("Invalid CUE sheet position: {}", s))?;
+    match parts[..] {
+        [m, sec, f] => Ok((m * 60 + sec) * CD_FRAMES_PER_SECOND + f),
+        _ => Err(anyhow!("Invalid CUE sheet position: {}", s)),
+    }
+}
+
+/// Return the length of a file by seeking to its end.
+fn file_len(file: &mut dyn CdImageFile) -> Result<u64, Error> {
+    let len = file.seek(SeekFrom::End(0))?;
+    Ok(len)
+}
+
+// A track as described by the CUE sheet, before its position on the disc is known.
+struct CueTrack {
+    number: u8,
+    track_type: CdTrackType,
+    sector_size: usize,
+    data_offset: usize,
+    pregap: u32,
+    index1: Option<u32>,
+}
+
+impl CdImage {
+    /// Create an image from an ISO file holding a single data track.
+    pub fn from_iso(mut file: Box<dyn CdImageFile>) -> Result<Self, Error> {
+        let len = file_len(file.as_mut())?;
+        let length = (len / CD_SECTOR_SIZE as u64) as u32;
+        if length == 0 {
+            return Err(anyhow!("ISO image is too small"));
+        }
+        Ok(Self {
+            files:  vec![file],
+            tracks: vec![CdTrack {
+                number: 1,
+                track_type: CdTrackType::Data,
+                start: 0,
+                length,
+                file: 0,
+                offset: 0,
+                sector_size: CD_SECTOR_SIZE,
+                data_offset: 0,
+            }],
+        })
+    }
+
+    /// Create an image from a CUE sheet. `open` is called to open each file the sheet refers to.
+    pub fn from_cue(
+        cue: &str,
+        mut open: impl FnMut(&str) -> Result<Box<dyn CdImageFile>, Error>,
+    ) -> Result<Self, Error> {
+        let mut files: Vec<Box<dyn CdImageFile>> = Vec::new();
+        let mut file_tracks: Vec<Vec<CueTrack>> = Vec::new();
+
+        for line in cue.lines() {
+            let line = line.trim();
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            match keyword.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    // The file name may be quoted and contain spaces. The file type follows it.
+                    let name = match rest.strip_prefix('"') {
+                        Some(quoted) => quoted.split('"').next().unwrap_or(""),
+                        None => rest.split_whitespace().next().unwrap_or(""),
+                    };
+                    files.push(open(name)?);
+                    file_tracks.push(Vec::new());
+                }
+                "TRACK" => {
+                    let mut args = rest.split_whitespace();
+                    let number = args
+                        .next()
+                        .and_then(|n| n.parse::<u8>().ok())
+                        .ok_or_else(|| anyhow!("Invalid CUE sheet track: {}", line))?;
+                    let (track_type, sector_size, data_offset) =
+                        match args.next().unwrap_or("").to_ascii_uppercase().as_str() {
+                            "AUDIO" => (CdTrackType::Audio, CD_RAW_SECTOR_SIZE, 0),
+                            "MODE1/2048" => (CdTrackType::Data, CD_SECTOR_SIZE, 0),
+                            "MODE1/2352" => (CdTrackType::Data, CD_RAW_SECTOR_SIZE, MODE1_DATA_OFFSET),
+                            "MODE2/2352" => (CdTrackType::Data, CD_RAW_SECTOR_SIZE, MODE2_DATA_OFFSET),
+                            mode => return Err(anyhow!("Unsupported CUE sheet track mode: {}", mode)),
+                        };
+                    let tracks = file_tracks
+                        .last_mut()
+                        .ok_or_else(|| anyhow!("CUE sheet track {} precedes any FILE", number))?;
+                    tracks.push(CueTrack {
+                        number,
+                        track_type,
+                        sector_size,
+                        data_offset,
+                        pregap: 0,
+                        index1: None,
+                    });
+                }
+                "INDEX" | "PREGAP" => {
+                    let track = file_tracks
+                        .last_mut()
+                        .and_then(|tracks| tracks.last_mut())
+                        .ok_or_else(|| anyhow!("CUE sheet {} precedes any TRACK", keyword))?;
+                    if keyword.eq_ignore_ascii_case("PREGAP") {
+                        track.pregap = parse_cue_msf(rest)?;
+                    }
+                    else {
+                        let mut args = rest.split_whitespace();
+                        let index = args.next().and_then(|n| n.parse::<u8>().ok());
+                        let position = parse_cue_msf(args.next().unwrap_or(""))?;
+                        // Index 0 marks the pregap stored in the file, which is played as part of the previous
+                        // track. The track starts at index 1.
+                        if index == Some(1) {
+                            track.index1 = Some(position);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Lay the tracks of each file out on the disc, one file after another. PREGAP silence isn't stored in the
+        // file, so it moves the track and every track after it.
+        let mut tracks: Vec<CdTrack> = Vec::new();
+        let mut file_start = 0;
+        for (file_idx, cue_tracks) in file_tracks.iter().enumerate() {
+            let len = file_len(files[file_idx].as_mut())?;
+            let mut shift = 0;
+            for (i, cue_track) in cue_tracks.iter().enumerate() {
+                let index1 = cue_track
+                    .index1
+                    .ok_or_else(|| anyhow!("CUE sheet track {} has no INDEX 01", cue_track.number))?;
+                shift += cue_track.pregap;
+                let offset = index1 as u64 * cue_track.sector_size as u64;
+                let length = match cue_tracks.get(i + 1).and_then(|next| next.index1) {
+                    Some(next) => next.saturating_sub(index1),
+                    None => (len.saturating_sub(offset) / cue_track.sector_size as u64) as u32,
+                };
+                tracks.push(CdTrack {
+                    number: cue_track.number,
+                    track_type: cue_track.track_type,
+                    start: file_start + shift + index1,
+                    length,
+                    file: file_idx,
+                    offset,
+                    sector_size: cue_track.sector_size,
+                    data_offset: cue_track.data_offset,
+                });
+            }
+            if let Some(last) = tracks.last() {
+                file_start = last.end();
+            }
+        }
+
+        if tracks.is_empty() {
+            return Err(anyhow!("CUE sheet has no tracks"));
+        }
+        Ok(Self { files, tracks })
+    }
+
+    /// Open an ISO image or CUE sheet, depending on the file extension. Files named by a CUE sheet are opened
+    /// relative to the directory containing it.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let is_cue = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+        if !is_cue {
+            return Self::from_iso(Box::new(File::open(path)?));
+        }
+
+        let cue = std::fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::from_cue(&cue, |name| {
+            let file = File::open(dir.join(name)).map_err(|e| anyhow!("Couldn't open {}: {}", name, e))?;
+            Ok(Box::new(file) as Box<dyn CdImageFile>)
+        })
+    }
+
+    pub fn tracks(&self) -> &[CdTrack] {
+        &self.tracks
+    }
+
+    /// Return the LBA of the lead out, which follows the last sector of the disc.
+    pub fn lead_out(&self) -> u32 {
+        self.tracks.last().map(|track| track.end()).unwrap_or(0)
+    }
+
+    /// Return the track containing the specified sector. Sectors in a gap before a track belong to the
+    /// preceding track.
+    pub fn track_at(&self, lba: u32) -> Option<&CdTrack> {
+        if lba >= self.lead_out() {
+            return None;
+        }
+        self.tracks.iter().rev().find(|track| track.start <= lba)
+    }
+
+    /// Read `buf.len()` bytes starting `start` bytes into the specified sector of a track. Data past the end of
+    /// the file, such as pregap silence, reads as zeros.
+    fn read_track(&mut self, track: &CdTrack, lba: u32, start: usize, buf: &mut [u8]) -> Result<(), Error> {
+        buf.fill(0);
+        if lba < track.start || lba >= track.end() {
+            return Ok(());
+        }
+        let pos = track.offset + (lba - track.start) as u64 * track.sector_size as u64 + start as u64;
+        let file = &mut self.files[track.file];
+        file.seek(SeekFrom::Start(pos))?;
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the 2048 bytes of user data in a sector of a data track.
+    pub fn read_sector(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let track = self
+            .track_at(lba)
+            .cloned()
+            .ok_or_else(|| anyhow!("Sector {} is past the end of the disc", lba))?;
+        if track.track_type != CdTrackType::Data {
+            return Err(anyhow!("Sector {} is in an audio track", lba));
+        }
+        self.read_track(&track, lba, track.data_offset, &mut buf[..CD_SECTOR_SIZE])
+    }
+
+    /// Read the 2352 bytes of 16-bit little endian stereo samples in a sector of an audio track.
+    pub fn read_audio(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let track = self
+            .track_at(lba)
+            .cloned()
+            .ok_or_else(|| anyhow!("Sector {} is past the end of the disc", lba))?;
+        if track.track_type != CdTrackType::Audio {
+            return Err(anyhow!("Sector {} is in a data track", lba));
+        }
+        self.read_track(&track, lba, 0, &mut buf[..CD_RAW_SECTOR_SIZE])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn raw_sectors(ct: usize, fill: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..ct {
+            let mut sector = vec![fill; CD_RAW_SECTOR_SIZE];
+            sector[MODE1_DATA_OFFSET] = i as u8;
+            data.extend(sector);
+        }
+        data
+    }
+
+    #[test]
+    fn test_msf() {
+        assert_eq!(lba_to_msf(0), (0, 2, 0));
+        assert_eq!(lba_to_msf(4350), (1, 0, 0));
+        assert_eq!(msf_to_lba(1, 0, 0), 4350);
+        assert_eq!(msf_to_lba(0, 1, 0), 0);
+    }
+
+    #[test]
+    fn test_iso() {
+        let mut data = vec![0u8; CD_SECTOR_SIZE * 4];
+        data[CD_SECTOR_SIZE * 2] = 0x55;
+        let mut image = CdImage::from_iso(Box::new(Cursor::new(data))).unwrap();
+        assert_eq!(image.lead_out(), 4);
+
+        let mut buf = [0u8; CD_SECTOR_SIZE];
+        image.read_sector(2, &mut buf).unwrap();
+        assert_eq!(buf[0], 0x55);
+        assert!(image.read_sector(4, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_cue() {
+        // A data track followed by two audio tracks, the second with a pregap stored in the file and a 1 second
+        // pregap that isn't.
+        let mut bin = raw_sectors(10, 0x00);
+        bin.extend(raw_sectors(30, 0xAA));
+        let cue = "FILE \"game disc.bin\" BINARY\n\
+                   TRACK 01 MODE1/2352\n  INDEX 01 00:00:00\n\
+                   TRACK 02 AUDIO\n  INDEX 01 00:00:10\n\
+                   TRACK 03 AUDIO\n  PREGAP 00:01:00\n  INDEX 00 00:00:20\n  INDEX 01 00:00:25\n";
+        let mut opened = Vec::new();
+        let mut image = CdImage::from_cue(cue, |name| {
+            opened.push(name.to_string());
+            Ok(Box::new(Cursor::new(bin.clone())) as Box<dyn CdImageFile>)
+        })
+        .unwrap();
+        assert_eq!(opened, vec!["game disc.bin"]);
+
+        let tracks = image.tracks();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!((tracks[0].start, tracks[0].length), (0, 10));
+        assert_eq!((tracks[1].start, tracks[1].length), (10, 15));
+        assert_eq!((tracks[2].start, tracks[2].length), (100, 15));
+        assert_eq!(tracks[1].track_type, CdTrackType::Audio);
+        assert_eq!(image.lead_out(), 115);
+
+        let mut buf = [0u8; CD_RAW_SECTOR_SIZE];
+        image.read_sector(3, &mut buf).unwrap();
+        assert_eq!(buf[0], 3);
+        assert!(image.read_sector(10, &mut buf).is_err());
+        image.read_audio(101, &mut buf).unwrap();
+        assert_eq!(buf[0], 0xAA);
+        assert_eq!(image.track_at(99).map(|t| t.number), Some(2));
+    }
+}
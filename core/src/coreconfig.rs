@@ -38,7 +38,7 @@ use crate::{
     cpu_common::TraceMode,
     cpu_validator::ValidatorType,
     device_traits::videocard::{ClockingMode, VideoType},
-    machine_types::MachineType,
+    machine_types::{BootDevice, MachineType},
 };
 use std::path::PathBuf;
 
@@ -59,8 +59,11 @@ pub trait CoreConfig {
     fn get_machine_type(&self) -> MachineType;
 
     fn get_audio_enabled(&self) -> bool;
+    fn get_drive_sounds(&self) -> bool;
+    fn get_drive_sound_volume(&self) -> Option<f32>;
     fn get_machine_noroms(&self) -> bool;
     fn get_machine_turbo(&self) -> bool;
+    fn get_boot_device(&self) -> BootDevice;
     //fn get_keyboard_type(&self) -> Option<KeyboardType>;
     fn get_keyboard_layout(&self) -> Option<String>;
     fn get_keyboard_debug(&self) -> bool;
@@ -78,4 +81,6 @@ pub trait CoreConfig {
     fn get_patch_enabled(&self) -> bool;
     fn get_halt_behavior(&self) -> OnHaltBehavior;
     fn get_terminal_port(&self) -> Option<u16>;
+    fn get_exit_port(&self) -> Option<u16>;
+    fn get_log_unmapped(&self) -> bool;
 }
@@ -70,7 +70,24 @@ impl Intel808x {
         (((segment_val as u32) << 4) + offset as u32) & 0xFFFFFu32
     }
 
+    /// Calculate the linear address of the high byte of a word operand at segment:offset.
+    /// The 8088 wraps the offset within the segment, so a word at offset FFFFh is assembled from
+    /// offsets FFFFh and 0000h. If segment overrun is enabled, the high byte is instead read from
+    /// the next linear address, as on the 286.
+    #[inline]
+    pub fn calc_linear_address_seg_hi(&self, segment: Segment, offset: u16) -> u32 {
+        if self.segment_overrun && offset == 0xFFFF {
+            (self.calc_linear_address_seg(segment, offset) + 1) & 0xFFFFFu32
+        }
+        else {
+            self.calc_linear_address_seg(segment, offset.wrapping_add(1))
+        }
+    }
+
     /// Calculate the Effective Address for the given AddressingMode enum
+    /// No cycles are spent here. EA clock costs are already spent when the modrm is read, by
+    /// ModRmByte::read() following the microcode for each addressing mode, and the two cycle
+    /// segment override penalty is spent when the prefix is decoded.
     pub fn calc_effective_address(
         &mut self,
         mode: AddressingMode,
@@ -412,19 +429,23 @@ impl Intel808x {
                     Register16::DI => self.set_register16(Register16::DI, value),
                     Register16::ES => {
                         self.set_register16(Register16::ES, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     Register16::CS => {
                         self.set_register16(Register16::CS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     Register16::SS => {
+                        // Loading any segment register inhibits interrupts for one instruction on the 8088.
+                        // Loading SS also inhibits single-step, so that the following load of SP completes
+                        // before anything can be pushed to the stack.
                         self.set_register16(Register16::SS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
+                        self.trap_suppressed = true;
                     }
                     Register16::DS => {
                         self.set_register16(Register16::DS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     _ => panic!("read_operand16(): Invalid Register16 operand"),
                 }
@@ -437,3 +458,24 @@ impl Intel808x {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_common::Register16;
+
+    #[test]
+    fn test_segment_load_inhibits_interrupts() {
+        for subtype in [CpuSubType::Intel8088, CpuSubType::Intel8086, CpuSubType::Harris80C88] {
+            for reg in [Register16::ES, Register16::CS, Register16::SS, Register16::DS] {
+                let mut cpu = Intel808x::default();
+                cpu.cpu_subtype = subtype;
+                cpu.write_operand16(OperandType::Register16(reg), None, 0x1234, ReadWriteFlag::Normal);
+
+                assert_eq!(cpu.get_register16(reg), 0x1234);
+                assert!(cpu.interrupt_inhibit, "loading {:?} did not inhibit interrupts", reg);
+                assert_eq!(cpu.trap_suppressed, reg == Register16::SS);
+            }
+        }
+    }
+}
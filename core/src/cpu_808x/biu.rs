@@ -400,7 +400,9 @@ impl Intel808x {
     }
 
     /// Issue an interrupt acknowledge, consisting of two consecutive INTA bus cycles.
-    pub fn biu_inta(&mut self, vector: u8) {
+    /// The PIC responds to the 2nd INTA cycle by placing the interrupt vector on the data bus,
+    /// which is returned. If no PIC is present, the vector defaults to 7.
+    pub fn biu_inta(&mut self) -> u8 {
         self.biu_bus_begin(
             BusStatus::InterruptAck,
             Segment::None,
@@ -413,6 +415,15 @@ impl Intel808x {
 
         self.biu_bus_wait_finish();
 
+        let mut vector = 7;
+        if let Some(pic) = self.bus.pic_mut().as_mut() {
+            if pic.query_interrupt_line() {
+                if let Some(iv) = pic.get_interrupt_vector() {
+                    vector = iv;
+                }
+            }
+        }
+
         self.biu_bus_begin(
             BusStatus::InterruptAck,
             Segment::None,
@@ -424,6 +435,7 @@ impl Intel808x {
         );
 
         self.biu_bus_wait_finish();
+        vector
     }
 
     pub fn biu_read_u8(&mut self, seg: Segment, offset: u16, flag: ReadWriteFlag) -> u8 {
@@ -579,7 +591,7 @@ impl Intel808x {
 
         self.biu_bus_wait_finish();
         word = self.data_bus & 0x00FF;
-        addr = self.calc_linear_address_seg(seg, offset.wrapping_add(1));
+        addr = self.calc_linear_address_seg_hi(seg, offset);
 
         self.biu_bus_begin(
             BusStatus::MemRead,
@@ -619,7 +631,7 @@ impl Intel808x {
         );
 
         self.biu_bus_wait_finish();
-        addr = self.calc_linear_address_seg(seg, offset.wrapping_add(1));
+        addr = self.calc_linear_address_seg_hi(seg, offset);
 
         self.biu_bus_begin(
             BusStatus::MemWrite,
@@ -788,9 +800,10 @@ impl Intel808x {
         );
 
         // Check this address for a memory access breakpoint
-        if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 {
+        if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 && self.check_mem_breakpoint(address, new_bus_status)
+        {
             // Breakpoint hit
-            self.state = CpuState::BreakpointHit;
+            self.state = CpuRunState::BreakpointHit;
         }
 
         let mut fetch_abort = false;
@@ -954,6 +967,13 @@ impl Intel808x {
     pub fn biu_fetch_bus_begin(&mut self) {
         let addr = Intel808x::calc_linear_address(self.cs, self.pc);
         if self.biu_queue_has_room() {
+            // Code fetches hit memory access breakpoints too
+            if self.bus.get_flags(addr as usize) & MEM_BPA_BIT != 0
+                && self.check_mem_breakpoint(addr, BusStatus::CodeFetch)
+            {
+                self.state = CpuRunState::BreakpointHit;
+            }
+
             //trace_print!(self, "Setting address bus to PC: {:05X}", self.pc);
             self.fetch_state = FetchState::Normal;
             self.pl_status = BusStatus::Passive; // Pipeline status must always be reset on T1
@@ -37,7 +37,7 @@ use crate::{
     cpu_808x::{
         trace_print,
         BusStatus,
-        CpuState,
+        CpuRunState,
         FetchState,
         Intel808x,
         Register16,
@@ -52,14 +52,19 @@ use crate::{
         CpuDispatch,
         CpuError,
         CpuOption,
+        CpuState,
         CpuStringState,
         CpuType,
+        HookId,
+        InstructionHook,
+        OpcodeProfiler,
         QueueOp,
         ServiceEvent,
         StepResult,
         TraceMode,
     },
     cpu_validator::{CycleState, VRegisters},
+    fpu_8087::Fpu8087,
     syntax_token::SyntaxToken,
 };
 
@@ -230,6 +235,36 @@ impl Cpu for Intel808x {
         self.get_string_state()
     }
 
+    #[inline]
+    fn get_state(&self) -> CpuState {
+        self.get_state()
+    }
+
+    #[inline]
+    fn set_state(&mut self, state: &CpuState) {
+        self.set_state(state)
+    }
+
+    fn fpu(&self) -> Option<&Fpu8087> {
+        self.fpu()
+    }
+
+    fn set_fpu(&mut self, fpu: Option<Fpu8087>) {
+        self.set_fpu(fpu)
+    }
+
+    fn add_instruction_hook(&mut self, hook: InstructionHook) -> HookId {
+        self.add_instruction_hook(hook)
+    }
+
+    fn remove_instruction_hook(&mut self, id: HookId) -> bool {
+        self.remove_instruction_hook(id)
+    }
+
+    fn opcode_profiler(&self) -> Option<&OpcodeProfiler> {
+        self.opcode_profiler.as_deref()
+    }
+
     fn eval_address(&self, expr: &str) -> Option<CpuAddress> {
         self.eval_address(expr)
     }
@@ -271,6 +306,10 @@ impl Cpu for Intel808x {
                 self.instruction_history.clear();
                 self.instruction_history_on = state;
             }
+            CpuOption::InstructionHistoryLen(len) => {
+                log::debug!("Setting InstructionHistoryLen to: {}", len);
+                self.set_instruction_history_len(len as usize);
+            }
             CpuOption::ScheduleInterrupt(_state, cycle_target, cycles, retrigger) => {
                 log::debug!("Setting InterruptHint to: ({},{})", cycle_target, cycles);
                 self.interrupt_scheduling = true;
@@ -307,6 +346,10 @@ impl Cpu for Intel808x {
                 log::debug!("Setting EnableWaitStates to: {:?}", state);
                 self.enable_wait_states = state;
             }
+            CpuOption::SegmentOverrun(state) => {
+                log::debug!("Setting SegmentOverrun to: {:?}", state);
+                self.segment_overrun = state;
+            }
             CpuOption::TraceLoggingEnabled(state) => {
                 log::debug!("Setting TraceLoggingEnabled to: {:?}", state);
                 self.trace_enabled = state;
@@ -321,20 +364,51 @@ impl Cpu for Intel808x {
                 log::debug!("Setting EnableServiceInterrupt to: {:?}", state);
                 self.enable_service_interrupt = state;
             }
+            CpuOption::OpcodeProfiling(state) => {
+                log::debug!("Setting OpcodeProfiling to: {:?}", state);
+                self.opcode_profiler = match state {
+                    true => Some(Box::new(OpcodeProfiler::new())),
+                    false => None,
+                };
+            }
+            CpuOption::InterruptTrace(state, filter) => {
+                log::debug!("Setting InterruptTrace to: {:?} ({:02X?})", state, filter);
+                self.int_trace = state;
+                self.int_trace_filter = filter;
+            }
+            CpuOption::StackGuard(state, limit, wrap, break_on_hit) => {
+                log::debug!(
+                    "Setting StackGuard to: {:?} (limit: {:04X}, wrap: {:?}, break: {:?})",
+                    state,
+                    limit,
+                    wrap,
+                    break_on_hit
+                );
+                self.stack_guard = state;
+                self.stack_guard_limit = limit;
+                self.stack_guard_wrap = wrap;
+                self.stack_guard_break = break_on_hit;
+                self.stack_guard_tripped = false;
+            }
         }
     }
 
     fn get_option(&self, opt: CpuOption) -> bool {
         match opt {
             CpuOption::InstructionHistory(_) => self.instruction_history_on,
+            CpuOption::InstructionHistoryLen(..) => true,
             CpuOption::ScheduleInterrupt(..) => self.interrupt_cycle_period > 0,
             CpuOption::ScheduleDramRefresh(..) => self.dram_refresh_simulation,
             CpuOption::DramRefreshAdjust(..) => true,
             CpuOption::HaltResumeDelay(..) => true,
             CpuOption::OffRailsDetection(_) => self.off_rails_detection,
             CpuOption::EnableWaitStates(_) => self.enable_wait_states,
+            CpuOption::SegmentOverrun(_) => self.segment_overrun,
             CpuOption::TraceLoggingEnabled(_) => self.trace_enabled,
             CpuOption::EnableServiceInterrupt(_) => self.enable_service_interrupt,
+            CpuOption::OpcodeProfiling(_) => self.opcode_profiler.is_some(),
+            CpuOption::InterruptTrace(..) => self.int_trace,
+            CpuOption::StackGuard(..) => self.stack_guard,
         }
     }
 
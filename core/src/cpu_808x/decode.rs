@@ -772,6 +772,15 @@ impl Intel808x {
             (operand2_type, operand2_size) = match_op(op_lu.operand2);
         }
 
+        if let Mnemonic::ESC = op_lu.mnemonic {
+            // ESC opcode, operand. Move the modrm operand to the second operand and present the
+            // 6-bit escape code as the first, as MASM does.
+            operand2_type = operand1_type;
+            operand2_size = operand1_size;
+            operand1_type = OperandType::Immediate8(((opcode & 0x07) << 3) | modrm.get_op_extension());
+            operand1_size = OperandSize::Operand8;
+        }
+
         // Disabled: Decode cannot fail, but this is a placeholder for future error handling in other CPUs
         /*
         if let Mnemonic::InvalidOpcode = op_lu.mnemonic {
@@ -794,3 +803,40 @@ impl Intel808x {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusInterface;
+
+    fn decode_bytes(bytes: &[u8]) -> Instruction {
+        let mut bus = BusInterface::default();
+        bus.patch_from(&bytes.to_vec(), 0).unwrap();
+        bus.seek(0);
+        Intel808x::decode(&mut bus, true).unwrap()
+    }
+
+    #[test]
+    fn test_decode_undocumented() {
+        // Undocumented opcodes and aliases of the 8088 must decode to their real behavior.
+        #[rustfmt::skip]
+        let cases: [(&[u8], Mnemonic, u32); 10] = [
+            (&[0x0F],             Mnemonic::POP,   1), // POP CS
+            (&[0x60, 0x00],       Mnemonic::JO,    2), // Alias of 0x70
+            (&[0x6F, 0x00],       Mnemonic::JNLE,  2), // Alias of 0x7F
+            (&[0xC0, 0x00, 0x00], Mnemonic::RETN,  3), // Alias of 0xC2
+            (&[0xC1],             Mnemonic::RETN,  1), // Alias of 0xC3
+            (&[0xC8, 0x00, 0x00], Mnemonic::RETF,  3), // Alias of 0xCA
+            (&[0xD6],             Mnemonic::SALC,  1),
+            (&[0xD0, 0xF0],       Mnemonic::SETMO, 2), // D0 /6
+            (&[0xD4, 0x07],       Mnemonic::AAM,   2), // AAM with non-standard base
+            (&[0xF6, 0xC8, 0x00], Mnemonic::TEST,  3), // F6 /1 alias of F6 /0
+        ];
+
+        for (bytes, mnemonic, size) in cases {
+            let i = decode_bytes(bytes);
+            assert_eq!(i.mnemonic, mnemonic, "opcode {:02X}", bytes[0]);
+            assert_eq!(i.size, size, "opcode {:02X}", bytes[0]);
+        }
+    }
+}
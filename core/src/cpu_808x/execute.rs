@@ -694,6 +694,10 @@ impl Intel808x {
             0x9B => {
                 // WAIT
                 cycles!(self, 3);
+                // WAIT samples the TEST pin every 5 cycles until it is asserted by the coprocessor.
+                while !self.test_pin_asserted() {
+                    cycles!(self, 5);
+                }
             }
             0x9C => {
                 // PUSHF - Push Flags
@@ -1235,7 +1239,8 @@ impl Intel808x {
                 // ESC - FPU instructions. 
                 
                 // Perform dummy read if memory operand
-                let _op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override);
+                let _op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override);
+                self.fpu_escape();
             }
             0xE0 | 0xE1 => {
                 // LOOPNE & LOOPE
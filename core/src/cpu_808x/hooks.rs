@@ -0,0 +1,100 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_808x::hooks.rs
+
+    Implements registration and dispatch of instruction hooks.
+
+*/
+
+use crate::{
+    cpu_808x::*,
+    cpu_common::{HookContext, HookId, HookPoint, HookRegisters, InstructionHook},
+};
+
+impl Intel808x {
+    /// Register a hook to be called before and after each instruction. Returns an id that can be
+    /// passed to [Self::remove_instruction_hook].
+    pub fn add_instruction_hook(&mut self, hook: InstructionHook) -> HookId {
+        let id = self.next_hook_id;
+        self.next_hook_id += 1;
+        self.instruction_hooks.push((id, hook));
+        id
+    }
+
+    pub fn remove_instruction_hook(&mut self, id: HookId) -> bool {
+        let len = self.instruction_hooks.len();
+        self.instruction_hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.instruction_hooks.len() != len
+    }
+
+    /// Call all registered instruction hooks for the current instruction, then write back any
+    /// registers they modified.
+    pub fn run_instruction_hooks(&mut self, point: HookPoint) {
+        let mut regs = HookRegisters {
+            ax:    self.a.x(),
+            bx:    self.b.x(),
+            cx:    self.c.x(),
+            dx:    self.d.x(),
+            sp:    self.sp,
+            bp:    self.bp,
+            si:    self.si,
+            di:    self.di,
+            cs:    self.cs,
+            ds:    self.ds,
+            ss:    self.ss,
+            es:    self.es,
+            ip:    self.ip(),
+            flags: self.flags,
+        };
+        let original = regs;
+
+        let mut context = HookContext {
+            point,
+            instruction: &self.i,
+            regs: &mut regs,
+            bus: &mut self.bus,
+        };
+        for (_, hook) in self.instruction_hooks.iter_mut() {
+            hook(&mut context);
+        }
+
+        if regs != original {
+            self.set_register16(Register16::AX, regs.ax);
+            self.set_register16(Register16::BX, regs.bx);
+            self.set_register16(Register16::CX, regs.cx);
+            self.set_register16(Register16::DX, regs.dx);
+            self.sp = regs.sp;
+            self.bp = regs.bp;
+            self.si = regs.si;
+            self.di = regs.di;
+            self.ds = regs.ds;
+            self.ss = regs.ss;
+            self.es = regs.es;
+            self.set_flags(regs.flags);
+        }
+    }
+}
@@ -34,6 +34,7 @@ use crate::{
     cpu_808x::*,
     cpu_common::{Segment, ServiceEvent},
     cycles_mc,
+    interrupt::describe_interrupt,
 };
 
 impl Intel808x {
@@ -82,6 +83,15 @@ impl Intel808x {
             return;
         }
 
+        if self.int_trace && (self.int_trace_filter.is_empty() || self.int_trace_filter.contains(&interrupt)) {
+            log::info!(
+                "[{:04X}:{:04X}] {}",
+                self.cs,
+                self.instruction_ip,
+                describe_interrupt(interrupt, &self.get_state(), &self.bus)
+            );
+        }
+
         cycles_mc!(self, 0x19d, 0x19e, 0x19f);
 
         // Read the IVT
@@ -257,12 +267,12 @@ impl Intel808x {
         self.farcall2(new_cs, new_ip);
     }
 
-    /// Perform a hardware interrupt
-    pub fn hw_interrupt(&mut self, vector: u8) {
+    /// Perform a hardware interrupt. Returns the vector read from the PIC during INTA.
+    pub fn hw_interrupt(&mut self) -> u8 {
         self.in_int = true;
         // Begin IRQ routine
         self.set_mc_pc(0x19a);
-        self.biu_inta(vector);
+        let vector = self.biu_inta();
         self.biu_fetch_suspend();
         cycles_mc!(self, 0x19b, 0x19c);
 
@@ -270,6 +280,7 @@ impl Intel808x {
         self.intr_routine(vector, InterruptType::Hardware, false);
         self.int_count += 1;
         self.in_int = false;
+        vector
     }
 
     /// Perform INT0 (Divide By 0)
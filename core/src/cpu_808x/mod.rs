@@ -37,10 +37,14 @@ use crate::cpu_common::{
     instruction::Instruction,
     AddressingMode,
     CpuAddress,
+    CpuState,
     CpuStringState,
     CpuSubType,
     ExecutionResult,
+    HookId,
+    InstructionHook,
     Mnemonic,
+    OpcodeProfiler,
     QueueOp,
     Segment,
     ServiceEvent,
@@ -61,8 +65,10 @@ mod cycle;
 mod decode;
 mod display;
 mod execute;
+mod fpu;
 mod fuzzer;
 mod gdr;
+mod hooks;
 mod instruction;
 mod interrupt;
 mod jump;
@@ -83,6 +89,7 @@ use crate::{
     cpu_808x::{microcode::*, queue::InstructionQueue},
     cpu_common::{CpuOption, CpuType, TraceMode},
     cycles_mc,
+    fpu_8087::Fpu8087,
     syntax_token::*,
     tracelogger::TraceLogger,
 };
@@ -136,6 +143,12 @@ const QUEUE_MAX: usize = 6;
 const FETCH_DELAY: u8 = 2;
 
 const CPU_HISTORY_LEN: usize = 32;
+const CPU_HISTORY_MAX_LEN: usize = 65536;
+
+// Registers compared before and after each instruction when instruction history is enabled.
+const HISTORY_REGISTER_NAMES: [&str; 13] = [
+    "AX", "BX", "CX", "DX", "SP", "BP", "SI", "DI", "CS", "DS", "ES", "SS", "FLAGS",
+];
 const CPU_CALL_STACK_LEN: usize = 48;
 
 const INTERRUPT_VEC_LEN: usize = 4;
@@ -276,13 +289,13 @@ pub const REGISTER16_LUT: [Register16; 8] = [
 pub const SEGMENT_REGISTER16_LUT: [Register16; 4] = [Register16::ES, Register16::CS, Register16::SS, Register16::DS];
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum CpuState {
+pub enum CpuRunState {
     Normal,
     BreakpointHit,
 }
-impl Default for CpuState {
+impl Default for CpuRunState {
     fn default() -> Self {
-        CpuState::Normal
+        CpuRunState::Normal
     }
 }
 
@@ -405,6 +418,8 @@ pub enum HistoryEntry {
         interrupt: bool,
         jump: bool,
         i: Instruction,
+        bytes: Vec<u8>,
+        deltas: Vec<(&'static str, u16)>,
     },
     InterruptEntry {
         cs: u16,
@@ -472,7 +487,7 @@ pub struct I8288 {
 pub struct Intel808x {
     cpu_type: CpuType,
     cpu_subtype: CpuSubType,
-    state: CpuState,
+    state: CpuRunState,
 
     a: GeneralRegister,
     b: GeneralRegister,
@@ -541,7 +556,20 @@ pub struct Intel808x {
     final_transfer: bool, // Flag that determines if the current bus transfer is the final transfer for this bus request
     bus_wait_states: u32,
     wait_states: u32,
-    lock: bool, // LOCK pin. Asserted during 2nd INTA bus cycle.
+    lock: bool,           // LOCK pin. Asserted during 2nd INTA bus cycle and for LOCK-prefixed instructions.
+    test_busy_until: u64, // Cycle number at which the TEST pin is next asserted (coprocessor idle).
+    fpu: Option<Fpu8087>, // Installed 8087 coprocessor, if any.
+    fpu_irq: bool,        // State of the coprocessor's INT output.
+    instruction_hooks: Vec<(HookId, InstructionHook)>,
+    next_hook_id: HookId,
+    opcode_profiler: Option<Box<OpcodeProfiler>>,
+    int_trace: bool,
+    int_trace_filter: Vec<u8>, // Interrupt vectors to trace. If empty, all vectors are traced.
+    stack_guard: bool,
+    stack_guard_limit: u16, // Pushes that take SP below this offset are reported.
+    stack_guard_wrap: bool, // Report SP wrapping around either end of the stack segment.
+    stack_guard_break: bool,
+    stack_guard_tripped: bool,
 
     // Halt-related stuff
     halted: bool,
@@ -578,6 +606,9 @@ pub struct Intel808x {
     instruction_address: u32,
     instruction_history_on: bool,
     instruction_history: VecDeque<HistoryEntry>,
+    instruction_history_len: usize,
+    history_regs: [u16; 13],
+    history_bytes: Vec<u8>,
 
     services:    CPUDebugServices,
     call_stack:  VecDeque<CallStackEntry>,
@@ -604,6 +635,7 @@ pub struct Intel808x {
 
     enable_wait_states: bool,
     off_rails_detection: bool,
+    segment_overrun: bool, // Word accesses at offset FFFFh continue into the next 64K instead of wrapping.
     opcode0_counter: u32,
 
     rng: Option<rand::rngs::StdRng>,
@@ -860,6 +892,7 @@ impl Intel808x {
 
         //cpu.instruction_history_on = true; // Control this from config/GUI instead
         cpu.instruction_history = VecDeque::with_capacity(16);
+        cpu.instruction_history_len = CPU_HISTORY_LEN;
 
         cpu.reset_vector = CpuAddress::Segmented(0xFFFF, 0x0000);
 
@@ -901,7 +934,7 @@ impl Intel808x {
         };
         */
 
-        self.state = CpuState::Normal;
+        self.state = CpuRunState::Normal;
 
         self.set_register16(Register16::AX, 0);
         self.set_register16(Register16::BX, 0);
@@ -943,12 +976,18 @@ impl Intel808x {
         self.iret_count = 0;
         self.instr_cycle = 0;
         self.cycle_num = 1;
+        self.test_busy_until = 0;
+        if let Some(fpu) = self.fpu.as_mut() {
+            fpu.reset();
+        }
+        self.fpu_irq = false;
         self.halt_cycles = 0;
         self.t_stamp = 0.0;
         self.t_step = 0.00000021;
         self.t_step_h = 0.000000105;
         self.ready = true;
         self.in_rep = false;
+        self.lock = false;
         self.halted = false;
         self.reported_halt = false;
         self.halt_not_hold = false;
@@ -1171,7 +1210,8 @@ impl Intel808x {
     }
 
     pub fn set_nmi(&mut self, nmi_state: bool) {
-        if nmi_state == false {
+        // The NMI line is the OR of the bus's NMI sources and the coprocessor's INT output.
+        if nmi_state == false && !self.fpu_irq {
             self.nmi_triggered = false;
         }
         self.nmi = nmi_state;
@@ -1452,7 +1492,7 @@ impl Intel808x {
         }
     }
 
-    pub fn get_state(&self) -> CpuRegisterState {
+    pub fn get_register_state(&self) -> CpuRegisterState {
         CpuRegisterState {
             ah:    self.a.h(),
             al:    self.a.l(),
@@ -1480,6 +1520,59 @@ impl Intel808x {
         }
     }
 
+    /// Capture the architectural state of the CPU. This is only meaningful at an instruction boundary;
+    /// in-progress bus cycles and the state of an interrupted REP string instruction are not captured.
+    pub fn get_state(&self) -> CpuState {
+        CpuState {
+            ax: self.a.x(),
+            bx: self.b.x(),
+            cx: self.c.x(),
+            dx: self.d.x(),
+            sp: self.sp,
+            bp: self.bp,
+            si: self.si,
+            di: self.di,
+            cs: self.cs,
+            ds: self.ds,
+            ss: self.ss,
+            es: self.es,
+            ip: self.ip(),
+            flags: self.flags,
+            queue: self.queue.to_vec(),
+            queue_preload: self.queue.has_preload(),
+            halted: self.halted,
+        }
+    }
+
+    /// Restore the architectural state of the CPU from a previously captured [CpuState].
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.set_register16(Register16::AX, state.ax);
+        self.set_register16(Register16::BX, state.bx);
+        self.set_register16(Register16::CX, state.cx);
+        self.set_register16(Register16::DX, state.dx);
+        self.sp = state.sp;
+        self.bp = state.bp;
+        self.si = state.si;
+        self.di = state.di;
+        self.cs = state.cs;
+        self.ds = state.ds;
+        self.ss = state.ss;
+        self.es = state.es;
+        self.set_flags(state.flags);
+
+        // PC points past the end of the queue, so rebuild it from IP as the queue is refilled.
+        self.queue.flush();
+        self.pc = state.ip;
+        self.set_queue_contents(state.queue.clone());
+        if state.queue_preload && !state.queue.is_empty() {
+            self.queue.set_preload();
+        }
+
+        self.halted = state.halted;
+        self.reported_halt = false;
+        self.in_rep = false;
+    }
+
     /// Get a string representation of the CPU state.
     /// This is used to display the CPU state viewer window in the debug GUI.
     pub fn get_string_state(&self) -> CpuStringState {
@@ -1740,7 +1833,9 @@ impl Intel808x {
 
     /// Set CPU breakpoints from provided list.
     ///
-    /// Clears bus breakpoint flags from previous breakpoint list before applying new.
+    /// Clears bus breakpoint flags from previous breakpoint list before applying new. MEM_BPA_BIT is shared
+    /// by memory access breakpoints and watchpoints, so it is only cleared from addresses that no breakpoint
+    /// in the new list still covers.
     pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
         // Clear bus flags for current breakpoints
         self.breakpoints.iter().for_each(|bp| match bp {
@@ -1749,7 +1844,16 @@ impl Intel808x {
                 self.bus.clear_flags(*addr as usize, MEM_BPE_BIT);
             }
             BreakPointType::MemAccessFlat(addr) => {
-                self.bus.clear_flags(*addr as usize, MEM_BPA_BIT);
+                if !Self::mem_access_flagged(&bp_list, *addr) {
+                    self.bus.clear_flags(*addr as usize, MEM_BPA_BIT);
+                }
+            }
+            BreakPointType::MemWatchFlat(start, end, _) => {
+                for addr in *start..*end {
+                    if !Self::mem_access_flagged(&bp_list, addr) {
+                        self.bus.clear_flags(addr as usize, MEM_BPA_BIT);
+                    }
+                }
             }
             BreakPointType::Interrupt(vector) => {
                 self.int_flags[*vector as usize] = 0;
@@ -1779,6 +1883,17 @@ impl Intel808x {
                 log::debug!("Setting breakpoint on memory access at address: {:05X}", *addr);
                 self.bus.set_flags(*addr as usize, MEM_BPA_BIT);
             }
+            BreakPointType::MemWatchFlat(start, end, mode) => {
+                log::debug!(
+                    "Setting {:?} watchpoint at addresses: [{:05X}-{:05X})",
+                    mode,
+                    *start,
+                    *end
+                );
+                for addr in *start..*end {
+                    self.bus.set_flags(addr as usize, MEM_BPA_BIT);
+                }
+            }
             BreakPointType::Interrupt(vector) => {
                 self.int_flags[*vector as usize] = INTERRUPT_BREAKPOINT;
             }
@@ -1805,6 +1920,11 @@ impl Intel808x {
             BreakPointType::MemAccessFlat(addr) => {
                 self.bus.set_flags(*addr as usize, MEM_BPA_BIT);
             }
+            BreakPointType::MemWatchFlat(start, end, _) => {
+                for addr in *start..*end {
+                    self.bus.set_flags(addr as usize, MEM_BPA_BIT);
+                }
+            }
             BreakPointType::Interrupt(vector) => {
                 self.int_flags[*vector as usize] = INTERRUPT_BREAKPOINT;
             }
@@ -1821,6 +1941,47 @@ impl Intel808x {
         }
     }
 
+    /// Determine whether any breakpoint in the list requires MEM_BPA_BIT to be set at an address.
+    fn mem_access_flagged(bp_list: &[BreakPointType], address: u32) -> bool {
+        bp_list.iter().any(|bp| match bp {
+            BreakPointType::MemAccessFlat(addr) => *addr == address,
+            BreakPointType::MemWatchFlat(start, end, _) => (*start..*end).contains(&address),
+            _ => false,
+        })
+    }
+
+    /// Determine whether a bus cycle to an address flagged with MEM_BPA_BIT hits a memory breakpoint or
+    /// watchpoint, logging the accessing instruction if so.
+    fn check_mem_breakpoint(&self, address: u32, status: BusStatus) -> bool {
+        let (access, write) = match status {
+            BusStatus::MemRead => ("read", false),
+            BusStatus::MemWrite => ("write", true),
+            BusStatus::CodeFetch => ("fetch", false),
+            _ => return false,
+        };
+
+        // Memory breakpoints catch code fetches as well as data accesses. Watchpoints only track data accesses,
+        // so prefetching past a watched range doesn't stop execution.
+        let hit = self.breakpoints.iter().any(|bp| match bp {
+            BreakPointType::MemAccessFlat(addr) => *addr == address,
+            BreakPointType::MemWatchFlat(start, end, mode) => {
+                status != BusStatus::CodeFetch && (*start..*end).contains(&address) && mode.matches(write)
+            }
+            _ => false,
+        });
+
+        if hit {
+            log::warn!(
+                "Memory {} breakpoint hit at [{:05X}] by instruction at [{:04X}:{:04X}]",
+                access,
+                address,
+                self.cs,
+                self.instruction_ip
+            );
+        }
+        hit
+    }
+
     pub fn set_stopwatch(&mut self, sw_idx: usize, start: u32, stop: u32) {
         if self.stopwatches.is_empty() {
             self.stopwatches.push(None);
@@ -1865,15 +2026,90 @@ impl Intel808x {
     }
 
     pub fn get_breakpoint_flag(&self) -> bool {
-        matches!(self.state, CpuState::BreakpointHit)
+        matches!(self.state, CpuRunState::BreakpointHit)
+    }
+
+    /// Return the state of the TEST pin. TEST is asserted unless an attached coprocessor is busy.
+    #[inline]
+    pub fn test_pin_asserted(&self) -> bool {
+        self.cycle_num >= self.test_busy_until
+    }
+
+    /// Deassert the TEST pin for the specified number of cycles, as a coprocessor does while
+    /// executing an instruction.
+    pub fn set_test_busy(&mut self, cycles: u32) {
+        self.test_busy_until = self.cycle_num + cycles as u64;
     }
 
     pub fn set_breakpoint_flag(&mut self) {
-        self.state = CpuState::BreakpointHit;
+        self.state = CpuRunState::BreakpointHit;
     }
 
     pub fn clear_breakpoint_flag(&mut self) {
-        self.state = CpuState::Normal;
+        self.state = CpuRunState::Normal;
+    }
+
+    /// Set the maximum number of entries kept in the instruction history.
+    pub fn set_instruction_history_len(&mut self, len: usize) {
+        self.instruction_history_len = len.clamp(1, CPU_HISTORY_MAX_LEN);
+        while self.instruction_history.len() > self.instruction_history_len {
+            self.instruction_history.pop_front();
+        }
+    }
+
+    fn push_history(&mut self, entry: HistoryEntry) {
+        while self.instruction_history.len() >= self.instruction_history_len {
+            self.instruction_history.pop_front();
+        }
+        self.instruction_history.push_back(entry);
+    }
+
+    fn history_registers(&self) -> [u16; 13] {
+        [
+            self.a.x(),
+            self.b.x(),
+            self.c.x(),
+            self.d.x(),
+            self.sp,
+            self.bp,
+            self.si,
+            self.di,
+            self.cs,
+            self.ds,
+            self.es,
+            self.ss,
+            self.flags,
+        ]
+    }
+
+    /// Read the bytes of the current instruction without side effects, wrapping at the end of the
+    /// address space.
+    fn history_instruction_bytes(&self, address: u32) -> Vec<u8> {
+        (0..self.i.size)
+            .map(|n| {
+                self.bus
+                    .peek_u8((address.wrapping_add(n) & 0xFFFFF) as usize)
+                    .unwrap_or(0xFF)
+            })
+            .collect()
+    }
+
+    /// Return the registers that changed since the snapshot taken before the current instruction.
+    fn history_deltas(&self) -> Vec<(&'static str, u16)> {
+        let regs = self.history_registers();
+        HISTORY_REGISTER_NAMES
+            .iter()
+            .zip(regs.iter().zip(self.history_regs.iter()))
+            .filter(|(_, (new, old))| new != old)
+            .map(|(name, (new, _))| (*name, *new))
+            .collect()
+    }
+
+    fn format_history_deltas(deltas: &[(&'static str, u16)]) -> String {
+        deltas
+            .iter()
+            .map(|(name, value)| format!(" {}={:04X}", name, value))
+            .collect()
     }
 
     pub fn dump_instruction_history_string(&self) -> String {
@@ -1888,14 +2124,17 @@ impl Intel808x {
                     interrupt,
                     jump: _,
                     i,
+                    bytes: _,
+                    deltas,
                 } => {
                     let i_string = format!(
-                        "{:05X}{} [{:04X}:{:04X}] {}\n",
+                        "{:05X}{} [{:04X}:{:04X}] {}{}\n",
                         i.address,
                         if *interrupt { '*' } else { ' ' },
                         *cs,
                         *ip,
-                        i
+                        i,
+                        Self::format_history_deltas(deltas)
                     );
                     disassembly_string.push_str(&i_string);
                 }
@@ -1918,6 +2157,8 @@ impl Intel808x {
                     interrupt,
                     jump,
                     i,
+                    bytes,
+                    deltas,
                 } => {
                     if *jump {
                         i_token_vec.push(SyntaxToken::Formatter(SyntaxFormatType::HighlightLine(
@@ -1930,10 +2171,16 @@ impl Intel808x {
                         *ip,
                         format!("{:04X}:{:04X}{}", cs, ip, if *interrupt { '*' } else { ' ' }),
                     ));
-                    i_token_vec.push(SyntaxToken::InstructionBytes(format!("{:012}", " ")));
+                    let mut bytes_str: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    bytes_str.truncate(12);
+                    i_token_vec.push(SyntaxToken::InstructionBytes(format!("{:12}", bytes_str)));
                     i_token_vec.extend(i.tokenize());
                     i_token_vec.push(SyntaxToken::Formatter(SyntaxFormatType::Tab));
                     i_token_vec.push(SyntaxToken::Text(format!("{}", *cycles)));
+                    if !deltas.is_empty() {
+                        i_token_vec.push(SyntaxToken::Formatter(SyntaxFormatType::Tab));
+                        i_token_vec.push(SyntaxToken::Text(Self::format_history_deltas(deltas)));
+                    }
                 }
                 HistoryEntry::InterruptEntry { cs, ip, cycles: _, iv } => {
                     i_token_vec.push(SyntaxToken::Formatter(SyntaxFormatType::HighlightLine(
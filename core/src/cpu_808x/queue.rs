@@ -182,15 +182,82 @@ impl InstructionQueue {
         base_str
     }
 
+    /// Return the contents of the processor instruction queue in order, including any preloaded byte.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len_p());
+
+        if let Some(preload) = self.preload {
+            bytes.push(preload);
+        }
+        for i in 0..self.len {
+            bytes.push(self.q[(self.back + i) % self.size]);
+        }
+        bytes
+    }
+
     /// Write the contents of the processor instruction queue in order to the
-    /// provided slice of u8. The slice must be the same size as the current piq
-    /// length for the given cpu type.
+    /// provided slice of u8. If the slice is shorter than the current queue
+    /// length, only the oldest bytes that fit are written. This allows the
+    /// 6-byte queue of the 8086 to be captured into 4-byte cycle states.
     #[allow(dead_code)]
     pub fn to_slice(&self, slice: &mut [u8]) {
-        assert_eq!(self.size, slice.len());
-
-        for i in 0..self.len {
+        let len = std::cmp::min(self.len, slice.len());
+        for i in 0..len {
             slice[i] = self.q[(self.back + i) % self.size];
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_wrap() {
+        let mut q = InstructionQueue::new(4, 1);
+        for b in 0..4 {
+            q.push8(b);
+        }
+        assert!(q.is_full());
+        assert_eq!(q.pop(), 0);
+        assert_eq!(q.pop(), 1);
+        q.push8(4);
+        q.push8(5);
+        assert_eq!(q.to_string(), "02030405");
+
+        q.set_preload();
+        assert_eq!(q.len(), 3);
+        assert_eq!(q.len_p(), 4);
+        assert_eq!(q.get_preload(), Some(2));
+        assert!(!q.has_preload());
+    }
+
+    #[test]
+    fn test_queue_to_slice() {
+        let mut q = InstructionQueue::new(6, 2);
+        q.push16(0x0201);
+        q.push16(0x0403);
+        q.push16(0x0605);
+
+        // A short slice receives the oldest bytes
+        let mut short = [0u8; 4];
+        q.to_slice(&mut short);
+        assert_eq!(short, [1, 2, 3, 4]);
+
+        let mut full = [0u8; 6];
+        q.to_slice(&mut full);
+        assert_eq!(full, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_queue_to_vec_preload() {
+        let mut q = InstructionQueue::new(4, 1);
+        q.push8(0xB8);
+        q.push8(0x34);
+        q.push8(0x12);
+        q.set_preload();
+
+        // The preloaded byte is reported first, followed by the remaining queue contents
+        assert_eq!(q.to_vec(), vec![0xB8, 0x34, 0x12]);
+    }
+}
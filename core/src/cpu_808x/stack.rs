@@ -39,12 +39,14 @@ impl Intel808x {
     pub fn push_u8(&mut self, data: u8, flag: ReadWriteFlag) {
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_sub(2);
+        self.stack_guard_push();
         self.biu_write_u8(Segment::SS, self.sp, data, flag);
     }
 
     pub fn push_u16(&mut self, data: u16, flag: ReadWriteFlag) {
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_sub(2);
+        self.stack_guard_push();
         self.biu_write_u16(Segment::SS, self.sp, data, flag);
     }
 
@@ -53,12 +55,14 @@ impl Intel808x {
 
         // Stack pointer shrinks upwards
         self.sp = self.sp.wrapping_add(2);
+        self.stack_guard_pop();
         result
     }
 
     pub fn push_register16(&mut self, reg: Register16, flag: ReadWriteFlag) {
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_sub(2);
+        self.stack_guard_push();
 
         let data = match reg {
             Register16::AX => self.a.x(),
@@ -118,25 +122,22 @@ impl Intel808x {
             Register16::DI => self.di = data,
             Register16::CS => {
                 self.cs = data;
-                if let CpuSubType::Harris80C88 = self.cpu_subtype {
-                    self.interrupt_inhibit = true;
-                }
+                self.interrupt_inhibit = true;
             }
             Register16::DS => {
                 self.ds = data;
-                if let CpuSubType::Harris80C88 = self.cpu_subtype {
-                    self.interrupt_inhibit = true;
-                }
+                self.interrupt_inhibit = true;
             }
             Register16::SS => {
                 self.ss = data;
-                self.interrupt_inhibit = true
+                self.interrupt_inhibit = true;
+                // A trap here would push a frame onto a half-switched stack, so the 8088
+                // suppresses single-step along with INTR until the next instruction completes.
+                self.trap_suppressed = true;
             }
             Register16::ES => {
                 self.es = data;
-                if let CpuSubType::Harris80C88 = self.cpu_subtype {
-                    self.interrupt_inhibit = true;
-                }
+                self.interrupt_inhibit = true;
             }
             Register16::PC => self.pc = data,
             _ => panic!("Invalid register"),
@@ -144,12 +145,14 @@ impl Intel808x {
         // Stack pointer grows downwards
         if update_sp {
             self.sp = self.sp.wrapping_add(2);
+            self.stack_guard_pop();
         }
     }
 
     pub fn push_flags(&mut self, wflag: ReadWriteFlag) {
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_sub(2);
+        self.stack_guard_push();
         self.biu_write_u16(Segment::SS, self.sp, self.flags, wflag);
     }
 
@@ -182,9 +185,74 @@ impl Intel808x {
 
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_add(2);
+        self.stack_guard_pop();
     }
 
     pub fn release(&mut self, disp: u16) {
         self.sp = self.sp.wrapping_add(disp);
     }
+
+    /// Check for a stack overflow after a push. SP wrapping below zero is reported if wrap checking is enabled;
+    /// descending into the guard region below the configured limit is reported once each time the guard region
+    /// is entered.
+    #[inline]
+    fn stack_guard_push(&mut self) {
+        if !self.stack_guard {
+            return;
+        }
+        if self.stack_guard_wrap && self.sp >= 0xFFFE {
+            log::warn!(
+                "Stack guard: SP wrapped below 0000 (overflow) at [{:04X}:{:04X}], SS:SP {:04X}:{:04X}",
+                self.cs,
+                self.instruction_ip,
+                self.ss,
+                self.sp
+            );
+            self.stack_guard_hit();
+        }
+        else if self.sp < self.stack_guard_limit {
+            if !self.stack_guard_tripped {
+                self.stack_guard_tripped = true;
+                log::warn!(
+                    "Stack guard: SP entered guard region below {:04X} at [{:04X}:{:04X}], SS:SP {:04X}:{:04X}",
+                    self.stack_guard_limit,
+                    self.cs,
+                    self.instruction_ip,
+                    self.ss,
+                    self.sp
+                );
+                self.stack_guard_hit();
+            }
+        }
+        else {
+            self.stack_guard_tripped = false;
+        }
+    }
+
+    /// Check for a stack underflow after a pop, if wrap checking is enabled.
+    #[inline]
+    fn stack_guard_pop(&mut self) {
+        if !self.stack_guard {
+            return;
+        }
+        if self.stack_guard_wrap && self.sp < 2 {
+            log::warn!(
+                "Stack guard: SP wrapped above FFFF (underflow) at [{:04X}:{:04X}], SS:SP {:04X}:{:04X}",
+                self.cs,
+                self.instruction_ip,
+                self.ss,
+                self.sp
+            );
+            self.stack_guard_hit();
+        }
+        else if self.sp >= self.stack_guard_limit {
+            self.stack_guard_tripped = false;
+        }
+    }
+
+    fn stack_guard_hit(&mut self) {
+        if self.stack_guard_break {
+            self.set_breakpoint_flag();
+        }
+    }
 }
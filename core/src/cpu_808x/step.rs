@@ -32,7 +32,16 @@
 
 use crate::{
     cpu_808x::{decode::DECODE, *},
-    cpu_common::{CpuAddress, CpuError, CpuException, Disassembly, ExecutionResult, StepResult},
+    cpu_common::{
+        CpuAddress,
+        CpuError,
+        CpuException,
+        Disassembly,
+        ExecutionResult,
+        HookPoint,
+        StepResult,
+        OPCODE_PREFIX_LOCK,
+    },
     gdr,
 };
 
@@ -173,6 +182,13 @@ impl Intel808x {
                 }
             };
 
+            // Snapshot registers and instruction bytes so that instruction history can record what the
+            // instruction changed. Bytes are captured now, as the instruction may modify its own code.
+            if self.instruction_history_on {
+                self.history_regs = self.history_registers();
+                self.history_bytes = self.history_instruction_bytes(instruction_address);
+            }
+
             // Begin the current instruction validation context.
             #[cfg(feature = "cpu_validator")]
             {
@@ -183,12 +199,25 @@ impl Intel808x {
         // Since Cpu::decode doesn't know anything about the current IP, it can't set it, so we do that now.
         self.i.address = instruction_address;
 
+        // A LOCK prefix asserts the LOCK pin for the duration of the instruction, which keeps the bus from being
+        // granted to DMA (and therefore DRAM refresh) until the instruction completes. The 8088 holds LOCK across
+        // every iteration of a REP string instruction as well; if the instruction is interrupted, LOCK is released
+        // by INTA and reasserted when the prefixes are fetched again on resumption.
+        if self.i.prefixes & OPCODE_PREFIX_LOCK != 0 {
+            self.lock = true;
+        }
+
         // Uncomment to debug instruction fetch
         //self.debug_fetch(instruction_address);
 
         self.last_cs = self.cs;
         self.last_ip = self.instruction_ip;
 
+        // Run pre-execution hooks. Hooks are called once for a REP string instruction, not per iteration.
+        if !self.instruction_hooks.is_empty() && !self.in_rep {
+            self.run_instruction_hooks(HookPoint::PreExecute);
+        }
+
         // Load the mod/rm operand for the instruction, if applicable.
         self.load_operand();
 
@@ -201,6 +230,14 @@ impl Intel808x {
         // Execute the current decoded instruction.
         self.exec_result = self.execute_instruction();
 
+        if let Some(profiler) = &mut self.opcode_profiler {
+            profiler.record(self.i.opcode, instruction_address, self.instr_cycle);
+        }
+
+        if !self.instruction_hooks.is_empty() && !matches!(self.exec_result, ExecutionResult::OkayRep) {
+            self.run_instruction_hooks(HookPoint::PostExecute);
+        }
+
         let step_result = match &self.exec_result {
             ExecutionResult::Okay => {
                 // Normal non-jump instruction updates CS:IP to next instruction during execute()
@@ -282,6 +319,12 @@ impl Intel808x {
             }
         };
 
+        // Release a LOCK prefix once the instruction has completed. REP string instructions keep LOCK asserted
+        // until their final iteration.
+        if (self.i.prefixes & OPCODE_PREFIX_LOCK != 0) && !matches!(self.exec_result, ExecutionResult::OkayRep) {
+            self.lock = false;
+        }
+
         // Reset interrupt pending flag - this flag is set on step_finish() and
         // only valid for a single instruction execution.
         self.intr_pending = false;
@@ -314,7 +357,7 @@ impl Intel808x {
         self.int_elapsed = 0;
         self.device_cycles = 0;
 
-        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
+        if (self.nmi || self.fpu_irq) && self.bus.nmi_enabled() && !self.nmi_triggered {
             // NMI takes priority over trap and INTR.
             if self.halted {
                 // Resume from halt on interrupt
@@ -345,28 +388,16 @@ impl Intel808x {
                     self.resume();
                 }
 
-                // Query the PIC to get the interrupt vector.
-                // This is a bit artificial as we don't actually read the IV during the 2nd
-                // INTA cycle like the CPU does, instead we save the value now and simulate it later.
-                // TODO: Think about changing this to query during INTA
-                if let Some(pic) = self.bus.pic_mut().as_mut() {
-                    // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
-                    if pic.query_interrupt_line() {
-                        if let Some(iv) = pic.get_interrupt_vector() {
-                            irq = iv;
-                        }
-                    }
-                }
-
                 // We will be jumping into an ISR now. Set the step result to Call and return
                 // the address of the next instruction. (Step Over skips ISRs)
                 step_result = StepResult::Call(CpuAddress::Segmented(self.cs, self.ip()));
 
+                // The interrupt vector is read from the PIC during the 2nd INTA bus cycle.
+                irq = self.hw_interrupt();
                 if self.int_flags[irq as usize] != 0 {
                     // This interrupt has a breakpoint
                     self.set_breakpoint_flag();
                 }
-                self.hw_interrupt(irq);
                 did_interrupt = true;
                 self.biu_fetch_next();
             }
@@ -407,48 +438,36 @@ impl Intel808x {
             // Only add non-reentrant instructions to history, unless they were interrupted.
             // This prevents spamming the history with multiple rep string operations.
             if !self.instruction_reentrant || cur_intr {
-                if self.instruction_history.len() == CPU_HISTORY_LEN {
-                    self.instruction_history.pop_front();
-                }
-
-                self.instruction_history.push_back(HistoryEntry::InstructionEntry {
+                let bytes = self.history_bytes.clone();
+                let deltas = self.history_deltas();
+                self.push_history(HistoryEntry::InstructionEntry {
                     cs: self.last_cs,
                     ip: self.last_ip,
                     cycles: self.instr_cycle as u16,
                     interrupt: self.last_intr,
                     jump: self.jumped,
                     i: self.i.clone(),
+                    bytes,
+                    deltas,
                 });
             }
 
             if did_nmi {
-                if self.instruction_history.len() == CPU_HISTORY_LEN {
-                    self.instruction_history.pop_front();
-                }
-
-                self.instruction_history.push_back(HistoryEntry::NmiEntry {
+                self.push_history(HistoryEntry::NmiEntry {
                     cs: self.last_cs,
                     ip: self.last_ip,
                 });
             }
 
             if did_trap {
-                if self.instruction_history.len() == CPU_HISTORY_LEN {
-                    self.instruction_history.pop_front();
-                }
-
-                self.instruction_history.push_back(HistoryEntry::TrapEntry {
+                self.push_history(HistoryEntry::TrapEntry {
                     cs: self.last_cs,
                     ip: self.last_ip,
                 });
             }
 
             if did_interrupt {
-                if self.instruction_history.len() == CPU_HISTORY_LEN {
-                    self.instruction_history.pop_front();
-                }
-
-                self.instruction_history.push_back(HistoryEntry::InterruptEntry {
+                self.push_history(HistoryEntry::InterruptEntry {
                     cs: self.last_cs,
                     ip: self.last_ip,
                     cycles: self.instr_cycle as u16,
@@ -34,6 +34,7 @@ use crate::{
     cpu_common::{CpuDispatch, CpuSubType, CpuType, TraceMode},
     cpu_validator::{ValidatorMode, ValidatorType},
     cpu_vx0::NecVx0,
+    fpu_8087::Fpu8087,
     tracelogger::TraceLogger,
 };
 use anyhow::{bail, Result};
@@ -44,6 +45,7 @@ pub struct CpuBuilder {
     cpu_subtype: Option<CpuSubType>,
     trace_mode: TraceMode,
     trace_logger: Option<TraceLogger>,
+    fpu: bool,
     #[cfg(feature = "cpu_validator")]
     validator_type: ValidatorType,
     #[cfg(feature = "cpu_validator")]
@@ -79,6 +81,9 @@ impl CpuBuilder {
                         #[cfg(feature = "cpu_validator")]
                         self.validator_baud.take().unwrap_or_default(),
                     );
+                    if self.fpu {
+                        cpu.set_fpu(Some(Fpu8087::new()));
+                    }
                     return Ok(cpu.into());
                 }
                 CpuType::NecV20 => {
@@ -95,6 +100,9 @@ impl CpuBuilder {
                         #[cfg(feature = "cpu_validator")]
                         self.validator_baud.take().unwrap_or_default(),
                     );
+                    if self.fpu {
+                        cpu.set_fpu(Some(Fpu8087::new()));
+                    }
                     return Ok(cpu.into());
                 }
                 _ => {
@@ -127,6 +135,12 @@ impl CpuBuilder {
         self
     }
 
+    /// Install an 8087 coprocessor alongside the CPU.
+    pub fn with_fpu(mut self, fpu: bool) -> Self {
+        self.fpu = fpu;
+        self
+    }
+
     #[cfg(feature = "cpu_validator")]
     pub fn with_validator_type(mut self, validator_type: ValidatorType) -> Self {
         self.validator_type = validator_type;
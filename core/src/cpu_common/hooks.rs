@@ -0,0 +1,75 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_common::hooks.rs
+
+    Defines the instruction hook interface, which allows external tooling to
+    observe or patch execution before and after each instruction.
+
+*/
+
+use crate::{bus::BusInterface, cpu_common::Instruction};
+
+/// Identifies a registered instruction hook so that it may later be removed.
+pub type HookId = usize;
+
+/// A callback invoked before and/or after each instruction is executed.
+pub type InstructionHook = Box<dyn FnMut(&mut HookContext) + Send>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HookPoint {
+    /// The instruction has been fetched and decoded, but not yet executed.
+    PreExecute,
+    /// The instruction has finished executing.
+    PostExecute,
+}
+
+/// Register file presented to instruction hooks. Changes made by a hook are written back to the CPU
+/// when the hook returns, with the exception of CS and IP, which are read-only.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct HookRegisters {
+    pub ax:    u16,
+    pub bx:    u16,
+    pub cx:    u16,
+    pub dx:    u16,
+    pub sp:    u16,
+    pub bp:    u16,
+    pub si:    u16,
+    pub di:    u16,
+    pub cs:    u16,
+    pub ds:    u16,
+    pub ss:    u16,
+    pub es:    u16,
+    pub ip:    u16,
+    pub flags: u16,
+}
+
+pub struct HookContext<'a> {
+    pub point: HookPoint,
+    pub instruction: &'a Instruction,
+    pub regs: &'a mut HookRegisters,
+    pub bus: &'a mut BusInterface,
+}
@@ -36,20 +36,24 @@ pub mod addressing;
 pub mod alu;
 pub mod builder;
 pub mod error;
+pub mod hooks;
 pub mod instruction;
 pub mod mnemonic;
 pub mod operands;
+pub mod profiler;
 pub mod services;
 
 use enum_dispatch::enum_dispatch;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 pub use addressing::{AddressingMode, CpuAddress, Displacement};
 pub use error::CpuError;
+pub use hooks::{HookContext, HookId, HookPoint, HookRegisters, InstructionHook};
 pub use instruction::Instruction;
 pub use mnemonic::Mnemonic;
 pub use operands::OperandType;
+pub use profiler::OpcodeProfiler;
 
 use crate::{
     breakpoints::{BreakPointType, StopWatchData},
@@ -58,6 +62,8 @@ use crate::{
     cpu_808x::Intel808x,
     cpu_validator::{CycleState, VRegisters},
     cpu_vx0::NecVx0,
+    fpu_8087::Fpu8087,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::{SyntaxToken, SyntaxTokenize},
 };
 
@@ -180,6 +186,71 @@ pub struct CpuStringState {
     pub cycle_count: String,
 }
 
+/// A snapshot of the architectural state of a CPU, sufficient to restore it deterministically at an
+/// instruction boundary. IP is stored as the logical instruction pointer; the prefetch queue contents
+/// are stored separately so that the internal program counter can be reconstructed on restore.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuState {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub sp: u16,
+    pub bp: u16,
+    pub si: u16,
+    pub di: u16,
+    pub cs: u16,
+    pub ds: u16,
+    pub ss: u16,
+    pub es: u16,
+    pub ip: u16,
+    pub flags: u16,
+    /// Contents of the prefetch queue, oldest byte first.
+    pub queue: Vec<u8>,
+    /// Whether the first byte of `queue` has been preloaded by the EU for the next instruction.
+    pub queue_preload: bool,
+    pub halted: bool,
+}
+
+impl SaveState for CpuState {
+    fn save_state(&self, w: &mut StateWriter) {
+        for reg in [
+            self.ax, self.bx, self.cx, self.dx, self.sp, self.bp, self.si, self.di, self.cs, self.ds, self.ss, self.es,
+            self.ip, self.flags,
+        ] {
+            w.write_u16(reg);
+        }
+        w.write_bytes(&self.queue);
+        w.write_bool(self.queue_preload);
+        w.write_bool(self.halted);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for reg in [
+            &mut self.ax,
+            &mut self.bx,
+            &mut self.cx,
+            &mut self.dx,
+            &mut self.sp,
+            &mut self.bp,
+            &mut self.si,
+            &mut self.di,
+            &mut self.cs,
+            &mut self.ds,
+            &mut self.ss,
+            &mut self.es,
+            &mut self.ip,
+            &mut self.flags,
+        ] {
+            *reg = r.read_u16()?;
+        }
+        self.queue = r.read_bytes()?.to_vec();
+        self.queue_preload = r.read_bool()?;
+        self.halted = r.read_bool()?;
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Hash)]
 pub enum CpuType {
     Intel8088,
@@ -240,6 +311,7 @@ pub enum TraceMode {
     CycleText,
     CycleCsv,
     CycleSigrok,
+    CycleVcd,
     Instruction,
 }
 
@@ -254,6 +326,7 @@ impl FromStr for TraceMode {
             "cycletext" => Ok(TraceMode::CycleText),
             "cyclecsv" => Ok(TraceMode::CycleCsv),
             "cyclesigrok" => Ok(TraceMode::CycleSigrok),
+            "cyclevcd" => Ok(TraceMode::CycleVcd),
             "instruction" => Ok(TraceMode::Instruction),
             _ => Err("Bad value for tracemode".to_string()),
         }
@@ -274,14 +347,19 @@ impl Default for CpuType {
 #[derive(Debug)]
 pub enum CpuOption {
     InstructionHistory(bool),
+    InstructionHistoryLen(u32),
     ScheduleInterrupt(bool, u32, u32, bool),
     ScheduleDramRefresh(bool, u32, u32, bool),
     DramRefreshAdjust(u32),
     HaltResumeDelay(u32),
     OffRailsDetection(bool),
     EnableWaitStates(bool),
+    SegmentOverrun(bool),
     TraceLoggingEnabled(bool),
     EnableServiceInterrupt(bool),
+    OpcodeProfiling(bool),
+    InterruptTrace(bool, Vec<u8>),
+    StackGuard(bool, u16, bool, bool),
 }
 
 #[derive(Debug)]
@@ -419,6 +497,17 @@ pub trait Cpu {
     fn get_cycle_trace_tokens(&self) -> &Vec<Vec<SyntaxToken>>;
 
     fn get_string_state(&self) -> CpuStringState;
+    fn get_state(&self) -> CpuState;
+    fn set_state(&mut self, state: &CpuState);
+    fn fpu(&self) -> Option<&Fpu8087>;
+    fn set_fpu(&mut self, fpu: Option<Fpu8087>);
+
+    // Hooks
+    fn add_instruction_hook(&mut self, hook: InstructionHook) -> HookId;
+    fn remove_instruction_hook(&mut self, id: HookId) -> bool;
+
+    // Profiling
+    fn opcode_profiler(&self) -> Option<&OpcodeProfiler>;
 
     // Eval
     fn eval_address(&self, expr: &str) -> Option<CpuAddress>;
@@ -0,0 +1,168 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_common::profiler.rs
+
+    Implements an execution profiler that accumulates instruction counts and
+    cycles per opcode and per block of code address space, to help locate
+    hot spots in guest software.
+
+*/
+
+use std::io::Write;
+
+/// Size of a code address bucket, as a power of two. 256 byte buckets divide the 1MB address space into 4096 entries.
+pub const PROFILER_BUCKET_SHIFT: u32 = 8;
+const PROFILER_BUCKETS: usize = 0x100000 >> PROFILER_BUCKET_SHIFT;
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProfileCounter {
+    pub count:  u64,
+    pub cycles: u64,
+}
+
+pub struct OpcodeProfiler {
+    opcodes: [ProfileCounter; 256],
+    buckets: Vec<ProfileCounter>,
+    total_cycles: u64,
+}
+
+impl Default for OpcodeProfiler {
+    fn default() -> Self {
+        Self {
+            opcodes: [ProfileCounter::default(); 256],
+            buckets: vec![ProfileCounter::default(); PROFILER_BUCKETS],
+            total_cycles: 0,
+        }
+    }
+}
+
+impl OpcodeProfiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one execution of `opcode` at the flat address `address`, taking `cycles` cycles.
+    /// Each iteration of a REP string instruction is recorded separately.
+    #[inline]
+    pub fn record(&mut self, opcode: u8, address: u32, cycles: u32) {
+        let op = &mut self.opcodes[opcode as usize];
+        op.count += 1;
+        op.cycles += cycles as u64;
+
+        let bucket = &mut self.buckets[(address as usize & 0xFFFFF) >> PROFILER_BUCKET_SHIFT];
+        bucket.count += 1;
+        bucket.cycles += cycles as u64;
+
+        self.total_cycles += cycles as u64;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn opcodes(&self) -> &[ProfileCounter; 256] {
+        &self.opcodes
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Return the base address and counters of the `n` address buckets that consumed the most cycles,
+    /// in descending order.
+    pub fn hot_spots(&self, n: usize) -> Vec<(u32, ProfileCounter)> {
+        let mut spots: Vec<(u32, ProfileCounter)> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.count > 0)
+            .map(|(i, c)| ((i as u32) << PROFILER_BUCKET_SHIFT, *c))
+            .collect();
+
+        spots.sort_by_key(|spot| std::cmp::Reverse(spot.1.cycles));
+        spots.truncate(n);
+        spots
+    }
+
+    /// Write all non-zero opcode and address bucket counters as CSV.
+    pub fn write_csv(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "kind,key,count,cycles,cycle_pct")?;
+
+        let pct = |cycles: u64| {
+            if self.total_cycles > 0 {
+                cycles as f64 * 100.0 / self.total_cycles as f64
+            }
+            else {
+                0.0
+            }
+        };
+
+        for (opcode, c) in self.opcodes.iter().enumerate().filter(|(_, c)| c.count > 0) {
+            writeln!(
+                writer,
+                "opcode,{:02X},{},{},{:.3}",
+                opcode,
+                c.count,
+                c.cycles,
+                pct(c.cycles)
+            )?;
+        }
+        for (i, c) in self.buckets.iter().enumerate().filter(|(_, c)| c.count > 0) {
+            let address = i << PROFILER_BUCKET_SHIFT;
+            writeln!(
+                writer,
+                "address,{:05X},{},{},{:.3}",
+                address,
+                c.count,
+                c.cycles,
+                pct(c.cycles)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiler_hot_spots() {
+        let mut profiler = OpcodeProfiler::new();
+        profiler.record(0x90, 0xF0000, 3);
+        profiler.record(0xF7, 0x00700, 150);
+        profiler.record(0xF7, 0x007FF, 150);
+
+        assert_eq!(profiler.opcodes()[0xF7].count, 2);
+        assert_eq!(profiler.total_cycles(), 303);
+
+        let spots = profiler.hot_spots(1);
+        assert_eq!(spots.len(), 1);
+        assert_eq!(spots[0].0, 0x00700);
+        assert_eq!(spots[0].1.cycles, 300);
+    }
+}
@@ -69,7 +69,24 @@ impl NecVx0 {
         (((segment_val as u32) << 4) + offset as u32) & 0xFFFFFu32
     }
 
+    /// Calculate the linear address of the high byte of a word operand at segment:offset.
+    /// The 8088 wraps the offset within the segment, so a word at offset FFFFh is assembled from
+    /// offsets FFFFh and 0000h. If segment overrun is enabled, the high byte is instead read from
+    /// the next linear address, as on the 286.
+    #[inline]
+    pub fn calc_linear_address_seg_hi(&self, segment: Segment, offset: u16) -> u32 {
+        if self.segment_overrun && offset == 0xFFFF {
+            (self.calc_linear_address_seg(segment, offset) + 1) & 0xFFFFFu32
+        }
+        else {
+            self.calc_linear_address_seg(segment, offset.wrapping_add(1))
+        }
+    }
+
     /// Calculate the Effective Address for the given AddressingMode enum
+    /// No cycles are spent here. EA clock costs are already spent when the modrm is read, by
+    /// ModRmByte::read() following the microcode for each addressing mode, and the two cycle
+    /// segment override penalty is spent when the prefix is decoded.
     pub fn calc_effective_address(
         &mut self,
         mode: AddressingMode,
@@ -399,19 +416,22 @@ impl NecVx0 {
                     Register16::DI => self.set_register16(Register16::DI, value),
                     Register16::ES => {
                         self.set_register16(Register16::ES, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     Register16::CS => {
                         self.set_register16(Register16::CS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     Register16::SS => {
+                        // Loading SS inhibits interrupts and single-step for one instruction, so that
+                        // the following load of SP completes before anything can be pushed to the stack.
                         self.set_register16(Register16::SS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
+                        self.trap_suppressed = true;
                     }
                     Register16::DS => {
                         self.set_register16(Register16::DS, value);
-                        //self.interrupt_inhibit = true;
+                        self.interrupt_inhibit = true;
                     },
                     _ => panic!("read_operand16(): Invalid Register16 operand"),
                 }
@@ -41,9 +41,13 @@ use crate::{
         CpuDispatch,
         CpuError,
         CpuOption,
+        CpuState,
         CpuStringState,
         CpuType,
         Disassembly,
+        HookId,
+        InstructionHook,
+        OpcodeProfiler,
         QueueOp,
         Register8,
         ServiceEvent,
@@ -54,7 +58,7 @@ use crate::{
     cpu_vx0::{
         trace_print,
         BusStatus,
-        CpuState,
+        CpuRunState,
         FetchState,
         NecVx0,
         Register16,
@@ -62,6 +66,7 @@ use crate::{
         TaCycle,
         CPU_FLAGS_RESERVED_ON,
     },
+    fpu_8087::Fpu8087,
     syntax_token::SyntaxToken,
 };
 
@@ -102,7 +107,7 @@ impl Cpu for NecVx0 {
         };
         */
 
-        self.state = CpuState::Normal;
+        self.state = CpuRunState::Normal;
 
         self.set_register16(Register16::AX, 0);
         self.set_register16(Register16::BX, 0);
@@ -144,12 +149,18 @@ impl Cpu for NecVx0 {
         self.iret_count = 0;
         self.instr_cycle = 0;
         self.cycle_num = 1;
+        self.test_busy_until = 0;
+        if let Some(fpu) = self.fpu.as_mut() {
+            fpu.reset();
+        }
+        self.fpu_irq = false;
         self.halt_cycles = 0;
         self.t_stamp = 0.0;
         self.t_step = 0.00000021;
         self.t_step_h = 0.000000105;
         self.ready = true;
         self.in_rep = false;
+        self.lock = false;
         self.halted = false;
         self.reported_halt = false;
         self.halt_not_hold = false;
@@ -381,6 +392,36 @@ impl Cpu for NecVx0 {
         self.get_string_state()
     }
 
+    #[inline]
+    fn get_state(&self) -> CpuState {
+        self.get_state()
+    }
+
+    #[inline]
+    fn set_state(&mut self, state: &CpuState) {
+        self.set_state(state)
+    }
+
+    fn fpu(&self) -> Option<&Fpu8087> {
+        self.fpu()
+    }
+
+    fn set_fpu(&mut self, fpu: Option<Fpu8087>) {
+        self.set_fpu(fpu)
+    }
+
+    fn add_instruction_hook(&mut self, hook: InstructionHook) -> HookId {
+        self.add_instruction_hook(hook)
+    }
+
+    fn remove_instruction_hook(&mut self, id: HookId) -> bool {
+        self.remove_instruction_hook(id)
+    }
+
+    fn opcode_profiler(&self) -> Option<&OpcodeProfiler> {
+        self.opcode_profiler.as_deref()
+    }
+
     fn eval_address(&self, expr: &str) -> Option<CpuAddress> {
         self.eval_address(expr)
     }
@@ -422,6 +463,10 @@ impl Cpu for NecVx0 {
                 self.instruction_history.clear();
                 self.instruction_history_on = state;
             }
+            CpuOption::InstructionHistoryLen(len) => {
+                log::debug!("Setting InstructionHistoryLen to: {}", len);
+                self.set_instruction_history_len(len as usize);
+            }
             CpuOption::ScheduleInterrupt(_state, cycle_target, cycles, retrigger) => {
                 log::debug!("Setting InterruptHint to: ({},{})", cycle_target, cycles);
                 self.interrupt_scheduling = true;
@@ -458,6 +503,10 @@ impl Cpu for NecVx0 {
                 log::debug!("Setting EnableWaitStates to: {:?}", state);
                 self.enable_wait_states = state;
             }
+            CpuOption::SegmentOverrun(state) => {
+                log::debug!("Setting SegmentOverrun to: {:?}", state);
+                self.segment_overrun = state;
+            }
             CpuOption::TraceLoggingEnabled(state) => {
                 log::debug!("Setting TraceLoggingEnabled to: {:?}", state);
                 self.trace_enabled = state;
@@ -472,20 +521,51 @@ impl Cpu for NecVx0 {
                 log::debug!("Setting EnableServiceInterrupt to: {:?}", state);
                 self.enable_service_interrupt = state;
             }
+            CpuOption::OpcodeProfiling(state) => {
+                log::debug!("Setting OpcodeProfiling to: {:?}", state);
+                self.opcode_profiler = match state {
+                    true => Some(Box::new(OpcodeProfiler::new())),
+                    false => None,
+                };
+            }
+            CpuOption::InterruptTrace(state, filter) => {
+                log::debug!("Setting InterruptTrace to: {:?} ({:02X?})", state, filter);
+                self.int_trace = state;
+                self.int_trace_filter = filter;
+            }
+            CpuOption::StackGuard(state, limit, wrap, break_on_hit) => {
+                log::debug!(
+                    "Setting StackGuard to: {:?} (limit: {:04X}, wrap: {:?}, break: {:?})",
+                    state,
+                    limit,
+                    wrap,
+                    break_on_hit
+                );
+                self.stack_guard = state;
+                self.stack_guard_limit = limit;
+                self.stack_guard_wrap = wrap;
+                self.stack_guard_break = break_on_hit;
+                self.stack_guard_tripped = false;
+            }
         }
     }
 
     fn get_option(&self, opt: CpuOption) -> bool {
         match opt {
             CpuOption::InstructionHistory(_) => self.instruction_history_on,
+            CpuOption::InstructionHistoryLen(..) => true,
             CpuOption::ScheduleInterrupt(..) => self.interrupt_cycle_period > 0,
             CpuOption::ScheduleDramRefresh(..) => self.dram_refresh_simulation,
             CpuOption::DramRefreshAdjust(..) => true,
             CpuOption::HaltResumeDelay(..) => true,
             CpuOption::OffRailsDetection(_) => self.off_rails_detection,
             CpuOption::EnableWaitStates(_) => self.enable_wait_states,
+            CpuOption::SegmentOverrun(_) => self.segment_overrun,
             CpuOption::TraceLoggingEnabled(_) => self.trace_enabled,
             CpuOption::EnableServiceInterrupt(_) => self.enable_service_interrupt,
+            CpuOption::OpcodeProfiling(_) => self.opcode_profiler.is_some(),
+            CpuOption::InterruptTrace(..) => self.int_trace,
+            CpuOption::StackGuard(..) => self.stack_guard,
         }
     }
 
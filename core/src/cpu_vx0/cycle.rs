@@ -566,8 +566,8 @@ impl NecVx0 {
                 validate_write_u8!(self, self.address_latch, (self.data_bus & 0x00FF) as u8, BusType::Io);
             }
             (BusStatus::InterruptAck, TransferSize::Byte) => {
-                // The vector is read from the PIC directly before we even enter an INTA bus state, so there's
-                // nothing to do.
+                // The vector is read from the PIC by biu_inta() between the two INTA bus cycles and is
+                // already on the data bus, so there's nothing to do.
 
                 //log::debug!("in INTA transfer_n: {}", self.transfer_n);
                 // Deassert lock
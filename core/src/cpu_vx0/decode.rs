@@ -908,6 +908,14 @@ impl NecVx0 {
                 let (imm8, _imm16) = bytes.q_peek_farptr16();
                 operand2_type = OperandType::Immediate8((imm8 & 0xFF) as u8);
             }
+            0xD8..=0xDF if matches!(op_lu.mnemonic, Mnemonic::ESC) => {
+                // ESC opcode, operand. Move the modrm operand to the second operand and present the
+                // 6-bit escape code as the first, as MASM does.
+                operand2_type = operand1_type;
+                operand2_size = operand1_size;
+                operand1_type = OperandType::Immediate8(((opcode & 0x07) << 3) | modrm.get_op_extension());
+                operand1_size = OperandSize::Operand8;
+            }
             _ => {}
         }
 
@@ -785,6 +785,10 @@ impl NecVx0 {
             0x9B => {
                 // WAIT
                 cycles!(self, 3);
+                // WAIT samples the TEST pin every 5 cycles until it is asserted by the coprocessor.
+                while !self.test_pin_asserted() {
+                    cycles!(self, 5);
+                }
             }
             0x9C => {
                 // PUSHF - Push Flags
@@ -1374,12 +1378,19 @@ impl NecVx0 {
                 
                 self.set_register8(Register8::AL, value);
             }
-            0x66 | 0x67 | 0xD8..=0xDF => {
+            0x66 | 0x67 => {
                 // ESC - FPU instructions. 
                 
                 // Perform dummy read if memory operand
                 let _op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override);
             }
+            0xD8..=0xDF => {
+                // ESC - FPU instructions. 
+                
+                // Perform dummy read if memory operand
+                let _op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override);
+                self.fpu_escape();
+            }
             0xE0 | 0xE1 => {
                 // LOOPNE & LOOPE
                 // LOOPNE - Decrement CX, Jump short if count!=0 and ZF=0
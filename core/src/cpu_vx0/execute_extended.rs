@@ -418,7 +418,20 @@ impl NecVx0 {
                         }
 
                     }
-                }                
+                }
+            }
+            0xFF => {
+                // BRKEM imm8
+                // On real hardware, BRKEM vectors through the IVT like INT and clears the MD flag,
+                // entering 8080 emulation mode. Emulation mode is not supported, so we take the
+                // interrupt and remain in native mode. This is sufficient for V20 detection
+                // routines that install their own handler to return immediately.
+                let irq = self.read_operand8(self.i.operand1_type, None).unwrap();
+                log::debug!("BRKEM {:02X}: 8080 emulation mode is not supported.", irq);
+
+                self.step_over_target = Some(CpuAddress::Segmented(self.cs, self.ip()));
+                self.sw_interrupt(irq);
+                jump = true;
             }
             _ => {
                 unhandled = true;
@@ -0,0 +1,116 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_vx0::fpu.rs
+
+    Forwards ESC instructions to an installed 8087 coprocessor.
+
+*/
+
+use crate::{
+    cpu_common::{OperandType, Register16, Segment},
+    cpu_vx0::*,
+    fpu_8087::{execute::EscOperation, Fpu8087, MemoryOperand},
+};
+
+impl NecVx0 {
+    /// Install or remove an 8087 coprocessor.
+    pub fn set_fpu(&mut self, fpu: Option<Fpu8087>) {
+        self.fpu_irq = fpu.as_ref().is_some_and(|fpu| fpu.interrupt_request());
+        self.fpu = fpu;
+    }
+
+    pub fn fpu(&self) -> Option<&Fpu8087> {
+        self.fpu.as_ref()
+    }
+
+    /// Hand the current ESC instruction to the coprocessor, if one is installed. The CPU has
+    /// already performed the dummy read of any memory operand.
+    pub fn fpu_escape(&mut self) {
+        let mut fpu = match self.fpu.take() {
+            Some(fpu) => fpu,
+            None => return,
+        };
+
+        let (esc, reg) = match self.i.operand1_type {
+            OperandType::Immediate8(code) => (code >> 3, code & 0x07),
+            _ => (self.i.opcode & 0x07, 0),
+        };
+
+        let (rm, segment, offset) = match self.i.operand2_type {
+            OperandType::AddressingMode(mode) => {
+                let (segment, offset) = self.calc_effective_address(mode, self.i.segment_override);
+                let segment_val = match segment {
+                    Segment::None => 0,
+                    Segment::ES => self.es,
+                    Segment::CS => self.cs,
+                    Segment::DS => self.ds,
+                    Segment::SS => self.ss,
+                };
+                (None, segment_val, offset)
+            }
+            OperandType::Register16(reg16) => {
+                let rm = match reg16 {
+                    Register16::AX => 0,
+                    Register16::CX => 1,
+                    Register16::DX => 2,
+                    Register16::BX => 3,
+                    Register16::SP => 4,
+                    Register16::BP => 5,
+                    Register16::SI => 6,
+                    _ => 7,
+                };
+                (Some(rm), 0, 0)
+            }
+            _ => (Some(0), 0, 0),
+        };
+
+        let op = EscOperation {
+            esc,
+            reg,
+            rm,
+            ip: self.instruction_address,
+            ea: NecVx0::calc_linear_address(segment, offset),
+        };
+
+        let mut operand = MemoryOperand {
+            bus: &mut self.bus,
+            segment,
+            offset,
+        };
+        let busy_cycles = fpu.execute(&op, &mut operand);
+
+        // The 8087's INT output is wired to NMI on the PC. NMI is edge-triggered, so re-arm it once
+        // both the bus NMI sources and the coprocessor have deasserted the line.
+        let irq = fpu.interrupt_request();
+        self.fpu = Some(fpu);
+        self.set_test_busy(busy_cycles);
+        if !irq && self.fpu_irq && !self.nmi {
+            self.nmi_triggered = false;
+        }
+        self.fpu_irq = irq;
+    }
+}
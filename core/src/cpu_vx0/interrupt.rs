@@ -33,6 +33,7 @@
 use crate::{
     cpu_common::{Segment, ServiceEvent},
     cpu_vx0::*,
+    interrupt::describe_interrupt,
 };
 
 impl NecVx0 {
@@ -81,6 +82,15 @@ impl NecVx0 {
             return;
         }
 
+        if self.int_trace && (self.int_trace_filter.is_empty() || self.int_trace_filter.contains(&interrupt)) {
+            log::info!(
+                "[{:04X}:{:04X}] {}",
+                self.cs,
+                self.instruction_ip,
+                describe_interrupt(interrupt, &self.get_state(), &self.bus)
+            );
+        }
+
         self.cycles_i(3, &[0x19d, 0x19e, 0x19f]);
 
         // Read the IVT
@@ -256,11 +266,11 @@ impl NecVx0 {
         self.farcall2(new_cs, new_ip);
     }
 
-    /// Perform a hardware interrupt
-    pub fn hw_interrupt(&mut self, vector: u8) {
+    /// Perform a hardware interrupt. Returns the vector read from the PIC during INTA.
+    pub fn hw_interrupt(&mut self) -> u8 {
         self.in_int = true;
         // Begin IRQ routine
-        self.biu_inta(vector);
+        let vector = self.biu_inta();
         self.biu_fetch_suspend();
         self.cycles_i(2, &[0x19b, 0x19c]);
 
@@ -268,6 +278,7 @@ impl NecVx0 {
         self.intr_routine(vector, InterruptType::Hardware, false);
         self.int_count += 1;
         self.in_int = false;
+        vector
     }
 
     /// Perform INT0 (Divide By 0)
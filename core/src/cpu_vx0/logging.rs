@@ -92,6 +92,9 @@ impl NecVx0 {
             TraceMode::CycleSigrok => {
                 self.trace_csv_line();
             }
+            TraceMode::CycleVcd => {
+                self.trace_vcd_cycle();
+            }
             _ => {}
         }
     }
@@ -123,10 +126,51 @@ impl NecVx0 {
     pub fn emit_header(&mut self) {
         match self.trace_mode {
             TraceMode::CycleSigrok => self.trace_print("Time(s),addr,clk,ready,qs,s,clk0,intr,dr0,holda,vs,hs,den,brd"),
+            TraceMode::CycleVcd => self.trace_vcd_header(),
             _ => {}
         }
     }
 
+    /// Emit the VCD header declaring the traced bus signals. Timestamps are in picoseconds.
+    pub fn trace_vcd_header(&mut self) {
+        self.trace_print("$timescale 1ps $end");
+        self.trace_print("$scope module cpu $end");
+        self.trace_print("$var wire 1 ! clk $end");
+        self.trace_print("$var wire 20 \" addr $end");
+        self.trace_print("$var wire 16 # data $end");
+        self.trace_print("$var wire 1 + ready $end");
+        self.trace_print("$var wire 2 % qs $end");
+        self.trace_print("$var wire 3 & s $end");
+        self.trace_print("$var wire 1 ' clk0 $end");
+        self.trace_print("$var wire 1 ( intr $end");
+        self.trace_print("$var wire 1 ) dr0 $end");
+        self.trace_print("$var wire 1 * holda $end");
+        self.trace_print("$upscope $end");
+        self.trace_print("$enddefinitions $end");
+    }
+
+    /// Emit one CPU cycle in VCD format: all signals on the rising clock edge, then the falling edge half a
+    /// cycle later.
+    pub fn trace_vcd_cycle(&mut self) {
+        let t_rise = (self.t_stamp * 1e12) as u64;
+        let t_fall = ((self.t_stamp + self.t_step_h) * 1e12) as u64;
+
+        self.trace_emit(&format!(
+            "#{}\n1!\nb{:020b} \"\nb{:016b} #\n{}+\nb{:02b} %\nb{:03b} &\n{}'\n{}(\n{})\n{}*",
+            t_rise,
+            self.address_bus & 0xFFFFF,
+            self.data_bus,
+            self.ready as u8,
+            self.last_queue_op as u8 & 0x03,
+            self.bus_status as u8 & 0x07,
+            self.clk0 as u8,
+            self.intr as u8,
+            self.dma_req as u8,
+            self.dma_holda as u8
+        ));
+        self.trace_emit(&format!("#{}\n0!", t_fall));
+    }
+
     pub fn trace_csv_line(&mut self) {
         let q = self.last_queue_op as u8;
         let s = self.bus_status as u8;
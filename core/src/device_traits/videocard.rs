@@ -392,6 +392,11 @@ pub trait VideoCard {
     /// Get the position of the CRT beam (Direct rendering only)
     fn get_beam_pos(&self) -> Option<(u32, u32)>;
 
+    /// Set the position of the light pen in video field coordinates, or None if the pen is not
+    /// pointed at the display, and whether the pen switch is pressed. Adapters without a light pen
+    /// interface ignore this.
+    fn set_light_pen(&mut self, _pos: Option<(u32, u32)>, _switch: bool) {}
+
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32;
 
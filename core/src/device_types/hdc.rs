@@ -70,7 +70,7 @@ impl Display for HardDiskFormat {
 }
 
 lazy_static! {
-    static ref XT_HARD_DISK_TYPES: [Option<HardDiskFormat>; 5] = [
+    pub static ref XT_HARD_DISK_TYPES: [Option<HardDiskFormat>; 5] = [
         None,
         // "Type 1"
         Some(HardDiskFormat {
@@ -224,3 +224,13 @@ lazy_static! {
         None,
     ];
 }
+
+/// Return the XT drive type whose capacity matches a raw sector image of `size` bytes. Where two types have the same
+/// capacity, the first is returned.
+pub fn xt_hard_disk_format_for_size(size: usize) -> Option<HardDiskFormat> {
+    XT_HARD_DISK_TYPES
+        .iter()
+        .flatten()
+        .find(|format| format.get_size() == size)
+        .cloned()
+}
@@ -31,5 +31,6 @@
 */
 
 pub mod chs;
+pub mod drive_status;
 pub mod fdc;
 pub mod hdc;
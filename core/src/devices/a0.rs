@@ -41,6 +41,7 @@ pub enum A0Type {
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
     devices::pit::Pit,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 
 pub struct A0Register {
@@ -84,6 +85,10 @@ impl IoDevice for A0Register {
                 self.clock_1_select = (data & 0x20) != 0;
                 self.hrq_disable = (data & 0x10) != 0;
             }
+            A0Type::PCXT => {
+                // Bit 7 is the NMI mask. The other bits are not connected.
+                self.nmi_enabled = (data & 0x80) != 0;
+            }
             _ => {}
         }
     }
@@ -155,3 +160,26 @@ impl A0Register {
         self.nmi_latch
     }
 }
+
+impl SaveState for A0Register {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.a0_byte);
+        w.write_bool(self.nmi_latch);
+        w.write_bool(self.nmi_enabled);
+        w.write_bool(self.ir_test_ena);
+        w.write_bool(self.clock_1_select);
+        w.write_bool(self.hrq_disable);
+        w.write_bool(self.clear_nmi_latch);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.a0_byte = r.read_u8()?;
+        self.nmi_latch = r.read_bool()?;
+        self.nmi_enabled = r.read_bool()?;
+        self.ir_test_ena = r.read_bool()?;
+        self.clock_1_select = r.read_bool()?;
+        self.hrq_disable = r.read_bool()?;
+        self.clear_nmi_latch = r.read_bool()?;
+        Ok(())
+    }
+}
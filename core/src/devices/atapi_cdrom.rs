@@ -0,0 +1,602 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::atapi_cdrom.rs
+
+    Implements an ATAPI CD-ROM drive. The drive is attached to an IDE
+    controller, which carries the 12-byte SCSI command packets and their
+    data; this module executes the packets.
+
+    The MMC commands used by DOS CD-ROM drivers are supported: reading data
+    sectors, the table of contents and capacity, and playing audio tracks.
+    Audio is played through a mixer source at the CD sample rate.
+
+*/
+
+use crate::{
+    cd_image::{lba_to_msf, msf_to_lba, CdImage, CdTrackType, CD_AUDIO_RATE, CD_RAW_SECTOR_SIZE, CD_SECTOR_SIZE},
+    sound_mixer::SoundSource,
+};
+
+// Sense keys
+const SENSE_NONE: u8 = 0x00;
+const SENSE_NOT_READY: u8 = 0x02;
+const SENSE_MEDIUM_ERROR: u8 = 0x03;
+const SENSE_ILLEGAL_REQUEST: u8 = 0x05;
+const SENSE_UNIT_ATTENTION: u8 = 0x06;
+
+// Additional sense codes
+const ASC_INVALID_COMMAND: u8 = 0x20;
+const ASC_LBA_OUT_OF_RANGE: u8 = 0x21;
+const ASC_INVALID_FIELD: u8 = 0x24;
+const ASC_MEDIUM_CHANGED: u8 = 0x28;
+const ASC_NO_MEDIUM: u8 = 0x3A;
+const ASC_ILLEGAL_MODE: u8 = 0x64;
+const ASC_UNRECOVERED_READ: u8 = 0x11;
+
+// Audio status codes returned by Read Sub-channel
+const AUDIO_PLAYING: u8 = 0x11;
+const AUDIO_PAUSED: u8 = 0x12;
+const AUDIO_COMPLETED: u8 = 0x13;
+const AUDIO_ERROR: u8 = 0x14;
+const AUDIO_NO_STATUS: u8 = 0x15;
+
+// Q sub-channel control field
+const CONTROL_DATA: u8 = 0x04;
+const ADR_POSITION: u8 = 0x10;
+
+const LEAD_OUT_TRACK: u8 = 0xAA;
+// Reported to the host as 4x speed, in kilobytes per second.
+const MAX_SPEED_KBPS: u16 = 706;
+
+/// A failed packet command, reported to the host through the sense data.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sense {
+    pub key:  u8,
+    pub asc:  u8,
+    pub ascq: u8,
+}
+
+impl Sense {
+    const fn new(key: u8, asc: u8) -> Self {
+        Self { key, asc, ascq: 0 }
+    }
+}
+
+/// The data phase of a packet command.
+pub enum PacketResponse {
+    /// The command has no data to transfer.
+    Complete,
+    /// The command returns the specified data.
+    Data(Vec<u8>),
+    /// The command reads data sectors, which are fetched with `read_sector` as the host takes them.
+    Sectors { lba: u32, count: u32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AudioState {
+    Stopped,
+    Playing,
+    Paused,
+    Completed,
+    Error,
+}
+
+pub struct AtapiCdrom {
+    image: Option<CdImage>,
+    sense: Sense,
+    media_changed: bool,
+    locked: bool,
+
+    audio_state: AudioState,
+    audio_lba: u32,
+    audio_end: u32,
+    audio_frame: Vec<u8>,
+    audio_frame_pos: usize,
+    volume: [u8; 2],
+
+    output_accum: f64,
+    source: Option<SoundSource>,
+}
+
+impl Default for AtapiCdrom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtapiCdrom {
+    pub fn new() -> Self {
+        Self {
+            image: None,
+            sense: Sense::new(SENSE_NONE, 0),
+            media_changed: false,
+            locked: false,
+
+            audio_state: AudioState::Stopped,
+            audio_lba: 0,
+            audio_end: 0,
+            audio_frame: vec![0; CD_RAW_SECTOR_SIZE],
+            audio_frame_pos: CD_RAW_SECTOR_SIZE,
+            volume: [0xFF, 0xFF],
+
+            output_accum: 0.0,
+            source: None,
+        }
+    }
+
+    /// Set the mixer source that CD audio is sent to.
+    pub fn set_sound_source(&mut self, source: SoundSource) {
+        self.source = Some(source);
+    }
+
+    pub fn source_id(&self) -> Option<usize> {
+        self.source.as_ref().map(|source| source.id())
+    }
+
+    /// Insert a disc. The host is told the medium has changed by the next command it sends.
+    pub fn insert(&mut self, image: CdImage) {
+        self.image = Some(image);
+        self.media_changed = true;
+        self.stop_audio();
+    }
+
+    pub fn eject(&mut self) -> Option<CdImage> {
+        if self.locked {
+            log::warn!("ATAPI: Ejecting disc while medium removal is prevented");
+        }
+        self.media_changed = true;
+        self.stop_audio();
+        self.image.take()
+    }
+
+    pub fn have_disc(&self) -> bool {
+        self.image.is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.audio_state == AudioState::Playing
+    }
+
+    /// Reset the drive, stopping audio playback.
+    pub fn reset(&mut self) {
+        self.sense = Sense::new(SENSE_NONE, 0);
+        self.locked = false;
+        self.stop_audio();
+    }
+
+    fn stop_audio(&mut self) {
+        self.audio_state = AudioState::Stopped;
+        self.audio_frame_pos = CD_RAW_SECTOR_SIZE;
+    }
+
+    /// Return the sense key of the last command, for the error register.
+    pub fn sense_key(&self) -> u8 {
+        self.sense.key
+    }
+
+    /// Execute a 12-byte command packet.
+    pub fn packet(&mut self, cdb: &[u8]) -> Result<PacketResponse, Sense> {
+        let result = self.execute(cdb);
+        self.sense = match result {
+            Ok(_) => Sense::new(SENSE_NONE, 0),
+            Err(sense) => sense,
+        };
+        if let Err(sense) = result {
+            log::debug!(
+                "ATAPI: Command {:02X} failed, sense: {:02X}/{:02X}/{:02X}",
+                cdb[0],
+                sense.key,
+                sense.asc,
+                sense.ascq
+            );
+        }
+        result
+    }
+
+    fn execute(&mut self, cdb: &[u8]) -> Result<PacketResponse, Sense> {
+        log::trace!("ATAPI: Packet {:02X?}", cdb);
+        let opcode = cdb[0];
+
+        // Inquiry and Request Sense work even when there is a pending unit attention or no disc.
+        match opcode {
+            0x03 => return Ok(self.command_request_sense(cdb)),
+            0x12 => return Ok(self.command_inquiry(cdb)),
+            _ => {}
+        }
+        if self.media_changed {
+            self.media_changed = false;
+            return Err(Sense::new(SENSE_UNIT_ATTENTION, ASC_MEDIUM_CHANGED));
+        }
+
+        match opcode {
+            0x00 => self.disc().map(|_| PacketResponse::Complete), // Test Unit Ready
+            0x1B => {
+                // Start/Stop Unit. Ejecting is left to the user.
+                if cdb[4] & 0x01 == 0 {
+                    self.stop_audio();
+                }
+                Ok(PacketResponse::Complete)
+            }
+            0x1E => {
+                // Prevent/Allow Medium Removal
+                self.locked = cdb[4] & 0x01 != 0;
+                Ok(PacketResponse::Complete)
+            }
+            0x25 => self.command_read_capacity(),
+            0x28 => self.command_read(be32(&cdb[2..6]), be16(&cdb[7..9]) as u32),
+            0xA8 => self.command_read(be32(&cdb[2..6]), be32(&cdb[6..10])),
+            0x2B => {
+                // Seek
+                self.disc()?;
+                self.stop_audio();
+                Ok(PacketResponse::Complete)
+            }
+            0x42 => self.command_read_subchannel(cdb),
+            0x43 => self.command_read_toc(cdb),
+            0x45 => self.command_play(be32(&cdb[2..6]), be16(&cdb[7..9]) as u32),
+            0xA5 => self.command_play(be32(&cdb[2..6]), be32(&cdb[6..10])),
+            0x47 => {
+                // Play Audio MSF. A start address of FF:FF:FF plays from the current position.
+                let start = if cdb[3..6] == [0xFF, 0xFF, 0xFF] {
+                    u32::MAX
+                }
+                else {
+                    msf_to_lba(cdb[3], cdb[4], cdb[5])
+                };
+                let end = msf_to_lba(cdb[6], cdb[7], cdb[8]);
+                let start_lba = if start == u32::MAX { self.audio_lba } else { start };
+                self.command_play(start, end.saturating_sub(start_lba))
+            }
+            0x4B => {
+                // Pause/Resume
+                self.disc()?;
+                match (self.audio_state, cdb[8] & 0x01 != 0) {
+                    (AudioState::Playing, false) => self.audio_state = AudioState::Paused,
+                    (AudioState::Paused, true) => self.audio_state = AudioState::Playing,
+                    (AudioState::Playing | AudioState::Paused, _) => {}
+                    _ => return Err(Sense::new(SENSE_ILLEGAL_REQUEST, 0x2C)), // Command sequence error
+                }
+                Ok(PacketResponse::Complete)
+            }
+            0x4E => {
+                // Stop Play/Scan
+                self.stop_audio();
+                Ok(PacketResponse::Complete)
+            }
+            0x5A => self.command_mode_sense(cdb),
+            _ => {
+                log::warn!("ATAPI: Unsupported packet command: {:02X}", opcode);
+                Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_INVALID_COMMAND))
+            }
+        }
+    }
+
+    fn disc(&mut self) -> Result<&mut CdImage, Sense> {
+        self.image.as_mut().ok_or(Sense::new(SENSE_NOT_READY, ASC_NO_MEDIUM))
+    }
+
+    /// Read the user data of a sector requested by a Read command.
+    pub fn read_sector(&mut self, lba: u32) -> Result<Vec<u8>, Sense> {
+        let mut buf = vec![0; CD_SECTOR_SIZE];
+        let result = self.disc()?.read_sector(lba, &mut buf);
+        match result {
+            Ok(_) => Ok(buf),
+            Err(e) => {
+                log::error!("ATAPI: Read failed at LBA {}: {}", lba, e);
+                self.sense = Sense::new(SENSE_MEDIUM_ERROR, ASC_UNRECOVERED_READ);
+                Err(self.sense)
+            }
+        }
+    }
+
+    fn command_request_sense(&mut self, cdb: &[u8]) -> PacketResponse {
+        let sense = if self.media_changed {
+            self.media_changed = false;
+            Sense::new(SENSE_UNIT_ATTENTION, ASC_MEDIUM_CHANGED)
+        }
+        else {
+            self.sense
+        };
+        let mut data = vec![0; 18];
+        data[0] = 0x70; // Current error
+        data[2] = sense.key;
+        data[7] = 10; // Additional sense length
+        data[12] = sense.asc;
+        data[13] = sense.ascq;
+        PacketResponse::Data(truncate(data, cdb[4] as usize))
+    }
+
+    fn command_inquiry(&mut self, cdb: &[u8]) -> PacketResponse {
+        let mut data = vec![0; 36];
+        data[0] = 0x05; // CD-ROM device
+        data[1] = 0x80; // Removable medium
+        data[3] = 0x21; // ATAPI, response data format 1
+        data[4] = 31; // Additional length
+        data[8..16].copy_from_slice(b"MARTYPC ");
+        data[16..32].copy_from_slice(b"CD-ROM          ");
+        data[32..36].copy_from_slice(b"1.0 ");
+        PacketResponse::Data(truncate(data, cdb[4] as usize))
+    }
+
+    fn command_read_capacity(&mut self) -> Result<PacketResponse, Sense> {
+        let last = self.disc()?.lead_out().saturating_sub(1);
+        let mut data = last.to_be_bytes().to_vec();
+        data.extend((CD_SECTOR_SIZE as u32).to_be_bytes());
+        Ok(PacketResponse::Data(data))
+    }
+
+    fn command_read(&mut self, lba: u32, count: u32) -> Result<PacketResponse, Sense> {
+        let disc = self.disc()?;
+        if lba as u64 + count as u64 > disc.lead_out() as u64 {
+            return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE));
+        }
+        if count == 0 {
+            return Ok(PacketResponse::Complete);
+        }
+        if disc.track_at(lba).map(|track| track.track_type) != Some(CdTrackType::Data) {
+            return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_ILLEGAL_MODE));
+        }
+        self.stop_audio();
+        Ok(PacketResponse::Sectors { lba, count })
+    }
+
+    fn command_play(&mut self, lba: u32, count: u32) -> Result<PacketResponse, Sense> {
+        let lba = if lba == u32::MAX { self.audio_lba } else { lba };
+        let disc = self.disc()?;
+        if count == 0 {
+            return Ok(PacketResponse::Complete);
+        }
+        if lba as u64 + count as u64 > disc.lead_out() as u64 {
+            return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_LBA_OUT_OF_RANGE));
+        }
+        if disc.track_at(lba).map(|track| track.track_type) != Some(CdTrackType::Audio) {
+            return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_ILLEGAL_MODE));
+        }
+        log::debug!("ATAPI: Playing audio from LBA {} for {} sectors", lba, count);
+        self.audio_lba = lba;
+        self.audio_end = lba + count;
+        self.audio_frame_pos = CD_RAW_SECTOR_SIZE;
+        self.audio_state = AudioState::Playing;
+        Ok(PacketResponse::Complete)
+    }
+
+    fn command_read_subchannel(&mut self, cdb: &[u8]) -> Result<PacketResponse, Sense> {
+        let msf = cdb[1] & 0x02 != 0;
+        let subq = cdb[2] & 0x40 != 0;
+        let format = cdb[3];
+        let alloc = be16(&cdb[7..9]) as usize;
+
+        let status = match self.audio_state {
+            AudioState::Stopped => AUDIO_NO_STATUS,
+            AudioState::Playing => AUDIO_PLAYING,
+            AudioState::Paused => AUDIO_PAUSED,
+            AudioState::Completed => AUDIO_COMPLETED,
+            AudioState::Error => AUDIO_ERROR,
+        };
+        // Completion and error statuses are only reported once.
+        if matches!(self.audio_state, AudioState::Completed | AudioState::Error) {
+            self.audio_state = AudioState::Stopped;
+        }
+
+        let mut data = vec![0, status, 0, 0];
+        if subq {
+            if format != 0x01 {
+                return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD));
+            }
+            let lba = self.audio_lba;
+            let track = self
+                .disc()?
+                .track_at(lba)
+                .map(|track| (track.number, track.track_type, track.start));
+            let (number, control, start) = match track {
+                Some((number, CdTrackType::Data, start)) => (number, CONTROL_DATA, start),
+                Some((number, CdTrackType::Audio, start)) => (number, 0, start),
+                None => (LEAD_OUT_TRACK, 0, lba),
+            };
+            data.extend([0x01, ADR_POSITION | control, number, 0x01]);
+            data.extend(address(lba, msf));
+            // The relative address counts from the start of the track
+            data.extend(relative_address(lba as i64 - start as i64, msf));
+        }
+        let len = (data.len() - 4) as u16;
+        data[2..4].copy_from_slice(&len.to_be_bytes());
+        Ok(PacketResponse::Data(truncate(data, alloc)))
+    }
+
+    fn command_read_toc(&mut self, cdb: &[u8]) -> Result<PacketResponse, Sense> {
+        let msf = cdb[1] & 0x02 != 0;
+        // The format may be given in the low bits of byte 2, or by older drivers in the top bits of byte 9.
+        let format = match cdb[2] & 0x0F {
+            0 => cdb[9] >> 6,
+            format => format,
+        };
+        let start_track = cdb[6];
+        let alloc = be16(&cdb[7..9]) as usize;
+
+        let disc = self.disc()?;
+        let tracks = disc.tracks();
+        let first = tracks.first().map(|track| track.number).unwrap_or(1);
+        let last = tracks.last().map(|track| track.number).unwrap_or(1);
+        let control = |track_type: CdTrackType| match track_type {
+            CdTrackType::Data => ADR_POSITION | CONTROL_DATA,
+            CdTrackType::Audio => ADR_POSITION,
+        };
+
+        let mut data = vec![0, 0, first, last];
+        match format {
+            0 => {
+                if start_track > last && start_track != LEAD_OUT_TRACK {
+                    return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD));
+                }
+                for track in tracks.iter().filter(|track| track.number >= start_track) {
+                    data.extend([0, control(track.track_type), track.number, 0]);
+                    data.extend(address(track.start, msf));
+                }
+                let lead_out_control = tracks.last().map(|track| control(track.track_type)).unwrap_or(0);
+                data.extend([0, lead_out_control, LEAD_OUT_TRACK, 0]);
+                data.extend(address(disc.lead_out(), msf));
+            }
+            1 => {
+                // Session information. Only single session discs are supported.
+                data[2] = 1;
+                data[3] = 1;
+                if let Some(track) = tracks.first() {
+                    data.extend([0, control(track.track_type), track.number, 0]);
+                    data.extend(address(track.start, msf));
+                }
+            }
+            _ => return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD)),
+        }
+        let len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&len.to_be_bytes());
+        Ok(PacketResponse::Data(truncate(data, alloc)))
+    }
+
+    fn command_mode_sense(&mut self, cdb: &[u8]) -> Result<PacketResponse, Sense> {
+        let page = cdb[2] & 0x3F;
+        let alloc = be16(&cdb[7..9]) as usize;
+
+        let medium_type = match &self.image {
+            None => 0x70, // No disc
+            Some(disc) => {
+                let data = disc.tracks().iter().any(|track| track.track_type == CdTrackType::Data);
+                let audio = disc.tracks().iter().any(|track| track.track_type == CdTrackType::Audio);
+                match (data, audio) {
+                    (true, true) => 0x03,
+                    (false, true) => 0x02,
+                    _ => 0x01,
+                }
+            }
+        };
+
+        let mut data = vec![0, 0, medium_type, 0, 0, 0, 0, 0];
+        if page == 0x0E || page == 0x3F {
+            // CD audio control page
+            data.extend([0x0E, 0x0E, 0x04, 0, 0, 0, 0, 0]);
+            data.extend([0x01, self.volume[0], 0x02, self.volume[1], 0, 0, 0, 0]);
+        }
+        if page == 0x2A || page == 0x3F {
+            // Capabilities page: audio play and a lockable tray
+            data.extend([0x2A, 0x12, 0x00, 0x00, 0x01, 0x00, 0x29, 0x00]);
+            data.extend(MAX_SPEED_KBPS.to_be_bytes());
+            data.extend(256u16.to_be_bytes()); // Volume levels
+            data.extend(64u16.to_be_bytes()); // Buffer size in KB
+            data.extend(MAX_SPEED_KBPS.to_be_bytes());
+            data.extend([0, 0, 0, 0]);
+        }
+        if data.len() == 8 {
+            return Err(Sense::new(SENSE_ILLEGAL_REQUEST, ASC_INVALID_FIELD));
+        }
+        let len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&len.to_be_bytes());
+        Ok(PacketResponse::Data(truncate(data, alloc)))
+    }
+
+    /// Run the drive for the specified number of microseconds, sending CD audio to the mixer.
+    pub fn run(&mut self, us: f64) {
+        if self.source.is_none() {
+            return;
+        }
+
+        let period = 1_000_000.0 / CD_AUDIO_RATE;
+        self.output_accum += us;
+        while self.output_accum >= period {
+            self.output_accum -= period;
+            let sample = self.next_audio_sample();
+            if let Some(source) = &mut self.source {
+                source.push(sample);
+            }
+        }
+    }
+
+    /// Return the next audio sample, mixed down to mono.
+    fn next_audio_sample(&mut self) -> f32 {
+        if self.audio_state != AudioState::Playing {
+            return 0.0;
+        }
+        if self.audio_frame_pos >= CD_RAW_SECTOR_SIZE {
+            if self.audio_lba >= self.audio_end {
+                self.audio_state = AudioState::Completed;
+                return 0.0;
+            }
+            let lba = self.audio_lba;
+            let result = match &mut self.image {
+                Some(image) => image.read_audio(lba, &mut self.audio_frame),
+                None => Err(anyhow::anyhow!("No disc")),
+            };
+            if let Err(e) = result {
+                log::warn!("ATAPI: Audio playback stopped at LBA {}: {}", lba, e);
+                self.audio_state = AudioState::Error;
+                return 0.0;
+            }
+            self.audio_lba += 1;
+            self.audio_frame_pos = 0;
+        }
+
+        let frame = &self.audio_frame[self.audio_frame_pos..self.audio_frame_pos + 4];
+        self.audio_frame_pos += 4;
+        let left = i16::from_le_bytes([frame[0], frame[1]]) as f32 * self.volume[0] as f32 / 255.0;
+        let right = i16::from_le_bytes([frame[2], frame[3]]) as f32 * self.volume[1] as f32 / 255.0;
+        (left + right) / 65536.0
+    }
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn truncate(mut data: Vec<u8>, len: usize) -> Vec<u8> {
+    data.truncate(len);
+    data
+}
+
+/// Encode an address as an LBA or MSF, in the 4 byte form used by Read TOC and Read Sub-channel.
+fn address(lba: u32, msf: bool) -> [u8; 4] {
+    if msf {
+        let (m, s, f) = lba_to_msf(lba);
+        [0, m, s, f]
+    }
+    else {
+        lba.to_be_bytes()
+    }
+}
+
+/// Encode a track relative address, which is negative in the pregap before a track.
+fn relative_address(offset: i64, msf: bool) -> [u8; 4] {
+    if msf {
+        let frames = offset.unsigned_abs() as u32;
+        let (m, s, f) = (frames / (60 * 75), (frames / 75) % 60, frames % 75);
+        [0, m as u8, s as u8, f as u8]
+    }
+    else {
+        (offset as i32).to_be_bytes()
+    }
+}
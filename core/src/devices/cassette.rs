@@ -0,0 +1,208 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::cassette.rs
+
+    Implementation of the cassette interface of the IBM 5150 and PCjr.
+
+    The cassette motor is switched by PPI port B bit 3, and the data read
+    from the tape appears on PPI port C bit 4. Data is written to the tape
+    from the output of PIT channel 2, which the BIOS programs with the
+    period of each bit.
+
+    As on a real deck, the tape only moves while the motor is running and
+    the play or record button is down.
+
+*/
+
+use crate::tape_image::TapeImage;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CassetteState {
+    #[default]
+    Stopped,
+    Playing,
+    Recording,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CassetteCommand {
+    Play,
+    Record,
+    Stop,
+    Rewind,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CassetteStatus {
+    pub loaded:   bool,
+    pub state:    CassetteState,
+    pub motor:    bool,
+    pub position: f64, // Tape position in seconds
+    pub length:   f64, // Length of the recorded portion of the tape in seconds
+    pub modified: bool,
+}
+
+#[derive(Default)]
+pub struct Cassette {
+    tape: Option<TapeImage>,
+    state: CassetteState,
+    motor: bool,
+    pulse: usize,     // Index of the pulse under the head
+    pulse_us: f64,    // Time spent in the current pulse
+    position_us: f64, // Time at the start of the current pulse
+    edge_us: f64,     // Time since the last transition while recording
+    modified: bool,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Insert a tape, rewound to the start.
+    pub fn insert(&mut self, tape: TapeImage) {
+        self.tape = Some(tape);
+        self.modified = false;
+        self.rewind();
+    }
+
+    pub fn eject(&mut self) -> Option<TapeImage> {
+        self.rewind();
+        self.modified = false;
+        self.tape.take()
+    }
+
+    pub fn tape(&self) -> Option<&TapeImage> {
+        self.tape.as_ref()
+    }
+
+    /// Mark the tape as saved.
+    pub fn clear_modified(&mut self) {
+        self.modified = false;
+    }
+
+    pub fn command(&mut self, command: CassetteCommand) {
+        if self.tape.is_none() {
+            return;
+        }
+        match command {
+            CassetteCommand::Play => {
+                self.state = CassetteState::Playing;
+            }
+            CassetteCommand::Record => {
+                // Recording erases the tape from the current position onwards.
+                if let Some(tape) = &mut self.tape {
+                    tape.truncate(self.pulse);
+                }
+                self.pulse_us = 0.0;
+                self.edge_us = 0.0;
+                self.state = CassetteState::Recording;
+            }
+            CassetteCommand::Stop => {
+                self.state = CassetteState::Stopped;
+            }
+            CassetteCommand::Rewind => {
+                self.rewind();
+            }
+        }
+    }
+
+    fn rewind(&mut self) {
+        self.state = CassetteState::Stopped;
+        self.pulse = 0;
+        self.pulse_us = 0.0;
+        self.position_us = 0.0;
+        self.edge_us = 0.0;
+    }
+
+    /// Switch the motor relay.
+    pub fn set_motor(&mut self, on: bool) {
+        if on != self.motor {
+            log::debug!("Cassette motor {}", if on { "on" } else { "off" });
+            self.motor = on;
+        }
+    }
+
+    /// Return the level read from the tape.
+    pub fn data_in(&self) -> bool {
+        self.motor && self.state == CassetteState::Playing && self.pulse % 2 == 1
+    }
+
+    /// Record a transition of the cassette data output.
+    pub fn data_out_edge(&mut self) {
+        if !self.motor || self.state != CassetteState::Recording {
+            return;
+        }
+        if let Some(tape) = &mut self.tape {
+            tape.push(self.edge_us);
+            self.position_us += self.edge_us;
+            self.pulse += 1;
+            self.edge_us = 0.0;
+            self.modified = true;
+        }
+    }
+
+    pub fn run(&mut self, us: f64) {
+        if !self.motor {
+            return;
+        }
+        match (self.state, &self.tape) {
+            (CassetteState::Playing, Some(tape)) => {
+                let pulses = tape.pulses();
+                self.pulse_us += us;
+                while self.pulse < pulses.len() && self.pulse_us >= pulses[self.pulse] {
+                    self.pulse_us -= pulses[self.pulse];
+                    self.position_us += pulses[self.pulse];
+                    self.pulse += 1;
+                }
+                if self.pulse >= pulses.len() {
+                    // The end of the tape has been reached.
+                    self.pulse_us = 0.0;
+                }
+            }
+            (CassetteState::Recording, Some(_)) => {
+                self.edge_us += us;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn status(&self) -> CassetteStatus {
+        let position = match self.state {
+            CassetteState::Recording => self.position_us + self.edge_us,
+            _ => self.position_us + self.pulse_us,
+        };
+        CassetteStatus {
+            loaded:   self.tape.is_some(),
+            state:    self.state,
+            motor:    self.motor,
+            position: position / 1_000_000.0,
+            length:   self.tape.as_ref().map_or(0.0, |tape| tape.length()) / 1_000_000.0,
+            modified: self.modified,
+        }
+    }
+}
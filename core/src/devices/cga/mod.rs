@@ -52,6 +52,7 @@ use super::*;
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
     device_traits::videocard::*,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     tracelogger::TraceLogger,
 };
 
@@ -558,6 +559,8 @@ pub struct CGACard {
 
     lightpen_latch: bool,
     lightpen_addr:  usize,
+    lightpen_pos:   Option<(u32, u32)>,
+    lightpen_down:  bool,
 
     out_of_sync: bool,
 }
@@ -756,6 +759,8 @@ impl Default for CGACard {
 
             lightpen_latch: false,
             lightpen_addr:  0,
+            lightpen_pos:   None,
+            lightpen_down:  false,
 
             out_of_sync: false,
         }
@@ -1353,7 +1358,9 @@ impl CGACard {
         }
 
         // This bit is logically reversed, i.e., 0 is switch on
-        //byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        if !self.lightpen_down {
+            byte |= STATUS_LIGHTPEN_SWITCH_STATUS;
+        }
 
         trace_regs!(self);
         trace!(
@@ -2011,6 +2018,17 @@ impl CGACard {
 
     /// Update the CRTC logic for next character.
     pub fn tick_crtc_char(&mut self) {
+        // Trigger the light pen latch as the beam passes under the pen.
+        if let Some((pen_x, pen_y)) = self.lightpen_pos {
+            if self.in_display_area
+                && self.beam_y == pen_y
+                && self.beam_x >= pen_x
+                && self.beam_x < pen_x + CGA_LCHAR_CLOCK as u32
+            {
+                self.set_lp_latch();
+            }
+        }
+
         if self.hcc_c0 == 0 {
             self.hborder = false;
             if self.vcc_c4 == 0 {
@@ -2307,3 +2325,52 @@ impl CGACard {
         println!("{}", self.vtac_c5);
     }
 }
+
+/// Video memory and registers are saved, but not the position of the beam. On load the card is reset and the
+/// registers are written back as if by the guest, so display starts again from the top of a frame.
+impl SaveState for CGACard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.mem[..]);
+        let crtc_regs = [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+            self.crtc_cursor_start_line | (self.cursor_attr << 5),
+            self.crtc_cursor_end_line,
+            self.crtc_start_address_ho,
+            self.crtc_start_address_lo,
+            self.crtc_cursor_address_ho,
+            self.crtc_cursor_address_lo,
+        ];
+        w.write_bytes(&crtc_regs);
+        w.write_u8(self.crtc_register_select_byte);
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.cc_register);
+        w.write_bool(self.lightpen_latch);
+        w.write_usize(self.lightpen_addr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.reset_private();
+        r.read_bytes_into(&mut self.mem[..], "CGA memory")?;
+        let crtc_regs = r.read_bytes()?;
+        for (i, byte) in crtc_regs.iter().enumerate() {
+            self.handle_crtc_register_select(i as u8);
+            self.handle_crtc_register_write(*byte);
+        }
+        self.handle_crtc_register_select(r.read_u8()?);
+        self.mode_byte = r.read_u8()?;
+        self.handle_cc_register_write(r.read_u8()?);
+        self.update_mode();
+        self.lightpen_latch = r.read_bool()?;
+        self.lightpen_addr = r.read_usize()?;
+        Ok(())
+    }
+}
@@ -136,6 +136,11 @@ impl VideoCard for CGACard {
         Some((self.beam_x, self.beam_y))
     }
 
+    fn set_light_pen(&mut self, pos: Option<(u32, u32)>, switch: bool) {
+        self.lightpen_pos = pos;
+        self.lightpen_down = switch;
+    }
+
     /// Tick the CGA the specified number of video clock cycles.
     fn debug_tick(&mut self, ticks: u32, _cpumem: Option<&[u8]>) {
         match self.clock_mode {
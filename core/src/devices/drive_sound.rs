@@ -0,0 +1,270 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::drive_sound.rs
+
+    Sound effects for floppy drives: a click for each head step, the motor
+    spinning up, and the hum of the spindle while the motor runs. These are
+    not part of the emulated machine's audio, so the mixer source is muted
+    unless drive sounds are enabled in the configuration.
+
+    Default sounds are synthesized. They can be replaced by placing mono
+    PCM WAV files named floppy_step.wav, floppy_spinup.wav and
+    floppy_spindle.wav in the sound directory. The spindle sound is looped.
+
+*/
+
+use std::{f32::consts::TAU, path::Path};
+
+use crate::{sound_mixer::SoundSource, wav::decode_wav};
+
+// Rate at which drive sounds are generated for the mixer.
+pub const DRIVE_SOUND_RATE: f64 = 44_100.0;
+
+pub const STEP_SOUND_FILE: &str = "floppy_step.wav";
+pub const SPINUP_SOUND_FILE: &str = "floppy_spinup.wav";
+pub const SPINDLE_SOUND_FILE: &str = "floppy_spindle.wav";
+
+// The most head steps that can be heard at once. A fast seek overlaps step sounds.
+const MAX_STEP_VOICES: usize = 8;
+// Time for the spindle hum to fade in or out when the motor starts or stops, in seconds.
+const SPINDLE_FADE_SECONDS: f32 = 0.3;
+
+const STEP_SECONDS: f32 = 0.015;
+const SPINUP_SECONDS: f32 = 0.4;
+// One revolution at 300RPM, so that the loop repeats seamlessly.
+const SPINDLE_SECONDS: f32 = 0.2;
+
+/// The set of samples played by the drive sound effects, at DRIVE_SOUND_RATE.
+#[derive(Clone)]
+pub struct DriveSoundSamples {
+    pub step:    Vec<f32>,
+    pub spin_up: Vec<f32>,
+    pub spindle: Vec<f32>,
+}
+
+impl Default for DriveSoundSamples {
+    fn default() -> Self {
+        Self {
+            step:    synthesize(STEP_SECONDS, |t, noise| {
+                // A sharp metallic tick from the stepper and a dull thump from the head carriage.
+                let tick = (TAU * 1800.0 * t).sin() * (-t / 0.0015).exp() * 0.5;
+                let thump = (TAU * 120.0 * t).sin() * (-t / 0.004).exp() * 0.3;
+                tick + thump + noise * (-t / 0.0006).exp() * 0.3
+            }),
+            spin_up: synthesize(SPINUP_SECONDS, |t, noise| {
+                // The motor whine rises in pitch as it comes up to speed.
+                let progress = t / SPINUP_SECONDS;
+                let phase = TAU * (60.0 * t + 150.0 * t * progress);
+                let env = (progress * 8.0).min(1.0) * (1.0 - progress * 0.6);
+                (phase.sin() * 0.12 + (phase * 2.0).sin() * 0.05 + noise * 0.02) * env
+            }),
+            spindle: synthesize(SPINDLE_SECONDS, |t, noise| {
+                // Motor hum, modulated once per revolution by the drag of the disk.
+                let rev = 1.0 + 0.2 * (TAU * 5.0 * t).sin();
+                let hum = (TAU * 50.0 * t).sin() * 0.05 + (TAU * 100.0 * t).sin() * 0.03;
+                hum * rev + noise * 0.01
+            }),
+        }
+    }
+}
+
+/// Generate `seconds` of audio at DRIVE_SOUND_RATE from a function of time and a noise value.
+fn synthesize(seconds: f32, f: impl Fn(f32, f32) -> f32) -> Vec<f32> {
+    let len = (seconds * DRIVE_SOUND_RATE as f32) as usize;
+    // A fixed seed keeps the sounds the same from run to run.
+    let mut seed: u32 = 0x1234_5678;
+    (0..len)
+        .map(|i| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (seed as f32 / u32::MAX as f32) * 2.0 - 1.0;
+            f(i as f32 / DRIVE_SOUND_RATE as f32, noise)
+        })
+        .collect()
+}
+
+/// Load a sample from a WAV file in `dir`, resampled to DRIVE_SOUND_RATE. Returns None if the file doesn't exist
+/// or can't be decoded.
+fn load_sample(dir: &Path, name: &str) -> Option<Vec<f32>> {
+    let path = dir.join(name);
+    if !path.is_file() {
+        return None;
+    }
+    let result = std::fs::read(&path).map_err(anyhow::Error::from);
+    match result.and_then(|data| decode_wav(&data)) {
+        Ok(wav) => {
+            log::debug!("Loaded drive sound: {}", path.display());
+            Some(wav.resample(DRIVE_SOUND_RATE as u32))
+        }
+        Err(e) => {
+            log::warn!("Failed to load drive sound {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Voice {
+    Step(usize),
+    SpinUp(usize),
+}
+
+pub struct DriveSound {
+    samples: DriveSoundSamples,
+    voices: Vec<Voice>,
+    motor: bool,
+    spindle_pos: usize,
+    spindle_gain: f32,
+
+    output_accum: f64,
+    source: Option<SoundSource>,
+}
+
+impl Default for DriveSound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DriveSound {
+    pub fn new() -> Self {
+        Self {
+            samples: DriveSoundSamples::default(),
+            voices: Vec::with_capacity(MAX_STEP_VOICES + 1),
+            motor: false,
+            spindle_pos: 0,
+            spindle_gain: 0.0,
+
+            output_accum: 0.0,
+            source: None,
+        }
+    }
+
+    /// Set the mixer source that output samples are sent to.
+    pub fn set_sound_source(&mut self, source: SoundSource) {
+        self.source = Some(source);
+    }
+
+    /// Return the mixer id of the output source, if one is set.
+    pub fn source_id(&self) -> Option<usize> {
+        self.source.as_ref().map(|source| source.id())
+    }
+
+    pub fn set_samples(&mut self, samples: DriveSoundSamples) {
+        self.samples = samples;
+        self.voices.clear();
+        self.spindle_pos = 0;
+    }
+
+    /// Replace the default sounds with any sound files present in the specified directory.
+    pub fn load_samples(&mut self, dir: &Path) {
+        let mut samples = self.samples.clone();
+        if let Some(step) = load_sample(dir, STEP_SOUND_FILE) {
+            samples.step = step;
+        }
+        if let Some(spin_up) = load_sample(dir, SPINUP_SOUND_FILE) {
+            samples.spin_up = spin_up;
+        }
+        if let Some(spindle) = load_sample(dir, SPINDLE_SOUND_FILE) {
+            samples.spindle = spindle;
+        }
+        self.set_samples(samples);
+    }
+
+    /// Play the sound of the head stepping one cylinder.
+    pub fn step(&mut self) {
+        if self.source.is_none() {
+            return;
+        }
+        let steps = self.voices.iter().filter(|v| matches!(v, Voice::Step(_))).count();
+        if steps >= MAX_STEP_VOICES {
+            if let Some(oldest) = self.voices.iter().position(|v| matches!(v, Voice::Step(_))) {
+                self.voices.remove(oldest);
+            }
+        }
+        self.voices.push(Voice::Step(0));
+    }
+
+    /// Run for the specified number of microseconds, sending output samples to the mixer. `motor` is true if the
+    /// motor of any drive is on.
+    pub fn run(&mut self, us: f64, motor: bool) {
+        if self.source.is_none() {
+            return;
+        }
+        if motor && !self.motor {
+            self.voices.push(Voice::SpinUp(0));
+        }
+        self.motor = motor;
+
+        let period = 1_000_000.0 / DRIVE_SOUND_RATE;
+        self.output_accum += us;
+        while self.output_accum >= period {
+            self.output_accum -= period;
+            let sample = self.next_sample();
+            if let Some(source) = &mut self.source {
+                source.push(sample);
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let samples = &self.samples;
+        let mut out = 0.0;
+
+        self.voices.retain_mut(|voice| {
+            let (sample, pos) = match voice {
+                Voice::Step(pos) => (&samples.step, pos),
+                Voice::SpinUp(pos) => (&samples.spin_up, pos),
+            };
+            match sample.get(*pos) {
+                Some(s) => {
+                    out += s;
+                    *pos += 1;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        // The spindle fades in as the motor comes up to speed and fades out as it spins down.
+        let fade = 1.0 / (SPINDLE_FADE_SECONDS * DRIVE_SOUND_RATE as f32);
+        self.spindle_gain = if self.motor {
+            (self.spindle_gain + fade).min(1.0)
+        }
+        else {
+            (self.spindle_gain - fade).max(0.0)
+        };
+        if self.spindle_gain > 0.0 && !samples.spindle.is_empty() {
+            self.spindle_pos %= samples.spindle.len();
+            out += samples.spindle[self.spindle_pos] * self.spindle_gain;
+            self.spindle_pos += 1;
+        }
+
+        out.clamp(-1.0, 1.0)
+    }
+}
@@ -27,17 +27,28 @@
     devices::fdc.rs
 
     Implements the NEC µPD765 Floppy Disk Controller
+
+    The controller operates on a sector level model of the disk in each drive.
+    The disk rotates under the head in real time: sectors are found by
+    watching ID fields pass the head, data is transferred at the drive's data
+    rate, and searches give up after two index pulses. This lets software
+    that depends on sector interleave, non-standard IDs, deleted data marks or
+    CRC errors see the same results it would on real hardware.
 */
 
 #![allow(dead_code)]
 
 use std::{collections::VecDeque, default::Default};
 
+use anyhow::{anyhow, Error};
+
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
-    device_types::{chs::DiskChs, fdc::DISK_FORMATS},
-    devices::{dma, floppy_drive::FloppyDiskDrive},
-    machine_types::FdcType,
+    device_types::drive_status::DriveStatus,
+    devices::{dma, drive_sound::DriveSound, floppy_drive::FloppyDiskDrive},
+    floppy_image::{sector_size, FloppyImage, FloppySector, FloppyTrack, SectorId, SECTOR_OVERHEAD},
+    machine_types::{FdcType, FloppyDriveType},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 
 pub const FDC_IRQ: u8 = 0x06;
@@ -62,9 +73,9 @@ pub const FDC_STATUS_FDD_B_BUSY: u8 = 0b0000_0010;
 pub const FDC_STATUS_FDD_C_BUSY: u8 = 0b0000_0100;
 pub const FDC_STATUS_FDD_D_BUSY: u8 = 0b0000_1000;
 
-// Busy bit seems to be on while there are bytes remaining to be read from
-// the Data register. The BIOS checks this bit to tell when it is done reading
-// from the FDC data register.
+// The busy bit is set from the first byte of a command until the last byte of
+// the result phase has been read. The BIOS checks this bit to tell when it is
+// done reading from the FDC data register.
 pub const FDC_STATUS_FDC_BUSY: u8 = 0b0001_0000;
 pub const FDC_STATUS_NON_DMA_MODE: u8 = 0b0010_0000;
 
@@ -97,12 +108,18 @@ pub const DOR_JRFDC_WATCHDOG_TRIGGER: u8 = 0b0100_0000;
 pub const WATCHDOG_TIMEOUT: f64 = 3_000_000.0; // 3 seconds in microseconds
 
 pub const COMMAND_MASK: u8 = 0b0001_1111;
+pub const COMMAND_MT_BIT: u8 = 0b1000_0000; // Multi-track
+pub const COMMAND_MF_BIT: u8 = 0b0100_0000; // MFM mode
+pub const COMMAND_SK_BIT: u8 = 0b0010_0000; // Skip deleted data
 pub const COMMAND_READ_TRACK: u8 = 0x02;
 pub const COMMAND_WRITE_SECTOR: u8 = 0x05;
 pub const COMMAND_READ_SECTOR: u8 = 0x06;
 pub const COMMAND_WRITE_DELETED_SECTOR: u8 = 0x09;
 pub const COMMAND_READ_DELETED_SECTOR: u8 = 0x0C;
 pub const COMMAND_FORMAT_TRACK: u8 = 0x0D;
+pub const COMMAND_SCAN_EQUAL: u8 = 0x11;
+pub const COMMAND_SCAN_LOW_OR_EQUAL: u8 = 0x19;
+pub const COMMAND_SCAN_HIGH_OR_EQUAL: u8 = 0x1D;
 
 pub const COMMAND_FIX_DRIVE_DATA: u8 = 0x03;
 pub const COMMAND_CHECK_DRIVE_STATUS: u8 = 0x04;
@@ -115,14 +132,25 @@ pub const ST0_HEAD_ACTIVE: u8 = 0b0000_0100;
 pub const ST0_NOT_READY: u8 = 0b0000_1000;
 pub const ST0_UNIT_CHECK: u8 = 0b0001_0000;
 pub const ST0_SEEK_END: u8 = 0b0010_0000;
-pub const ST0_ABNORMAL_TERMINATION: u8 = 0b01000_0000;
+pub const ST0_ABNORMAL_TERMINATION: u8 = 0b0100_0000;
 pub const ST0_INVALID_OPCODE: u8 = 0b1000_0000;
 pub const ST0_ABNORMAL_POLLING: u8 = 0b1100_0000;
 pub const ST0_RESET: u8 = 0b1100_0000;
 
-pub const ST1_NO_ID: u8 = 0b0000_0001;
+pub const ST1_NO_ID: u8 = 0b0000_0001; // Missing address mark
 pub const ST1_WRITE_PROTECT: u8 = 0b0000_0010;
 pub const ST1_NODATA: u8 = 0b0000_0100;
+pub const ST1_OVERRUN: u8 = 0b0001_0000;
+pub const ST1_CRC_ERROR: u8 = 0b0010_0000;
+pub const ST1_END_OF_CYLINDER: u8 = 0b1000_0000;
+
+pub const ST2_MISSING_DATA_MARK: u8 = 0b0000_0001;
+pub const ST2_BAD_CYLINDER: u8 = 0b0000_0010;
+pub const ST2_SCAN_NOT_SATISFIED: u8 = 0b0000_0100;
+pub const ST2_SCAN_HIT: u8 = 0b0000_1000;
+pub const ST2_WRONG_CYLINDER: u8 = 0b0001_0000;
+pub const ST2_DATA_CRC_ERROR: u8 = 0b0010_0000;
+pub const ST2_CONTROL_MARK: u8 = 0b0100_0000;
 
 pub const ST3_ESIG: u8 = 0b1000_0000;
 pub const ST3_WRITE_PROTECT: u8 = 0b0100_0000;
@@ -131,14 +159,27 @@ pub const ST3_TRACK0: u8 = 0b0001_0000;
 pub const ST3_DOUBLESIDED: u8 = 0b0000_1000;
 pub const ST3_HEAD: u8 = 0b0000_0100;
 
-/// Represent the state of the DIO bit of the Main Status Register in a readable way.
-pub enum IoMode {
-    ToCpu,
-    FromCpu,
-}
+// Time to transfer one byte at 250Kbps (double density) and 500Kbps (high density) MFM.
+pub const BYTE_TIME_DD_US: f64 = 32.0;
+pub const BYTE_TIME_HD_US: f64 = 16.0;
+
+// Bytes between the index pulse and the first ID field: Gap 4a, sync, index address mark and Gap 1.
+pub const TRACK_PREAMBLE_BYTES: usize = 146;
+
+// Bytes between the start of an ID field and the start of its data: the ID field and CRC, Gap 2, sync and data
+// address mark.
+pub const ID_TO_DATA_BYTES: usize = 44;
+
+// Initial state of the generator used to read weak bits.
+pub const WEAK_BIT_SEED: u32 = 0x2545_F491;
+// Bytes between the end of a data field and the end of the sector: the data field CRC.
+pub const DATA_CRC_BYTES: usize = 2;
+
+// A Recalibrate command gives up if track 0 isn't reached after this many steps.
+pub const RECALIBRATE_MAX_STEPS: u8 = 77;
 
 /// Represent the various commands that the NEC FDC knows how to handle.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     NoCommand,
     ReadTrack,
@@ -147,6 +188,9 @@ pub enum Command {
     WriteDeletedSector,
     ReadDeletedSector,
     FormatTrack,
+    ScanEqual,
+    ScanLowOrEqual,
+    ScanHighOrEqual,
     FixDriveData,
     CheckDriveStatus,
     CalibrateDrive,
@@ -156,14 +200,6 @@ pub enum Command {
     Invalid,
 }
 
-/// Encapsulates a result from a command or operation execution and used to build a
-/// status response.
-pub enum ControllerResult {
-    Success(InterruptCode),
-    GeneralFailure(InterruptCode),
-    WriteProtectFailure,
-}
-
 /// Represents the possible values of the Interrupt Code field in Status Register 0.
 /// Returning 'AbnormalTermination' may result in a General Failure reading drive
 /// message in DOS.
@@ -176,38 +212,70 @@ pub enum InterruptCode {
     AbnormalPolling,
 }
 
-/// Attempt to classify every general error condition a virtual disk drive may experience.
-/// These states are used to build the status bytes presented after a command has been
-/// executed. The exact mapping between error conditions and status flags is uncertain...
-#[derive(Clone, Copy, Debug)]
-pub enum DriveError {
-    NoError,
-    NoMedia,
-    BadSeek,
-    BadRead,
-    BadWrite,
-    WriteProtect,
-    DMAError,
-}
-
-pub struct OperationSpecifier {
-    pub chs: DiskChs,
-    pub sector_size: u8,
-    pub track_len: u8,
-    pub gap3_len: u8,
-    pub data_len: u8,
+/// The phase of the controller determines how the Data Register is used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ControllerPhase {
+    Command,
+    Execution,
+    Result,
 }
 
 /// Classify operations - an Operation is intiated by any Command that does not immediately
 /// terminate, and is called on a repeated basis by the run() method until complete.
 ///
 /// Operations usually involve DMA transfers.
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Operation {
     NoOperation,
-    ReadSector(u8, u8, u8, u8, u8, u8, u8), // cylinder, head, sector, sector_size, track_len, gap3_len, data_len
-    WriteSector(u8, u8, u8, u8, u8, u8, u8), // cylinder, head, sector, sector_size, track_len, gap3_len, data_len
-    FormatTrack(u8, u8, u8, u8),
+    ReadSector,
+    WriteSector,
+    ReadTrack,
+    ReadSectorId,
+    FormatTrack,
+    Scan,
+}
+
+/// The step of an operation that is waiting for its next event.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum OperationState {
+    FindSector, // Waiting for an ID field to pass under the head
+    SectorData, // Transferring the data field of a sector
+    SectorEnd,  // Waiting for the data field CRC
+    WaitIndex,  // Waiting for the index pulse that begins a Read Track or Format Track
+    FormatId,   // Receiving the ID of the next sector to format
+    FormatEnd,  // Waiting for the index pulse that ends a Format Track
+}
+
+/// The parameters of the current operation. `id` holds the C, H, R and N registers, which are updated as a
+/// multi-sector operation progresses.
+#[derive(Copy, Clone, Debug, Default)]
+struct OperationParams {
+    drive: usize,
+    head:  u8, // Physical head, selected by the HDS bit
+    id:    SectorId,
+    eot:   u8,
+    gpl:   u8,
+    dtl:   u8, // Data length when N is 0, or STP for scan commands
+    mt:    bool,
+    sk:    bool,
+    sc:    u8, // Sectors per track for Format Track
+    fill:  u8,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct SeekState {
+    active: bool,
+    recalibrate: bool,
+    target: u8,
+    steps: u8,
+    timer: f64,
+}
+
+/// The layout of the track under the head, used to determine when each sector passes the head.
+struct TrackTiming {
+    starts: Vec<f64>, // Time of each ID field from the index pulse
+    byte_us: f64,
+    rotation_us: f64,
 }
 
 type CommandDispatchFn = fn(&mut FloppyController) -> Continuation;
@@ -217,53 +285,70 @@ pub enum Continuation {
 }
 
 pub struct FloppyController {
-    us_accumulator: f64,
     watchdog_accumulator: f64,
     fdc_type: FdcType,
-    status_byte: u8,
     reset_flag: bool,
     reset_sense_count: u8,
-    mrq: bool,
+    in_reset: bool,
 
-    data_register: u8,
     dma: bool,
     dor: u8,
-    busy: bool,
-    dio: IoMode,
-    reading_command: bool,
+    phase: ControllerPhase,
     command: Command,
     command_fn: Option<CommandDispatchFn>,
+    command_flags: u8,
     last_command: Command,
     receiving_command: bool,
     command_byte_n: u32,
+
     operation: Operation,
-    operation_init: bool,
-    send_interrupt: bool,
-    pending_interrupt: bool,
-    end_interrupt: bool,
+    op_state: OperationState,
+    op: OperationParams,
+    op_timer: f64,
+    index_ct: u8,
+    ids_seen: bool,
+    wrong_cylinder: u8,
+    next_sector: Option<usize>,
+    cross_index: bool,
+    sector_idx: usize,
+    sector_buf: Vec<u8>,
+    sector_len: usize,
+    sector_pos: usize,
+    sector_byte_us: f64,
+    sector_crc_error: bool,
+    weak_bit_state: u32,
+    terminal_count: bool,
+    terminate_after_sector: bool,
+    scan_equal: bool,
+    scan_satisfied: bool,
+    format_sectors: Vec<FloppySector>,
+    format_id: Vec<u8>,
+    pio_request: bool,
+    st1: u8,
+    st2: u8,
+
+    int_line: bool,
+    irq_active: bool,
     watchdog_enabled: bool,     // IBM PCJr only.  Watchdog timer enabled.
     watchdog_trigger_bit: bool, // IBM PCJr only.  Watchdog timer trigger bit status.
     watchdog_triggered: bool,   // IBM PCJr only.  Watchdog timer triggered.
 
-    last_error: DriveError,
+    step_rate: u8,
+    head_unload_time: u8,
+    head_load_time: u8,
+    pcn: [u8; FDC_MAX_DRIVES],
+    seeks: [SeekState; FDC_MAX_DRIVES],
+    seek_status: [Option<u8>; FDC_MAX_DRIVES],
 
     data_register_out: VecDeque<u8>,
-    data_register_in: VecDeque<u8>,
-    format_buffer: VecDeque<u8>,
+    data_register_in:  VecDeque<u8>,
 
     drives: [FloppyDiskDrive; 4],
     drive_ct: usize,
     drive_select: usize,
+    drives_swapped: bool, // Drives 0 and 1 are exchanged, so the guest sees drive B as drive A
 
-    in_dma: bool,
-    dma_byte_count: usize,
-    dma_bytes_left: usize,
-    pio_byte_count: usize,
-    pio_sector_byte_count: usize,
-    pio_bytes_left: usize,
-    xfer_size_sectors: u32,
-    xfer_size_bytes: usize,
-    xfer_completed_sectors: u32,
+    sound: DriveSound,
 }
 
 /// IO Port handlers for the FDC
@@ -326,39 +411,63 @@ impl IoDevice for FloppyController {
 impl Default for FloppyController {
     fn default() -> Self {
         Self {
-            us_accumulator: 0.0,
             watchdog_accumulator: 0.0,
             fdc_type: FdcType::IbmNec,
-            status_byte: 0,
             reset_flag: false,
             reset_sense_count: 0,
-            mrq: true,
-            data_register: 0,
+            in_reset: false,
+
             dma: true,
             dor: 0,
-            busy: false,
-            dio: IoMode::FromCpu,
-            reading_command: false,
+            phase: ControllerPhase::Command,
             command: Command::NoCommand,
             command_fn: None,
+            command_flags: 0,
             last_command: Command::NoCommand,
             command_byte_n: 0,
             receiving_command: false,
-            operation: Operation::NoOperation,
-            operation_init: false,
 
-            last_error: DriveError::NoError,
-
-            send_interrupt: false,
-            pending_interrupt: false,
-            end_interrupt: false,
+            operation: Operation::NoOperation,
+            op_state: OperationState::FindSector,
+            op: Default::default(),
+            op_timer: 0.0,
+            index_ct: 0,
+            ids_seen: false,
+            wrong_cylinder: 0,
+            next_sector: None,
+            cross_index: false,
+            sector_idx: 0,
+            sector_buf: Vec::new(),
+            sector_len: 0,
+            sector_pos: 0,
+            sector_byte_us: BYTE_TIME_DD_US,
+            sector_crc_error: false,
+            weak_bit_state: WEAK_BIT_SEED,
+            terminal_count: false,
+            terminate_after_sector: false,
+            scan_equal: false,
+            scan_satisfied: false,
+            format_sectors: Vec::new(),
+            format_id: Vec::new(),
+            pio_request: false,
+            st1: 0,
+            st2: 0,
+
+            int_line: false,
+            irq_active: false,
             watchdog_enabled: false,
             watchdog_trigger_bit: false,
             watchdog_triggered: false,
 
+            step_rate: 0,
+            head_unload_time: 0,
+            head_load_time: 0,
+            pcn: [0; FDC_MAX_DRIVES],
+            seeks: [SeekState::default(); FDC_MAX_DRIVES],
+            seek_status: [None; FDC_MAX_DRIVES],
+
             data_register_out: VecDeque::new(),
-            data_register_in: VecDeque::new(),
-            format_buffer: VecDeque::new(),
+            data_register_in:  VecDeque::new(),
 
             drives: [
                 FloppyDiskDrive::new(),
@@ -368,16 +477,9 @@ impl Default for FloppyController {
             ],
             drive_ct: 0,
             drive_select: 0,
+            drives_swapped: false,
 
-            in_dma: false,
-            dma_byte_count: 0,
-            dma_bytes_left: 0,
-            pio_byte_count: 0,
-            pio_sector_byte_count: 0,
-            pio_bytes_left: 0,
-            xfer_size_sectors: 0,
-            xfer_size_bytes: 0,
-            xfer_completed_sectors: 0,
+            sound: DriveSound::new(),
         }
     }
 }
@@ -395,129 +497,178 @@ impl FloppyController {
         Self {
             fdc_type,
             drive_ct,
+            // The PCJr has no DMA controller, so all transfers are done by PIO.
+            dma: !matches!(fdc_type, FdcType::IbmPCJrNec),
             ..Default::default()
         }
     }
 
     /// Reset the Floppy Drive Controller
     pub fn reset(&mut self) {
-        // TODO: Implement in terms of Default
-        self.status_byte = 0;
-        self.drive_select = 0;
-        self.reset_flag = true;
-        self.reset_sense_count = 0;
-
-        self.data_register_out.clear();
-        self.data_register_in.clear();
-        self.format_buffer.clear();
-
-        self.mrq = true;
-        self.dio = IoMode::FromCpu;
+        // The Specify parameters and the DOR are not affected by a reset.
+        *self = Self {
+            fdc_type: self.fdc_type,
+            dma: self.dma,
+            dor: self.dor,
+            step_rate: self.step_rate,
+            head_unload_time: self.head_unload_time,
+            head_load_time: self.head_load_time,
+            irq_active: self.irq_active,
+            watchdog_enabled: self.watchdog_enabled,
+            watchdog_trigger_bit: self.watchdog_trigger_bit,
+            watchdog_triggered: self.watchdog_triggered,
+            watchdog_accumulator: self.watchdog_accumulator,
+            drives: std::mem::take(&mut self.drives),
+            drive_ct: self.drive_ct,
+            drives_swapped: self.drives_swapped,
+            sound: std::mem::take(&mut self.sound),
+            reset_flag: true,
+            ..Default::default()
+        };
 
         // Reset all drives.
         for drive in &mut self.drives.iter_mut() {
             drive.reset();
         }
+    }
 
-        self.last_error = DriveError::NoError;
-        self.receiving_command = false;
-        self.command = Command::NoCommand;
-        self.command_fn = None;
-        self.command_byte_n = 0;
+    pub fn drive_ct(&self) -> usize {
+        self.drive_ct
+    }
 
-        self.send_interrupt = false;
-        self.pending_interrupt = false;
-        self.end_interrupt = false;
+    /// Map a drive number as seen by the frontend to the drive the guest selects to reach it. The methods that
+    /// manage drives and their disks take frontend drive numbers.
+    fn guest_drive(&self, drive_select: usize) -> usize {
+        match drive_select {
+            0 | 1 if self.drives_swapped => drive_select ^ 1,
+            _ => drive_select,
+        }
+    }
 
-        self.in_dma = false;
-        self.dma_byte_count = 0;
-        self.dma_bytes_left = 0;
+    /// Exchange drives 0 and 1 as seen by the guest, so that the machine boots from the disk in drive B.
+    pub fn set_drives_swapped(&mut self, swapped: bool) {
+        if swapped == self.drives_swapped || (swapped && self.drive_ct < 2) {
+            return;
+        }
+        log::debug!("Floppy drives A: and B: swapped: {}", swapped);
+        self.drives.swap(0, 1);
+        self.drives_swapped = swapped;
     }
 
-    pub fn drive_ct(&self) -> usize {
-        self.drive_ct
+    /// Hide the disks in all drives from the guest, so that the BIOS finds the drives empty and boots from
+    /// the hard disk.
+    pub fn set_disks_hidden(&mut self, hidden: bool) {
+        for drive in self.drives.iter_mut() {
+            drive.hidden = hidden;
+        }
+    }
+
+    /// Return the status of the specified drive for activity indicators. Read and write activity is cleared.
+    pub fn take_drive_status(&mut self, drive_select: usize) -> DriveStatus {
+        let drive_select = self.guest_drive(drive_select);
+        self.drives[drive_select].take_status()
+    }
+
+    pub fn sound_mut(&mut self) -> &mut DriveSound {
+        &mut self.sound
+    }
+
+    /// Set the type of the specified drive.
+    pub fn set_drive_type(&mut self, drive_select: usize, drive_type: FloppyDriveType) {
+        if drive_select < FDC_MAX_DRIVES {
+            let drive_select = self.guest_drive(drive_select);
+            self.drives[drive_select].set_drive_type(drive_type);
+        }
+    }
+
+    /// Return the type of the specified drive.
+    pub fn drive_type(&self, drive_select: usize) -> Option<FloppyDriveType> {
+        self.drives[..self.drive_ct]
+            .get(self.guest_drive(drive_select))
+            .map(|drive| drive.drive_type)
+    }
+
+    /// Load a disk image in any supported format into the specified drive
+    pub fn load_image_from(&mut self, drive_select: usize, src_vec: Vec<u8>, write_protect: bool) -> Result<(), Error> {
+        let image = FloppyImage::load(&src_vec)?;
+        self.load_floppy_image(drive_select, image, write_protect)
     }
 
     /// Load a disk into the specified drive
-    pub fn load_image_from(
+    pub fn load_floppy_image(
         &mut self,
         drive_select: usize,
-        src_vec: Vec<u8>,
+        image: FloppyImage,
         write_protect: bool,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), Error> {
         if drive_select >= FDC_MAX_DRIVES {
-            return Err("Invalid drive selection");
+            return Err(anyhow!("Invalid drive selection"));
         }
 
-        let image_len: usize = src_vec.len();
+        log::debug!("Loading floppy image into drive: {}", drive_select);
+        let drive_select = self.guest_drive(drive_select);
+        self.drives[drive_select].load_image(image);
+        self.drives[drive_select].write_protected = write_protect;
+        Ok(())
+    }
 
-        // Disk images must contain whole sectors
-        if image_len % SECTOR_SIZE > 0 {
-            return Err("Invalid image length");
+    /// Return the contents of the disk in the specified drive as a raw sector image.
+    pub fn get_image_data(&self, drive_select: usize) -> Result<Vec<u8>, Error> {
+        if drive_select >= FDC_MAX_DRIVES {
+            return Err(anyhow!("Invalid drive selection"));
         }
 
-        // Look up disk parameters based on image size
-        if let Some(fmt) = DISK_FORMATS.get(&image_len) {
-            self.drives[drive_select].max_cylinders = fmt.chs.c();
-            self.drives[drive_select].max_heads = fmt.chs.h();
-            self.drives[drive_select].max_sectors = fmt.chs.s();
-        }
-        else {
-            // No image format found.
-            if image_len < 163_840 {
-                // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
-                // This is useful for loading things like boot sector images without having to copy them to
-                // a full disk image
-                self.drives[drive_select].max_cylinders = 40;
-                self.drives[drive_select].max_heads = 1;
-                self.drives[drive_select].max_sectors = 8;
-            }
-            else {
-                return Err("Invalid image length");
-            }
+        match &self.drives[self.guest_drive(drive_select)].image {
+            Some(image) => image.to_raw(),
+            None => Err(anyhow!("No disk in drive")),
         }
+    }
 
-        self.drives[drive_select].have_disk = true;
-        self.drives[drive_select].disk_image = src_vec;
-        log::debug!(
-            "Loaded floppy image, drive: {} size: {} c: {} h: {} s: {}",
-            drive_select,
-            self.drives[drive_select].disk_image.len(),
-            self.drives[drive_select].max_cylinders,
-            self.drives[drive_select].max_heads,
-            self.drives[drive_select].max_sectors
-        );
-
-        self.drives[drive_select].write_protected = write_protect;
+    pub fn get_image(&self, drive_select: usize) -> Option<&FloppyImage> {
+        self.drives
+            .get(self.guest_drive(drive_select))
+            .and_then(|drive| drive.image.as_ref())
+    }
 
-        Ok(())
+    /// Returns true if the disk in the specified drive has been written to since it was loaded or since
+    /// [clear_image_modified](Self::clear_image_modified) was last called.
+    pub fn image_modified(&self, drive_select: usize) -> bool {
+        self.drives
+            .get(self.guest_drive(drive_select))
+            .is_some_and(|drive| drive.image.is_some() && drive.modified)
     }
 
-    pub fn get_image_data(&self, drive_select: usize) -> Option<&[u8]> {
-        if self.drives[drive_select].disk_image.len() > 0 {
-            // We have at least some kind of disk image, return it
-            Some(&self.drives[drive_select].disk_image)
-        }
-        else {
-            None
+    /// Mark the disk in the specified drive as unmodified, for example after it has been saved.
+    pub fn clear_image_modified(&mut self, drive_select: usize) {
+        let drive_select = self.guest_drive(drive_select);
+        if let Some(drive) = self.drives.get_mut(drive_select) {
+            drive.modified = false;
         }
     }
 
+    /// Returns true if the disk in the specified drive is write protected.
+    pub fn is_write_protected(&self, drive_select: usize) -> bool {
+        self.drives
+            .get(self.guest_drive(drive_select))
+            .is_some_and(|drive| drive.write_protected)
+    }
+
     /// Unload (eject) the disk in the specified drive
     pub fn unload_image(&mut self, drive_select: usize) {
-        let drive = &mut self.drives[drive_select];
+        if drive_select >= FDC_MAX_DRIVES {
+            return;
+        }
 
-        drive.chs.set_c(0);
-        drive.chs.set_h(0);
-        drive.chs.set_s(1);
-        drive.max_cylinders = 40;
-        drive.max_heads = 1;
-        drive.max_sectors = 8;
-        drive.have_disk = false;
-        drive.disk_image.clear();
+        let drive_select = self.guest_drive(drive_select);
+        self.drives[drive_select].image = None;
+        self.drives[drive_select].modified = false;
     }
 
     pub fn handle_status_register_read(&mut self) -> u8 {
+        if self.in_reset {
+            return 0;
+        }
+
         let mut msr_byte = 0;
         for (i, drive) in self.drives.iter().enumerate() {
             if drive.positioning {
@@ -525,25 +676,32 @@ impl FloppyController {
             }
         }
 
-        if self.busy {
-            msr_byte |= FDC_STATUS_FDC_BUSY;
-        }
-
-        // The NDMA bit is sort of an PIO operation status bit. It is cleared when the drive is no
-        // longer busy with the operation.
-        if !self.dma && !matches!(self.operation, Operation::NoOperation) {
-            msr_byte |= FDC_STATUS_NON_DMA_MODE;
-        }
-
-        // DIO bit => 0=FDC Receiving 1=FDC Sending
-        if let IoMode::ToCpu = self.dio {
-            msr_byte |= FDC_STATUS_DIO;
-        }
-
-        // MRQ => Ready to receive or send data or commands via the data register
-        // set this always on for now
-        if self.mrq {
-            msr_byte |= FDC_STATUS_MRQ;
+        match self.phase {
+            ControllerPhase::Command => {
+                msr_byte |= FDC_STATUS_MRQ;
+                if self.receiving_command {
+                    msr_byte |= FDC_STATUS_FDC_BUSY;
+                }
+            }
+            ControllerPhase::Execution => {
+                msr_byte |= FDC_STATUS_FDC_BUSY;
+
+                // In PIO mode, MRQ is set when a data byte is available to read or is needed from the CPU.
+                if !self.dma {
+                    msr_byte |= FDC_STATUS_NON_DMA_MODE;
+                    if self.operation_reads_disk() {
+                        if !self.data_register_out.is_empty() {
+                            msr_byte |= FDC_STATUS_MRQ | FDC_STATUS_DIO;
+                        }
+                    }
+                    else if self.pio_request {
+                        msr_byte |= FDC_STATUS_MRQ;
+                    }
+                }
+            }
+            ControllerPhase::Result => {
+                msr_byte |= FDC_STATUS_MRQ | FDC_STATUS_DIO | FDC_STATUS_FDC_BUSY;
+            }
         }
 
         //log::trace!("Status Register Read: Drive select:{}, Value: {:02X}", self.drive_select, msr_byte);
@@ -551,21 +709,18 @@ impl FloppyController {
     }
 
     pub fn motor_on(&mut self, drive_select: usize) {
-        if self.drives[drive_select].have_disk {
-            self.drives[drive_select].motor_on = true;
-            self.drives[drive_select].ready = true;
-        }
+        self.drives[drive_select].set_motor(true);
     }
 
     pub fn motor_off(&mut self, drive_select: usize) {
         if self.drives[drive_select].motor_on {
             log::trace!("Drive {}: turning motor off.", drive_select)
         }
-        self.drives[drive_select].motor_on = false;
-        //self.drives[drive_select].ready = false;    // Breaks booting(?)
+        self.drives[drive_select].set_motor(false);
     }
 
     pub fn write_protect(&mut self, drive_select: usize, write_protected: bool) {
+        let drive_select = self.guest_drive(drive_select);
         self.drives[drive_select].write_protected = write_protected;
     }
 
@@ -575,9 +730,11 @@ impl FloppyController {
             // Ignore all other commands
             log::debug!("FDC Reset requested: {:02X}", data);
             self.reset();
-            self.send_interrupt = true;
+            self.in_reset = true;
         }
         else {
+            self.leave_reset();
+
             // Not reset. Turn drive motors on or off based on the MOTx bits in the DOR byte.
             let disk_n = data & 0x03;
             if data & DOR_MOTOR_FDD_A != 0 {
@@ -605,24 +762,17 @@ impl FloppyController {
                 self.motor_off(3);
             }
 
-            if data & DOR_DMA_ENABLED != 0 {
-                self.dma = true;
-            }
-            else {
-                self.dma = false;
-            }
-
             // Select drive from DRx bits.
             if self.drives[disk_n as usize].motor_on {
                 log::debug!("Drive {} selected, motor on", disk_n);
                 self.drive_select = disk_n as usize;
-                self.drives[disk_n as usize].motor_on = true;
             }
             else {
                 // It's valid to write to the dor without turning a motor on.
                 // In this case the FDC can be re-enabled, but with no drive selected.
             }
         }
+        // The DMA enable bit gates the FDC's DMA request and interrupt lines, which is checked when they are used.
         self.dor = data;
     }
 
@@ -632,9 +782,11 @@ impl FloppyController {
             // Ignore all other commands
             log::debug!("PCJr FDC Reset requested: {:02X}", data);
             self.reset();
-            self.send_interrupt = true;
+            self.in_reset = true;
         }
         else {
+            self.leave_reset();
+
             // Not reset. Turn drive motors on or off based on the drive enable bit.
             if data & DOR_JRFDC_MOTOR != 0 {
                 self.motor_on(0);
@@ -645,10 +797,6 @@ impl FloppyController {
 
             if data & DOR_DMA_ENABLED != 0 {
                 log::error!("PCJr FDC DMA was erroneously enabled");
-                self.dma = true;
-            }
-            else {
-                self.dma = false;
             }
 
             if data & DOR_JRFDC_WATCHDOG_ENABLE != 0 {
@@ -676,31 +824,36 @@ impl FloppyController {
         self.dor = data;
     }
 
+    /// Release the controller from reset. On coming out of reset the controller polls the drives and finds that
+    /// their ready status has changed, which raises an interrupt.
+    fn leave_reset(&mut self) {
+        if self.in_reset {
+            log::debug!("FDC leaving reset");
+            self.in_reset = false;
+            self.int_line = true;
+        }
+    }
+
     /// Create the ST0 status register bitfield with the given parameters.
     ///
     /// Note: returning an Interrupt Code of Abnormal Termination will result in a "General failure reading drive"
     ///
-    pub fn make_st0_byte(&self, interrupt_code: InterruptCode, drive_select: usize, seek_end: bool) -> u8 {
+    pub fn make_st0_byte(&self, interrupt_code: InterruptCode, drive_select: usize, head: u8) -> u8 {
         let mut st0: u8 = 0;
 
         // Set selected drive bits
         st0 |= (drive_select as u8) & 0x03;
 
         // Set active head bit
-        if self.drives[drive_select].chs.h() == 1 {
+        if head & 0x01 != 0 {
             st0 |= ST0_HEAD_ACTIVE;
         }
 
         // Set ready bit
-        if !self.drives[drive_select].ready || !self.drives[drive_select].have_disk {
+        if !self.drives[drive_select].ready() {
             st0 |= ST0_NOT_READY;
         }
 
-        // Set seek bit
-        if seek_end {
-            st0 |= ST0_SEEK_END;
-        }
-
         // Set interrupt code
         st0 |= match interrupt_code {
             InterruptCode::NormalTermination => 0,
@@ -712,53 +865,24 @@ impl FloppyController {
         st0
     }
 
-    /// Generate the value of the ST1 Status Register in response to a command
-    pub fn make_st1_byte(&self, drive_select: usize) -> u8 {
-        // The ST1 status register contains mostly error codes
-        let mut st1_byte = 0;
-
-        // Set the "No Data" bit if we received an invalid request
-        st1_byte |= match self.last_error {
-            DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => ST1_NODATA,
-            DriveError::WriteProtect => ST1_WRITE_PROTECT | ST1_NO_ID,
-            _ => 0,
-        };
-
-        // Based on DOS's behavior regarding the "Not ready error" it appears that
-        // operations without a disk timeout instead of returning a particular error
-        // flag. Need to verify this on real hardware if possible.
-        if !self.drives[drive_select].have_disk {
-            st1_byte |= ST1_NODATA | ST1_NO_ID;
-        }
-        st1_byte
-    }
-
-    /// Generate the value of the ST2 Status Register in response to a command
-    pub fn make_st2_byte(&self, _drive_select: usize) -> u8 {
-        // The ST2 status register contains mostly error codes, so for now we can just always return success
-        // by returning 0 until we handle possible errors.
-        0
-    }
-
     /// Generate the value of the ST3 Status Register in response to a command
-    pub fn make_st3_byte(&self, drive_select: usize) -> u8 {
+    pub fn make_st3_byte(&self, drive_select: usize, head: u8) -> u8 {
         // Set drive select bits DS0 & DS1
         let mut st3_byte = (drive_select & 0x03) as u8;
 
         // HDSEL signal: 1 == head 1 active
-        if self.drives[drive_select].chs.h() == 1 {
+        if head & 0x01 != 0 {
             st3_byte |= ST3_HEAD;
         }
 
-        // DSDR signal - Is this active for a double sided drive, or only when a double-sided disk is present?
+        // All supported drives are double sided.
         st3_byte |= ST3_DOUBLESIDED;
 
-        if self.drives[drive_select].chs.c() == 0 {
+        if self.drives[drive_select].track0() {
             st3_byte |= ST3_TRACK0;
         }
 
-        // Drive ready - Should drive be ready when no disk is present?
-        if self.drives[drive_select].ready {
+        if self.drives[drive_select].ready() {
             st3_byte |= ST3_READY;
         }
 
@@ -778,13 +902,25 @@ impl FloppyController {
     pub fn handle_data_register_read(&mut self) -> u8 {
         let mut out_byte = 0;
 
-        if self.data_register_out.len() > 0 {
-            out_byte = self.data_register_out.pop_front().unwrap();
-            if self.data_register_out.len() == 0 {
-                //log::trace!("Popped last byte, clearing busy flag");
-                // CPU has read all available bytes
-                self.busy = false;
-                self.dio = IoMode::FromCpu;
+        match self.phase {
+            ControllerPhase::Result => {
+                // Reading the first result byte clears the interrupt.
+                self.int_line = false;
+                if let Some(byte) = self.data_register_out.pop_front() {
+                    out_byte = byte;
+                }
+                if self.data_register_out.is_empty() {
+                    // CPU has read all available bytes
+                    self.phase = ControllerPhase::Command;
+                }
+            }
+            ControllerPhase::Execution if !self.dma => {
+                if let Some(byte) = self.data_register_out.pop_front() {
+                    out_byte = byte;
+                }
+            }
+            _ => {
+                log::warn!("Data Register read outside of result phase");
             }
         }
 
@@ -793,41 +929,22 @@ impl FloppyController {
     }
 
     pub fn set_command(&mut self, command: Command, n_bytes: u32, command_fn: CommandDispatchFn) {
-        // Since we are entering a new command, clear the previous error status
-        self.last_error = DriveError::NoError;
         self.receiving_command = true;
         self.command = command;
         self.command_fn = Some(command_fn);
         self.command_byte_n = n_bytes;
     }
 
-    pub fn send_data_register(&mut self) {
-        self.busy = true;
-        self.dio = IoMode::ToCpu;
-        self.mrq = true;
-    }
-
-    /// Returns whether the CHS address is valid for the specified drive
-    pub fn is_id_valid(&self, drive_select: usize, c: u8, h: u8, s: u8) -> bool {
-        if !self.drives[drive_select].have_disk {
-            log::debug!("is_id_valid(): false due to no disk: {}", drive_select);
-            return false;
-        }
-
-        // Sectors are 1-indexed
-        if c < self.drives[drive_select].max_cylinders
-            && h < self.drives[drive_select].max_heads
-            && s <= self.drives[drive_select].max_sectors
-        {
-            return true;
-        }
-        log::debug!("is_id_valid(): false due to chs out of range");
-        return false;
+    /// Enter the result phase with the specified result bytes.
+    fn send_results(&mut self, results: &[u8]) {
+        self.data_register_out.clear();
+        self.data_register_out.extend(results);
+        self.phase = ControllerPhase::Result;
     }
 
     /// Handle a write to the Data Register, 0x3F5.
     ///
-    /// This register receives various commands which may be up to 8 bytes long.
+    /// This register receives various commands which may be up to 9 bytes long.
     ///
     /// We register both the size of the command and the callback function to call once all bytes for the command
     /// have been read in.
@@ -836,12 +953,33 @@ impl FloppyController {
     /// time like DMA transfers.
     pub fn handle_data_register_write(&mut self, data: u8) {
         //log::trace!("Data Register Write");
+        if self.in_reset {
+            return;
+        }
+
+        match self.phase {
+            ControllerPhase::Execution => {
+                // In PIO mode, the CPU writes the data for write and format operations
+                if !self.dma {
+                    self.data_register_in.push_back(data);
+                    self.pio_request = false;
+                }
+                return;
+            }
+            ControllerPhase::Result => {
+                log::warn!("Data Register write during result phase");
+                return;
+            }
+            ControllerPhase::Command => {}
+        }
+
         if !self.receiving_command {
             let command = data & COMMAND_MASK;
+            self.command_flags = data;
             match command {
                 COMMAND_READ_TRACK => {
                     log::trace!("Received Read Track command: {:02}", command);
-                    log::error!("Command unimplemented");
+                    self.set_command(Command::ReadTrack, 8, FloppyController::command_read_track);
                 }
                 COMMAND_WRITE_SECTOR => {
                     log::trace!("Received Write Sector command: {:02}", command);
@@ -853,16 +991,28 @@ impl FloppyController {
                 }
                 COMMAND_WRITE_DELETED_SECTOR => {
                     log::trace!("Received Write Deleted Sector command: {:02}", command);
-                    log::error!("Command unimplemented");
+                    self.set_command(Command::WriteDeletedSector, 8, FloppyController::command_write_sector);
                 }
                 COMMAND_READ_DELETED_SECTOR => {
                     log::trace!("Received Read Deleted Sector command: {:02}", command);
-                    log::error!("Command unimplemented");
+                    self.set_command(Command::ReadDeletedSector, 8, FloppyController::command_read_sector);
                 }
                 COMMAND_FORMAT_TRACK => {
                     log::trace!("Received Format Track command: {:02}", command);
                     self.set_command(Command::FormatTrack, 5, FloppyController::command_format_track);
                 }
+                COMMAND_SCAN_EQUAL => {
+                    log::trace!("Received Scan Equal command: {:02}", command);
+                    self.set_command(Command::ScanEqual, 8, FloppyController::command_scan);
+                }
+                COMMAND_SCAN_LOW_OR_EQUAL => {
+                    log::trace!("Received Scan Low or Equal command: {:02}", command);
+                    self.set_command(Command::ScanLowOrEqual, 8, FloppyController::command_scan);
+                }
+                COMMAND_SCAN_HIGH_OR_EQUAL => {
+                    log::trace!("Received Scan High or Equal command: {:02}", command);
+                    self.set_command(Command::ScanHighOrEqual, 8, FloppyController::command_scan);
+                }
                 COMMAND_FIX_DRIVE_DATA => {
                     log::trace!("Received Fix Drive Data command: {:02}", command);
                     self.set_command(Command::FixDriveData, 2, FloppyController::command_fix_drive_data);
@@ -893,7 +1043,10 @@ impl FloppyController {
                     self.set_command(Command::SeekParkHead, 2, FloppyController::command_seek_head);
                 }
                 _ => {
-                    log::warn!("Received invalid command byte: {:02}", command);
+                    // Invalid commands go directly to the result phase with a single ST0 byte.
+                    log::warn!("Received invalid command byte: {:02X}", data);
+                    self.last_command = Command::Invalid;
+                    self.send_results(&[ST0_INVALID_OPCODE]);
                 }
             }
         }
@@ -903,7 +1056,6 @@ impl FloppyController {
                 self.data_register_in.push_back(data);
                 self.command_byte_n -= 1;
                 if self.command_byte_n == 0 {
-                    // We read last byte expected for this command, so dispatch to the appropriate command handler
                     // We read last byte expected for this command, so dispatch to the appropriate command handler
                     let mut result = Continuation::CommandComplete;
 
@@ -951,79 +1103,74 @@ impl FloppyController {
             Sense Interrupt returns the Invalid Opcode interrupt code if an interrupt was not in progress.
         */
 
-        let mut st0_byte = ST0_INVALID_OPCODE;
+        let mut status = None;
 
         if self.reset_flag {
             // FDC was just reset, answer with an ST0 for the first drive, but prepare to send up
             // to three more ST0 responses
-            st0_byte |= ST0_RESET;
+            status = Some((ST0_RESET, self.pcn[0]));
             self.reset_sense_count = 1;
             self.reset_flag = false;
         }
-        else if let Command::SenseIntStatus = self.last_command {
+        else if self.reset_sense_count > 0 && self.last_command == Command::SenseIntStatus {
             // This Sense Interrupt command was preceded by another.
             // Advance the reset sense count to clear all drives assuming the calling code is doing
             // a four sense-interrupt sequence.
             if self.reset_sense_count < 4 {
-                st0_byte |= ST0_RESET;
-                st0_byte |= self.reset_sense_count & 0x03;
+                let drive = self.reset_sense_count as usize;
+                status = Some((ST0_RESET | (drive as u8 & 0x03), self.pcn[drive]));
                 self.reset_sense_count += 1;
             }
             else {
                 // More than four sense interrupts in a row shouldn't happen
-                st0_byte = ST0_INVALID_OPCODE;
-                self.reset_flag = false;
                 self.reset_sense_count = 0;
             }
         }
         else {
-            // Sense interrupt in response to some other command
-            if self.pending_interrupt {
-                let seek_flag = match self.last_command {
-                    Command::CalibrateDrive => true,
-                    Command::SeekParkHead => true,
-                    _ => false,
-                };
-
-                let code = match self.last_error {
-                    DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => {
-                        InterruptCode::AbnormalTermination
-                    }
-                    _ => InterruptCode::NormalTermination,
-                };
+            self.reset_sense_count = 0;
+        }
 
-                st0_byte = self.make_st0_byte(code, self.drive_select, seek_flag);
+        if status.is_none() {
+            // Report the first drive with a completed seek
+            if let Some(drive) = self.seek_status.iter().position(|s| s.is_some()) {
+                let st0 = self.seek_status[drive].take().unwrap();
+                self.drives[drive].positioning = false;
+                status = Some((st0, self.pcn[drive]));
             }
-            else {
+        }
+
+        match status {
+            Some((st0, pcn)) => {
+                // Send ST0 register and Current Cylinder to FIFO
+                self.send_results(&[st0, pcn]);
+            }
+            None => {
                 // Sense Interrupt without pending interrupt is invalid
-                st0_byte = ST0_INVALID_OPCODE;
+                self.send_results(&[ST0_INVALID_OPCODE]);
             }
         }
 
-        // Send ST0 register to FIFO
-        let cb0 = st0_byte;
-        self.data_register_out.push_back(cb0);
-
-        // Send Current Cylinder to FIFO
-        let cb1 = self.drives[self.drive_select].chs.c();
-        self.data_register_out.push_back(cb1);
-
-        // We have data for CPU to read
-        self.send_data_register();
-        // Deassert interrupt
-        self.end_interrupt = true;
+        // Deassert interrupt, unless other drives have completed seeks to report
+        self.int_line = self.seek_status.iter().any(|s| s.is_some());
 
         self.last_command = Command::SenseIntStatus;
         self.command = Command::NoCommand;
         log::trace!("command_sense_interrupt completed.");
     }
 
-    /// Perform the Fix Drive Data command.
-    /// We don't do anything currently with the provided values which are only useful for real drive timings.
+    /// Perform the Fix Drive Data (Specify) command.
+    /// Sets the step rate and head load timings, and selects DMA or non-DMA mode.
     pub fn command_fix_drive_data(&mut self) -> Continuation {
         let steprate_unload = self.data_register_in.pop_front().unwrap();
         let headload_ndm = self.data_register_in.pop_front().unwrap();
 
+        self.step_rate = steprate_unload >> 4;
+        self.head_unload_time = steprate_unload & 0x0F;
+        self.head_load_time = headload_ndm >> 1;
+
+        // The PCJr has no DMA controller, so it is always in non-DMA mode.
+        self.dma = headload_ndm & 0x01 == 0 && !matches!(self.fdc_type, FdcType::IbmPCJrNec);
+
         log::trace!(
             "command_fix_drive_data completed: {:08b},{:08b}",
             steprate_unload,
@@ -1036,13 +1183,12 @@ impl FloppyController {
     /// Perform the Check Drive Status command.
     /// This command returns the ST3 status register.
     pub fn command_check_drive_status(&mut self) -> Continuation {
-        let drive_select: usize = (self.data_register_in.pop_front().unwrap() & 0x03) as usize;
-
-        let st3 = self.make_st3_byte(drive_select);
-        self.data_register_out.push_back(st3);
+        let drive_head_select = self.data_register_in.pop_front().unwrap();
+        let drive_select: usize = (drive_head_select & 0x03) as usize;
+        let head_select = (drive_head_select >> 2) & 0x01;
 
-        // We have data for the CPU to read
-        self.send_data_register();
+        let st3 = self.make_st3_byte(drive_select, head_select);
+        self.send_results(&[st3]);
 
         log::trace!("command_check_drive_status completed: {}", drive_select);
 
@@ -1051,24 +1197,17 @@ impl FloppyController {
 
     /// Perform the Calibrate Drive command (0x07)
     ///
-    /// Resets the drive specified drive head to cylinder 0.
+    /// Steps the head of the specified drive outward until the drive signals track 0. This command has no result
+    /// phase. The status of the command is checked via Sense Interrupt.
     pub fn command_calibrate_drive(&mut self) -> Continuation {
-        // A real floppy drive might fail to seek completely to cylinder 0 with one calibrate command.
-        // Any point to emulating this behavior?
         let drive_head_select = self.data_register_in.pop_front().unwrap();
         let drive_select = (drive_head_select & 0x03) as usize;
-        let head_select = drive_head_select >> 2 & 0x01;
+        let head_select = (drive_head_select >> 2) & 0x01;
 
-        // Set drive select?
         self.drive_select = drive_select;
+        self.start_seek(drive_select, head_select, None);
 
-        // Set CHS
-        self.drives[drive_select].chs.seek(0, head_select, 1);
-
-        log::trace!("command_calibrate_drive completed: {}", drive_select);
-
-        // Calibrate command sends interrupt when complete
-        self.send_interrupt = true;
+        log::trace!("command_calibrate_drive started: {}", drive_select);
         Continuation::CommandComplete
     }
 
@@ -1076,137 +1215,126 @@ impl FloppyController {
     ///
     /// This command has no result phase. The status of the command is checked via Sense Interrupt.
     pub fn command_seek_head(&mut self) -> Continuation {
-        // A real floppy drive would take some time to seek
-        // Not sure how to go about determining proper timings. For now, seek instantly
-
         let drive_head_select = self.data_register_in.pop_front().unwrap();
         let cylinder = self.data_register_in.pop_front().unwrap();
         let drive_select = (drive_head_select & 0x03) as usize;
         let head_select = (drive_head_select >> 2) & 0x01;
 
-        // Is this seek out of bounds?
-        if !self.is_id_valid(drive_select, cylinder, head_select, 1) {
-            self.last_error = DriveError::BadSeek;
-            self.send_interrupt = true;
-            log::warn!(
-                "command_seek_head: invalid seek: drive:{} c: {} h: {}",
-                drive_head_select,
-                cylinder,
-                head_select
-            );
-            return Continuation::CommandComplete;
-        }
-
-        // Seek to values given in command
-        self.drives[drive_select].chs.seek(cylinder, head_select, 1);
+        self.drive_select = drive_select;
+        self.start_seek(drive_select, head_select, Some(cylinder));
 
         log::trace!(
-            "command_seek_head completed: {} new chs: {}",
-            drive_head_select,
-            self.drives[drive_select].chs
+            "command_seek_head started: drive: {} from: {} to: {}",
+            drive_select,
+            self.pcn[drive_select],
+            cylinder
         );
-
-        self.last_error = DriveError::NoError;
-        self.send_interrupt = true;
         Continuation::CommandComplete
     }
 
-    /// Perform the Read Sector Command
-    pub fn command_read_sector(&mut self) -> Continuation {
-        let drive_head_select = self.data_register_in.pop_front().unwrap();
-        let cylinder = self.data_register_in.pop_front().unwrap();
-        let head = self.data_register_in.pop_front().unwrap();
-        let sector = self.data_register_in.pop_front().unwrap();
-        let sector_size = self.data_register_in.pop_front().unwrap();
-        let track_len = self.data_register_in.pop_front().unwrap();
-        let gap3_len = self.data_register_in.pop_front().unwrap();
-        let data_len = self.data_register_in.pop_front().unwrap();
+    /// Return the time between step pulses. Step rate times are specified for an 8Mhz controller clock and are
+    /// doubled at the 250Kbps data rate.
+    fn step_time_us(&self) -> f64 {
+        2000.0 * (16 - self.step_rate as u32) as f64
+    }
 
-        let drive_select = (drive_head_select & 0x03) as usize;
-        let head_select = (drive_head_select >> 2) & 0x01;
+    /// Begin a seek of the specified drive, or a recalibrate if no target cylinder is given.
+    fn start_seek(&mut self, drive_select: usize, head: u8, target: Option<u8>) {
+        self.drives[drive_select].head = head;
+        self.drives[drive_select].positioning = true;
+        self.seek_status[drive_select] = None;
+        self.seeks[drive_select] = SeekState {
+            active: true,
+            recalibrate: target.is_none(),
+            target: target.unwrap_or(0),
+            steps: 0,
+            timer: self.step_time_us(),
+        };
 
-        if head != head_select {
-            // Head and head_select should always match. Seems redundant
-            log::warn!("command_read_sector: non-matching head specifiers");
+        // Complete immediately if the head is already in position
+        let done = match target {
+            Some(cylinder) => self.pcn[drive_select] == cylinder,
+            None => self.drive_track0(drive_select),
+        };
+        if done {
+            self.complete_seek(drive_select, false);
         }
+    }
 
-        // Set drive_select for status register reads
-        self.drive_select = drive_select;
+    /// Returns the state of the track 0 signal of the specified drive. A drive that isn't installed never signals
+    /// track 0.
+    fn drive_track0(&self, drive_select: usize) -> bool {
+        drive_select < self.drive_ct && self.drives[drive_select].track0()
+    }
 
-        // Is there no disk in the drive?
-        //
-        // Initially I had this command send an interrupt and try to return some error code in the
-        // sense bytes. However that would give inconsistent results in DOS like garbled directory
-        // listings, or produce a "General error" reading drive instead of "Not Ready".
-        // Also, returning error codes would cause the BIOS to issue an error 601.
-        // So, we just let this operation time out if no disk is present, and that seems to work.
-        if !self.drives[drive_select].have_disk {
-            return Continuation::CommandComplete;
-        }
-
-        // Is this read out of bounds?
-        if !self.is_id_valid(drive_select, cylinder, head, sector) {
-            self.last_error = DriveError::BadRead;
-            self.send_interrupt = true;
-            log::warn!(
-                "command_read_sector: invalid chs: drive:{}, c:{} h:{} s:{}",
-                drive_select,
-                cylinder,
-                head,
-                sector
-            );
-            return Continuation::CommandComplete;
+    /// Step the head of the specified drive one cylinder, and play the step sound if the drive is installed.
+    fn step_drive(&mut self, drive_select: usize, inward: bool) {
+        self.drives[drive_select].step(inward);
+        if drive_select < self.drive_ct {
+            self.sound.step();
         }
+    }
 
-        // Seek to values given in command
-        self.drives[drive_select].chs.seek(cylinder, head, sector);
-
-        // Start read operation
-        self.operation = Operation::ReadSector(cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
-
-        if self.dma {
-            // Clear MRQ until operation completion so there is no attempt to read result values
-            self.mrq = false;
-
-            // DMA now in progress
-            self.in_dma = true;
+    /// Issue one step pulse to the specified drive as part of a seek or recalibrate.
+    fn step_seek(&mut self, drive_select: usize) {
+        let seek = self.seeks[drive_select];
+        if seek.recalibrate {
+            if seek.steps >= RECALIBRATE_MAX_STEPS {
+                // Track 0 was never reached
+                self.complete_seek(drive_select, true);
+                return;
+            }
+            self.step_drive(drive_select, false);
+            self.seeks[drive_select].steps += 1;
+            if self.drive_track0(drive_select) {
+                self.complete_seek(drive_select, false);
+            }
         }
         else {
-            // When not in DMA mode, we can leave MRQ high and let the CPU poll for completion
-            self.mrq = true;
-            self.in_dma = false;
+            let pcn = self.pcn[drive_select];
+            if pcn < seek.target {
+                self.pcn[drive_select] = pcn + 1;
+                self.step_drive(drive_select, true);
+            }
+            else if pcn > seek.target {
+                self.pcn[drive_select] = pcn - 1;
+                self.step_drive(drive_select, false);
+            }
+            if self.pcn[drive_select] == seek.target {
+                self.complete_seek(drive_select, false);
+            }
         }
+    }
 
-        // The IBM PC BIOS only seems to ever set a track_len of 8. How do we support 9 sector (365k) floppies?
-        // Answer: DOS seems to know to request sector #9 and the BIOS doesn't complain
-
-        // Maximum size of DMA transfer
-
-        //let max_sectors;
-        //if track_len > 0 {
-        //    max_sectors = track_len - sector + 1;
-        //}
-        //else {
-        //    max_sectors = 1;
-        //}
-        //self.dma_bytes_left = max_sectors as usize * SECTOR_SIZE;
-
-        log::trace!("command_read_sector: dhs: {:02X} drive: {} cyl:{} head:{} sector:{} sector_size:{} track_len:{} gap3_len:{} data_len:{}",
-            drive_head_select, drive_select, cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
-        //log::trace!("command_read_sector: may operate on maximum of {} sectors", max_sectors);
-
-        let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
-        log::trace!("command_read_sector: base address of image read: {:06X}", base_address);
+    fn complete_seek(&mut self, drive_select: usize, equipment_check: bool) {
+        let seek = &mut self.seeks[drive_select];
+        seek.active = false;
+        if seek.recalibrate {
+            self.pcn[drive_select] = 0;
+        }
 
-        // Flag to set up transfer size later
-        self.operation_init = false;
+        let head = self.drives[drive_select].head;
+        let mut st0 = if equipment_check {
+            self.make_st0_byte(InterruptCode::AbnormalTermination, drive_select, head) | ST0_UNIT_CHECK
+        }
+        else {
+            self.make_st0_byte(InterruptCode::NormalTermination, drive_select, head)
+        };
+        st0 |= ST0_SEEK_END;
 
-        // Keep running command until DMA transfer completes
-        Continuation::ContinueAsOperation
+        log::trace!(
+            "Seek complete: drive: {} pcn: {} cylinder: {} st0: {:02X}",
+            drive_select,
+            self.pcn[drive_select],
+            self.drives[drive_select].cylinder,
+            st0
+        );
+        self.seek_status[drive_select] = Some(st0);
+        self.int_line = true;
     }
 
-    /// Perform the Write Sector Command
-    pub fn command_write_sector(&mut self) -> Continuation {
+    /// Read the parameters common to the read, write and scan commands
+    fn read_data_command_params(&mut self) -> OperationParams {
         let drive_head_select = self.data_register_in.pop_front().unwrap();
         let cylinder = self.data_register_in.pop_front().unwrap();
         let head = self.data_register_in.pop_front().unwrap();
@@ -1216,58 +1344,62 @@ impl FloppyController {
         let gap3_len = self.data_register_in.pop_front().unwrap();
         let data_len = self.data_register_in.pop_front().unwrap();
 
-        let drive_select = (drive_head_select & 0x03) as usize;
-        let head_select = (drive_head_select >> 2) & 0x01;
-
-        if head != head_select {
-            log::warn!("command_write_sector: non-matching head specifiers");
-        }
-
-        // Seek to values given in command
-        self.drives[drive_select].chs.seek(cylinder, head, sector);
-
-        // Start write operation
-        self.operation = Operation::WriteSector(cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
-
-        if self.dma {
-            // Clear MRQ until operation completion so there is no attempt to read result values
-            self.mrq = false;
-
-            // DMA now in progress
-            self.in_dma = true;
-        }
-        else {
-            // When not in DMA mode, we can leave MRQ high and let the CPU poll for completion
-            self.mrq = true;
-            self.in_dma = false;
-        }
+        let params = OperationParams {
+            drive: (drive_head_select & 0x03) as usize,
+            head: (drive_head_select >> 2) & 0x01,
+            id: SectorId::new(cylinder, head, sector, sector_size),
+            eot: track_len,
+            gpl: gap3_len,
+            dtl: data_len,
+            mt: self.command_flags & COMMAND_MT_BIT != 0,
+            sk: self.command_flags & COMMAND_SK_BIT != 0,
+            ..Default::default()
+        };
 
         log::trace!(
-            "command_write_sector: cyl:{} head:{} sector:{} sector_size:{} track_len:{} gap3_len:{} data_len:{}",
-            cylinder,
-            head,
-            sector,
-            sector_size,
-            track_len,
-            gap3_len,
-            data_len
+            "{:?}: drive: {} head: {} id: {:?} eot: {} gpl: {} dtl: {} mt: {} sk: {}",
+            self.command,
+            params.drive,
+            params.head,
+            params.id,
+            params.eot,
+            params.gpl,
+            params.dtl,
+            params.mt,
+            params.sk
         );
-        //log::trace!("command_read_sector: may operate on maximum of {} sectors", max_sectors);
+        params
+    }
 
-        let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
-        log::trace!(
-            "command_write_sector: base address of image write: {:06X}",
-            base_address
-        );
+    /// Perform the Read Sector and Read Deleted Sector commands
+    pub fn command_read_sector(&mut self) -> Continuation {
+        let params = self.read_data_command_params();
+        self.start_operation(Operation::ReadSector, params)
+    }
+
+    /// Perform the Write Sector and Write Deleted Sector commands
+    pub fn command_write_sector(&mut self) -> Continuation {
+        let params = self.read_data_command_params();
+        self.start_operation(Operation::WriteSector, params)
+    }
 
-        // Flag to set up transfer size later
-        self.operation_init = false;
+    /// Perform the Read Track command. Sectors are read in the order they appear on the track starting from the
+    /// index pulse, regardless of their IDs.
+    pub fn command_read_track(&mut self) -> Continuation {
+        let mut params = self.read_data_command_params();
+        // Read Track doesn't support multi-track or skip
+        params.mt = false;
+        params.sk = false;
+        self.start_operation(Operation::ReadTrack, params)
+    }
 
-        // Keep running command until DMA transfer completes
-        Continuation::ContinueAsOperation
+    /// Perform the Scan Equal, Scan Low or Equal and Scan High or Equal commands
+    pub fn command_scan(&mut self) -> Continuation {
+        let params = self.read_data_command_params();
+        self.start_operation(Operation::Scan, params)
     }
 
-    /// Perform the Write Sector Command
+    /// Perform the Format Track Command
     pub fn command_format_track(&mut self) -> Continuation {
         let drive_head_select = self.data_register_in.pop_front().unwrap();
         let sector_size = self.data_register_in.pop_front().unwrap();
@@ -1275,25 +1407,15 @@ impl FloppyController {
         let gap3_len = self.data_register_in.pop_front().unwrap();
         let fill_byte = self.data_register_in.pop_front().unwrap();
 
-        let _drive_select = (drive_head_select & 0x03) as usize;
-        let _head_select = (drive_head_select >> 2) & 0x01;
-
-        // Start format operation
-        self.operation_init = false;
-        self.operation = Operation::FormatTrack(sector_size, track_len, gap3_len, fill_byte);
-
-        if self.dma {
-            // Clear MRQ until operation completion so there is no attempt to read result values
-            self.mrq = false;
-
-            // DMA now in progress
-            self.in_dma = true;
-        }
-        else {
-            // When not in DMA mode, we can leave MRQ high and let the CPU poll for completion
-            self.mrq = true;
-            self.in_dma = false;
-        }
+        let params = OperationParams {
+            drive: (drive_head_select & 0x03) as usize,
+            head: (drive_head_select >> 2) & 0x01,
+            id: SectorId::new(0, 0, 0, sector_size),
+            gpl: gap3_len,
+            sc: track_len,
+            fill: fill_byte,
+            ..Default::default()
+        };
 
         log::trace!(
             "command_format_track: sector_size:{} track_len:{} gap3_len:{} fill_byte:{:02X}",
@@ -1303,631 +1425,1394 @@ impl FloppyController {
             fill_byte
         );
 
-        // Keep running command until DMA transfer completes
-        Continuation::ContinueAsOperation
+        self.start_operation(Operation::FormatTrack, params)
     }
 
-    /// Perform the Read Sector ID Command
+    /// Perform the Read Sector ID Command. Returns the first valid ID field found under the head.
     pub fn command_read_sector_id(&mut self) -> Continuation {
         let drive_head_select = self.data_register_in.pop_front().unwrap();
 
-        let drive_select = (drive_head_select & 0x03) as usize;
-        let _head_select = (drive_head_select >> 2) & 0x01;
-
-        self.send_results_phase(
-            InterruptCode::NormalTermination,
-            drive_select,
-            self.drives[drive_select].chs,
-            0x02,
-        );
+        let params = OperationParams {
+            drive: (drive_head_select & 0x03) as usize,
+            head: (drive_head_select >> 2) & 0x01,
+            ..Default::default()
+        };
 
-        self.send_interrupt = true;
-        Continuation::CommandComplete
+        self.start_operation(Operation::ReadSectorId, params)
     }
 
-    /// Return a byte offset given a CHS (Cylinder, Head, Sector) address
-    pub fn get_image_address(&self, drive_select: usize, cylinder: u8, head: u8, sector: u8) -> usize {
-        if sector == 0 {
-            log::warn!("Invalid sector == 0");
-            return 0;
-        }
-        let hpc = self.drives[drive_select].max_heads as usize;
-        let spt = self.drives[drive_select].max_sectors as usize;
-        let lba: usize = (cylinder as usize * hpc + (head as usize)) * spt + (sector as usize - 1);
-        lba * SECTOR_SIZE
-    }
+    /// Begin the execution phase of an operation.
+    fn start_operation(&mut self, operation: Operation, params: OperationParams) -> Continuation {
+        self.operation = operation;
+        self.op = params;
+        self.phase = ControllerPhase::Execution;
+        self.drive_select = params.drive;
+        self.drives[params.drive].head = params.head;
+
+        self.st1 = 0;
+        self.st2 = 0;
+        self.index_ct = 0;
+        self.ids_seen = false;
+        self.wrong_cylinder = 0;
+        self.terminal_count = false;
+        self.terminate_after_sector = false;
+        self.scan_satisfied = false;
+        self.pio_request = false;
+        self.data_register_out.clear();
+        self.format_sectors.clear();
+        self.format_id.clear();
 
-    pub fn get_chs_sector_offset(
-        &self,
-        drive_select: usize,
-        sector_offset: u32,
-        cylinder: u8,
-        head: u8,
-        sector: u8,
-    ) -> (u8, u8, u8) {
-        let mut c = cylinder;
-        let mut h = head;
-        let mut s = sector;
+        let writes_disk = matches!(operation, Operation::WriteSector | Operation::FormatTrack);
+        if writes_disk && self.drives[params.drive].have_disk() && self.drives[params.drive].write_protected {
+            log::warn!("{:?} operation on write protected disk!", operation);
+            self.st1 |= ST1_WRITE_PROTECT;
+            self.finish_operation(InterruptCode::AbnormalTermination, params.id);
+            return Continuation::ContinueAsOperation;
+        }
 
-        for _ in 0..sector_offset {
-            (c, h, s) = self.get_next_sector(drive_select, c, h, s);
+        // Is there no disk in the drive?
+        //
+        // Initially I had this command send an interrupt and try to return some error code in the
+        // sense bytes. However that would give inconsistent results in DOS like garbled directory
+        // listings, or produce a "General error" reading drive instead of "Not Ready".
+        // Also, returning error codes would cause the BIOS to issue an error 601.
+        // With no disk, there are no index pulses so the operation never completes and software times out,
+        // as it would on real hardware.
+        match operation {
+            Operation::ReadTrack | Operation::FormatTrack => {
+                self.op_state = OperationState::WaitIndex;
+                self.schedule_index();
+            }
+            _ => {
+                self.op_state = OperationState::FindSector;
+                self.schedule_next_id();
+            }
         }
 
-        (c, h, s)
+        // Keep running command until the operation completes
+        Continuation::ContinueAsOperation
     }
 
-    pub fn get_next_sector(&self, drive_select: usize, cylinder: u8, head: u8, sector: u8) -> (u8, u8, u8) {
-        if sector < self.drives[drive_select].max_sectors {
-            // Not at last sector, just return next sector
-            (cylinder, head, sector + 1)
-        }
-        else if head < self.drives[drive_select].max_heads - 1 {
-            // At last sector, but not at last head, go to next head, same cylinder, sector 1
-            (cylinder, head + 1, 1)
-        }
-        else if cylinder < self.drives[drive_select].max_cylinders - 1 {
-            // At last sector and last head, go to next cylinder, head 0, sector 1
-            (cylinder + 1, 0, 1)
-        }
-        else {
-            // Return end of drive? What does this do on real hardware
-            (self.drives[drive_select].max_cylinders, 0, 1)
+    /// Returns true if the current operation transfers data from the disk to the CPU.
+    fn operation_reads_disk(&self) -> bool {
+        matches!(self.operation, Operation::ReadSector | Operation::ReadTrack)
+    }
+
+    fn current_track(&self) -> Option<&FloppyTrack> {
+        let drive = &self.drives[self.op.drive];
+        if drive.hidden {
+            return None;
         }
+        drive.image.as_ref()?.track(drive.cylinder, drive.head)
     }
 
-    fn send_results_phase(&mut self, result: InterruptCode, drive_select: usize, chs: DiskChs, sector_size: u8) {
-        /*
-        let (ir_result, wp_flag) = match result {
-            ControllerResult::Success(code) => (code, 0),
-            ControllerResult::GeneralFailure(code) => (code, 0),
-            ControllerResult::WriteProtectFailure => (InterruptCode::AbnormalTermination, 1),
-        };*/
+    fn current_sector(&self, idx: usize) -> Option<&FloppySector> {
+        self.current_track()?.sectors.get(idx)
+    }
 
-        // Create the 3 status bytes. Most of these are error flags of some sort
-        let st0_byte = self.make_st0_byte(result, drive_select, false);
-        let st1_byte = self.make_st1_byte(drive_select);
-        let st2_byte = self.make_st2_byte(drive_select);
+    /// Determine where the sectors of the track under the head pass the head, and the rate data is read at.
+    /// Tracks with more data than fits at the double density rate are assumed to be high density. If a track
+    /// still doesn't fit in a revolution, it is treated as though written at a proportionally higher rate.
+    fn track_timing(&self) -> TrackTiming {
+        let rotation_us = self.drives[self.op.drive].rotation_us;
+        let mut timing = TrackTiming {
+            starts: Vec::new(),
+            byte_us: BYTE_TIME_DD_US,
+            rotation_us,
+        };
 
-        // Push result codes into FIFO
-        self.data_register_out.clear();
-        self.data_register_out.push_back(st0_byte);
-        self.data_register_out.push_back(st1_byte);
-        self.data_register_out.push_back(st2_byte);
-
-        self.data_register_out.push_back(chs.c());
-        self.data_register_out.push_back(chs.h());
-        self.data_register_out.push_back(chs.s());
-        self.data_register_out.push_back(sector_size);
-
-        self.send_data_register();
-
-        // Clear error state
-        self.last_error = DriveError::NoError;
-    }
-
-    fn operation_read_sector_pio(&mut self, cylinder: u8, head: u8, sector: u8, sector_size: u8, track_len: u8) {
-        if !self.operation_init {
-            self.xfer_size_sectors = ((track_len.saturating_sub(sector)) + 1) as u32;
-            self.xfer_completed_sectors = 0;
-            self.xfer_size_bytes = self.xfer_size_sectors as usize * SECTOR_SIZE;
-
-            self.pio_bytes_left = self.xfer_size_bytes;
-            self.pio_byte_count = 0;
-            self.pio_sector_byte_count = 0;
-            self.operation_init = true;
-        }
-
-        if self.pio_bytes_left > 0 {
-            // Calculate how many sectors we've done
-            if (self.pio_bytes_left < self.xfer_size_bytes)
-                && (self.pio_bytes_left % SECTOR_SIZE == 0)
-                && self.data_register_out.is_empty()
-            {
-                // Completed one sector
-                self.xfer_completed_sectors += 1;
-                self.pio_sector_byte_count = 0;
-                log::trace!(
-                    "operation_read_sector_pio: Transferred {}/{} sectors, {}/{} bytes ({} left)",
-                    self.xfer_completed_sectors,
-                    self.xfer_size_sectors,
-                    self.pio_byte_count,
-                    self.xfer_size_bytes,
-                    self.pio_bytes_left
-                );
+        if let Some(track) = self.current_track() {
+            match track.recorded_len {
+                // A track from a bitstream image fills exactly one revolution, however long it was recorded.
+                Some(len) if len > 0 => timing.byte_us = rotation_us / len as f64,
+                _ => {
+                    let track_len = (TRACK_PREAMBLE_BYTES + track.encoded_len()) as f64;
+                    if track_len * BYTE_TIME_DD_US > rotation_us * 1.5 {
+                        timing.byte_us = BYTE_TIME_HD_US;
+                    }
+                    if track_len * timing.byte_us > rotation_us {
+                        timing.byte_us = rotation_us / track_len;
+                    }
+                }
             }
 
-            let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
-            let byte_address = base_address + self.pio_byte_count;
-
-            //log::trace!("Byte address for FDC read: {:04X}", byte_address);
-            if byte_address >= self.drives[self.drive_select].disk_image.len() {
-                log::error!(
-                    "Read past end of disk image: {}/{}!",
-                    byte_address,
-                    self.drives[self.drive_select].disk_image.len()
-                );
-                self.pio_bytes_left = 0;
-                self.pio_byte_count = 0;
-            }
-            else if self.data_register_out.is_empty() {
-                let byte = self.drives[self.drive_select].disk_image[byte_address];
-                log::trace!(
-                    "Read byte: {:02X}, bytes remaining: {} DR: {}",
-                    byte,
-                    self.pio_bytes_left,
-                    self.data_register_out.len()
-                );
-
-                self.data_register_out.push_back(byte);
-                self.pio_byte_count += 1;
-                self.pio_sector_byte_count + 1;
-                self.pio_bytes_left -= 1;
+            let mut pos = TRACK_PREAMBLE_BYTES;
+            for sector in &track.sectors {
+                let start = sector.position.unwrap_or(pos);
+                timing.starts.push(start as f64 * timing.byte_us);
+                pos = start + sector.id.size() + SECTOR_OVERHEAD;
             }
         }
-        else if self.data_register_out.is_empty() {
-            // No more bytes left to transfer. Finalize operation
-            self.pio_bytes_left = 0;
-            self.pio_byte_count = 0;
-
-            let (new_c, new_h, new_s) =
-                self.get_chs_sector_offset(self.drive_select, self.xfer_size_sectors, cylinder, head, sector);
-            //let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, cylinder, head, sector);
-
-            let new_chs = DiskChs::new(new_c, new_h, new_s);
-
-            // Terminate normally by sending results registers
-            self.send_results_phase(
-                InterruptCode::NormalTermination,
-                self.drive_select,
-                new_chs,
-                sector_size,
-            );
-
-            // Seek to new CHS
-            self.drives[self.drive_select].chs.seek_to(&new_chs);
-
-            log::trace!(
-                "operation_read_sector_pio completed ({} bytes transferred): new chs: {} drive: {}",
-                self.xfer_size_bytes - self.pio_bytes_left,
-                &self.drives[self.drive_select].chs,
-                self.drive_select
-            );
-            // Finalize operation
-            self.operation = Operation::NoOperation;
-            //self.send_interrupt = true;
-        }
+        timing
     }
 
-    fn operation_read_sector(
-        &mut self,
-        dma: &mut dma::DMAController,
-        bus: &mut BusInterface,
-        cylinder: u8,
-        head: u8,
-        sector: u8,
-        sector_size: u8,
-        _track_len: u8,
-    ) {
-        if !self.in_dma {
-            log::error!("FDC in invalid state: ReadSector operation without DMA! Aborting.");
-            self.operation = Operation::NoOperation;
+    /// Schedule the next ID field to pass under the head. If the track has no sectors, the next event is the index
+    /// pulse.
+    fn schedule_next_id(&mut self) {
+        let timing = self.track_timing();
+        let angle = self.drives[self.op.drive].angle_us;
+
+        if timing.starts.is_empty() {
+            self.next_sector = None;
+            self.cross_index = true;
+            self.op_timer = timing.rotation_us - angle;
             return;
         }
 
-        // Is read valid?
-
-        if !self.operation_init {
-            let xfer_size = dma.get_dma_transfer_size(FDC_DMA);
-
-            if xfer_size % SECTOR_SIZE != 0 {
-                log::warn!("DMA word count not multiple of sector size");
+        // Allow a little slack so that we don't find the ID field we just read again.
+        match timing.starts.iter().position(|&t| t > angle + 1.0) {
+            Some(idx) => {
+                self.next_sector = Some(idx);
+                self.cross_index = false;
+                self.op_timer = timing.starts[idx] - angle;
             }
+            None => {
+                self.next_sector = Some(0);
+                self.cross_index = true;
+                self.op_timer = timing.rotation_us - angle + timing.starts[0];
+            }
+        }
+    }
 
-            let xfer_sectors = xfer_size / SECTOR_SIZE;
-            log::trace!("DMA programmed for transfer of {} sectors", xfer_sectors);
-
-            let dst_address = dma.get_dma_transfer_address(FDC_DMA);
-            log::trace!("DMA destination address: {:05X}", dst_address);
+    fn schedule_index(&mut self) {
+        let drive = &self.drives[self.op.drive];
+        self.op_timer = drive.rotation_us - drive.angle_us;
+    }
 
-            self.xfer_size_sectors = xfer_sectors as u32;
-            self.xfer_completed_sectors = 0;
-            self.xfer_size_bytes = xfer_sectors * SECTOR_SIZE;
-            self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
-            self.operation_init = true;
+    /// Handle the next event of the current operation, once its timer has expired.
+    fn operation_event(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
+        match self.op_state {
+            OperationState::FindSector => self.event_find_sector(),
+            OperationState::SectorData => self.event_sector_data(dma, bus),
+            OperationState::SectorEnd => self.event_sector_end(),
+            OperationState::WaitIndex => self.event_index(),
+            OperationState::FormatId => self.event_format_id(dma, bus),
+            OperationState::FormatEnd => self.event_format_end(),
         }
+    }
 
-        if self.dma_bytes_left > 0 {
-            // Bytes left to transfer
-
-            // Calculate how many sectors we've done
-            if (self.dma_bytes_left < self.xfer_size_bytes) && (self.dma_bytes_left % SECTOR_SIZE == 0) {
-                // Completed one sector
+    /// An ID field (or the index pulse, on an unformatted track) has arrived under the head.
+    fn event_find_sector(&mut self) {
+        if self.cross_index {
+            self.index_ct += 1;
 
-                self.xfer_completed_sectors += 1;
-                log::trace!(
-                    "operation_read_sector: Transferred {} sectors.",
-                    self.xfer_completed_sectors
-                );
+            // Read Track ends at the next index pulse. Other operations give up on finding a sector after two.
+            let limit = if self.operation == Operation::ReadTrack { 1 } else { 2 };
+            if self.index_ct >= limit {
+                if !self.ids_seen || self.operation == Operation::ReadSectorId {
+                    self.st1 |= ST1_NO_ID;
+                }
+                else if self.operation == Operation::ReadTrack {
+                    self.end_of_cylinder();
+                    return;
+                }
+                else {
+                    self.st1 |= ST1_NODATA;
+                    self.st2 |= self.wrong_cylinder;
+                }
+                self.finish_operation(InterruptCode::AbnormalTermination, self.op.id);
+                return;
             }
+        }
 
-            // Check if DMA is ready
-            if dma.check_dma_ready(FDC_DMA) {
-                let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
-                let byte_address = base_address + self.dma_byte_count;
+        // On an unformatted track the only event is the index pulse
+        let sector = self
+            .next_sector
+            .and_then(|idx| self.current_sector(idx).map(|s| (idx, s.id, s.id_crc_error)));
+        let (idx, id, id_crc_error) = match sector {
+            Some(sector) => sector,
+            None => {
+                self.schedule_next_id();
+                return;
+            }
+        };
+        self.ids_seen = true;
 
-                //log::trace!("Byte address for FDC read: {:04X}", byte_address);
-                if byte_address >= self.drives[self.drive_select].disk_image.len() {
-                    log::error!(
-                        "Read past end of disk image: {}/{}!",
-                        byte_address,
-                        self.drives[self.drive_select].disk_image.len()
-                    );
-                    self.dma_bytes_left = 0;
+        match self.operation {
+            Operation::ReadSectorId => {
+                if id_crc_error {
+                    self.schedule_next_id();
                 }
                 else {
-                    let byte = self.drives[self.drive_select].disk_image[byte_address];
-
-                    dma.do_dma_write_u8(bus, FDC_DMA, byte);
-                    self.dma_byte_count += 1;
-                    self.dma_bytes_left -= 1;
-
-                    // See if we are done
-                    let tc = dma.check_terminal_count(FDC_DMA);
-                    if tc {
-                        log::trace!(
-                            "DMA terminal count triggered end of Sector Read operation, {} bytes read.",
-                            self.dma_byte_count
-                        );
-                        self.dma_bytes_left = 0;
+                    self.finish_operation(InterruptCode::NormalTermination, id);
+                }
+            }
+            Operation::ReadTrack => {
+                if id != self.op.id {
+                    self.st1 |= ST1_NODATA;
+                }
+                self.begin_sector_data(idx);
+            }
+            _ => {
+                if id == self.op.id {
+                    if id_crc_error {
+                        self.st1 |= ST1_CRC_ERROR;
+                        self.finish_operation(InterruptCode::AbnormalTermination, self.op.id);
+                    }
+                    else {
+                        self.begin_sector_data(idx);
                     }
                 }
+                else {
+                    if id.c != self.op.id.c {
+                        self.wrong_cylinder = if id.c == 0xFF {
+                            ST2_WRONG_CYLINDER | ST2_BAD_CYLINDER
+                        }
+                        else {
+                            ST2_WRONG_CYLINDER
+                        };
+                    }
+                    self.schedule_next_id();
+                }
             }
         }
-        else {
-            // No more bytes left to transfer. Finalize operation
+    }
 
-            let tc = dma.check_terminal_count(FDC_DMA);
-            if !tc {
-                log::warn!("FDC sector read complete without DMA terminal count.");
-            }
+    /// A matching ID field was found. Prepare to transfer its data field.
+    fn begin_sector_data(&mut self, idx: usize) {
+        let sector = self.current_sector(idx).unwrap();
+        let (no_data, deleted, data_crc_error) = (sector.no_data, sector.deleted, sector.data_crc_error);
 
-            self.dma_byte_count = 0;
-            self.dma_bytes_left = 0;
+        // With N of 0, DTL sets the number of bytes to transfer from a 128 byte sector
+        let len = if self.op.id.n == 0 {
+            std::cmp::min(self.op.dtl as usize, sector_size(0))
+        }
+        else {
+            self.op.id.size()
+        };
 
-            let (new_c, new_h, new_s) = self.get_chs_sector_offset(
-                self.drive_select,
-                self.xfer_completed_sectors + 1,
-                cylinder,
-                head,
-                sector,
-            );
-            //let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, cylinder, head, sector);
+        // Writes lay down a new data field, so only reads need to find one
+        if no_data && self.operation != Operation::WriteSector {
+            self.st1 |= ST1_NO_ID;
+            self.st2 |= ST2_MISSING_DATA_MARK;
+            if self.operation == Operation::ReadTrack {
+                self.next_track_sector();
+            }
+            else {
+                self.finish_operation(InterruptCode::AbnormalTermination, self.op.id);
+            }
+            return;
+        }
 
-            let new_chs = DiskChs::new(new_c, new_h, new_s);
+        if matches!(self.operation, Operation::ReadSector | Operation::Scan) {
+            let want_deleted = self.command == Command::ReadDeletedSector;
+            if deleted != want_deleted {
+                // Data address mark doesn't match the command. Either skip the sector, or read it and stop.
+                self.st2 |= ST2_CONTROL_MARK;
+                if self.op.sk {
+                    self.next_sector();
+                    return;
+                }
+                self.terminate_after_sector = true;
+            }
+        }
 
-            // Terminate normally by sending results registers
-            self.send_results_phase(
-                InterruptCode::NormalTermination,
-                self.drive_select,
-                new_chs,
-                sector_size,
-            );
+        self.sector_idx = idx;
+        self.sector_len = len;
+        self.sector_pos = 0;
+        self.sector_byte_us = self.track_timing().byte_us;
+        self.sector_crc_error = data_crc_error;
+        self.sector_buf = match self.operation {
+            Operation::WriteSector => Vec::with_capacity(len),
+            _ => self.read_sector_data(idx, len),
+        };
+        if self.operation == Operation::Scan {
+            self.scan_equal = true;
+            self.scan_satisfied = true;
+        }
 
-            // Seek to new CHS
-            self.drives[self.drive_select].chs.seek_to(&new_chs);
+        self.op_state = OperationState::SectorData;
+        self.op_timer = ID_TO_DATA_BYTES as f64 * self.sector_byte_us;
 
-            log::trace!(
-                "operation_read_sector completed: new chs: {}",
-                &self.drives[self.drive_select].chs
-            );
-            // Finalize operation
-            self.operation = Operation::NoOperation;
-            self.send_interrupt = true;
+        // In PIO mode, request the first byte from the CPU now so that it arrives before it is needed
+        if !self.dma && !self.operation_reads_disk() {
+            self.pio_request = true;
         }
     }
 
-    fn operation_write_sector(
-        &mut self,
-        dma: &mut dma::DMAController,
-        bus: &mut BusInterface,
-        chs: DiskChs,
-        sector_size: u8,
-        _track_len: u8,
-    ) {
-        if !self.in_dma {
-            log::error!("Error: WriteSector operation without DMA!");
-            self.operation = Operation::NoOperation;
-            return;
+    /// Read the data field of a sector on the current track. Weak bits read back as random values, which almost
+    /// always results in a CRC error.
+    fn read_sector_data(&mut self, idx: usize, len: usize) -> Vec<u8> {
+        let sector = self.current_sector(idx).unwrap();
+        let mut data = sector.read_data(len);
+        let weak = match &sector.weak {
+            Some(weak) => weak.clone(),
+            None => return data,
+        };
+
+        let mut changed = false;
+        for (byte, mask) in data.iter_mut().zip(weak.iter()) {
+            if *mask != 0 {
+                let noise = self.weak_bit_noise() & *mask;
+                changed |= noise != 0;
+                *byte ^= noise;
+            }
         }
+        if changed {
+            self.sector_crc_error = true;
+        }
+        data
+    }
 
-        // Fail operation if disk is write protected
-        if self.drives[self.drive_select].write_protected {
-            log::warn!("WriteSector operation on write protected disk!");
+    /// Generate random bits for weak bit reads. A fixed seed keeps repeated runs of the emulator identical.
+    fn weak_bit_noise(&mut self) -> u8 {
+        let mut x = self.weak_bit_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.weak_bit_state = x;
+        x as u8
+    }
 
-            // Terminate with WriteProtect error.
-            self.last_error = DriveError::WriteProtect;
-            self.send_results_phase(InterruptCode::AbnormalPolling, self.drive_select, chs, sector_size);
+    /// Returns true if a DMA request from the FDC can be serviced.
+    fn dma_ready(&self, dma: &dma::DMAController) -> bool {
+        let gated = matches!(self.fdc_type, FdcType::IbmNec) && self.dor & DOR_DMA_ENABLED == 0;
+        !gated && dma.check_dma_ready(FDC_DMA)
+    }
 
-            self.send_interrupt = true;
-            self.operation = Operation::NoOperation;
-            return;
+    /// Send a byte to the CPU by DMA or PIO. Returns false on overrun.
+    fn transfer_out(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, byte: u8) -> bool {
+        if self.dma {
+            if !self.dma_ready(dma) {
+                return false;
+            }
+            dma.do_dma_write_u8(bus, FDC_DMA, byte);
+            self.terminal_count = dma.check_terminal_count(FDC_DMA);
         }
+        else {
+            if !self.data_register_out.is_empty() {
+                // CPU didn't read the last byte in time
+                return false;
+            }
+            self.data_register_out.push_back(byte);
+        }
+        true
+    }
 
-        if !self.operation_init {
-            let xfer_size = dma.get_dma_transfer_size(FDC_DMA);
-
-            if xfer_size % SECTOR_SIZE != 0 {
-                log::warn!("DMA word count not multiple of sector size");
+    /// Receive a byte from the CPU by DMA or PIO. Returns None on overrun.
+    fn transfer_in(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) -> Option<u8> {
+        if self.dma {
+            if !self.dma_ready(dma) {
+                return None;
             }
+            let byte = dma.do_dma_read_u8(bus, FDC_DMA);
+            self.terminal_count = dma.check_terminal_count(FDC_DMA);
+            Some(byte)
+        }
+        else {
+            self.data_register_in.pop_front()
+        }
+    }
 
-            let xfer_sectors = xfer_size / SECTOR_SIZE;
-            log::trace!("DMA programmed for transfer of {} sectors", xfer_sectors);
+    fn overrun(&mut self) {
+        log::warn!("FDC overrun during {:?} operation", self.operation);
+        self.st1 |= ST1_OVERRUN;
+        self.finish_operation(InterruptCode::AbnormalTermination, self.op.id);
+    }
 
-            self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
-            self.operation_init = true;
+    /// Transfer the next byte of a sector's data field.
+    fn event_sector_data(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
+        let pos = self.sector_pos;
+        match self.operation {
+            Operation::WriteSector => match self.transfer_in(dma, bus) {
+                Some(byte) => self.sector_buf.push(byte),
+                None => {
+                    self.overrun();
+                    return;
+                }
+            },
+            Operation::Scan => match self.transfer_in(dma, bus) {
+                Some(byte) => self.scan_byte(self.sector_buf[pos], byte),
+                None => {
+                    self.overrun();
+                    return;
+                }
+            },
+            _ => {
+                if !self.transfer_out(dma, bus, self.sector_buf[pos]) {
+                    self.overrun();
+                    return;
+                }
+            }
         }
+        self.sector_pos += 1;
 
-        if self.dma_bytes_left == SECTOR_SIZE {
-            let dst_address = dma.get_dma_transfer_address(FDC_DMA);
-            log::trace!("DMA source address: {:05X}", dst_address)
+        if self.sector_pos >= self.sector_len || self.terminal_count {
+            self.op_state = OperationState::SectorEnd;
+            self.op_timer = DATA_CRC_BYTES as f64 * self.sector_byte_us;
         }
+        else {
+            if !self.dma && !self.operation_reads_disk() {
+                self.pio_request = true;
+            }
+            self.op_timer = self.sector_byte_us;
+        }
+    }
 
-        if self.dma_bytes_left > 0 {
-            // Bytes left to transfer
+    /// Compare a byte from the disk against a byte from the CPU for a scan command. A value of 0xFF from either
+    /// source always matches.
+    fn scan_byte(&mut self, disk: u8, cpu: u8) {
+        if disk == 0xFF || cpu == 0xFF {
+            return;
+        }
+        if disk != cpu {
+            self.scan_equal = false;
+        }
+        let satisfied = match self.command {
+            Command::ScanLowOrEqual => disk <= cpu,
+            Command::ScanHighOrEqual => disk >= cpu,
+            _ => disk == cpu,
+        };
+        if !satisfied {
+            self.scan_satisfied = false;
+        }
+    }
 
-            // Check if DMA is ready
-            if dma.check_dma_ready(FDC_DMA) {
-                let base_address = self.get_image_address(self.drive_select, chs.c(), chs.h(), chs.s());
-                let byte_address = base_address + self.dma_byte_count;
+    /// The end of a sector's data field has passed the head.
+    fn event_sector_end(&mut self) {
+        // In PIO mode the CPU must have read the last byte before the operation can continue.
+        if !self.dma && self.operation_reads_disk() && !self.data_register_out.is_empty() {
+            self.overrun();
+            return;
+        }
 
-                //log::trace!("Byte address for FDC write: {:04X}", byte_address);
-                if byte_address >= self.drives[self.drive_select].disk_image.len() {
-                    log::error!(
-                        "Write past end of disk image: {}/{}!",
-                        byte_address,
-                        self.drives[self.drive_select].disk_image.len()
-                    );
-                    self.dma_bytes_left = 0;
-                    // cleanup ?
+        match self.operation {
+            Operation::WriteSector => {
+                let deleted = self.command == Command::WriteDeletedSector;
+                let mut data = std::mem::take(&mut self.sector_buf);
+                let idx = self.sector_idx;
+                let drive = &mut self.drives[self.op.drive];
+                let (cylinder, head) = (drive.cylinder, drive.head);
+                if let Some(sector) = drive
+                    .image
+                    .as_mut()
+                    .and_then(|image| image.track_mut(cylinder, head))
+                    .and_then(|track| track.sectors.get_mut(idx))
+                {
+                    // A terminal count before the end of the sector fills the rest of the data field with zeros
+                    data.resize(sector.id.size(), 0);
+                    sector.data = data;
+                    sector.deleted = deleted;
+                    sector.data_crc_error = false;
+                    sector.no_data = false;
+                    sector.weak = None;
+                    drive.modified = true;
+                }
+            }
+            Operation::ReadTrack => {
+                if self.sector_crc_error {
+                    self.st1 |= ST1_CRC_ERROR;
+                    self.st2 |= ST2_DATA_CRC_ERROR;
+                }
+                if self.terminal_count {
+                    self.finish_operation(InterruptCode::NormalTermination, self.op.id);
                 }
                 else {
-                    let byte = dma.do_dma_read_u8(bus, FDC_DMA);
-                    self.drives[self.drive_select].disk_image[byte_address] = byte;
-                    self.dma_byte_count += 1;
-                    self.dma_bytes_left -= 1;
-
-                    // See if we are done
-                    let tc = dma.check_terminal_count(FDC_DMA);
-                    if tc {
-                        log::trace!(
-                            "DMA terminal count triggered end of Sector Write operation, {} byte(s) written.",
-                            self.dma_byte_count
-                        );
-                        self.dma_bytes_left = 0;
-                    }
+                    self.next_track_sector();
+                }
+                return;
+            }
+            _ => {
+                if self.sector_crc_error {
+                    self.st1 |= ST1_CRC_ERROR;
+                    self.st2 |= ST2_DATA_CRC_ERROR;
+                    self.finish_operation(InterruptCode::AbnormalTermination, self.op.id);
+                    return;
                 }
             }
         }
+
+        if self.operation == Operation::Scan && self.scan_satisfied {
+            if self.scan_equal {
+                self.st2 |= ST2_SCAN_HIT;
+            }
+            self.finish_operation(InterruptCode::NormalTermination, self.op.id);
+            return;
+        }
+
+        if self.terminal_count || self.terminate_after_sector {
+            let id = self.next_result_id();
+            self.finish_operation(InterruptCode::NormalTermination, id);
+        }
         else {
-            // No more bytes left to transfer. Finalize operation
+            self.next_sector();
+        }
+    }
 
-            let tc = dma.check_terminal_count(FDC_DMA);
-            if !tc {
-                log::warn!("FDC sector write complete without DMA terminal count.");
+    /// Return the ID reported in the result phase after a sector completes without error.
+    fn next_result_id(&self) -> SectorId {
+        let mut id = self.op.id;
+        if id.r != self.op.eot {
+            id.r = id.r.wrapping_add(1);
+        }
+        else if self.op.mt {
+            id.h ^= 0x01;
+            id.r = 1;
+            if self.op.head == 1 {
+                id.c = id.c.wrapping_add(1);
             }
+        }
+        else {
+            id.c = id.c.wrapping_add(1);
+            id.r = 1;
+        }
+        id
+    }
 
-            self.dma_byte_count = 0;
-            self.dma_bytes_left = 0;
+    /// Move on to the next sector of a multi-sector operation.
+    fn next_sector(&mut self) {
+        if self.op.id.r >= self.op.eot {
+            if self.op.mt && self.op.head == 0 {
+                // Continue on the second side of the cylinder
+                self.op.head = 1;
+                self.op.id.h ^= 0x01;
+                self.op.id.r = 1;
+                self.drives[self.op.drive].head = 1;
+            }
+            else {
+                self.end_of_cylinder();
+                return;
+            }
+        }
+        else if self.operation == Operation::Scan {
+            // The STP parameter sets the sector increment for scan commands
+            self.op.id.r = self.op.id.r.saturating_add(std::cmp::max(self.op.dtl, 1));
+            if self.op.id.r > self.op.eot {
+                self.end_of_cylinder();
+                return;
+            }
+        }
+        else {
+            self.op.id.r += 1;
+        }
 
-            let (new_c, new_h, new_s) = self.get_chs_sector_offset(
-                self.drive_select,
-                self.xfer_completed_sectors + 1,
-                chs.c(),
-                chs.h(),
-                chs.s(),
-            );
+        self.index_ct = 0;
+        self.ids_seen = false;
+        self.wrong_cylinder = 0;
+        self.op_state = OperationState::FindSector;
+        self.schedule_next_id();
+    }
 
-            //let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, chs.c(), chs.h(), chs.s());
-            let new_chs = DiskChs::new(new_c, new_h, new_s);
+    /// Move on to the next sector on the track for Read Track.
+    fn next_track_sector(&mut self) {
+        if self.op.id.r >= self.op.eot {
+            self.end_of_cylinder();
+            return;
+        }
+        self.op.id.r += 1;
+        self.op_state = OperationState::FindSector;
+        self.schedule_next_id();
+    }
 
-            // Terminate normally by sending results registers
-            self.send_results_phase(
-                InterruptCode::NormalTermination,
-                self.drive_select,
-                new_chs,
-                sector_size,
-            );
+    /// The last sector was processed without a terminal count.
+    fn end_of_cylinder(&mut self) {
+        let id = self.next_result_id();
+        if self.operation == Operation::Scan {
+            if !self.scan_satisfied {
+                self.st2 |= ST2_SCAN_NOT_SATISFIED;
+            }
+            self.finish_operation(InterruptCode::NormalTermination, id);
+        }
+        else if self.dma {
+            self.st1 |= ST1_END_OF_CYLINDER;
+            self.finish_operation(InterruptCode::AbnormalTermination, id);
+        }
+        else {
+            // The PCJr can't assert terminal count in PIO mode, so reaching the end of the track is the normal way
+            // to end an operation.
+            self.finish_operation(InterruptCode::NormalTermination, id);
+        }
+    }
 
-            // Set new CHS
-            self.drives[self.drive_select].chs.seek_to(&new_chs);
+    /// The index pulse that begins a Read Track or Format Track has arrived.
+    fn event_index(&mut self) {
+        match self.operation {
+            Operation::FormatTrack => {
+                self.op_state = OperationState::FormatId;
+                self.op_timer = self.format_byte_us();
+                if !self.dma {
+                    self.pio_request = true;
+                }
+            }
+            _ => {
+                self.op.id.r = 1;
+                self.index_ct = 0;
+                self.op_state = OperationState::FindSector;
+                self.schedule_next_id();
+            }
+        }
+    }
 
-            // Finalize operation
-            self.operation = Operation::NoOperation;
-            self.send_interrupt = true;
+    /// Return the time to write one byte of the track being formatted.
+    fn format_byte_us(&self) -> f64 {
+        let rotation_us = self.drives[self.op.drive].rotation_us;
+        let track_len = TRACK_PREAMBLE_BYTES + self.op.sc as usize * (self.op.id.size() + SECTOR_OVERHEAD);
+        if track_len as f64 * BYTE_TIME_DD_US > rotation_us * 1.5 {
+            BYTE_TIME_HD_US
+        }
+        else {
+            BYTE_TIME_DD_US
         }
     }
 
-    /// Run the Format Track Operation
-    ///
-    /// DOS will program DMA for the entire track length, but we only read track_len * 4 bytes from DMA
-    /// to read in the format buffers for each sector
-    fn operation_format_track(
-        &mut self,
-        dma: &mut dma::DMAController,
-        bus: &mut BusInterface,
-        sector_size: u8,
-        track_len: u8,
-        _gap3_len: u8,
-        fill_byte: u8,
-    ) {
-        if !self.in_dma {
-            log::error!("Error: Format Track operation without DMA!");
-            self.operation = Operation::NoOperation;
+    /// Receive the next byte of a sector ID for Format Track. Each sector is written once its ID is complete.
+    fn event_format_id(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface) {
+        let byte_us = self.format_byte_us();
+        match self.transfer_in(dma, bus) {
+            Some(byte) => self.format_id.push(byte),
+            None => {
+                self.overrun();
+                return;
+            }
+        }
+
+        if self.format_id.len() < FORMAT_BUFFER_SIZE {
+            if !self.dma {
+                self.pio_request = true;
+            }
+            self.op_timer = byte_us;
             return;
         }
 
-        // Fail operation if disk is write protected
-        if self.drives[self.drive_select].write_protected {
-            log::warn!("FormatTrack operation on write protected disk!");
+        let id = SectorId::new(
+            self.format_id[0],
+            self.format_id[1],
+            self.format_id[2],
+            self.format_id[3],
+        );
+        log::trace!(
+            "Formatting cylinder: {} head: {} sector: {} size: {} with byte: {:02X}",
+            id.c,
+            id.h,
+            id.r,
+            id.n,
+            self.op.fill
+        );
+        self.format_id.clear();
+        self.format_sectors
+            .push(FloppySector::new(id, vec![self.op.fill; self.op.id.size()]));
+        self.op.id = SectorId { n: self.op.id.n, ..id };
 
-            // Terminate with WriteProtect error.
-            self.last_error = DriveError::WriteProtect;
-            self.send_results_phase(
-                InterruptCode::AbnormalPolling,
-                self.drive_select,
-                Default::default(),
-                sector_size,
-            );
+        // The rest of the sector is written before the next ID is needed
+        self.op_timer = (self.op.id.size() + SECTOR_OVERHEAD - FORMAT_BUFFER_SIZE) as f64 * byte_us;
+        if self.format_sectors.len() >= self.op.sc as usize || self.terminal_count {
+            self.op_state = OperationState::FormatEnd;
+        }
+        else if !self.dma {
+            self.pio_request = true;
+        }
+    }
 
-            self.send_interrupt = true;
-            self.operation = Operation::NoOperation;
+    /// The last sector has been written. Gap 4b is written up to the index pulse, which ends the operation.
+    fn event_format_end(&mut self) {
+        if !self.format_sectors.is_empty() {
+            let sectors = std::mem::take(&mut self.format_sectors);
+            let drive = &mut self.drives[self.op.drive];
+            let (cylinder, head) = (drive.cylinder, drive.head);
+            if let Some(image) = drive.image.as_mut() {
+                image.set_track(cylinder, head, FloppyTrack::new(sectors));
+                drive.modified = true;
+            }
+            self.schedule_index();
             return;
         }
 
-        if !self.operation_init {
-            let xfer_size = dma.get_dma_transfer_size(FDC_DMA);
+        // Note the u765a whitepaper says this about the result codes of the Format Track command:
+        // "In this case, the ID information has no meaning"
+        self.finish_operation(InterruptCode::NormalTermination, self.op.id);
+    }
 
-            if xfer_size < (track_len as usize * FORMAT_BUFFER_SIZE) {
-                log::error!(
-                    "Format Track: DMA word count too small for track_len({:02}) format buffers.",
-                    track_len
-                );
-                self.operation = Operation::NoOperation;
-                return;
-            }
+    /// End the execution phase and enter the result phase with the status registers and the specified ID.
+    fn finish_operation(&mut self, result: InterruptCode, id: SectorId) {
+        let st0 = self.make_st0_byte(result, self.op.drive, self.op.head);
+
+        log::trace!(
+            "{:?} operation completed: st0: {:02X} st1: {:02X} st2: {:02X} id: {:?} drive: {}",
+            self.operation,
+            st0,
+            self.st1,
+            self.st2,
+            id,
+            self.op.drive
+        );
 
-            let xfer_sectors = xfer_size / SECTOR_SIZE;
-            log::trace!("Format Track: DMA programmed for transfer of {} sectors", xfer_sectors);
+        self.send_results(&[st0, self.st1, self.st2, id.c, id.h, id.r, id.n]);
+        self.data_register_in.clear();
+        self.pio_request = false;
+        self.sector_buf.clear();
+        self.format_id.clear();
 
-            self.dma_bytes_left = track_len as usize * FORMAT_BUFFER_SIZE;
-            self.operation_init = true;
+        // The PCJr doesn't receive an interrupt at the end of a PIO operation; it polls the status register.
+        if self.dma {
+            self.int_line = true;
         }
 
-        if self.dma_bytes_left > 0 {
-            // Bytes left to transfer
+        // Finalize operation
+        self.operation = Operation::NoOperation;
+        self.last_command = self.command;
+        self.command = Command::NoCommand;
+        self.command_fn = None;
+    }
+
+    /// Abort the current operation without a result phase.
+    fn abort_operation(&mut self) {
+        self.operation = Operation::NoOperation;
+        self.phase = ControllerPhase::Command;
+        self.command = Command::NoCommand;
+        self.command_fn = None;
+        self.data_register_out.clear();
+        self.data_register_in.clear();
+        self.format_sectors.clear();
+        self.pio_request = false;
+    }
+
+    /// Run the Floppy Drive Controller. Process running Operations.
+    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64) {
+        if self.watchdog_triggered {
+            self.watchdog_accumulator += us;
+            if self.watchdog_enabled && self.watchdog_accumulator > WATCHDOG_TIMEOUT {
+                log::warn!("FDC watchdog timeout!");
+                self.watchdog_triggered = false;
+                self.watchdog_accumulator = 0.0;
+                self.abort_operation();
+                self.int_line = true;
+            }
+        }
 
-            // Check if DMA is ready
-            if dma.check_dma_ready(FDC_DMA) {
-                let byte = dma.do_dma_read_u8(bus, FDC_DMA);
-                self.format_buffer.push_back(byte);
-                self.dma_bytes_left = self.dma_bytes_left.saturating_sub(1);
+        // Step the heads of any seeking drives
+        for drive_select in 0..FDC_MAX_DRIVES {
+            if self.seeks[drive_select].active {
+                self.seeks[drive_select].timer -= us;
+                while self.seeks[drive_select].active && self.seeks[drive_select].timer <= 0.0 {
+                    self.seeks[drive_select].timer += self.step_time_us();
+                    self.step_seek(drive_select);
+                }
             }
+        }
 
-            // Have we read in all 4 bytes of a format buffer? Format the sector specified by the buffer.
-            if self.format_buffer.len() == FORMAT_BUFFER_SIZE {
-                let f_cylinder = self.format_buffer.pop_front().unwrap();
-                let f_head = self.format_buffer.pop_front().unwrap();
-                let f_sector = self.format_buffer.pop_front().unwrap();
-                let f_sector_size = self.format_buffer.pop_front().unwrap();
+        // Run the operation as the disk turns under the head. The drive is rotated up to each event so that
+        // events see the disk at the right position.
+        let op_drive = self.op.drive;
+        // Latch read and write activity for drive indicators.
+        if self.operation != Operation::NoOperation && self.phase == ControllerPhase::Execution {
+            let drive = &mut self.drives[op_drive];
+            match self.operation {
+                Operation::WriteSector | Operation::FormatTrack => drive.write_activity = true,
+                _ => drive.read_activity = true,
+            }
+        }
+        let mut remaining = us;
+        while self.operation != Operation::NoOperation
+            && self.phase == ControllerPhase::Execution
+            && self.drives[op_drive].is_spinning()
+        {
+            if self.op_timer > remaining {
+                self.op_timer -= remaining;
+                break;
+            }
+            remaining -= self.op_timer;
+            self.drives[op_drive].rotate(self.op_timer);
+            self.op_timer = 0.0;
+            self.operation_event(dma, bus);
+        }
 
-                log::trace!(
-                    "Formatting cylinder: {} head: {} sector: {} size: {} with byte: {:02X}",
-                    f_cylinder,
-                    f_head,
-                    f_sector,
-                    f_sector_size,
-                    fill_byte
-                );
+        for (i, drive) in self.drives.iter_mut().enumerate() {
+            drive.rotate(if i == op_drive { remaining } else { us });
+        }
 
-                self.format_sector(f_cylinder, f_head, f_sector, fill_byte);
-                self.send_interrupt = true;
+        let motor = self.drives[..self.drive_ct].iter().any(|drive| drive.motor_on);
+        self.sound.run(us, motor);
 
-                // Clear for next 4 bytes
-                self.format_buffer.clear();
+        // The interrupt line is gated by the DMA enable bit of the DOR on the PC and XT.
+        let irq = match self.fdc_type {
+            FdcType::IbmNec => self.int_line && (self.dor & DOR_DMA_ENABLED != 0),
+            FdcType::IbmPCJrNec => self.int_line,
+        };
+        if irq != self.irq_active {
+            if irq {
+                bus.pic_mut().as_mut().unwrap().request_interrupt(FDC_IRQ);
+            }
+            else {
+                bus.pic_mut().as_mut().unwrap().clear_interrupt(FDC_IRQ);
             }
+            self.irq_active = irq;
         }
-        else {
-            // No more bytes left to transfer. Finalize operation
+    }
+}
 
-            //let tc = dma.check_terminal_count(FDC_DMA);
-            //if !tc {
-            //    log::warn!("FDC Format Track complete without DMA terminal count.");
-            //}
+// Variant order of the controller's state enums in a save state.
+const STATE_COMMANDS: [Command; 17] = [
+    Command::NoCommand,
+    Command::ReadTrack,
+    Command::WriteSector,
+    Command::ReadSector,
+    Command::WriteDeletedSector,
+    Command::ReadDeletedSector,
+    Command::FormatTrack,
+    Command::ScanEqual,
+    Command::ScanLowOrEqual,
+    Command::ScanHighOrEqual,
+    Command::FixDriveData,
+    Command::CheckDriveStatus,
+    Command::CalibrateDrive,
+    Command::SenseIntStatus,
+    Command::ReadSectorID,
+    Command::SeekParkHead,
+    Command::Invalid,
+];
+const STATE_PHASES: [ControllerPhase; 3] = [
+    ControllerPhase::Command,
+    ControllerPhase::Execution,
+    ControllerPhase::Result,
+];
+const STATE_OPERATIONS: [Operation; 7] = [
+    Operation::NoOperation,
+    Operation::ReadSector,
+    Operation::WriteSector,
+    Operation::ReadTrack,
+    Operation::ReadSectorId,
+    Operation::FormatTrack,
+    Operation::Scan,
+];
+const STATE_OP_STATES: [OperationState; 6] = [
+    OperationState::FindSector,
+    OperationState::SectorData,
+    OperationState::SectorEnd,
+    OperationState::WaitIndex,
+    OperationState::FormatId,
+    OperationState::FormatEnd,
+];
+
+fn write_variant<T: PartialEq>(w: &mut StateWriter, table: &[T], value: &T) {
+    w.write_u8(table.iter().position(|v| v == value).unwrap_or(0) as u8);
+}
 
-            self.dma_byte_count = 0;
-            self.dma_bytes_left = 0;
+fn read_variant<T: Copy>(r: &mut StateReader, table: &[T], what: &'static str) -> Result<T, SaveStateError> {
+    table
+        .get(r.read_u8()? as usize)
+        .copied()
+        .ok_or(SaveStateError::InvalidValue(what))
+}
 
-            //let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, cylinder, head, sector);
+fn write_sector_id(w: &mut StateWriter, id: &SectorId) {
+    w.write_u8(id.c);
+    w.write_u8(id.h);
+    w.write_u8(id.r);
+    w.write_u8(id.n);
+}
 
-            // Terminate normally by sending results registers
+fn read_sector_id(r: &mut StateReader) -> Result<SectorId, SaveStateError> {
+    Ok(SectorId::new(r.read_u8()?, r.read_u8()?, r.read_u8()?, r.read_u8()?))
+}
 
-            // Note the u765a whitepaper says this about the result codes of the Format Track command:
-            // "In this case, the ID information has no meaning"
-            self.send_results_phase(
-                InterruptCode::NormalTermination,
-                self.drive_select,
-                Default::default(), // Default CHS
-                sector_size,
-            );
+impl FloppyController {
+    /// Return the handler for a command that is still receiving its parameter bytes.
+    fn command_dispatch_fn(command: Command) -> Option<CommandDispatchFn> {
+        match command {
+            Command::ReadTrack => Some(FloppyController::command_read_track),
+            Command::WriteSector | Command::WriteDeletedSector => Some(FloppyController::command_write_sector),
+            Command::ReadSector | Command::ReadDeletedSector => Some(FloppyController::command_read_sector),
+            Command::FormatTrack => Some(FloppyController::command_format_track),
+            Command::ScanEqual | Command::ScanLowOrEqual | Command::ScanHighOrEqual => {
+                Some(FloppyController::command_scan)
+            }
+            Command::FixDriveData => Some(FloppyController::command_fix_drive_data),
+            Command::CheckDriveStatus => Some(FloppyController::command_check_drive_status),
+            Command::CalibrateDrive => Some(FloppyController::command_calibrate_drive),
+            Command::ReadSectorID => Some(FloppyController::command_read_sector_id),
+            Command::SeekParkHead => Some(FloppyController::command_seek_head),
+            _ => None,
+        }
+    }
+}
 
-            // Set new CHS
-            //self.drives[self.drive_select].cylinder = new_c;
-            //self.drives[self.drive_select].head = new_h;
-            //self.drives[self.drive_select].sector = new_s;
+/// Disk images are not part of the save state; the disks inserted when a state is loaded remain in their drives.
+impl SaveState for FloppyController {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_f64(self.watchdog_accumulator);
+        w.write_bool(self.reset_flag);
+        w.write_u8(self.reset_sense_count);
+        w.write_bool(self.in_reset);
+        w.write_bool(self.dma);
+        w.write_u8(self.dor);
+        write_variant(w, &STATE_PHASES, &self.phase);
+        write_variant(w, &STATE_COMMANDS, &self.command);
+        w.write_u8(self.command_flags);
+        write_variant(w, &STATE_COMMANDS, &self.last_command);
+        w.write_bool(self.receiving_command);
+        w.write_u32(self.command_byte_n);
+
+        write_variant(w, &STATE_OPERATIONS, &self.operation);
+        write_variant(w, &STATE_OP_STATES, &self.op_state);
+        w.write_usize(self.op.drive);
+        w.write_u8(self.op.head);
+        write_sector_id(w, &self.op.id);
+        w.write_u8(self.op.eot);
+        w.write_u8(self.op.gpl);
+        w.write_u8(self.op.dtl);
+        w.write_bool(self.op.mt);
+        w.write_bool(self.op.sk);
+        w.write_u8(self.op.sc);
+        w.write_u8(self.op.fill);
+        w.write_f64(self.op_timer);
+        w.write_u8(self.index_ct);
+        w.write_bool(self.ids_seen);
+        w.write_u8(self.wrong_cylinder);
+        w.write_bool(self.next_sector.is_some());
+        w.write_usize(self.next_sector.unwrap_or(0));
+        w.write_bool(self.cross_index);
+        w.write_usize(self.sector_idx);
+        w.write_bytes(&self.sector_buf);
+        w.write_usize(self.sector_len);
+        w.write_usize(self.sector_pos);
+        w.write_f64(self.sector_byte_us);
+        w.write_bool(self.sector_crc_error);
+        w.write_u32(self.weak_bit_state);
+        w.write_bool(self.terminal_count);
+        w.write_bool(self.terminate_after_sector);
+        w.write_bool(self.scan_equal);
+        w.write_bool(self.scan_satisfied);
+        w.write_u32(self.format_sectors.len() as u32);
+        for sector in &self.format_sectors {
+            write_sector_id(w, &sector.id);
+            w.write_bytes(&sector.data);
+        }
+        w.write_bytes(&self.format_id);
+        w.write_bool(self.pio_request);
+        w.write_u8(self.st1);
+        w.write_u8(self.st2);
+
+        w.write_bool(self.int_line);
+        w.write_bool(self.irq_active);
+        w.write_bool(self.watchdog_enabled);
+        w.write_bool(self.watchdog_trigger_bit);
+        w.write_bool(self.watchdog_triggered);
+
+        w.write_u8(self.step_rate);
+        w.write_u8(self.head_unload_time);
+        w.write_u8(self.head_load_time);
+        for i in 0..FDC_MAX_DRIVES {
+            w.write_u8(self.pcn[i]);
+            let seek = &self.seeks[i];
+            w.write_bool(seek.active);
+            w.write_bool(seek.recalibrate);
+            w.write_u8(seek.target);
+            w.write_u8(seek.steps);
+            w.write_f64(seek.timer);
+            w.write_bool(self.seek_status[i].is_some());
+            w.write_u8(self.seek_status[i].unwrap_or(0));
+        }
+
+        w.write_bytes(&self.data_register_out.iter().copied().collect::<Vec<u8>>());
+        w.write_bytes(&self.data_register_in.iter().copied().collect::<Vec<u8>>());
+
+        w.write_usize(self.drive_select);
+        w.write_bool(self.drives_swapped);
+        for drive in &self.drives {
+            drive.save_state(w);
+        }
+    }
 
-            // Finalize operation
-            self.operation = Operation::NoOperation;
-            self.send_interrupt = true;
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.watchdog_accumulator = r.read_f64()?;
+        self.reset_flag = r.read_bool()?;
+        self.reset_sense_count = r.read_u8()?;
+        self.in_reset = r.read_bool()?;
+        self.dma = r.read_bool()?;
+        self.dor = r.read_u8()?;
+        self.phase = read_variant(r, &STATE_PHASES, "FDC phase")?;
+        self.command = read_variant(r, &STATE_COMMANDS, "FDC command")?;
+        self.command_flags = r.read_u8()?;
+        self.last_command = read_variant(r, &STATE_COMMANDS, "FDC command")?;
+        self.receiving_command = r.read_bool()?;
+        self.command_byte_n = r.read_u32()?;
+        self.command_fn = FloppyController::command_dispatch_fn(self.command);
+
+        self.operation = read_variant(r, &STATE_OPERATIONS, "FDC operation")?;
+        self.op_state = read_variant(r, &STATE_OP_STATES, "FDC operation state")?;
+        self.op.drive = r.read_usize()?;
+        if self.op.drive >= FDC_MAX_DRIVES {
+            return Err(SaveStateError::InvalidValue("FDC operation drive"));
+        }
+        self.op.head = r.read_u8()?;
+        self.op.id = read_sector_id(r)?;
+        self.op.eot = r.read_u8()?;
+        self.op.gpl = r.read_u8()?;
+        self.op.dtl = r.read_u8()?;
+        self.op.mt = r.read_bool()?;
+        self.op.sk = r.read_bool()?;
+        self.op.sc = r.read_u8()?;
+        self.op.fill = r.read_u8()?;
+        self.op_timer = r.read_f64()?;
+        self.index_ct = r.read_u8()?;
+        self.ids_seen = r.read_bool()?;
+        self.wrong_cylinder = r.read_u8()?;
+        let have_next = r.read_bool()?;
+        let next_sector = r.read_usize()?;
+        self.next_sector = have_next.then_some(next_sector);
+        self.cross_index = r.read_bool()?;
+        self.sector_idx = r.read_usize()?;
+        self.sector_buf = r.read_bytes()?.to_vec();
+        self.sector_len = r.read_usize()?;
+        self.sector_pos = r.read_usize()?;
+        self.sector_byte_us = r.read_f64()?;
+        self.sector_crc_error = r.read_bool()?;
+        self.weak_bit_state = r.read_u32()?;
+        self.terminal_count = r.read_bool()?;
+        self.terminate_after_sector = r.read_bool()?;
+        self.scan_equal = r.read_bool()?;
+        self.scan_satisfied = r.read_bool()?;
+        self.format_sectors.clear();
+        for _ in 0..r.read_u32()? {
+            let id = read_sector_id(r)?;
+            self.format_sectors
+                .push(FloppySector::new(id, r.read_bytes()?.to_vec()));
+        }
+        self.format_id = r.read_bytes()?.to_vec();
+        self.pio_request = r.read_bool()?;
+        self.st1 = r.read_u8()?;
+        self.st2 = r.read_u8()?;
+
+        self.int_line = r.read_bool()?;
+        self.irq_active = r.read_bool()?;
+        self.watchdog_enabled = r.read_bool()?;
+        self.watchdog_trigger_bit = r.read_bool()?;
+        self.watchdog_triggered = r.read_bool()?;
+
+        self.step_rate = r.read_u8()?;
+        self.head_unload_time = r.read_u8()?;
+        self.head_load_time = r.read_u8()?;
+        for i in 0..FDC_MAX_DRIVES {
+            self.pcn[i] = r.read_u8()?;
+            self.seeks[i] = SeekState {
+                active: r.read_bool()?,
+                recalibrate: r.read_bool()?,
+                target: r.read_u8()?,
+                steps: r.read_u8()?,
+                timer: r.read_f64()?,
+            };
+            let have_status = r.read_bool()?;
+            let status = r.read_u8()?;
+            self.seek_status[i] = have_status.then_some(status);
+        }
+
+        self.data_register_out = r.read_bytes()?.iter().copied().collect();
+        self.data_register_in = r.read_bytes()?.iter().copied().collect();
+
+        self.drive_select = r.read_usize()?;
+        if self.drive_select >= FDC_MAX_DRIVES {
+            return Err(SaveStateError::InvalidValue("FDC drive select"));
+        }
+        self.drives_swapped = r.read_bool()?;
+        for drive in self.drives.iter_mut() {
+            drive.load_state(r)?;
         }
+        Ok(())
     }
+}
 
-    pub fn format_sector(&mut self, _cylinder: u8, _head: u8, _sector: u8, _fill_byte: u8) {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{dma::DMAController, floppy_drive::DRIVE_SPINUP_US, pic::Pic};
 
-    /// Run the Floppy Drive Controller. Process running Operations.
-    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64) {
-        self.us_accumulator += us;
+    const TEST_IMAGE_SIZE: usize = 368_640;
 
-        if self.watchdog_triggered {
-            self.watchdog_accumulator += us;
-            if self.watchdog_enabled && self.watchdog_accumulator > WATCHDOG_TIMEOUT {
-                log::warn!("FDC watchdog timeout!");
-                self.watchdog_triggered = false;
-                self.watchdog_accumulator = 0.0;
-                self.operation = Operation::NoOperation;
-                self.send_interrupt = true;
+    struct TestSystem {
+        fdc: FloppyController,
+        dma: DMAController,
+        bus: BusInterface,
+    }
+
+    impl TestSystem {
+        /// Create a controller with a 360K disk in drive A. The sectors of the disk are filled with their
+        /// logical sector number.
+        fn new() -> Self {
+            let mut bus = BusInterface::default();
+            *bus.pic_mut() = Some(Pic::new());
+            let mut system = TestSystem {
+                fdc: FloppyController::new(FdcType::IbmNec, 2),
+                dma: DMAController::new(),
+                bus,
+            };
+
+            let image = (0..TEST_IMAGE_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect();
+            system.fdc.load_image_from(0, image, false).unwrap();
+            system
+                .fdc
+                .handle_dor_write(DOR_FDC_RESET | DOR_DMA_ENABLED | DOR_MOTOR_FDD_A | DOR_DRIVE_SELECT_0);
+            // Let the motor spin up
+            system.run(DRIVE_SPINUP_US);
+            system
+        }
+
+        fn run(&mut self, us: f64) {
+            let mut elapsed = 0.0;
+            while elapsed < us {
+                self.fdc.run(&mut self.dma, &mut self.bus, 1000.0);
+                elapsed += 1000.0;
             }
         }
 
-        // Send an interrupt if one is queued
-        if self.send_interrupt {
-            bus.pic_mut().as_mut().unwrap().request_interrupt(FDC_IRQ);
-            self.pending_interrupt = true;
-            self.send_interrupt = false;
+        /// Program DMA channel 2 for a transfer of `len` bytes at address 0. `mode` selects the transfer type.
+        fn setup_dma(&mut self, mode: u8, len: u16) {
+            self.dma.handle_clear_flopflop();
+            self.dma.handle_addr_port_write(FDC_DMA, 0);
+            self.dma.handle_addr_port_write(FDC_DMA, 0);
+            self.dma.handle_wc_port_write(FDC_DMA, (len - 1) as u8);
+            self.dma.handle_wc_port_write(FDC_DMA, ((len - 1) >> 8) as u8);
+            self.dma.handle_channel_mode_register_write(mode | FDC_DMA as u8);
         }
 
-        // End an interrupt if one was handled
-        if self.end_interrupt {
-            bus.pic_mut().as_mut().unwrap().clear_interrupt(FDC_IRQ);
-            self.pending_interrupt = false;
-            self.end_interrupt = false;
+        fn command(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                assert_eq!(
+                    self.fdc.handle_status_register_read() & (FDC_STATUS_MRQ | FDC_STATUS_DIO),
+                    FDC_STATUS_MRQ
+                );
+                self.fdc.handle_data_register_write(*byte);
+            }
         }
 
-        // Run operation
-        #[allow(unreachable_patterns)]
-        match self.operation {
-            Operation::NoOperation => {
-                // Do nothing
-            }
-            Operation::ReadSector(cylinder, head, sector, sector_size, track_len, _gap3_len, _data_len) => {
-                match self.dma {
-                    true => self.operation_read_sector(dma, bus, cylinder, head, sector, sector_size, track_len),
-                    false => self.operation_read_sector_pio(cylinder, head, sector, sector_size, track_len),
+        /// Run the controller until it enters the result phase, then read the result bytes.
+        fn results(&mut self) -> Vec<u8> {
+            for _ in 0..2000 {
+                if self.fdc.handle_status_register_read() & FDC_STATUS_DIO != 0 {
+                    break;
                 }
+                self.run(1000.0);
             }
-            Operation::WriteSector(cylinder, head, sector, sector_size, track_len, _gap3_len, _data_len) => self
-                .operation_write_sector(
-                    dma,
-                    bus,
-                    DiskChs::from((cylinder, head, sector)),
-                    sector_size,
-                    track_len,
-                ),
-            Operation::FormatTrack(sector_size, track_len, gap3_len, fill_byte) => {
-                self.operation_format_track(dma, bus, sector_size, track_len, gap3_len, fill_byte)
+
+            let mut results = Vec::new();
+            while self.fdc.handle_status_register_read() & FDC_STATUS_DIO != 0 {
+                results.push(self.fdc.handle_data_register_read());
             }
-            _ => {
-                log::error!("Invalid FDC operation: {:?}", self.operation)
+            results
+        }
+
+        /// Run the controller until it raises an interrupt.
+        fn wait_interrupt(&mut self) {
+            for _ in 0..2000 {
+                self.run(1000.0);
+                if self.fdc.irq_active {
+                    return;
+                }
+            }
+            panic!("no interrupt from FDC");
+        }
+    }
+
+    #[test]
+    fn test_read_data_multitrack() {
+        let mut system = TestSystem::new();
+
+        // Read the last sector of head 0 and continue onto head 1, stopping at terminal count.
+        system.setup_dma(0x44, 1024);
+        let read_mt = COMMAND_MT_BIT | COMMAND_MF_BIT | COMMAND_READ_SECTOR;
+        system.command(&[read_mt, 0x00, 0, 0, 9, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(system.fdc.phase, ControllerPhase::Execution);
+        assert_eq!(system.results(), vec![ST0_HEAD_ACTIVE, 0, 0, 0, 1, 2, 2]);
+        assert_eq!(system.bus.peek_u8(0x0000).unwrap(), 8);
+        assert_eq!(system.bus.peek_u8(0x03FF).unwrap(), 9);
+        // Reading the results clears the interrupt
+        system.run(1000.0);
+        assert!(!system.fdc.irq_active);
+
+        // Without MT, reading past EOT without a terminal count ends the operation at the end of the cylinder.
+        system.setup_dma(0x44, 4096);
+        system.command(&[COMMAND_MF_BIT | COMMAND_READ_SECTOR, 0x00, 0, 0, 8, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(
+            system.results(),
+            vec![ST0_ABNORMAL_TERMINATION, ST1_END_OF_CYLINDER, 0, 1, 0, 1, 2]
+        );
+        assert_eq!(system.bus.peek_u8(0x0000).unwrap(), 7);
+        assert_eq!(system.bus.peek_u8(0x0200).unwrap(), 8);
+        assert_eq!(system.bus.peek_u8(0x0400).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_read_data_missing_sector() {
+        let mut system = TestSystem::new();
+
+        // Sector 10 doesn't exist on a 9 sector track. The controller gives up after two index pulses.
+        system.setup_dma(0x44, 512);
+        system.command(&[COMMAND_MF_BIT | COMMAND_READ_SECTOR, 0x00, 0, 0, 10, 2, 10, 0x2A, 0xFF]);
+        assert_eq!(
+            system.results(),
+            vec![ST0_ABNORMAL_TERMINATION, ST1_NODATA, 0, 0, 0, 10, 2]
+        );
+    }
+
+    #[test]
+    fn test_write_data() {
+        let mut system = TestSystem::new();
+        for i in 0..SECTOR_SIZE {
+            system.bus.write_u8(i, 0xA5, 0).unwrap();
+        }
+
+        system.setup_dma(0x48, 512);
+        system.command(&[COMMAND_MF_BIT | COMMAND_WRITE_SECTOR, 0x04, 0, 1, 3, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(system.results(), vec![ST0_HEAD_ACTIVE, 0, 0, 0, 1, 4, 2]);
+        assert!(system.fdc.image_modified(0));
+
+        // Cylinder 0, head 1, sector 3 is logical sector 11
+        let data = system.fdc.get_image_data(0).unwrap();
+        assert!(data[11 * SECTOR_SIZE..12 * SECTOR_SIZE].iter().all(|&b| b == 0xA5));
+        assert_eq!(data[12 * SECTOR_SIZE], 12);
+
+        // Writes to a write protected disk fail without transferring any data
+        system.fdc.write_protect(0, true);
+        system.setup_dma(0x48, 512);
+        system.command(&[COMMAND_MF_BIT | COMMAND_WRITE_SECTOR, 0x00, 0, 0, 1, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(
+            system.results(),
+            vec![ST0_ABNORMAL_TERMINATION, ST1_WRITE_PROTECT, 0, 0, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_read_id() {
+        let mut system = TestSystem::new();
+
+        system.command(&[COMMAND_MF_BIT | COMMAND_READ_SECTOR_ID, 0x04]);
+        let results = system.results();
+        assert_eq!(results.len(), 7);
+        assert_eq!(results[..3], [ST0_HEAD_ACTIVE, 0, 0]);
+        assert_eq!(results[3], 0);
+        assert_eq!(results[4], 1);
+        assert!((1..=9).contains(&results[5]));
+        assert_eq!(results[6], 2);
+    }
+
+    #[test]
+    fn test_format_track() {
+        let mut system = TestSystem::new();
+
+        // Format cylinder 0, head 0 with 8 sectors numbered in reverse
+        for r in 0..8u8 {
+            let id = [0, 0, 8 - r, 2];
+            for (i, byte) in id.iter().enumerate() {
+                system.bus.write_u8(r as usize * 4 + i, *byte, 0).unwrap();
             }
         }
+        system.setup_dma(0x48, 32);
+        system.command(&[COMMAND_MF_BIT | COMMAND_FORMAT_TRACK, 0x00, 2, 8, 0x50, 0xF6]);
+        let results = system.results();
+        assert_eq!(results.len(), 7);
+        assert_eq!(results[..3], [0, 0, 0]);
+
+        let track = system.fdc.get_image(0).unwrap().track(0, 0).unwrap();
+        let ids: Vec<u8> = track.sectors.iter().map(|sector| sector.id.r).collect();
+        assert_eq!(ids, vec![8, 7, 6, 5, 4, 3, 2, 1]);
+        assert!(track
+            .sectors
+            .iter()
+            .all(|sector| sector.data.iter().all(|&b| b == 0xF6)));
+        assert!(system.fdc.image_modified(0));
+
+        // Sector 9 is gone from the reformatted track
+        system.setup_dma(0x44, 512);
+        system.command(&[COMMAND_MF_BIT | COMMAND_READ_SECTOR, 0x00, 0, 0, 9, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(system.results()[1], ST1_NODATA);
+    }
+
+    #[test]
+    fn test_seek_recalibrate_sense_interrupt() {
+        let mut system = TestSystem::new();
+
+        // Coming out of reset reports a status change for all four drives
+        system.fdc.handle_dor_write(0);
+        system
+            .fdc
+            .handle_dor_write(DOR_FDC_RESET | DOR_DMA_ENABLED | DOR_MOTOR_FDD_A);
+        system.wait_interrupt();
+        for drive in 0..4 {
+            system.command(&[COMMAND_SENSE_INT_STATUS]);
+            assert_eq!(
+                system.fdc.handle_status_register_read() & FDC_STATUS_DIO,
+                FDC_STATUS_DIO
+            );
+            assert_eq!(system.results(), vec![ST0_RESET | drive, 0]);
+        }
+
+        // Specify a 6ms step rate, as the BIOS does
+        system.command(&[COMMAND_FIX_DRIVE_DATA, 0xDF, 0x02]);
+        assert_eq!(system.fdc.step_time_us(), 6000.0);
+
+        // Seek has no result phase. Completion is reported by Sense Interrupt Status.
+        system.command(&[COMMAND_SEEK_HEAD, 0x00, 5]);
+        assert_eq!(
+            system.fdc.handle_status_register_read() & FDC_STATUS_FDD_A_BUSY,
+            FDC_STATUS_FDD_A_BUSY
+        );
+        system.wait_interrupt();
+        system.command(&[COMMAND_SENSE_INT_STATUS]);
+        assert_eq!(system.results(), vec![ST0_SEEK_END, 5]);
+        assert_eq!(system.fdc.handle_status_register_read() & FDC_STATUS_FDD_A_BUSY, 0);
+        system.run(1000.0);
+        assert!(!system.fdc.irq_active);
+
+        system.command(&[COMMAND_CHECK_DRIVE_STATUS, 0x00]);
+        assert_eq!(system.results()[0] & ST3_TRACK0, 0);
+
+        system.command(&[COMMAND_CALIBRATE_DRIVE, 0x00]);
+        system.wait_interrupt();
+        system.command(&[COMMAND_SENSE_INT_STATUS]);
+        assert_eq!(system.results(), vec![ST0_SEEK_END, 0]);
+
+        system.command(&[COMMAND_CHECK_DRIVE_STATUS, 0x00]);
+        assert_eq!(system.results()[0] & ST3_TRACK0, ST3_TRACK0);
+
+        // Recalibrating a drive that isn't installed never finds track 0
+        system.command(&[COMMAND_CALIBRATE_DRIVE, 0x02]);
+        system.wait_interrupt();
+        system.command(&[COMMAND_SENSE_INT_STATUS]);
+        let results = system.results();
+        assert_eq!(
+            results[0] & (ST0_UNIT_CHECK | ST0_SEEK_END | 0x03),
+            ST0_UNIT_CHECK | ST0_SEEK_END | 0x02
+        );
+
+        // With no interrupt pending, Sense Interrupt Status is an invalid command
+        system.command(&[COMMAND_SENSE_INT_STATUS]);
+        assert_eq!(system.results(), vec![ST0_INVALID_OPCODE]);
+
+        // Read Data after the seek checks the cylinder of the ID fields it finds
+        system.command(&[COMMAND_SEEK_HEAD, 0x00, 2]);
+        system.wait_interrupt();
+        system.command(&[COMMAND_SENSE_INT_STATUS]);
+        assert_eq!(system.results(), vec![ST0_SEEK_END, 2]);
+        system.setup_dma(0x44, 512);
+        system.command(&[COMMAND_MF_BIT | COMMAND_READ_SECTOR, 0x00, 2, 0, 1, 2, 9, 0x2A, 0xFF]);
+        assert_eq!(system.results(), vec![0, 0, 0, 2, 0, 2, 2]);
+        assert_eq!(system.bus.peek_u8(0x0000).unwrap(), 36);
+    }
+
+    #[test]
+    fn test_invalid_command() {
+        let mut system = TestSystem::new();
+
+        system.command(&[0x1F]);
+        assert_eq!(
+            system.fdc.handle_status_register_read() & (FDC_STATUS_MRQ | FDC_STATUS_DIO),
+            FDC_STATUS_MRQ | FDC_STATUS_DIO
+        );
+        assert_eq!(system.results(), vec![ST0_INVALID_OPCODE]);
+        assert_eq!(system.fdc.phase, ControllerPhase::Command);
+
+        // The controller accepts a new command afterwards
+        system.command(&[COMMAND_CHECK_DRIVE_STATUS, 0x01]);
+        assert_eq!(system.results()[0] & 0x03, 0x01);
+    }
+
+    #[test]
+    fn test_invalid_drive() {
+        let mut system = TestSystem::new();
+
+        system.fdc.unload_image(FDC_MAX_DRIVES);
+        assert!(system.fdc.get_image_data(FDC_MAX_DRIVES).is_err());
+        assert!(system.fdc.get_image_data(0).is_ok());
+        system.fdc.unload_image(0);
+        assert!(system.fdc.get_image_data(0).is_err());
     }
 }
@@ -30,45 +30,61 @@
 */
 
 use crate::{
-    device_types::{chs::DiskChs, fdc::DISK_FORMATS},
-    devices::fdc::SECTOR_SIZE,
+    device_types::drive_status::DriveStatus,
+    floppy_image::FloppyImage,
+    machine_types::FloppyDriveType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
-use anyhow::{anyhow, Error};
+
+pub const DRIVE_SPINUP_US: f64 = 500_000.0;
+pub const ROTATION_300RPM_US: f64 = 200_000.0;
+pub const ROTATION_360RPM_US: f64 = 166_666.667;
+
+// The head can be stepped a few cylinders past the last cylinder formatted on standard media before reaching the
+// mechanical stop.
+pub const DRIVE_OVERTRAVEL: u8 = 3;
 
 pub struct FloppyDiskDrive {
     pub(crate) error_signal: bool,
 
-    pub(crate) chs: DiskChs,
-    media_geom: DiskChs,
-    drive_geom: DiskChs,
-
-    pub(crate) max_cylinders: u8,
-    pub(crate) max_heads: u8,
-    pub(crate) max_sectors: u8,
-    pub(crate) ready: bool,
+    pub(crate) drive_type: FloppyDriveType,
+    pub(crate) max_cylinder: u8, // The last cylinder the head can physically reach
+    pub(crate) cylinder: u8,     // Physical cylinder the head is positioned over
+    pub(crate) head: u8,         // Currently selected head
+    pub(crate) rotation_us: f64, // Time of one revolution
+    pub(crate) angle_us: f64,    // Rotational position of the disk, as time elapsed since the index pulse
+    pub(crate) spinup_us: f64,   // Time remaining until the motor reaches operating speed
     pub(crate) motor_on: bool,
     pub(crate) positioning: bool,
-    pub(crate) have_disk: bool,
     pub(crate) write_protected: bool,
-    pub(crate) disk_image: Vec<u8>,
+    pub(crate) modified: bool, // The disk has been written since it was loaded or last saved
+    pub(crate) image: Option<FloppyImage>,
+    pub(crate) hidden: bool, // The disk is hidden from the guest while the machine boots from another drive
+
+    pub(crate) read_activity:  bool, // The disk has been read since the drive status was last taken
+    pub(crate) write_activity: bool, // The disk has been written since the drive status was last taken
 }
 
 impl Default for FloppyDiskDrive {
     fn default() -> Self {
         Self {
             error_signal: false,
-            chs: Default::default(),
-            media_geom: Default::default(),
-            drive_geom: Default::default(),
-            max_cylinders: 0,
-            max_heads: 0,
-            max_sectors: 0,
-            ready: false,
+            drive_type: FloppyDriveType::Floppy360K,
+            max_cylinder: 39 + DRIVE_OVERTRAVEL,
+            cylinder: 0,
+            head: 0,
+            rotation_us: ROTATION_300RPM_US,
+            angle_us: 0.0,
+            spinup_us: DRIVE_SPINUP_US,
             motor_on: false,
             positioning: false,
-            have_disk: false,
             write_protected: true,
-            disk_image: Vec::new(),
+            modified: false,
+            image: None,
+            hidden: false,
+
+            read_activity:  false,
+            write_activity: false,
         }
     }
 }
@@ -80,64 +96,119 @@ impl FloppyDiskDrive {
     /// Reset the drive to default state. Like other device patterns we use default after preserving persistent state.
     /// Called when FDC itself is reset.
     pub fn reset(&mut self) {
-        // Preserve the disk image before defaulting the drive
-        let image = std::mem::replace(&mut self.disk_image, Vec::new());
+        // Preserve the disk image before defaulting the drive. The head does not move on reset.
+        let image = self.image.take();
 
         *self = Self {
-            ready: self.have_disk,
-            have_disk: self.have_disk,
+            drive_type: self.drive_type,
             write_protected: self.write_protected,
-            max_cylinders: self.max_cylinders,
-            max_heads: self.max_heads,
-            max_sectors: self.max_sectors,
-            motor_on: false,
-            positioning: false,
-            disk_image: image,
+            modified: self.modified,
+            cylinder: self.cylinder,
+            image,
+            hidden: self.hidden,
             ..Default::default()
         };
+        self.set_drive_type(self.drive_type);
+    }
+
+    /// Set the type of the drive, which determines how many cylinders it can seek to and how fast it spins.
+    pub fn set_drive_type(&mut self, drive_type: FloppyDriveType) {
+        let cylinders = match drive_type {
+            FloppyDriveType::Floppy360K => 40,
+            _ => 80,
+        };
+        self.drive_type = drive_type;
+        self.max_cylinder = cylinders - 1 + DRIVE_OVERTRAVEL;
+        self.rotation_us = match drive_type {
+            FloppyDriveType::Floppy12M => ROTATION_360RPM_US,
+            _ => ROTATION_300RPM_US,
+        };
+    }
+
+    pub fn have_disk(&self) -> bool {
+        self.image.is_some() && !self.hidden
+    }
+
+    /// The drive is ready when a disk is inserted.
+    pub fn ready(&self) -> bool {
+        self.have_disk()
     }
 
-    /// Load a disk into the specified drive
-    pub fn load_image_from(&mut self, src_vec: Vec<u8>) -> Result<(), Error> {
-        let image_len: usize = src_vec.len();
+    /// Returns true if the disk is turning at operating speed, so that index pulses and data can be read.
+    pub fn is_spinning(&self) -> bool {
+        self.motor_on && self.spinup_us <= 0.0 && self.have_disk()
+    }
+
+    /// Return the drive status for activity indicators, clearing the read and write activity flags.
+    pub fn take_status(&mut self) -> DriveStatus {
+        DriveStatus {
+            motor: self.motor_on,
+            read:  std::mem::take(&mut self.read_activity),
+            write: std::mem::take(&mut self.write_activity),
+        }
+    }
 
-        // Disk images must contain whole sectors
-        if image_len % SECTOR_SIZE > 0 {
-            return Err(anyhow!("Invalid image length"));
+    pub fn track0(&self) -> bool {
+        self.cylinder == 0
+    }
+
+    pub fn set_motor(&mut self, on: bool) {
+        if on && !self.motor_on {
+            self.spinup_us = DRIVE_SPINUP_US;
         }
+        self.motor_on = on;
+    }
 
-        // Look up disk parameters based on image size
-        if let Some(fmt) = DISK_FORMATS.get(&image_len) {
-            self.max_cylinders = fmt.chs.c();
-            self.max_heads = fmt.chs.h();
-            self.max_sectors = fmt.chs.s();
+    /// Move the head one cylinder in or out, stopping at the mechanical limits of the drive.
+    pub fn step(&mut self, inward: bool) {
+        if inward {
+            self.cylinder = std::cmp::min(self.cylinder + 1, self.max_cylinder);
         }
         else {
-            // No image format found.
-            if image_len < 163_840 {
-                // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
-                // This is useful for loading things like boot sector images without having to copy them to
-                // a full disk image
-                self.max_cylinders = 40;
-                self.max_heads = 1;
-                self.max_sectors = 8;
-            }
-            else {
-                return Err(anyhow!("Invalid image length"));
-            }
+            self.cylinder = self.cylinder.saturating_sub(1);
         }
+    }
 
-        self.have_disk = true;
-        self.disk_image = src_vec;
+    /// Advance the drive motor by the specified number of microseconds.
+    pub fn rotate(&mut self, us: f64) {
+        if !self.motor_on {
+            return;
+        }
+        if self.spinup_us > 0.0 {
+            self.spinup_us -= us;
+            return;
+        }
+        self.angle_us = (self.angle_us + us) % self.rotation_us;
+    }
 
-        log::debug!(
-            "Loaded floppy image, size: {} c: {} h: {} s: {}",
-            self.disk_image.len(),
-            self.max_cylinders,
-            self.max_heads,
-            self.max_sectors
-        );
+    pub fn load_image(&mut self, image: FloppyImage) {
+        log::debug!("Loaded floppy image, c: {} h: {}", image.cylinders(), image.heads());
+        self.image = Some(image);
+        self.modified = false;
+    }
+}
+
+/// Only the mechanical state of the drive is saved. The disk, its write protection and the drive type stay as
+/// they are.
+impl SaveState for FloppyDiskDrive {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.error_signal);
+        w.write_u8(self.cylinder);
+        w.write_u8(self.head);
+        w.write_f64(self.angle_us);
+        w.write_f64(self.spinup_us);
+        w.write_bool(self.motor_on);
+        w.write_bool(self.positioning);
+    }
 
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.error_signal = r.read_bool()?;
+        self.cylinder = r.read_u8()?.min(self.max_cylinder);
+        self.head = r.read_u8()?;
+        self.angle_us = r.read_f64()? % self.rotation_us;
+        self.spinup_us = r.read_f64()?;
+        self.motor_on = r.read_bool()?;
+        self.positioning = r.read_bool()?;
         Ok(())
     }
 }
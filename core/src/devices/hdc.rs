@@ -82,11 +82,22 @@ const ERR_ILLEGAL_ACCESS: u8 = 0b10_0001;
 
 const RESET_DELAY_US: f64 = 200_000.0; // 200ms
 
+// Drive types selectable for each drive with the adapter's DIP switches, in switch order, as cylinders, heads and
+// write precompensation cylinder. All types have 17 sectors per track.
+const DRIVE_TYPES: [(u16, u8, u16, &str); 4] = [
+    (306, 4, 306, "10MB, Type 0"),
+    (612, 4, 0, "20MB, Type 1"),
+    (615, 4, 300, "20MB, Type 2"),
+    (306, 8, 128, "20MB, Type 3"),
+];
+const DRIVE_TYPE_SECTORS: u8 = 17;
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug)]
 pub enum OperationError {
     NoError,
     NoReadySignal,
+    WriteFault,
     InvalidCommand,
     IllegalAccess,
 }
@@ -244,6 +255,20 @@ impl HardDisk {
             (self.max_cylinders, 0, 0)
         }
     }
+
+    /// Format the specified track, filling each of its sectors with zeros.
+    pub fn format_track(&mut self, cylinder: u16, head: u8) -> Result<(), anyhow::Error> {
+        let buf = vec![0; SECTOR_SIZE];
+        match &mut self.vhd {
+            Some(vhd) => {
+                for sector in 0..self.max_sectors {
+                    vhd.write_sector(&buf, cylinder, head, sector)?;
+                }
+                Ok(())
+            }
+            None => Err(anyhow::anyhow!("No VHD mounted")),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -318,13 +343,16 @@ impl Default for HardDiskController {
             drives: [HardDisk::new(), HardDisk::new()],
             drive_ct: 1,
             drive_select: 0,
-            supported_formats: vec![HardDiskFormat {
-                max_cylinders: 615,
-                max_heads: 4,
-                max_sectors: 17,
-                wpc: Some(300),
-                desc: "20MB, Type 2".to_string(),
-            }],
+            supported_formats: DRIVE_TYPES
+                .iter()
+                .map(|(c, h, wpc, desc)| HardDiskFormat {
+                    max_cylinders: *c,
+                    max_heads: *h,
+                    max_sectors: DRIVE_TYPE_SECTORS,
+                    wpc: Some(*wpc),
+                    desc: desc.to_string(),
+                })
+                .collect(),
             drive_type_dip: 0,
             state: State::Reset,
             last_error: OperationError::NoError,
@@ -395,20 +423,18 @@ impl HardDiskController {
             return Err(ControllerError::InvalidDevice);
         }
 
-        // Check that the VHD geometry is in the list of supported formats
-        // (Currently there is only one supported format but that might change)
-        let mut supported = false;
-        for format in &self.supported_formats {
-            if vhd.max_cylinders as u16 == format.max_cylinders
+        // Check that the VHD geometry is one of the drive types the controller supports
+        let drive_type = self.supported_formats.iter().position(|format| {
+            vhd.max_cylinders as u16 == format.max_cylinders
                 && vhd.max_heads as u8 == format.max_heads
                 && vhd.max_sectors as u8 == format.max_sectors
-            {
-                supported = true;
-                break;
-            }
-        }
+        });
+
+        if let Some(drive_type) = drive_type {
+            // Set the DIP switches for the drive to its type. Drive 0 uses switches 3-4, drive 1 switches 1-2.
+            let shift = if device_id == 0 { 2 } else { 0 };
+            self.drive_type_dip = (self.drive_type_dip & !(0x03 << shift)) | ((drive_type as u8) << shift);
 
-        if supported {
             self.drives[device_id].max_cylinders = vhd.max_cylinders as u16;
             self.drives[device_id].max_heads = vhd.max_heads as u8;
             self.drives[device_id].max_sectors = vhd.max_sectors as u8;
@@ -421,6 +447,11 @@ impl HardDiskController {
         Ok(())
     }
 
+    /// Return the VHD mounted in the specified drive, if any.
+    pub fn vhd_mut(&mut self, device_id: usize) -> Option<&mut VirtualHardDisk> {
+        self.drives.get_mut(device_id).and_then(|drive| drive.vhd.as_mut())
+    }
+
     pub fn set_command(&mut self, command: Command, n_bytes: u32, command_fn: CommandDispatchFn) {
         self.state = State::ReceivingCommand;
         self.receiving_dcb = true;
@@ -493,7 +524,7 @@ impl HardDiskController {
                 if let OperationError::NoError = self.last_error {
                     error_flag = 0;
                 }
-                byte = (self.drive_select as u8 & 0x01 << 5) | (error_flag << 1);
+                byte = ((self.drive_select as u8 & 0x01) << 5) | (error_flag << 1);
 
                 log::trace!("Status Byte read: {:02X}", 0);
                 self.clear_interrupt = true;
@@ -584,6 +615,7 @@ impl HardDiskController {
                     0b000_00100 => {
                         // Format drive
                         log::trace!("Received Format Drive Command");
+                        self.set_command(Command::FormatDrive, DBC_LEN, HardDiskController::command_format_drive);
                     }
                     0b000_00101 => {
                         // Read Verify
@@ -593,10 +625,16 @@ impl HardDiskController {
                     0b000_00110 => {
                         // Format Track
                         log::trace!("Received Format Track Command");
+                        self.set_command(Command::FormatTrack, DBC_LEN, HardDiskController::command_format_track);
                     }
                     0b000_00111 => {
                         // Format Bad Track
                         log::trace!("Received Format Bad Track Command");
+                        self.set_command(
+                            Command::FormatBadTrack,
+                            DBC_LEN,
+                            HardDiskController::command_format_track,
+                        );
                     }
                     0b000_01000 => {
                         // Read
@@ -625,6 +663,11 @@ impl HardDiskController {
                     0b000_01101 => {
                         // Read ECC Burst Length
                         log::trace!("Received ECC Burst Length Command");
+                        self.set_command(
+                            Command::ReadEccBurstLength,
+                            DBC_LEN,
+                            HardDiskController::command_read_ecc_burst_length,
+                        );
                     }
                     0b000_01110 => {
                         // Read Data From Sector Buffer
@@ -708,8 +751,8 @@ impl HardDiskController {
 
                     // Clear command if complete
                     if let Continuation::CommandComplete = result {
-                        if let Command::RequestSense = self.command {
-                            // Present Sense Bytes after Sense Status command
+                        if let Command::RequestSense | Command::ReadEccBurstLength = self.command {
+                            // Present Sense Bytes after Sense Status command, or the burst length byte
                             self.state = State::HaveSenseBytes
                         }
                         else {
@@ -799,6 +842,7 @@ impl HardDiskController {
         let byte0 = match self.last_error {
             OperationError::NoError => 0,
             OperationError::NoReadySignal => ERR_NO_READY_SIGNAL,
+            OperationError::WriteFault => ERR_WRITE_FAULT,
             OperationError::InvalidCommand => ERR_INVALID_COMMAND,
             OperationError::IllegalAccess => ERR_ILLEGAL_ACCESS,
         };
@@ -830,7 +874,7 @@ impl HardDiskController {
         */
         let byte1 = (dcb.drive_select << 5) as u8 | (self.drives[dcb.drive_select].head & 0x1F);
         let byte2 =
-            (self.drives[dcb.drive_select].cylinder & 0x700 >> 3) as u8 | self.drives[dcb.drive_select].sector & 0x1F;
+            ((self.drives[dcb.drive_select].cylinder & 0x700) >> 3) as u8 | self.drives[dcb.drive_select].sector & 0x1F;
         let byte3 = (self.drives[dcb.drive_select].cylinder & 0xFF) as u8;
 
         self.data_register_out.push_back(byte0);
@@ -1016,6 +1060,91 @@ impl HardDiskController {
         Continuation::CommandComplete
     }
 
+    /// Perform the Format Drive command. Every track from the cylinder and head specified in the DCB to the end of
+    /// the drive is formatted.
+    fn command_format_drive(&mut self, _bus: &mut BusInterface) -> Continuation {
+        let dcb = self.read_dcb();
+        self.data_register_in.clear();
+
+        log::trace!(
+            "Command Format Drive: drive: {} c: {} h: {} interleave: {}",
+            dcb.drive_select,
+            dcb.c,
+            dcb.h,
+            dcb.interleave
+        );
+        self.format(dcb, true)
+    }
+
+    /// Perform the Format Track and Format Bad Track commands. Bad tracks are not flagged on the virtual disk; they
+    /// are formatted the same as any other track.
+    fn command_format_track(&mut self, _bus: &mut BusInterface) -> Continuation {
+        let dcb = self.read_dcb();
+        self.data_register_in.clear();
+
+        log::trace!(
+            "Command {:?}: drive: {} c: {} h: {} interleave: {}",
+            self.command,
+            dcb.drive_select,
+            dcb.c,
+            dcb.h,
+            dcb.interleave
+        );
+        self.format(dcb, false)
+    }
+
+    /// Format the track specified by the DCB, and if `to_end` is set, every following track on the drive.
+    fn format(&mut self, dcb: DeviceControlBlock, to_end: bool) -> Continuation {
+        self.drive_select = dcb.drive_select;
+
+        let error = if self.drive_present(dcb.drive_select) {
+            let drive = &mut self.drives[dcb.drive_select];
+            if dcb.c >= drive.max_cylinders || dcb.h >= drive.max_heads {
+                OperationError::IllegalAccess
+            }
+            else {
+                let (max_c, max_h) = (drive.max_cylinders, drive.max_heads);
+                let mut tracks = vec![(dcb.c, dcb.h)];
+                if to_end {
+                    tracks.extend((dcb.h + 1..max_h).map(|h| (dcb.c, h)));
+                    tracks.extend((dcb.c + 1..max_c).flat_map(|c| (0..max_h).map(move |h| (c, h))));
+                }
+
+                let mut error = OperationError::NoError;
+                for (c, h) in tracks {
+                    drive.cylinder = c;
+                    drive.head = h;
+                    drive.sector = 0;
+                    if let Err(err) = drive.format_track(c, h) {
+                        log::error!("Format failed: c: {} h: {} Error: {}", c, h, err);
+                        error = OperationError::WriteFault;
+                        break;
+                    }
+                }
+                error
+            }
+        }
+        else {
+            OperationError::NoReadySignal
+        };
+
+        self.set_error(error, dcb.drive_select);
+        self.send_interrupt = true;
+        Continuation::CommandComplete
+    }
+
+    /// Perform the Read ECC Burst Length command. Data on a virtual disk is never corrected, so the burst length
+    /// is always 0.
+    fn command_read_ecc_burst_length(&mut self, _bus: &mut BusInterface) -> Continuation {
+        let dcb = self.read_dcb();
+        self.data_register_in.clear();
+
+        self.data_register_out.push_back(0);
+        self.set_error(OperationError::NoError, dcb.drive_select);
+        self.send_interrupt = true;
+        Continuation::CommandComplete
+    }
+
     /// Perform the Read Sector Buffer command.
     ///
     fn command_read_sector_buffer(&mut self, bus: &mut BusInterface) -> Continuation {
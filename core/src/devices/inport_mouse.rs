@@ -0,0 +1,258 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::inport_mouse.rs
+
+   Implements a Microsoft InPort bus mouse adapter.
+
+   The InPort presents four I/O ports:
+     base+0: Address register. Selects the internal register accessed via the
+             data port. Writing 0x80 resets the chip.
+     base+1: Data register.
+     base+2: Identification register. Reads alternate between the InPort
+             signature byte (0xDE) and the chip revision.
+     base+3: Test register.
+
+   Internal registers:
+     0: Status  - bits 0-2: current button state (R, M, L)
+                  bits 3-5: button state changed since last hold
+                  bit 6:    mouse moved since last hold
+     1: X data  - signed X movement latched by hold
+     2: Y data  - signed Y movement latched by hold
+     7: Mode    - bits 0-2: interrupt rate (1=30Hz, 2=50Hz, 3=100Hz, 4=200Hz)
+                  bit 3:    data interrupt enable
+                  bit 4:    timer interrupt enable
+                  bit 5:    hold. Setting this bit latches the movement
+                            counters and button state.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    devices::pic::Pic,
+};
+
+pub const INPORT_DEFAULT_IO_BASE: u16 = 0x23C;
+pub const INPORT_DEFAULT_IRQ: u8 = 5;
+
+// Scale factor for real vs emulated mouse deltas.
+const INPORT_MOUSE_SCALE: f64 = 0.5;
+
+const INPORT_SIGNATURE: u8 = 0xDE;
+const INPORT_REVISION: u8 = 0x12;
+const INPORT_RESET: u8 = 0x80;
+
+const INPORT_REG_STATUS: u8 = 0x00;
+const INPORT_REG_X: u8 = 0x01;
+const INPORT_REG_Y: u8 = 0x02;
+const INPORT_REG_MODE: u8 = 0x07;
+
+const INPORT_STATUS_RBUTTON: u8 = 0b0000_0001;
+const INPORT_STATUS_LBUTTON: u8 = 0b0000_0100;
+const INPORT_STATUS_MOVED: u8 = 0b0100_0000;
+
+const INPORT_MODE_RATE_MASK: u8 = 0b0000_0111;
+const INPORT_MODE_DATA_INT: u8 = 0b0000_1000;
+const INPORT_MODE_TIMER_INT: u8 = 0b0001_0000;
+const INPORT_MODE_HOLD: u8 = 0b0010_0000;
+
+#[derive(Default)]
+pub struct InportMouse {
+    io_base: u16,
+    irq: u8,
+    address: u8,
+    mode: u8,
+    id_toggle: bool,
+    buttons: u8,
+    pressed_since_hold: u8,
+    held_buttons: u8,
+    delta_x: i32,
+    delta_y: i32,
+    status: u8,
+    data_x: u8,
+    data_y: u8,
+    timer_accum: f64,
+    irq_active: bool,
+}
+
+impl InportMouse {
+    pub fn new(io_base: Option<u16>, irq: Option<u8>) -> Self {
+        Self {
+            io_base: io_base.unwrap_or(INPORT_DEFAULT_IO_BASE),
+            irq: irq.unwrap_or(INPORT_DEFAULT_IRQ),
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(Some(self.io_base), Some(self.irq));
+    }
+
+    /// Accumulate a host mouse update. Movement is reported to the guest when it next latches
+    /// the counters by setting the hold bit in the mode register.
+    pub fn update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) {
+        self.buttons = 0;
+        if l_button_pressed {
+            self.buttons |= INPORT_STATUS_LBUTTON;
+        }
+        if r_button_pressed {
+            self.buttons |= INPORT_STATUS_RBUTTON;
+        }
+        // Remember presses so that a click shorter than the guest's polling interval isn't lost.
+        self.pressed_since_hold |= self.buttons;
+
+        self.delta_x += (delta_x * INPORT_MOUSE_SCALE).round() as i32;
+        self.delta_y += (delta_y * INPORT_MOUSE_SCALE).round() as i32;
+    }
+
+    /// Latch the movement counters and button state into the data registers.
+    fn hold(&mut self) {
+        let x = self.delta_x.clamp(i8::MIN as i32, i8::MAX as i32);
+        let y = self.delta_y.clamp(i8::MIN as i32, i8::MAX as i32);
+        self.delta_x -= x;
+        self.delta_y -= y;
+        self.data_x = x as i8 as u8;
+        self.data_y = y as i8 as u8;
+
+        let buttons = self.buttons | self.pressed_since_hold;
+        self.pressed_since_hold = 0;
+        let changed = (buttons ^ self.held_buttons) << 3;
+        self.status = buttons | changed;
+        if x != 0 || y != 0 {
+            self.status |= INPORT_STATUS_MOVED;
+        }
+        self.held_buttons = buttons;
+    }
+
+    fn timer_period_us(&self) -> Option<f64> {
+        match self.mode & INPORT_MODE_RATE_MASK {
+            1 => Some(1_000_000.0 / 30.0),
+            2 => Some(1_000_000.0 / 50.0),
+            3 => Some(1_000_000.0 / 100.0),
+            4 => Some(1_000_000.0 / 200.0),
+            _ => None,
+        }
+    }
+
+    fn has_data(&self) -> bool {
+        self.delta_x != 0 || self.delta_y != 0 || (self.buttons | self.pressed_since_hold) != self.held_buttons
+    }
+
+    /// Run the InPort for the specified number of microseconds, raising its IRQ at the programmed rate.
+    pub fn run(&mut self, pic: &mut Pic, us: f64) {
+        let period = match self.timer_period_us() {
+            Some(period) if self.mode & (INPORT_MODE_TIMER_INT | INPORT_MODE_DATA_INT) != 0 => period,
+            _ => {
+                self.timer_accum = 0.0;
+                return;
+            }
+        };
+
+        self.timer_accum += us;
+        if self.timer_accum < period {
+            return;
+        }
+        self.timer_accum %= period;
+
+        // With only data interrupts enabled, an interrupt is raised on the timer tick only if there is new data.
+        let timer_int = self.mode & INPORT_MODE_TIMER_INT != 0;
+        if timer_int || self.has_data() {
+            if self.irq_active {
+                pic.clear_interrupt(self.irq);
+            }
+            pic.request_interrupt(self.irq);
+            self.irq_active = true;
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        match self.address {
+            INPORT_REG_MODE => {
+                if data & INPORT_MODE_HOLD != 0 && self.mode & INPORT_MODE_HOLD == 0 {
+                    self.hold();
+                }
+                self.mode = data;
+            }
+            _ => {
+                log::trace!("InPort: Write to unhandled register {}: {:02X}", self.address, data);
+            }
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        match self.address {
+            INPORT_REG_STATUS => self.status,
+            INPORT_REG_X => self.data_x,
+            INPORT_REG_Y => self.data_y,
+            INPORT_REG_MODE => self.mode,
+            _ => 0,
+        }
+    }
+}
+
+impl IoDevice for InportMouse {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.io_base) {
+            0 => self.address,
+            1 => self.read_data(),
+            2 => {
+                self.id_toggle = !self.id_toggle;
+                if self.id_toggle {
+                    INPORT_SIGNATURE
+                }
+                else {
+                    INPORT_REVISION
+                }
+            }
+            3 => 0,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            0 => {
+                if data == INPORT_RESET {
+                    self.reset();
+                }
+                else {
+                    self.address = data & 0x07;
+                }
+            }
+            1 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            ("InPort Address".to_string(), self.io_base),
+            ("InPort Data".to_string(), self.io_base + 1),
+            ("InPort Identification".to_string(), self.io_base + 2),
+            ("InPort Test".to_string(), self.io_base + 3),
+        ]
+    }
+}
@@ -43,7 +43,17 @@ use strum::IntoEnumIterator;
 use serde_derive::Deserialize;
 use toml;
 
-use crate::{keys::MartyKey, machine::KeybufferEntry};
+use crate::{
+    keys::MartyKey,
+    machine::KeybufferEntry,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+/// Number of scancodes the keyboard's internal buffer can hold before reporting an overflow.
+pub const KB_BUFFER_SIZE_MODELF: usize = 16;
+pub const KB_BUFFER_SIZE_TANDY: usize = 16;
+/// Scancode sent in place of further keystrokes when the keyboard buffer overflows.
+pub const KB_OVERFLOW_SCANCODE: u8 = 0xFF;
 
 // Define the various types of keyboard we can emulate.
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
@@ -173,11 +183,12 @@ pub struct Keyboard {
     kb_type: KeyboardType,
     kb_hash: HashMap<MartyKey, KeyState>,
     keys_pressed: Vec<MartyKey>,
+    typematic_key: Option<MartyKey>, // The key currently eligible for typematic repeat.
     typematic: bool,
     typematic_delay: f64, // Typematic repeat delay from initial keypress (ms)
     typematic_rate: f64,  // Typematic repeat rate (ms)
     kb_buffer_size: usize,
-    kb_buffer: Vec<u8>, // Keyboard buffer. Variable length depending on keyboard model.
+    kb_buffer: VecDeque<u8>, // Keyboard buffer. Variable length depending on keyboard model.
     kb_buffer_overflow: bool,
     keycode_mappings: Vec<KeycodeMapping>,
 }
@@ -189,11 +200,12 @@ impl Default for Keyboard {
             kb_type: KeyboardType::ModelF,
             kb_hash: HashMap::new(),
             keys_pressed: Vec::new(),
+            typematic_key: None,
             typematic: true,
             typematic_delay: 500.0,
             typematic_rate: 100.0,
-            kb_buffer_size: 1,
-            kb_buffer: Vec::new(),
+            kb_buffer_size: KB_BUFFER_SIZE_MODELF,
+            kb_buffer: VecDeque::new(),
             kb_buffer_overflow: false,
             keycode_mappings: Vec::new(),
         }
@@ -205,6 +217,7 @@ impl Keyboard {
         let mut kb = Keyboard {
             debug,
             kb_type,
+            kb_buffer_size: Keyboard::buffer_size(kb_type),
             ..Keyboard::default()
         };
 
@@ -265,7 +278,25 @@ impl Keyboard {
 
     pub fn set_type(&mut self, kb_type: KeyboardType) {
         self.kb_type = kb_type;
-        // Do any reinitialization here
+        self.kb_buffer_size = Keyboard::buffer_size(kb_type);
+        self.reset();
+    }
+
+    fn buffer_size(kb_type: KeyboardType) -> usize {
+        match kb_type {
+            KeyboardType::Tandy1000 => KB_BUFFER_SIZE_TANDY,
+            _ => KB_BUFFER_SIZE_MODELF,
+        }
+    }
+
+    /// Reset the keyboard, as when the host holds the clock line low. The keyboard's buffer is
+    /// emptied and all keys are considered released. The self-test result byte is sent by the PPI.
+    pub fn reset(&mut self) {
+        self.kb_buffer.clear();
+        self.kb_buffer_overflow = false;
+        self.keys_pressed.clear();
+        self.typematic_key = None;
+        self.clear();
     }
 
     /// Get the KeyState for the corresponding key.
@@ -338,6 +369,110 @@ impl Keyboard {
         Ok(keycodes)
     }
 
+    /// Convert text into the sequence of key events that types it on a US layout keyboard.
+    /// Shift is held down around each character that requires it. The events bypass keyboard
+    /// layout translation, as the text is already in the guest's character set. Characters that
+    /// cannot be typed are skipped.
+    pub fn keycodes_from_text(text: &str) -> Vec<KeybufferEntry> {
+        let mut keycodes = Vec::new();
+        let mut push_key = |keycode: MartyKey, pressed: bool| {
+            keycodes.push(KeybufferEntry {
+                keycode,
+                pressed,
+                modifiers: KeyboardModifiers::default(),
+                translate: false,
+            });
+        };
+
+        let mut skipped = 0;
+        for c in text.chars() {
+            match Keyboard::char_to_keycode(c) {
+                Some((keycode, shift)) => {
+                    if shift {
+                        push_key(MartyKey::ShiftLeft, true);
+                    }
+                    push_key(keycode, true);
+                    push_key(keycode, false);
+                    if shift {
+                        push_key(MartyKey::ShiftLeft, false);
+                    }
+                }
+                None => {
+                    // Carriage returns are dropped silently, newlines produce Enter.
+                    if c != '\r' {
+                        log::debug!("keycodes_from_text(): No key for character {:?}", c);
+                        skipped += 1;
+                    }
+                }
+            }
+        }
+        if skipped > 0 {
+            log::warn!(
+                "keycodes_from_text(): Skipped {} character(s) that can't be typed",
+                skipped
+            );
+        }
+
+        keycodes
+    }
+
+    /// Return the key that produces the specified character on a US layout keyboard, and whether
+    /// shift must be held.
+    fn char_to_keycode(c: char) -> Option<(MartyKey, bool)> {
+        if c.is_ascii_alphabetic() {
+            return MartyKey::from_str(&format!("Key{}", c.to_ascii_uppercase()))
+                .ok()
+                .map(|keycode| (keycode, c.is_ascii_uppercase()));
+        }
+        if c.is_ascii_digit() {
+            return MartyKey::from_str(&format!("Digit{}", c))
+                .ok()
+                .map(|keycode| (keycode, false));
+        }
+
+        let key = match c {
+            ' ' => (MartyKey::Space, false),
+            '\n' => (MartyKey::Enter, false),
+            '\t' => (MartyKey::Tab, false),
+            '\x08' => (MartyKey::Backspace, false),
+            '\x1B' => (MartyKey::Escape, false),
+            '!' => (MartyKey::Digit1, true),
+            '@' => (MartyKey::Digit2, true),
+            '#' => (MartyKey::Digit3, true),
+            '$' => (MartyKey::Digit4, true),
+            '%' => (MartyKey::Digit5, true),
+            '^' => (MartyKey::Digit6, true),
+            '&' => (MartyKey::Digit7, true),
+            '*' => (MartyKey::Digit8, true),
+            '(' => (MartyKey::Digit9, true),
+            ')' => (MartyKey::Digit0, true),
+            '`' => (MartyKey::Backquote, false),
+            '~' => (MartyKey::Backquote, true),
+            '-' => (MartyKey::Minus, false),
+            '_' => (MartyKey::Minus, true),
+            '=' => (MartyKey::Equal, false),
+            '+' => (MartyKey::Equal, true),
+            '[' => (MartyKey::BracketLeft, false),
+            '{' => (MartyKey::BracketLeft, true),
+            ']' => (MartyKey::BracketRight, false),
+            '}' => (MartyKey::BracketRight, true),
+            '\\' => (MartyKey::Backslash, false),
+            '|' => (MartyKey::Backslash, true),
+            ';' => (MartyKey::Semicolon, false),
+            ':' => (MartyKey::Semicolon, true),
+            '\'' => (MartyKey::Quote, false),
+            '"' => (MartyKey::Quote, true),
+            ',' => (MartyKey::Comma, false),
+            '<' => (MartyKey::Comma, true),
+            '.' => (MartyKey::Period, false),
+            '>' => (MartyKey::Period, true),
+            '/' => (MartyKey::Slash, false),
+            '?' => (MartyKey::Slash, true),
+            _ => return None,
+        };
+        Some(key)
+    }
+
     /// Convert a MartyKey key code into a physical scancode based on the configured
     /// keyboard model.
     pub fn keycode_to_scancodes(&self, key_code: MartyKey) -> Vec<u8> {
@@ -564,15 +699,22 @@ impl Keyboard {
         scancodes
     }
 
-    /// Set the corresponding key to pressed.
+    /// Set the corresponding key to pressed. If translate is false, the keyboard layout mapping is
+    /// bypassed and the key's default scancodes are sent.
     pub fn key_down(
         &mut self,
         key_code: MartyKey,
         modifiers: &KeyboardModifiers,
+        translate: bool,
         kb_buf: Option<&mut VecDeque<KeybufferEntry>>,
     ) {
         // Translation will produce either a Scancode or Keycode result
-        let translation = self.translate_keydown(key_code, modifiers);
+        let translation = if translate {
+            self.translate_keydown(key_code, modifiers)
+        }
+        else {
+            TranslationType::Scancode(self.keycode_to_scancodes(key_code))
+        };
 
         match translation {
             TranslationType::Keycode(kvec) => {
@@ -610,6 +752,7 @@ impl Keyboard {
                             key.pressed_time = 0.0;
 
                             self.keys_pressed.push(key_code);
+                            self.typematic_key = Some(key_code);
                             self.send_scancodes(&svec);
                         }
                     }
@@ -645,6 +788,9 @@ impl Keyboard {
 
         // Remove this key from keys_pressed.
         self.keys_pressed.retain(|&k| k != key_code);
+        if self.typematic_key == Some(key_code) {
+            self.typematic_key = None;
+        }
     }
 
     /// Reset key states for all keys to unpressed.
@@ -659,15 +805,18 @@ impl Keyboard {
         if keys.len() > 0 {
             if self.kb_buffer_size > 1 {
                 // We have a keyboard buffer
-                if self.kb_buffer.len() + keys.len() >= self.kb_buffer_size {
-                    // KB overflow!
+                if self.kb_buffer_overflow || (self.kb_buffer.len() + keys.len() > self.kb_buffer_size) {
+                    // KB overflow! Keystrokes are discarded until the buffer has drained.
                     self.kb_buffer_overflow = true;
                 }
+                else {
+                    self.kb_buffer.extend(keys);
+                }
             }
             else if self.kb_buffer_size == 1 {
                 // No keyboard buffer (kb_buffer_size == 1). Just set one scancode.
                 self.kb_buffer.clear();
-                self.kb_buffer.push(keys[0]);
+                self.kb_buffer.push_back(keys[0]);
             }
             else {
                 panic!("invalid kb_buffer_size");
@@ -676,14 +825,17 @@ impl Keyboard {
     }
 
     /// Read out a scancode from the keyboard or None if no key in buffer.
+    /// Scancodes are delivered in the order they were produced. If the buffer overflowed, the
+    /// overflow scancode is sent after the buffered scancodes have been read.
     pub fn recv_scancode(&mut self) -> Option<u8> {
-        if self.kb_buffer_overflow {
-            // Send the keyboard overflow scancode
-            self.kb_buffer_overflow = false;
-            Some(0xFF)
-        }
-        else {
-            self.kb_buffer.pop()
+        match self.kb_buffer.pop_front() {
+            Some(byte) => Some(byte),
+            None if self.kb_buffer_overflow => {
+                // Send the keyboard overflow scancode
+                self.kb_buffer_overflow = false;
+                Some(KB_OVERFLOW_SCANCODE)
+            }
+            None => None,
         }
     }
 
@@ -793,11 +945,12 @@ impl Keyboard {
         // Convert to milliseconds, all typematic delays are in ms.
         let ms: f64 = us / 1000.0;
 
-        let mut repeating_keys = Vec::new();
+        let mut repeat_translation = None;
 
-        // Update keys pressed.
-        for vkey in &self.keys_pressed {
-            if self.typematic && self.is_typematic_key(*vkey) {
+        // Only the most recently pressed key repeats. Pressing another key stops the repeat of the
+        // previous one, and releasing the repeating key stops repeating altogether.
+        if let Some(vkey) = self.typematic_key {
+            if self.typematic && self.is_typematic_key(vkey) {
                 if let Some(key_state) = self.kb_hash.get_mut(&vkey) {
                     key_state.pressed_time += ms;
                     if key_state.pressed_time > (self.typematic_delay - self.typematic_rate) {
@@ -808,26 +961,15 @@ impl Keyboard {
                         key_state.repeat_time += ms;
                         if key_state.repeat_time > self.typematic_rate {
                             key_state.repeat_time -= self.typematic_rate;
-                            repeating_keys.push(key_state.clone());
+                            repeat_translation = key_state.translation.clone();
                         }
                     }
                 }
             }
         }
 
-        // Sort all repeating keys by pressed_time
-        repeating_keys.sort_by(|a, b| {
-            a.pressed_time
-                .partial_cmp(&b.pressed_time)
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .reverse()
-        });
-
-        // Only repeat the oldest pressed key
-        if let Some(key) = repeating_keys.pop() {
-            if let Some(translation) = key.translation {
-                self.send_scancodes(&translation);
-            }
+        if let Some(translation) = repeat_translation {
+            self.send_scancodes(&translation);
         }
     }
 
@@ -851,3 +993,24 @@ impl Keyboard {
         }
     }
 }
+
+impl SaveState for Keyboard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.kb_buffer.iter().copied().collect::<Vec<u8>>());
+        w.write_bool(self.kb_buffer_overflow);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let buffer = r.read_bytes()?;
+        if buffer.len() > self.kb_buffer_size {
+            return Err(SaveStateError::SizeMismatch("keyboard buffer"));
+        }
+        // Keys held on the host are released, so they don't stay stuck down in the restored state.
+        self.keys_pressed.clear();
+        self.typematic_key = None;
+        self.clear();
+        self.kb_buffer = buffer.iter().copied().collect();
+        self.kb_buffer_overflow = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -38,6 +38,7 @@ use crate::{
         lpt_card::{ParallelController, LPT_DEFAULT_IO_BASE, LPT_PORT_MASK},
         lpt_port::ParallelPort,
     },
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
 };
 
 pub const LOTECH_DEFAULT_IO_BASE: u16 = 0x260;
@@ -188,3 +189,19 @@ impl MemoryMappedDevice for LotechEmsCard {
         mapping
     }
 }
+
+impl SaveState for LotechEmsCard {
+    fn save_state(&self, w: &mut StateWriter) {
+        for page in &self.pages {
+            w.write_usize(page.page_addr);
+        }
+        w.write_bytes(&self.mem);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for page in self.pages.iter_mut() {
+            page.page_addr = r.read_usize()? & (LOTECH_EMS_SIZE - 1);
+        }
+        r.read_bytes_into(&mut self.mem, "EMS memory")
+    }
+}
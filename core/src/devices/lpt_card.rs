@@ -59,6 +59,14 @@ impl ParallelController {
             ..Default::default()
         }
     }
+
+    pub fn lpt_mut(&mut self) -> &mut ParallelPort {
+        &mut self.lpt
+    }
+
+    pub fn run(&mut self, us: f64) {
+        self.lpt.run(us);
+    }
 }
 
 impl IoDevice for ParallelController {
@@ -0,0 +1,134 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::lpt_dac.rs
+
+    Implements parallel port DACs: the Covox Speech Thing and the Disney
+    Sound Source.
+
+    The Covox is a resistor ladder on the data lines, so every write to the
+    data register is output immediately.
+
+    The Sound Source latches the data register into a 16-byte FIFO on the
+    falling edge of the SELECT IN control line, and plays samples from the
+    FIFO at a fixed rate of 7kHz. While the FIFO is full, the ACK line in
+    the status register is set.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{machine_types::LptDacType, sound_mixer::SoundSource};
+
+pub const DSS_SAMPLE_RATE: f64 = 7000.0;
+pub const DSS_FIFO_LEN: usize = 16;
+
+const DAC_SILENCE: u8 = 0x80;
+
+// Rate at which the DAC output is sampled for the mixer.
+pub const DAC_OUTPUT_RATE: f64 = 44_100.0;
+
+pub struct LptDac {
+    dac_type: LptDacType,
+    level: u8,
+    fifo: VecDeque<u8>,
+    fifo_accum: f64,
+
+    output_accum: f64,
+    source: Option<SoundSource>,
+}
+
+impl LptDac {
+    pub fn new(dac_type: LptDacType) -> Self {
+        Self {
+            dac_type,
+            level: DAC_SILENCE,
+            fifo: VecDeque::with_capacity(DSS_FIFO_LEN),
+            fifo_accum: 0.0,
+
+            output_accum: 0.0,
+            source: None,
+        }
+    }
+
+    pub fn dac_type(&self) -> LptDacType {
+        self.dac_type
+    }
+
+    /// Set the mixer source that output samples are sent to.
+    pub fn set_sound_source(&mut self, source: SoundSource) {
+        self.source = Some(source);
+    }
+
+    /// Handle a write to the parallel port data register.
+    pub fn data_write(&mut self, data: u8) {
+        if let LptDacType::Covox = self.dac_type {
+            self.level = data;
+        }
+    }
+
+    /// Handle a write to the parallel port control register. `data` is the value of the data
+    /// register at the time of the write.
+    pub fn control_write(&mut self, old_select_in: u8, new_select_in: u8, data: u8) {
+        if let LptDacType::DisneySoundSource = self.dac_type {
+            if old_select_in == 1 && new_select_in == 0 && self.fifo.len() < DSS_FIFO_LEN {
+                self.fifo.push_back(data);
+            }
+        }
+    }
+
+    /// Return the ACK status bit. The Sound Source sets ACK while its FIFO is full.
+    pub fn ack(&self) -> bool {
+        match self.dac_type {
+            LptDacType::Covox => false,
+            LptDacType::DisneySoundSource => self.fifo.len() >= DSS_FIFO_LEN,
+        }
+    }
+
+    /// Run the DAC for the specified number of microseconds, sending output samples to the mixer.
+    pub fn run(&mut self, us: f64) {
+        if let LptDacType::DisneySoundSource = self.dac_type {
+            // The DAC holds the last sample played when the FIFO runs empty.
+            let period = 1_000_000.0 / DSS_SAMPLE_RATE;
+            self.fifo_accum += us;
+            while self.fifo_accum >= period {
+                self.fifo_accum -= period;
+                if let Some(sample) = self.fifo.pop_front() {
+                    self.level = sample;
+                }
+            }
+        }
+
+        let period = 1_000_000.0 / DAC_OUTPUT_RATE;
+        self.output_accum += us;
+        while self.output_accum >= period {
+            self.output_accum -= period;
+            if let Some(source) = &mut self.source {
+                source.push((self.level as f32 - 128.0) / 128.0);
+            }
+        }
+    }
+}
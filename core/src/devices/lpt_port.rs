@@ -30,9 +30,19 @@
     implementation, and must be embedded into a card implementation that can
     decode the proper port address.
 
+    A virtual printer may be attached to the port. When a printer is attached,
+    data bytes are latched into it on the rising edge of STROBE, and the status
+    register reports an online, ready printer.
+
+    Alternatively, a parallel port DAC may be attached, which receives all
+    data and control register writes.
+
 */
 
-use crate::tracelogger::TraceLogger;
+use crate::{
+    devices::{lpt_dac::LptDac, printer::Printer},
+    tracelogger::TraceLogger,
+};
 use modular_bitfield::{bitfield, prelude::*};
 
 pub const LPT_DEFAULT_IRQ: u16 = 7;
@@ -68,6 +78,8 @@ pub struct ParallelPort {
     control: ParallelControl,
     irq: u16,
     trace_logger: TraceLogger,
+    printer: Option<Printer>,
+    dac: Option<LptDac>,
 }
 
 impl Default for ParallelPort {
@@ -78,6 +90,8 @@ impl Default for ParallelPort {
             control: ParallelControl::from_bytes([0]),
             irq: LPT_DEFAULT_IRQ,
             trace_logger: TraceLogger::None,
+            printer: None,
+            dac: None,
         }
     }
 }
@@ -91,6 +105,31 @@ impl ParallelPort {
         }
     }
 
+    pub fn attach_printer(&mut self, printer: Printer) {
+        self.printer = Some(printer);
+    }
+
+    pub fn printer_mut(&mut self) -> Option<&mut Printer> {
+        self.printer.as_mut()
+    }
+
+    pub fn attach_dac(&mut self, dac: LptDac) {
+        self.dac = Some(dac);
+    }
+
+    pub fn dac_mut(&mut self) -> Option<&mut LptDac> {
+        self.dac.as_mut()
+    }
+
+    pub fn run(&mut self, us: f64) {
+        if let Some(printer) = &mut self.printer {
+            printer.run(us);
+        }
+        if let Some(dac) = &mut self.dac {
+            dac.run(us);
+        }
+    }
+
     pub fn port_write(&mut self, port: u16, data: u8) {
         match port & 0x03 {
             0 => {
@@ -123,6 +162,9 @@ impl ParallelPort {
 
     pub fn data_register_write(&mut self, data: u8) {
         self.data = data;
+        if let Some(dac) = &mut self.dac {
+            dac.data_write(data);
+        }
         self.trace_logger
             .print(format!("LPT: Data register write: {:#02X}", data));
     }
@@ -134,7 +176,21 @@ impl ParallelPort {
     }
 
     pub fn control_register_write(&mut self, data: u8) {
+        let old_control = self.control;
         self.control = ParallelControl::from_bytes([data]);
+
+        if let Some(printer) = &mut self.printer {
+            if old_control.initialize() == 1 && self.control.initialize() == 0 {
+                // INIT is active low.
+                printer.initialize();
+            }
+            if old_control.strobe() == 0 && self.control.strobe() == 1 {
+                printer.feed(self.data);
+            }
+        }
+        if let Some(dac) = &mut self.dac {
+            dac.control_write(old_control.select_in(), self.control.select_in(), self.data);
+        }
         self.trace_logger
             .print(format!("LPT: Control register write: {:#02X}", data));
     }
@@ -146,7 +202,24 @@ impl ParallelPort {
     }
 
     pub fn status_register_read(&mut self) -> u8 {
-        let byte = self.status.into_bytes()[0];
+        let byte = if self.printer.is_some() {
+            // Printer is always ready: not busy, no ack pending, paper present, selected, no error.
+            ParallelStatus::new()
+                .with_busy(1)
+                .with_ack(1)
+                .with_select(1)
+                .with_error(1)
+                .into_bytes()[0]
+                | 0x07
+        }
+        else if let Some(dac) = &self.dac {
+            let mut status = self.status;
+            status.set_ack(dac.ack() as u8);
+            status.into_bytes()[0]
+        }
+        else {
+            self.status.into_bytes()[0]
+        };
         self.trace_logger
             .print(format!("LPT: Status register read: {:#02X}", byte));
         byte
@@ -31,7 +31,11 @@
 
 */
 
-use crate::{device_traits::videocard::VideoCardStateEntry, tracelogger::TraceLogger};
+use crate::{
+    device_traits::videocard::VideoCardStateEntry,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    tracelogger::TraceLogger,
+};
 
 const CURSOR_LINE_MASK: u8 = 0b0000_1111;
 const CURSOR_ATTR_MASK: u8 = 0b0011_0000;
@@ -716,3 +720,26 @@ impl Crtc6845 {
         counter_vec
     }
 }
+
+/// Only the register file is saved. Registers are written back on load so that the values calculated from them are
+/// updated, and the counters start again at the top of a frame.
+impl SaveState for Crtc6845 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.reg);
+        w.write_u8(self.reg_select as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut reg = [0; 18];
+        r.read_bytes_into(&mut reg, "CRTC registers")?;
+        // The light pen registers are read-only.
+        for (i, byte) in reg.iter().enumerate().take(16) {
+            self.select_register(i);
+            self.write_register(*byte);
+        }
+        self.reg[16] = reg[16];
+        self.reg[17] = reg[17];
+        self.select_register(r.read_u8()? as usize);
+        Ok(())
+    }
+}
@@ -119,13 +119,15 @@ impl MemoryMappedDevice for MDACard {
                     read_only: false,
                     priority: 3, // Allow another MDA card to override this
                 });
-                mapping.push(MemRangeDescriptor {
-                    address: 0xB8000,
-                    size: HGC_MEM_APERTURE_HALF,
-                    cycle_cost: 0,
-                    read_only: false,
-                    priority: 0,
-                });
+                if !self.hgc_half_only {
+                    mapping.push(MemRangeDescriptor {
+                        address: 0xB8000,
+                        size: HGC_MEM_APERTURE_HALF,
+                        cycle_cost: 0,
+                        read_only: false,
+                        priority: 0,
+                    });
+                }
             }
             _ => {
                 panic!("Bad subtype for MDA!")
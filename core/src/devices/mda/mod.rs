@@ -48,6 +48,7 @@ mod videocard;
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit},
     device_traits::videocard::*,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     tracelogger::TraceLogger,
 };
 
@@ -509,6 +510,7 @@ pub struct MDACard {
     hgc_config: HercConfigSwitch,
     hgc_page_offset: usize,
     hgc_page_flips: u32,
+    hgc_half_only: bool,
 }
 
 #[derive(Debug)]
@@ -670,6 +672,7 @@ impl Default for MDACard {
             hgc_config: HercConfigSwitch::new(),
             hgc_page_offset: 0,
             hgc_page_flips: 0,
+            hgc_half_only: false,
         }
     }
 }
@@ -710,6 +713,21 @@ impl MDACard {
         mda
     }
 
+    pub fn lpt_mut(&mut self) -> Option<&mut ParallelPort> {
+        self.lpt.as_mut()
+    }
+
+    /// Restrict a Hercules card to its first 32K page at B000, leaving B800 free for a color card
+    /// installed alongside it. This must be set before the card's memory mapping is registered.
+    pub fn set_half_only(&mut self, state: bool) {
+        if let VideoCardSubType::Hercules = self.subtype {
+            self.hgc_half_only = state;
+            if state {
+                self.mem_mask = HGC_MEM_MASK_HALF;
+            }
+        }
+    }
+
     /// Reset CGA state (on reboot, for example)
     fn reset_private(&mut self) {
         let trace_logger = std::mem::replace(&mut self.trace_logger, TraceLogger::None);
@@ -719,6 +737,7 @@ impl MDACard {
         // Save non-default values
         *self = Self {
             subtype: self.subtype,
+            mem_mask: self.mem_mask,
             debug: self.debug,
             clock_mode: self.clock_mode,
             frame_count: self.frame_count, // Keep frame count as to not confuse frontend
@@ -726,6 +745,7 @@ impl MDACard {
             extents: self.extents.clone(),
             hblank_fn,
             lpt,
+            hgc_half_only: self.hgc_half_only,
             ..Self::default()
         }
     }
@@ -912,6 +932,11 @@ impl MDACard {
     fn handle_hgc_config_switch(&mut self, data: u8) {
         log::debug!("Write to Hercules configuration switch: {:02X}", data);
         self.hgc_config = HercConfigSwitch::from_bytes([data]);
+        if self.hgc_half_only && self.hgc_config.enable_page() {
+            // The second page would conflict with the color card's memory aperture.
+            log::warn!("Hercules second page requested while installed with a color card; ignoring.");
+            self.hgc_config.set_enable_page(false);
+        }
     }
 
     /// Handle a read from the MDA status register. This register has bits to indicate whether
@@ -1463,3 +1488,30 @@ impl MDACard {
         }
     }
 }
+
+/// Video memory and registers are saved, but not the position of the beam. On load the card is reset and the
+/// registers are written back as if by the guest, so display starts again from the top of a frame.
+impl SaveState for MDACard {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.mem[..]);
+        self.crtc.save_state(w);
+        w.write_u8(self.hgc_config.into_bytes()[0]);
+        w.write_u8(self.mode.into_bytes()[0]);
+        w.write_bool(self.lightpen_latch);
+        w.write_usize(self.lightpen_addr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.reset_private();
+        r.read_bytes_into(&mut self.mem[..], "MDA memory")?;
+        self.crtc.load_state(r)?;
+        let hgc_config = r.read_u8()?;
+        if let VideoCardSubType::Hercules = self.subtype {
+            self.handle_hgc_config_switch(hgc_config);
+        }
+        self.handle_mode_register(r.read_u8()?);
+        self.lightpen_latch = r.read_bool()?;
+        self.lightpen_addr = r.read_usize()?;
+        Ok(())
+    }
+}
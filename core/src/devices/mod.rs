@@ -31,27 +31,39 @@
 */
 
 pub mod a0;
+pub mod atapi_cdrom;
 pub mod cartridge_slots;
+pub mod cassette;
 pub mod cga;
 pub mod dipswitch;
 pub mod dma;
+pub mod drive_sound;
 #[cfg(feature = "ega")]
 pub mod ega;
 pub mod fdc;
 pub mod floppy_drive;
 pub mod game_port;
 pub mod hdc;
+pub mod inport_mouse;
 pub mod keyboard;
 pub mod lotech_ems;
 pub mod lpt_card;
+pub mod lpt_dac;
 pub mod lpt_port;
 pub mod mc6845;
 pub mod mda;
+pub mod modem;
 pub mod mouse;
+pub mod mpu401;
+pub mod ne2000;
 pub mod pic;
 pub mod pit;
 pub mod ppi;
+pub mod printer;
 pub mod serial;
+pub mod sn76489;
+pub mod sound_blaster;
 pub mod tga;
 #[cfg(feature = "vga")]
 pub mod vga;
+pub mod xtide;
@@ -0,0 +1,709 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::modem.rs
+
+   Implements a Hayes-compatible virtual modem attached to a serial port.
+
+   Dialing a host name with ATD (ie, ATDT bbs.example.com:23) opens an
+   outbound TCP connection. If a listen port is configured, inbound TCP
+   connections ring the modem and can be answered with ATA, or answered
+   automatically when S0 is non-zero. The listener binds to the loopback
+   address unless another listen address is configured.
+
+   Supported commands: A, D, E, H, I, O, Q, S, V, Z, &F. Other common setup
+   commands (B, L, M, X, &C, &D, &K, ...) are accepted and ignored.
+   The "+++" escape sequence with guard time returns to command mode.
+
+   Sockets are not available on wasm32 targets; there, dialing always
+   results in NO CARRIER and the listen port is ignored.
+*/
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use crate::devices::serial::SerialPortController;
+
+const MODEM_DEFAULT_TCP_PORT: u16 = 23;
+// Inbound connections are only accepted from the local machine unless a listen address is configured.
+const MODEM_DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1";
+#[cfg(not(target_arch = "wasm32"))]
+const MODEM_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+// How often to poll the network, in microseconds.
+const MODEM_POLL_INTERVAL: f64 = 1_000.0;
+// Time between rings for an inbound connection, in microseconds.
+const MODEM_RING_INTERVAL: f64 = 2_000_000.0;
+// Maximum number of unanswered rings before an inbound connection is dropped.
+const MODEM_MAX_RINGS: u8 = 10;
+// Don't read more from the network while the serial RX queue holds this many bytes.
+const MODEM_RX_HIGH_WATER: usize = 256;
+const MODEM_CONNECT_BAUD: u32 = 9600;
+
+const S_AUTO_ANSWER: usize = 0;
+const S_RING_COUNT: usize = 1;
+const S_ESCAPE_CHAR: usize = 2;
+const S_CR_CHAR: usize = 3;
+const S_LF_CHAR: usize = 4;
+const S_BS_CHAR: usize = 5;
+const S_GUARD_TIME: usize = 12;
+
+const S_REGISTER_DEFAULTS: [u8; 16] = [0, 0, 43, 13, 10, 8, 2, 50, 2, 6, 14, 95, 50, 0, 0, 0];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ModemResult {
+    Ok,
+    Connect,
+    Ring,
+    NoCarrier,
+    Error,
+}
+
+impl ModemResult {
+    fn code(&self) -> u8 {
+        match self {
+            ModemResult::Ok => 0,
+            ModemResult::Connect => 1,
+            ModemResult::Ring => 2,
+            ModemResult::NoCarrier => 3,
+            ModemResult::Error => 4,
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            ModemResult::Ok => "OK".to_string(),
+            ModemResult::Connect => format!("CONNECT {}", MODEM_CONNECT_BAUD),
+            ModemResult::Ring => "RING".to_string(),
+            ModemResult::NoCarrier => "NO CARRIER".to_string(),
+            ModemResult::Error => "ERROR".to_string(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ModemMode {
+    Command,
+    Online,
+}
+
+pub struct VirtualModem {
+    port: usize,
+    listen_port: Option<u16>,
+    listen_address: String,
+    mode: ModemMode,
+    cmd_buf: Vec<u8>,
+    echo: bool,
+    verbose: bool,
+    quiet: bool,
+    s_regs: [u8; 16],
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+    inbound: Option<TcpStream>,
+    dialing: Option<Receiver<std::io::Result<TcpStream>>>,
+    rings: u8,
+    ring_timer: f64,
+    escape_count: u8,
+    guard_timer: f64,
+    poll_timer: f64,
+    dtr: bool,
+    tx_buf: Vec<u8>,
+}
+
+impl VirtualModem {
+    pub fn new(port: usize, listen_port: Option<u16>, listen_address: Option<String>) -> Self {
+        let mut modem = Self {
+            port,
+            listen_port,
+            listen_address: listen_address.unwrap_or_else(|| MODEM_DEFAULT_LISTEN_ADDRESS.to_string()),
+            mode: ModemMode::Command,
+            cmd_buf: Vec::new(),
+            echo: true,
+            verbose: true,
+            quiet: false,
+            s_regs: S_REGISTER_DEFAULTS,
+            listener: None,
+            stream: None,
+            inbound: None,
+            dialing: None,
+            rings: 0,
+            ring_timer: 0.0,
+            escape_count: 0,
+            guard_timer: 0.0,
+            poll_timer: 0.0,
+            dtr: false,
+            tx_buf: Vec::new(),
+        };
+        modem.listen();
+        modem
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn listen(&mut self) {
+        if let Some(listen_port) = self.listen_port {
+            log::warn!(
+                "Modem: Can't listen on port {}: not supported on this platform",
+                listen_port
+            );
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn listen(&mut self) {
+        if let Some(listen_port) = self.listen_port {
+            let address = (self.listen_address.as_str(), listen_port);
+            match TcpListener::bind(address).and_then(|l| l.set_nonblocking(true).map(|_| l)) {
+                Ok(listener) => {
+                    log::info!("Modem: Listening for connections on {}:{}", address.0, listen_port);
+                    self.listener = Some(listener);
+                }
+                Err(e) => {
+                    log::error!("Modem: Failed to listen on {}:{}: {}", address.0, listen_port, e);
+                }
+            }
+        }
+    }
+
+    /// Restore the modem's configuration to defaults (ATZ, AT&F)
+    fn reset_config(&mut self) {
+        self.echo = true;
+        self.verbose = true;
+        self.quiet = false;
+        self.s_regs = S_REGISTER_DEFAULTS;
+    }
+
+    fn guard_time_us(&self) -> f64 {
+        // S12 is in units of 1/50th of a second.
+        self.s_regs[S_GUARD_TIME] as f64 * 20_000.0
+    }
+
+    fn respond(&self, serial: &mut SerialPortController, result: ModemResult) {
+        if self.quiet {
+            return;
+        }
+        let cr = self.s_regs[S_CR_CHAR];
+        let lf = self.s_regs[S_LF_CHAR];
+        if self.verbose {
+            serial.queue_byte(self.port, cr);
+            serial.queue_byte(self.port, lf);
+            for byte in result.text().bytes() {
+                serial.queue_byte(self.port, byte);
+            }
+            serial.queue_byte(self.port, cr);
+            serial.queue_byte(self.port, lf);
+        }
+        else {
+            for byte in result.code().to_string().bytes() {
+                serial.queue_byte(self.port, byte);
+            }
+            serial.queue_byte(self.port, cr);
+        }
+    }
+
+    fn respond_text(&self, serial: &mut SerialPortController, text: &str) {
+        let cr = self.s_regs[S_CR_CHAR];
+        let lf = self.s_regs[S_LF_CHAR];
+        serial.queue_byte(self.port, cr);
+        serial.queue_byte(self.port, lf);
+        for byte in text.bytes() {
+            serial.queue_byte(self.port, byte);
+        }
+    }
+
+    fn go_online(&mut self, serial: &mut SerialPortController, stream: TcpStream) {
+        if let Err(e) = stream.set_nonblocking(true) {
+            log::error!("Modem: Failed to set socket non-blocking: {}", e);
+        }
+        let _ = stream.set_nodelay(true);
+        log::debug!("Modem: Connected to {:?}", stream.peer_addr());
+        self.stream = Some(stream);
+        self.mode = ModemMode::Online;
+        self.escape_count = 0;
+        self.guard_timer = 0.0;
+        self.respond(serial, ModemResult::Connect);
+    }
+
+    fn hang_up(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        self.inbound = None;
+        self.dialing = None;
+        self.rings = 0;
+        self.s_regs[S_RING_COUNT] = 0;
+        self.mode = ModemMode::Command;
+    }
+
+    fn dial(&mut self, serial: &mut SerialPortController, number: &str) {
+        // Strip dial modifiers and quotes. What remains is a host name with optional port.
+        let target: String = number
+            .trim()
+            .trim_start_matches(['T', 't', 'P', 'p'])
+            .chars()
+            .filter(|c| *c != '"' && !c.is_whitespace())
+            .collect();
+
+        if target.is_empty() {
+            self.respond(serial, ModemResult::Error);
+            return;
+        }
+
+        let address = if target.contains(':') {
+            target
+        }
+        else {
+            format!("{}:{}", target, MODEM_DEFAULT_TCP_PORT)
+        };
+
+        log::debug!("Modem: Dialing {}", address);
+        self.dialing = Some(Self::connect(address));
+    }
+
+    /// Start connecting to the specified address. The result is delivered through the returned channel.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn connect(address: String) -> Receiver<std::io::Result<TcpStream>> {
+        use std::net::ToSocketAddrs;
+
+        // Connect on a separate thread so that name resolution and connection don't stall emulation.
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let result = address.to_socket_addrs().and_then(|mut addrs| {
+                addrs
+                    .next()
+                    .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "Host not found"))
+                    .and_then(|addr| TcpStream::connect_timeout(&addr, MODEM_CONNECT_TIMEOUT))
+            });
+            let _ = sender.send(result);
+        });
+        receiver
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn connect(_address: String) -> Receiver<std::io::Result<TcpStream>> {
+        let (sender, receiver) = mpsc::channel();
+        let _ = sender.send(Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "Sockets are not supported on this platform",
+        )));
+        receiver
+    }
+
+    /// Execute a command line. The line has had the AT prefix removed.
+    fn execute(&mut self, serial: &mut SerialPortController, line: &str) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        // Read a decimal parameter following a command, defaulting to 0.
+        let read_num = |i: &mut usize| -> usize {
+            let mut n = 0usize;
+            while *i < chars.len() && chars[*i].is_ascii_digit() {
+                n = n
+                    .saturating_mul(10)
+                    .saturating_add(chars[*i].to_digit(10).unwrap() as usize);
+                *i += 1;
+            }
+            n
+        };
+
+        while i < chars.len() {
+            let c = chars[i].to_ascii_uppercase();
+            i += 1;
+            match c {
+                ' ' => {}
+                'A' => {
+                    if let Some(inbound) = self.inbound.take() {
+                        self.go_online(serial, inbound);
+                    }
+                    else {
+                        self.respond(serial, ModemResult::NoCarrier);
+                    }
+                    return;
+                }
+                'D' => {
+                    let number: String = chars[i..].iter().collect();
+                    self.dial(serial, &number);
+                    return;
+                }
+                'E' => self.echo = read_num(&mut i) != 0,
+                'V' => self.verbose = read_num(&mut i) != 0,
+                'Q' => self.quiet = read_num(&mut i) != 0,
+                'H' => {
+                    read_num(&mut i);
+                    self.hang_up();
+                }
+                'I' => {
+                    read_num(&mut i);
+                    self.respond_text(serial, "MartyPC Virtual Modem");
+                }
+                'O' => {
+                    read_num(&mut i);
+                    if self.stream.is_some() {
+                        self.mode = ModemMode::Online;
+                        self.respond(serial, ModemResult::Connect);
+                    }
+                    else {
+                        self.respond(serial, ModemResult::NoCarrier);
+                    }
+                    return;
+                }
+                'Z' => {
+                    read_num(&mut i);
+                    self.hang_up();
+                    self.reset_config();
+                }
+                'S' => {
+                    let reg = read_num(&mut i);
+                    if reg >= self.s_regs.len() {
+                        self.respond(serial, ModemResult::Error);
+                        return;
+                    }
+                    if i < chars.len() && chars[i] == '=' {
+                        i += 1;
+                        match u8::try_from(read_num(&mut i)) {
+                            Ok(value) => self.s_regs[reg] = value,
+                            Err(_) => {
+                                self.respond(serial, ModemResult::Error);
+                                return;
+                            }
+                        }
+                    }
+                    else if i < chars.len() && chars[i] == '?' {
+                        i += 1;
+                        self.respond_text(serial, &format!("{:03}", self.s_regs[reg]));
+                    }
+                }
+                '&' => {
+                    if i < chars.len() {
+                        if chars[i].eq_ignore_ascii_case(&'F') {
+                            self.reset_config();
+                        }
+                        i += 1;
+                        read_num(&mut i);
+                    }
+                }
+                'B' | 'L' | 'M' | 'N' | 'P' | 'T' | 'W' | 'X' => {
+                    read_num(&mut i);
+                }
+                _ => {
+                    self.respond(serial, ModemResult::Error);
+                    return;
+                }
+            }
+        }
+        self.respond(serial, ModemResult::Ok);
+    }
+
+    /// Handle a byte sent from the guest while in command mode.
+    fn command_byte(&mut self, serial: &mut SerialPortController, byte: u8) {
+        if self.echo {
+            serial.queue_byte(self.port, byte);
+        }
+
+        if byte == self.s_regs[S_CR_CHAR] {
+            let line = String::from_utf8_lossy(&self.cmd_buf).trim().to_string();
+            self.cmd_buf.clear();
+            if line.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("AT")) {
+                self.execute(serial, &line[2..]);
+            }
+            else if !line.is_empty() {
+                self.respond(serial, ModemResult::Error);
+            }
+        }
+        else if byte == self.s_regs[S_BS_CHAR] {
+            self.cmd_buf.pop();
+        }
+        else if byte != self.s_regs[S_LF_CHAR] && self.cmd_buf.len() < 256 {
+            self.cmd_buf.push(byte);
+        }
+    }
+
+    /// Handle a byte sent from the guest while online, watching for the escape sequence.
+    fn online_byte(&mut self, byte: u8) {
+        let guard_time = self.guard_time_us();
+        if byte == self.s_regs[S_ESCAPE_CHAR] && (self.escape_count > 0 || self.guard_timer >= guard_time) {
+            self.escape_count += 1;
+        }
+        else {
+            self.escape_count = 0;
+        }
+        self.guard_timer = 0.0;
+        self.tx_buf.push(byte);
+    }
+
+    fn poll_network(&mut self, serial: &mut SerialPortController) {
+        // Check for completion of an outbound call
+        if let Some(dialing) = &self.dialing {
+            match dialing.try_recv() {
+                Ok(Ok(stream)) => {
+                    self.dialing = None;
+                    self.go_online(serial, stream);
+                }
+                Ok(Err(e)) => {
+                    log::debug!("Modem: Connection failed: {}", e);
+                    self.dialing = None;
+                    self.respond(serial, ModemResult::NoCarrier);
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => {
+                    self.dialing = None;
+                    self.respond(serial, ModemResult::NoCarrier);
+                }
+            }
+        }
+
+        // Check for an inbound call
+        if let Some(listener) = &self.listener {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    if self.stream.is_some() || self.inbound.is_some() || self.dialing.is_some() {
+                        // Busy
+                        log::debug!("Modem: Rejecting inbound connection from {}: line busy", addr);
+                        let _ = stream.shutdown(std::net::Shutdown::Both);
+                    }
+                    else {
+                        log::debug!("Modem: Inbound connection from {}", addr);
+                        self.inbound = Some(stream);
+                        self.rings = 0;
+                        // Ring immediately.
+                        self.ring_timer = MODEM_RING_INTERVAL;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => log::warn!("Modem: Error accepting connection: {}", e),
+            }
+        }
+
+        // Exchange data with the remote end
+        let mut disconnected = false;
+        if let Some(stream) = &mut self.stream {
+            if !self.tx_buf.is_empty() {
+                match stream.write(&self.tx_buf) {
+                    Ok(n) => {
+                        self.tx_buf.drain(..n);
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => disconnected = true,
+                }
+            }
+
+            if self.mode == ModemMode::Online && serial.rx_queue_len(self.port) < MODEM_RX_HIGH_WATER {
+                let mut buf = [0u8; MODEM_RX_HIGH_WATER];
+                match stream.read(&mut buf) {
+                    Ok(0) => disconnected = true,
+                    Ok(n) => {
+                        for byte in &buf[..n] {
+                            serial.queue_byte(self.port, *byte);
+                        }
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(_) => disconnected = true,
+                }
+            }
+        }
+
+        if disconnected {
+            log::debug!("Modem: Remote end disconnected");
+            self.hang_up();
+            self.tx_buf.clear();
+            self.respond(serial, ModemResult::NoCarrier);
+        }
+    }
+
+    /// Run the modem for the specified number of microseconds
+    pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
+        // Dropping DTR hangs up the modem (&D2 behavior)
+        let dtr = serial.get_dtr(self.port);
+        if self.dtr && !dtr && (self.stream.is_some() || self.dialing.is_some()) {
+            log::debug!("Modem: DTR dropped, hanging up");
+            self.hang_up();
+            self.respond(serial, ModemResult::NoCarrier);
+        }
+        self.dtr = dtr;
+
+        // Process bytes sent by the guest
+        while let Some(byte) = serial.take_tx_byte(self.port) {
+            match self.mode {
+                ModemMode::Command => self.command_byte(serial, byte),
+                ModemMode::Online => self.online_byte(byte),
+            }
+        }
+
+        // Check for escape sequence completion: "+++" followed by the guard time.
+        self.guard_timer += us;
+        if self.mode == ModemMode::Online && self.escape_count >= 3 && self.guard_timer >= self.guard_time_us() {
+            self.escape_count = 0;
+            self.mode = ModemMode::Command;
+            self.respond(serial, ModemResult::Ok);
+        }
+
+        // Ring for an inbound call
+        if self.inbound.is_some() {
+            self.ring_timer += us;
+            if self.ring_timer >= MODEM_RING_INTERVAL {
+                self.ring_timer = 0.0;
+                self.rings += 1;
+                self.s_regs[S_RING_COUNT] = self.rings;
+                if self.rings > MODEM_MAX_RINGS {
+                    log::debug!("Modem: Inbound call not answered");
+                    self.inbound = None;
+                    self.rings = 0;
+                }
+                else {
+                    self.respond(serial, ModemResult::Ring);
+                    let auto_answer = self.s_regs[S_AUTO_ANSWER];
+                    if auto_answer > 0 && self.rings >= auto_answer {
+                        if let Some(inbound) = self.inbound.take() {
+                            self.go_online(serial, inbound);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.poll_timer += us;
+        if self.poll_timer >= MODEM_POLL_INTERVAL {
+            self.poll_timer = 0.0;
+            self.poll_network(serial);
+        }
+
+        // Drive the modem status lines. RI is asserted for the first half of each ring interval.
+        let ringing = self.inbound.is_some() && self.ring_timer < MODEM_RING_INTERVAL / 2.0;
+        serial.set_modem_status_lines(self.port, true, true, ringing, self.stream.is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_line(modem: &mut VirtualModem, serial: &mut SerialPortController, line: &str) -> String {
+        for byte in line.bytes() {
+            modem.command_byte(serial, byte);
+        }
+        modem.command_byte(serial, b'\r');
+        String::from_utf8_lossy(&serial.take_rx_queue(0)).to_string()
+    }
+
+    #[test]
+    fn test_at_commands() {
+        let mut serial = SerialPortController::new(false, false);
+        let mut modem = VirtualModem::new(0, None, None);
+
+        // Commands are echoed by default.
+        assert_eq!(send_line(&mut modem, &mut serial, "AT"), "AT\r\r\nOK\r\n");
+        assert_eq!(send_line(&mut modem, &mut serial, "ate0"), "ate0\r\r\nOK\r\n");
+        assert!(!modem.echo);
+
+        // Several commands on one line, ignored commands, numeric result codes.
+        assert_eq!(send_line(&mut modem, &mut serial, "ATV0 X4 L1 &C1"), "0\r");
+        assert!(!modem.verbose);
+        assert_eq!(send_line(&mut modem, &mut serial, "ATY"), "4\r");
+        assert_eq!(send_line(&mut modem, &mut serial, "HELLO"), "4\r");
+        assert_eq!(send_line(&mut modem, &mut serial, ""), "");
+
+        // Answer and return online without a connection.
+        assert_eq!(send_line(&mut modem, &mut serial, "ATA"), "3\r");
+        assert_eq!(send_line(&mut modem, &mut serial, "ATO"), "3\r");
+        assert_eq!(modem.mode, ModemMode::Command);
+
+        // Backspace removes the last character of the command line.
+        assert_eq!(send_line(&mut modem, &mut serial, "ATQ0\x081"), "");
+        assert!(modem.quiet);
+
+        assert_eq!(send_line(&mut modem, &mut serial, "ATZ"), "\r\nOK\r\n");
+        assert!(modem.echo && modem.verbose && !modem.quiet);
+    }
+
+    #[test]
+    fn test_s_registers() {
+        let mut serial = SerialPortController::new(false, false);
+        let mut modem = VirtualModem::new(0, None, None);
+        modem.echo = false;
+
+        assert_eq!(send_line(&mut modem, &mut serial, "ATS0=2"), "\r\nOK\r\n");
+        assert_eq!(modem.s_regs[S_AUTO_ANSWER], 2);
+        assert_eq!(send_line(&mut modem, &mut serial, "ATS0?"), "\r\n002\r\nOK\r\n");
+
+        // Out of range registers and values are rejected.
+        assert_eq!(send_line(&mut modem, &mut serial, "ATS16=1"), "\r\nERROR\r\n");
+        assert_eq!(send_line(&mut modem, &mut serial, "ATS0=256"), "\r\nERROR\r\n");
+        assert_eq!(modem.s_regs[S_AUTO_ANSWER], 2);
+        assert_eq!(
+            send_line(&mut modem, &mut serial, "ATS99999999999999999999999=1"),
+            "\r\nERROR\r\n"
+        );
+        assert_eq!(
+            send_line(&mut modem, &mut serial, "ATS0=99999999999999999999999"),
+            "\r\nERROR\r\n"
+        );
+
+        // Changing S3 changes the command line terminator.
+        assert_eq!(send_line(&mut modem, &mut serial, "ATS3=33"), "!\nOK!\n");
+        for byte in b"AT&F!" {
+            modem.command_byte(&mut serial, *byte);
+        }
+        assert_eq!(serial.take_rx_queue(0), b"\r\nOK\r\n");
+        assert_eq!(modem.s_regs, S_REGISTER_DEFAULTS);
+    }
+
+    #[test]
+    fn test_escape_sequence() {
+        let mut serial = SerialPortController::new(false, false);
+        let mut modem = VirtualModem::new(0, None, None);
+        let guard_time = modem.guard_time_us();
+        modem.mode = ModemMode::Online;
+
+        // Without a guard time before it, "+++" is passed through as data.
+        modem.online_byte(b'a');
+        for _ in 0..3 {
+            modem.online_byte(b'+');
+        }
+        modem.run(&mut serial, guard_time * 2.0);
+        assert_eq!(modem.mode, ModemMode::Online);
+        assert_eq!(modem.tx_buf, b"a+++");
+
+        // Data following "+++" within the guard time cancels the escape.
+        for _ in 0..3 {
+            modem.online_byte(b'+');
+        }
+        modem.run(&mut serial, guard_time / 2.0);
+        modem.online_byte(b'b');
+        modem.run(&mut serial, guard_time * 2.0);
+        assert_eq!(modem.mode, ModemMode::Online);
+
+        // Guard time, "+++", guard time returns to command mode.
+        for _ in 0..3 {
+            modem.online_byte(b'+');
+        }
+        modem.run(&mut serial, guard_time / 2.0);
+        assert_eq!(modem.mode, ModemMode::Online);
+        modem.run(&mut serial, guard_time / 2.0);
+        assert_eq!(modem.mode, ModemMode::Command);
+        assert_eq!(serial.take_rx_queue(0), b"\r\nOK\r\n");
+    }
+}
@@ -26,12 +26,14 @@
 
    devices::mouse.rs
 
-   Implements a Microsoft Serial Mouse
+   Implements a Microsoft Serial Mouse, and a Mouse Systems compatible mouse
+   using the 5-byte protocol (used by Logitech C-series and other 3-button
+   serial mice).
 
 */
 use std::collections::VecDeque;
 
-use crate::devices::serial::SerialPortController;
+use crate::{devices::serial::SerialPortController, machine_types::SerialMouseType};
 
 // Scale factor for real vs emulated mouse deltas. Need to play with
 // this value until it feels right.
@@ -50,8 +52,15 @@ const MOUSE_UPDATE_RBUTTON: u8 = 0b0001_0000;
 const MOUSE_UPDATE_HO_BITS: u8 = 0b1100_0000;
 const MOUSE_UPDATE_LO_BITS: u8 = 0b0011_1111;
 
+// Mouse Systems 5-byte protocol sync byte. Button bits are active-low.
+const MSYS_SYNC: u8 = 0b1000_0000;
+const MSYS_LBUTTON: u8 = 0b0000_0100;
+const MSYS_MBUTTON: u8 = 0b0000_0010;
+const MSYS_RBUTTON: u8 = 0b0000_0001;
+
 #[allow(dead_code)]
 pub struct Mouse {
+    mouse_type: SerialMouseType,
     updates: VecDeque<MouseUpdate>,
     rts: bool,
     rts_low_timer: f64,
@@ -61,11 +70,13 @@ pub struct Mouse {
 
 pub enum MouseUpdate {
     Update(u8, u8, u8),
+    UpdateFiveByte(u8, u8, u8, u8, u8),
 }
 
 impl Mouse {
-    pub fn new(port: usize) -> Self {
+    pub fn new(mouse_type: SerialMouseType, port: usize) -> Self {
         Self {
+            mouse_type,
             updates: VecDeque::new(),
             rts: false,
             rts_low_timer: 0.0,
@@ -94,6 +105,11 @@ impl Mouse {
         let delta_x_i8 = scaled_x as i8;
         let delta_y_i8 = scaled_y as i8;
 
+        if let SerialMouseType::MouseSystems = self.mouse_type {
+            self.update_mouse_systems(l_button_pressed, r_button_pressed, delta_x_i8, delta_y_i8);
+            return;
+        }
+
         let mut byte1 = MOUSE_UPDATE_STARTBIT;
 
         if l_button_pressed {
@@ -130,13 +146,47 @@ impl Mouse {
         serial.queue_byte(MOUSE_PORT, byte3);*/
     }
 
+    /// Queue a Mouse Systems 5-byte update. The positive Y direction is up in this protocol. The second pair of
+    /// deltas represents movement since the first pair was sampled; we report all movement in the first pair.
+    fn update_mouse_systems(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: i8, delta_y: i8) {
+        let mut byte1 = MSYS_SYNC | MSYS_LBUTTON | MSYS_MBUTTON | MSYS_RBUTTON;
+
+        if l_button_pressed {
+            byte1 &= !MSYS_LBUTTON;
+        }
+        if r_button_pressed {
+            byte1 &= !MSYS_RBUTTON;
+        }
+
+        self.updates.push_back(MouseUpdate::UpdateFiveByte(
+            byte1,
+            delta_x as u8,
+            delta_y.saturating_neg() as u8,
+            0,
+            0,
+        ));
+    }
+
     /// Run the mouse device for the specified number of microseconds
     pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
         // Send a queued update.
-        if let Some(MouseUpdate::Update(byte1, byte2, byte3)) = self.updates.pop_front() {
-            serial.queue_byte(self.port, byte1);
-            serial.queue_byte(self.port, byte2);
-            serial.queue_byte(self.port, byte3);
+        match self.updates.pop_front() {
+            Some(MouseUpdate::Update(byte1, byte2, byte3)) => {
+                serial.queue_byte(self.port, byte1);
+                serial.queue_byte(self.port, byte2);
+                serial.queue_byte(self.port, byte3);
+            }
+            Some(MouseUpdate::UpdateFiveByte(byte1, byte2, byte3, byte4, byte5)) => {
+                for byte in [byte1, byte2, byte3, byte4, byte5] {
+                    serial.queue_byte(self.port, byte);
+                }
+            }
+            None => {}
+        }
+
+        // Mouse Systems mice don't identify themselves on reset.
+        if let SerialMouseType::MouseSystems = self.mouse_type {
+            return;
         }
 
         // Check RTS line for mouse reset
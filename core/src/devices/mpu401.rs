@@ -0,0 +1,174 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::mpu401.rs
+
+   Implements a Roland MPU-401 MIDI interface in UART ("dumb") mode.
+
+   The MPU-401 presents two I/O ports:
+     base+0: Data. Writes in UART mode are sent to the MIDI output.
+     base+1: Write: Command. Read: Status.
+             Status bit 6 is set while the interface is busy.
+             Status bit 7 is clear when a byte is available to read.
+
+   Only the reset (0xFF) and enter UART mode (0x3F) commands are
+   implemented; other intelligent mode commands are acknowledged and
+   ignored. Outgoing MIDI bytes are queued for the frontend to send to a
+   host MIDI device. There is no MIDI input.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    devices::pic::Pic,
+};
+
+pub const MPU401_DEFAULT_IO_BASE: u16 = 0x330;
+pub const MPU401_DEFAULT_IRQ: u8 = 2;
+
+const MPU401_CMD_RESET: u8 = 0xFF;
+const MPU401_CMD_UART_MODE: u8 = 0x3F;
+const MPU401_ACK: u8 = 0xFE;
+
+const MPU401_STATUS_INPUT_EMPTY: u8 = 0b1000_0000;
+
+// Maximum number of MIDI bytes to hold if the frontend isn't draining the output queue.
+const MPU401_MAX_OUTPUT_LEN: usize = 0x10000;
+
+pub struct Mpu401 {
+    io_base: u16,
+    irq: u8,
+    uart_mode: bool,
+    input: VecDeque<u8>,
+    output: VecDeque<u8>,
+    irq_active: bool,
+}
+
+impl Mpu401 {
+    pub fn new(io_base: Option<u16>, irq: Option<u8>) -> Self {
+        Self {
+            io_base: io_base.unwrap_or(MPU401_DEFAULT_IO_BASE),
+            irq: irq.unwrap_or(MPU401_DEFAULT_IRQ),
+            uart_mode: false,
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            irq_active: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.uart_mode = false;
+        self.input.clear();
+    }
+
+    /// Take all MIDI bytes written since the last call.
+    pub fn take_midi_output(&mut self) -> Vec<u8> {
+        self.output.drain(..).collect()
+    }
+
+    fn write_command(&mut self, command: u8) {
+        match command {
+            MPU401_CMD_RESET => {
+                // A reset from UART mode returns to intelligent mode without an acknowledge.
+                let was_uart = self.uart_mode;
+                self.reset();
+                if !was_uart {
+                    self.input.push_back(MPU401_ACK);
+                }
+            }
+            MPU401_CMD_UART_MODE => {
+                if !self.uart_mode {
+                    self.uart_mode = true;
+                    self.input.push_back(MPU401_ACK);
+                }
+            }
+            _ if self.uart_mode => {
+                // Commands other than reset are ignored in UART mode.
+            }
+            _ => {
+                log::debug!("MPU-401: Unsupported intelligent mode command: {:02X}", command);
+                self.input.push_back(MPU401_ACK);
+            }
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if !self.uart_mode {
+            return;
+        }
+        if self.output.len() >= MPU401_MAX_OUTPUT_LEN {
+            self.output.pop_front();
+        }
+        self.output.push_back(data);
+    }
+
+    /// Run the MPU-401, raising its IRQ while there is data to read.
+    pub fn run(&mut self, pic: &mut Pic) {
+        let pending = !self.input.is_empty();
+        if pending && !self.irq_active {
+            pic.request_interrupt(self.irq);
+            self.irq_active = true;
+        }
+        else if !pending && self.irq_active {
+            pic.clear_interrupt(self.irq);
+            self.irq_active = false;
+        }
+    }
+}
+
+impl IoDevice for Mpu401 {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.io_base) {
+            0 => self.input.pop_front().unwrap_or(NO_IO_BYTE),
+            1 => {
+                // The output busy bit is never set, as the interface is always ready to accept a byte.
+                let mut status = 0x3F;
+                if self.input.is_empty() {
+                    status |= MPU401_STATUS_INPUT_EMPTY;
+                }
+                status
+            }
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            0 => self.write_data(data),
+            1 => self.write_command(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            ("MPU-401 Data".to_string(), self.io_base),
+            ("MPU-401 Command/Status".to_string(), self.io_base + 1),
+        ]
+    }
+}
@@ -0,0 +1,503 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::ne2000.rs
+
+   Implements an NE2000 compatible Ethernet card, built around the National
+   DP8390 network interface controller.
+
+   The card occupies 32 I/O ports:
+     base+0x00-0x0F: DP8390 registers, in one of three pages selected by the
+                     command register.
+     base+0x10-0x17: Remote DMA data port, used to move data between the host
+                     and the card's buffer memory.
+     base+0x18-0x1F: Reset port. Reading it resets the card.
+
+   The card's address space holds the station address PROM at 0x0000 and
+   16KB of buffer RAM at 0x4000-0x7FFF. As the 8088 splits word I/O into two
+   byte accesses to consecutive ports, which both land on the data port, the
+   data port doesn't need to distinguish byte and word transfer modes.
+
+   Frames are exchanged with a NetworkBackend. Received frames are only
+   taken from the backend while the receive ring has room for a full sized
+   frame, so a slow guest applies back pressure instead of overflowing.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    devices::pic::Pic,
+    network::{MacAddress, NetworkBackend},
+};
+
+pub const NE2000_DEFAULT_IO_BASE: u16 = 0x300;
+pub const NE2000_DEFAULT_IRQ: u8 = 3;
+// Locally administered address used if none is configured.
+pub const NE2000_DEFAULT_MAC: MacAddress = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const NE2000_DATA_PORT: u16 = 0x10;
+const NE2000_RESET_PORT: u16 = 0x18;
+const NE2000_PORT_COUNT: u16 = 0x20;
+
+const NE2000_RAM_START: usize = 0x4000;
+const NE2000_RAM_SIZE: usize = 0x4000;
+const NE2000_PROM_SIZE: usize = 32;
+
+const MIN_FRAME_LEN: usize = 60;
+const MAX_FRAME_LEN: usize = 1518;
+// Receive ring pages needed for the largest frame and its header.
+const MAX_FRAME_PAGES: usize = (MAX_FRAME_LEN + 4).div_ceil(256);
+
+// Command register bits
+const CR_STP: u8 = 0x01;
+const CR_STA: u8 = 0x02;
+const CR_TXP: u8 = 0x04;
+const CR_RD_MASK: u8 = 0x38;
+const CR_RD_ABORT: u8 = 0x20;
+
+// Interrupt status register bits
+const ISR_PRX: u8 = 0x01;
+const ISR_PTX: u8 = 0x02;
+const ISR_OVW: u8 = 0x10;
+const ISR_RDC: u8 = 0x40;
+const ISR_RST: u8 = 0x80;
+
+// Receive configuration register bits
+const RCR_AB: u8 = 0x04;
+const RCR_AM: u8 = 0x08;
+const RCR_PRO: u8 = 0x10;
+const RCR_MON: u8 = 0x20;
+
+// Transmit configuration register loopback bits
+const TCR_LB_MASK: u8 = 0x06;
+
+// Receive status bits
+const RSR_PRX: u8 = 0x01;
+const RSR_PHY: u8 = 0x20;
+
+// Transmit status bits
+const TSR_PTX: u8 = 0x01;
+
+pub struct Ne2000 {
+    io_base: u16,
+    irq: u8,
+    backend: Option<Box<dyn NetworkBackend>>,
+    prom: [u8; NE2000_PROM_SIZE],
+    ram: Vec<u8>,
+
+    cr: u8,
+    isr: u8,
+    imr: u8,
+    dcr: u8,
+    tcr: u8,
+    rcr: u8,
+    tsr: u8,
+    rsr: u8,
+    pstart: u8,
+    pstop: u8,
+    bnry: u8,
+    curr: u8,
+    tpsr: u8,
+    tbcr: u16,
+    rsar: u16,
+    rbcr: u16,
+    par: MacAddress,
+    mar: [u8; 8],
+
+    irq_active: bool,
+}
+
+impl Ne2000 {
+    pub fn new(
+        io_base: Option<u16>,
+        irq: Option<u8>,
+        mac: Option<MacAddress>,
+        backend: Option<Box<dyn NetworkBackend>>,
+    ) -> Self {
+        let mac = mac.unwrap_or(NE2000_DEFAULT_MAC);
+
+        // The PROM holds the station address followed by the 'WW' signature NE2000 drivers look
+        // for. Each byte is doubled, as the PROM is wired to both halves of the 16-bit bus.
+        let mut prom = [0u8; NE2000_PROM_SIZE];
+        for (i, byte) in mac.iter().enumerate() {
+            prom[i * 2] = *byte;
+            prom[i * 2 + 1] = *byte;
+        }
+        prom[28..32].copy_from_slice(&[0x57; 4]);
+
+        let mut ne2000 = Self {
+            io_base: io_base.unwrap_or(NE2000_DEFAULT_IO_BASE),
+            irq: irq.unwrap_or(NE2000_DEFAULT_IRQ),
+            backend,
+            prom,
+            ram: vec![0; NE2000_RAM_SIZE],
+            cr: 0,
+            isr: 0,
+            imr: 0,
+            dcr: 0,
+            tcr: 0,
+            rcr: 0,
+            tsr: 0,
+            rsr: 0,
+            pstart: 0,
+            pstop: 0,
+            bnry: 0,
+            curr: 0,
+            tpsr: 0,
+            tbcr: 0,
+            rsar: 0,
+            rbcr: 0,
+            par: mac,
+            mar: [0; 8],
+            irq_active: false,
+        };
+        ne2000.reset();
+        ne2000
+    }
+
+    pub fn reset(&mut self) {
+        self.cr = CR_STP | CR_RD_ABORT;
+        self.isr = ISR_RST;
+        self.imr = 0;
+        self.dcr = 0;
+        self.tcr = 0;
+        self.rcr = 0;
+        self.tsr = 0;
+        self.rsr = 0;
+        self.tbcr = 0;
+        self.rsar = 0;
+        self.rbcr = 0;
+    }
+
+    fn stopped(&self) -> bool {
+        self.cr & CR_STP != 0
+    }
+
+    fn page(&self) -> u8 {
+        self.cr >> 6
+    }
+
+    fn mem_read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        if addr < NE2000_RAM_START {
+            self.prom[addr % NE2000_PROM_SIZE]
+        }
+        else if addr < NE2000_RAM_START + NE2000_RAM_SIZE {
+            self.ram[addr - NE2000_RAM_START]
+        }
+        else {
+            0xFF
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        if (NE2000_RAM_START..NE2000_RAM_START + NE2000_RAM_SIZE).contains(&addr) {
+            self.ram[addr - NE2000_RAM_START] = data;
+        }
+    }
+
+    /// Advance the remote DMA address after a data port access. Remote DMA wraps around the
+    /// receive ring so a packet can be read out in one transfer.
+    fn advance_remote_dma(&mut self) {
+        self.rsar = self.rsar.wrapping_add(1);
+        if self.pstop > self.pstart && self.rsar == (self.pstop as u16) << 8 {
+            self.rsar = (self.pstart as u16) << 8;
+        }
+        self.rbcr = self.rbcr.saturating_sub(1);
+        if self.rbcr == 0 {
+            self.isr |= ISR_RDC;
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.rbcr == 0 {
+            return NO_IO_BYTE;
+        }
+        let byte = self.mem_read(self.rsar);
+        self.advance_remote_dma();
+        byte
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if self.rbcr == 0 {
+            return;
+        }
+        self.mem_write(self.rsar, data);
+        self.advance_remote_dma();
+    }
+
+    fn write_command(&mut self, data: u8) {
+        // The page select and remote DMA bits are always latched. STP and STA are exclusive.
+        let mut cr = (self.cr & (CR_STP | CR_STA | CR_TXP)) | (data & 0xF8);
+        if data & CR_STP != 0 {
+            cr = (cr & !CR_STA) | CR_STP;
+            self.isr |= ISR_RST;
+        }
+        else if data & CR_STA != 0 {
+            cr = (cr & !CR_STP) | CR_STA;
+            self.isr &= !ISR_RST;
+        }
+        self.cr = cr;
+
+        if data & CR_RD_MASK == CR_RD_ABORT && self.rbcr == 0 {
+            self.isr |= ISR_RDC;
+        }
+        if data & CR_TXP != 0 && !self.stopped() {
+            self.transmit();
+        }
+    }
+
+    fn transmit(&mut self) {
+        let start = (self.tpsr as u16) << 8;
+        let len = std::cmp::min(self.tbcr as usize, MAX_FRAME_LEN);
+        let frame: Vec<u8> = (0..len).map(|i| self.mem_read(start.wrapping_add(i as u16))).collect();
+
+        if self.tcr & TCR_LB_MASK != 0 {
+            // Loopback mode. The frame is received by the card itself.
+            self.receive(&frame);
+        }
+        else if let Some(backend) = &mut self.backend {
+            backend.send(&frame);
+        }
+
+        self.tsr = TSR_PTX;
+        self.isr |= ISR_PTX;
+        self.cr &= !CR_TXP;
+    }
+
+    /// Returns the number of free pages in the receive ring.
+    fn ring_free_pages(&self) -> usize {
+        let ring_pages = self.pstop.saturating_sub(self.pstart) as usize;
+        if self.curr < self.bnry {
+            (self.bnry - self.curr) as usize
+        }
+        else {
+            ring_pages.saturating_sub((self.curr - self.bnry) as usize)
+        }
+    }
+
+    /// Returns true if the DP8390's address filter accepts a frame sent to `dst`.
+    fn accepts(&self, dst: &[u8]) -> bool {
+        if self.rcr & RCR_PRO != 0 {
+            return true;
+        }
+        if dst == [0xFF; 6] {
+            return self.rcr & RCR_AB != 0;
+        }
+        if dst[0] & 0x01 != 0 {
+            // Multicast. The top six bits of the address CRC select a bit in the hash filter.
+            let bit = (crc32(dst) >> 26) as usize;
+            return self.rcr & RCR_AM != 0 && self.mar[bit >> 3] & (1 << (bit & 7)) != 0;
+        }
+        dst == self.par
+    }
+
+    fn receive(&mut self, frame: &[u8]) {
+        if self.stopped() || self.rcr & RCR_MON != 0 || frame.len() < 6 || !self.accepts(&frame[0..6]) {
+            return;
+        }
+        if self.pstop <= self.pstart || self.curr < self.pstart || self.curr >= self.pstop {
+            return;
+        }
+
+        let len = std::cmp::max(frame.len(), MIN_FRAME_LEN);
+        let total = len + 4;
+        let pages = total.div_ceil(256);
+        if pages >= self.ring_free_pages() {
+            log::trace!("NE2000: receive ring overflow");
+            self.isr |= ISR_OVW;
+            return;
+        }
+
+        let mut next = self.curr as usize + pages;
+        if next >= self.pstop as usize {
+            next -= (self.pstop - self.pstart) as usize;
+        }
+
+        let mut status = RSR_PRX;
+        if frame[0] & 0x01 != 0 {
+            status |= RSR_PHY;
+        }
+
+        let header = [status, next as u8, total as u8, (total >> 8) as u8];
+        let ring_start = (self.pstart as u16) << 8;
+        let ring_end = (self.pstop as u16) << 8;
+        let mut addr = (self.curr as u16) << 8;
+        // Short frames are padded to the minimum frame length.
+        let mut data = frame.to_vec();
+        data.resize(len, 0);
+        for byte in header.into_iter().chain(data) {
+            self.mem_write(addr, byte);
+            addr += 1;
+            if addr == ring_end {
+                addr = ring_start;
+            }
+        }
+
+        self.curr = next as u8;
+        self.rsr = status;
+        self.isr |= ISR_PRX;
+    }
+
+    /// Run the NE2000, receiving frames from the network backend and updating the IRQ line.
+    pub fn run(&mut self, pic: &mut Pic, us: f64) {
+        if let Some(mut backend) = self.backend.take() {
+            // Only accept a frame while there is room for it; otherwise it waits in the backend.
+            // While the card is stopped frames are discarded, as the real card would miss them.
+            if self.stopped() || self.ring_free_pages() > MAX_FRAME_PAGES {
+                if let Some(frame) = backend.poll(us) {
+                    self.receive(&frame);
+                }
+            }
+            self.backend = Some(backend);
+        }
+
+        let pending = self.isr & self.imr & 0x7F != 0;
+        if pending && !self.irq_active {
+            pic.request_interrupt(self.irq);
+            self.irq_active = true;
+        }
+        else if !pending && self.irq_active {
+            pic.clear_interrupt(self.irq);
+            self.irq_active = false;
+        }
+    }
+
+    fn read_register(&mut self, reg: u16) -> u8 {
+        if reg == 0 {
+            return self.cr;
+        }
+        match (self.page(), reg) {
+            (0, 0x03) => self.bnry,
+            (0, 0x04) => self.tsr,
+            (0, 0x07) => self.isr,
+            (0, 0x08) => self.rsar as u8,
+            (0, 0x09) => (self.rsar >> 8) as u8,
+            (0, 0x0C) => self.rsr,
+            (0, 0x0D..=0x0F) => 0, // Tally counters
+            (1, 0x01..=0x06) => self.par[reg as usize - 1],
+            (1, 0x07) => self.curr,
+            (1, 0x08..=0x0F) => self.mar[reg as usize - 8],
+            (2, 0x01) => self.pstart,
+            (2, 0x02) => self.pstop,
+            (2, 0x04) => self.tpsr,
+            (2, 0x0C) => self.rcr | 0xC0,
+            (2, 0x0D) => self.tcr | 0xE0,
+            (2, 0x0E) => self.dcr | 0x80,
+            (2, 0x0F) => self.imr | 0x80,
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_register(&mut self, reg: u16, data: u8) {
+        if reg == 0 {
+            self.write_command(data);
+            return;
+        }
+        match (self.page(), reg) {
+            (0, 0x01) => self.pstart = data,
+            (0, 0x02) => self.pstop = data,
+            (0, 0x03) => self.bnry = data,
+            (0, 0x04) => self.tpsr = data,
+            (0, 0x05) => self.tbcr = (self.tbcr & 0xFF00) | data as u16,
+            (0, 0x06) => self.tbcr = (self.tbcr & 0x00FF) | (data as u16) << 8,
+            // Writing a 1 to an ISR bit acknowledges it. The reset bit can't be cleared this way.
+            (0, 0x07) => self.isr &= !(data & 0x7F),
+            (0, 0x08) => self.rsar = (self.rsar & 0xFF00) | data as u16,
+            (0, 0x09) => self.rsar = (self.rsar & 0x00FF) | (data as u16) << 8,
+            (0, 0x0A) => self.rbcr = (self.rbcr & 0xFF00) | data as u16,
+            (0, 0x0B) => self.rbcr = (self.rbcr & 0x00FF) | (data as u16) << 8,
+            (0, 0x0C) => self.rcr = data & 0x3F,
+            (0, 0x0D) => self.tcr = data & 0x1F,
+            (0, 0x0E) => self.dcr = data & 0x7F,
+            (0, 0x0F) => self.imr = data & 0x7F,
+            (1, 0x01..=0x06) => self.par[reg as usize - 1] = data,
+            (1, 0x07) => self.curr = data,
+            (1, 0x08..=0x0F) => self.mar[reg as usize - 8] = data,
+            _ => {}
+        }
+    }
+}
+
+impl IoDevice for Ne2000 {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.io_base) {
+            reg @ 0x00..=0x0F => self.read_register(reg),
+            0x10..=0x17 => self.read_data(),
+            0x18..=0x1F => {
+                log::debug!("NE2000: Reset");
+                self.reset();
+                NO_IO_BYTE
+            }
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            reg @ 0x00..=0x0F => self.write_register(reg, data),
+            0x10..=0x17 => self.write_data(data),
+            _ => {
+                // Writes to the reset port are ignored; drivers write back the value they read.
+            }
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        (0..NE2000_PORT_COUNT)
+            .map(|offset| {
+                let name = if offset < NE2000_DATA_PORT {
+                    format!("NE2000 Register {:X}h", offset)
+                }
+                else if offset < NE2000_RESET_PORT {
+                    "NE2000 Data".to_string()
+                }
+                else {
+                    "NE2000 Reset".to_string()
+                };
+                (name, self.io_base + offset)
+            })
+            .collect()
+    }
+}
+
+/// The Ethernet CRC-32, as used by the multicast hash filter.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        let mut b = *byte;
+        for _ in 0..8 {
+            let bit = (crc >> 31) ^ (b as u32 & 1);
+            crc <<= 1;
+            if bit != 0 {
+                crc ^= 0x04C1_1DB7;
+            }
+            b >>= 1;
+        }
+    }
+    crc
+}
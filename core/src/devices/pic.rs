@@ -34,7 +34,10 @@
 
 //use std::io::Read;
 
-use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
 
 //pub const PIC_INTERRUPT_OFFSET: u8 = 8;
 
@@ -56,8 +59,17 @@ const ICW4_NESTED: u8 = 0b0001_0000; // Bit on if Fully Nested mode
 
 const OCW_IS_OCW3: u8 = 0b0000_1000; // Bit on if OCW is OCW3
 
+const OCW2_COMMAND_MASK: u8 = 0b1110_0000; // R, SL and EOI bits select the OCW2 command
+const OCW2_LEVEL_MASK: u8 = 0b0000_0111;
 const OCW2_NONSPECIFIC_EOI: u8 = 0b0010_0000;
 const OCW2_SPECIFIC_EOI: u8 = 0b0110_0000;
+const OCW2_ROTATE_NONSPECIFIC_EOI: u8 = 0b1010_0000;
+const OCW2_ROTATE_AEOI_SET: u8 = 0b1000_0000;
+const OCW2_ROTATE_AEOI_CLEAR: u8 = 0b0000_0000;
+const OCW2_ROTATE_SPECIFIC_EOI: u8 = 0b1110_0000;
+const OCW2_SET_PRIORITY: u8 = 0b1100_0000;
+const OCW3_ESMM: u8 = 0b0100_0000; // Enable special mask mode change
+const OCW3_SMM: u8 = 0b0010_0000; // Special mask mode
 const OCW3_POLL_COMMAND: u8 = 0b0000_0100;
 const OCW3_RR_COMMAND: u8 = 0b0000_0011;
 
@@ -116,6 +128,8 @@ pub struct Pic {
     polled: bool,                    // Polled mode
     auto_eoi: bool,                  // Auto-EOI mode
     rotate_on_aeoi: bool,            // Should rotate in Auto-EOI mode
+    special_mask: bool,              // Special mask mode
+    lowest_priority: u8,             // IR with the lowest priority. IR0 is highest priority by default.
     trigger_mode: TriggerMode,
     expecting_icw2: bool,
     expecting_icw4: bool, // ICW3 not supported in Single mode operation
@@ -146,6 +160,8 @@ impl Default for Pic {
             auto_eoi: false,
             trigger_mode: TriggerMode::Edge,
             rotate_on_aeoi: false,
+            special_mask: false,
+            lowest_priority: 7,
             expecting_icw2: false,
             expecting_icw4: false,
             error: false,
@@ -167,6 +183,8 @@ pub struct PicStringState {
     pub intr: String,
     pub autoeoi: String,
     pub trigger_mode: String,
+    pub priority: String,
+    pub special_mask: String,
     pub spurious_irqs: String,
     pub interrupt_stats: Vec<(String, String, String)>,
 }
@@ -213,9 +231,12 @@ impl Pic {
         if byte & ICW1_IS_ICW1 != 0 {
             // Parse Initialization Command Word
             if let InitializationState::Normal = self.init_state {
-                // Reset the IMR & ISR on ICW
+                // Reset the IMR & ISR on ICW. Priority returns to IR0 highest and special mask mode is cleared.
                 self.isr = 0;
                 self.imr = 0;
+                self.lowest_priority = 7;
+                self.special_mask = false;
+                self.rotate_on_aeoi = false;
 
                 log::debug!("PIC: Read ICW1: {:02X}", byte);
             }
@@ -245,13 +266,13 @@ impl Pic {
                 self.expecting_icw4 = true;
             }
         }
-        else if byte & OCW2_NONSPECIFIC_EOI != 0 {
-            self.eoi(None);
-        }
-        else if byte & OCW2_SPECIFIC_EOI != 0 {
-            self.eoi(Some(byte & 0x07));
-        }
         else if byte & OCW_IS_OCW3 != 0 {
+            if byte & OCW3_ESMM != 0 {
+                self.special_mask = byte & OCW3_SMM != 0;
+                log::debug!("PIC: OCW3 Special mask mode: {:?}", self.special_mask);
+            }
+            // A poll command takes precedence over a register read for the next read of the command port.
+            self.polled = byte & OCW3_POLL_COMMAND != 0;
             self.read_select = match byte & OCW3_RR_COMMAND {
                 0b10 => {
                     //log::debug!("PIC: OCW3 Read Selected IRR register");
@@ -265,7 +286,42 @@ impl Pic {
             };
         }
         else {
-            log::trace!("PIC: Unhandled command: {:02X}", byte)
+            self.handle_ocw2(byte);
+        }
+    }
+
+    /// Handle an Operation Command Word 2, which performs EOI and priority rotation commands.
+    fn handle_ocw2(&mut self, byte: u8) {
+        let level = byte & OCW2_LEVEL_MASK;
+        match byte & OCW2_COMMAND_MASK {
+            OCW2_NONSPECIFIC_EOI => {
+                self.eoi(None);
+            }
+            OCW2_SPECIFIC_EOI => {
+                self.eoi(Some(level));
+            }
+            OCW2_ROTATE_NONSPECIFIC_EOI => {
+                if let Some(ir) = self.eoi(None) {
+                    self.lowest_priority = ir;
+                }
+            }
+            OCW2_ROTATE_SPECIFIC_EOI => {
+                self.eoi(Some(level));
+                self.lowest_priority = level;
+            }
+            OCW2_SET_PRIORITY => {
+                log::debug!("PIC: Set lowest priority to IR{}", level);
+                self.lowest_priority = level;
+            }
+            OCW2_ROTATE_AEOI_SET => {
+                self.rotate_on_aeoi = true;
+            }
+            OCW2_ROTATE_AEOI_CLEAR => {
+                self.rotate_on_aeoi = false;
+            }
+            _ => {
+                log::trace!("PIC: Unhandled command: {:02X}", byte)
+            }
         }
     }
 
@@ -273,53 +329,64 @@ impl Pic {
     /// An EOI resets a bit in the ISR.
     /// If an IR number is provided, it will perform a specific EOI and reset a specific bit.
     /// If None is provided, it will perform a non-specific EOI and reset the highest priority bit.
-    pub fn eoi(&mut self, line: Option<u8>) {
-        if let Some(ir) = line {
+    /// Returns the IR that was reset, if any.
+    pub fn eoi(&mut self, line: Option<u8>) -> Option<u8> {
+        let ir = match line {
             // Specific EOI
-
-            self.isr = Pic::clear_bit(self.isr, ir);
-            // Is there a corresponding bit set in the IRR?
-            if Pic::check_bit(self.irr, ir) {
-                // Raise INTR for new interrupt.
-                self.intr = true;
+            Some(ir) => ir,
+            None => {
+                if self.isr == 0 {
+                    return None;
+                }
+                self.get_highest_priority_is()
             }
-        }
-        else {
-            let ir = self.get_highest_priority_is();
+        };
 
-            self.isr = Pic::clear_bit(self.isr, ir);
-            // Is there a corresponding bit set in the IRR?
-            if Pic::check_bit(self.irr, ir) {
-                // Raise INTR for new interrupt.
-                self.intr = true;
-            }
+        self.isr = Pic::clear_bit(self.isr, ir);
+        // Clearing the ISR bit may unblock pending requests of the same or lower priority.
+        if self.calc_intr() {
+            // Raise INTR for new interrupt.
+            self.intr = true;
         }
+        Some(ir)
     }
 
-    pub fn get_highest_priority_ir(&self) -> u8 {
-        let mask: u8 = 0x01;
-        let mut ir = 0;
+    /// Return the IR lines in priority order, from highest to lowest priority.
+    #[inline]
+    fn priority_order(&self) -> impl Iterator<Item = u8> {
+        let lowest = self.lowest_priority;
+        (1..=8).map(move |i| (lowest + i) & 0x07)
+    }
 
-        for i in 0..8 {
-            ir = i;
-            if self.irr & (mask << ir) != 0 {
-                break;
-            }
-        }
-        ir
+    pub fn get_highest_priority_ir(&self) -> u8 {
+        self.priority_order()
+            .find(|&ir| self.irr & (0x01 << ir) != 0)
+            .unwrap_or(self.lowest_priority)
     }
 
     pub fn get_highest_priority_is(&self) -> u8 {
-        let mask: u8 = 0x01;
-        let mut ir = 0;
+        self.priority_order()
+            .find(|&ir| self.isr & (0x01 << ir) != 0)
+            .unwrap_or(self.lowest_priority)
+    }
 
-        for i in 0..8 {
-            ir = i;
-            if self.isr & (mask << ir) != 0 {
-                break;
+    /// Return the highest priority IR with a pending request that is not blocked by an interrupt of
+    /// equal or higher priority in service. In special mask mode, in-service levels do not block lower
+    /// priorities, so that only the IMR selects which levels can interrupt.
+    fn get_pending_ir(&self, respect_imr: bool) -> Option<u8> {
+        for ir in self.priority_order() {
+            let ir_bit = 0x01 << ir;
+            if self.isr & ir_bit != 0 {
+                if self.special_mask {
+                    continue;
+                }
+                return None;
+            }
+            if self.irr & ir_bit != 0 && !(respect_imr && self.imr & ir_bit != 0) {
+                return Some(ir);
             }
         }
-        ir
+        None
     }
 
     pub fn clear_lsb(byte: u8) -> u8 {
@@ -388,6 +455,18 @@ impl Pic {
     }
 
     pub fn handle_command_register_read(&mut self) -> u8 {
+        if self.polled {
+            // A poll acts as an interrupt acknowledge. Bit 7 is set if an interrupt is pending, with the IR level
+            // in bits 0-2.
+            self.polled = false;
+            return match self.get_pending_ir(true) {
+                Some(ir) => {
+                    self.acknowledge(ir);
+                    0x80 | ir
+                }
+                None => 0x00,
+            };
+        }
         match self.read_select {
             ReadSelect::ISR => self.isr,
             ReadSelect::IRR => self.irr,
@@ -437,8 +516,8 @@ impl Pic {
             // If the corresponding bit is set in the IMR, it is masked: do not process right now
             self.interrupt_stats[interrupt as usize].imr_masked_count += 1;
         }
-        else if self.isr & ir_bit != 0 {
-            // If the corresponding bit is set in the ISR, do not process right now
+        else if !self.calc_intr() {
+            // If the corresponding bit or a higher priority bit is set in the ISR, do not process right now
             self.interrupt_stats[interrupt as usize].isr_masked_count += 1;
         }
         else {
@@ -471,8 +550,8 @@ impl Pic {
             // If the corresponding bit is set in the IMR, it is masked: do not process right now
             self.interrupt_stats[interrupt as usize].imr_masked_count += 1;
         }
-        else if self.isr & intr_bit != 0 {
-            // If the corresponding bit is set in the ISR, do not process right now
+        else if !self.calc_intr() {
+            // If the corresponding bit or a higher priority bit is set in the ISR, do not process right now
             self.interrupt_stats[interrupt as usize].isr_masked_count += 1;
         }
         else {
@@ -519,35 +598,9 @@ impl Pic {
 
         // Return the highest priority vector. The mask register does not affect this,
         // as the IMR can be set after INTR asserts.
-        let mut ir_bit: u8 = 0x01;
-        for irq in 0..8 {
-            let have_request = self.irr & ir_bit != 0;
-            let in_service = self.isr & ir_bit != 0;
-
-            // TODO: Can an interrupt vector be delivered while in service? We assume not for now.
-            if have_request && !in_service {
-                // Found the highest priority IRR not in service
-
-                // If in edge triggered mode, clear the bit in the IRR.
-                // The IR line will need to make another low-to-high transition to re-assert the IRR bit.
-                if let TriggerMode::Edge = self.trigger_mode {
-                    self.irr &= !ir_bit;
-                }
-                // Set the bit in the ISR to mark as in service. (This technically occurs during the first INTA pulse.)
-                self.isr |= ir_bit;
-                // If Auto-EOI is enabled, the ISR bit is cleared during the second INTA pulse.
-                if self.auto_eoi {
-                    //log::trace!("Executing Auto-EOI");
-                    self.isr &= !ir_bit;
-                }
-                self.irq = irq;
-
-                // Finally, set INTR line low
-                self.intr = false;
-
-                return Some(irq | self.int_offset);
-            }
-            ir_bit <<= 1;
+        if let Some(irq) = self.get_pending_ir(false) {
+            self.acknowledge(irq);
+            return Some(irq | self.int_offset);
         }
 
         // If no bit in the IRR was found to be set, then a spurious interrupt occurs.
@@ -557,6 +610,31 @@ impl Pic {
         Some(SPURIOUS_INTERRUPT)
     }
 
+    /// Mark the specified IR as in service, as done by an INTA sequence or a poll.
+    fn acknowledge(&mut self, irq: u8) {
+        let ir_bit: u8 = 0x01 << irq;
+
+        // If in edge triggered mode, clear the bit in the IRR.
+        // The IR line will need to make another low-to-high transition to re-assert the IRR bit.
+        if let TriggerMode::Edge = self.trigger_mode {
+            self.irr &= !ir_bit;
+        }
+        // Set the bit in the ISR to mark as in service. (This technically occurs during the first INTA pulse.)
+        self.isr |= ir_bit;
+        // If Auto-EOI is enabled, the ISR bit is cleared during the second INTA pulse.
+        if self.auto_eoi {
+            //log::trace!("Executing Auto-EOI");
+            self.isr &= !ir_bit;
+            if self.rotate_on_aeoi {
+                self.lowest_priority = irq;
+            }
+        }
+        self.irq = irq;
+
+        // Finally, set INTR line low
+        self.intr = false;
+    }
+
     pub fn get_string_state(&self) -> PicStringState {
         let mut state = PicStringState {
             imr: format!("{:08b}", self.imr),
@@ -566,6 +644,8 @@ impl Pic {
             intr: format!("{}", self.intr),
             autoeoi: format!("{:?}", self.auto_eoi),
             trigger_mode: format!("{:?}", self.trigger_mode),
+            priority: format!("IR{} lowest", self.lowest_priority),
+            special_mask: format!("{:?}", self.special_mask),
             spurious_irqs: format!("{}", self.spurious_irqs),
             interrupt_stats: Vec::new(),
         };
@@ -588,19 +668,7 @@ impl Pic {
     /// Calculate the intended INTR line state based on the current state of the PIC.
     #[inline]
     pub fn calc_intr(&self) -> bool {
-        let mut ir_bit: u8 = 0x01;
-        for _irq in 0..8 {
-            let have_request = self.irr & ir_bit != 0;
-            let is_not_masked = self.imr & ir_bit == 0;
-            let is_not_in_service = self.isr & ir_bit == 0;
-
-            if have_request && is_not_masked && is_not_in_service {
-                return true;
-            }
-
-            ir_bit <<= 1;
-        }
-        false
+        self.get_pending_ir(true).is_some()
     }
 
     /// Run the PIC. This is primarily used to effect a delay in raising INTR when the IMR is changed.
@@ -625,3 +693,212 @@ impl Pic {
         }
     }
 }
+
+impl SaveState for Pic {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.init_state {
+            InitializationState::Normal => 0,
+            InitializationState::ExpectingICW2 => 1,
+            InitializationState::ExpectingICW4 => 2,
+        });
+        w.write_u8(self.int_offset);
+        w.write_u8(self.imr);
+        w.write_u8(self.isr);
+        w.write_u8(self.irr);
+        w.write_u8(self.ir);
+        w.write_bool(matches!(self.read_select, ReadSelect::ISR));
+        w.write_u8(self.irq);
+        w.write_bool(self.intr);
+        w.write_bool(self.buffered);
+        w.write_bool(self.nested);
+        w.write_bool(self.special_nested);
+        w.write_bool(self.polled);
+        w.write_bool(self.auto_eoi);
+        w.write_bool(self.rotate_on_aeoi);
+        w.write_bool(self.special_mask);
+        w.write_u8(self.lowest_priority);
+        w.write_bool(self.trigger_mode == TriggerMode::Level);
+        w.write_bool(self.expecting_icw2);
+        w.write_bool(self.expecting_icw4);
+        w.write_bool(self.intr_scheduled);
+        w.write_u32(self.intr_timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.init_state = match r.read_u8()? {
+            0 => InitializationState::Normal,
+            1 => InitializationState::ExpectingICW2,
+            2 => InitializationState::ExpectingICW4,
+            _ => return Err(SaveStateError::InvalidValue("PIC initialization state")),
+        };
+        self.int_offset = r.read_u8()?;
+        self.imr = r.read_u8()?;
+        self.isr = r.read_u8()?;
+        self.irr = r.read_u8()?;
+        self.ir = r.read_u8()?;
+        self.read_select = match r.read_bool()? {
+            true => ReadSelect::ISR,
+            false => ReadSelect::IRR,
+        };
+        self.irq = r.read_u8()?;
+        self.intr = r.read_bool()?;
+        self.buffered = r.read_bool()?;
+        self.nested = r.read_bool()?;
+        self.special_nested = r.read_bool()?;
+        self.polled = r.read_bool()?;
+        self.auto_eoi = r.read_bool()?;
+        self.rotate_on_aeoi = r.read_bool()?;
+        self.special_mask = r.read_bool()?;
+        self.lowest_priority = r.read_u8()?;
+        self.trigger_mode = match r.read_bool()? {
+            true => TriggerMode::Level,
+            false => TriggerMode::Edge,
+        };
+        self.expecting_icw2 = r.read_bool()?;
+        self.expecting_icw4 = r.read_bool()?;
+        self.intr_scheduled = r.read_bool()?;
+        self.intr_timer = r.read_u32()?;
+        self.error = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Initialize a PIC the way the IBM BIOS does: edge triggered, single, vector offset 8, buffered 8086 mode.
+    fn init_pic(icw4: u8) -> Pic {
+        let mut pic = Pic::new();
+        pic.handle_command_register_write(0x13);
+        pic.handle_data_register_write(0x08);
+        pic.handle_data_register_write(icw4);
+        pic.handle_data_register_write(0x00);
+        pic
+    }
+
+    #[test]
+    fn test_ocw2_rotation() {
+        let mut pic = init_pic(0x09);
+
+        pic.request_interrupt(3);
+        pic.request_interrupt(5);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0B));
+
+        // Rotate on non-specific EOI makes IR3 the lowest priority, so IR5 now outranks IR1.
+        pic.handle_command_register_write(0xA0);
+        assert_eq!(pic.isr, 0);
+        assert_eq!(pic.lowest_priority, 3);
+        pic.request_interrupt(1);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0D));
+
+        // Rotate on specific EOI
+        pic.handle_command_register_write(0xE5);
+        assert_eq!(pic.isr, 0);
+        assert_eq!(pic.lowest_priority, 5);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x09));
+        pic.handle_command_register_write(0x20);
+
+        // Set priority returns IR0 to the highest priority
+        pic.handle_command_register_write(0xC7);
+        pic.request_interrupt(0);
+        pic.request_interrupt(6);
+        assert_eq!(pic.get_highest_priority_ir(), 0);
+        // A non-specific EOI clears the highest priority level in service
+        assert_eq!(pic.get_interrupt_vector(), Some(0x08));
+        pic.handle_command_register_write(0x20);
+        assert_eq!(pic.isr, 0);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0E));
+        pic.handle_command_register_write(0x66);
+        assert_eq!(pic.isr, 0);
+
+        // Rotate in automatic EOI mode
+        let mut pic = init_pic(0x0B);
+        pic.handle_command_register_write(0x80);
+        pic.request_interrupt(2);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0A));
+        assert_eq!(pic.isr, 0);
+        assert_eq!(pic.lowest_priority, 2);
+        pic.handle_command_register_write(0x00);
+        pic.request_interrupt(4);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0C));
+        assert_eq!(pic.lowest_priority, 2);
+    }
+
+    #[test]
+    fn test_special_mask_mode() {
+        let mut pic = init_pic(0x09);
+
+        pic.request_interrupt(1);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x09));
+
+        // Normally the level in service blocks lower priority requests
+        pic.request_interrupt(4);
+        assert!(!pic.query_interrupt_line());
+
+        // In special mask mode, masking the level in service lets lower priority levels through
+        pic.handle_command_register_write(0x68);
+        pic.handle_data_register_write(0x02);
+        assert!(pic.calc_intr());
+        pic.run(100);
+        assert!(pic.query_interrupt_line());
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0C));
+        assert_eq!(pic.isr, 0x12);
+
+        // Unmasked levels in service don't block lower priorities either
+        pic.handle_data_register_write(0x00);
+        pic.request_interrupt(6);
+        assert!(pic.query_interrupt_line());
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0E));
+
+        // A level in service is not serviced again while it remains in service
+        pic.request_interrupt(4);
+        assert!(!pic.calc_intr());
+        pic.handle_command_register_write(0x64);
+        assert!(pic.calc_intr());
+        pic.handle_command_register_write(0x66);
+        pic.handle_command_register_write(0x61);
+        assert_eq!(pic.isr, 0);
+
+        // Leaving special mask mode restores normal nesting
+        pic.handle_command_register_write(0x48);
+        assert!(!pic.special_mask);
+        assert_eq!(pic.get_interrupt_vector(), Some(0x0C));
+        pic.request_interrupt(7);
+        assert!(!pic.calc_intr());
+    }
+
+    #[test]
+    fn test_poll() {
+        let mut pic = init_pic(0x09);
+
+        // Poll with nothing pending
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x00);
+
+        // A poll acknowledges the highest priority request
+        pic.request_interrupt(5);
+        pic.request_interrupt(3);
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x83);
+        assert_eq!(pic.isr, 0x08);
+        assert!(!pic.query_interrupt_line());
+
+        // The poll only applies to the next read; following reads return the selected register
+        pic.handle_command_register_write(0x0B);
+        assert_eq!(pic.handle_command_register_read(), 0x08);
+        pic.handle_command_register_write(0x0A);
+        assert_eq!(pic.handle_command_register_read(), 0x20);
+
+        // IR5 is blocked by IR3 in service, then masked
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x00);
+        pic.handle_command_register_write(0x20);
+        pic.handle_data_register_write(0x20);
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x00);
+        pic.handle_data_register_write(0x00);
+        pic.handle_command_register_write(0x0C);
+        assert_eq!(pic.handle_command_register_read(), 0x85);
+    }
+}
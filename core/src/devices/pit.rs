@@ -38,6 +38,7 @@ use modular_bitfield::prelude::*;
 
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::*,
     updatable::*,
 };
@@ -1163,14 +1164,9 @@ impl ProgrammableIntervalTimer {
         //log::trace!("tick(): cycle: {} channel 1 count: {}", self.pit_cycles * 4 + 7, *self.channels[1].counting_element);
 
         if self.do_speaker {
-            let mut speaker_sample = *self.channels[2].output && speaker_data;
-
-            if let ChannelMode::SquareWaveGenerator = *self.channels[2].mode {
-                // Silence speaker if frequency is > 14Khz (approx)
-                if *self.channels[2].count_register <= 170 {
-                    speaker_sample = false;
-                }
-            }
+            // High frequency tones and PWM carriers are passed through unmodified; they are removed
+            // by the speaker filter when resampling to the host audio rate.
+            let speaker_sample = *self.channels[2].output && speaker_data;
 
             // If we have been passed a buffer, fill it with any queued samples
             // and the current sample.
@@ -1334,3 +1330,141 @@ impl ProgrammableIntervalTimer {
         state_vec
     }
 }
+
+impl SaveState for Channel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match *self.mode {
+            ChannelMode::InterruptOnTerminalCount => 0,
+            ChannelMode::HardwareRetriggerableOneShot => 1,
+            ChannelMode::RateGenerator => 2,
+            ChannelMode::SquareWaveGenerator => 3,
+            ChannelMode::SoftwareTriggeredStrobe => 4,
+            ChannelMode::HardwareTriggeredStrobe => 5,
+        });
+        w.write_u8(match *self.rw_mode {
+            RwMode::Lsb => 0,
+            RwMode::Msb => 1,
+            RwMode::LsbMsb => 2,
+        });
+        w.write_u8(match self.channel_state {
+            ChannelState::WaitingForReload => 0,
+            ChannelState::WaitingForGate => 1,
+            ChannelState::DeferLoadCycle => 2,
+            ChannelState::WaitingForLoadCycle => 3,
+            ChannelState::WaitingForLoadTrigger => 4,
+            ChannelState::Counting(ReloadFlag::Normal) => 5,
+            ChannelState::Counting(ReloadFlag::ReloadNextCycle) => 6,
+        });
+        w.write_u32(self.cycles_in_state);
+        w.write_u16(*self.count_register);
+        w.write_bool(self.load_state == LoadState::WaitingForMsb);
+        w.write_bool(self.load_type == LoadType::SubsequentLoad);
+        w.write_u16(self.load_mask);
+        w.write_u16(*self.reload_value);
+        w.write_u16(*self.counting_element);
+        w.write_bool(self.ce_undefined);
+        w.write_bool(self.armed);
+        w.write_bool(self.read_state == ReadState::ReadLsb);
+        w.write_bool(self.count_is_latched);
+        w.write_bool(*self.output);
+        w.write_bool(self.output_on_reload);
+        w.write_bool(self.reload_on_trigger);
+        w.write_u16(*self.output_latch);
+        w.write_bool(self.bcd_mode);
+        w.write_bool(*self.gate);
+        w.write_bool(self.incomplete_reload);
+        w.write_bool(self.ticked);
+        w.write_bool(self.defer_reload_flag);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mode = r.read_u8()?;
+        if mode > 5 {
+            return Err(SaveStateError::InvalidValue("PIT channel mode"));
+        }
+        self.mode.set(ChannelMode::from(mode));
+        self.rw_mode.set(match r.read_u8()? {
+            0 => RwMode::Lsb,
+            1 => RwMode::Msb,
+            2 => RwMode::LsbMsb,
+            _ => return Err(SaveStateError::InvalidValue("PIT rw mode")),
+        });
+        self.channel_state = match r.read_u8()? {
+            0 => ChannelState::WaitingForReload,
+            1 => ChannelState::WaitingForGate,
+            2 => ChannelState::DeferLoadCycle,
+            3 => ChannelState::WaitingForLoadCycle,
+            4 => ChannelState::WaitingForLoadTrigger,
+            5 => ChannelState::Counting(ReloadFlag::Normal),
+            6 => ChannelState::Counting(ReloadFlag::ReloadNextCycle),
+            _ => return Err(SaveStateError::InvalidValue("PIT channel state")),
+        };
+        self.cycles_in_state = r.read_u32()?;
+        self.count_register.set(r.read_u16()?);
+        self.load_state = match r.read_bool()? {
+            true => LoadState::WaitingForMsb,
+            false => LoadState::WaitingForLsb,
+        };
+        self.load_type = match r.read_bool()? {
+            true => LoadType::SubsequentLoad,
+            false => LoadType::InitialLoad,
+        };
+        self.load_mask = r.read_u16()?;
+        self.reload_value.set(r.read_u16()?);
+        self.counting_element.set(r.read_u16()?);
+        self.ce_undefined = r.read_bool()?;
+        self.armed = r.read_bool()?;
+        self.read_state = match r.read_bool()? {
+            true => ReadState::ReadLsb,
+            false => ReadState::NoRead,
+        };
+        self.count_is_latched = r.read_bool()?;
+        self.output.set(r.read_bool()?);
+        self.output_on_reload = r.read_bool()?;
+        self.reload_on_trigger = r.read_bool()?;
+        self.output_latch.set(r.read_u16()?);
+        self.bcd_mode = r.read_bool()?;
+        self.gate.set(r.read_bool()?);
+        self.incomplete_reload = r.read_bool()?;
+        self.ticked = r.read_bool()?;
+        self.defer_reload_flag = r.read_bool()?;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+impl SaveState for ProgrammableIntervalTimer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.pit_cycles);
+        w.write_u32(self.sys_tick_accumulator);
+        w.write_u32(self.sys_ticks_advance);
+        w.write_f64(self.cycle_accumulator);
+        w.write_bool(self.defer_reload_flag);
+        for output in self.last_output_state {
+            w.write_bool(output);
+        }
+        w.write_u8(self.channels.len() as u8);
+        for channel in &self.channels {
+            channel.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pit_cycles = r.read_u64()?;
+        self.sys_tick_accumulator = r.read_u32()?;
+        self.sys_ticks_advance = r.read_u32()?;
+        self.cycle_accumulator = r.read_f64()?;
+        self.defer_reload_flag = r.read_bool()?;
+        for output in self.last_output_state.iter_mut() {
+            *output = r.read_bool()?;
+        }
+        if r.read_u8()? as usize != self.channels.len() {
+            return Err(SaveStateError::SizeMismatch("PIT channels"));
+        }
+        for channel in self.channels.iter_mut() {
+            channel.load_state(r)?;
+        }
+        self.speaker_buf.clear();
+        Ok(())
+    }
+}
@@ -44,8 +44,9 @@ use std::{cell::Cell, collections::BTreeMap};
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
     device_traits::videocard::VideoType,
-    devices::{pic, pit::PitDisplayState},
+    devices::{cassette::Cassette, pic, pit::PitDisplayState},
     machine_types::MachineType,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
     updatable::Updatable,
 };
@@ -90,7 +91,11 @@ pub const PPI_PORT_B: u16 = 0x61;
 pub const PPI_PORT_C: u16 = 0x62;
 pub const PPI_COMMAND_PORT: u16 = 0x63;
 
-pub const KB_RESET_US: f64 = 10_000.0; // Time with clock line pulled low before kb is reset - 10ms
+// Time the clock line must be held low before the keyboard resets - 10ms. The BIOS KBD_RESET routine in the IBM
+// 5150/5160 Technical Reference listings holds the clock low for 20ms ("HOLD KBD CLK LOW FOR 20 MS"), so any threshold
+// below that resets on a BIOS request. 10ms leaves margin for BIOSes with faster delay loops, while shorter low
+// pulses on the clock line don't reset the keyboard.
+pub const KB_RESET_US: f64 = 10_000.0;
 pub const KB_RESET_DELAY_US: f64 = 1000.0; // Delay period between detecting reset and sending reset byte - 1ms
 
 // Dipswitch information from
@@ -105,7 +110,7 @@ pub const SW1_HAS_FLOPPIES: u8 = 0b0000_0000;
 
 // SW2 ON:  8087 NOT installed
 // SW2 OFF: 8087 installed
-pub const SW1_HAVE_8087: u8 = 0b0000_0010;
+pub const SW1_NO_8087: u8 = 0b0000_0010;
 
 // SW4_3: ON,ON: Only bank 0 populated
 // SW4_3: ON, OFF: Only banks 0/1 populated
@@ -194,6 +199,10 @@ pub const PORTB_PULL_KB_LOW: u8 = 0b0100_0000;
 pub const PORTB_KB_CLEAR: u8 = 0b1000_0000;
 pub const PORTB_PRESENT_SW1_PORTA: u8 = 0b1000_0000;
 
+// PC7 and PC6 are the latched RAM parity check and I/O channel check NMI sources on 5150/5160
+pub const PORTC_PARITY_CHECK: u8 = 0b1000_0000;
+pub const PORTC_IO_CHECK: u8 = 0b0100_0000;
+
 pub const PORTC_TANDY_COLOR: u8 = 0b0100_0000;
 pub const PORTC_PCJR_NO_MODEM: u8 = 0b0000_0010;
 
@@ -298,6 +307,11 @@ impl KbSerializer {
         }
     }
 
+    /// Return whether a byte is currently being serialized.
+    pub fn is_busy(&self) -> bool {
+        self.data.is_some()
+    }
+
     pub fn get_bit(&self) -> bool {
         match self.state {
             KbSerializeState::StartBit => self.firsthalf,
@@ -356,6 +370,7 @@ pub struct Ppi {
     kb_do_reset: bool,
     kb_count_until_reset_byte: f64,
     kb_resets_counter: Updatable<u32>,
+    kb_reset_requested: bool,
     port_a_byte: u8,
     port_b_byte: u8,
     kb_byte: Updatable<u8>,
@@ -369,8 +384,11 @@ pub struct Ppi {
     speaker_in: bool,
     jr_kb_in: bool,
     nmi_latch_in: bool,
+    parity_check: bool,
+    io_check: bool,
     kb_serializer: KbSerializer,
     num_floppies: u32,
+    cassette: Option<Cassette>,
 }
 
 impl Default for Ppi {
@@ -392,6 +410,7 @@ impl Default for Ppi {
             kb_do_reset: false,
             kb_count_until_reset_byte: 0.0,
             kb_resets_counter: Updatable::Dirty(0, false),
+            kb_reset_requested: false,
             port_a_byte: 0,
             port_b_byte: 0,
             kb_byte: Updatable::Dirty(0, false),
@@ -405,8 +424,11 @@ impl Default for Ppi {
             speaker_in: false,
             jr_kb_in: false,
             nmi_latch_in: false,
+            parity_check: false,
+            io_check: false,
             kb_serializer: KbSerializer::default(),
             num_floppies: 0,
+            cassette: None,
         }
     }
 }
@@ -448,6 +470,7 @@ impl Ppi {
         mut have_expansion: bool,
         video_types: Vec<VideoType>,
         num_floppies: u32,
+        have_fpu: bool,
     ) -> Self {
         // Creation of the PPI is primarily concerned with setting up the DIP switches.
         let (sw2_ram_dip_bits, sw1_bank_bits) = Ppi::get_ram_dip(machine_type, conventional_mem);
@@ -486,6 +509,8 @@ impl Ppi {
             SW1_HAVE_MDA
         };
 
+        let sw1_fpu_bits = if have_fpu { 0 } else { SW1_NO_8087 };
+
         Self {
             machine_type,
             port_a_mode: match machine_type {
@@ -508,12 +533,14 @@ impl Ppi {
             },
             dip_sw1: match machine_type {
                 MachineType::Ibm5150v64K | MachineType::Ibm5150v256K => {
-                    let dip_sw1 = sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit;
+                    let dip_sw1 =
+                        sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit | sw1_fpu_bits;
                     log::debug!("DIP SW1: {:08b}", dip_sw1);
                     !dip_sw1
                 }
                 MachineType::Ibm5160 => {
-                    let dip_sw1 = sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit;
+                    let dip_sw1 =
+                        sw1_bank_bits | sw1_floppy_ct_bits | sw1_video_bits | sw1_master_floppy_bit | sw1_fpu_bits;
                     log::debug!("DIP SW1: {:08b}", dip_sw1);
                     !dip_sw1
                 }
@@ -525,6 +552,11 @@ impl Ppi {
             },
             dip_sw2: !sw2_ram_dip_bits,
             num_floppies,
+            // Only the 5150 and PCjr have a cassette interface.
+            cassette: match machine_type {
+                MachineType::Ibm5150v64K | MachineType::Ibm5150v256K | MachineType::IbmPCJr => Some(Cassette::new()),
+                _ => None,
+            },
             ..Default::default()
         }
     }
@@ -738,26 +770,61 @@ impl Ppi {
             }
         }
 
+        if let Some(cassette) = &mut self.cassette {
+            cassette.set_motor(byte & PORTB_CASSETTE_MOTOR_OFF == 0);
+        }
+
+        // Setting PB4 or PB5 disables and clears the corresponding NMI check latch.
+        if byte & PORTB_PARITY_MB_EN != 0 {
+            self.parity_check = false;
+        }
+        if byte & PORTB_PARITY_EX_EN != 0 {
+            self.io_check = false;
+        }
+
         // Handle keyboard clock line bit for either 5150 or 5160
         if self.port_b_byte & PORTB_PULL_KB_LOW == 0 {
             //log::trace!("PPI: Pulling keyboard clock LOW");
+            if !self.kb_clock_low {
+                // Start timing a new low period.
+                self.kb_low_count = 0.0;
+            }
             self.kb_clock_low = true;
             self.kb_counting_low = true;
         }
         else if self.kb_clock_low {
             //log::trace!("PPI: Keyboard clock resume HIGH");
             self.kb_clock_low = false;
+            self.kb_counting_low = false;
 
-            if self.kb_low_count > KB_RESET_DELAY_US {
+            if self.kb_low_count >= KB_RESET_US {
                 // Clock line was low long enough to trigger reset
                 // Start timer until reset byte is sent
                 self.kb_low_count = 0.0;
                 self.kb_do_reset = true;
+                self.kb_reset_requested = true;
                 self.kb_count_until_reset_byte = 0.0;
             }
         }
     }
 
+    /// Return whether the keyboard interface can accept another scancode from the keyboard.
+    /// A scancode remains in the shift register until it is cleared by the host, and is not
+    /// overwritten by the next one.
+    pub fn kb_ready(&self) -> bool {
+        match self.machine_type {
+            MachineType::IbmPCJr => !self.kb_serializer.is_busy(),
+            MachineType::Tandy1000 => self.ksr_cleared,
+            _ => self.kb_enabled() && self.ksr_cleared && !self.kb_do_reset,
+        }
+    }
+
+    /// Return whether the host has reset the keyboard by holding the clock line low since the last
+    /// call. The keyboard should discard its buffer; the PPI delivers the self-test result byte.
+    pub fn take_kb_reset(&mut self) -> bool {
+        std::mem::replace(&mut self.kb_reset_requested, false)
+    }
+
     /// Send a byte to the keyboard shift register on PC/XT, or to the serializer on PCjr.
     pub fn send_keyboard(&mut self, byte: u8) {
         match self.machine_type {
@@ -798,8 +865,9 @@ impl Ppi {
             (self.timer_in as u8) << 4
         }
         else {
-            // TODO: Implement cassette data input
-            0
+            self.cassette
+                .as_ref()
+                .map_or(0, |cassette| (cassette.data_in() as u8) << 4)
         };
 
         let speaker_bit = (self.speaker_in as u8) << 4;
@@ -807,23 +875,21 @@ impl Ppi {
 
         match (&self.machine_type, &self.port_c_mode) {
             (MachineType::Ibm5150v64K | MachineType::Ibm5150v256K, PortCMode::Switch2OneToFour) => {
-                // We aren't implementing the cassette on 5150, and we'll never have parity errors
-
-                (self.dip_sw2 & 0x0F) | cassette_bit | timer_bit
+                (self.dip_sw2 & 0x0F) | cassette_bit | timer_bit | self.check_bits()
             }
             (MachineType::Ibm5150v64K | MachineType::Ibm5150v256K, PortCMode::Switch2Five) => {
                 // On 5150, only Switch Block 2, Switch #5 is actually passed through
                 // If Port C is in Switch Block 2 mode, switches 6, 7, 8 and will read high (off)
-                (self.dip_sw2 >> 4 & 0x01) | cassette_bit | timer_bit
+                (self.dip_sw2 >> 4 & 0x01) | cassette_bit | timer_bit | self.check_bits()
             }
             (MachineType::Ibm5160, PortCMode::Switch1OneToFour) => {
                 // Cassette data line has been replaced with a speaker monitor line.
-                (self.dip_sw1 & 0x0F) | speaker_bit | timer_bit
+                (self.dip_sw1 & 0x0F) | speaker_bit | timer_bit | self.check_bits()
             }
             (MachineType::Ibm5160, PortCMode::Switch1FiveToEight) => {
                 // Cassette data line has been replaced with a speaker monitor line.
                 // On 5160, all four switches 5-8 are readable
-                (self.dip_sw1 >> 4 & 0x0F) | speaker_bit | timer_bit
+                (self.dip_sw1 >> 4 & 0x0F) | speaker_bit | timer_bit | self.check_bits()
             }
             (MachineType::Tandy1000, _) => {
                 // Tandy 1000 has no DIP switches
@@ -1013,9 +1079,19 @@ impl Ppi {
     }
 
     pub fn set_pit_output_bit(&mut self, state: bool) {
+        // The output of timer channel 2 is also the cassette data output.
+        if state != self.timer_in {
+            if let Some(cassette) = &mut self.cassette {
+                cassette.data_out_edge();
+            }
+        }
         self.timer_in = state;
     }
 
+    pub fn cassette_mut(&mut self) -> Option<&mut Cassette> {
+        self.cassette.as_mut()
+    }
+
     pub fn set_speaker_bit(&mut self, state: bool) {
         self.speaker_in = state;
     }
@@ -1024,12 +1100,34 @@ impl Ppi {
         self.nmi_latch_in = state;
     }
 
-    /// Return whether NMI generation is enabled
-    pub fn nmi_enabled(&self) -> bool {
-        self.port_b_byte & PORTB_PARITY_MB_EN == 0 || self.port_b_byte & PORTB_PARITY_EX_EN == 0
+    /// Latch a RAM parity error, if parity checking is enabled via PB4.
+    pub fn set_parity_check(&mut self) {
+        if self.port_b_byte & PORTB_PARITY_MB_EN == 0 {
+            self.parity_check = true;
+        }
+    }
+
+    /// Latch an I/O channel check, if enabled via PB5.
+    pub fn set_io_check(&mut self) {
+        if self.port_b_byte & PORTB_PARITY_EX_EN == 0 {
+            self.io_check = true;
+        }
+    }
+
+    /// Return whether either check latch is driving the NMI line.
+    pub fn nmi_source(&self) -> bool {
+        self.parity_check || self.io_check
+    }
+
+    fn check_bits(&self) -> u8 {
+        (if self.parity_check { PORTC_PARITY_CHECK } else { 0 }) | (if self.io_check { PORTC_IO_CHECK } else { 0 })
     }
 
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
+        if let Some(cassette) = &mut self.cassette {
+            cassette.run(us);
+        }
+
         match self.machine_type {
             MachineType::IbmPCJr => {
                 self.kb_serializer.tick(us);
@@ -1047,9 +1145,8 @@ impl Ppi {
                     //log::trace!("PPI: Clearing keyboard");
                 }
 
-                // Keyboard should send a 'aa' byte when clock line is held low (for how long?)
-                // BIOS waits 20ms.
-                // Clock line must go high again
+                // The keyboard sends an 'aa' byte once the clock line has been held low for KB_RESET_US
+                // and goes high again.
                 if self.kb_counting_low && self.kb_low_count < KB_RESET_US {
                     self.kb_low_count += us;
                 }
@@ -1065,6 +1162,7 @@ impl Ppi {
                         self.kb_resets_counter.update((*self.kb_resets_counter).wrapping_add(1));
 
                         log::trace!("PPI: Sending keyboard reset byte");
+                        self.ksr_cleared = false;
                         self.kb_byte.update(0xAA);
 
                         if self.kb_enabled {
@@ -1076,3 +1174,135 @@ impl Ppi {
         }
     }
 }
+
+impl SaveState for KbSerializer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_f64(self.us_accum);
+        w.write_bool(self.data.is_some());
+        w.write_u8(self.data.unwrap_or(0));
+        match self.state {
+            KbSerializeState::Idle => w.write_u8(0),
+            KbSerializeState::StartBit => w.write_u8(1),
+            KbSerializeState::DataBit(bit) => {
+                w.write_u8(2);
+                w.write_u8(bit);
+            }
+            KbSerializeState::ParityBit => w.write_u8(3),
+            KbSerializeState::StopBit => w.write_u8(4),
+        }
+        w.write_bool(self.firsthalf);
+        w.write_u8(self.bit_ct);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.us_accum = r.read_f64()?;
+        let have_data = r.read_bool()?;
+        let data = r.read_u8()?;
+        self.data = have_data.then_some(data);
+        self.state = match r.read_u8()? {
+            0 => KbSerializeState::Idle,
+            1 => KbSerializeState::StartBit,
+            2 => KbSerializeState::DataBit(r.read_u8()?),
+            3 => KbSerializeState::ParityBit,
+            4 => KbSerializeState::StopBit,
+            _ => return Err(SaveStateError::InvalidValue("keyboard serializer state")),
+        };
+        self.firsthalf = r.read_bool()?;
+        self.bit_ct = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Ppi {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.control_word.into_bytes()[0]);
+        w.write_u8(match self.group_a_mode {
+            PpiModeA::Mode0Io => 0,
+            PpiModeA::Mode1StrobedIo => 1,
+            PpiModeA::Mode2BiDirectional => 2,
+            PpiModeA::Mode2BiDirectional2 => 3,
+        });
+        w.write_bool(matches!(self.group_b_mode, PpiModeB::Mode1StrobedIo));
+        w.write_bool(matches!(self.port_a_mode, PortAMode::SwitchBlock1));
+        w.write_u8(match self.port_c_mode {
+            PortCMode::Switch2OneToFour => 0,
+            PortCMode::Switch2Five => 1,
+            PortCMode::Switch1OneToFour => 2,
+            PortCMode::Switch1FiveToEight => 3,
+            PortCMode::Tandy1000 => 4,
+        });
+        w.write_bool(self.kb_clock_low);
+        w.write_bool(self.kb_counting_low);
+        w.write_f64(self.kb_low_count);
+        w.write_bool(self.kb_do_reset);
+        w.write_f64(self.kb_count_until_reset_byte);
+        w.write_bool(self.kb_reset_requested);
+        w.write_u8(self.port_a_byte);
+        w.write_u8(self.port_b_byte);
+        w.write_u8(*self.kb_byte);
+        w.write_u8(*self.kb_byte_last);
+        w.write_bool(self.keyboard_clear_scheduled);
+        w.write_bool(self.ksr_cleared);
+        w.write_bool(self.kb_enabled);
+        w.write_bool(self.timer_in);
+        w.write_bool(self.speaker_in);
+        w.write_bool(self.jr_kb_in);
+        w.write_bool(self.nmi_latch_in);
+        w.write_bool(self.parity_check);
+        w.write_bool(self.io_check);
+        self.kb_serializer.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.control_word = PpiControlWord::from_bytes([r.read_u8()?]);
+        self.group_a_mode = match r.read_u8()? {
+            0 => PpiModeA::Mode0Io,
+            1 => PpiModeA::Mode1StrobedIo,
+            2 => PpiModeA::Mode2BiDirectional,
+            3 => PpiModeA::Mode2BiDirectional2,
+            _ => return Err(SaveStateError::InvalidValue("PPI group A mode")),
+        };
+        self.group_b_mode = match r.read_bool()? {
+            true => PpiModeB::Mode1StrobedIo,
+            false => PpiModeB::Mode0Io,
+        };
+        self.port_a_mode = match r.read_bool()? {
+            true => PortAMode::SwitchBlock1,
+            false => PortAMode::KeyboardByte,
+        };
+        self.port_c_mode = match r.read_u8()? {
+            0 => PortCMode::Switch2OneToFour,
+            1 => PortCMode::Switch2Five,
+            2 => PortCMode::Switch1OneToFour,
+            3 => PortCMode::Switch1FiveToEight,
+            4 => PortCMode::Tandy1000,
+            _ => return Err(SaveStateError::InvalidValue("PPI port C mode")),
+        };
+        self.kb_clock_low = r.read_bool()?;
+        self.kb_counting_low = r.read_bool()?;
+        self.kb_low_count = r.read_f64()?;
+        self.kb_do_reset = r.read_bool()?;
+        self.kb_count_until_reset_byte = r.read_f64()?;
+        self.kb_reset_requested = r.read_bool()?;
+        self.port_a_byte = r.read_u8()?;
+        self.port_b_byte = r.read_u8()?;
+        self.kb_byte.set(r.read_u8()?);
+        self.kb_byte_last.set(r.read_u8()?);
+        self.keyboard_clear_scheduled = r.read_bool()?;
+        self.ksr_cleared = r.read_bool()?;
+        self.kb_enabled = r.read_bool()?;
+        self.timer_in = r.read_bool()?;
+        self.speaker_in = r.read_bool()?;
+        self.jr_kb_in = r.read_bool()?;
+        self.nmi_latch_in = r.read_bool()?;
+        self.parity_check = r.read_bool()?;
+        self.io_check = r.read_bool()?;
+        self.kb_serializer.load_state(r)?;
+
+        // The cassette motor is switched by port B.
+        if let Some(cassette) = &mut self.cassette {
+            cassette.set_motor(self.port_b_byte & PORTB_CASSETTE_MOTOR_OFF == 0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,485 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::printer.rs
+
+   Implements a virtual dot matrix printer that can be attached to a
+   parallel port. Print data is interpreted as a subset of Epson FX-80
+   ESC/P - enough for text with pitch, emphasis, underline and line spacing
+   controls, and 8-pin bit image graphics - and rendered onto a page bitmap.
+
+   Each print job produces a plain text file containing the printable
+   characters received (with form feeds between pages), and one PBM bitmap
+   image per page. A job ends when the printer has been idle for a few
+   seconds.
+
+   Pages are rendered at 120 dpi horizontally and 72 dpi vertically, the
+   native resolution of an FX-80 in double density mode.
+*/
+
+use std::path::PathBuf;
+
+const PRINTER_FONT: &[u8] = include_bytes!("../../../assets/cga_8by8.bin");
+
+// Horizontal positions are tracked in 1/240", vertical positions in 1/216".
+const H_UNITS_PER_INCH: u32 = 240;
+const V_UNITS_PER_INCH: u32 = 216;
+const H_UNITS_PER_PIXEL: u32 = 2;
+const V_UNITS_PER_PIXEL: u32 = 3;
+
+const PAGE_WIDTH: u32 = H_UNITS_PER_INCH * 17 / 2; // 8.5"
+const PAGE_LENGTH: u32 = V_UNITS_PER_INCH * 11; // 11"
+const PAGE_WIDTH_PX: usize = (PAGE_WIDTH / H_UNITS_PER_PIXEL) as usize;
+const PAGE_LENGTH_PX: usize = (PAGE_LENGTH / V_UNITS_PER_PIXEL) as usize;
+
+const PITCH_PICA: u32 = 24; // 10 cpi
+const PITCH_ELITE: u32 = 20; // 12 cpi
+const PITCH_CONDENSED: u32 = 14; // ~17 cpi
+const DEFAULT_LINE_SPACING: u32 = 36; // 1/6"
+
+// Microseconds of inactivity after which the current job is considered finished.
+const JOB_IDLE_TIMEOUT: f64 = 3_000_000.0;
+
+const ESC: u8 = 0x1B;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ParseState {
+    Normal,
+    Escape,
+    Params { cmd: u8, count: usize },
+    Graphics { remaining: usize, column_units: u32 },
+    SkipToNul,
+}
+
+pub struct Printer {
+    output_dir: Option<PathBuf>,
+    state: ParseState,
+    params: Vec<u8>,
+
+    // Formatting
+    pitch: u32,
+    double_width: bool,
+    double_width_line: bool,
+    bold: bool,
+    underline: bool,
+    line_spacing: u32,
+    left_margin: u32,
+    right_margin: u32,
+
+    // Head position
+    x: u32,
+    y: u32,
+
+    // Current job
+    job_active: bool,
+    idle_time: f64,
+    page: Vec<u8>,
+    page_dirty: bool,
+    page_number: usize,
+    job_number: usize,
+    text: String,
+}
+
+impl Printer {
+    pub fn new(output_dir: Option<PathBuf>) -> Self {
+        let mut printer = Self {
+            output_dir,
+            state: ParseState::Normal,
+            params: Vec::new(),
+            pitch: PITCH_PICA,
+            double_width: false,
+            double_width_line: false,
+            bold: false,
+            underline: false,
+            line_spacing: DEFAULT_LINE_SPACING,
+            left_margin: 0,
+            right_margin: PAGE_WIDTH,
+            x: 0,
+            y: 0,
+            job_active: false,
+            idle_time: 0.0,
+            page: vec![0; PAGE_WIDTH_PX * PAGE_LENGTH_PX],
+            page_dirty: false,
+            page_number: 0,
+            job_number: 0,
+            text: String::new(),
+        };
+        printer.initialize();
+        printer
+    }
+
+    pub fn set_output_dir(&mut self, output_dir: PathBuf) {
+        self.output_dir = Some(output_dir);
+    }
+
+    /// Reset formatting state to power-on defaults (ESC @, or the INIT line).
+    pub fn initialize(&mut self) {
+        self.state = ParseState::Normal;
+        self.pitch = PITCH_PICA;
+        self.double_width = false;
+        self.double_width_line = false;
+        self.bold = false;
+        self.underline = false;
+        self.line_spacing = DEFAULT_LINE_SPACING;
+        self.left_margin = 0;
+        self.right_margin = PAGE_WIDTH;
+        self.x = 0;
+    }
+
+    /// Receive a byte strobed in from the parallel port.
+    pub fn feed(&mut self, byte: u8) {
+        self.job_active = true;
+        self.idle_time = 0.0;
+
+        match self.state {
+            ParseState::Normal => self.control_or_char(byte),
+            ParseState::Escape => self.escape(byte),
+            ParseState::Params { cmd, count } => {
+                self.params.push(byte);
+                if self.params.len() >= count {
+                    self.state = ParseState::Normal;
+                    self.command(cmd);
+                }
+            }
+            ParseState::Graphics {
+                remaining,
+                column_units,
+            } => {
+                self.graphics_column(byte, column_units);
+                self.state = if remaining > 1 {
+                    ParseState::Graphics {
+                        remaining: remaining - 1,
+                        column_units,
+                    }
+                }
+                else {
+                    ParseState::Normal
+                };
+            }
+            ParseState::SkipToNul => {
+                if byte == 0 {
+                    self.state = ParseState::Normal;
+                }
+            }
+        }
+    }
+
+    /// Run the printer for the specified number of microseconds, ending the current job after a period
+    /// of inactivity.
+    pub fn run(&mut self, us: f64) {
+        if self.job_active {
+            self.idle_time += us;
+            if self.idle_time > JOB_IDLE_TIMEOUT {
+                self.end_job();
+            }
+        }
+    }
+
+    fn control_or_char(&mut self, byte: u8) {
+        match byte {
+            0x00 | 0x07 | 0x11 | 0x13 | 0x18 | 0x7F => {}
+            0x08 => {
+                // Backspace
+                self.x = self.x.saturating_sub(self.char_width());
+            }
+            0x09 => {
+                // Horizontal tab. Default tab stops every 8 characters.
+                let tab = self.pitch * 8;
+                self.x = (self.x / tab + 1) * tab;
+                self.text.push('\t');
+            }
+            0x0A | 0x0B => self.line_feed(self.line_spacing),
+            0x0C => self.form_feed(),
+            0x0D => self.x = self.left_margin,
+            0x0E => self.double_width_line = true,
+            0x0F => self.pitch = PITCH_CONDENSED,
+            0x12 => self.pitch = PITCH_PICA,
+            0x14 => self.double_width_line = false,
+            ESC => self.state = ParseState::Escape,
+            _ => self.print_char(byte),
+        }
+    }
+
+    fn escape(&mut self, cmd: u8) {
+        self.params.clear();
+        let count = match cmd {
+            b'3' | b'A' | b'J' | b'-' | b'W' | b'C' | b'N' | b'Q' | b'l' | b'S' | b'x' | b'R' | b'U' | b'!' | b'p'
+            | b's' | b'j' | b'i' | b'k' | b'/' | b'q' | b'a' => 1,
+            b'K' | b'L' | b'Y' | b'Z' | b'%' => 2,
+            b'*' | b':' => 3,
+            b'D' | b'B' => {
+                // Tab stop lists are terminated by NUL
+                self.state = ParseState::SkipToNul;
+                return;
+            }
+            _ => 0,
+        };
+
+        if count == 0 {
+            self.state = ParseState::Normal;
+            self.command(cmd);
+        }
+        else {
+            self.state = ParseState::Params { cmd, count };
+        }
+    }
+
+    fn command(&mut self, cmd: u8) {
+        let p0 = self.params.first().copied().unwrap_or(0) as u32;
+        let p1 = self.params.get(1).copied().unwrap_or(0) as u32;
+        let p2 = self.params.get(2).copied().unwrap_or(0) as u32;
+        match cmd {
+            b'@' => self.initialize(),
+            b'E' | b'G' => self.bold = true,
+            b'F' | b'H' => self.bold = false,
+            b'M' => self.pitch = PITCH_ELITE,
+            b'P' => self.pitch = PITCH_PICA,
+            0x0E => self.double_width_line = true,
+            0x0F => self.pitch = PITCH_CONDENSED,
+            b'0' => self.line_spacing = V_UNITS_PER_INCH / 8,
+            b'1' => self.line_spacing = V_UNITS_PER_INCH * 7 / 72,
+            b'2' => self.line_spacing = DEFAULT_LINE_SPACING,
+            b'3' => self.line_spacing = p0,
+            b'A' => self.line_spacing = p0 * V_UNITS_PER_INCH / 72,
+            b'J' => self.line_feed_no_text(p0),
+            b'-' => self.underline = p0 & 0x01 != 0,
+            b'W' => self.double_width = p0 & 0x01 != 0,
+            b'C' => {
+                // ESC C 0 n sets the page length in inches; take another parameter.
+                // We always print on letter size pages, so the page length is ignored.
+                if p0 == 0 && self.params.len() == 1 {
+                    self.state = ParseState::Params { cmd: b'C', count: 2 };
+                }
+            }
+            b'l' => self.left_margin = p0 * self.pitch,
+            b'Q' => self.right_margin = (p0 * self.pitch).min(PAGE_WIDTH),
+            b'!' => {
+                let mode = p0;
+                self.pitch = if mode & 0x04 != 0 {
+                    PITCH_CONDENSED
+                }
+                else if mode & 0x01 != 0 {
+                    PITCH_ELITE
+                }
+                else {
+                    PITCH_PICA
+                };
+                self.bold = mode & 0x18 != 0;
+                self.double_width = mode & 0x20 != 0;
+                self.underline = mode & 0x80 != 0;
+            }
+            b'K' => self.begin_graphics(p0 | p1 << 8, 4),
+            b'L' | b'Y' => self.begin_graphics(p0 | p1 << 8, 2),
+            b'Z' => self.begin_graphics(p0 | p1 << 8, 1),
+            b'*' => {
+                let column_units = match p0 {
+                    0 => 4,
+                    4..=6 => 3,
+                    _ => 2,
+                };
+                self.begin_graphics(p1 | p2 << 8, column_units)
+            }
+            _ => {
+                log::trace!("Printer: Ignoring ESC {:?} {:?}", cmd as char, self.params);
+            }
+        }
+    }
+
+    fn begin_graphics(&mut self, columns: u32, column_units: u32) {
+        if columns > 0 {
+            self.state = ParseState::Graphics {
+                remaining: columns as usize,
+                column_units,
+            };
+        }
+    }
+
+    fn char_width(&self) -> u32 {
+        if self.double_width || self.double_width_line {
+            self.pitch * 2
+        }
+        else {
+            self.pitch
+        }
+    }
+
+    fn set_dot(&mut self, x: u32, y: u32) {
+        let px = (x / H_UNITS_PER_PIXEL) as usize;
+        let py = (y / V_UNITS_PER_PIXEL) as usize;
+        if px < PAGE_WIDTH_PX && py < PAGE_LENGTH_PX {
+            self.page[py * PAGE_WIDTH_PX + px] = 1;
+            self.page_dirty = true;
+        }
+    }
+
+    fn print_char(&mut self, byte: u8) {
+        let width = self.char_width();
+        if self.x + width > self.right_margin {
+            // Wrap to the next line when the right margin is reached.
+            self.x = self.left_margin;
+            self.line_feed(self.line_spacing);
+        }
+
+        let dot_width = if self.double_width || self.double_width_line {
+            2 * H_UNITS_PER_PIXEL
+        }
+        else {
+            H_UNITS_PER_PIXEL
+        };
+
+        if byte != b' ' {
+            let glyph = &PRINTER_FONT[byte as usize * 8..byte as usize * 8 + 8];
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..8 {
+                    if bits & (0x80 >> col) != 0 {
+                        for d in 0..(dot_width / H_UNITS_PER_PIXEL) {
+                            let x = self.x + col * dot_width + d * H_UNITS_PER_PIXEL;
+                            let y = self.y + row as u32 * V_UNITS_PER_PIXEL;
+                            self.set_dot(x, y);
+                            if self.bold {
+                                self.set_dot(x + H_UNITS_PER_PIXEL, y);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if self.underline {
+            for ux in (self.x..self.x + width).step_by(H_UNITS_PER_PIXEL as usize) {
+                self.set_dot(ux, self.y + 8 * V_UNITS_PER_PIXEL);
+            }
+        }
+
+        if (0x20..0x7F).contains(&byte) {
+            self.text.push(byte as char);
+        }
+        else {
+            self.text.push('?');
+        }
+        self.x += width;
+    }
+
+    fn graphics_column(&mut self, byte: u8, column_units: u32) {
+        for pin in 0..8 {
+            if byte & (0x80 >> pin) != 0 {
+                self.set_dot(self.x, self.y + pin * V_UNITS_PER_PIXEL);
+            }
+        }
+        self.x += column_units;
+    }
+
+    fn line_feed_no_text(&mut self, units: u32) {
+        self.y += units;
+        if self.y + DEFAULT_LINE_SPACING > PAGE_LENGTH {
+            self.form_feed();
+        }
+    }
+
+    fn line_feed(&mut self, units: u32) {
+        self.text.push('\n');
+        self.double_width_line = false;
+        self.line_feed_no_text(units);
+    }
+
+    fn form_feed(&mut self) {
+        self.text.push('\x0C');
+        self.eject_page();
+    }
+
+    /// Write out the current page if anything was printed on it, and start a new page.
+    fn eject_page(&mut self) {
+        if self.page_dirty {
+            if self.page_number == 0 {
+                self.job_number = self.next_job_number();
+            }
+            self.page_number += 1;
+            let path = self.job_path(self.job_number, &format!("_p{:02}.pbm", self.page_number));
+            if let Err(e) = std::fs::write(&path, self.page_to_pbm()) {
+                log::error!("Printer: Failed to write page image {:?}: {}", path, e);
+            }
+            else {
+                log::info!("Printer: Wrote page image {:?}", path);
+            }
+        }
+        self.page.fill(0);
+        self.page_dirty = false;
+        self.x = self.left_margin;
+        self.y = 0;
+    }
+
+    fn end_job(&mut self) {
+        self.eject_page();
+        if self.page_number > 0 || !self.text.trim().is_empty() {
+            if self.page_number == 0 {
+                self.job_number = self.next_job_number();
+            }
+            let path = self.job_path(self.job_number, ".txt");
+            if let Err(e) = std::fs::write(&path, self.text.as_bytes()) {
+                log::error!("Printer: Failed to write print job text {:?}: {}", path, e);
+            }
+            else {
+                log::info!("Printer: Wrote print job text {:?}", path);
+            }
+        }
+        self.text.clear();
+        self.page_number = 0;
+        self.job_active = false;
+    }
+
+    fn job_path(&self, job_number: usize, suffix: &str) -> PathBuf {
+        let dir = self.output_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+        dir.join(format!("printjob_{:04}{}", job_number, suffix))
+    }
+
+    /// Find the first job number without an existing output file, so that jobs from previous sessions
+    /// are not overwritten.
+    fn next_job_number(&self) -> usize {
+        let mut number = self.job_number + 1;
+        loop {
+            if !self.job_path(number, ".txt").exists() && !self.job_path(number, "_p01.pbm").exists() {
+                return number;
+            }
+            number += 1;
+        }
+    }
+
+    /// Encode the current page as a binary PBM (P4) image.
+    fn page_to_pbm(&self) -> Vec<u8> {
+        let mut out = format!("P4\n{} {}\n", PAGE_WIDTH_PX, PAGE_LENGTH_PX).into_bytes();
+        for row in self.page.chunks(PAGE_WIDTH_PX) {
+            for bits in row.chunks(8) {
+                let mut byte = 0u8;
+                for (i, dot) in bits.iter().enumerate() {
+                    if *dot != 0 {
+                        byte |= 0x80 >> i;
+                    }
+                }
+                out.push(byte);
+            }
+        }
+        out
+    }
+}
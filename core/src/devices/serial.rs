@@ -27,7 +27,9 @@
     devices::serial.rs
 
     Implements the IBM Asynchronous Communications Adapter based on the
-    INS8250 Serial Controller chip.
+    INS8250 Serial Controller chip. Optionally, the NS16550A may be emulated
+    instead, which adds 16-byte receive and transmit FIFOs and a scratch
+    register.
 
     Two adapters are emulated, a primary and secondary controller.
 
@@ -44,6 +46,7 @@ use std::{
 use crate::{
     bus::{BusInterface, DeviceRunTimeUnit, IoDevice},
     devices::{pic, pit::PitDisplayState},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
     syntax_token::SyntaxToken,
 };
 
@@ -73,6 +76,7 @@ pub const SERIAL1_LINE_CONTROL: u16 = 0x3FB;
 pub const SERIAL1_MODEM_CONTROL: u16 = 0x3FC;
 pub const SERIAL1_LINE_STATUS: u16 = 0x3FD;
 pub const SERIAL1_MODEM_STATUS: u16 = 0x3FE;
+pub const SERIAL1_SCRATCH: u16 = 0x3FF;
 
 pub const SERIAL2_RX_TX_BUFFER: u16 = 0x2F8;
 //pub const SERIAL2_DIVISOR_LATCH_LSB: u16 = 0x2F8;
@@ -83,11 +87,13 @@ pub const SERIAL2_LINE_CONTROL: u16 = 0x2FB;
 pub const SERIAL2_MODEM_CONTROL: u16 = 0x2FC;
 pub const SERIAL2_LINE_STATUS: u16 = 0x2FD;
 pub const SERIAL2_MODEM_STATUS: u16 = 0x2FE;
+pub const SERIAL2_SCRATCH: u16 = 0x2FF;
 
 // Line Control Register constants
 const WORD_LENGTH_SELECT_MASK: u8 = 0b0000_0011;
 const STOP_BIT_SELECT_BIT: u8 = 0b0000_0100;
 const PARITY_ENABLE_BIT: u8 = 0b0000_1000;
+const EVEN_PARITY_SELECT_BIT: u8 = 0b0001_0000;
 const DIVISOR_LATCH_ACCESS_BIT: u8 = 0b1000_0000;
 
 // Line Status Register constants
@@ -100,12 +106,25 @@ const STATUS_TRANSMIT_EMPTY: u8 = 0b0010_0000;
 const STATUS_TRANSMIT_SHIFT_EMPTY: u8 = 0b0100_0000;
 const STATUS_RO_MASK: u8 = 0b1100_0000;
 
-const INTERRUPT_ID_MASK: u8 = 0b0000_1111;
+const INTERRUPT_ID_MASK: u8 = 0b0001_1111;
 
 const INTERRUPT_DATA_AVAIL: u8 = 0b0000_0001;
 const INTERRUPT_TX_EMPTY: u8 = 0b0000_0010;
 const INTERRUPT_RX_LINE_STATUS: u8 = 0b0000_0100;
 const INTERRUPT_MODEM_STATUS: u8 = 0b0000_1000;
+// Not an Interrupt Enable Register bit. The character timeout interrupt is enabled along with Data Available.
+const INTERRUPT_CHAR_TIMEOUT: u8 = 0b0001_0000;
+
+// 16550 FIFO Control Register bits
+const FIFO_CONTROL_ENABLE: u8 = 0b0000_0001;
+const FIFO_CONTROL_RX_RESET: u8 = 0b0000_0010;
+const FIFO_CONTROL_TX_RESET: u8 = 0b0000_0100;
+const FIFO_CONTROL_TRIGGER_MASK: u8 = 0b1100_0000;
+// Interrupt ID Register bits reporting FIFOs enabled
+const INTERRUPT_ID_FIFOS_ENABLED: u8 = 0b1100_0000;
+const FIFO_LEN: usize = 16;
+// The character timeout interrupt fires after 4 character times without FIFO activity.
+const FIFO_TIMEOUT_CHARS: f64 = 4.0;
 
 //const INTERRUPT_PRIORITY_0: u8 = 0b0000_0001;
 //const INTERRUPT_PRIORITY_1: u8 = 0b0000_0010;
@@ -146,6 +165,8 @@ impl IoDevice for SerialPortController {
             SERIAL2_LINE_STATUS => self.port[1].line_status_read(),
             SERIAL1_MODEM_STATUS => self.port[0].modem_status_read(),
             SERIAL2_MODEM_STATUS => self.port[1].modem_status_read(),
+            SERIAL1_SCRATCH if self.fifo_capable => self.port[0].scratch_reg,
+            SERIAL2_SCRATCH if self.fifo_capable => self.port[1].scratch_reg,
             _ => 0,
         }
     }
@@ -156,8 +177,8 @@ impl IoDevice for SerialPortController {
             SERIAL2_RX_TX_BUFFER => self.port[1].tx_buffer_write(byte),
             SERIAL1_INTERRUPT_ENABLE => self.port[0].interrupt_enable_write(byte),
             SERIAL2_INTERRUPT_ENABLE => self.port[1].interrupt_enable_write(byte),
-            SERIAL1_INTERRUPT_ID => {}
-            SERIAL2_INTERRUPT_ID => {}
+            SERIAL1_INTERRUPT_ID => self.port[0].fifo_control_write(byte),
+            SERIAL2_INTERRUPT_ID => self.port[1].fifo_control_write(byte),
             SERIAL1_LINE_CONTROL => self.port[0].line_control_write(byte),
             SERIAL2_LINE_CONTROL => self.port[1].line_control_write(byte),
             SERIAL1_MODEM_CONTROL => self.port[0].modem_control_write(byte),
@@ -166,12 +187,14 @@ impl IoDevice for SerialPortController {
             SERIAL2_LINE_STATUS => self.port[1].line_status_write(byte),
             SERIAL1_MODEM_STATUS => self.port[0].modem_status_write(byte),
             SERIAL2_MODEM_STATUS => self.port[1].modem_status_write(byte),
+            SERIAL1_SCRATCH => self.port[0].scratch_reg = byte,
+            SERIAL2_SCRATCH => self.port[1].scratch_reg = byte,
             _ => {}
         }
     }
 
     fn port_list(&self) -> Vec<(String, u16)> {
-        vec![
+        let mut ports = vec![
             (String::from("SERIAL1 RX/TX Buffer"), SERIAL1_RX_TX_BUFFER),
             (String::from("SERIAL1 Interrupt Enable"), SERIAL1_INTERRUPT_ENABLE),
             (String::from("SERIAL1 Interrupt ID"), SERIAL1_INTERRUPT_ID),
@@ -186,7 +209,12 @@ impl IoDevice for SerialPortController {
             (String::from("SERIAL2 Modem Control"), SERIAL2_MODEM_CONTROL),
             (String::from("SERIAL2 Line Status"), SERIAL2_LINE_STATUS),
             (String::from("SERIAL2 Modem Status"), SERIAL2_MODEM_STATUS),
-        ]
+        ];
+        if self.fifo_capable {
+            ports.push((String::from("SERIAL1 Scratch"), SERIAL1_SCRATCH));
+            ports.push((String::from("SERIAL2 Scratch"), SERIAL2_SCRATCH));
+        }
+        ports
     }
 }
 
@@ -234,6 +262,7 @@ pub struct SerialPort {
     word_length: u8,
     stop_bits: StopBits,
     parity_enable: bool,
+    even_parity: bool,
     divisor_latch_access: bool,
     divisor: u16,
     line_status_reg: u8,
@@ -256,6 +285,18 @@ pub struct SerialPort {
     tx_queue: VecDeque<u8>,
     tx_timer: f64,
     us_per_byte: f64,
+    scratch_reg: u8,
+
+    // 16550 FIFOs
+    fifo_capable: bool,
+    fifo_enabled: bool,
+    fifo_trigger: usize,
+    rx_fifo: VecDeque<u8>,
+    tx_fifo: VecDeque<u8>,
+    rx_timeout_timer: f64,
+
+    // Transmitted bytes are queued for an attached virtual device, such as a modem
+    tx_capture: bool,
 
     // Serial port bridge
     bridge_port_id: Option<usize>,
@@ -272,6 +313,7 @@ impl Default for SerialPort {
             word_length: 8,
             stop_bits: StopBits::One,
             parity_enable: false,
+            even_parity: false,
             divisor_latch_access: false,
             divisor: 12, // 9600 baud
             line_status_reg: STATUS_TRANSMIT_EMPTY,
@@ -294,6 +336,16 @@ impl Default for SerialPort {
             tx_queue: VecDeque::new(),
             tx_timer: 0.0,
             us_per_byte: 833.333, // 9600 baud
+            scratch_reg: 0,
+
+            fifo_capable: false,
+            fifo_enabled: false,
+            fifo_trigger: 1,
+            rx_fifo: VecDeque::with_capacity(FIFO_LEN),
+            tx_fifo: VecDeque::with_capacity(FIFO_LEN),
+            rx_timeout_timer: 0.0,
+
+            tx_capture: false,
 
             bridge_port_id: None,
             bridge_port: None,
@@ -303,39 +355,78 @@ impl Default for SerialPort {
 }
 
 impl SerialPort {
-    pub fn new(name: String, irq: u8, out2_suppresses_int: bool) -> Self {
+    pub fn new(name: String, irq: u8, out2_suppresses_int: bool, fifo_capable: bool) -> Self {
         Self {
             name,
             irq,
             out2_suppresses_int,
+            fifo_capable,
             ..Default::default()
         }
     }
 
     pub fn reset(&mut self) {
+        // Keep any host bridge across a reset.
+        let bridge_port = self.bridge_port.take();
         *self = Self {
             name: self.name.clone(),
             irq: self.irq,
             out2_suppresses_int: self.out2_suppresses_int,
+            fifo_capable: self.fifo_capable,
+            tx_capture: self.tx_capture,
+            bridge_port_id: self.bridge_port_id,
+            bridge_port,
             ..Default::default()
         }
     }
 
     /// Convert the integer divisor value into baud rate
-    fn divisor_to_baud(divisor: u16) -> u16 {
-        return ((SERIAL_CLOCK * 1_000_000.0) / divisor as f64 / 16.0) as u16;
+    fn divisor_to_baud(divisor: u16) -> u32 {
+        return ((SERIAL_CLOCK * 1_000_000.0) / divisor as f64 / 16.0) as u32;
     }
 
     /// Sets the value of us_per_byte, the microsecond delay between sending a byte out of the
     /// Send or receive queue based on the current baud rate.
     /// This function should be called whenever the divisor has changed.
     fn set_timing(&mut self) {
-        if self.divisor < 12 {
-            // Minimum divisor of 12 (9600 baud)
-            self.divisor = 12;
+        // The 8250 is rated to 9600 baud, but the 16550 can run with a divisor of 1 (115200 baud).
+        let min_divisor = if self.fifo_capable { 1 } else { 12 };
+        if self.divisor < min_divisor {
+            self.divisor = min_divisor;
         }
-        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u16;
+        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u32;
         self.us_per_byte = 1.0 / bytes_per_second as f64 * 1_000_000.0;
+        self.update_bridge_settings();
+    }
+
+    /// Apply the current line settings to the bridged host port, if any.
+    fn update_bridge_settings(&mut self) {
+        if let Some(bridge_port) = &mut self.bridge_port {
+            let data_bits = match self.word_length {
+                5 => serialport::DataBits::Five,
+                6 => serialport::DataBits::Six,
+                7 => serialport::DataBits::Seven,
+                _ => serialport::DataBits::Eight,
+            };
+            let stop_bits = match self.stop_bits {
+                StopBits::One => serialport::StopBits::One,
+                _ => serialport::StopBits::Two,
+            };
+            let parity = match (self.parity_enable, self.even_parity) {
+                (false, _) => serialport::Parity::None,
+                (true, false) => serialport::Parity::Odd,
+                (true, true) => serialport::Parity::Even,
+            };
+
+            let result = bridge_port
+                .set_baud_rate(SerialPort::divisor_to_baud(self.divisor))
+                .and_then(|_| bridge_port.set_data_bits(data_bits))
+                .and_then(|_| bridge_port.set_stop_bits(stop_bits))
+                .and_then(|_| bridge_port.set_parity(parity));
+            if let Err(e) = result {
+                log::warn!("{}: Failed to update host port settings: {}", self.name, e);
+            }
+        }
     }
 
     fn line_control_read(&self) -> u8 {
@@ -362,7 +453,9 @@ impl SerialPort {
         };
 
         self.parity_enable = byte & PARITY_ENABLE_BIT != 0;
+        self.even_parity = byte & EVEN_PARITY_SELECT_BIT != 0;
         self.divisor_latch_access = byte & DIVISOR_LATCH_ACCESS_BIT != 0;
+        self.update_bridge_settings();
 
         log::trace!(
             "{}: Write to Line Control Register: {:02X} Word Length: {} Parity: {} Stop Bits: {:?}",
@@ -381,6 +474,18 @@ impl SerialPort {
         if self.divisor_latch_access {
             return (self.divisor & 0xFF) as u8;
         }
+        else if self.fifo_enabled {
+            let byte = self.rx_fifo.pop_front().unwrap_or(0);
+            self.rx_timeout_timer = 0.0;
+            self.lower_interrupt_type(INTERRUPT_CHAR_TIMEOUT);
+            if self.rx_fifo.len() < self.fifo_trigger {
+                self.lower_interrupt_type(INTERRUPT_DATA_AVAIL);
+            }
+            if self.rx_fifo.is_empty() {
+                self.line_status_reg &= !STATUS_DATA_READY;
+            }
+            byte
+        }
         else {
             // Read the byte in the RX buffer
             if !self.rx_was_read {
@@ -422,6 +527,16 @@ impl SerialPort {
                 self.receive(byte);
             }
 
+            if self.fifo_enabled {
+                // Bytes written with a full FIFO are lost.
+                if self.tx_fifo.len() < FIFO_LEN {
+                    self.tx_fifo.push_back(byte);
+                }
+                self.line_status_reg &= !(STATUS_TRANSMIT_EMPTY | STATUS_TRANSMIT_SHIFT_EMPTY);
+                self.lower_interrupt_type(INTERRUPT_TX_EMPTY);
+                return;
+            }
+
             self.tx_count += 1;
             self.tx_holding_reg = byte;
             self.tx_holding_empty = false;
@@ -476,7 +591,7 @@ impl SerialPort {
             self.lower_interrupt_type(INTERRUPT_RX_LINE_STATUS);
         }
         if mask & INTERRUPT_DATA_AVAIL == 0 {
-            self.lower_interrupt_type(INTERRUPT_DATA_AVAIL);
+            self.lower_interrupt_type(INTERRUPT_DATA_AVAIL | INTERRUPT_CHAR_TIMEOUT);
         }
         if mask & INTERRUPT_TX_EMPTY == 0 {
             self.lower_interrupt_type(INTERRUPT_TX_EMPTY);
@@ -549,6 +664,10 @@ impl SerialPort {
     fn calc_irr(&self) -> u8 {
         let mut byte = 0;
 
+        if self.fifo_enabled {
+            byte |= INTERRUPT_ID_FIFOS_ENABLED;
+        }
+
         // Set bit 0 to 1 if interrupt is NOT pending
         if self.interrupts_active & INTERRUPT_ID_MASK == 0 {
             byte |= 1;
@@ -559,6 +678,7 @@ impl SerialPort {
         // Note: Priority does not match the order of bits in the Interrupt Enable register.
         // 0b11 -> Receiver Line Status
         // 0b10 -> Received Data Available
+        // 0b110 -> Character Timeout (16550 only, bit 3 set)
         // 0b01 -> Transmitter Holding Register Empty
         // 0b00 -> Modem Status
         if self.interrupts_active & INTERRUPT_RX_LINE_STATUS != 0 {
@@ -567,6 +687,9 @@ impl SerialPort {
         else if self.interrupts_active & INTERRUPT_DATA_AVAIL != 0 {
             byte |= 2 << 1;
         }
+        else if self.interrupts_active & INTERRUPT_CHAR_TIMEOUT != 0 {
+            byte |= 6 << 1;
+        }
         else if self.interrupts_active & INTERRUPT_TX_EMPTY != 0 {
             byte |= 1 << 1;
         }
@@ -578,6 +701,44 @@ impl SerialPort {
         byte
     }
 
+    /// Handle a write to the FIFO Control Register, which shares an address with the Interrupt ID
+    /// Register. Only the 16550 has this register; writes are ignored on the 8250.
+    fn fifo_control_write(&mut self, byte: u8) {
+        if !self.fifo_capable {
+            return;
+        }
+        log::trace!("{}: Write to FIFO Control Register: {:02X}", self.name, byte);
+
+        let enable = byte & FIFO_CONTROL_ENABLE != 0;
+        if enable != self.fifo_enabled {
+            // Changing FIFO mode clears both FIFOs.
+            self.rx_fifo.clear();
+            self.tx_fifo.clear();
+        }
+        self.fifo_enabled = enable;
+        if !enable {
+            return;
+        }
+
+        if byte & FIFO_CONTROL_RX_RESET != 0 {
+            self.rx_fifo.clear();
+        }
+        if byte & FIFO_CONTROL_TX_RESET != 0 {
+            self.tx_fifo.clear();
+        }
+        if self.rx_fifo.is_empty() {
+            self.line_status_reg &= !STATUS_DATA_READY;
+            self.lower_interrupt_type(INTERRUPT_DATA_AVAIL | INTERRUPT_CHAR_TIMEOUT);
+        }
+
+        self.fifo_trigger = match (byte & FIFO_CONTROL_TRIGGER_MASK) >> 6 {
+            0 => 1,
+            1 => 4,
+            2 => 8,
+            _ => 14,
+        };
+    }
+
     /// Handle reading the Modem Control Register
     fn modem_control_read(&self) -> u8 {
         self.modem_control_reg
@@ -683,6 +844,38 @@ impl SerialPort {
         self.modem_status_reg = byte;
     }
 
+    /// Drive the modem status input lines from an attached device. Changes set the corresponding delta bits
+    /// and raise a modem status interrupt.
+    fn set_modem_status_lines(&mut self, lines: u8) {
+        const LINES_MASK: u8 = MODEM_STATUS_CTS | MODEM_STATUS_DSR | MODEM_STATUS_RI | MODEM_STATUS_RLSD;
+        let lines = lines & LINES_MASK;
+        let old_lines = self.modem_status_reg & LINES_MASK;
+        if lines == old_lines {
+            return;
+        }
+
+        let changed = lines ^ old_lines;
+        let mut delta = 0;
+        if changed & MODEM_STATUS_CTS != 0 {
+            delta |= MODEM_STATUS_DCTS;
+        }
+        if changed & MODEM_STATUS_DSR != 0 {
+            delta |= MODEM_STATUS_DDSR;
+        }
+        if old_lines & MODEM_STATUS_RI != 0 && lines & MODEM_STATUS_RI == 0 {
+            // Trailing edge of the ring indicator
+            delta |= MODEM_STATUS_TERI;
+        }
+        if changed & MODEM_STATUS_RLSD != 0 {
+            delta |= MODEM_STATUS_DRLSD;
+        }
+
+        self.modem_status_reg = (self.modem_status_reg & !LINES_MASK) | lines | delta;
+        if delta != 0 {
+            self.raise_interrupt_type(INTERRUPT_MODEM_STATUS);
+        }
+    }
+
     fn set_modem_status_connected(&mut self) {
         if self.modem_status_reg & MODEM_STATUS_CTS == 0 {
             self.modem_status_reg |= MODEM_STATUS_CTS;
@@ -706,6 +899,22 @@ impl SerialPort {
 
     /// Receive a byte on this port.
     fn receive(&mut self, byte: u8) {
+        if self.fifo_enabled {
+            self.rx_count += 1;
+            self.rx_timeout_timer = 0.0;
+            if self.rx_fifo.len() >= FIFO_LEN {
+                self.overrun();
+                return;
+            }
+            self.rx_fifo.push_back(byte);
+            self.rx_byte = byte;
+            self.line_status_reg |= STATUS_DATA_READY;
+            if self.rx_fifo.len() >= self.fifo_trigger {
+                self.raise_interrupt_type(INTERRUPT_DATA_AVAIL);
+            }
+            return;
+        }
+
         if !self.rx_was_read {
             self.overrun();
         }
@@ -730,8 +939,15 @@ impl SerialPort {
     fn transmit(&mut self, byte: u8) {}
 
     fn raise_interrupt_type(&mut self, interrupt_flag: u8) {
+        let enable_flag = if interrupt_flag == INTERRUPT_CHAR_TIMEOUT {
+            INTERRUPT_DATA_AVAIL
+        }
+        else {
+            interrupt_flag
+        };
+
         // Interrupt enable register completely disables interrupts
-        if interrupt_flag & self.interrupt_enable_reg != 0 {
+        if enable_flag & self.interrupt_enable_reg != 0 {
             self.interrupts_active |= interrupt_flag;
 
             // PC/XT: only raise interrupt if MCR bit 3 is set. out2_suppresses_int will be true.
@@ -774,6 +990,7 @@ impl SerialPort {
                 self.bridge_port = Some(bridge_port);
                 self.bridge_port_id = Some(port_id);
                 self.set_modem_status_connected();
+                self.update_bridge_settings();
                 Ok(true)
             }
             Err(e) => {
@@ -822,6 +1039,20 @@ impl SerialPort {
             "Loopback Active:",
             SyntaxToken::StateString(format!("{}", self.loopback), false, 0),
         );
+        if self.fifo_capable {
+            state.insert(
+                "FIFOs Enabled:",
+                SyntaxToken::StateString(format!("{}", self.fifo_enabled), false, 0),
+            );
+            state.insert(
+                "RX FIFO Level:",
+                SyntaxToken::StateString(format!("{}/{}", self.rx_fifo.len(), self.fifo_trigger), false, 0),
+            );
+            state.insert(
+                "TX FIFO Level:",
+                SyntaxToken::StateString(format!("{}", self.tx_fifo.len()), false, 0),
+            );
+        }
 
         state.insert(
             "RX Last Byte:",
@@ -863,15 +1094,19 @@ impl SerialPort {
 
 pub struct SerialPortController {
     port: [SerialPort; 2],
+    fifo_capable: bool,
 }
 
 impl SerialPortController {
-    pub fn new(out2_suppresses_int: bool) -> Self {
+    /// Create a new serial port controller. If `fifo_capable` is set, NS16550A UARTs are emulated instead of
+    /// the INS8250.
+    pub fn new(out2_suppresses_int: bool, fifo_capable: bool) -> Self {
         Self {
             port: [
-                SerialPort::new("COM1".to_string(), SERIAL1_IRQ, out2_suppresses_int),
-                SerialPort::new("COM2".to_string(), SERIAL2_IRQ, out2_suppresses_int),
+                SerialPort::new("COM1".to_string(), SERIAL1_IRQ, out2_suppresses_int, fifo_capable),
+                SerialPort::new("COM2".to_string(), SERIAL2_IRQ, out2_suppresses_int, fifo_capable),
             ],
+            fifo_capable,
         }
     }
 
@@ -911,7 +1146,6 @@ impl SerialPortController {
     }
 
     /// Get status of the specified serial port's DTR line
-    #[allow(dead_code)]
     pub fn get_dtr(&self, port: usize) -> bool {
         self.port[port].modem_control_reg & MODEM_CONTROL_DTR != 0
     }
@@ -921,11 +1155,63 @@ impl SerialPortController {
         self.port[port].rx_queue.push_back(byte);
     }
 
+    /// Return the number of bytes waiting to be delivered to the specified serial port's RX buffer
+    pub fn rx_queue_len(&self, port: usize) -> usize {
+        self.port[port].rx_queue.len()
+    }
+
+    /// Take all bytes waiting to be delivered to the specified serial port's RX buffer
+    #[cfg(test)]
+    pub(crate) fn take_rx_queue(&mut self, port: usize) -> Vec<u8> {
+        self.port[port].rx_queue.drain(..).collect()
+    }
+
+    /// Set whether bytes transmitted by the specified serial port should be queued for retrieval
+    /// with take_tx_byte(). Used by virtual devices that process guest output.
+    pub fn set_tx_capture(&mut self, port: usize, state: bool) {
+        self.port[port].tx_capture = state;
+    }
+
+    /// Take the next byte transmitted by the specified serial port, if tx capture is enabled and the port
+    /// is not bridged to a host port.
+    pub fn take_tx_byte(&mut self, port: usize) -> Option<u8> {
+        let port = &mut self.port[port];
+        if port.tx_capture && port.bridge_port.is_none() {
+            port.tx_queue.pop_front()
+        }
+        else {
+            None
+        }
+    }
+
+    /// Drive the specified serial port's modem status input lines
+    pub fn set_modem_status_lines(&mut self, port: usize, cts: bool, dsr: bool, ri: bool, dcd: bool) {
+        let mut lines = 0;
+        if cts {
+            lines |= MODEM_STATUS_CTS;
+        }
+        if dsr {
+            lines |= MODEM_STATUS_DSR;
+        }
+        if ri {
+            lines |= MODEM_STATUS_RI;
+        }
+        if dcd {
+            lines |= MODEM_STATUS_RLSD;
+        }
+        self.port[port].set_modem_status_lines(lines);
+    }
+
     /// Bridge the specified serial port
     pub fn bridge_port(&mut self, port: usize, host_port_name: String, host_port_id: usize) -> anyhow::Result<bool> {
         self.port[port].bridge_port(host_port_name, host_port_id)
     }
 
+    /// Returns true if either serial port is bridged to a host serial port.
+    pub fn is_bridged(&self) -> bool {
+        self.port.iter().any(|port| port.bridge_port.is_some())
+    }
+
     /// Run the serial ports for the specified number of microseconds
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
         for port in self.port.iter_mut() {
@@ -955,13 +1241,40 @@ impl SerialPortController {
                 port.rx_timer -= port.us_per_byte;
             }
 
+            // Character timeout for the RX FIFO
+            if port.fifo_enabled && !port.rx_fifo.is_empty() {
+                port.rx_timeout_timer += us;
+                if port.rx_timeout_timer > port.us_per_byte * FIFO_TIMEOUT_CHARS
+                    && port.interrupts_active & INTERRUPT_CHAR_TIMEOUT == 0
+                {
+                    port.raise_interrupt_type(INTERRUPT_CHAR_TIMEOUT);
+                }
+            }
+
             // Transmit byte timer
             port.tx_timer += us;
             while port.tx_timer > port.us_per_byte {
+                if port.fifo_enabled {
+                    // Shift out one byte from the TX FIFO
+                    if let Some(byte) = port.tx_fifo.pop_front() {
+                        if port.bridge_port.is_some() || port.tx_capture {
+                            port.tx_queue.push_back(byte);
+                        }
+                        port.tx_count += 1;
+                        port.tx_holding_reg = byte;
+                        if port.tx_fifo.is_empty() {
+                            port.line_status_reg |= STATUS_TRANSMIT_EMPTY | STATUS_TRANSMIT_SHIFT_EMPTY;
+                            port.raise_interrupt_type(INTERRUPT_TX_EMPTY);
+                        }
+                    }
+                    port.tx_timer -= port.us_per_byte;
+                    continue;
+                }
+
                 // Is there a byte waiting to be sent in the tx holding register?
                 if !port.tx_holding_empty {
                     // If we have bridged this serial port, send the byte to the tx queue
-                    if let Some(_) = &port.bridge_port {
+                    if port.bridge_port.is_some() || port.tx_capture {
                         //log::trace!("{}: Sending byte: {:02X}", port.name, port.tx_holding_reg);
                         port.tx_queue.push_back(port.tx_holding_reg);
                     }
@@ -1033,3 +1346,104 @@ impl SerialPortController {
         }
     }
 }
+
+/// The serial bridge and any bytes waiting to be sent to the host are not saved.
+impl SaveState for SerialPort {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.line_control_reg);
+        w.write_u8(self.word_length);
+        w.write_u8(match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::OneAndAHalf => 1,
+            StopBits::Two => 2,
+        });
+        w.write_bool(self.parity_enable);
+        w.write_bool(self.even_parity);
+        w.write_bool(self.divisor_latch_access);
+        w.write_u16(self.divisor);
+        w.write_u8(self.line_status_reg);
+        w.write_u8(self.interrupts_active);
+        w.write_u8(self.interrupt_enable_reg);
+        w.write_u8(match self.intr_action {
+            IntrAction::None => 0,
+            IntrAction::Raise => 1,
+            IntrAction::Lower => 2,
+        });
+        w.write_u8(self.modem_control_reg);
+        w.write_bool(self.loopback);
+        w.write_u8(self.modem_status_reg);
+        w.write_u8(self.rx_byte);
+        w.write_bool(self.rx_was_read);
+        w.write_u8(self.tx_holding_reg);
+        w.write_bool(self.tx_holding_empty);
+        w.write_bytes(&self.rx_queue.iter().copied().collect::<Vec<u8>>());
+        w.write_f64(self.rx_timer);
+        w.write_f64(self.tx_timer);
+        w.write_f64(self.us_per_byte);
+        w.write_u8(self.scratch_reg);
+        w.write_bool(self.fifo_enabled);
+        w.write_usize(self.fifo_trigger);
+        w.write_bytes(&self.rx_fifo.iter().copied().collect::<Vec<u8>>());
+        w.write_bytes(&self.tx_fifo.iter().copied().collect::<Vec<u8>>());
+        w.write_f64(self.rx_timeout_timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.line_control_reg = r.read_u8()?;
+        self.word_length = r.read_u8()?;
+        self.stop_bits = match r.read_u8()? {
+            0 => StopBits::One,
+            1 => StopBits::OneAndAHalf,
+            2 => StopBits::Two,
+            _ => return Err(SaveStateError::InvalidValue("serial stop bits")),
+        };
+        self.parity_enable = r.read_bool()?;
+        self.even_parity = r.read_bool()?;
+        self.divisor_latch_access = r.read_bool()?;
+        self.divisor = r.read_u16()?;
+        self.line_status_reg = r.read_u8()?;
+        self.interrupts_active = r.read_u8()?;
+        self.interrupt_enable_reg = r.read_u8()?;
+        self.intr_action = match r.read_u8()? {
+            0 => IntrAction::None,
+            1 => IntrAction::Raise,
+            2 => IntrAction::Lower,
+            _ => return Err(SaveStateError::InvalidValue("serial interrupt action")),
+        };
+        self.modem_control_reg = r.read_u8()?;
+        self.loopback = r.read_bool()?;
+        self.modem_status_reg = r.read_u8()?;
+        self.rx_byte = r.read_u8()?;
+        self.rx_was_read = r.read_bool()?;
+        self.tx_holding_reg = r.read_u8()?;
+        self.tx_holding_empty = r.read_bool()?;
+        self.rx_queue = r.read_bytes()?.iter().copied().collect();
+        self.rx_timer = r.read_f64()?;
+        self.tx_timer = r.read_f64()?;
+        self.us_per_byte = r.read_f64()?;
+        self.scratch_reg = r.read_u8()?;
+        let fifo_enabled = r.read_bool()?;
+        self.fifo_enabled = fifo_enabled && self.fifo_capable;
+        self.fifo_trigger = r.read_usize()?;
+        self.rx_fifo = r.read_bytes()?.iter().copied().collect();
+        self.tx_fifo = r.read_bytes()?.iter().copied().collect();
+        self.rx_timeout_timer = r.read_f64()?;
+        self.tx_queue.clear();
+        Ok(())
+    }
+}
+
+impl SaveState for SerialPortController {
+    fn save_state(&self, w: &mut StateWriter) {
+        for port in &self.port {
+            port.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for port in self.port.iter_mut() {
+            port.load_state(r)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::sn76489.rs
+
+   Implements the Texas Instruments SN76489 (SN76496) programmable sound
+   generator found in the IBM PCjr and Tandy 1000.
+
+   The chip has three square wave tone channels and a noise channel, each
+   with a 4-bit attenuator in 2dB steps. It is write-only. A byte with bit 7
+   set latches a channel and register type and writes the low 4 bits of
+   data; a byte with bit 7 clear writes the high 6 bits of a tone period
+   or replaces the low 4 bits of any other register.
+
+*/
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+    sound_mixer::SoundSource,
+};
+
+pub const SN76489_DEFAULT_IO_BASE: u16 = 0xC0;
+// The chip is clocked by the NTSC color burst frequency, and divides it by 16 internally.
+pub const SN76489_CLOCK_MHZ: f64 = 3.579545;
+const SN76489_DIVISOR: f64 = 16.0;
+
+// Rate at which the chip output is averaged into samples for the mixer.
+pub const SN76489_OUTPUT_RATE: f64 = 44_100.0;
+
+const NOISE_LFSR_RESET: u16 = 0x4000;
+const NOISE_FEEDBACK_WHITE: u8 = 0b0000_0100;
+const NOISE_RATE_MASK: u8 = 0b0000_0011;
+
+pub struct Sn76489 {
+    io_base: u16,
+    latched_channel: usize,
+    latched_volume: bool,
+
+    // Channels 0-2 are tone channels, channel 3 is noise.
+    period: [u16; 3],
+    counter: [u16; 4],
+    output: [bool; 4],
+    attenuation: [u8; 4],
+    noise_control: u8,
+    lfsr: u16,
+    volume_table: [f32; 16],
+
+    tick_accum: f64,
+    sample_sum: f32,
+    sample_ticks: u32,
+    sample_accum: f64,
+    source: Option<SoundSource>,
+}
+
+impl Sn76489 {
+    pub fn new(io_base: Option<u16>) -> Self {
+        let mut volume_table = [0.0; 16];
+        for (i, volume) in volume_table.iter_mut().enumerate().take(15) {
+            *volume = 10.0f32.powf(-2.0 * i as f32 / 20.0);
+        }
+
+        Self {
+            io_base: io_base.unwrap_or(SN76489_DEFAULT_IO_BASE),
+            latched_channel: 0,
+            latched_volume: false,
+
+            period: [0; 3],
+            counter: [0; 4],
+            output: [false; 4],
+            // All channels are silent at power on.
+            attenuation: [0x0F; 4],
+            noise_control: 0,
+            lfsr: NOISE_LFSR_RESET,
+            volume_table,
+
+            tick_accum: 0.0,
+            sample_sum: 0.0,
+            sample_ticks: 0,
+            sample_accum: 0.0,
+            source: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let source = self.source.take();
+        *self = Self::new(Some(self.io_base));
+        self.source = source;
+    }
+
+    /// Set the mixer source that output samples are sent to.
+    pub fn set_sound_source(&mut self, source: SoundSource) {
+        self.source = Some(source);
+    }
+
+    fn write(&mut self, data: u8) {
+        if data & 0x80 != 0 {
+            self.latched_channel = ((data >> 5) & 0x03) as usize;
+            self.latched_volume = data & 0x10 != 0;
+            let value = data & 0x0F;
+
+            match (self.latched_channel, self.latched_volume) {
+                (channel, true) => self.attenuation[channel] = value,
+                (3, false) => self.write_noise_control(value),
+                (channel, false) => {
+                    self.period[channel] = (self.period[channel] & 0x3F0) | value as u16;
+                }
+            }
+        }
+        else {
+            match (self.latched_channel, self.latched_volume) {
+                (channel, true) => self.attenuation[channel] = data & 0x0F,
+                (3, false) => self.write_noise_control(data & 0x0F),
+                (channel, false) => {
+                    self.period[channel] = (self.period[channel] & 0x00F) | ((data as u16 & 0x3F) << 4);
+                }
+            }
+        }
+    }
+
+    fn write_noise_control(&mut self, value: u8) {
+        self.noise_control = value & 0x07;
+        // Writing the noise control register resets the shift register.
+        self.lfsr = NOISE_LFSR_RESET;
+    }
+
+    fn shift_noise(&mut self) {
+        let feedback = if self.noise_control & NOISE_FEEDBACK_WHITE != 0 {
+            (self.lfsr ^ (self.lfsr >> 1)) & 0x01
+        }
+        else {
+            self.lfsr & 0x01
+        };
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        self.output[3] = self.lfsr & 0x01 != 0;
+    }
+
+    /// Advance the chip by one tick of the divided clock.
+    fn tick(&mut self) {
+        for channel in 0..3 {
+            if self.counter[channel] > 0 {
+                self.counter[channel] -= 1;
+            }
+            if self.counter[channel] == 0 {
+                // A period of 0 behaves as the maximum period of 0x400.
+                self.counter[channel] = if self.period[channel] == 0 {
+                    0x400
+                }
+                else {
+                    self.period[channel]
+                };
+                self.output[channel] = !self.output[channel];
+
+                if channel == 2 && self.noise_control & NOISE_RATE_MASK == 3 && self.output[2] {
+                    self.shift_noise();
+                }
+            }
+        }
+
+        if self.noise_control & NOISE_RATE_MASK != 3 {
+            if self.counter[3] > 0 {
+                self.counter[3] -= 1;
+            }
+            if self.counter[3] == 0 {
+                self.counter[3] = 0x10 << (self.noise_control & NOISE_RATE_MASK);
+                self.shift_noise();
+            }
+        }
+    }
+
+    /// Return the current mixed output level of all channels.
+    fn level(&self) -> f32 {
+        let mut level = 0.0;
+        for channel in 0..4 {
+            // A tone period of 1 holds the output high, which software uses to play samples by
+            // writing the attenuator directly.
+            let high = if channel < 3 && self.period[channel] == 1 {
+                true
+            }
+            else {
+                self.output[channel]
+            };
+            let volume = self.volume_table[self.attenuation[channel] as usize];
+            level += if high { volume } else { -volume };
+        }
+        level / 4.0
+    }
+
+    /// Run the sound chip for the specified number of microseconds, sending output samples to
+    /// the mixer.
+    pub fn run(&mut self, us: f64) {
+        let ticks_per_us = SN76489_CLOCK_MHZ / SN76489_DIVISOR;
+        let us_per_sample = 1_000_000.0 / SN76489_OUTPUT_RATE;
+
+        self.tick_accum += us * ticks_per_us;
+        while self.tick_accum >= 1.0 {
+            self.tick_accum -= 1.0;
+            self.tick();
+
+            // Average the chip output over each output sample period.
+            self.sample_sum += self.level();
+            self.sample_ticks += 1;
+            self.sample_accum += 1.0 / ticks_per_us;
+            if self.sample_accum >= us_per_sample {
+                self.sample_accum -= us_per_sample;
+                if let Some(source) = &mut self.source {
+                    source.push(self.sample_sum / self.sample_ticks as f32);
+                }
+                self.sample_sum = 0.0;
+                self.sample_ticks = 0;
+            }
+        }
+    }
+}
+
+impl IoDevice for Sn76489 {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // The SN76489 is write-only.
+        NO_IO_BYTE
+    }
+
+    fn write_u8(&mut self, _port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        self.write(data);
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        // The chip is decoded across eight consecutive ports.
+        (0..8)
+            .map(|i| ("SN76489 Sound".to_string(), self.io_base + i))
+            .collect()
+    }
+}
+
+impl SaveState for Sn76489 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_usize(self.latched_channel);
+        w.write_bool(self.latched_volume);
+        for period in self.period {
+            w.write_u16(period);
+        }
+        for i in 0..4 {
+            w.write_u16(self.counter[i]);
+            w.write_bool(self.output[i]);
+            w.write_u8(self.attenuation[i]);
+        }
+        w.write_u8(self.noise_control);
+        w.write_u16(self.lfsr);
+        w.write_f64(self.tick_accum);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.latched_channel = r.read_usize()? & 0x03;
+        self.latched_volume = r.read_bool()?;
+        for period in self.period.iter_mut() {
+            *period = r.read_u16()?;
+        }
+        for i in 0..4 {
+            self.counter[i] = r.read_u16()?;
+            self.output[i] = r.read_bool()?;
+            self.attenuation[i] = r.read_u8()? & 0x0F;
+        }
+        self.noise_control = r.read_u8()?;
+        self.lfsr = r.read_u16()?;
+        self.tick_accum = r.read_f64()?;
+        self.sample_sum = 0.0;
+        self.sample_ticks = 0;
+        self.sample_accum = 0.0;
+        Ok(())
+    }
+}
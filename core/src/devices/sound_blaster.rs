@@ -0,0 +1,466 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   --------------------------------------------------------------------------
+
+   devices::sound_blaster.rs
+
+   Implements a Creative Labs Sound Blaster 2.0 (DSP version 2.01).
+
+   The DSP presents the following I/O ports:
+     base+6: Reset. Writing 1 then 0 resets the DSP, which then returns 0xAA.
+     base+A: Read data.
+     base+C: Write command/data. Reads return the write buffer status;
+             bit 7 is clear when the DSP is ready to accept a byte.
+     base+E: Read buffer status. Bit 7 is set when data is available.
+             Reading this port acknowledges the DSP interrupt.
+
+   8-bit mono playback and recording are supported via DMA in single-cycle,
+   auto-init and high-speed modes, with an interrupt raised at the end of
+   every block. There is no host audio input, so recording returns silence.
+   ADPCM transfers are timed correctly but played back as silence. The FM
+   synthesizer and mixer (SB Pro and later) are not part of this device.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    devices::dma::DMAController,
+    sound_mixer::SoundSource,
+};
+
+pub const SB_DEFAULT_IO_BASE: u16 = 0x220;
+pub const SB_DEFAULT_IRQ: u8 = 7;
+pub const SB_DEFAULT_DMA: u8 = 1;
+
+const DSP_VERSION_MAJOR: u8 = 0x02;
+const DSP_VERSION_MINOR: u8 = 0x01;
+const DSP_RESET_RESPONSE: u8 = 0xAA;
+const DSP_RESET_DELAY_US: f64 = 20.0;
+const DSP_SILENCE: u8 = 0x80;
+
+// Rate at which the DAC output is sampled for the mixer.
+pub const SB_OUTPUT_RATE: f64 = 44_100.0;
+
+const SB_PORT_RESET: u16 = 0x06;
+const SB_PORT_READ_DATA: u16 = 0x0A;
+const SB_PORT_WRITE: u16 = 0x0C;
+const SB_PORT_READ_STATUS: u16 = 0x0E;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DmaMode {
+    Playback,
+    Record,
+    Silence,
+    // ADPCM playback, with the number of samples decoded from each byte.
+    Adpcm(u32),
+}
+
+pub struct SoundBlaster {
+    io_base: u16,
+    irq: u8,
+    dma: u8,
+
+    reset_latch: bool,
+    reset_timer: f64,
+    read_queue: VecDeque<u8>,
+    command: Option<u8>,
+    params: Vec<u8>,
+    test_reg: u8,
+
+    time_constant: u8,
+    block_len: u32,
+    speaker_on: bool,
+    high_speed: bool,
+    dac: u8,
+
+    dma_mode: Option<DmaMode>,
+    dma_auto_init: bool,
+    dma_exit_auto: bool,
+    dma_paused: bool,
+    dma_len: u32,
+    dma_count: u32,
+    dma_sub_sample: u32,
+    sample_accum: f64,
+
+    irq_pending: bool,
+    irq_active: bool,
+    irq_ack: bool,
+
+    output_accum: f64,
+    source: Option<SoundSource>,
+}
+
+impl SoundBlaster {
+    pub fn new(io_base: Option<u16>, irq: Option<u8>, dma: Option<u8>) -> Self {
+        Self {
+            io_base: io_base.unwrap_or(SB_DEFAULT_IO_BASE),
+            irq: irq.unwrap_or(SB_DEFAULT_IRQ),
+            dma: dma.unwrap_or(SB_DEFAULT_DMA),
+
+            reset_latch: false,
+            reset_timer: 0.0,
+            read_queue: VecDeque::new(),
+            command: None,
+            params: Vec::new(),
+            test_reg: 0,
+
+            time_constant: 0,
+            block_len: 0x800,
+            speaker_on: false,
+            high_speed: false,
+            dac: DSP_SILENCE,
+
+            dma_mode: None,
+            dma_auto_init: false,
+            dma_exit_auto: false,
+            dma_paused: false,
+            dma_len: 0,
+            dma_count: 0,
+            dma_sub_sample: 0,
+            sample_accum: 0.0,
+
+            irq_pending: false,
+            irq_active: false,
+            irq_ack: false,
+
+            output_accum: 0.0,
+            source: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let source = self.source.take();
+        *self = Self::new(Some(self.io_base), Some(self.irq), Some(self.dma));
+        self.source = source;
+    }
+
+    /// Set the mixer source that output samples are sent to.
+    pub fn set_sound_source(&mut self, source: SoundSource) {
+        self.source = Some(source);
+    }
+
+    /// The DSP sample rate in Hz, as set by the time constant.
+    fn sample_rate(&self) -> f64 {
+        1_000_000.0 / (256 - self.time_constant as u32) as f64
+    }
+
+    fn dsp_reset(&mut self) {
+        self.read_queue.clear();
+        self.command = None;
+        self.params.clear();
+        self.speaker_on = false;
+        self.high_speed = false;
+        self.dac = DSP_SILENCE;
+        self.dma_mode = None;
+        self.dma_paused = false;
+        self.irq_pending = false;
+        self.irq_ack = self.irq_active;
+    }
+
+    /// Number of parameter bytes the specified DSP command takes.
+    fn param_count(command: u8) -> usize {
+        match command {
+            0x10 | 0x38 | 0x40 | 0xE0 | 0xE4 => 1,
+            0x14 | 0x16 | 0x17 | 0x24 | 0x48 | 0x74..=0x77 | 0x80 => 2,
+            _ => 0,
+        }
+    }
+
+    fn write_dsp(&mut self, data: u8) {
+        // In high-speed mode the DSP ignores all commands until it is reset.
+        if self.high_speed {
+            return;
+        }
+
+        match self.command {
+            None => {
+                self.command = Some(data);
+                self.params.clear();
+            }
+            Some(_) => self.params.push(data),
+        }
+
+        let command = self.command.unwrap();
+        if self.params.len() >= Self::param_count(command) {
+            self.command = None;
+            self.execute(command);
+        }
+    }
+
+    /// Return the 16-bit length parameter of the current command, in bytes.
+    fn param_length(&self) -> u32 {
+        (self.params[0] as u32 | (self.params[1] as u32) << 8) + 1
+    }
+
+    fn start_dma(&mut self, mode: DmaMode, auto_init: bool, len: u32) {
+        log::debug!(
+            "SB: Starting {:?} DMA, auto-init: {}, length: {}, rate: {:.0}Hz",
+            mode,
+            auto_init,
+            len,
+            self.sample_rate()
+        );
+        self.dma_mode = Some(mode);
+        self.dma_auto_init = auto_init;
+        self.dma_exit_auto = false;
+        self.dma_paused = false;
+        self.dma_len = len;
+        self.dma_count = len;
+        self.dma_sub_sample = 0;
+        self.sample_accum = 0.0;
+    }
+
+    fn execute(&mut self, command: u8) {
+        match command {
+            // Direct DAC output
+            0x10 => self.dac = self.params[0],
+            // 8-bit single-cycle DMA DAC
+            0x14 => self.start_dma(DmaMode::Playback, false, self.param_length()),
+            // 2-bit ADPCM single-cycle DMA DAC, optionally with reference byte
+            0x16 | 0x17 => self.start_dma(DmaMode::Adpcm(4), false, self.param_length()),
+            // 8-bit auto-init DMA DAC
+            0x1C => self.start_dma(DmaMode::Playback, true, self.block_len),
+            // 2-bit ADPCM auto-init DMA DAC
+            0x1F => self.start_dma(DmaMode::Adpcm(4), true, self.block_len),
+            // Direct ADC input
+            0x20 => self.read_queue.push_back(DSP_SILENCE),
+            // 8-bit single-cycle DMA ADC
+            0x24 => self.start_dma(DmaMode::Record, false, self.param_length()),
+            // 8-bit auto-init DMA ADC
+            0x2C => self.start_dma(DmaMode::Record, true, self.block_len),
+            // MIDI read and write. There is no MIDI device attached, so output is discarded.
+            0x30..=0x38 => {}
+            // Set time constant
+            0x40 => self.time_constant = self.params[0],
+            // Set block transfer size
+            0x48 => self.block_len = self.param_length(),
+            // 4-bit and 2.6-bit ADPCM single-cycle DMA DAC
+            0x74 | 0x75 => self.start_dma(DmaMode::Adpcm(2), false, self.param_length()),
+            0x76 | 0x77 => self.start_dma(DmaMode::Adpcm(3), false, self.param_length()),
+            // 4-bit and 2.6-bit ADPCM auto-init DMA DAC
+            0x7D => self.start_dma(DmaMode::Adpcm(2), true, self.block_len),
+            0x7F => self.start_dma(DmaMode::Adpcm(3), true, self.block_len),
+            // Silence DAC for the specified number of samples
+            0x80 => self.start_dma(DmaMode::Silence, false, self.param_length()),
+            // High-speed 8-bit DMA DAC, auto-init and single-cycle
+            0x90 | 0x91 => {
+                self.start_dma(DmaMode::Playback, command == 0x90, self.block_len);
+                self.high_speed = true;
+            }
+            // High-speed 8-bit DMA ADC, auto-init and single-cycle
+            0x98 | 0x99 => {
+                self.start_dma(DmaMode::Record, command == 0x98, self.block_len);
+                self.high_speed = true;
+            }
+            // Halt DMA
+            0xD0 => self.dma_paused = true,
+            // Speaker on / off
+            0xD1 => self.speaker_on = true,
+            0xD3 => self.speaker_on = false,
+            // Continue DMA
+            0xD4 => self.dma_paused = false,
+            // Speaker status
+            0xD8 => self.read_queue.push_back(if self.speaker_on { 0xFF } else { 0x00 }),
+            // Exit auto-init DMA at the end of the current block
+            0xDA => self.dma_exit_auto = true,
+            // DSP identification
+            0xE0 => self.read_queue.push_back(!self.params[0]),
+            // Get DSP version
+            0xE1 => {
+                self.read_queue.push_back(DSP_VERSION_MAJOR);
+                self.read_queue.push_back(DSP_VERSION_MINOR);
+            }
+            // Write / read test register
+            0xE4 => self.test_reg = self.params[0],
+            0xE8 => self.read_queue.push_back(self.test_reg),
+            // Force 8-bit IRQ
+            0xF2 => self.irq_pending = true,
+            _ => {
+                log::warn!("SB: Unhandled DSP command: {:02X}", command);
+            }
+        }
+    }
+
+    /// Transfer a single sample of the active DMA operation, returning false if the DMA
+    /// controller was not ready.
+    fn transfer_sample(&mut self, mode: DmaMode, dma: &mut DMAController, bus: &mut BusInterface) -> bool {
+        let channel = self.dma as usize;
+        match mode {
+            DmaMode::Playback => {
+                if !dma.check_dma_ready(channel) {
+                    return false;
+                }
+                self.dac = dma.do_dma_read_u8(bus, channel);
+            }
+            DmaMode::Record => {
+                if !dma.check_dma_ready(channel) {
+                    return false;
+                }
+                dma.do_dma_write_u8(bus, channel, DSP_SILENCE);
+            }
+            DmaMode::Silence => {
+                self.dac = DSP_SILENCE;
+            }
+            DmaMode::Adpcm(samples_per_byte) => {
+                // Read a new byte only when all the samples of the previous one have been played.
+                if self.dma_sub_sample == 0 {
+                    if !dma.check_dma_ready(channel) {
+                        return false;
+                    }
+                    _ = dma.do_dma_read_u8(bus, channel);
+                }
+                self.dac = DSP_SILENCE;
+                self.dma_sub_sample = (self.dma_sub_sample + 1) % samples_per_byte;
+                if self.dma_sub_sample != 0 {
+                    // Only count whole bytes towards the block length.
+                    return true;
+                }
+            }
+        }
+
+        self.dma_count -= 1;
+        if self.dma_count == 0 {
+            self.irq_pending = true;
+            if self.dma_auto_init && !self.dma_exit_auto {
+                self.dma_count = self.dma_len;
+            }
+            else {
+                self.dma_mode = None;
+                self.high_speed = false;
+            }
+        }
+        true
+    }
+
+    /// Run the Sound Blaster for the specified number of microseconds. Samples are transferred
+    /// from the DMA controller at the programmed rate, and output samples are sent to the mixer.
+    pub fn run(&mut self, dma: &mut DMAController, bus: &mut BusInterface, us: f64) {
+        if self.reset_timer > 0.0 {
+            self.reset_timer -= us;
+            if self.reset_timer <= 0.0 {
+                self.read_queue.clear();
+                self.read_queue.push_back(DSP_RESET_RESPONSE);
+            }
+        }
+
+        match self.dma_mode {
+            Some(mode) if !self.dma_paused => {
+                let period = 1_000_000.0 / self.sample_rate();
+                self.sample_accum += us;
+                while self.sample_accum >= period {
+                    self.sample_accum -= period;
+                    if !self.transfer_sample(mode, dma, bus) || self.dma_mode.is_none() {
+                        self.sample_accum = 0.0;
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Handle interrupts
+        if let Some(pic) = bus.pic_mut().as_mut() {
+            if self.irq_ack {
+                pic.clear_interrupt(self.irq);
+                self.irq_active = false;
+                self.irq_ack = false;
+            }
+            if self.irq_pending {
+                pic.request_interrupt(self.irq);
+                self.irq_active = true;
+                self.irq_pending = false;
+            }
+        }
+
+        // Send output samples to the mixer. The DAC output is only connected to the amplifier
+        // while the speaker is on.
+        let period = 1_000_000.0 / SB_OUTPUT_RATE;
+        self.output_accum += us;
+        while self.output_accum >= period {
+            self.output_accum -= period;
+            let sample = if self.speaker_on {
+                (self.dac as f32 - 128.0) / 128.0
+            }
+            else {
+                0.0
+            };
+            if let Some(source) = &mut self.source {
+                source.push(sample);
+            }
+        }
+    }
+}
+
+impl IoDevice for SoundBlaster {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.io_base) {
+            SB_PORT_READ_DATA => self.read_queue.pop_front().unwrap_or(0xFF),
+            SB_PORT_WRITE => {
+                // The DSP is always ready to accept a byte.
+                0x7F
+            }
+            SB_PORT_READ_STATUS => {
+                if self.irq_active {
+                    self.irq_ack = true;
+                }
+                if self.read_queue.is_empty() {
+                    0x7F
+                }
+                else {
+                    0xFF
+                }
+            }
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.io_base) {
+            SB_PORT_RESET => {
+                if data & 0x01 != 0 {
+                    self.reset_latch = true;
+                }
+                else if self.reset_latch {
+                    self.reset_latch = false;
+                    self.dsp_reset();
+                    self.reset_timer = DSP_RESET_DELAY_US;
+                }
+            }
+            SB_PORT_WRITE => self.write_dsp(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        vec![
+            ("SB DSP Reset".to_string(), self.io_base + SB_PORT_RESET),
+            ("SB DSP Read Data".to_string(), self.io_base + SB_PORT_READ_DATA),
+            ("SB DSP Write".to_string(), self.io_base + SB_PORT_WRITE),
+            ("SB DSP Read Status".to_string(), self.io_base + SB_PORT_READ_STATUS),
+        ]
+    }
+}
@@ -0,0 +1,311 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    vga::draw.rs
+
+    Frame generation for the VGA.
+
+    The VGA is not clocked by character. Instead, each scanline is generated
+    from VRAM as the CRTC finishes it, and buffers are swapped at the start of
+    vertical blank. This allows palette and CRTC register changes made during
+    the frame to appear on screen. Scanlines are written as 32-bit RGBA pixels
+    in display dots (720 wide in 9-dot text modes, 640 otherwise).
+
+*/
+
+use super::*;
+
+// Font table offsets into plane 2 for each of the 8 character maps selectable on the VGA.
+const FONT_MAP_OFFSETS: [usize; 8] = [0x0000, 0x4000, 0x8000, 0xC000, 0x2000, 0x6000, 0xA000, 0xE000];
+const FONT_GLYPH_SPAN: usize = 32;
+
+const CURSOR_BLINK_FRAMES: u64 = 8;
+const TEXT_BLINK_FRAMES: u64 = 16;
+
+/// The location in video memory of a scanline, as determined by the CRTC.
+struct ScanlineAddress {
+    row:   u32,  // Character row (or graphics row) counter
+    line:  u32,  // Row scan counter within the row
+    base:  u32,  // Start address for this portion of the screen
+    split: bool, // Whether the scanline is below the Line Compare split
+}
+
+impl VGACard {
+    /// Return the size in dots of the visible display area, clamped to the size of the frame buffer.
+    pub(crate) fn frame_size(&self) -> (u32, u32) {
+        let char_w = self.char_width();
+        let mut w = (self.crtc_horizontal_display_end as u32 + 1) * char_w;
+        if let DotClock::HalfClock = self.sequencer_clocking_mode.dot_clock() {
+            w *= 2;
+        }
+        let h = self.crtc_vertical_display_end as u32 + 1;
+
+        (std::cmp::min(w, VGA_FIELD_W), std::cmp::min(h, VGA_FIELD_H))
+    }
+
+    #[inline]
+    fn char_width(&self) -> u32 {
+        match self.sequencer_clocking_mode.character_clock() {
+            CharacterClock::EightDots => 8,
+            CharacterClock::NineDots => 9,
+        }
+    }
+
+    /// Returns true if the Attribute Controller is configured for 256-color mode. This is true of
+    /// mode 13h, as well as any unchained 'mode X' variant.
+    #[inline]
+    pub(crate) fn is_256_color(&self) -> bool {
+        matches!(
+            self.attribute_mode_control.pixel_clock_select(),
+            PixelClock::EveryOtherCycle
+        )
+    }
+
+    /// Translate a 4-bit attribute into an 8-bit DAC index via the attribute palette registers and
+    /// the Color Select register.
+    #[inline]
+    fn attribute_to_dac(&self, attr: u8) -> u8 {
+        let pal = self.attribute_palette_registers[(attr & 0x0F) as usize];
+        let c67 = self.attribute_color_select.c67() << 6;
+        match self.attribute_mode_control.internal_palette_size() {
+            PaletteSize::PaletteRegister45 => c67 | (pal & 0x3F),
+            PaletteSize::ColorRegister45 => c67 | (self.attribute_color_select.c45() << 4) | (pal & 0x0F),
+        }
+    }
+
+    /// Resolve a display scanline into a row and row scan value, taking into account scan doubling,
+    /// the Maximum Scanline and Preset Row Scan registers, and the Line Compare register which resets
+    /// the address counter to 0 to produce a split screen.
+    fn scanline_address(&self, y: u32) -> ScanlineAddress {
+        let line_compare = self.crtc_line_compare as u32;
+
+        let (mut y_rel, base, split) = if y > line_compare {
+            (y - line_compare - 1, 0, true)
+        }
+        else {
+            (
+                y,
+                self.crtc_start_address as u32 + self.crtc_preset_row_scan.byte_panning() as u32,
+                false,
+            )
+        };
+
+        if self.crtc_maximum_scanline.two_to_four() {
+            // Scan doubling
+            y_rel >>= 1;
+        }
+        if !split {
+            y_rel += self.crtc_preset_row_scan.preset_row_scan() as u32;
+        }
+
+        let row_h = self.crtc_maximum_scanline.maximum_scanline() as u32 + 1;
+        ScanlineAddress {
+            row: y_rel / row_h,
+            line: y_rel % row_h,
+            base,
+            split,
+        }
+    }
+
+    /// Return the horizontal pel panning value in pixels for the current mode. Panning is reset below
+    /// the split screen if the Pixel Panning Compatibility bit is set.
+    #[inline]
+    fn pel_panning(&self, split: bool) -> u32 {
+        if split && self.attribute_mode_control.pixel_panning_compatibility() {
+            return 0;
+        }
+        let pan = self.attribute_pel_panning as u32;
+        if let AttributeMode::Text = self.attribute_mode_control.mode() {
+            if self.char_width() == 9 {
+                // In 9 dot modes, a value of 8 is no shift and 0-7 shift by 1-8 pixels.
+                return (pan + 1) % 9;
+            }
+        }
+        if self.is_256_color() {
+            // Panning is specified in half-pixels in 256-color mode.
+            return (pan >> 1) & 0x03;
+        }
+        pan & 0x07
+    }
+
+    #[inline]
+    fn row_span(&self) -> u32 {
+        self.crtc_offset as u32 * 2
+    }
+
+    /// Return the DAC index of the pixel at the specified dot in 256-color mode.
+    ///
+    /// Chained (mode 13h) and unchained (mode X) configurations both resolve here. In chain4 mode,
+    /// CPU writes are stored in plane (address & 3) at offset (address >> 2), so a CRTC address
+    /// selects a group of four consecutive pixels across the planes in either case.
+    pub(crate) fn pixel_256(&self, x: u32, y: u32) -> u8 {
+        let sa = self.scanline_address(y);
+        let px = (x >> 1) + self.pel_panning(sa.split);
+
+        let addr = (sa.base + sa.row * self.row_span() + (px >> 2)) as usize & VGA_PLANE_MASK;
+        self.planes[(px & 0x03) as usize].buf[addr]
+    }
+
+    /// Return the 4-bit attribute of the pixel at the specified dot in a 16-color planar mode.
+    pub(crate) fn pixel_planar(&self, x: u32, y: u32) -> u8 {
+        let sa = self.scanline_address(y);
+        let mut px = x;
+        if let DotClock::HalfClock = self.sequencer_clocking_mode.dot_clock() {
+            px >>= 1;
+        }
+        px += self.pel_panning(sa.split);
+
+        let addr = (sa.base + sa.row * self.row_span() + (px >> 3)) as usize & VGA_PLANE_MASK;
+        let bit = 7 - (px & 0x07);
+
+        let mut attr = 0;
+        for i in 0..4 {
+            attr |= ((self.planes[i].buf[addr] >> bit) & 0x01) << i;
+        }
+        attr & self.attribute_color_plane_enable.enable_plane()
+    }
+
+    /// Return the 4-bit attribute of the pixel at the specified dot in text mode.
+    fn pixel_text(&self, x: u32, y: u32) -> u8 {
+        let sa = self.scanline_address(y);
+        let char_w = self.char_width();
+        let mut px = x;
+        if let DotClock::HalfClock = self.sequencer_clocking_mode.dot_clock() {
+            px >>= 1;
+        }
+        px += self.pel_panning(sa.split);
+
+        let col = px / char_w;
+        let cx = px % char_w;
+
+        // Character and attribute bytes are stored interleaved, as written by the CPU.
+        let char_addr = sa.base + sa.row * self.row_span() + col;
+        let byte_addr = (char_addr as usize * 2) & VGA_PLANE_MASK;
+        let glyph_char = self.planes[0].buf[byte_addr];
+        let attr = self.planes[0].buf[(byte_addr + 1) & VGA_PLANE_MASK];
+
+        // Attribute bit 3 selects between character maps A and B.
+        let font_map = match attr & 0x08 != 0 {
+            true => self.sequencer_character_map_a,
+            false => self.sequencer_character_map_b,
+        };
+        let glyph_row = if sa.line < FONT_GLYPH_SPAN as u32 {
+            let glyph_addr =
+                FONT_MAP_OFFSETS[(font_map & 0x07) as usize] + glyph_char as usize * FONT_GLYPH_SPAN + sa.line as usize;
+            self.planes[2].buf[glyph_addr & VGA_PLANE_MASK]
+        }
+        else {
+            0
+        };
+
+        let mut glyph_on = if cx < 8 {
+            glyph_row & (0x80 >> cx) != 0
+        }
+        else {
+            // Line graphics characters repeat the 8th column into the 9th.
+            self.attribute_mode_control.enable_line_character_codes()
+                && (0xC0..=0xDF).contains(&glyph_char)
+                && glyph_row & 0x01 != 0
+        };
+
+        let mut fg = attr & 0x0F;
+        let mut bg = attr >> 4;
+        if let AttributeBlinkOrIntensity::Blink = self.attribute_mode_control.enable_blink_or_intensity() {
+            bg &= 0x07;
+            if attr & 0x80 != 0 && (self.frame_count / TEXT_BLINK_FRAMES) & 0x01 != 0 {
+                fg = bg;
+            }
+        }
+
+        // Bit 5 of the Cursor Start register disables the cursor on the VGA.
+        if !self.crtc_cursor_start.cursor_enable()
+            && char_addr == self.get_cursor_address()
+            && sa.line >= self.crtc_cursor_start.cursor_start() as u32
+            && sa.line <= self.crtc_cursor_end.cursor_end() as u32
+            && (self.frame_count / CURSOR_BLINK_FRAMES) & 0x01 == 0
+        {
+            glyph_on = true;
+        }
+
+        match glyph_on {
+            true => fg,
+            false => bg,
+        }
+    }
+
+    /// Return the DAC index of the pixel at the specified dot for the current display mode.
+    pub(crate) fn pixel_dac_index(&self, x: u32, y: u32) -> u8 {
+        let index = match self.attribute_mode_control.mode() {
+            AttributeMode::Text => self.attribute_to_dac(self.pixel_text(x, y)),
+            AttributeMode::Graphics if self.is_256_color() => self.pixel_256(x, y),
+            AttributeMode::Graphics => self.attribute_to_dac(self.pixel_planar(x, y)),
+        };
+        index & self.color_pel_mask
+    }
+
+    /// Generate the specified scanline into the back buffer. This is called at the end of each
+    /// displayed scanline so that register changes made mid-frame take effect on the following line.
+    pub(crate) fn draw_scanline(&mut self, y: u32) {
+        let (w, h) = self.frame_size();
+        if y >= h {
+            return;
+        }
+
+        let mut buf = std::mem::take(&mut self.buf[self.back_buf]);
+        let row_offset = (y * VGA_FIELD_W) as usize * 4;
+
+        if self.sequencer_clocking_mode.screen_off() {
+            buf[row_offset..row_offset + w as usize * 4].fill(0);
+        }
+        else {
+            for x in 0..w {
+                let color = &self.color_registers_rgba[self.pixel_dac_index(x, y) as usize];
+                let o = row_offset + x as usize * 4;
+                buf[o..o + 3].copy_from_slice(&color[0..3]);
+                buf[o + 3] = 0xFF;
+            }
+        }
+
+        self.buf[self.back_buf] = buf;
+    }
+
+    /// Complete the current frame by swapping buffers.
+    pub(crate) fn finish_frame(&mut self) {
+        let (w, h) = self.frame_size();
+
+        std::mem::swap(&mut self.front_buf, &mut self.back_buf);
+        self.frame_count += 1;
+
+        // Resize the display apertures if the display mode changed size.
+        if self.extents.apertures[0].w != w || self.extents.apertures[0].h != h {
+            log::debug!("VGA: display size changed to {}x{}", w, h);
+            for aperture in self.extents.apertures.iter_mut() {
+                aperture.w = w;
+                aperture.h = h;
+            }
+        }
+    }
+}
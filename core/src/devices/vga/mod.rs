@@ -44,15 +44,16 @@ use std::{collections::HashMap, path::Path};
 use modular_bitfield::prelude::*;
 
 use crate::{
-    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemoryMappedDevice},
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemRangeDescriptor, MemoryMappedDevice},
+    device_traits::videocard::*,
+    devices::pic::Pic,
     tracelogger::TraceLogger,
 };
 
-use crate::videocard::*;
-
 mod attribute_regs;
 mod color_regs;
 mod crtc_regs;
+mod draw;
 mod graphics_regs;
 mod sequencer_regs;
 
@@ -78,6 +79,39 @@ pub const MEM_SIZE_32K: usize = 32768;
 // pub const CGA_MEM_SIZE: usize = 16384;
 pub const VGA_TEXT_PLANE_SIZE: usize = 16384;
 pub const VGA_GFX_PLANE_SIZE: usize = 65536;
+pub const VGA_PLANE_MASK: usize = VGA_GFX_PLANE_SIZE - 1;
+
+// Size of the frame buffer in dots. This fits 720x400 text and 640x480 graphics modes.
+pub const VGA_FIELD_W: u32 = 720;
+pub const VGA_FIELD_H: u32 = 480;
+pub const VGA_FRAME_BUF_SIZE: usize = (VGA_FIELD_W * VGA_FIELD_H) as usize * 4;
+
+const VGA_DEFAULT_APERTURE: DisplayAperture = DisplayAperture {
+    w: 640,
+    h: 400,
+    x: 0,
+    y: 0,
+    debug: false,
+};
+
+const VGA_APERTURE_DESCS: [DisplayApertureDesc; 4] = [
+    DisplayApertureDesc {
+        name: "Cropped",
+        aper_enum: DisplayApertureType::Cropped,
+    },
+    DisplayApertureDesc {
+        name: "Accurate",
+        aper_enum: DisplayApertureType::Accurate,
+    },
+    DisplayApertureDesc {
+        name: "Full",
+        aper_enum: DisplayApertureType::Full,
+    },
+    DisplayApertureDesc {
+        name: "Debug",
+        aper_enum: DisplayApertureType::Debug,
+    },
+];
 
 // For an EGA card connected to an EGA monitor
 // See http://www.minuszerodegrees.net/ibm_ega/ibm_ega_switch_settings.htm
@@ -272,6 +306,24 @@ impl DisplayPlane {
     }
 }
 
+// VGA implementation of Default for DisplayExtents.
+// The VGA renders only the visible display area, so all apertures are the same size.
+trait VgaDefault {
+    fn default() -> Self;
+}
+impl VgaDefault for DisplayExtents {
+    fn default() -> Self {
+        Self {
+            apertures: vec![VGA_DEFAULT_APERTURE; 4],
+            field_w: VGA_FIELD_W,
+            field_h: VGA_FIELD_H,
+            row_stride: VGA_FIELD_W as usize,
+            double_scan: false,
+            mode_byte: 0,
+        }
+    }
+}
+
 macro_rules! trace {
     ($self:ident, $($t:tt)*) => {{
         $self.trace_logger.print(&format!($($t)*));
@@ -399,6 +451,12 @@ pub struct VGACard {
     pipeline_buf: [u8; 4],
     write_buf: [u8; 4],
 
+    // Frame buffers
+    buf: [Box<[u8]>; 2],
+    front_buf: usize,
+    back_buf: usize,
+    frame_count: u64,
+
     trace_logger: TraceLogger,
 }
 
@@ -476,11 +534,12 @@ impl IoDevice for VGACard {
             GRAPHICS_DATA => self.read_graphics_data(),
             SEQUENCER_ADDRESS_REGISTER => self.sequencer_address_byte,
             SEQUENCER_DATA_REGISTER => self.read_sequencer_data(),
+            CRTC_REGISTER_ADDRESS | CRTC_REGISTER_ADDRESS_MDA => self.crtc_register_select_byte,
             CRTC_REGISTER => {
                 // Don't answer this port if we are in MDA compatibility mode
                 match self.misc_output_register.io_address_select() {
                     IoAddressSelect::CompatMonochrome => 0xFF,
-                    IoAddressSelect::CompatCGA => self.read_input_status_register_1(),
+                    IoAddressSelect::CompatCGA => self.read_crtc_register(),
                 }
             }
             CRTC_REGISTER_MDA => {
@@ -540,8 +599,15 @@ impl IoDevice for VGACard {
             SEQUENCER_ADDRESS_REGISTER => self.write_sequencer_address(data),
             SEQUENCER_DATA_REGISTER => self.write_sequencer_data(data),
             ATTRIBUTE_REGISTER | ATTRIBUTE_REGISTER_ALT => self.write_attribute_register(data),
-            PEL_ADDRESS_WRITE_MODE => self.color_pel_write_address = data,
-            PEL_ADDRESS_READ_MODE => self.color_pel_read_address = data,
+            PEL_ADDRESS_WRITE_MODE => {
+                // Writing an address resets the DAC to the red component.
+                self.color_pel_write_address = data;
+                self.color_pel_write_address_color = 0;
+            }
+            PEL_ADDRESS_READ_MODE => {
+                self.color_pel_read_address = data;
+                self.color_pel_read_address_color = 0;
+            }
             PEL_DATA => self.write_pel_data(data),
             PEL_MASK => self.color_pel_mask = data,
             //COLOR_CONTROL_REGISTER => {
@@ -551,28 +617,38 @@ impl IoDevice for VGACard {
         }
     }
 
-    fn port_list(&self) -> Vec<u16> {
+    fn port_list(&self) -> Vec<(String, u16)> {
         vec![
-            ATTRIBUTE_REGISTER,
-            ATTRIBUTE_REGISTER_ALT,
-            MISC_OUTPUT_REGISTER_READ,
-            MISC_OUTPUT_REGISTER_WRITE,
-            INPUT_STATUS_REGISTER_0,
-            INPUT_STATUS_REGISTER_1,
-            INPUT_STATUS_REGISTER_1_MDA,
-            SEQUENCER_ADDRESS_REGISTER,
-            SEQUENCER_DATA_REGISTER,
-            CRTC_REGISTER_ADDRESS,
-            CRTC_REGISTER,
-            CRTC_REGISTER_ADDRESS_MDA,
-            CRTC_REGISTER_MDA,
-            GRAPHICS_ADDRESS,
-            GRAPHICS_DATA,
-            PEL_ADDRESS_READ_MODE,
-            PEL_ADDRESS_WRITE_MODE,
-            PEL_DATA,
-            PEL_MASK,
-            DAC_STATE_REGISTER,
+            (String::from("VGA Attribute Register"), ATTRIBUTE_REGISTER),
+            (String::from("VGA Attribute Register Alt"), ATTRIBUTE_REGISTER_ALT),
+            (String::from("VGA Misc Output Register Read"), MISC_OUTPUT_REGISTER_READ),
+            (
+                String::from("VGA Misc Output Register Write"),
+                MISC_OUTPUT_REGISTER_WRITE,
+            ),
+            (String::from("VGA Input Status Register 1"), INPUT_STATUS_REGISTER_1),
+            (
+                String::from("VGA Input Status Register 1 (MDA)"),
+                INPUT_STATUS_REGISTER_1_MDA,
+            ),
+            (
+                String::from("VGA Sequencer Address Register"),
+                SEQUENCER_ADDRESS_REGISTER,
+            ),
+            (String::from("VGA Sequencer Data Register"), SEQUENCER_DATA_REGISTER),
+            (String::from("VGA CRTC Address Register"), CRTC_REGISTER_ADDRESS),
+            (String::from("VGA CRTC Data Register"), CRTC_REGISTER),
+            (
+                String::from("VGA CRTC Address Register (MDA)"),
+                CRTC_REGISTER_ADDRESS_MDA,
+            ),
+            (String::from("VGA CRTC Data Register (MDA)"), CRTC_REGISTER_MDA),
+            (String::from("VGA Graphics Address Register"), GRAPHICS_ADDRESS),
+            (String::from("VGA Graphics Data Register"), GRAPHICS_DATA),
+            (String::from("VGA PEL Address Read Mode"), PEL_ADDRESS_READ_MODE),
+            (String::from("VGA PEL Address Write Mode"), PEL_ADDRESS_WRITE_MODE),
+            (String::from("VGA PEL Data Register"), PEL_DATA),
+            (String::from("VGA PEL Mask Register"), PEL_MASK),
         ]
     }
 }
@@ -595,7 +671,7 @@ impl VGACard {
                 },
             ],
             u_timings: Default::default(),
-            extents: Default::default(),
+            extents: VgaDefault::default(),
             mode_byte: 0,
             display_mode: DisplayMode::Mode3TextCo80,
             mode_enable: true,
@@ -712,6 +788,14 @@ impl VGACard {
             pipeline_buf: [0; 4],
             write_buf: [0; 4],
 
+            buf: [
+                vec![0; VGA_FRAME_BUF_SIZE].into_boxed_slice(),
+                vec![0; VGA_FRAME_BUF_SIZE].into_boxed_slice(),
+            ],
+            front_buf: 0,
+            back_buf: 1,
+            frame_count: 0,
+
             trace_logger,
         }
     }
@@ -913,8 +997,8 @@ impl VGACard {
                 ) {
                     (00..=39, AttributeDisplayType::Color) => DisplayMode::ModeDEGALowResGraphics,
                     (79, AttributeDisplayType::Color) => {
-                        if self.sequencer_memory_mode.chain4_enable() {
-                            // Chain4 => mode13h
+                        if self.is_256_color() {
+                            // 256-color mode. Chained (mode 13h) or unchained (mode X)
                             DisplayMode::Mode13VGALowRes256
                         }
                         else {
@@ -940,7 +1024,7 @@ impl VGACard {
         match self.graphics_micellaneous.memory_map() {
             MemoryMap::A0000_128k => {
                 if address >= VGA_GFX_ADDRESS && address < VGA_GFX_ADDRESS + MEM_SIZE_128K {
-                    return Some(address - VGA_GFX_ADDRESS);
+                    return Some((address - VGA_GFX_ADDRESS) & VGA_PLANE_MASK);
                 }
                 else {
                     return None;
@@ -1162,6 +1246,11 @@ impl VGACard {
         if self.scanline_cycles >= self.u_timings.scanline_end {
             self.scanline_cycles = 0;
 
+            if self.scanline <= self.crtc_vertical_display_end as u32 {
+                // Finished a displayed scanline
+                self.draw_scanline(self.scanline);
+            }
+
             if self.scanline == (self.crtc_vertical_total + 2) as u32 {
                 //log::trace!("last scanline hit: {}", self.scanline);
                 self.scanline = 0;
@@ -1177,6 +1266,7 @@ impl VGACard {
                 if !self.in_vblank {
                     // Transitioning to vblank
                     //log::trace!("vblank at {} cycles", self.frame_cycles);
+                    self.finish_frame();
                 }
                 self.in_vblank = true;
             }
@@ -1242,7 +1332,11 @@ impl VideoCard for VGACard {
     }
 
     fn get_render_mode(&self) -> RenderMode {
-        RenderMode::Indirect
+        RenderMode::Direct
+    }
+
+    fn get_render_depth(&self) -> RenderBpp {
+        RenderBpp::Eight
     }
 
     fn get_display_mode(&self) -> DisplayMode {
@@ -1254,46 +1348,38 @@ impl VideoCard for VGACard {
     }
 
     fn get_display_size(&self) -> (u32, u32) {
-        // VGA supports multiple fonts.
-
-        let font_w = EGA_FONTS[self.current_font].w;
-        let _font_h = EGA_FONTS[self.current_font].h;
-
-        // Clock divisor effectively doubles the CRTC register values
-        let _clock_divisor = match self.sequencer_clocking_mode.dot_clock() {
-            DotClock::Native => 1,
-            DotClock::HalfClock => 2,
-        };
-
-        //let width = (self.crtc_horizontal_display_end as u32 + 1) * clock_divisor * font_w as u32;
-        let width = (self.crtc_horizontal_display_end as u32 + 1) * font_w as u32;
-        let height = self.crtc_vertical_display_end as u32 + 1;
-        (width, height)
+        self.frame_size()
     }
 
-    /// Unimplemented for indirect rendering.
     fn get_display_extents(&self) -> &DisplayExtents {
         &self.extents
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_display_aperture(&self) -> (u32, u32) {
-        (0, 0)
+    fn list_display_apertures(&self) -> Vec<DisplayApertureDesc> {
+        VGA_APERTURE_DESCS.to_vec()
     }
 
-    fn list_display_apertures(&self) -> (Vec<DisplayApertureDesc>, usize) {
-        (Vec::new(), 0)
+    fn get_display_apertures(&self) -> Vec<DisplayAperture> {
+        self.extents.apertures.clone()
     }
 
-    fn set_aperture(&mut self, aperture: u32) {}
-
-    /// Unimplemented for indirect rendering.
+    /// Return the beam position in dots, clamped to the visible display area.
     fn get_beam_pos(&self) -> Option<(u32, u32)> {
-        None
+        let (w, h) = self.frame_size();
+        let mut x = self.scanline_cycles;
+        if let DotClock::HalfClock = self.sequencer_clocking_mode.dot_clock() {
+            x *= 2;
+        }
+        Some((
+            std::cmp::min(x, w.saturating_sub(1)),
+            std::cmp::min(self.scanline, h.saturating_sub(1)),
+        ))
     }
 
-    fn debug_tick(&mut self, _ticks: u32) {
-        self.tick();
+    fn debug_tick(&mut self, ticks: u32, _cpumem: Option<&[u8]>) {
+        for _ in 0..ticks {
+            self.tick();
+        }
     }
 
     fn get_overscan_color(&self) -> u8 {
@@ -1311,14 +1397,15 @@ impl VideoCard for VGACard {
         false
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_display_buf(&self) -> &[u8] {
-        &[0]
+    fn get_buf(&self, buf_select: BufferSelect) -> &[u8] {
+        match buf_select {
+            BufferSelect::Back => &self.buf[self.back_buf][..],
+            BufferSelect::Front => &self.buf[self.front_buf][..],
+        }
     }
 
-    /// Unimplemented for indirect rendering.
-    fn get_back_buf(&self) -> &[u8] {
-        &[0]
+    fn get_display_buf(&self) -> &[u8] {
+        &self.buf[self.front_buf][..]
     }
 
     /// Return the current refresh rate.
@@ -1387,18 +1474,16 @@ impl VideoCard for VGACard {
         }
     }
 
-    fn get_current_font(&self) -> FontInfo {
+    fn get_current_font(&self) -> Option<FontInfo> {
         let w = EGA_FONTS[self.current_font].w;
         let h = EGA_FONTS[self.current_font].h;
         let data = EGA_FONTS[self.current_font].data;
 
-        FontInfo { w, h, font_data: data }
+        Some(FontInfo { w, h, font_data: data })
     }
 
     fn get_character_height(&self) -> u8 {
-        //self.crtc_maximum_scanline.maximum_scanline() + 1
-
-        14
+        self.crtc_maximum_scanline.maximum_scanline() + 1
     }
 
     /// Return the current palette number, intensity attribute bit, and alt color
@@ -1791,7 +1876,7 @@ impl VideoCard for VGACard {
         map
     }
 
-    fn run(&mut self, time: DeviceRunTimeUnit) {
+    fn run(&mut self, time: DeviceRunTimeUnit, _pic: &mut Option<Pic>, _cpumem: Option<&[u8]>) {
         let elapsed_us = if let DeviceRunTimeUnit::Microseconds(us) = time {
             us
         }
@@ -1858,73 +1943,15 @@ impl VideoCard for VGACard {
     }
 
     fn get_pixel(&self, x: u32, y: u32) -> &[u8] {
-        let pixel_byte = self.get_pixel_raw(x, y);
-
-        return &self.color_registers_rgba[pixel_byte as usize];
+        &self.color_registers_rgba[self.pixel_dac_index(x, y) as usize]
     }
 
     fn get_pixel_raw(&self, x: u32, y: u32) -> u8 {
-        let mut byte = 0;
-
-        if self.sequencer_memory_mode.chain4_enable() {
-            // Chain4 mode
-            let x_byte_offset = x + self.attribute_pel_panning as u32;
-
-            let span = self.crtc_offset as u32 * 2;
-            let y_offset = y * span;
-
-            let byte_select = (x_byte_offset + self.crtc_start_address as u32) >> 2 as usize;
-            let plane_select = ((x_byte_offset + self.crtc_start_address as u32) & 0x03) as usize;
-
-            let read_offset = (y_offset + byte_select) as usize;
-            // LO 2 bits selects plane
-
-            let byte = self.planes[plane_select].buf[read_offset];
-            return byte;
-        }
-        else {
-            let x_byte_offset = (x + self.attribute_pel_panning as u32) / 8;
-            let x_bit_offset = (x + self.attribute_pel_panning as u32) % 8;
-
-            // Get the current width of screen + offset
-            //let span = (self.crtc_horizontal_display_end + 1 + 64) as u32;
-            let span = self.crtc_offset as u32 * 2;
-
-            let y_offset = y * span;
-
-            // The line compare register resets the CRTC Start Address and line counter to 0 at the
-            // specified scanline.
-            // If we are above the value in Line Compare calculate the read offset as normal.
-            let read_offset;
-            if y >= self.crtc_line_compare as u32 {
-                read_offset = (((y - self.crtc_line_compare as u32) * span) + x_byte_offset) as usize;
-            }
-            else {
-                read_offset = (y_offset + x_byte_offset + self.crtc_start_address as u32) as usize;
-            }
-
-            if read_offset < self.planes[0].buf.len() {
-                for i in 0..4 {
-                    let read_byte = self.planes[i].buf[read_offset];
-                    let read_bit = match read_byte & (0x01 << (7 - x_bit_offset)) != 0 {
-                        true => 1,
-                        false => 0,
-                    };
-
-                    byte |= read_bit << i;
-                }
-                // return self.attribute_palette_registers[byte & 0x0F].into_bytes()[0];
-
-                if x == 0 && y == 0 {
-                    // break me
-
-                    //log::trace!("pixel (0,0): byte: {:01X}, palette: {:04X}", byte, self.attribute_palette_registers[byte & 0x0F]);
-                }
-
-                return self.attribute_palette_registers[byte & 0x0F];
-            }
+        match self.attribute_mode_control.mode() {
+            AttributeMode::Text => 0,
+            AttributeMode::Graphics if self.is_256_color() => self.pixel_256(x, y),
+            AttributeMode::Graphics => self.pixel_planar(x, y),
         }
-        0
     }
 
     fn get_plane_slice(&self, plane: usize) -> &[u8] {
@@ -1948,7 +1975,7 @@ impl VideoCard for VGACard {
     }
 
     fn get_frame_count(&self) -> u64 {
-        0
+        self.frame_count
     }
 
     fn write_trace_log(&mut self, msg: String) {
@@ -1958,6 +1985,10 @@ impl VideoCard for VGACard {
     fn trace_flush(&mut self) {
         self.trace_logger.flush();
     }
+
+    fn get_text_mode_strings(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl MemoryMappedDevice for VGACard {
@@ -1969,14 +2000,14 @@ impl MemoryMappedDevice for VGACard {
         0
     }
 
-    fn mmio_read_u8(&mut self, address: usize, _cycles: u32) -> (u8, u32) {
+    fn mmio_read_u8(&mut self, address: usize, _cycles: u32, _cpumem: Option<&[u8]>) -> (u8, u32) {
         // RAM Enable disables memory mapped IO
         if !self.misc_output_register.enable_ram() {
             return (0, 0);
         }
 
         // Validate address is within current memory map and get the offset into VRAM
-        let offset = match self.plane_bounds_check(address) {
+        let mut offset = match self.plane_bounds_check(address) {
             Some(offset) => offset,
             None => {
                 trace!(
@@ -1993,6 +2024,16 @@ impl MemoryMappedDevice for VGACard {
             }
         };
 
+        // In chain4 mode, the first two bits of the memory address select the plane to read
+        if self.sequencer_memory_mode.chain4_enable() {
+            let plane = offset & 0x03;
+            offset >>= 2;
+            for i in 0..4 {
+                self.planes[i].latch = self.planes[i].buf[offset];
+            }
+            return (self.planes[plane].buf[offset], 0);
+        }
+
         // Load all the latches regardless of selected plane or read mode
         self.latch_addr = address as u32;
         for i in 0..4 {
@@ -2044,15 +2085,15 @@ impl MemoryMappedDevice for VGACard {
         (0, 0)
     }
 
-    fn mmio_read_u16(&mut self, address: usize, _cycles: u32) -> (u16, u32) {
-        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, 0);
-        let (ho_byte, wait2) = MemoryMappedDevice::mmio_read_u8(self, address + 1, 0);
+    fn mmio_read_u16(&mut self, address: usize, _cycles: u32, cpumem: Option<&[u8]>) -> (u16, u32) {
+        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, 0, cpumem);
+        let (ho_byte, wait2) = MemoryMappedDevice::mmio_read_u8(self, address + 1, 0, cpumem);
 
         log::warn!("Unsupported 16 bit read from VRAM");
         ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2)
     }
 
-    fn mmio_peek_u8(&self, address: usize) -> u8 {
+    fn mmio_peek_u8(&self, address: usize, _cpumem: Option<&[u8]>) -> u8 {
         // RAM Enable disables memory mapped IO
         if !self.misc_output_register.enable_ram() {
             return 0;
@@ -2064,10 +2105,13 @@ impl MemoryMappedDevice for VGACard {
             None => return 0,
         };
 
+        if self.sequencer_memory_mode.chain4_enable() {
+            return self.planes[offset & 0x03].buf[offset >> 2];
+        }
         self.planes[0].buf[offset]
     }
 
-    fn mmio_peek_u16(&self, address: usize) -> u16 {
+    fn mmio_peek_u16(&self, address: usize, _cpumem: Option<&[u8]>) -> u16 {
         // RAM Enable disables memory mapped IO
         if !self.misc_output_register.enable_ram() {
             return 0;
@@ -2082,7 +2126,7 @@ impl MemoryMappedDevice for VGACard {
         (self.planes[0].buf[offset] as u16) << 8 | self.planes[0].buf[offset + 1] as u16
     }
 
-    fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32) -> u32 {
+    fn mmio_write_u8(&mut self, address: usize, byte: u8, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
         // RAM Enable disables memory mapped IO
         if !self.misc_output_register.enable_ram() {
             return 0;
@@ -2305,11 +2349,30 @@ impl MemoryMappedDevice for VGACard {
         0
     }
 
-    fn mmio_write_u16(&mut self, address: usize, data: u16, _cycles: u32) -> u32 {
+    fn mmio_write_u16(&mut self, address: usize, data: u16, _cycles: u32, _cpumem: Option<&mut [u8]>) -> u32 {
         trace!(self, "16 byte write to VRAM, {:04X} -> {:05X} ", data, address);
         log::warn!("Unsupported 16 bit write to VRAM");
         0
     }
+
+    fn get_mapping(&self) -> Vec<MemRangeDescriptor> {
+        vec![
+            MemRangeDescriptor {
+                address: CGA_ADDRESS,
+                size: MEM_SIZE_32K,
+                cycle_cost: 0,
+                read_only: false,
+                priority: 0,
+            },
+            MemRangeDescriptor {
+                address: VGA_GFX_ADDRESS,
+                size: MEM_SIZE_64K,
+                cycle_cost: 0,
+                read_only: false,
+                priority: 0,
+            },
+        ]
+    }
 }
 
 #[cfg(test)]
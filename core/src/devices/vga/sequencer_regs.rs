@@ -85,10 +85,11 @@ pub enum ShiftFour {
     EightDots,
 }
 
+/// Bit 0 of the Clocking Mode register selects 8 dot characters when set.
 #[derive(Copy, Clone, Debug, BitfieldSpecifier)]
 pub enum CharacterClock {
-    EightDots,
     NineDots,
+    EightDots,
 }
 
 #[derive(Copy, Clone, Debug, BitfieldSpecifier)]
@@ -0,0 +1,1082 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::xtide.rs
+
+    Implements an ATA (IDE) hard disk interface for 8-bit machines, as used
+    with the XT-IDE Universal BIOS.
+
+    Two register layouts are supported:
+
+    XT-IDE rev 1 - The ATA command block registers occupy base+0-7. As the
+    8-bit bus can't carry 16-bit data transfers, the card latches the high
+    byte of each data word in a register at base+8. The alternate status and
+    device control register is at base+0Eh.
+
+    8-bit ATA - The standard ATA register layout, with the control block at
+    base+206h. The guest must enable 8-bit data transfers with the Set
+    Features command, as 16-bit transfers only carry the low byte of each
+    word.
+
+    Either layout may use 8-bit transfers once enabled. Up to two drives,
+    master and slave, are supported, with CHS and 28-bit LBA addressing, so
+    images of up to 128GB can be used.
+
+    Either drive may instead be an ATAPI CD-ROM drive, which aborts ATA
+    commands and is driven with the Packet command. Packet commands and
+    their data are sent through the data register; as there is no latch for
+    them, a DOS driver must enable 8-bit transfers before using the drive.
+
+*/
+
+use std::error::Error;
+
+use core::fmt::Display;
+
+use crate::{
+    bus::{BusInterface, DeviceRunTimeUnit, IoDevice, NO_IO_BYTE},
+    cd_image::CD_SECTOR_SIZE,
+    device_types::hdc::{HardDiskFormat, XT_HARD_DISK_TYPES},
+    devices::{
+        atapi_cdrom::{AtapiCdrom, PacketResponse, Sense},
+        hdc::SECTOR_SIZE,
+        pic::Pic,
+    },
+    vhd::VirtualHardDisk,
+};
+
+pub const XTIDE_DEFAULT_IO_BASE: u16 = 0x300;
+pub const ATA_DEFAULT_IO_BASE: u16 = 0x1F0;
+
+const CONTROL_OFFSET_XTIDE: u16 = 0x08;
+const CONTROL_OFFSET_ATA: u16 = 0x200;
+
+// Command block register offsets
+const REG_DATA: u16 = 0x00;
+const REG_ERROR: u16 = 0x01; // Features when written
+const REG_SECTOR_COUNT: u16 = 0x02;
+const REG_SECTOR: u16 = 0x03;
+const REG_CYLINDER_LO: u16 = 0x04;
+const REG_CYLINDER_HI: u16 = 0x05;
+const REG_DRIVE_HEAD: u16 = 0x06;
+const REG_STATUS: u16 = 0x07; // Command when written
+
+// Control block register offsets
+const REG_DATA_HI: u16 = 0x00; // XT-IDE only
+const REG_ALT_STATUS: u16 = 0x06; // Device control when written
+
+// Status register bits
+const ST_BSY: u8 = 0x80;
+const ST_DRDY: u8 = 0x40;
+const ST_DSC: u8 = 0x10;
+const ST_DRQ: u8 = 0x08;
+const ST_ERR: u8 = 0x01;
+
+// Error register bits
+const ERR_ABRT: u8 = 0x04;
+const ERR_IDNF: u8 = 0x10;
+const ERR_UNC: u8 = 0x40;
+
+// Drive/head register bits
+const DH_LBA: u8 = 0x40;
+const DH_DEV: u8 = 0x10;
+
+// Device control register bits
+const DC_NIEN: u8 = 0x02;
+const DC_SRST: u8 = 0x04;
+
+// Set Features subcommands
+const FEATURE_ENABLE_8BIT: u8 = 0x01;
+const FEATURE_DISABLE_8BIT: u8 = 0x81;
+
+const MAX_MULTIPLE: u8 = 16;
+const MAX_LBA28_SECTORS: u64 = 0x0FFF_FFFF;
+const MAX_CHS_CYLINDERS: u64 = 16383;
+const DEFAULT_HEADS: u64 = 16;
+const DEFAULT_SECTORS: u64 = 63;
+
+// ATAPI devices leave this signature in the cylinder registers after reset.
+const ATAPI_SIGNATURE: u16 = 0xEB14;
+const ATAPI_PACKET_SIZE: usize = 12;
+// Interrupt reason bits, reported in the sector count register
+const IR_COD: u8 = 0x01;
+const IR_IO: u8 = 0x02;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IdeRegisterLayout {
+    XtIde,
+    Ata8Bit,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum IdeError {
+    InvalidDevice,
+    ImageTooSmall,
+    DeviceInUse,
+}
+impl Error for IdeError {}
+impl Display for IdeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            IdeError::InvalidDevice => write!(f, "The specified Device ID was out of range [0..1]"),
+            IdeError::ImageTooSmall => write!(f, "The image is too small to be used as an ATA drive."),
+            IdeError::DeviceInUse => write!(f, "The specified device is already in use by another drive."),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Transfer {
+    None,
+    Read,
+    Write,
+    Identify,
+    Packet,
+    PacketData,
+}
+
+#[derive(Default)]
+struct IdeDrive {
+    vhd: Option<VirtualHardDisk>,
+    sectors: u64,
+    // Default geometry reported by Identify Device
+    cylinders: u16,
+    heads: u8,
+    spt: u8,
+    // Logical geometry set by Initialize Device Parameters
+    cur_heads: u8,
+    cur_spt: u8,
+    multiple: u8,
+    cdrom: Option<AtapiCdrom>,
+}
+
+impl IdeDrive {
+    fn cur_cylinders(&self) -> u16 {
+        if self.cur_heads == 0 || self.cur_spt == 0 {
+            return 0;
+        }
+        std::cmp::min(
+            self.sectors.min(MAX_LBA28_SECTORS) / (self.cur_heads as u64 * self.cur_spt as u64),
+            u16::MAX as u64,
+        ) as u16
+    }
+}
+
+pub struct XtIdeController {
+    layout: IdeRegisterLayout,
+    io_base: u16,
+    irq: Option<u8>,
+    drives: [IdeDrive; 2],
+
+    features: u8,
+    error: u8,
+    sector_count: u8,
+    sector: u8,
+    cylinder: u16,
+    drive_head: u8,
+    status: u8,
+    device_control: u8,
+    eight_bit: bool,
+    data_latch: u8,
+
+    transfer: Transfer,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    block_sectors: usize,
+    sectors_left: usize,
+    lba: u64,
+    packet_data: Vec<u8>,
+    byte_limit: usize,
+
+    interrupt_pending: bool,
+    irq_active: bool,
+}
+
+impl XtIdeController {
+    pub fn new(layout: IdeRegisterLayout, io_base: Option<u16>, irq: Option<u8>) -> Self {
+        let io_base = io_base.unwrap_or(match layout {
+            IdeRegisterLayout::XtIde => XTIDE_DEFAULT_IO_BASE,
+            IdeRegisterLayout::Ata8Bit => ATA_DEFAULT_IO_BASE,
+        });
+        let mut ide = Self {
+            layout,
+            io_base,
+            irq,
+            drives: Default::default(),
+            features: 0,
+            error: 0,
+            sector_count: 0,
+            sector: 0,
+            cylinder: 0,
+            drive_head: 0,
+            status: 0,
+            device_control: 0,
+            eight_bit: false,
+            data_latch: 0,
+            transfer: Transfer::None,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            block_sectors: 0,
+            sectors_left: 0,
+            lba: 0,
+            packet_data: Vec::new(),
+            byte_limit: 0,
+            interrupt_pending: false,
+            irq_active: false,
+        };
+        ide.reset();
+        ide
+    }
+
+    /// Reset the controller and both drives to their power-on state, leaving the drive signature in the
+    /// command block registers.
+    pub fn reset(&mut self) {
+        self.error = 0x01; // Diagnostic passed
+        self.sector_count = 1;
+        self.sector = 1;
+        self.drive_head = 0;
+        self.cylinder = self.signature();
+        self.status = ST_DRDY | ST_DSC;
+        self.eight_bit = false;
+        self.end_transfer();
+        self.interrupt_pending = false;
+        for drive in self.drives.iter_mut() {
+            drive.cur_heads = drive.heads;
+            drive.cur_spt = drive.spt;
+            drive.multiple = 0;
+            if let Some(cdrom) = &mut drive.cdrom {
+                cdrom.reset();
+            }
+        }
+    }
+
+    pub fn drive_ct(&self) -> usize {
+        self.drives.len()
+    }
+
+    /// ATA drives aren't limited to a fixed set of drive types. Return the XT drive types along with some
+    /// larger geometries for creating new images.
+    pub fn get_supported_formats(&self) -> Vec<HardDiskFormat> {
+        let mut formats: Vec<HardDiskFormat> = XT_HARD_DISK_TYPES.iter().flatten().cloned().collect();
+        for (c, desc) in [(1024, "504MB"), (4161, "2GB"), (16383, "8GB")] {
+            formats.push(HardDiskFormat {
+                max_cylinders: c,
+                max_heads: DEFAULT_HEADS as u8,
+                max_sectors: DEFAULT_SECTORS as u8,
+                wpc: None,
+                desc: desc.to_string(),
+            });
+        }
+        formats
+    }
+
+    pub fn set_vhd(&mut self, device_id: usize, vhd: VirtualHardDisk) -> Result<(), IdeError> {
+        if device_id > 1 {
+            return Err(IdeError::InvalidDevice);
+        }
+        if self.drives[device_id].cdrom.is_some() {
+            return Err(IdeError::DeviceInUse);
+        }
+
+        let sectors = vhd.sector_count();
+        let image_chs = vhd.max_cylinders as u64 * vhd.max_heads as u64 * vhd.max_sectors as u64;
+
+        // Use the image geometry if it can be expressed to the BIOS, otherwise translate to 16 heads and 63 sectors.
+        let (cylinders, heads, spt) = if vhd.max_cylinders as u64 <= MAX_CHS_CYLINDERS
+            && vhd.max_heads as u64 <= DEFAULT_HEADS
+            && (1..=255).contains(&vhd.max_sectors)
+            && image_chs > 0
+            && image_chs <= sectors
+        {
+            (vhd.max_cylinders as u64, vhd.max_heads as u64, vhd.max_sectors as u64)
+        }
+        else {
+            let c = std::cmp::min(sectors / (DEFAULT_HEADS * DEFAULT_SECTORS), MAX_CHS_CYLINDERS);
+            (c, DEFAULT_HEADS, DEFAULT_SECTORS)
+        };
+        if cylinders == 0 {
+            return Err(IdeError::ImageTooSmall);
+        }
+
+        log::debug!(
+            "IDE drive {}: {} sectors, default geometry c: {} h: {} s: {}",
+            device_id,
+            sectors,
+            cylinders,
+            heads,
+            spt
+        );
+
+        self.drives[device_id] = IdeDrive {
+            vhd: Some(vhd),
+            sectors,
+            cylinders: cylinders as u16,
+            heads: heads as u8,
+            spt: spt as u8,
+            cur_heads: heads as u8,
+            cur_spt: spt as u8,
+            multiple: 0,
+            cdrom: None,
+        };
+        Ok(())
+    }
+
+    /// Return the VHD mounted in the specified drive, if any.
+    pub fn vhd_mut(&mut self, device_id: usize) -> Option<&mut VirtualHardDisk> {
+        self.drives.get_mut(device_id).and_then(|drive| drive.vhd.as_mut())
+    }
+
+    pub fn unload_vhd(&mut self, device_id: usize) {
+        if device_id < self.drives.len() && self.drives[device_id].cdrom.is_none() {
+            self.drives[device_id] = Default::default();
+        }
+    }
+
+    /// Attach an ATAPI CD-ROM drive as the specified device.
+    pub fn set_cdrom(&mut self, device_id: usize, cdrom: AtapiCdrom) -> Result<(), IdeError> {
+        if device_id > 1 {
+            return Err(IdeError::InvalidDevice);
+        }
+        if self.drives[device_id].vhd.is_some() {
+            return Err(IdeError::DeviceInUse);
+        }
+        log::debug!("IDE drive {}: ATAPI CD-ROM", device_id);
+        self.drives[device_id] = IdeDrive {
+            cdrom: Some(cdrom),
+            ..Default::default()
+        };
+        self.cylinder = self.signature();
+        Ok(())
+    }
+
+    /// Return the attached CD-ROM drive, if any.
+    pub fn cdrom_mut(&mut self) -> Option<&mut AtapiCdrom> {
+        self.drives.iter_mut().find_map(|drive| drive.cdrom.as_mut())
+    }
+
+    /// Run the CD-ROM drive and update the IRQ line. Interrupts are only delivered if an IRQ is configured and
+    /// the guest hasn't disabled them with the nIEN bit.
+    pub fn run(&mut self, pic: &mut Pic, us: f64) {
+        for drive in self.drives.iter_mut() {
+            if let Some(cdrom) = &mut drive.cdrom {
+                cdrom.run(us);
+            }
+        }
+        let irq = match self.irq {
+            Some(irq) => irq,
+            None => return,
+        };
+        let pending = self.interrupt_pending && self.device_control & DC_NIEN == 0;
+        if pending && !self.irq_active {
+            pic.request_interrupt(irq);
+            self.irq_active = true;
+        }
+        else if !pending && self.irq_active {
+            pic.clear_interrupt(irq);
+            self.irq_active = false;
+        }
+    }
+
+    fn control_base(&self) -> u16 {
+        match self.layout {
+            IdeRegisterLayout::XtIde => self.io_base + CONTROL_OFFSET_XTIDE,
+            IdeRegisterLayout::Ata8Bit => self.io_base + CONTROL_OFFSET_ATA,
+        }
+    }
+
+    fn selected(&self) -> usize {
+        (self.drive_head & DH_DEV != 0) as usize
+    }
+
+    fn drive_present(&self) -> bool {
+        let drive = &self.drives[self.selected()];
+        drive.vhd.is_some() || drive.cdrom.is_some()
+    }
+
+    fn is_atapi(&self) -> bool {
+        self.drives[self.selected()].cdrom.is_some()
+    }
+
+    /// Return the signature the selected device leaves in the cylinder registers.
+    fn signature(&self) -> u16 {
+        if self.is_atapi() {
+            ATAPI_SIGNATURE
+        }
+        else {
+            0
+        }
+    }
+
+    fn read_status(&self) -> u8 {
+        // A bus with no device on it reads back as 0, which the BIOS uses to detect absent drives.
+        if self.drive_present() {
+            self.status
+        }
+        else {
+            0
+        }
+    }
+
+    /// Return the logical block address in the command block registers, or None if the CHS address is invalid
+    /// for the current logical geometry.
+    fn command_lba(&self) -> Option<u64> {
+        if self.drive_head & DH_LBA != 0 {
+            return Some(((self.drive_head as u64 & 0x0F) << 24) | ((self.cylinder as u64) << 8) | self.sector as u64);
+        }
+
+        let drive = &self.drives[self.selected()];
+        let head = self.drive_head & 0x0F;
+        if self.sector == 0 || self.sector > drive.cur_spt || head >= drive.cur_heads {
+            return None;
+        }
+        Some(
+            (self.cylinder as u64 * drive.cur_heads as u64 + head as u64) * drive.cur_spt as u64
+                + (self.sector as u64 - 1),
+        )
+    }
+
+    /// Load the command block registers with the specified logical block address.
+    fn set_command_lba(&mut self, lba: u64) {
+        if self.drive_head & DH_LBA != 0 {
+            self.sector = lba as u8;
+            self.cylinder = (lba >> 8) as u16;
+            self.drive_head = (self.drive_head & 0xF0) | ((lba >> 24) as u8 & 0x0F);
+            return;
+        }
+
+        let drive = &self.drives[self.selected()];
+        let track_len = drive.cur_spt as u64;
+        let cyl_len = track_len * drive.cur_heads as u64;
+        if cyl_len == 0 {
+            return;
+        }
+        self.cylinder = (lba / cyl_len) as u16;
+        self.drive_head = (self.drive_head & 0xF0) | ((lba % cyl_len) / track_len) as u8;
+        self.sector = (lba % track_len) as u8 + 1;
+    }
+
+    fn complete(&mut self, error: u8) {
+        self.error = error;
+        self.status = ST_DRDY | ST_DSC;
+        if error != 0 {
+            self.status |= ST_ERR;
+        }
+        self.interrupt_pending = true;
+    }
+
+    fn end_transfer(&mut self) {
+        self.transfer = Transfer::None;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.sectors_left = 0;
+        self.packet_data.clear();
+        self.status &= !ST_DRQ;
+    }
+
+    fn write_command(&mut self, command: u8) {
+        if !self.drive_present() {
+            return;
+        }
+
+        self.end_transfer();
+        self.interrupt_pending = false;
+        log::trace!("IDE: Command {:02X} drive: {}", command, self.selected());
+
+        if self.is_atapi() {
+            match command {
+                0xA0 => {
+                    self.command_packet();
+                    return;
+                }
+                0xA1 => {
+                    self.command_identify_packet();
+                    return;
+                }
+                0x08 => {
+                    // Device Reset
+                    if let Some(cdrom) = &mut self.drives[self.selected()].cdrom {
+                        cdrom.reset();
+                    }
+                    self.reset_signature();
+                    self.error = 0x01;
+                    self.status = ST_DRDY | ST_DSC;
+                    return;
+                }
+                0x90 | 0xEF | 0xE0..=0xE3 | 0xE5 | 0xE7 | 0x94..=0x99 => {} // Shared with ATA devices
+                _ => {
+                    // Other ATA commands are aborted, leaving the signature so the host can detect the device
+                    self.reset_signature();
+                    self.complete(ERR_ABRT);
+                    return;
+                }
+            }
+        }
+
+        match command {
+            0x20 | 0x21 => self.start_transfer(Transfer::Read, 1),
+            0x30 | 0x31 => self.start_transfer(Transfer::Write, 1),
+            0xC4 | 0xC5 => {
+                // Read and Write Multiple require block mode to have been enabled
+                let multiple = self.drives[self.selected()].multiple;
+                if multiple == 0 {
+                    self.complete(ERR_ABRT);
+                }
+                else if command == 0xC4 {
+                    self.start_transfer(Transfer::Read, multiple as usize);
+                }
+                else {
+                    self.start_transfer(Transfer::Write, multiple as usize);
+                }
+            }
+            0x40 | 0x41 => self.command_verify(),
+            0x10..=0x1F => {
+                // Recalibrate
+                self.cylinder = 0;
+                self.complete(0);
+            }
+            0x70 => {
+                // Seek
+                let error = match self.command_lba() {
+                    Some(lba) if lba < self.drives[self.selected()].sectors => 0,
+                    _ => ERR_IDNF,
+                };
+                self.complete(error);
+            }
+            0x90 => {
+                // Execute Device Diagnostic
+                self.reset_signature();
+                self.complete(0);
+                self.error = 0x01;
+            }
+            0x91 => {
+                // Initialize Device Parameters
+                let drive = &mut self.drives[self.selected()];
+                drive.cur_heads = (self.drive_head & 0x0F) + 1;
+                drive.cur_spt = self.sector_count;
+                log::debug!(
+                    "IDE: Logical geometry set to c: {} h: {} s: {}",
+                    drive.cur_cylinders(),
+                    drive.cur_heads,
+                    drive.cur_spt
+                );
+                let error = if drive.cur_spt == 0 { ERR_ABRT } else { 0 };
+                self.complete(error);
+            }
+            0xC6 => {
+                // Set Multiple Mode
+                let count = self.sector_count;
+                if count == 0 || (count <= MAX_MULTIPLE && count.is_power_of_two()) {
+                    self.drives[self.selected()].multiple = count;
+                    self.complete(0);
+                }
+                else {
+                    self.complete(ERR_ABRT);
+                }
+            }
+            0xEC => self.command_identify(),
+            0xEF => {
+                // Set Features
+                match self.features {
+                    FEATURE_ENABLE_8BIT => {
+                        self.eight_bit = true;
+                        self.complete(0);
+                    }
+                    FEATURE_DISABLE_8BIT => {
+                        self.eight_bit = false;
+                        self.complete(0);
+                    }
+                    0x03 | 0x02 | 0x82 | 0x55 | 0xAA => self.complete(0), // Transfer mode and cache settings
+                    _ => self.complete(ERR_ABRT),
+                }
+            }
+            0xE0..=0xE3 | 0xE7 | 0x94..=0x99 => self.complete(0), // Power management and cache flush
+            0xE5 => {
+                // Check Power Mode: always active
+                self.sector_count = 0xFF;
+                self.complete(0);
+            }
+            _ => {
+                log::warn!("IDE: Unsupported command: {:02X}", command);
+                self.complete(ERR_ABRT);
+            }
+        }
+    }
+
+    fn reset_signature(&mut self) {
+        self.sector_count = 1;
+        self.sector = 1;
+        self.cylinder = self.signature();
+        self.drive_head &= DH_DEV;
+    }
+
+    fn transfer_count(&self) -> usize {
+        match self.sector_count {
+            0 => 256,
+            n => n as usize,
+        }
+    }
+
+    fn start_transfer(&mut self, transfer: Transfer, block_sectors: usize) {
+        let lba = match self.command_lba() {
+            Some(lba) => lba,
+            None => {
+                self.complete(ERR_IDNF);
+                return;
+            }
+        };
+        let count = self.transfer_count();
+        if lba + count as u64 > self.drives[self.selected()].sectors {
+            self.complete(ERR_IDNF);
+            return;
+        }
+
+        self.transfer = transfer;
+        self.lba = lba;
+        self.sectors_left = count;
+        self.block_sectors = block_sectors;
+        match transfer {
+            Transfer::Read => self.read_block(),
+            _ => self.request_block(),
+        }
+    }
+
+    /// Read the next block of sectors into the buffer and request the host to take it.
+    fn read_block(&mut self) {
+        let n = std::cmp::min(self.block_sectors, self.sectors_left);
+        self.buffer.resize(n * SECTOR_SIZE, 0);
+        self.buffer_pos = 0;
+
+        let selected = self.selected();
+        let mut error = 0;
+        if let Some(vhd) = &mut self.drives[selected].vhd {
+            for i in 0..n {
+                let buf = &mut self.buffer[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE];
+                if let Err(err) = vhd.read_sector_lba(buf, self.lba + i as u64) {
+                    log::error!("IDE: Read failed at LBA {}: {}", self.lba + i as u64, err);
+                    error = ERR_UNC;
+                    break;
+                }
+            }
+        }
+
+        if error != 0 {
+            self.set_command_lba(self.lba);
+            self.end_transfer();
+            self.complete(error);
+            return;
+        }
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+        self.interrupt_pending = true;
+    }
+
+    /// Request the next block of sectors to write from the host.
+    fn request_block(&mut self) {
+        let n = std::cmp::min(self.block_sectors, self.sectors_left);
+        self.buffer.resize(n * SECTOR_SIZE, 0);
+        self.buffer_pos = 0;
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+    }
+
+    /// Handle the host consuming or filling the buffer.
+    fn buffer_done(&mut self) {
+        let n = self.buffer.len() / SECTOR_SIZE;
+        let writing = self.transfer == Transfer::Write;
+
+        if writing {
+            let selected = self.selected();
+            let mut error = 0;
+            if let Some(vhd) = &mut self.drives[selected].vhd {
+                for i in 0..n {
+                    let buf = &self.buffer[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE];
+                    if let Err(err) = vhd.write_sector_lba(buf, self.lba + i as u64) {
+                        log::error!("IDE: Write failed at LBA {}: {}", self.lba + i as u64, err);
+                        error = ERR_ABRT;
+                        break;
+                    }
+                }
+            }
+            if error != 0 {
+                self.set_command_lba(self.lba);
+                self.end_transfer();
+                self.complete(error);
+                return;
+            }
+        }
+
+        self.lba += n as u64;
+        self.sectors_left -= n;
+        self.sector_count = self.sector_count.wrapping_sub(n as u8);
+        // The registers hold the address of the last sector transferred
+        self.set_command_lba(self.lba - 1);
+
+        if self.sectors_left > 0 {
+            if writing {
+                self.request_block();
+                self.interrupt_pending = true;
+            }
+            else {
+                self.read_block();
+            }
+        }
+        else if writing {
+            self.end_transfer();
+            self.complete(0);
+        }
+        else {
+            // The interrupt for a read was delivered when the last block became ready
+            self.end_transfer();
+            self.error = 0;
+            self.status = ST_DRDY | ST_DSC;
+        }
+    }
+
+    fn command_verify(&mut self) {
+        let error = match self.command_lba() {
+            Some(lba) if lba + self.transfer_count() as u64 <= self.drives[self.selected()].sectors => {
+                self.set_command_lba(lba + self.transfer_count() as u64 - 1);
+                self.sector_count = 0;
+                0
+            }
+            _ => ERR_IDNF,
+        };
+        self.complete(error);
+    }
+
+    fn command_identify(&mut self) {
+        let drive = &self.drives[self.selected()];
+        let mut id = [0u16; 256];
+
+        let lba_sectors = drive.sectors.min(MAX_LBA28_SECTORS) as u32;
+        let cur_c = drive.cur_cylinders();
+        let cur_capacity = cur_c as u32 * drive.cur_heads as u32 * drive.cur_spt as u32;
+
+        id[0] = 0x0040; // Fixed disk
+        id[1] = drive.cylinders;
+        id[3] = drive.heads as u16;
+        id[4] = (drive.spt as u16) * SECTOR_SIZE as u16;
+        id[5] = SECTOR_SIZE as u16;
+        id[6] = drive.spt as u16;
+        put_ata_string(&mut id[10..20], "MARTYPC0001");
+        id[20] = 3; // Buffer type
+        id[21] = 16; // Buffer size in sectors
+        put_ata_string(&mut id[23..27], "1.0");
+        put_ata_string(&mut id[27..47], "MartyPC IDE Disk");
+        id[47] = 0x8000 | MAX_MULTIPLE as u16;
+        id[49] = 0x0200; // LBA supported
+        id[51] = 0x0200; // PIO mode 2 timing
+        id[53] = 0x0001; // Words 54-58 are valid
+        id[54] = cur_c;
+        id[55] = drive.cur_heads as u16;
+        id[56] = drive.cur_spt as u16;
+        id[57] = cur_capacity as u16;
+        id[58] = (cur_capacity >> 16) as u16;
+        if drive.multiple != 0 {
+            id[59] = 0x0100 | drive.multiple as u16;
+        }
+        id[60] = lba_sectors as u16;
+        id[61] = (lba_sectors >> 16) as u16;
+
+        self.buffer = id.iter().flat_map(|w| w.to_le_bytes()).collect();
+        self.buffer_pos = 0;
+        self.transfer = Transfer::Identify;
+        self.error = 0;
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+        self.interrupt_pending = true;
+    }
+
+    fn command_identify_packet(&mut self) {
+        let mut id = [0u16; 256];
+
+        id[0] = 0x8580; // ATAPI CD-ROM, removable, 12 byte packets
+        put_ata_string(&mut id[10..20], "MARTYPC0002");
+        put_ata_string(&mut id[23..27], "1.0");
+        put_ata_string(&mut id[27..47], "MartyPC ATAPI CD-ROM");
+        id[49] = 0x0200; // LBA supported
+        id[51] = 0x0200; // PIO mode 2 timing
+
+        self.buffer = id.iter().flat_map(|w| w.to_le_bytes()).collect();
+        self.buffer_pos = 0;
+        self.transfer = Transfer::Identify;
+        self.error = 0;
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+        self.interrupt_pending = true;
+    }
+
+    /// Start the Packet command by requesting the command packet from the host.
+    fn command_packet(&mut self) {
+        // The byte count limit must be even; zero is treated as the largest limit.
+        self.byte_limit = match self.cylinder {
+            0 => 0xFFFE,
+            1 => 2,
+            n => (n & !1) as usize,
+        };
+        self.buffer = vec![0; ATAPI_PACKET_SIZE];
+        self.buffer_pos = 0;
+        self.transfer = Transfer::Packet;
+        self.sector_count = IR_COD;
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+    }
+
+    fn execute_packet(&mut self) {
+        let cdb = std::mem::take(&mut self.buffer);
+        self.buffer_pos = 0;
+        let selected = self.selected();
+        let result = match &mut self.drives[selected].cdrom {
+            Some(cdrom) => cdrom.packet(&cdb),
+            None => return,
+        };
+
+        match result {
+            Ok(PacketResponse::Complete) => self.packet_complete(),
+            Ok(PacketResponse::Data(data)) => {
+                self.packet_data = data;
+                self.sectors_left = 0;
+                self.packet_data_phase();
+            }
+            Ok(PacketResponse::Sectors { lba, count }) => {
+                self.lba = lba as u64;
+                self.sectors_left = count as usize;
+                self.packet_data_phase();
+            }
+            Err(sense) => self.packet_error(sense),
+        }
+    }
+
+    /// Request the host to take the next part of a packet command's data, up to the byte count limit, or
+    /// complete the command if it has all been transferred.
+    fn packet_data_phase(&mut self) {
+        if self.packet_data.is_empty() && self.sectors_left > 0 {
+            let n = std::cmp::max(1, self.byte_limit / CD_SECTOR_SIZE).min(self.sectors_left);
+            let selected = self.selected();
+            let mut result = Ok(());
+            if let Some(cdrom) = &mut self.drives[selected].cdrom {
+                for i in 0..n {
+                    match cdrom.read_sector(self.lba as u32 + i as u32) {
+                        Ok(sector) => self.packet_data.extend(sector),
+                        Err(sense) => {
+                            result = Err(sense);
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Err(sense) = result {
+                self.packet_error(sense);
+                return;
+            }
+            self.lba += n as u64;
+            self.sectors_left -= n;
+        }
+        if self.packet_data.is_empty() {
+            self.packet_complete();
+            return;
+        }
+
+        let n = std::cmp::min(self.byte_limit, self.packet_data.len());
+        self.buffer = self.packet_data.drain(..n).collect();
+        if !self.eight_bit && n % 2 != 0 {
+            self.buffer.push(0);
+        }
+        self.buffer_pos = 0;
+        self.cylinder = n as u16;
+        self.sector_count = IR_IO;
+        self.transfer = Transfer::PacketData;
+        self.status = ST_DRDY | ST_DSC | ST_DRQ;
+        self.interrupt_pending = true;
+    }
+
+    fn packet_complete(&mut self) {
+        self.end_transfer();
+        self.sector_count = IR_IO | IR_COD;
+        self.complete(0);
+    }
+
+    fn packet_error(&mut self, sense: Sense) {
+        self.end_transfer();
+        self.sector_count = IR_IO | IR_COD;
+        self.complete((sense.key << 4) | ERR_ABRT);
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.status & ST_DRQ == 0
+            || !matches!(
+                self.transfer,
+                Transfer::Read | Transfer::Identify | Transfer::PacketData
+            )
+        {
+            return NO_IO_BYTE;
+        }
+
+        let byte = self.buffer[self.buffer_pos];
+        if self.eight_bit {
+            self.buffer_pos += 1;
+        }
+        else {
+            // A 16-bit transfer. Only XT-IDE can latch the high byte; otherwise it is lost.
+            self.data_latch = self.buffer[self.buffer_pos + 1];
+            self.buffer_pos += 2;
+        }
+
+        if self.buffer_pos >= self.buffer.len() {
+            match self.transfer {
+                Transfer::Identify => self.end_transfer(),
+                Transfer::PacketData => self.packet_data_phase(),
+                _ => self.buffer_done(),
+            }
+        }
+        byte
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if self.status & ST_DRQ == 0 || !matches!(self.transfer, Transfer::Write | Transfer::Packet) {
+            return;
+        }
+
+        self.buffer[self.buffer_pos] = data;
+        if self.eight_bit {
+            self.buffer_pos += 1;
+        }
+        else {
+            self.buffer[self.buffer_pos + 1] = match self.layout {
+                IdeRegisterLayout::XtIde => self.data_latch,
+                IdeRegisterLayout::Ata8Bit => 0,
+            };
+            self.buffer_pos += 2;
+        }
+
+        if self.buffer_pos >= self.buffer.len() {
+            if self.transfer == Transfer::Packet {
+                self.execute_packet();
+            }
+            else {
+                self.buffer_done();
+            }
+        }
+    }
+
+    fn write_device_control(&mut self, data: u8) {
+        if self.device_control & DC_SRST != 0 && data & DC_SRST == 0 {
+            // Software reset released
+            log::debug!("IDE: Software reset");
+            self.reset();
+        }
+        else if data & DC_SRST != 0 {
+            self.status = ST_BSY;
+        }
+        self.device_control = data;
+    }
+}
+
+/// Store an ATA identify string, which is space padded with the first character of each pair in the high byte.
+fn put_ata_string(words: &mut [u16], s: &str) {
+    let mut bytes = s.bytes().chain(std::iter::repeat(b' '));
+    for word in words.iter_mut() {
+        let hi = bytes.next().unwrap_or(b' ');
+        let lo = bytes.next().unwrap_or(b' ');
+        *word = (hi as u16) << 8 | lo as u16;
+    }
+}
+
+impl IoDevice for XtIdeController {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        let control_base = self.control_base();
+        if port == control_base + REG_ALT_STATUS {
+            return self.read_status();
+        }
+        if self.layout == IdeRegisterLayout::XtIde && port == control_base + REG_DATA_HI {
+            return self.data_latch;
+        }
+
+        match port.wrapping_sub(self.io_base) {
+            REG_DATA => self.read_data(),
+            REG_ERROR => self.error,
+            REG_SECTOR_COUNT => self.sector_count,
+            REG_SECTOR => self.sector,
+            REG_CYLINDER_LO => self.cylinder as u8,
+            REG_CYLINDER_HI => (self.cylinder >> 8) as u8,
+            REG_DRIVE_HEAD => self.drive_head | 0xA0,
+            REG_STATUS => {
+                // Reading the status register acknowledges the interrupt
+                self.interrupt_pending = false;
+                self.read_status()
+            }
+            _ => NO_IO_BYTE,
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        let control_base = self.control_base();
+        if port == control_base + REG_ALT_STATUS {
+            self.write_device_control(data);
+            return;
+        }
+        if self.layout == IdeRegisterLayout::XtIde && port == control_base + REG_DATA_HI {
+            self.data_latch = data;
+            return;
+        }
+
+        match port.wrapping_sub(self.io_base) {
+            REG_DATA => self.write_data(data),
+            REG_ERROR => self.features = data,
+            REG_SECTOR_COUNT => self.sector_count = data,
+            REG_SECTOR => self.sector = data,
+            REG_CYLINDER_LO => self.cylinder = (self.cylinder & 0xFF00) | data as u16,
+            REG_CYLINDER_HI => self.cylinder = (self.cylinder & 0x00FF) | (data as u16) << 8,
+            REG_DRIVE_HEAD => self.drive_head = data & 0x5F,
+            REG_STATUS => self.write_command(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<(String, u16)> {
+        let names = [
+            "IDE Data",
+            "IDE Error/Features",
+            "IDE Sector Count",
+            "IDE Sector Number",
+            "IDE Cylinder Low",
+            "IDE Cylinder High",
+            "IDE Drive/Head",
+            "IDE Status/Command",
+        ];
+        let mut ports: Vec<(String, u16)> = names
+            .iter()
+            .enumerate()
+            .map(|(offset, name)| (name.to_string(), self.io_base + offset as u16))
+            .collect();
+
+        let control_base = self.control_base();
+        if self.layout == IdeRegisterLayout::XtIde {
+            ports.push(("IDE Data High".to_string(), control_base + REG_DATA_HI));
+        }
+        ports.push((
+            "IDE Alt Status/Device Control".to_string(),
+            control_base + REG_ALT_STATUS,
+        ));
+        ports
+    }
+}
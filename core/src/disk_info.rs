@@ -0,0 +1,470 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    disk_info.rs
+
+    Identify disk images and what is on them.
+
+    Floppy images are identified by format, and hard disk images as VHD or
+    raw sector images. The boot sector of the disk, or of the active
+    partition of a partitioned hard disk, is then examined for a FAT file
+    system. The volume label is read from the root directory, and the
+    operating system is recognized from the system files DOS places at the
+    start of the root directory of a bootable disk.
+
+*/
+
+use std::fmt::Display;
+
+use anyhow::Error;
+
+use crate::{
+    floppy_image::{d86f, fat12::FatParams, imd, pri, psi, scp, td0, FloppyImage, RAW_SECTOR_SIZE},
+    vhd::VirtualHardDisk,
+};
+
+const DIR_ENTRY_LEN: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LFN: u8 = 0x0F;
+// A FAT with fewer clusters than this is a FAT12 FAT.
+const FAT12_MAX_CLUSTERS: usize = 4085;
+const PARTITION_TABLE_OFFSET: usize = 0x1BE;
+
+// Strings identifying the boot sectors of systems that don't boot from a FAT file system.
+const BOOT_SIGNATURES: [(&[u8], &str); 4] = [
+    (b"ELKS", "ELKS"),
+    (b"MINIX", "Minix"),
+    (b"Minix", "Minix"),
+    (b"CP/M", "CP/M-86"),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    RawSector,
+    D86f,
+    Scp,
+    Imd,
+    Td0,
+    Psi,
+    Pri,
+    Vhd,
+    RawHardDisk,
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageFormat::RawSector => write!(f, "Raw sector image"),
+            ImageFormat::D86f => write!(f, "86Box 86F"),
+            ImageFormat::Scp => write!(f, "SuperCard Pro"),
+            ImageFormat::Imd => write!(f, "ImageDisk"),
+            ImageFormat::Td0 => write!(f, "Teledisk"),
+            ImageFormat::Psi => write!(f, "PCE PSI"),
+            ImageFormat::Pri => write!(f, "PCE PRI"),
+            ImageFormat::Vhd => write!(f, "VHD"),
+            ImageFormat::RawHardDisk => write!(f, "Raw hard disk image"),
+        }
+    }
+}
+
+/// An entry in the partition table of a hard disk.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    pub kind:    u8,
+    pub active:  bool,
+    pub start:   u32,
+    pub sectors: u32,
+}
+
+impl Partition {
+    pub fn type_name(&self) -> &'static str {
+        match self.kind {
+            0x01 => "FAT12",
+            0x04 => "FAT16 (<32MB)",
+            0x05 | 0x0F => "Extended",
+            0x06 | 0x0E => "FAT16",
+            0x07 => "NTFS/HPFS",
+            0x0B | 0x0C => "FAT32",
+            0x63 => "Unix System V",
+            0x80 | 0x81 => "Minix",
+            0x82 | 0x83 => "Linux",
+            0xDB => "CP/M-86",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// What was found in the boot sector and root directory of a volume.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VolumeInfo {
+    pub oem_name: Option<String>,
+    pub label: Option<String>,
+    pub file_system: Option<&'static str>,
+    pub os: Option<String>,
+    pub bootable: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiskInfo {
+    pub format: ImageFormat,
+    pub geometry: Option<(u32, u32, u32)>, // Cylinders, heads, sectors per track
+    pub capacity: u64,                     // Bytes of sector data
+    pub partitions: Vec<Partition>,
+    pub volume: Option<VolumeInfo>,
+}
+
+/// Identify the format of a floppy image and what is on the disk.
+pub fn identify_floppy(data: &[u8]) -> Result<DiskInfo, Error> {
+    let format = if d86f::detect(data) {
+        ImageFormat::D86f
+    }
+    else if scp::detect(data) {
+        ImageFormat::Scp
+    }
+    else if imd::detect(data) {
+        ImageFormat::Imd
+    }
+    else if td0::detect(data) {
+        ImageFormat::Td0
+    }
+    else if psi::detect(data) {
+        ImageFormat::Psi
+    }
+    else if pri::detect(data) {
+        ImageFormat::Pri
+    }
+    else {
+        ImageFormat::RawSector
+    };
+    let image = FloppyImage::load(data)?;
+
+    let mut capacity = 0;
+    for c in 0..image.cylinders() {
+        for h in 0..image.heads() {
+            if let Some(track) = image.track(c, h) {
+                capacity += track.sectors.iter().map(|s| s.id.size() as u64).sum::<u64>();
+            }
+        }
+    }
+    let spt = match image.standard_geometry() {
+        Some(geom) => geom.s() as usize,
+        None => image.track(0, 0).map_or(0, |t| t.sectors.len()),
+    };
+    let geometry = Some((image.cylinders() as u32, image.heads() as u32, spt as u32));
+
+    // Logical sectors are located using the geometry in the BPB where there is one, so that disks with
+    // non-standard tracks elsewhere can still be read.
+    let boot = find_floppy_sector(&image, 0, 0, 1);
+    let (bpb_spt, bpb_heads) = match boot.as_deref().and_then(FatParams::from_boot_sector) {
+        Some(params) => (params.sectors_per_track, params.heads),
+        None => (spt, image.heads() as usize),
+    };
+    let mut read = |lba: u64| -> Option<Vec<u8>> {
+        if bpb_spt == 0 || bpb_heads == 0 {
+            return None;
+        }
+        let lba = lba as usize;
+        let c = lba / (bpb_spt * bpb_heads);
+        let h = (lba / bpb_spt) % bpb_heads;
+        let r = lba % bpb_spt + 1;
+        if c > u8::MAX as usize || r > u8::MAX as usize {
+            return None;
+        }
+        find_floppy_sector(&image, c as u8, h as u8, r as u8)
+    };
+    // Disks formatted by DOS 1.x have no BPB, so fall back to the layout DOS uses for disks of this size.
+    let volume = detect_volume(&mut read, 0, FatParams::for_size(capacity as usize));
+
+    Ok(DiskInfo {
+        format,
+        geometry,
+        capacity,
+        partitions: Vec::new(),
+        volume,
+    })
+}
+
+/// Identify what is on a hard disk image, examining the active partition if the disk is partitioned.
+pub fn identify_hard_disk(vhd: &mut VirtualHardDisk) -> DiskInfo {
+    let format = if vhd.is_raw() {
+        ImageFormat::RawHardDisk
+    }
+    else {
+        ImageFormat::Vhd
+    };
+    let sector_ct = vhd.sector_count();
+    let mut read = |lba: u64| -> Option<Vec<u8>> {
+        let mut buf = vec![0; RAW_SECTOR_SIZE];
+        vhd.read_sector_lba(&mut buf, lba).ok().map(|_| buf)
+    };
+
+    let mut partitions = Vec::new();
+    let mbr = read(0);
+    // A disk may also hold a single volume without a partition table, like a floppy.
+    let unpartitioned = mbr.as_deref().and_then(FatParams::from_boot_sector).is_some();
+    if let (Some(mbr), false) = (&mbr, unpartitioned) {
+        if mbr[510..512] == [0x55, 0xAA] {
+            for entry in mbr[PARTITION_TABLE_OFFSET..510].chunks_exact(16) {
+                let partition = Partition {
+                    kind:    entry[4],
+                    active:  entry[0] == 0x80,
+                    start:   u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]),
+                    sectors: u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]),
+                };
+                let valid = partition.kind != 0
+                    && entry[0] & 0x7F == 0
+                    && partition.start > 0
+                    && partition.start as u64 + partition.sectors as u64 <= sector_ct;
+                if valid {
+                    partitions.push(partition);
+                }
+            }
+        }
+    }
+
+    let volume_start = match partitions.iter().find(|p| p.active).or(partitions.first()) {
+        Some(partition) => partition.start as u64,
+        None => 0,
+    };
+    let volume = detect_volume(&mut read, volume_start, None);
+
+    DiskInfo {
+        format,
+        geometry: Some((vhd.max_cylinders, vhd.max_heads, vhd.max_sectors)),
+        capacity: sector_ct * RAW_SECTOR_SIZE as u64,
+        partitions,
+        volume,
+    }
+}
+
+fn find_floppy_sector(image: &FloppyImage, c: u8, h: u8, r: u8) -> Option<Vec<u8>> {
+    image
+        .track(c, h)?
+        .sectors
+        .iter()
+        .find(|s| s.id.r == r && !s.no_data)
+        .map(|s| s.read_data(RAW_SECTOR_SIZE))
+}
+
+fn printable(bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|b| (0x20..0x7F).contains(b)) {
+        let s = String::from_utf8_lossy(bytes).trim().to_string();
+        (!s.is_empty()).then_some(s)
+    }
+    else {
+        None
+    }
+}
+
+/// Examine the volume starting at the specified sector. Returns None if the boot sector is unreadable or blank.
+/// `fallback` provides the file system layout to use when the boot sector has no BPB.
+pub fn detect_volume(
+    read: &mut dyn FnMut(u64) -> Option<Vec<u8>>,
+    start: u64,
+    fallback: Option<FatParams>,
+) -> Option<VolumeInfo> {
+    let boot = read(start)?;
+    if boot.iter().all(|b| *b == boot[0]) {
+        return None;
+    }
+    let signature = boot[510..512] == [0x55, 0xAA];
+    let jump = boot[0] == 0xEB || boot[0] == 0xE9;
+
+    let bpb = FatParams::from_boot_sector(&boot);
+    let mut info = VolumeInfo {
+        oem_name: if jump { printable(&boot[3..11]) } else { None },
+        ..Default::default()
+    };
+
+    // Without a BPB, the disk could still be a DOS 1.x disk, which is identified by the media byte at the start of
+    // its FAT.
+    let params = match (bpb, fallback) {
+        (Some(params), _) => Some(params),
+        (None, Some(params)) => read(start + params.reserved_sectors as u64)
+            .filter(|fat| fat[0] == params.media && fat[1] == 0xFF && fat[2] == 0xFF)
+            .map(|_| params),
+        (None, None) => None,
+    };
+
+    let params = match params {
+        Some(params) => params,
+        None => {
+            // Something that isn't DOS. If it looks like boot code, try to recognize it.
+            if signature || jump {
+                info.bootable = true;
+                info.os = BOOT_SIGNATURES
+                    .iter()
+                    .find(|(sig, _)| boot.windows(sig.len()).any(|w| w == *sig))
+                    .map(|(_, name)| name.to_string());
+            }
+            return Some(info);
+        }
+    };
+
+    let clusters = (params.total_sectors - params.data_start()) / params.sectors_per_cluster;
+    info.file_system = Some(if clusters < FAT12_MAX_CLUSTERS {
+        "FAT12"
+    }
+    else {
+        "FAT16"
+    });
+    if bpb.is_some() && boot[0x26] == 0x29 {
+        info.label = printable(&boot[0x2B..0x36]).filter(|label| label != "NO NAME");
+    }
+
+    // The label in the root directory takes precedence over the one in the boot sector, as it does in DOS.
+    let mut names = Vec::new();
+    'root: for i in 0..params.root_sectors() {
+        let sector = match read(start + (params.root_start() + i) as u64) {
+            Some(sector) => sector,
+            None => break,
+        };
+        for entry in sector.chunks_exact(DIR_ENTRY_LEN) {
+            match entry[0] {
+                0x00 => break 'root,
+                0xE5 => continue,
+                _ => {}
+            }
+            let attributes = entry[11];
+            if attributes & ATTR_LFN == ATTR_LFN {
+                continue;
+            }
+            if attributes & ATTR_VOLUME_ID != 0 {
+                info.label = printable(&entry[0..11]).or(info.label);
+                continue;
+            }
+            let base = String::from_utf8_lossy(&entry[0..8]).trim_end().to_string();
+            let ext = String::from_utf8_lossy(&entry[8..11]).trim_end().to_string();
+            names.push(format!("{}.{}", base, ext));
+        }
+    }
+
+    info.os = detect_dos(&names, info.oem_name.as_deref());
+    info.bootable = info.os.is_some();
+    Some(info)
+}
+
+/// Recognize a DOS from the system files in the root directory, taking the version from the OEM name where the
+/// formatting DOS recorded one.
+fn detect_dos(names: &[String], oem: Option<&str>) -> Option<String> {
+    let has = |name: &str| names.iter().any(|n| n == name);
+    let oem = oem.unwrap_or("");
+
+    let (os, version_prefix) = if has("IBMBIO.COM") && has("IBMDOS.COM") {
+        if oem.starts_with("DRDOS") || oem.starts_with("NWDOS") {
+            ("DR DOS", "DRDOS")
+        }
+        else {
+            ("PC DOS", "IBM")
+        }
+    }
+    else if has("IO.SYS") && has("MSDOS.SYS") {
+        if oem.starts_with("MSWIN4") {
+            ("Windows 9x", "MSWIN")
+        }
+        else {
+            ("MS-DOS", "MSDOS")
+        }
+    }
+    else if has("DRBIOS.SYS") && has("DRBDOS.SYS") {
+        ("DR DOS", "DRDOS")
+    }
+    else if has("KERNEL.SYS") {
+        ("FreeDOS", "FRDOS")
+    }
+    else {
+        return None;
+    };
+
+    let version = oem.strip_prefix(version_prefix).map(|v| v.trim()).filter(|v| {
+        let mut chars = v.chars();
+        chars.next().is_some_and(|c| c.is_ascii_digit()) && chars.next() == Some('.')
+    });
+    match version {
+        Some(version) if os != "Windows 9x" => Some(format!("{} {}", os, version)),
+        _ => Some(os.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::floppy_image::fat12::{build_volume, FatEntry};
+
+    #[test]
+    fn test_identify_floppy() {
+        let mut image = build_volume(
+            368_640,
+            Some("SYSTEM"),
+            &[
+                FatEntry::file("IBMBIO.COM", vec![0x90; 100]),
+                FatEntry::file("IBMDOS.COM", vec![0x90; 100]),
+                FatEntry::file("COMMAND.COM", vec![0x90; 100]),
+            ],
+        )
+        .unwrap();
+        image[3..11].copy_from_slice(b"IBM  3.3");
+
+        let info = identify_floppy(&image).unwrap();
+        assert_eq!(info.format, ImageFormat::RawSector);
+        assert_eq!(info.geometry, Some((40, 2, 9)));
+        assert_eq!(info.capacity, 368_640);
+        let volume = info.volume.unwrap();
+        assert_eq!(volume.label.as_deref(), Some("SYSTEM"));
+        assert_eq!(volume.file_system, Some("FAT12"));
+        assert_eq!(volume.os.as_deref(), Some("PC DOS 3.3"));
+        assert!(volume.bootable);
+    }
+
+    #[test]
+    fn test_identify_data_disk() {
+        let image = build_volume(737_280, None, &[FatEntry::file("README.TXT", b"hello".to_vec())]).unwrap();
+        let volume = identify_floppy(&image).unwrap().volume.unwrap();
+        assert_eq!(volume.oem_name.as_deref(), Some("MARTYPC"));
+        assert_eq!(volume.label, None);
+        assert_eq!(volume.os, None);
+        assert!(!volume.bootable);
+
+        // A blank disk has no volume.
+        assert_eq!(identify_floppy(&vec![0xF6; 368_640]).unwrap().volume, None);
+    }
+
+    #[test]
+    fn test_dos1_disk() {
+        // Remove the BPB, as DOS 1.x didn't write one.
+        let mut image = build_volume(
+            163_840,
+            None,
+            &[FatEntry::file("IO.SYS", vec![1]), FatEntry::file("MSDOS.SYS", vec![2])],
+        )
+        .unwrap();
+        image[0x0B..0x3E].fill(0);
+        let volume = identify_floppy(&image).unwrap().volume.unwrap();
+        assert_eq!(volume.file_system, Some("FAT12"));
+        assert_eq!(volume.os.as_deref(), Some("MS-DOS"));
+    }
+}
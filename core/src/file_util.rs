@@ -29,7 +29,10 @@
     Miscellaneous file utility routines.
 */
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub fn find_unique_filename(path: &Path, base: &str, ext: &str) -> PathBuf {
     let mut i = 1;
@@ -42,3 +45,76 @@ pub fn find_unique_filename(path: &Path, base: &str, ext: &str) -> PathBuf {
 
     test_path
 }
+
+/// Find a unique filename in the specified directory, named with the current UTC date and time,
+/// ie, 'screenshot_2024-05-01_13-45-12.png'. A counter is appended if the file already exists.
+pub fn find_unique_timestamped_filename(path: &Path, base: &str, ext: &str) -> PathBuf {
+    let (year, month, day, hour, minute, second) = utc_date_time(SystemTime::now());
+    let stamp = format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year, month, day, hour, minute, second
+    );
+
+    let mut i = 1;
+    let mut test_path = path.join(format!("{}_{}.{}", base, stamp, ext));
+
+    while test_path.exists() {
+        i += 1;
+        test_path = path.join(format!("{}_{}_{}.{}", base, stamp, i, ext));
+    }
+
+    test_path
+}
+
+/// Break the specified time down into a UTC (year, month, day, hour, minute, second) tuple.
+pub fn utc_date_time(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let tod = (secs % 86400) as u32;
+    (year, month, day, tod / 3600, (tod % 3600) / 60, tod % 60)
+}
+
+/// Convert a count of days since the Unix epoch into a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+
+        // 2000 is divisible by 400, so it is a leap year.
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+
+        // 2100 is divisible by 100 but not 400, so it is not.
+        assert_eq!(civil_from_days(47482), (2100, 1, 1));
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn test_utc_date_time() {
+        assert_eq!(utc_date_time(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+
+        let time = UNIX_EPOCH + Duration::from_secs(11016 * 86400 + 13 * 3600 + 45 * 60 + 12);
+        assert_eq!(utc_date_time(time), (2000, 2, 29, 13, 45, 12));
+    }
+}
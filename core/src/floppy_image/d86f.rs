@@ -0,0 +1,276 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::d86f.rs
+
+    Loader for 86F bitstream images, as produced by 86Box.
+
+    An 86F image stores the MFM bit cells of each track along with an
+    optional surface description, which marks cells that are weak or have
+    no flux transitions at all. Tracks may be longer or shorter than the
+    nominal track length. Each track is decoded into sectors with their
+    positions, weak bits and recorded length.
+
+    FM and GCR encoded tracks and zoned (variable speed) disks are not
+    supported.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+use crate::floppy_image::{
+    mfm::{self, Bitstream},
+    FloppyImage,
+};
+
+pub const D86F_MAGIC: &[u8; 4] = b"86BF";
+pub const D86F_HEADER_LEN: usize = 8;
+pub const D86F_MAX_TRACKS: usize = 512;
+
+const DISK_HAS_SURFACE: u16 = 0x0001;
+const DISK_DOUBLE_SIDED: u16 = 0x0008;
+const DISK_RPM_SLOWDOWN_MASK: u16 = 0x0060;
+const DISK_BITCELL_MODE: u16 = 0x0080;
+const DISK_ZONED: u16 = 0x0100;
+const DISK_WORD_ORDER: u16 = 0x0800;
+const DISK_RPM_SPEEDUP: u16 = 0x1000;
+
+const TRACK_RATE_MASK: u16 = 0x0007;
+const TRACK_ENCODING_MASK: u16 = 0x0018;
+const TRACK_ENCODING_MFM: u16 = 0x0008;
+const TRACK_RPM_MASK: u16 = 0x00E0;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= D86F_HEADER_LEN && &data[0..4] == D86F_MAGIC
+}
+
+/// Load an 86F image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    if !detect(data) {
+        return Err(anyhow!("Not an 86F image"));
+    }
+    let (minor, major) = (data[4], data[5]);
+    if major != 2 {
+        return Err(anyhow!("Unsupported 86F version: {}.{}", major, minor));
+    }
+
+    let disk_flags = u16::from_le_bytes([data[6], data[7]]);
+    if disk_flags & DISK_ZONED != 0 {
+        return Err(anyhow!("Zoned 86F images are not supported"));
+    }
+    let heads: usize = if disk_flags & DISK_DOUBLE_SIDED != 0 { 2 } else { 1 };
+
+    // Disks written on a drive running slightly slow have proportionally longer tracks.
+    let slowdown = match (disk_flags & DISK_RPM_SLOWDOWN_MASK) >> 5 {
+        1 => 0.01,
+        2 => 0.015,
+        3 => 0.02,
+        _ => 0.0,
+    };
+    let speed_factor = if disk_flags & DISK_RPM_SPEEDUP != 0 {
+        1.0 - slowdown
+    }
+    else {
+        1.0 + slowdown
+    };
+
+    let mut offsets = Vec::new();
+    for entry in data[D86F_HEADER_LEN..].chunks_exact(4).take(D86F_MAX_TRACKS) {
+        let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+        if offset == 0 {
+            break;
+        }
+        offsets.push(offset);
+    }
+    if offsets.is_empty() {
+        return Err(anyhow!("86F image contains no tracks"));
+    }
+
+    let cylinders = offsets.len().div_ceil(heads);
+    if cylinders > u8::MAX as usize {
+        return Err(anyhow!(
+            "86F image has {} cylinders, more than the {} supported",
+            cylinders,
+            u8::MAX
+        ));
+    }
+    let mut image = FloppyImage::new(cylinders as u8, heads as u8);
+
+    for (idx, offset) in offsets.iter().enumerate() {
+        let (c, h) = ((idx / heads) as u8, (idx % heads) as u8);
+        let mut pos = *offset;
+        let header = data
+            .get(pos..pos + 2)
+            .ok_or_else(|| anyhow!("86F track {} offset out of range", idx))?;
+        let track_flags = u16::from_le_bytes([header[0], header[1]]);
+        pos += 2;
+
+        let mut extra_cells: i64 = 0;
+        if disk_flags & DISK_BITCELL_MODE != 0 {
+            extra_cells = read_u32(data, pos)? as i32 as i64;
+            pos += 4;
+        }
+        let index_pos = read_u32(data, pos)? as usize;
+        pos += 4;
+
+        let rate_kbps: f64 = match track_flags & TRACK_RATE_MASK {
+            0 => 500.0,
+            1 => 300.0,
+            2 => 250.0,
+            3 => 1000.0,
+            _ => return Err(anyhow!("86F track {} has an invalid data rate", idx)),
+        };
+        let rpm: f64 = match (track_flags & TRACK_RPM_MASK) >> 5 {
+            0 => 300.0,
+            1 => 360.0,
+            _ => return Err(anyhow!("86F track {} has an unsupported rotation speed", idx)),
+        };
+
+        // MFM at a given data rate has two bit cells per data bit.
+        let nominal_cells = (rate_kbps * 1000.0 * 2.0 * 60.0 / rpm * speed_factor) as i64;
+        let cells = std::cmp::max(nominal_cells + extra_cells, 0) as usize;
+        let byte_len = cells.div_ceil(16) * 2;
+
+        let bits = data
+            .get(pos..pos + byte_len)
+            .ok_or_else(|| anyhow!("86F track {} is truncated", idx))?;
+        pos += byte_len;
+        let surface = if disk_flags & DISK_HAS_SURFACE != 0 {
+            Some(
+                data.get(pos..pos + byte_len)
+                    .ok_or_else(|| anyhow!("86F track {} surface data is truncated", idx))?,
+            )
+        }
+        else {
+            None
+        };
+
+        if track_flags & TRACK_ENCODING_MASK != TRACK_ENCODING_MFM {
+            log::warn!(
+                "86F track c:{} h:{} is not MFM encoded and will read as unformatted",
+                c,
+                h
+            );
+            continue;
+        }
+
+        let word_order = disk_flags & DISK_WORD_ORDER != 0;
+        let stream = Bitstream::from_bytes(
+            cell_bytes(bits, word_order),
+            surface.map(|surface| cell_bytes(surface, word_order)),
+            cells,
+        );
+        image.set_track(c, h, mfm::decode_track(&stream.rotate(index_pos)));
+    }
+
+    Ok(image)
+}
+
+/// Return the bit cells of a track as bytes read most significant bit first. Images with the word order flag set
+/// store cells in little-endian 16-bit words.
+fn cell_bytes(data: &[u8], word_order: bool) -> Vec<u8> {
+    if word_order {
+        data.chunks(2).flat_map(|word| word.iter().rev().copied()).collect()
+    }
+    else {
+        data.to_vec()
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Unexpected end of 86F image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::floppy_image::FloppyTrack;
+
+    #[test]
+    fn test_load_weak_sector() {
+        let mut track = FloppyTrack::standard(0, 0, 9, 0xE5);
+        track.sectors[2].data = vec![0x55; 512];
+        let stream = mfm::encode_track(&track, 6250, 84);
+        let bits = stream.as_bytes();
+
+        // Mark the middle of the third sector's data field as weak
+        let mut surface = vec![0u8; bits.len()];
+        let sector3 = track.sectors[2].id;
+        let decoded = mfm::decode_track(&stream);
+        let start = decoded
+            .sectors
+            .iter()
+            .find(|s| s.id == sector3)
+            .unwrap()
+            .position
+            .unwrap()
+            + 100;
+        for byte in &mut surface[start * 2..start * 2 + 32] {
+            *byte = 0xFF;
+        }
+
+        let mut file = Vec::new();
+        file.extend_from_slice(D86F_MAGIC);
+        file.extend_from_slice(&[0x0C, 0x02]);
+        file.extend_from_slice(&DISK_HAS_SURFACE.to_le_bytes());
+        let track_offset = D86F_HEADER_LEN + D86F_MAX_TRACKS * 4;
+        file.extend_from_slice(&(track_offset as u32).to_le_bytes());
+        file.resize(track_offset, 0);
+        file.extend_from_slice(&(TRACK_ENCODING_MFM | 2).to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(bits);
+        file.extend_from_slice(&surface);
+
+        let image = load(&file).unwrap();
+        assert_eq!(image.cylinders(), 1);
+        let loaded = image.track(0, 0).unwrap();
+        assert_eq!(loaded.sectors.len(), 9);
+        assert_eq!(loaded.recorded_len, Some(6250));
+
+        let weak = loaded.sectors.iter().find(|s| s.id == sector3).unwrap();
+        let mask = weak.weak.as_ref().unwrap();
+        assert!(mask[..52].iter().all(|b| *b == 0));
+        assert!(mask[52..68].iter().all(|b| *b == 0xFF));
+        assert!(mask[68..].iter().all(|b| *b == 0));
+        assert_eq!(loaded.sectors.iter().filter(|s| s.weak.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn test_too_many_cylinders() {
+        let mut file = Vec::new();
+        file.extend_from_slice(D86F_MAGIC);
+        file.extend_from_slice(&[0x0C, 0x02]);
+        file.extend_from_slice(&0u16.to_le_bytes());
+        for _ in 0..256 {
+            file.extend_from_slice(&((D86F_HEADER_LEN + D86F_MAX_TRACKS * 4) as u32).to_le_bytes());
+        }
+        file.resize(D86F_HEADER_LEN + D86F_MAX_TRACKS * 4, 0);
+
+        assert!(load(&file).is_err_and(|e| e.to_string().contains("256 cylinders")));
+    }
+}
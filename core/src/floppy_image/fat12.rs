@@ -0,0 +1,739 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    floppy_image::fat12.rs
+
+    Build and read FAT12 file systems on raw floppy sector images.
+
+    A volume is built from a tree of files and directories using the BPB
+    parameters DOS uses for each standard disk size, with files stored in
+    contiguous clusters. Reading a volume walks the directory tree of any
+    FAT12 disk, so files written by the guest can be extracted again. Disks
+    without a valid BPB, such as those formatted by DOS 1.x, are read using
+    the parameters for their size.
+
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+
+use crate::floppy_image::RAW_SECTOR_SIZE;
+
+const DIR_ENTRY_LEN: usize = 32;
+const FAT_FREE: u16 = 0x000;
+const FAT_EOC: u16 = 0xFFF;
+// Cluster values from 0xFF8 onwards mark the end of a chain.
+const FAT_EOC_MIN: u16 = 0xFF8;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+// Long file name entries set all of the low four attribute bits.
+const ATTR_LFN: u8 = 0x0F;
+
+// A non-system disk boot sector: print a message, wait for a key and retry the boot.
+const BOOT_CODE_OFFSET: usize = 0x3E;
+const BOOT_CODE: [u8; 27] = [
+    0x31, 0xC0, // xor ax, ax
+    0x8E, 0xD8, // mov ds, ax
+    0xBE, 0x59, 0x7C, // mov si, 7C59h
+    0xBB, 0x07, 0x00, // mov bx, 0007h
+    0xAC, // lodsb
+    0x0A, 0xC0, // or al, al
+    0x74, 0x06, // jz +6
+    0xB4, 0x0E, // mov ah, 0Eh
+    0xCD, 0x10, // int 10h
+    0xEB, 0xF5, // jmp lodsb
+    0x32, 0xE4, // xor ah, ah
+    0xCD, 0x16, // int 16h
+    0xCD, 0x19, // int 19h
+];
+const BOOT_MESSAGE: &[u8] = b"Non-system disk\r\nPress any key to reboot\r\n\0";
+
+/// The layout of a FAT12 volume, as described by the BIOS Parameter Block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FatParams {
+    pub total_sectors: usize,
+    pub sectors_per_cluster: usize,
+    pub reserved_sectors: usize,
+    pub fat_ct: usize,
+    pub root_entries: usize,
+    pub media: u8,
+    pub fat_sectors: usize,
+    pub sectors_per_track: usize,
+    pub heads: usize,
+}
+
+impl FatParams {
+    /// Return the parameters DOS uses when formatting a disk of the given size in bytes.
+    pub fn for_size(size: usize) -> Option<Self> {
+        // (total sectors, sectors per cluster, root entries, media, FAT sectors, sectors per track, heads)
+        let (total_sectors, sectors_per_cluster, root_entries, media, fat_sectors, sectors_per_track, heads) =
+            match size {
+                163_840 => (320, 1, 64, 0xFE, 1, 8, 1),
+                184_320 => (360, 1, 64, 0xFC, 2, 9, 1),
+                327_680 => (640, 2, 112, 0xFF, 1, 8, 2),
+                368_640 => (720, 2, 112, 0xFD, 2, 9, 2),
+                737_280 => (1440, 2, 112, 0xF9, 3, 9, 2),
+                1_228_800 => (2400, 1, 224, 0xF9, 7, 15, 2),
+                1_474_560 => (2880, 1, 224, 0xF0, 9, 18, 2),
+                2_949_120 => (5760, 2, 240, 0xF0, 9, 36, 2),
+                _ => return None,
+            };
+        Some(Self {
+            total_sectors,
+            sectors_per_cluster,
+            reserved_sectors: 1,
+            fat_ct: 2,
+            root_entries,
+            media,
+            fat_sectors,
+            sectors_per_track,
+            heads,
+        })
+    }
+
+    /// Read the parameters from the BPB in the boot sector of a volume, if it has a plausible one. Volumes of
+    /// 32MB or more store their sector count in the DOS 3.31 extended field.
+    pub fn from_boot_sector(boot: &[u8]) -> Option<Self> {
+        if boot.len() < RAW_SECTOR_SIZE {
+            return None;
+        }
+        let word = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as usize;
+        let total_sectors = match word(0x13) {
+            0 => u32::from_le_bytes([boot[0x20], boot[0x21], boot[0x22], boot[0x23]]) as usize,
+            total => total,
+        };
+        let params = Self {
+            total_sectors,
+            sectors_per_cluster: boot[0x0D] as usize,
+            reserved_sectors: word(0x0E),
+            fat_ct: boot[0x10] as usize,
+            root_entries: word(0x11),
+            media: boot[0x15],
+            fat_sectors: word(0x16),
+            sectors_per_track: word(0x18),
+            heads: word(0x1A),
+        };
+
+        let valid = word(0x0B) == RAW_SECTOR_SIZE
+            && params.sectors_per_cluster.is_power_of_two()
+            && params.reserved_sectors > 0
+            && params.fat_ct > 0
+            && params.root_entries > 0
+            && params.fat_sectors > 0
+            && params.media >= 0xF0
+            && params.data_start() < params.total_sectors;
+        valid.then_some(params)
+    }
+
+    /// Return the number of sectors occupied by the root directory.
+    pub fn root_sectors(&self) -> usize {
+        (self.root_entries * DIR_ENTRY_LEN).div_ceil(RAW_SECTOR_SIZE)
+    }
+
+    fn fat_start(&self) -> usize {
+        self.reserved_sectors
+    }
+
+    /// Return the first sector of the root directory.
+    pub fn root_start(&self) -> usize {
+        self.reserved_sectors + self.fat_ct * self.fat_sectors
+    }
+
+    /// Return the first sector of the data area, where cluster 2 begins.
+    pub fn data_start(&self) -> usize {
+        self.root_start() + self.root_sectors()
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster * RAW_SECTOR_SIZE
+    }
+
+    /// Return the number of data clusters on the volume. Clusters are numbered from 2.
+    pub fn cluster_ct(&self) -> usize {
+        // A FAT can't describe more clusters than it has room for.
+        let fat_clusters = (self.fat_sectors * RAW_SECTOR_SIZE * 2 / 3).saturating_sub(2);
+        ((self.total_sectors - self.data_start()) / self.sectors_per_cluster).min(fat_clusters)
+    }
+
+    /// Return the number of bytes available for files and subdirectories.
+    pub fn capacity(&self) -> usize {
+        self.cluster_ct() * self.cluster_bytes()
+    }
+
+    fn cluster_offset(&self, cluster: u16) -> usize {
+        (self.data_start() + (cluster as usize - 2) * self.sectors_per_cluster) * RAW_SECTOR_SIZE
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FatNode {
+    File(Vec<u8>),
+    Directory(Vec<FatEntry>),
+}
+
+/// A file or directory on a FAT volume. The name is a DOS 8.3 name in the form 'NAME.EXT'; `date` and `time`
+/// are the last modification time in the packed DOS format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FatEntry {
+    pub name: String,
+    pub attributes: u8,
+    pub date: u16,
+    pub time: u16,
+    pub node: FatNode,
+}
+
+impl FatEntry {
+    pub fn file(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            attributes: ATTR_ARCHIVE,
+            date: 0,
+            time: 0,
+            node: FatNode::File(data),
+        }
+    }
+
+    pub fn directory(name: &str, entries: Vec<FatEntry>) -> Self {
+        Self {
+            name: name.to_string(),
+            attributes: ATTR_DIRECTORY,
+            date: 0,
+            time: 0,
+            node: FatNode::Directory(entries),
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        matches!(self.node, FatNode::Directory(_))
+    }
+
+    /// Set the modification time from a host timestamp.
+    pub fn with_time(mut self, time: SystemTime) -> Self {
+        (self.date, self.time) = dos_datetime(time);
+        self
+    }
+}
+
+/// Convert a host timestamp to a packed DOS (date, time) pair, clamped to the range DOS can represent.
+pub fn dos_datetime(time: SystemTime) -> (u16, u16) {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    };
+    let days = (secs / 86_400) as i64;
+    let day_secs = secs % 86_400;
+
+    // Convert days since 1970-01-01 to a civil date.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        // 1980-01-01 00:00:00
+        return (0x0021, 0);
+    }
+    if year > 2107 {
+        // 2107-12-31 23:59:58
+        return (0xFF9F, 0xBF7D);
+    }
+
+    let date = (((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time =
+        (((day_secs / 3600) as u16) << 11) | ((((day_secs / 60) % 60) as u16) << 5) | ((day_secs % 60) / 2) as u16;
+    (date, time)
+}
+
+fn is_valid_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// Returns true if `name` is a valid 'NAME.EXT' DOS name. Such a name contains no path separators, drive letters or
+/// relative components, so it is safe to join to a host path.
+pub fn is_valid_short_name(name: &str) -> bool {
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    };
+    !base.is_empty() && base.len() <= 8 && ext.len() <= 3 && base.chars().chain(ext.chars()).all(is_valid_name_char)
+}
+
+/// Generate a unique DOS 8.3 name for a host file name. Names that can't be represented exactly are shortened
+/// to a numbered 'NAME~1.EXT' form that does not collide with any name in `existing`.
+pub fn short_name(name: &str, existing: &[String]) -> String {
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    };
+    let clean = |s: &str| -> String {
+        s.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_valid_name_char(c) {
+                    c
+                }
+                else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+    let mut clean_base = clean(base);
+    let clean_ext: String = clean(ext).chars().take(3).collect();
+    if clean_base.is_empty() {
+        clean_base.push('_');
+    }
+
+    let join = |base: &str| -> String {
+        if clean_ext.is_empty() {
+            base.to_string()
+        }
+        else {
+            format!("{}.{}", base, clean_ext)
+        }
+    };
+    let exact = clean_base.len() <= 8 && join(&clean_base).eq_ignore_ascii_case(name);
+    if exact && !existing.contains(&join(&clean_base)) {
+        return join(&clean_base);
+    }
+
+    let mut n = 1;
+    loop {
+        let tail = format!("~{}", n);
+        let stem: String = clean_base.chars().take(8 - tail.len()).collect();
+        let candidate = join(&format!("{}{}", stem, tail));
+        if !existing.contains(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Encode a 'NAME.EXT' name as the 11 byte, space padded form stored in a directory entry.
+fn encode_name(name: &str) -> Result<[u8; 11], Error> {
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || !name.is_ascii() {
+        return Err(anyhow!("Invalid DOS file name: {}", name));
+    }
+    let mut raw = [b' '; 11];
+    raw[..base.len()].copy_from_slice(base.as_bytes());
+    raw[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    // A leading 0xE5 would mark the entry as deleted, so it is stored as 0x05.
+    if raw[0] == 0xE5 {
+        raw[0] = 0x05;
+    }
+    Ok(raw)
+}
+
+fn decode_name(raw: &[u8]) -> String {
+    let mut base: Vec<u8> = raw[0..8].to_vec();
+    if base[0] == 0x05 {
+        base[0] = 0xE5;
+    }
+    let base = String::from_utf8_lossy(&base).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&raw[8..11]).trim_end().to_string();
+    if ext.is_empty() {
+        base
+    }
+    else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+fn get_fat(fat: &[u8], cluster: u16) -> u16 {
+    let i = cluster as usize * 3 / 2;
+    if i + 1 >= fat.len() {
+        return FAT_EOC;
+    }
+    let word = u16::from_le_bytes([fat[i], fat[i + 1]]);
+    if cluster & 1 == 0 {
+        word & 0x0FFF
+    }
+    else {
+        word >> 4
+    }
+}
+
+fn set_fat(fat: &mut [u8], cluster: u16, value: u16) {
+    let i = cluster as usize * 3 / 2;
+    if cluster & 1 == 0 {
+        fat[i] = value as u8;
+        fat[i + 1] = (fat[i + 1] & 0xF0) | ((value >> 8) as u8 & 0x0F);
+    }
+    else {
+        fat[i] = (fat[i] & 0x0F) | ((value << 4) as u8);
+        fat[i + 1] = (value >> 4) as u8;
+    }
+}
+
+fn make_dir_entry(
+    raw_name: &[u8; 11],
+    attributes: u8,
+    date: u16,
+    time: u16,
+    cluster: u16,
+    size: usize,
+) -> [u8; DIR_ENTRY_LEN] {
+    let mut entry = [0u8; DIR_ENTRY_LEN];
+    entry[0..11].copy_from_slice(raw_name);
+    entry[11] = attributes;
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&(size as u32).to_le_bytes());
+    entry
+}
+
+/// Lays out files on a volume being built.
+struct VolumeBuilder {
+    params: FatParams,
+    image: Vec<u8>,
+    fat: Vec<u8>,
+    next_cluster: usize,
+}
+
+impl VolumeBuilder {
+    /// Allocate a contiguous chain of clusters to hold `len` bytes, returning the first cluster, or 0 for an
+    /// empty chain.
+    fn alloc(&mut self, len: usize) -> Result<u16, Error> {
+        let clusters = len.div_ceil(self.params.cluster_bytes());
+        if clusters == 0 {
+            return Ok(0);
+        }
+        if self.next_cluster + clusters > self.params.cluster_ct() + 2 {
+            return Err(anyhow!(
+                "Files don't fit on the disk ({} bytes available)",
+                self.params.capacity()
+            ));
+        }
+        let first = self.next_cluster;
+        for cluster in first..first + clusters {
+            let next = if cluster + 1 < first + clusters {
+                (cluster + 1) as u16
+            }
+            else {
+                FAT_EOC
+            };
+            set_fat(&mut self.fat, cluster as u16, next);
+        }
+        self.next_cluster += clusters;
+        Ok(first as u16)
+    }
+
+    fn write_clusters(&mut self, first: u16, data: &[u8]) {
+        // Chains are allocated contiguously, so the data can be written in one piece.
+        if first > 0 {
+            let offset = self.params.cluster_offset(first);
+            self.image[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    /// Allocate and write the contents of each entry, returning the directory table describing them.
+    fn write_entries(&mut self, entries: &[FatEntry], this: u16, parent: u16) -> Result<Vec<u8>, Error> {
+        let mut table = Vec::new();
+        if this > 0 {
+            let dot = make_dir_entry(b".          ", ATTR_DIRECTORY, 0, 0, this, 0);
+            let dotdot = make_dir_entry(b"..         ", ATTR_DIRECTORY, 0, 0, parent, 0);
+            table.extend_from_slice(&dot);
+            table.extend_from_slice(&dotdot);
+        }
+
+        for entry in entries {
+            let raw_name = encode_name(&entry.name)?;
+            match &entry.node {
+                FatNode::File(data) => {
+                    let first = self.alloc(data.len())?;
+                    self.write_clusters(first, data);
+                    table.extend_from_slice(&make_dir_entry(
+                        &raw_name,
+                        entry.attributes & !ATTR_DIRECTORY,
+                        entry.date,
+                        entry.time,
+                        first,
+                        data.len(),
+                    ));
+                }
+                FatNode::Directory(children) => {
+                    // Reserve the directory's own clusters first, so that its '.' entry can refer to them.
+                    let len = (children.len() + 2) * DIR_ENTRY_LEN;
+                    let first = self.alloc(len)?;
+                    let child_table = self.write_entries(children, first, this)?;
+                    self.write_clusters(first, &child_table);
+                    table.extend_from_slice(&make_dir_entry(
+                        &raw_name,
+                        entry.attributes | ATTR_DIRECTORY,
+                        entry.date,
+                        entry.time,
+                        first,
+                        0,
+                    ));
+                }
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Build a raw sector image of the given size containing a FAT12 file system with the specified files,
+/// in the same layout DOS would produce when formatting a disk of that size.
+pub fn build_volume(size: usize, label: Option<&str>, entries: &[FatEntry]) -> Result<Vec<u8>, Error> {
+    let params = FatParams::for_size(size).ok_or_else(|| anyhow!("Unsupported disk size: {}", size))?;
+
+    let root_ct = entries.len() + label.is_some() as usize;
+    if root_ct > params.root_entries {
+        return Err(anyhow!(
+            "Too many files in the root directory ({} of a maximum {})",
+            root_ct,
+            params.root_entries
+        ));
+    }
+
+    let mut builder = VolumeBuilder {
+        params,
+        image: vec![0; size],
+        fat: vec![0; params.fat_sectors * RAW_SECTOR_SIZE],
+        next_cluster: 2,
+    };
+    set_fat(&mut builder.fat, 0, 0xF00 | params.media as u16);
+    set_fat(&mut builder.fat, 1, FAT_EOC);
+
+    let mut root = Vec::new();
+    let raw_label = match label {
+        Some(label) => {
+            let mut raw = [b' '; 11];
+            for (dst, src) in raw.iter_mut().zip(label.to_ascii_uppercase().bytes()) {
+                *dst = src;
+            }
+            Some(raw)
+        }
+        None => None,
+    };
+    if let Some(raw) = &raw_label {
+        root.extend_from_slice(&make_dir_entry(raw, ATTR_VOLUME_ID, 0, 0, 0, 0));
+    }
+    root.extend(builder.write_entries(entries, 0, 0)?);
+
+    let root_offset = params.root_start() * RAW_SECTOR_SIZE;
+    builder.image[root_offset..root_offset + root.len()].copy_from_slice(&root);
+    for i in 0..params.fat_ct {
+        let offset = (params.fat_start() + i * params.fat_sectors) * RAW_SECTOR_SIZE;
+        builder.image[offset..offset + builder.fat.len()].copy_from_slice(&builder.fat);
+    }
+
+    // Boot sector, with a DOS 4.0 extended BPB.
+    let boot = &mut builder.image[0..RAW_SECTOR_SIZE];
+    boot[0..3].copy_from_slice(&[0xEB, BOOT_CODE_OFFSET as u8 - 2, 0x90]);
+    boot[3..11].copy_from_slice(b"MARTYPC ");
+    boot[0x0B..0x0D].copy_from_slice(&(RAW_SECTOR_SIZE as u16).to_le_bytes());
+    boot[0x0D] = params.sectors_per_cluster as u8;
+    boot[0x0E..0x10].copy_from_slice(&(params.reserved_sectors as u16).to_le_bytes());
+    boot[0x10] = params.fat_ct as u8;
+    boot[0x11..0x13].copy_from_slice(&(params.root_entries as u16).to_le_bytes());
+    boot[0x13..0x15].copy_from_slice(&(params.total_sectors as u16).to_le_bytes());
+    boot[0x15] = params.media;
+    boot[0x16..0x18].copy_from_slice(&(params.fat_sectors as u16).to_le_bytes());
+    boot[0x18..0x1A].copy_from_slice(&(params.sectors_per_track as u16).to_le_bytes());
+    boot[0x1A..0x1C].copy_from_slice(&(params.heads as u16).to_le_bytes());
+    boot[0x26] = 0x29;
+    let serial = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    boot[0x27..0x2B].copy_from_slice(&serial.to_le_bytes());
+    boot[0x2B..0x36].copy_from_slice(&raw_label.unwrap_or(*b"NO NAME    "));
+    boot[0x36..0x3E].copy_from_slice(b"FAT12   ");
+    boot[BOOT_CODE_OFFSET..BOOT_CODE_OFFSET + BOOT_CODE.len()].copy_from_slice(&BOOT_CODE);
+    let msg_offset = BOOT_CODE_OFFSET + BOOT_CODE.len();
+    boot[msg_offset..msg_offset + BOOT_MESSAGE.len()].copy_from_slice(BOOT_MESSAGE);
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    Ok(builder.image)
+}
+
+/// Reads the directory tree of a volume.
+struct VolumeReader<'a> {
+    params: FatParams,
+    data:   &'a [u8],
+    fat:    &'a [u8],
+}
+
+impl VolumeReader<'_> {
+    /// Read a cluster chain. Chains are cut short if they run off the disk or loop.
+    fn read_chain(&self, first: u16, limit: Option<usize>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut cluster = first;
+        let mut steps = 0;
+        while cluster >= 2 && (cluster as usize) < self.params.cluster_ct() + 2 && steps <= self.params.cluster_ct() {
+            let offset = self.params.cluster_offset(cluster);
+            let end = (offset + self.params.cluster_bytes()).min(self.data.len());
+            out.extend_from_slice(&self.data[offset.min(end)..end]);
+            if limit.is_some_and(|limit| out.len() >= limit) {
+                break;
+            }
+            cluster = get_fat(self.fat, cluster);
+            if cluster == FAT_FREE || cluster >= FAT_EOC_MIN {
+                break;
+            }
+            steps += 1;
+        }
+        if let Some(limit) = limit {
+            out.truncate(limit);
+        }
+        out
+    }
+
+    fn read_dir(&self, table: &[u8], depth: usize) -> Vec<FatEntry> {
+        let mut entries = Vec::new();
+        for raw in table.chunks_exact(DIR_ENTRY_LEN) {
+            match raw[0] {
+                0x00 => break,
+                0xE5 => continue,
+                _ => {}
+            }
+            let attributes = raw[11];
+            if attributes & ATTR_LFN == ATTR_LFN || attributes & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+                continue;
+            }
+            let cluster = u16::from_le_bytes([raw[26], raw[27]]);
+            let size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]) as usize;
+
+            let node = if attributes & ATTR_DIRECTORY != 0 {
+                // Guard against directory loops on a corrupt disk.
+                if depth >= 32 {
+                    continue;
+                }
+                FatNode::Directory(self.read_dir(&self.read_chain(cluster, None), depth + 1))
+            }
+            else {
+                FatNode::File(self.read_chain(cluster, Some(size)))
+            };
+            entries.push(FatEntry {
+                name: decode_name(&raw[0..11]),
+                attributes,
+                date: u16::from_le_bytes([raw[24], raw[25]]),
+                time: u16::from_le_bytes([raw[22], raw[23]]),
+                node,
+            });
+        }
+        entries
+    }
+}
+
+/// Read the directory tree of a FAT12 volume from a raw sector image.
+pub fn read_volume(data: &[u8]) -> Result<Vec<FatEntry>, Error> {
+    let params = FatParams::from_boot_sector(data)
+        .filter(|params| params.total_sectors * RAW_SECTOR_SIZE <= data.len())
+        .or_else(|| FatParams::for_size(data.len()))
+        .ok_or_else(|| anyhow!("Disk does not contain a FAT12 file system"))?;
+
+    let fat_offset = params.fat_start() * RAW_SECTOR_SIZE;
+    let fat = &data[fat_offset..fat_offset + params.fat_sectors * RAW_SECTOR_SIZE];
+    if fat[0] != params.media {
+        return Err(anyhow!("Disk does not contain a FAT12 file system"));
+    }
+    let root_offset = params.root_start() * RAW_SECTOR_SIZE;
+    let root = &data[root_offset..root_offset + params.root_entries * DIR_ENTRY_LEN];
+
+    let reader = VolumeReader { params, data, fat };
+    Ok(reader.read_dir(root, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let big: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let entries = vec![
+            FatEntry::file("README.TXT", b"Hello from the host".to_vec()),
+            FatEntry::file("EMPTY", Vec::new()),
+            FatEntry::directory(
+                "GAMES",
+                vec![
+                    FatEntry::file("BIG.DAT", big.clone()),
+                    FatEntry::directory("SAVES", vec![FatEntry::file("SLOT1.SAV", vec![1, 2, 3])]),
+                ],
+            ),
+        ];
+
+        for size in [163_840, 368_640, 1_474_560] {
+            let image = build_volume(size, Some("marty"), &entries).unwrap();
+            assert_eq!(image.len(), size);
+            assert_eq!(read_volume(&image).unwrap(), entries);
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let params = FatParams::for_size(368_640).unwrap();
+        assert_eq!(params.data_start(), 12);
+        assert_eq!(params.cluster_ct(), 354);
+
+        let fits = vec![FatEntry::file("A.BIN", vec![0; params.capacity()])];
+        assert!(build_volume(368_640, None, &fits).is_ok());
+        let too_big = vec![FatEntry::file("A.BIN", vec![0; params.capacity() + 1])];
+        assert!(build_volume(368_640, None, &too_big).is_err());
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name("readme.txt", &[]), "README.TXT");
+        assert_eq!(short_name("Makefile", &[]), "MAKEFILE");
+        assert_eq!(short_name("a long name.html", &[]), "ALONGN~1.HTM");
+        assert_eq!(short_name("archive.tar.gz", &[]), "ARCHIV~1.GZ");
+        let existing = vec!["ALONGN~1.HTM".to_string()];
+        assert_eq!(short_name("a long name.html", &existing), "ALONGN~2.HTM");
+        let existing = vec!["README.TXT".to_string()];
+        assert_eq!(short_name("README.TXT", &existing), "README~1.TXT");
+    }
+
+    #[test]
+    fn test_dos_datetime() {
+        // 2024-02-29 13:45:10 UTC
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_709_214_310);
+        assert_eq!(
+            dos_datetime(time),
+            ((44 << 9) | (2 << 5) | 29, (13 << 11) | (45 << 5) | 5)
+        );
+        assert_eq!(dos_datetime(UNIX_EPOCH), (0x0021, 0));
+    }
+}
@@ -0,0 +1,213 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::imd.rs
+
+    Loader for ImageDisk (IMD) images.
+
+    An IMD image stores each track as it was read, with the ID of every
+    sector and a record type for each sector's data that notes deleted
+    address marks, data CRC errors and sectors whose data couldn't be read.
+    Sectors filled with a single value are compressed to one byte.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+use crate::floppy_image::{sector_size, FloppyImage, FloppySector, FloppyTrack, SectorId};
+
+pub const IMD_MAGIC: &[u8; 4] = b"IMD ";
+// The ASCII header and comment are terminated by an EOF character.
+pub const IMD_COMMENT_END: u8 = 0x1A;
+
+const HEAD_CYLINDER_MAP: u8 = 0x80;
+const HEAD_HEAD_MAP: u8 = 0x40;
+// A sector size code of 0xFF means a table of sector sizes follows the sector maps.
+const SIZE_TABLE: u8 = 0xFF;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= IMD_MAGIC.len() && &data[0..4] == IMD_MAGIC
+}
+
+/// Load an IMD image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    if !detect(data) {
+        return Err(anyhow!("Not an IMD image"));
+    }
+    let comment_end = data
+        .iter()
+        .position(|b| *b == IMD_COMMENT_END)
+        .ok_or_else(|| anyhow!("IMD header is not terminated"))?;
+    log::debug!(
+        "IMD header: {}",
+        String::from_utf8_lossy(&data[..comment_end])
+            .lines()
+            .next()
+            .unwrap_or("")
+    );
+
+    let mut reader = Reader {
+        data,
+        pos: comment_end + 1,
+    };
+    let mut image = FloppyImage::new(0, 1);
+    let mut have_fm = false;
+
+    while !reader.at_end() {
+        let mode = reader.u8()?;
+        if mode > 5 {
+            return Err(anyhow!("IMD track has an invalid mode: {}", mode));
+        }
+        have_fm |= mode < 3;
+        let cylinder = reader.u8()?;
+        let head_flags = reader.u8()?;
+        let sector_ct = reader.u8()? as usize;
+        let size_code = reader.u8()?;
+
+        let numbers = reader.bytes(sector_ct)?.to_vec();
+        let cylinders = match head_flags & HEAD_CYLINDER_MAP {
+            0 => vec![cylinder; sector_ct],
+            _ => reader.bytes(sector_ct)?.to_vec(),
+        };
+        let head = head_flags & 0x01;
+        let heads = match head_flags & HEAD_HEAD_MAP {
+            0 => vec![head; sector_ct],
+            _ => reader.bytes(sector_ct)?.to_vec(),
+        };
+        let sizes: Vec<(u8, usize)> = match size_code {
+            SIZE_TABLE => (0..sector_ct)
+                .map(|_| {
+                    let size = reader.u16()? as usize;
+                    Ok((size_to_code(size), size))
+                })
+                .collect::<Result<_, Error>>()?,
+            n if n < 7 => vec![(n, sector_size(n)); sector_ct],
+            n => return Err(anyhow!("IMD track has an invalid sector size code: {}", n)),
+        };
+
+        let mut sectors = Vec::with_capacity(sector_ct);
+        let ids = numbers.iter().zip(&cylinders).zip(&heads);
+        for (((r, c), h), (n, size)) in ids.zip(sizes) {
+            let id = SectorId::new(*c, *h, *r, n);
+            let mut sector = FloppySector::new(id, Vec::new());
+
+            let record = reader.u8()?;
+            match record {
+                0 => sector.no_data = true,
+                1..=8 => {
+                    let kind = record - 1;
+                    sector.data = match kind & 0x01 {
+                        0 => reader.bytes(size)?.to_vec(),
+                        _ => vec![reader.u8()?; size],
+                    };
+                    sector.deleted = kind & 0x02 != 0;
+                    sector.data_crc_error = kind & 0x04 != 0;
+                }
+                _ => return Err(anyhow!("IMD sector has an invalid data record type: {}", record)),
+            }
+            sectors.push(sector);
+        }
+        image.set_track(cylinder, head, FloppyTrack::new(sectors));
+    }
+
+    if image.cylinders() == 0 {
+        return Err(anyhow!("IMD image contains no tracks"));
+    }
+    if have_fm {
+        log::warn!("IMD image has FM encoded tracks, which will be read as MFM");
+    }
+    Ok(image)
+}
+
+/// Return the size code for a sector of the given size in bytes.
+fn size_to_code(size: usize) -> u8 {
+    (0..8).find(|n| sector_size(*n) >= size).unwrap_or(7)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow!("Unexpected end of image"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let mut file = b"IMD 1.18: 01/01/2024 12:00:00\r\nTest image".to_vec();
+        file.push(IMD_COMMENT_END);
+
+        // Cylinder 0, head 1 with a cylinder map: a normal, compressed, deleted, bad and missing sector
+        file.extend_from_slice(&[5, 0, 1 | HEAD_CYLINDER_MAP, 5, 2]);
+        file.extend_from_slice(&[1, 2, 3, 4, 5]);
+        file.extend_from_slice(&[0, 0, 0, 0, 40]);
+        file.push(1);
+        file.extend_from_slice(&[0x11; 512]);
+        file.extend_from_slice(&[2, 0xE5]);
+        file.push(3);
+        file.extend_from_slice(&[0x33; 512]);
+        file.push(5);
+        file.extend_from_slice(&[0x44; 512]);
+        file.push(0);
+
+        let image = load(&file).unwrap();
+        assert_eq!(image.heads(), 2);
+        let track = image.track(0, 1).unwrap();
+        assert_eq!(track.sectors.len(), 5);
+        assert_eq!(track.sectors[0].data, vec![0x11; 512]);
+        assert_eq!(track.sectors[1].data, vec![0xE5; 512]);
+        assert!(track.sectors[2].deleted && !track.sectors[2].data_crc_error);
+        assert!(track.sectors[3].data_crc_error && !track.sectors[3].deleted);
+        assert!(track.sectors[4].no_data);
+        assert_eq!(track.sectors[4].id, SectorId::new(40, 1, 5, 2));
+        assert!(!image.track(0, 0).unwrap().is_formatted());
+    }
+}
@@ -0,0 +1,346 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::lzhuf.rs
+
+    Decompressor for the LZSS and adaptive Huffman coding used by Teledisk's
+    "advanced compression" mode. This is Haruyasu Yoshizaki's LZHUF: a 4KB
+    sliding window with matches of up to 60 bytes, where literals and match
+    lengths share an adaptive Huffman tree and the upper bits of match
+    positions use a fixed code.
+
+*/
+
+const WINDOW_SIZE: usize = 4096;
+const MAX_MATCH: usize = 60;
+const THRESHOLD: usize = 2;
+
+// Literals 0-255 followed by match lengths THRESHOLD + 1 to MAX_MATCH.
+const CHAR_CT: usize = 256 - THRESHOLD + MAX_MATCH;
+const TABLE_SIZE: usize = CHAR_CT * 2 - 1;
+const ROOT: usize = TABLE_SIZE - 1;
+const MAX_FREQ: u16 = 0x8000;
+
+// Number of 8-bit position codes, and their lengths in bits, for each value of the upper 6 bits of a match
+// position. Codes are assigned in ascending order.
+const POSITION_CODE_GROUPS: [(usize, usize); 6] = [(1, 3), (3, 4), (8, 5), (12, 6), (24, 7), (16, 8)];
+
+/// Reads bits most significant first. Reading past the end of the input returns zeros.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos:  usize,
+    bit:  u8,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, bit: 0 }
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn bit(&mut self) -> usize {
+        let bit = match self.data.get(self.pos) {
+            Some(byte) => (byte >> (7 - self.bit)) & 0x01,
+            None => 0,
+        };
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        bit as usize
+    }
+
+    pub(crate) fn bits(&mut self, ct: usize) -> usize {
+        (0..ct).fold(0, |acc, _| (acc << 1) | self.bit())
+    }
+}
+
+/// The adaptive Huffman tree shared by literals and match lengths.
+pub(crate) struct HuffmanTree {
+    pub(crate) freq:   Vec<u16>,
+    pub(crate) parent: Vec<usize>,
+    pub(crate) son:    Vec<usize>,
+}
+
+impl HuffmanTree {
+    pub(crate) fn new() -> Self {
+        let mut tree = Self {
+            freq:   vec![0; TABLE_SIZE + 1],
+            parent: vec![0; TABLE_SIZE + CHAR_CT],
+            son:    vec![0; TABLE_SIZE],
+        };
+        for i in 0..CHAR_CT {
+            tree.freq[i] = 1;
+            tree.son[i] = i + TABLE_SIZE;
+            tree.parent[i + TABLE_SIZE] = i;
+        }
+        let mut i = 0;
+        for j in CHAR_CT..TABLE_SIZE {
+            tree.freq[j] = tree.freq[i] + tree.freq[i + 1];
+            tree.son[j] = i;
+            tree.parent[i] = j;
+            tree.parent[i + 1] = j;
+            i += 2;
+        }
+        tree.freq[TABLE_SIZE] = 0xFFFF;
+        tree.parent[ROOT] = 0;
+        tree
+    }
+
+    /// Rebuild the tree with halved frequencies once the root frequency reaches the maximum.
+    fn reconstruct(&mut self) {
+        // Collect the leaves and halve their frequencies
+        let mut j = 0;
+        for i in 0..TABLE_SIZE {
+            if self.son[i] >= TABLE_SIZE {
+                self.freq[j] = self.freq[i].div_ceil(2);
+                self.son[j] = self.son[i];
+                j += 1;
+            }
+        }
+
+        // Rebuild the internal nodes, keeping the nodes sorted by frequency
+        let mut i = 0;
+        for j in CHAR_CT..TABLE_SIZE {
+            let f = self.freq[i] + self.freq[i + 1];
+            let mut k = j;
+            while k > 0 && f < self.freq[k - 1] {
+                k -= 1;
+            }
+            self.freq.copy_within(k..j, k + 1);
+            self.freq[k] = f;
+            self.son.copy_within(k..j, k + 1);
+            self.son[k] = i;
+            i += 2;
+        }
+
+        for i in 0..TABLE_SIZE {
+            let k = self.son[i];
+            self.parent[k] = i;
+            if k < TABLE_SIZE {
+                self.parent[k + 1] = i;
+            }
+        }
+    }
+
+    /// Increment the frequency of a symbol, moving nodes to keep the tree ordered.
+    pub(crate) fn update(&mut self, symbol: usize) {
+        if self.freq[ROOT] == MAX_FREQ {
+            self.reconstruct();
+        }
+        let mut c = self.parent[symbol + TABLE_SIZE];
+        loop {
+            self.freq[c] += 1;
+            let k = self.freq[c];
+
+            // If the order is disturbed, exchange nodes
+            let mut l = c + 1;
+            if k > self.freq[l] {
+                while k > self.freq[l + 1] {
+                    l += 1;
+                }
+                self.freq[c] = self.freq[l];
+                self.freq[l] = k;
+
+                let i = self.son[c];
+                self.parent[i] = l;
+                if i < TABLE_SIZE {
+                    self.parent[i + 1] = l;
+                }
+                let j = self.son[l];
+                self.son[l] = i;
+                self.parent[j] = c;
+                if j < TABLE_SIZE {
+                    self.parent[j + 1] = c;
+                }
+                self.son[c] = j;
+                c = l;
+            }
+
+            c = self.parent[c];
+            if c == 0 {
+                break;
+            }
+        }
+    }
+
+    fn decode_symbol(&mut self, reader: &mut BitReader) -> usize {
+        let mut c = self.son[ROOT];
+        while c < TABLE_SIZE {
+            c = self.son[c + reader.bit()];
+        }
+        let symbol = c - TABLE_SIZE;
+        self.update(symbol);
+        symbol
+    }
+}
+
+/// Return the upper 6 bits of a match position and the code length in bits for each 8-bit position code.
+pub(crate) fn position_code_table() -> [(usize, usize); 256] {
+    let mut table = [(0, 0); 256];
+    let mut code = 0;
+    let mut value = 0;
+    for (values, len) in POSITION_CODE_GROUPS {
+        let span = 1 << (8 - len);
+        for _ in 0..values {
+            for entry in table.iter_mut().skip(code).take(span) {
+                *entry = (value, len);
+            }
+            code += span;
+            value += 1;
+        }
+    }
+    table
+}
+
+fn decode_position(reader: &mut BitReader, table: &[(usize, usize); 256]) -> usize {
+    let mut i = reader.bits(8);
+    let (upper, len) = table[i];
+    // The code is followed by the lower 6 bits of the position; 8 bits have already been read.
+    for _ in 0..len - 2 {
+        i = (i << 1) | reader.bit();
+    }
+    (upper << 6) | (i & 0x3F)
+}
+
+/// Decompress LZHUF data until the input is exhausted.
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let table = position_code_table();
+    let mut reader = BitReader::new(data);
+    let mut tree = HuffmanTree::new();
+    let mut window = [0x20u8; WINDOW_SIZE];
+    let mut r = WINDOW_SIZE - MAX_MATCH;
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    while !reader.at_end() {
+        let symbol = tree.decode_symbol(&mut reader);
+        if symbol < 256 {
+            out.push(symbol as u8);
+            window[r] = symbol as u8;
+            r = (r + 1) % WINDOW_SIZE;
+        }
+        else {
+            let start = (r + WINDOW_SIZE * 2 - decode_position(&mut reader, &table) - 1) % WINDOW_SIZE;
+            let len = symbol - 255 + THRESHOLD;
+            for k in 0..len {
+                let byte = window[(start + k) % WINDOW_SIZE];
+                out.push(byte);
+                window[r] = byte;
+                r = (r + 1) % WINDOW_SIZE;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes bits most significant first.
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        len:  usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: usize, ct: usize) {
+            for i in (0..ct).rev() {
+                if self.len % 8 == 0 {
+                    self.data.push(0);
+                }
+                if value & (1 << i) != 0 {
+                    self.data[self.len / 8] |= 0x80 >> (self.len % 8);
+                }
+                self.len += 1;
+            }
+        }
+    }
+
+    fn encode_symbol(tree: &mut HuffmanTree, writer: &mut BitWriter, symbol: usize) {
+        // Walk from the leaf to the root, then write the path from the root down
+        let mut path = Vec::new();
+        let mut k = tree.parent[symbol + TABLE_SIZE];
+        loop {
+            path.push(k & 1);
+            k = tree.parent[k];
+            if k == ROOT {
+                break;
+            }
+        }
+        for bit in path.iter().rev() {
+            writer.bits(*bit, 1);
+        }
+        tree.update(symbol);
+    }
+
+    fn encode_position(writer: &mut BitWriter, table: &[(usize, usize); 256], position: usize) {
+        let code = table.iter().position(|(upper, _)| *upper == position >> 6).unwrap();
+        let len = table[code].1;
+        writer.bits(code >> (8 - len), len);
+        writer.bits(position & 0x3F, 6);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let table = position_code_table();
+        let mut tree = HuffmanTree::new();
+        let mut writer = BitWriter::default();
+        let mut expected = Vec::new();
+
+        // Enough literals to force the tree to be rebuilt several times
+        for i in 0..100_000usize {
+            let byte = ((i * 7) % 251) as u8 & if i % 3 == 0 { 0x0F } else { 0xFF };
+            encode_symbol(&mut tree, &mut writer, byte as usize);
+            expected.push(byte);
+        }
+
+        // A match of 10 bytes starting 1000 bytes back, and one reaching into the initial window of spaces
+        encode_symbol(&mut tree, &mut writer, 255 - THRESHOLD + 10);
+        encode_position(&mut writer, &table, 999);
+        let start = expected.len() - 1000;
+        expected.extend_from_within(start..start + 10);
+
+        let mut tree2 = HuffmanTree::new();
+        let mut writer2 = BitWriter::default();
+        let mut expected2 = b"AB".to_vec();
+        encode_symbol(&mut tree2, &mut writer2, b'A' as usize);
+        encode_symbol(&mut tree2, &mut writer2, b'B' as usize);
+        encode_symbol(&mut tree2, &mut writer2, 255 - THRESHOLD + 5);
+        encode_position(&mut writer2, &table, 3);
+        expected2.extend_from_slice(b"  AB ");
+
+        let out = decompress(&writer.data);
+        assert_eq!(&out[..expected.len()], &expected[..]);
+        let out2 = decompress(&writer2.data);
+        assert_eq!(&out2[..expected2.len()], &expected2[..]);
+    }
+}
@@ -0,0 +1,435 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::mfm.rs
+
+    Conversion between MFM encoded bitstreams and the sector level track
+    representation.
+
+    Bitstream image formats record every bit cell of a track. Decoding finds
+    each address mark by its sync pattern and records the sector's position
+    on the track, so that timing dependent protection checks see sectors
+    where they were on the original disk. Regions with no reliable flux
+    transitions are kept as weak bits that read differently every time.
+
+*/
+
+use crate::floppy_image::{FloppySector, FloppyTrack, SectorId};
+
+// The A1 sync byte written with a missing clock bit, as it appears in the bitstream.
+pub const MFM_SYNC_A1: u16 = 0x4489;
+// The C2 index sync byte written with a missing clock bit.
+pub const MFM_SYNC_C2: u16 = 0x5224;
+
+pub const MARK_INDEX: u8 = 0xFC;
+pub const MARK_ID: u8 = 0xFE;
+pub const MARK_DATA: u8 = 0xFB;
+pub const MARK_DELETED_DATA: u8 = 0xF8;
+
+// Each MFM encoded byte takes 16 bit cells.
+pub const CELLS_PER_BYTE: usize = 16;
+
+// The data address mark must follow the ID field within this many bytes to belong to the same sector.
+pub const MAX_ID_TO_DATA_BYTES: usize = 64;
+
+// Nominal length in bytes of a track at 300 RPM at the double and high density data rates.
+pub const TRACK_LEN_DD: usize = 6250;
+pub const TRACK_LEN_HD: usize = 12500;
+
+// Bytes written by encode_track before the first sector, and for each sector in addition to its data and Gap 3.
+const TRACK_PREAMBLE_BYTES: usize = 146;
+const SECTOR_FIXED_BYTES: usize = 62;
+
+/// Calculate the CRC-CCITT used by the ID and data fields, which covers the sync bytes and address mark.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            }
+            else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The bit cells of one revolution of a track, starting at the index pulse.
+#[derive(Clone, Debug, Default)]
+pub struct Bitstream {
+    bits: Vec<u8>,
+    weak: Option<Vec<u8>>,
+    len:  usize,
+}
+
+impl Bitstream {
+    /// Create a bitstream of `len` cells from bytes holding eight cells each, most significant bit first.
+    /// Cells set in `weak` have no reliable flux transitions.
+    pub fn from_bytes(bits: Vec<u8>, weak: Option<Vec<u8>>, len: usize) -> Self {
+        let len = std::cmp::min(len, bits.len() * 8);
+        Self {
+            bits,
+            weak: weak.filter(|weak| weak.iter().any(|b| *b != 0)),
+            len,
+        }
+    }
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the cells packed eight to a byte, most significant bit first.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// Return the weak cell mask in the same layout as [as_bytes](Self::as_bytes), if any cells are weak.
+    pub fn weak_bytes(&self) -> Option<&[u8]> {
+        self.weak.as_deref()
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        self.push_cell(bit, false);
+    }
+
+    pub fn push_cell(&mut self, bit: bool, weak: bool) {
+        if self.len % 8 == 0 {
+            self.bits.push(0);
+            if let Some(weak) = &mut self.weak {
+                weak.push(0);
+            }
+        }
+        let mask = 0x80 >> (self.len % 8);
+        if bit {
+            self.bits[self.len / 8] |= mask;
+        }
+        if weak {
+            let bytes = self.bits.len();
+            self.weak.get_or_insert_with(|| vec![0; bytes])[self.len / 8] |= mask;
+        }
+        self.len += 1;
+    }
+
+    /// Return the cell at position `pos`. The track is circular, so positions past the end wrap around to the
+    /// index.
+    pub fn bit(&self, pos: usize) -> bool {
+        let pos = pos % self.len;
+        self.bits[pos / 8] & (0x80 >> (pos % 8)) != 0
+    }
+
+    pub fn is_weak(&self, pos: usize) -> bool {
+        match &self.weak {
+            Some(weak) => {
+                let pos = pos % self.len;
+                weak[pos / 8] & (0x80 >> (pos % 8)) != 0
+            }
+            None => false,
+        }
+    }
+
+    /// Rotate the stream so that it starts at cell `pos`.
+    pub fn rotate(&self, pos: usize) -> Self {
+        if self.is_empty() || pos % self.len == 0 {
+            return self.clone();
+        }
+        let mut rotated = Self::new();
+        for i in 0..self.len {
+            rotated.push_cell(self.bit(pos + i), self.is_weak(pos + i));
+        }
+        rotated
+    }
+
+    /// Read the MFM encoded byte whose first clock cell is at `pos`, with a mask of the bits that are weak.
+    fn read_byte(&self, pos: usize) -> (u8, u8) {
+        let mut byte = 0;
+        let mut weak = 0;
+        for i in 0..8 {
+            byte <<= 1;
+            weak <<= 1;
+            let data_pos = pos + i * 2 + 1;
+            if self.bit(data_pos) {
+                byte |= 1;
+            }
+            if self.is_weak(data_pos) || self.is_weak(data_pos - 1) {
+                weak |= 1;
+            }
+        }
+        (byte, weak)
+    }
+
+    fn read_bytes(&self, pos: usize, len: usize) -> (Vec<u8>, Vec<u8>) {
+        (0..len).map(|i| self.read_byte(pos + i * CELLS_PER_BYTE)).unzip()
+    }
+
+    fn push_raw(&mut self, cells: u16) {
+        for i in (0..16).rev() {
+            self.push(cells & (1 << i) != 0);
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.push_byte_weak(byte, 0);
+    }
+
+    /// Encode a byte, marking the cells of the bits set in `weak` as weak.
+    fn push_byte_weak(&mut self, byte: u8, weak: u8) {
+        for i in (0..8).rev() {
+            let data = byte & (1 << i) != 0;
+            let is_weak = weak & (1 << i) != 0;
+            let prev = self.len > 0 && self.bit(self.len - 1);
+            self.push_cell(!prev && !data, is_weak);
+            self.push_cell(data, is_weak);
+        }
+    }
+
+    fn push_bytes(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.push_byte(byte);
+        }
+    }
+}
+
+/// Decode one revolution of an MFM track into its sectors. Sectors that start before the index and continue past
+/// it are read by wrapping around to the start of the stream.
+pub fn decode_track(stream: &Bitstream) -> FloppyTrack {
+    let mut track = FloppyTrack::default();
+    if stream.len() < CELLS_PER_BYTE * 8 {
+        return track;
+    }
+    track.recorded_len = Some(stream.len() / CELLS_PER_BYTE);
+
+    let mut pending_id: Option<FloppySector> = None;
+    let mut shift: u64 = 0;
+    let mut pos = 0;
+    loop {
+        // Scan past the end of the stream far enough to find a sync pattern that straddles the index, and the
+        // data field of an ID field found just before the index.
+        let mut limit = stream.len() + 3 * CELLS_PER_BYTE;
+        if let Some(sector) = &pending_id {
+            let id_pos = sector.position.unwrap_or(0) * CELLS_PER_BYTE;
+            limit = std::cmp::max(limit, id_pos + (MAX_ID_TO_DATA_BYTES + 4) * CELLS_PER_BYTE);
+        }
+        if pos >= limit {
+            break;
+        }
+
+        shift = (shift << 1) | stream.bit(pos) as u64;
+        pos += 1;
+        if shift & 0xFFFF_FFFF_FFFF != 0x4489_4489_4489 {
+            continue;
+        }
+        shift = 0;
+
+        let mark_pos = pos - 3 * CELLS_PER_BYTE;
+        let (mark, _) = stream.read_byte(pos);
+        match mark {
+            MARK_ID => {
+                if mark_pos >= stream.len() {
+                    // Back at the first sector on the track
+                    break;
+                }
+                let (field, _) = stream.read_bytes(pos + CELLS_PER_BYTE, 6);
+                let crc = crc16(&[0xA1, 0xA1, 0xA1, MARK_ID, field[0], field[1], field[2], field[3]]);
+
+                let mut sector = FloppySector::new(SectorId::new(field[0], field[1], field[2], field[3]), Vec::new());
+                sector.id_crc_error = crc != u16::from_be_bytes([field[4], field[5]]);
+                sector.position = Some(mark_pos / CELLS_PER_BYTE);
+                sector.no_data = true;
+
+                if let Some(orphan) = pending_id.replace(sector) {
+                    track.sectors.push(orphan);
+                }
+                pos += 7 * CELLS_PER_BYTE;
+            }
+            MARK_DATA | MARK_DELETED_DATA => {
+                let mut sector = match pending_id.take() {
+                    Some(sector) => sector,
+                    None => continue,
+                };
+                let id_pos = sector.position.unwrap_or(0) * CELLS_PER_BYTE;
+                if mark_pos - id_pos > MAX_ID_TO_DATA_BYTES * CELLS_PER_BYTE {
+                    // The data field is too far away to belong to the last ID field
+                    track.sectors.push(sector);
+                    continue;
+                }
+
+                let len = sector.id.size();
+                let (data, weak) = stream.read_bytes(pos + CELLS_PER_BYTE, len + 2);
+                let mut crc_bytes = vec![0xA1, 0xA1, 0xA1, mark];
+                crc_bytes.extend_from_slice(&data[..len]);
+
+                sector.data_crc_error = crc16(&crc_bytes) != u16::from_be_bytes([data[len], data[len + 1]]);
+                sector.deleted = mark == MARK_DELETED_DATA;
+                sector.no_data = false;
+                sector.data = data[..len].to_vec();
+                if weak[..len].iter().any(|b| *b != 0) {
+                    sector.weak = Some(weak[..len].to_vec());
+                }
+                track.sectors.push(sector);
+
+                // Skip the data field, unless it wraps past the index, in which case the scan is done.
+                pos += (len + 3) * CELLS_PER_BYTE;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(orphan) = pending_id {
+        track.sectors.push(orphan);
+    }
+    track
+}
+
+/// Encode a track in the IBM System 34 layout used by PC formatted disks, padding the stream with gap bytes to
+/// `track_len` bytes. The track is recorded longer than `track_len` if its sectors don't fit. Sectors with a known
+/// position are placed there if the preceding sectors leave room, and weak bits are marked in the stream.
+pub fn encode_track(track: &FloppyTrack, track_len: usize, gap3: usize) -> Bitstream {
+    let mut stream = Bitstream::new();
+    stream.push_bytes(0x4E, 80);
+    stream.push_bytes(0x00, 12);
+    for _ in 0..3 {
+        stream.push_raw(MFM_SYNC_C2);
+    }
+    stream.push_byte(MARK_INDEX);
+    stream.push_bytes(0x4E, 50);
+
+    for sector in &track.sectors {
+        let id = sector.id;
+        let mut field = vec![0xA1, 0xA1, 0xA1, MARK_ID, id.c, id.h, id.r, id.n];
+        let mut crc = crc16(&field);
+        if sector.id_crc_error {
+            crc = !crc;
+        }
+        field.extend_from_slice(&crc.to_be_bytes());
+
+        if let Some(position) = sector.position {
+            // The position is that of the sync bytes, which follow 12 bytes of zeros
+            while stream.len() < position.saturating_sub(12) * CELLS_PER_BYTE {
+                stream.push_byte(0x4E);
+            }
+        }
+        stream.push_bytes(0x00, 12);
+        push_field(&mut stream, &field, None);
+        stream.push_bytes(0x4E, 22);
+
+        if !sector.no_data {
+            let mark = if sector.deleted { MARK_DELETED_DATA } else { MARK_DATA };
+            let mut field = vec![0xA1, 0xA1, 0xA1, mark];
+            field.extend_from_slice(&sector.read_data(id.size()));
+            let mut crc = crc16(&field);
+            if sector.data_crc_error {
+                crc = !crc;
+            }
+            field.extend_from_slice(&crc.to_be_bytes());
+
+            stream.push_bytes(0x00, 12);
+            push_field(&mut stream, &field, sector.weak.as_deref());
+        }
+        stream.push_bytes(0x4E, gap3);
+    }
+
+    while stream.len() < track_len * CELLS_PER_BYTE {
+        stream.push_byte(0x4E);
+    }
+    stream
+}
+
+/// Encode a track at its recorded length, or otherwise at the nominal length of the lowest data rate its sectors fit
+/// in, with as large a Gap 3 as will fit up to the standard 84 bytes.
+pub fn encode_track_fit(track: &FloppyTrack) -> Bitstream {
+    let sectors_len: usize = track.sectors.iter().map(|s| s.id.size() + SECTOR_FIXED_BYTES).sum();
+    let track_len = match track.recorded_len {
+        Some(len) => len,
+        None if TRACK_PREAMBLE_BYTES + sectors_len <= TRACK_LEN_DD => TRACK_LEN_DD,
+        None => TRACK_LEN_HD,
+    };
+    let spare = track_len.saturating_sub(TRACK_PREAMBLE_BYTES + sectors_len);
+    let gap3 = match track.sectors.len() {
+        0 => 0,
+        n => (spare / n).clamp(1, 84),
+    };
+    encode_track(track, track_len, gap3)
+}
+
+/// Write an address field, encoding the leading A1 bytes as sync marks. `weak` is a mask of weak bits in the
+/// bytes following the address mark.
+fn push_field(stream: &mut Bitstream, field: &[u8], weak: Option<&[u8]>) {
+    for _ in 0..3 {
+        stream.push_raw(MFM_SYNC_A1);
+    }
+    stream.push_byte(field[3]);
+    for (i, byte) in field[4..].iter().enumerate() {
+        let mask = weak.and_then(|weak| weak.get(i)).copied().unwrap_or(0);
+        stream.push_byte_weak(*byte, mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16() {
+        // The ID field CRC of cylinder 0, head 0, sector 1, 512 bytes.
+        assert_eq!(crc16(&[0xA1, 0xA1, 0xA1, 0xFE, 0x00, 0x00, 0x01, 0x02]), 0xCA6F);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut track = FloppyTrack::standard(3, 1, 9, 0);
+        for (i, sector) in track.sectors.iter_mut().enumerate() {
+            sector.data = (0..512).map(|b| (b + i) as u8).collect();
+        }
+        track.sectors[4].deleted = true;
+        track.sectors[6].data_crc_error = true;
+
+        let stream = encode_track(&track, 6250, 84);
+        assert_eq!(stream.len(), 6250 * CELLS_PER_BYTE);
+
+        // Start the stream part way through the first ID field, so that sector wraps around the index
+        let decoded = decode_track(&stream.rotate(160 * CELLS_PER_BYTE));
+        assert_eq!(decoded.recorded_len, Some(6250));
+        assert_eq!(decoded.sectors.len(), 9);
+        for sector in &decoded.sectors {
+            let original = track.sectors.iter().find(|s| s.id == sector.id).unwrap();
+            assert_eq!(sector.data, original.data);
+            assert_eq!(sector.deleted, original.deleted);
+            assert_eq!(sector.data_crc_error, original.data_crc_error);
+            assert!(!sector.id_crc_error);
+        }
+    }
+}
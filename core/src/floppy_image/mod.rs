@@ -0,0 +1,389 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::mod.rs
+
+    A sector level representation of a floppy disk.
+
+    Each track holds its sectors in the order they pass under the head, with
+    the full address field (C, H, R, N) of each. A track can therefore hold
+    sectors with arbitrary IDs and sizes, duplicate IDs, or no sectors at
+    all. Sectors also carry deleted address mark and CRC error flags, which
+    copy protection schemes depend on.
+
+    Sectors decoded from bitstream images also record where they start on
+    the track and which of their bits are weak, and the track records its
+    length, so that long tracks and weak bit protection survive conversion.
+
+    Image format loaders convert into this representation. Any disk can be
+    written out as a PSI or PRI image, but only a disk with a standard
+    layout can be written back out as a raw sector image.
+
+*/
+
+pub mod d86f;
+pub mod fat12;
+pub mod imd;
+mod lzhuf;
+pub mod mfm;
+mod pce;
+pub mod pri;
+pub mod psi;
+pub mod scp;
+pub mod td0;
+
+use anyhow::{anyhow, Error};
+
+use crate::device_types::{chs::DiskChs, fdc::DISK_FORMATS};
+
+pub const RAW_SECTOR_SIZE: usize = 512;
+pub const RAW_SECTOR_SIZE_CODE: u8 = 2;
+
+// The byte DOS fills sectors with when it formats a disk.
+pub const FORMAT_FILL_BYTE: u8 = 0xF6;
+
+// Approximate number of bytes each sector occupies on a track in addition to its data: the ID field, data address
+// mark, CRCs, sync bytes and gaps. Used to estimate the data rate a track was recorded at.
+pub const SECTOR_OVERHEAD: usize = 100;
+
+/// Return the size in bytes of a sector with the given size code. The µPD765 only decodes the low three bits.
+pub fn sector_size(n: u8) -> usize {
+    128 << (n & 0x07)
+}
+
+/// The address field of a sector.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SectorId {
+    pub c: u8,
+    pub h: u8,
+    pub r: u8,
+    pub n: u8,
+}
+
+impl SectorId {
+    pub fn new(c: u8, h: u8, r: u8, n: u8) -> Self {
+        Self { c, h, r, n }
+    }
+
+    pub fn size(&self) -> usize {
+        sector_size(self.n)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FloppySector {
+    pub id: SectorId,
+    pub data: Vec<u8>,
+    pub deleted: bool,           // Data field has a deleted data address mark
+    pub id_crc_error: bool,      // CRC error in the ID field
+    pub data_crc_error: bool,    // CRC error in the data field
+    pub no_data: bool,           // ID field without a data field
+    pub weak: Option<Vec<u8>>,   // Mask of data bits that read back randomly
+    pub position: Option<usize>, // Offset of the ID field from the index, in bytes
+}
+
+impl FloppySector {
+    pub fn new(id: SectorId, data: Vec<u8>) -> Self {
+        Self {
+            id,
+            data,
+            deleted: false,
+            id_crc_error: false,
+            data_crc_error: false,
+            no_data: false,
+            weak: None,
+            position: None,
+        }
+    }
+
+    /// Return `len` bytes of the data field. Data fields recorded shorter than requested are padded with zeros.
+    pub fn read_data(&self, len: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = self.data.iter().take(len).copied().collect();
+        data.resize(len, 0);
+        data
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FloppyTrack {
+    pub sectors: Vec<FloppySector>,
+    pub recorded_len: Option<usize>, // Length of the track in bytes, if known from a bitstream image
+}
+
+impl FloppyTrack {
+    pub fn new(sectors: Vec<FloppySector>) -> Self {
+        Self {
+            sectors,
+            recorded_len: None,
+        }
+    }
+
+    /// Create a track in the standard layout: `spt` sectors of 512 bytes numbered from 1, filled with `fill`.
+    pub fn standard(c: u8, h: u8, spt: u8, fill: u8) -> Self {
+        Self {
+            sectors: (1..=spt)
+                .map(|r| {
+                    FloppySector::new(
+                        SectorId::new(c, h, r, RAW_SECTOR_SIZE_CODE),
+                        vec![fill; RAW_SECTOR_SIZE],
+                    )
+                })
+                .collect(),
+            recorded_len: None,
+        }
+    }
+
+    pub fn is_formatted(&self) -> bool {
+        !self.sectors.is_empty()
+    }
+
+    /// Estimate the number of bytes the track occupies when encoded on the disk.
+    pub fn encoded_len(&self) -> usize {
+        match self.recorded_len {
+            Some(len) => len,
+            None => self.sectors.iter().map(|s| s.id.size() + SECTOR_OVERHEAD).sum(),
+        }
+    }
+}
+
+pub struct FloppyImage {
+    cylinders: u8,
+    heads: u8,
+    tracks: Vec<FloppyTrack>,
+}
+
+impl FloppyImage {
+    /// Create an unformatted disk.
+    pub fn new(cylinders: u8, heads: u8) -> Self {
+        Self {
+            cylinders,
+            heads,
+            tracks: vec![FloppyTrack::default(); cylinders as usize * heads as usize],
+        }
+    }
+
+    /// Create a disk formatted with the standard layout for the given geometry, with every sector filled with
+    /// `fill`.
+    pub fn formatted(geom: DiskChs, fill: u8) -> Self {
+        let mut image = Self::new(geom.c(), geom.h());
+        for c in 0..geom.c() {
+            for h in 0..geom.h() {
+                image.set_track(c, h, FloppyTrack::standard(c, h, geom.s(), fill));
+            }
+        }
+        image
+    }
+
+    /// Return the standard geometry of a raw sector image of the given size.
+    pub fn geometry_for_size(len: usize) -> Option<DiskChs> {
+        if let Some(fmt) = DISK_FORMATS.get(&len) {
+            Some(fmt.chs)
+        }
+        else if len < 163_840 {
+            // If image is smaller than single sided disk, assume single sided disk, 8 sectors per track
+            // This is useful for loading things like boot sector images without having to copy them to
+            // a full disk image
+            Some(DiskChs::new(40, 1, 8))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Load a disk from an image in any supported format. Bitstream, flux and archival images are recognized by
+    /// their signature; anything else is treated as a raw sector image.
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        if d86f::detect(data) {
+            d86f::load(data)
+        }
+        else if scp::detect(data) {
+            scp::load(data)
+        }
+        else if imd::detect(data) {
+            imd::load(data)
+        }
+        else if td0::detect(data) {
+            td0::load(data)
+        }
+        else if psi::detect(data) {
+            psi::load(data)
+        }
+        else if pri::detect(data) {
+            pri::load(data)
+        }
+        else {
+            Self::from_raw(data)
+        }
+    }
+
+    /// Create a disk from a raw sector image, determining the geometry from the image size.
+    pub fn from_raw(data: &[u8]) -> Result<Self, Error> {
+        // Disk images must contain whole sectors
+        if data.len() % RAW_SECTOR_SIZE > 0 {
+            return Err(anyhow!("Invalid image length"));
+        }
+        let geom = Self::geometry_for_size(data.len()).ok_or_else(|| anyhow!("Invalid image length"))?;
+        Ok(Self::from_raw_geometry(data, geom))
+    }
+
+    /// Create a disk from a raw sector image with the specified geometry. Sectors past the end of the image are
+    /// filled with zeros.
+    pub fn from_raw_geometry(data: &[u8], geom: DiskChs) -> Self {
+        let mut image = Self::new(geom.c(), geom.h());
+        let mut chunks = data.chunks(RAW_SECTOR_SIZE);
+        for c in 0..geom.c() {
+            for h in 0..geom.h() {
+                let mut track = FloppyTrack::standard(c, h, geom.s(), 0);
+                for sector in track.sectors.iter_mut() {
+                    if let Some(chunk) = chunks.next() {
+                        sector.data[..chunk.len()].copy_from_slice(chunk);
+                    }
+                }
+                image.set_track(c, h, track);
+            }
+        }
+        image
+    }
+
+    pub fn cylinders(&self) -> u8 {
+        self.cylinders
+    }
+
+    pub fn heads(&self) -> u8 {
+        self.heads
+    }
+
+    pub fn track(&self, c: u8, h: u8) -> Option<&FloppyTrack> {
+        if c >= self.cylinders || h >= self.heads {
+            return None;
+        }
+        self.tracks.get(c as usize * self.heads as usize + h as usize)
+    }
+
+    pub fn track_mut(&mut self, c: u8, h: u8) -> Option<&mut FloppyTrack> {
+        if c >= self.cylinders || h >= self.heads {
+            return None;
+        }
+        self.tracks.get_mut(c as usize * self.heads as usize + h as usize)
+    }
+
+    /// Replace the specified track, adding unformatted cylinders or a second side to the disk as needed.
+    pub fn set_track(&mut self, c: u8, h: u8, track: FloppyTrack) {
+        if c >= self.cylinders || h >= self.heads {
+            self.resize(std::cmp::max(self.cylinders, c + 1), std::cmp::max(self.heads, h + 1));
+        }
+        let idx = c as usize * self.heads as usize + h as usize;
+        self.tracks[idx] = track;
+    }
+
+    fn resize(&mut self, cylinders: u8, heads: u8) {
+        let mut tracks = vec![FloppyTrack::default(); cylinders as usize * heads as usize];
+        for c in 0..self.cylinders {
+            for h in 0..self.heads {
+                let old_idx = c as usize * self.heads as usize + h as usize;
+                tracks[c as usize * heads as usize + h as usize] = std::mem::take(&mut self.tracks[old_idx]);
+            }
+        }
+        self.cylinders = cylinders;
+        self.heads = heads;
+        self.tracks = tracks;
+    }
+
+    /// Return the geometry of the disk if every track has the standard layout, with 512 byte sectors numbered from
+    /// 1 and IDs matching their physical location. Only such a disk can be saved as a raw sector image.
+    pub fn standard_geometry(&self) -> Option<DiskChs> {
+        let spt = self.track(0, 0)?.sectors.len();
+        if spt == 0 || spt > u8::MAX as usize {
+            return None;
+        }
+        for c in 0..self.cylinders {
+            for h in 0..self.heads {
+                let track = self.track(c, h)?;
+                if track.sectors.len() != spt {
+                    return None;
+                }
+                let mut seen = vec![false; spt];
+                for sector in &track.sectors {
+                    let id = sector.id;
+                    if id.c != c || id.h != h || id.n != RAW_SECTOR_SIZE_CODE || id.r == 0 || id.r as usize > spt {
+                        return None;
+                    }
+                    if std::mem::replace(&mut seen[id.r as usize - 1], true) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(DiskChs::new(self.cylinders, self.heads, spt as u8))
+    }
+
+    /// Write the disk out as a raw sector image.
+    pub fn to_raw(&self) -> Result<Vec<u8>, Error> {
+        let geom = self
+            .standard_geometry()
+            .ok_or_else(|| anyhow!("Disk does not have a standard layout and can't be saved as a sector image"))?;
+
+        let mut data = Vec::with_capacity(geom.get_sector_count() as usize * RAW_SECTOR_SIZE);
+        for c in 0..geom.c() {
+            for h in 0..geom.h() {
+                let track = self.track(c, h).unwrap();
+                for r in 1..=geom.s() {
+                    let sector = track.sectors.iter().find(|s| s.id.r == r).unwrap();
+                    data.extend_from_slice(&sector.read_data(RAW_SECTOR_SIZE));
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_round_trip() {
+        let raw: Vec<u8> = (0..368_640).map(|i| (i / RAW_SECTOR_SIZE) as u8).collect();
+        let image = FloppyImage::from_raw(&raw).unwrap();
+
+        assert_eq!(image.cylinders(), 40);
+        assert_eq!(image.heads(), 2);
+        assert_eq!(image.track(1, 0).unwrap().sectors.len(), 9);
+        assert_eq!(image.to_raw().unwrap(), raw);
+    }
+
+    #[test]
+    fn test_nonstandard_layout() {
+        let mut image = FloppyImage::formatted(DiskChs::new(40, 2, 9), 0xF6);
+        assert!(image.standard_geometry().is_some());
+
+        // Reordering sectors doesn't prevent saving, but a non-standard ID does
+        image.track_mut(0, 0).unwrap().sectors.reverse();
+        assert!(image.standard_geometry().is_some());
+        image.track_mut(5, 1).unwrap().sectors[0].id.c = 0x20;
+        assert!(image.to_raw().is_err());
+    }
+}
@@ -0,0 +1,121 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::pce.rs
+
+    The chunk container shared by the image formats of the PCE emulator.
+
+    A PCE image is a sequence of chunks, each made up of a four character
+    ID, a big-endian 32-bit length, the chunk data and a CRC over all of the
+    preceding fields. The first chunk identifies the format and the last is
+    always an END chunk.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+pub const CHUNK_END: &[u8; 4] = b"END ";
+pub const CHUNK_TEXT: &[u8; 4] = b"TEXT";
+
+/// Calculate the CRC used by PCE images: CRC-32 with polynomial 0x1EDC6F41, not reflected, with an initial value of
+/// zero.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = crc;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x1EDC_6F41
+            }
+            else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub struct Chunk<'a> {
+    pub id:   [u8; 4],
+    pub data: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    pub fn u8(&self, pos: usize) -> u8 {
+        self.data.get(pos).copied().unwrap_or(0)
+    }
+
+    pub fn u16(&self, pos: usize) -> u16 {
+        u16::from_be_bytes([self.u8(pos), self.u8(pos + 1)])
+    }
+
+    pub fn u32(&self, pos: usize) -> u32 {
+        u32::from_be_bytes([self.u8(pos), self.u8(pos + 1), self.u8(pos + 2), self.u8(pos + 3)])
+    }
+}
+
+/// Split an image into chunks, stopping at the END chunk. The first chunk must have the ID `magic`.
+pub fn read_chunks<'a>(data: &'a [u8], magic: &[u8; 4]) -> Result<Vec<Chunk<'a>>, Error> {
+    let mut chunks = Vec::new();
+    let mut pos = 0;
+    loop {
+        let header = data
+            .get(pos..pos + 8)
+            .ok_or_else(|| anyhow!("Image is truncated: missing END chunk"))?;
+        let id = [header[0], header[1], header[2], header[3]];
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let chunk_data = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| anyhow!("Chunk {} is truncated", String::from_utf8_lossy(&id)))?;
+        let crc = data
+            .get(pos + 8 + len..pos + 12 + len)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| anyhow!("Chunk {} is truncated", String::from_utf8_lossy(&id)))?;
+        if crc32(0, &data[pos..pos + 8 + len]) != crc {
+            log::warn!("CRC error in chunk {} at offset {}", String::from_utf8_lossy(&id), pos);
+        }
+
+        if chunks.is_empty() && &id != magic {
+            return Err(anyhow!("Not a {} image", String::from_utf8_lossy(magic).trim_end()));
+        }
+        pos += len + 12;
+        if &id == CHUNK_END {
+            break;
+        }
+        chunks.push(Chunk { id, data: chunk_data });
+    }
+    Ok(chunks)
+}
+
+/// Append a chunk to an image.
+pub fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    let start = out.len();
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+    let crc = crc32(0, &out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
@@ -0,0 +1,206 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::pri.rs
+
+    Reader and writer for PCE raw bitstream images (PRI).
+
+    A PRI image stores each track as a TRAK chunk giving its location,
+    length in bit cells and cell rate, followed by a DATA chunk with the
+    MFM encoded cells and an optional WEAK chunk listing the cells that have
+    no reliable flux transitions. Tracks are decoded into sectors when
+    loaded, and re-encoded in the standard layout when saved.
+
+*/
+
+use anyhow::Error;
+
+use crate::floppy_image::{
+    mfm::{self, Bitstream},
+    pce,
+    FloppyImage,
+};
+
+pub const PRI_MAGIC: &[u8; 4] = b"PRI ";
+pub const PRI_VERSION: u16 = 0;
+
+const CHUNK_TRACK: &[u8; 4] = b"TRAK";
+const CHUNK_DATA: &[u8; 4] = b"DATA";
+const CHUNK_WEAK: &[u8; 4] = b"WEAK";
+
+// Cell rates of double and high density tracks at 300 RPM
+const CLOCK_DD: u32 = 500_000;
+const CLOCK_HD: u32 = 1_000_000;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..4] == PRI_MAGIC
+}
+
+/// A track being assembled from its chunks.
+struct PriTrack {
+    c:    u8,
+    h:    u8,
+    len:  usize,
+    bits: Vec<u8>,
+    weak: Option<Vec<u8>>,
+}
+
+/// Load a PRI image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    let chunks = pce::read_chunks(data, PRI_MAGIC)?;
+
+    let mut image = FloppyImage::new(0, 1);
+    let mut current: Option<PriTrack> = None;
+    for chunk in &chunks {
+        match &chunk.id {
+            CHUNK_TRACK => {
+                if let Some(track) = current.take() {
+                    add_track(&mut image, track);
+                }
+                current = Some(PriTrack {
+                    c:    chunk.u32(0) as u8,
+                    h:    chunk.u32(4) as u8,
+                    len:  chunk.u32(8) as usize,
+                    bits: Vec::new(),
+                    weak: None,
+                });
+            }
+            CHUNK_DATA => {
+                if let Some(track) = &mut current {
+                    track.bits = chunk.data.to_vec();
+                }
+            }
+            CHUNK_WEAK => {
+                if let Some(track) = &mut current {
+                    // Each entry is a cell offset and a mask of the 32 cells starting there
+                    let mut weak = vec![0; track.len.div_ceil(8)];
+                    for entry in chunk.data.chunks_exact(8) {
+                        let offset = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+                        let mask = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]);
+                        for i in (0..32).filter(|i| mask & (0x8000_0000 >> i) != 0) {
+                            let pos = offset + i;
+                            if pos < track.len {
+                                weak[pos / 8] |= 0x80 >> (pos % 8);
+                            }
+                        }
+                    }
+                    track.weak = Some(weak);
+                }
+            }
+            pce::CHUNK_TEXT => log::debug!("PRI comment: {}", String::from_utf8_lossy(chunk.data)),
+            _ => {}
+        }
+    }
+    if let Some(track) = current.take() {
+        add_track(&mut image, track);
+    }
+    Ok(image)
+}
+
+fn add_track(image: &mut FloppyImage, track: PriTrack) {
+    let stream = Bitstream::from_bytes(track.bits, track.weak, track.len);
+    image.set_track(track.c, track.h, mfm::decode_track(&stream));
+}
+
+/// Write a disk as a PRI image.
+pub fn save(image: &FloppyImage) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut header = PRI_VERSION.to_be_bytes().to_vec();
+    header.extend_from_slice(&[0, 0]);
+    pce::write_chunk(&mut out, PRI_MAGIC, &header);
+
+    for c in 0..image.cylinders() {
+        for h in 0..image.heads() {
+            let track = match image.track(c, h) {
+                Some(track) => track,
+                None => continue,
+            };
+            let stream = mfm::encode_track_fit(track);
+            let clock = if stream.len() > mfm::TRACK_LEN_DD * 3 / 2 * mfm::CELLS_PER_BYTE {
+                CLOCK_HD
+            }
+            else {
+                CLOCK_DD
+            };
+
+            let mut trak = Vec::new();
+            for value in [c as u32, h as u32, stream.len() as u32, clock] {
+                trak.extend_from_slice(&value.to_be_bytes());
+            }
+            pce::write_chunk(&mut out, CHUNK_TRACK, &trak);
+            pce::write_chunk(&mut out, CHUNK_DATA, stream.as_bytes());
+
+            if let Some(weak) = stream.weak_bytes() {
+                let mut entries = Vec::new();
+                for (i, word) in weak.chunks(4).enumerate() {
+                    let mut bytes = [0; 4];
+                    bytes[..word.len()].copy_from_slice(word);
+                    let mask = u32::from_be_bytes(bytes);
+                    if mask != 0 {
+                        entries.extend_from_slice(&((i * 32) as u32).to_be_bytes());
+                        entries.extend_from_slice(&mask.to_be_bytes());
+                    }
+                }
+                pce::write_chunk(&mut out, CHUNK_WEAK, &entries);
+            }
+        }
+    }
+    pce::write_chunk(&mut out, pce::CHUNK_END, &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::floppy_image::FloppyTrack;
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = FloppyImage::new(1, 2);
+        for h in 0..2 {
+            let mut track = FloppyTrack::standard(0, h, 9, 0xE5);
+            track.sectors[2].data = (0..512).map(|i| (i * 7) as u8).collect();
+            track.sectors[3].weak = Some([vec![0; 20], vec![0xFF; 8], vec![0; 484]].concat());
+            track.sectors[5].data_crc_error = true;
+            image.set_track(0, h, track);
+        }
+
+        let loaded = load(&save(&image)).unwrap();
+        assert_eq!(loaded.heads(), 2);
+        for h in 0..2 {
+            let original = image.track(0, h).unwrap();
+            let track = loaded.track(0, h).unwrap();
+            assert_eq!(track.recorded_len, Some(mfm::TRACK_LEN_DD));
+            assert_eq!(track.sectors.len(), 9);
+            for (a, b) in track.sectors.iter().zip(&original.sectors) {
+                assert_eq!(a.id, b.id);
+                assert_eq!(a.data, b.data);
+                assert_eq!(a.weak, b.weak);
+                assert_eq!(a.data_crc_error, b.data_crc_error);
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::psi.rs
+
+    Reader and writer for PCE sector images (PSI).
+
+    A PSI image describes every sector with a SECT chunk giving its physical
+    location and size, followed by optional chunks: an IBM address field
+    with CRC error and address mark flags, the sector data, a weak bit mask
+    and the sector's position on the track. A sector that was read
+    differently on repeated reads is stored again as an alternate sector;
+    alternates are combined into the sector's weak bit mask.
+
+*/
+
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+
+use crate::floppy_image::{
+    mfm,
+    pce::{self, Chunk},
+    FloppyImage,
+    FloppySector,
+    FloppyTrack,
+    SectorId,
+};
+
+pub const PSI_MAGIC: &[u8; 4] = b"PSI ";
+pub const PSI_VERSION: u16 = 0;
+
+const CHUNK_SECT: &[u8; 4] = b"SECT";
+const CHUNK_IBM_MFM: &[u8; 4] = b"IBMM";
+const CHUNK_IBM_FM: &[u8; 4] = b"IBMF";
+const CHUNK_DATA: &[u8; 4] = b"DATA";
+const CHUNK_WEAK: &[u8; 4] = b"WEAK";
+const CHUNK_OFFSET: &[u8; 4] = b"OFFS";
+
+const FORMAT_MFM_DD: u16 = 0x0200;
+const FORMAT_MFM_HD: u16 = 0x0201;
+
+// SECT chunk flags
+const SECT_COMPRESSED: u8 = 0x01;
+const SECT_ALTERNATE: u8 = 0x02;
+const SECT_CRC_DATA: u8 = 0x04;
+
+// IBMM chunk flags
+const IBM_CRC_ID: u8 = 0x01;
+const IBM_CRC_DATA: u8 = 0x02;
+const IBM_DELETED: u8 = 0x04;
+const IBM_NO_DATA: u8 = 0x08;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..4] == PSI_MAGIC
+}
+
+/// A sector being assembled from its chunks.
+struct PsiSector {
+    c: u8,
+    h: u8,
+    alternate: bool,
+    sector: FloppySector,
+}
+
+/// Load a PSI image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    let chunks = pce::read_chunks(data, PSI_MAGIC)?;
+
+    let mut tracks: BTreeMap<(u8, u8), Vec<FloppySector>> = BTreeMap::new();
+    let mut current: Option<PsiSector> = None;
+    for chunk in &chunks {
+        match &chunk.id {
+            CHUNK_SECT => {
+                if let Some(sector) = current.take() {
+                    add_sector(&mut tracks, sector);
+                }
+                current = Some(read_sect(chunk));
+            }
+            CHUNK_IBM_MFM | CHUNK_IBM_FM => {
+                if let Some(PsiSector { sector, .. }) = &mut current {
+                    let flags = chunk.u8(4);
+                    sector.id = SectorId::new(chunk.u8(0), chunk.u8(1), chunk.u8(2), chunk.u8(3));
+                    sector.id_crc_error = flags & IBM_CRC_ID != 0;
+                    sector.data_crc_error |= flags & IBM_CRC_DATA != 0;
+                    sector.deleted = flags & IBM_DELETED != 0;
+                    sector.no_data = flags & IBM_NO_DATA != 0;
+                }
+            }
+            CHUNK_DATA => {
+                if let Some(PsiSector { sector, .. }) = &mut current {
+                    let len = sector.data.len();
+                    sector.data = chunk.data.to_vec();
+                    sector.data.resize(len, 0);
+                }
+            }
+            CHUNK_WEAK => {
+                if let Some(PsiSector { sector, .. }) = &mut current {
+                    // The mask starts at the given offset into the sector data
+                    let offset = chunk.u16(0) as usize;
+                    let mut mask = vec![0; sector.data.len()];
+                    for (m, w) in mask.iter_mut().skip(offset).zip(&chunk.data[2.min(chunk.data.len())..]) {
+                        *m = *w;
+                    }
+                    sector.weak = Some(mask);
+                }
+            }
+            CHUNK_OFFSET => {
+                if let Some(PsiSector { sector, .. }) = &mut current {
+                    // Offsets are in bit cells from the index
+                    sector.position = Some(chunk.u32(0) as usize / 16);
+                }
+            }
+            pce::CHUNK_TEXT => log::debug!("PSI comment: {}", String::from_utf8_lossy(chunk.data)),
+            _ => {}
+        }
+    }
+    if let Some(sector) = current.take() {
+        add_sector(&mut tracks, sector);
+    }
+
+    let mut image = FloppyImage::new(0, 1);
+    for ((c, h), sectors) in tracks {
+        image.set_track(c, h, FloppyTrack::new(sectors));
+    }
+    Ok(image)
+}
+
+fn read_sect(chunk: &Chunk) -> PsiSector {
+    let (c, h, r) = (chunk.u16(0) as u8, chunk.u8(2), chunk.u8(3));
+    let size = chunk.u16(4) as usize;
+    let flags = chunk.u8(6);
+    let fill = chunk.u8(7);
+
+    let mut sector = FloppySector::new(SectorId::new(c, h, r, size_code(size)), vec![fill; size]);
+    sector.data_crc_error = flags & SECT_CRC_DATA != 0;
+    if flags & SECT_COMPRESSED == 0 {
+        // Uncompressed sectors are followed by a DATA chunk
+        sector.data.fill(0);
+    }
+    PsiSector {
+        c,
+        h,
+        alternate: flags & SECT_ALTERNATE != 0,
+        sector,
+    }
+}
+
+/// Add a sector to its track. An alternate sector marks the bits in which it differs from the previous copy of the
+/// same sector as weak.
+fn add_sector(tracks: &mut BTreeMap<(u8, u8), Vec<FloppySector>>, psi_sector: PsiSector) {
+    let sectors = tracks.entry((psi_sector.c, psi_sector.h)).or_default();
+    let sector = psi_sector.sector;
+    if psi_sector.alternate {
+        if let Some(original) = sectors.iter_mut().rev().find(|s| s.id == sector.id) {
+            let mut mask = original.weak.take().unwrap_or_else(|| vec![0; original.data.len()]);
+            for ((m, a), b) in mask.iter_mut().zip(&original.data).zip(&sector.data) {
+                *m |= a ^ b;
+            }
+            if mask.iter().any(|m| *m != 0) {
+                original.weak = Some(mask);
+            }
+            return;
+        }
+    }
+    sectors.push(sector);
+}
+
+/// Return the size code of a sector of `size` bytes.
+fn size_code(size: usize) -> u8 {
+    (0..8).find(|n| super::sector_size(*n) >= size).unwrap_or(7)
+}
+
+/// Write a disk as a PSI image.
+pub fn save(image: &FloppyImage) -> Vec<u8> {
+    let high_density = (0..image.cylinders())
+        .any(|c| (0..image.heads()).any(|h| image.track(c, h).is_some_and(|t| t.encoded_len() > mfm::TRACK_LEN_DD)));
+    let format = if high_density { FORMAT_MFM_HD } else { FORMAT_MFM_DD };
+
+    let mut out = Vec::new();
+    let mut header = PSI_VERSION.to_be_bytes().to_vec();
+    header.extend_from_slice(&format.to_be_bytes());
+    pce::write_chunk(&mut out, PSI_MAGIC, &header);
+
+    for c in 0..image.cylinders() {
+        for h in 0..image.heads() {
+            let track = match image.track(c, h) {
+                Some(track) => track,
+                None => continue,
+            };
+            for sector in &track.sectors {
+                write_sector(&mut out, c, h, sector);
+            }
+        }
+    }
+    pce::write_chunk(&mut out, pce::CHUNK_END, &[]);
+    out
+}
+
+fn write_sector(out: &mut Vec<u8>, c: u8, h: u8, sector: &FloppySector) {
+    let data = if sector.no_data {
+        Vec::new()
+    }
+    else {
+        sector.read_data(sector.id.size())
+    };
+    let fill = data.first().copied().unwrap_or(0);
+    let compressed = data.iter().all(|b| *b == fill);
+
+    let mut flags = 0;
+    if compressed {
+        flags |= SECT_COMPRESSED;
+    }
+    if sector.data_crc_error {
+        flags |= SECT_CRC_DATA;
+    }
+    let mut sect = (c as u16).to_be_bytes().to_vec();
+    sect.extend_from_slice(&[h, sector.id.r]);
+    sect.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    sect.extend_from_slice(&[flags, fill]);
+    pce::write_chunk(out, CHUNK_SECT, &sect);
+
+    let mut ibm_flags = 0;
+    if sector.id_crc_error {
+        ibm_flags |= IBM_CRC_ID;
+    }
+    if sector.data_crc_error {
+        ibm_flags |= IBM_CRC_DATA;
+    }
+    if sector.deleted {
+        ibm_flags |= IBM_DELETED;
+    }
+    if sector.no_data {
+        ibm_flags |= IBM_NO_DATA;
+    }
+    let id = sector.id;
+    pce::write_chunk(out, CHUNK_IBM_MFM, &[id.c, id.h, id.r, id.n, ibm_flags, 0]);
+
+    if !compressed {
+        pce::write_chunk(out, CHUNK_DATA, &data);
+    }
+    if let Some(weak) = &sector.weak {
+        if let Some(offset) = weak.iter().position(|m| *m != 0) {
+            let mut chunk = (offset as u16).to_be_bytes().to_vec();
+            chunk.extend_from_slice(&weak[offset..]);
+            pce::write_chunk(out, CHUNK_WEAK, &chunk);
+        }
+    }
+    if let Some(position) = sector.position {
+        pce::write_chunk(out, CHUNK_OFFSET, &((position * 16) as u32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut image = FloppyImage::new(2, 2);
+        let mut sectors = FloppyTrack::standard(1, 1, 4, 0xF6).sectors;
+        sectors[0].data = (0..512).map(|i| i as u8).collect();
+        sectors[1].deleted = true;
+        sectors[1].data_crc_error = true;
+        sectors[2].id = SectorId::new(0x20, 0, 0xF0, 3);
+        sectors[2].data = vec![0x01; 1024];
+        sectors[2].weak = Some([vec![0; 100], vec![0x0F; 10], vec![0; 914]].concat());
+        sectors[2].position = Some(2000);
+        sectors[3].no_data = true;
+        sectors[3].id_crc_error = true;
+        image.set_track(1, 1, FloppyTrack::new(sectors.clone()));
+
+        let loaded = load(&save(&image)).unwrap();
+        assert_eq!(loaded.cylinders(), 2);
+        assert_eq!(loaded.heads(), 2);
+        let track = loaded.track(1, 1).unwrap();
+        assert_eq!(track.sectors.len(), 4);
+        for (a, b) in track.sectors.iter().zip(&sectors) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.deleted, b.deleted);
+            assert_eq!(a.data_crc_error, b.data_crc_error);
+            assert_eq!(a.id_crc_error, b.id_crc_error);
+            assert_eq!(a.no_data, b.no_data);
+            assert_eq!(a.weak, b.weak);
+            assert_eq!(a.position, b.position);
+            if !b.no_data {
+                assert_eq!(a.data, b.data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternate_sectors() {
+        let sector = FloppySector::new(SectorId::new(0, 0, 1, 2), vec![0x55; 512]);
+        let mut out = Vec::new();
+        pce::write_chunk(&mut out, PSI_MAGIC, &[0, 0, 2, 0]);
+        write_sector(&mut out, 0, 0, &sector);
+
+        // An alternate read of the sector with one bit different
+        let mut alternate = sector.clone();
+        alternate.data[7] = 0x54;
+        let start = out.len();
+        write_sector(&mut out, 0, 0, &alternate);
+        out[start + 8 + 6] |= SECT_ALTERNATE;
+        let crc_pos = start + 8 + 8;
+        let crc = pce::crc32(0, &out[start..crc_pos]);
+        out[crc_pos..crc_pos + 4].copy_from_slice(&crc.to_be_bytes());
+        pce::write_chunk(&mut out, pce::CHUNK_END, &[]);
+
+        let image = load(&out).unwrap();
+        let track = image.track(0, 0).unwrap();
+        assert_eq!(track.sectors.len(), 1);
+        let weak = track.sectors[0].weak.as_ref().unwrap();
+        assert_eq!(weak[7], 0x01);
+        assert_eq!(weak.iter().filter(|m| **m != 0).count(), 1);
+    }
+}
@@ -0,0 +1,284 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::scp.rs
+
+    Loader for SuperCard Pro flux images.
+
+    An SCP image records the time between flux transitions over one or more
+    revolutions of each track. Each revolution is converted to a bitstream
+    with a simple software PLL and decoded into sectors. Where a sector
+    reads back differently on different revolutions, the bits that changed
+    are marked weak, which is how weak bit protection appears at the flux
+    level. Where a revolution has a sector with a CRC error that another
+    revolution read correctly, the good copy is kept.
+
+    Tracks are numbered cylinder * 2 + head, as written by double sided
+    captures.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+use crate::floppy_image::{
+    mfm::{self, Bitstream},
+    FloppyImage,
+    FloppyTrack,
+};
+
+pub const SCP_MAGIC: &[u8; 3] = b"SCP";
+pub const SCP_HEADER_LEN: usize = 0x10;
+pub const SCP_MAX_TRACKS: usize = 168;
+pub const SCP_TICK_NS: f64 = 25.0;
+
+const SCP_FLAG_EXTENDED: u8 = 0x40;
+
+// How far the PLL may drift from the initial bit cell estimate.
+const PLL_RANGE: f64 = 0.15;
+// Proportion of the phase error corrected on each flux transition.
+const PLL_GAIN: f64 = 0.05;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= SCP_HEADER_LEN && &data[0..3] == SCP_MAGIC
+}
+
+/// Load an SCP image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    if !detect(data) {
+        return Err(anyhow!("Not an SCP image"));
+    }
+    let revolutions = data[5] as usize;
+    let (start_track, end_track) = (data[6] as usize, data[7] as usize);
+    let flags = data[8];
+    let cell_width = data[9];
+    let tick_ns = SCP_TICK_NS * (data[11] as f64 + 1.0);
+
+    if flags & SCP_FLAG_EXTENDED != 0 {
+        return Err(anyhow!("Extended mode SCP images are not supported"));
+    }
+    if cell_width != 0 && cell_width != 16 {
+        return Err(anyhow!("Unsupported SCP flux cell width: {}", cell_width));
+    }
+    if revolutions == 0 || end_track < start_track || end_track >= SCP_MAX_TRACKS {
+        return Err(anyhow!("Invalid SCP header"));
+    }
+
+    let mut image = FloppyImage::new(0, 1);
+    for track_no in start_track..=end_track {
+        let offset = read_u32(data, SCP_HEADER_LEN + track_no * 4)? as usize;
+        if offset == 0 {
+            continue;
+        }
+        if data.get(offset..offset + 3) != Some(b"TRK".as_slice()) {
+            return Err(anyhow!("SCP track {} has an invalid header", track_no));
+        }
+
+        let mut tracks = Vec::with_capacity(revolutions);
+        for rev in 0..revolutions {
+            let entry = offset + 4 + rev * 12;
+            let flux_ct = read_u32(data, entry + 4)? as usize;
+            let flux_offset = offset + read_u32(data, entry + 8)? as usize;
+            let flux = data
+                .get(flux_offset..flux_offset + flux_ct * 2)
+                .ok_or_else(|| anyhow!("SCP track {} is truncated", track_no))?;
+
+            let intervals = flux_intervals(flux, tick_ns);
+            tracks.push(mfm::decode_track(&flux_to_bitstream(&intervals)));
+        }
+
+        let (c, h) = ((track_no / 2) as u8, (track_no % 2) as u8);
+        image.set_track(c, h, merge_revolutions(tracks));
+    }
+
+    if image.cylinders() == 0 {
+        return Err(anyhow!("SCP image contains no tracks"));
+    }
+    Ok(image)
+}
+
+/// Convert big-endian 16-bit flux counts to intervals in nanoseconds. A count of zero means the interval is longer
+/// than 16 bits and continues into the next value.
+fn flux_intervals(flux: &[u8], tick_ns: f64) -> Vec<f64> {
+    let mut intervals = Vec::with_capacity(flux.len() / 2);
+    let mut carry = 0.0;
+    for word in flux.chunks_exact(2) {
+        match u16::from_be_bytes([word[0], word[1]]) {
+            0 => carry += 65536.0,
+            ticks => {
+                intervals.push((carry + ticks as f64) * tick_ns);
+                carry = 0.0;
+            }
+        }
+    }
+    intervals
+}
+
+/// Recover the bit cells of a revolution from its flux transition intervals.
+fn flux_to_bitstream(intervals: &[f64]) -> Bitstream {
+    let mut stream = Bitstream::new();
+    if intervals.is_empty() {
+        return stream;
+    }
+
+    // The shortest MFM interval is two bit cells. Use a low percentile rather than the minimum so a single noisy
+    // transition doesn't skew the estimate.
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let nominal = sorted[sorted.len() / 20] / 2.0;
+    let mut cell = nominal;
+
+    for interval in intervals {
+        let cells = std::cmp::max((interval / cell).round() as usize, 1);
+        for _ in 1..cells {
+            stream.push(false);
+        }
+        stream.push(true);
+
+        // Only track the cell width over intervals that are valid for MFM, so that gaps in the flux don't pull
+        // the PLL off frequency.
+        if cells <= 4 {
+            let error = interval / cells as f64 - cell;
+            cell = (cell + error * PLL_GAIN).clamp(nominal * (1.0 - PLL_RANGE), nominal * (1.0 + PLL_RANGE));
+        }
+    }
+    stream
+}
+
+/// Combine the sectors decoded from each revolution of a track. The first revolution provides the layout of the
+/// track; a sector with a CRC error is replaced by a good copy from another revolution if there is one. If a sector
+/// can't be read correctly from any revolution, any bits that differ between revolutions are marked weak.
+fn merge_revolutions(mut tracks: Vec<FloppyTrack>) -> FloppyTrack {
+    let mut merged = tracks.remove(0);
+    let track_len = merged.recorded_len.unwrap_or(0);
+
+    for sector in merged.sectors.iter_mut() {
+        if sector.id_crc_error || sector.no_data || !sector.data_crc_error {
+            continue;
+        }
+        let position = sector.position.unwrap_or(0);
+
+        // Find the same sector on each other revolution, by ID and position on the track.
+        let copies: Vec<_> = tracks
+            .iter()
+            .filter_map(|track| {
+                track.sectors.iter().find(|other| {
+                    let distance = other.position.unwrap_or(0).abs_diff(position);
+                    other.id == sector.id
+                        && !other.no_data
+                        && std::cmp::min(distance, track_len.saturating_sub(distance)) < mfm::MAX_ID_TO_DATA_BYTES
+                })
+            })
+            .collect();
+
+        if let Some(good) = copies.iter().find(|copy| !copy.data_crc_error && !copy.id_crc_error) {
+            sector.data = good.data.clone();
+            sector.data_crc_error = false;
+            sector.weak = None;
+            continue;
+        }
+
+        let mut mask = sector.weak.take().unwrap_or_else(|| vec![0; sector.data.len()]);
+        for copy in &copies {
+            for ((m, a), b) in mask.iter_mut().zip(&sector.data).zip(&copy.data) {
+                *m |= a ^ b;
+            }
+        }
+        if mask.iter().any(|m| *m != 0) {
+            sector.weak = Some(mask);
+        }
+    }
+    merged
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Unexpected end of SCP image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Convert a bitstream to SCP flux counts at the given bit cell width.
+    fn bitstream_to_flux(stream: &Bitstream, cell_ns: f64) -> Vec<u8> {
+        let mut flux = Vec::new();
+        let mut cells = 0;
+        for i in 0..stream.len() {
+            cells += 1;
+            if stream.bit(i) {
+                let ticks = (cells as f64 * cell_ns / SCP_TICK_NS) as u16;
+                flux.extend_from_slice(&ticks.to_be_bytes());
+                cells = 0;
+            }
+        }
+        flux
+    }
+
+    #[test]
+    fn test_load_weak_sector() {
+        let mut track = FloppyTrack::standard(0, 0, 9, 0xF6);
+        let streams: Vec<Vec<u8>> = (0..2u8)
+            .map(|rev| {
+                // The fifth sector reads differently on each revolution, and fails its CRC on both
+                track.sectors[4].data[10] = rev;
+                track.sectors[4].data_crc_error = true;
+                // Vary the rotation speed slightly between revolutions
+                let cell_ns = 2000.0 * (1.0 + rev as f64 * 0.01);
+                bitstream_to_flux(&mfm::encode_track(&track, 6250, 84), cell_ns)
+            })
+            .collect();
+
+        let mut file = vec![0u8; SCP_HEADER_LEN + SCP_MAX_TRACKS * 4];
+        file[0..3].copy_from_slice(SCP_MAGIC);
+        file[5] = 2; // Revolutions
+        let offset = file.len();
+        file[SCP_HEADER_LEN..SCP_HEADER_LEN + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+
+        file.extend_from_slice(b"TRK\0");
+        let mut flux_offset = 4 + 12 * streams.len();
+        for flux in &streams {
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(&(flux.len() as u32 / 2).to_le_bytes());
+            file.extend_from_slice(&(flux_offset as u32).to_le_bytes());
+            flux_offset += flux.len();
+        }
+        for flux in &streams {
+            file.extend_from_slice(flux);
+        }
+
+        let image = load(&file).unwrap();
+        let loaded = image.track(0, 0).unwrap();
+        assert_eq!(loaded.sectors.len(), 9);
+        for (i, sector) in loaded.sectors.iter().enumerate() {
+            assert_eq!(sector.id.r as usize, i + 1);
+            assert_eq!(sector.data_crc_error, i == 4);
+        }
+        let mask = loaded.sectors[4].weak.as_ref().unwrap();
+        assert_eq!(mask[10], 0x01);
+        assert_eq!(mask.iter().filter(|m| **m != 0).count(), 1);
+    }
+}
@@ -0,0 +1,303 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::td0.rs
+
+    Loader for Teledisk (TD0) images.
+
+    A Teledisk image lists the sectors of each track in the order they were
+    read, with a flag byte per sector recording CRC errors, deleted address
+    marks and ID fields without data. Sector data may be stored raw, as a
+    repeated two byte pattern, or run length encoded. Images made with
+    advanced compression are additionally LZHUF compressed after the image
+    header.
+
+    Images created with Teledisk versions before 2.0 used a different
+    compression method and are only supported if they are uncompressed.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+use crate::floppy_image::{lzhuf, FloppyImage, FloppySector, FloppyTrack, SectorId};
+
+pub const TD0_MAGIC: &[u8; 2] = b"TD";
+pub const TD0_MAGIC_COMPRESSED: &[u8; 2] = b"td";
+pub const TD0_HEADER_LEN: usize = 12;
+pub const TD0_COMMENT_HEADER_LEN: usize = 10;
+// The first version to use LZHUF for advanced compression.
+pub const TD0_LZHUF_VERSION: u8 = 20;
+
+const STEPPING_COMMENT: u8 = 0x80;
+const END_OF_IMAGE: u8 = 0xFF;
+
+const SECTOR_CRC_ERROR: u8 = 0x02;
+const SECTOR_DELETED: u8 = 0x04;
+const SECTOR_SKIPPED: u8 = 0x10;
+const SECTOR_NO_DATA: u8 = 0x20;
+const SECTOR_NO_ID: u8 = 0x40;
+
+const ENCODING_RAW: u8 = 0;
+const ENCODING_PATTERN: u8 = 1;
+const ENCODING_RLE: u8 = 2;
+
+pub fn detect(data: &[u8]) -> bool {
+    data.len() >= TD0_HEADER_LEN && (&data[0..2] == TD0_MAGIC || &data[0..2] == TD0_MAGIC_COMPRESSED)
+}
+
+/// Load a Teledisk image.
+pub fn load(data: &[u8]) -> Result<FloppyImage, Error> {
+    if !detect(data) {
+        return Err(anyhow!("Not a Teledisk image"));
+    }
+    let version = data[4];
+    let stepping = data[7];
+
+    let body = if &data[0..2] == TD0_MAGIC_COMPRESSED {
+        if version < TD0_LZHUF_VERSION {
+            return Err(anyhow!(
+                "Compressed Teledisk images from version {}.{} are not supported",
+                version / 10,
+                version % 10
+            ));
+        }
+        lzhuf::decompress(&data[TD0_HEADER_LEN..])
+    }
+    else {
+        data[TD0_HEADER_LEN..].to_vec()
+    };
+
+    let mut pos = 0;
+    if stepping & STEPPING_COMMENT != 0 {
+        let header = body
+            .get(0..TD0_COMMENT_HEADER_LEN)
+            .ok_or_else(|| anyhow!("Teledisk comment is truncated"))?;
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        pos = TD0_COMMENT_HEADER_LEN + len;
+        if let Some(comment) = body.get(TD0_COMMENT_HEADER_LEN..pos) {
+            log::debug!(
+                "Teledisk comment: {}",
+                String::from_utf8_lossy(comment).trim_end_matches('\0')
+            );
+        }
+    }
+
+    let mut image = FloppyImage::new(0, 1);
+    loop {
+        let header = body
+            .get(pos..pos + 4)
+            .ok_or_else(|| anyhow!("Teledisk image is truncated"))?;
+        let (sector_ct, cylinder, head) = (header[0], header[1], header[2] & 0x01);
+        pos += 4;
+        if sector_ct == END_OF_IMAGE {
+            break;
+        }
+
+        let mut sectors = Vec::with_capacity(sector_ct as usize);
+        for _ in 0..sector_ct {
+            let header = body
+                .get(pos..pos + 6)
+                .ok_or_else(|| anyhow!("Teledisk image is truncated"))?;
+            let id = SectorId::new(header[0], header[1], header[2], header[3]);
+            let flags = header[4];
+            pos += 6;
+
+            let mut sector = FloppySector::new(id, Vec::new());
+            sector.data_crc_error = flags & SECTOR_CRC_ERROR != 0;
+            sector.deleted = flags & SECTOR_DELETED != 0;
+
+            // Sectors with an invalid size code have no data block
+            let has_data = flags & (SECTOR_SKIPPED | SECTOR_NO_DATA) == 0 && id.n < 7;
+            if has_data {
+                let len = read_u16(&body, pos)? as usize;
+                let block = body
+                    .get(pos + 2..pos + 2 + len)
+                    .ok_or_else(|| anyhow!("Teledisk sector data is truncated"))?;
+                pos += 2 + len;
+                sector.data = decode_sector(block, id.size())?;
+            }
+            else if flags & SECTOR_NO_DATA != 0 {
+                sector.no_data = true;
+            }
+            else {
+                // Teledisk didn't store sectors DOS had not allocated
+                sector.data = vec![0; id.size()];
+            }
+
+            if flags & SECTOR_NO_ID != 0 {
+                // A data field without an ID field can't be found by the controller
+                log::debug!("Teledisk sector {:?} has no ID field and was dropped", id);
+                continue;
+            }
+            sectors.push(sector);
+        }
+        image.set_track(cylinder, head, FloppyTrack::new(sectors));
+    }
+
+    if image.cylinders() == 0 {
+        return Err(anyhow!("Teledisk image contains no tracks"));
+    }
+    Ok(image)
+}
+
+/// Decode a sector data block: an encoding method byte followed by encoded data.
+fn decode_sector(block: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let (method, encoded) = block
+        .split_first()
+        .ok_or_else(|| anyhow!("Teledisk sector data block is empty"))?;
+    // Expanded data can't be allowed to grow past the sector size, as a small block can encode a huge run.
+    let overflow = || anyhow!("Teledisk sector data exceeds the sector size of {} bytes", size);
+    let mut data = Vec::with_capacity(size);
+    match *method {
+        ENCODING_RAW => {
+            if encoded.len() > size {
+                return Err(overflow());
+            }
+            data.extend_from_slice(encoded)
+        }
+        ENCODING_PATTERN => {
+            for pattern in encoded.chunks_exact(4) {
+                let count = u16::from_le_bytes([pattern[0], pattern[1]]);
+                if data.len() + count as usize * 2 > size {
+                    return Err(overflow());
+                }
+                for _ in 0..count {
+                    data.extend_from_slice(&pattern[2..4]);
+                }
+            }
+        }
+        ENCODING_RLE => {
+            let mut pos = 0;
+            while pos + 2 <= encoded.len() {
+                let (kind, count) = (encoded[pos] as usize, encoded[pos + 1] as usize);
+                pos += 2;
+                // A kind of 0 is a run of literal bytes; otherwise a pattern of 2 * kind bytes is repeated
+                let (len, repeat) = match kind {
+                    0 => (count, 1),
+                    _ => (kind * 2, count),
+                };
+                let run = encoded
+                    .get(pos..pos + len)
+                    .ok_or_else(|| anyhow!("Teledisk sector data is truncated"))?;
+                pos += len;
+                if data.len() + len * repeat > size {
+                    return Err(overflow());
+                }
+                for _ in 0..repeat {
+                    data.extend_from_slice(run);
+                }
+            }
+        }
+        _ => return Err(anyhow!("Unknown Teledisk sector encoding: {}", method)),
+    }
+    data.resize(size, 0);
+    Ok(data)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, Error> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Unexpected end of Teledisk image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sector(file: &mut Vec<u8>, id: [u8; 4], flags: u8, block: &[u8]) {
+        file.extend_from_slice(&id);
+        file.extend_from_slice(&[flags, 0]);
+        if !block.is_empty() {
+            file.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            file.extend_from_slice(block);
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let mut file = b"TD\0\0\x15\x02\x00\x80\x00\x02\x00\x00".to_vec();
+        file.extend_from_slice(&[0, 0, 5, 0, 24, 1, 1, 12, 0, 0]);
+        file.extend_from_slice(b"hello");
+
+        // Track 1, head 0: raw, pattern, RLE, bad and missing data sectors
+        file.extend_from_slice(&[5, 1, 0, 0]);
+        let mut raw = vec![ENCODING_RAW];
+        raw.extend((0..128).map(|i| i as u8));
+        sector(&mut file, [1, 0, 1, 0], 0, &raw);
+        sector(
+            &mut file,
+            [1, 0, 2, 2],
+            SECTOR_DELETED,
+            &[ENCODING_PATTERN, 0, 1, 0xF6, 0xE5],
+        );
+        sector(
+            &mut file,
+            [1, 0, 3, 0],
+            0,
+            &[ENCODING_RLE, 0, 3, 1, 2, 3, 2, 10, 0xAA, 0xBB, 0xCC, 0xDD],
+        );
+        sector(
+            &mut file,
+            [1, 0, 4, 0],
+            SECTOR_CRC_ERROR,
+            &[ENCODING_PATTERN, 64, 0, 0x12, 0x34],
+        );
+        sector(&mut file, [1, 0, 5, 0], SECTOR_NO_DATA, &[]);
+        file.extend_from_slice(&[END_OF_IMAGE, 0, 0, 0]);
+
+        let image = load(&file).unwrap();
+        assert_eq!(image.cylinders(), 2);
+        let track = image.track(1, 0).unwrap();
+        assert_eq!(track.sectors.len(), 5);
+        assert_eq!(track.sectors[0].data, (0..128).map(|i| i as u8).collect::<Vec<u8>>());
+
+        let pattern = &track.sectors[1];
+        assert!(pattern.deleted);
+        assert_eq!(pattern.data.len(), 512);
+        assert_eq!(&pattern.data[0..4], &[0xF6, 0xE5, 0xF6, 0xE5]);
+
+        let rle = &track.sectors[2].data;
+        assert_eq!(&rle[0..3], &[1, 2, 3]);
+        assert_eq!(&rle[3..7], &[0xAA, 0xBB, 0xCC, 0xDD]);
+        assert_eq!(&rle[39..45], &[0xAA, 0xBB, 0xCC, 0xDD, 0, 0]);
+
+        assert!(track.sectors[3].data_crc_error);
+        assert!(track.sectors[4].no_data);
+    }
+
+    #[test]
+    fn test_decode_overflow() {
+        let data = decode_sector(&[ENCODING_PATTERN, 64, 0, 0x12, 0x34], 128).unwrap();
+        assert_eq!(data.len(), 128);
+        assert!(decode_sector(&[ENCODING_PATTERN, 0xFF, 0xFF, 0x12, 0x34], 128).is_err());
+        assert!(decode_sector(&[ENCODING_RLE, 1, 0xFF, 0xAA, 0xBB], 128).is_err());
+        assert!(decode_sector(&[ENCODING_RLE, 0, 3, 1, 2, 3], 2).is_err());
+
+        let mut raw = vec![ENCODING_RAW];
+        raw.extend_from_slice(&[0; 129]);
+        assert!(decode_sector(&raw, 128).is_err());
+    }
+}
@@ -0,0 +1,760 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    fpu_8087::execute.rs
+
+    Decodes and executes ESC instructions on the 8087.
+
+    Cycle counts returned are typical execution times from the 8087 datasheet.
+
+*/
+
+use crate::fpu_8087::{float80::*, *};
+
+/// An ESC instruction forwarded from the CPU to the coprocessor.
+pub struct EscOperation {
+    /// The low three bits of the ESC opcode (0xD8-0xDF).
+    pub esc: u8,
+    /// The reg field of the modrm byte.
+    pub reg: u8,
+    /// The r/m field of the modrm byte for register forms (mod == 11), or None for memory forms.
+    pub rm:  Option<u8>,
+    /// Linear address of the ESC instruction, including prefixes.
+    pub ip:  u32,
+    /// Linear address of the memory operand, if any.
+    pub ea:  u32,
+}
+
+// Memory operand formats
+#[derive(Copy, Clone, Debug)]
+enum MemFormat {
+    Real32,
+    Real64,
+    Real80,
+    Int16,
+    Int32,
+    Int64,
+    Bcd,
+}
+
+fn read_bytes<const N: usize>(bus: &mut dyn FpuBus) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = bus.fpu_read_u8(i as u16);
+    }
+    bytes
+}
+
+fn write_bytes(bus: &mut dyn FpuBus, offset: u16, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+        bus.fpu_write_u8(offset.wrapping_add(i as u16), *byte);
+    }
+}
+
+fn read_u16_at(bus: &mut dyn FpuBus, offset: u16) -> u16 {
+    u16::from_le_bytes([bus.fpu_read_u8(offset), bus.fpu_read_u8(offset.wrapping_add(1))])
+}
+
+impl Fpu8087 {
+    /// Execute an ESC instruction. Returns the number of cycles the coprocessor will be busy.
+    pub fn execute(&mut self, op: &EscOperation, bus: &mut dyn FpuBus) -> u32 {
+        let (cycles, control) = match op.rm {
+            Some(rm) => self.execute_register(op.esc & 0x07, op.reg & 0x07, rm & 0x07),
+            None => self.execute_memory(op.esc & 0x07, op.reg & 0x07, bus),
+        };
+
+        // Control instructions do not update the instruction and operand pointers.
+        if !control {
+            self.ip = op.ip;
+            self.opcode = ((op.esc as u16 & 0x07) << 8) | ((op.reg as u16 & 0x07) << 3);
+            if let Some(rm) = op.rm {
+                self.opcode |= 0xC0 | rm as u16;
+            }
+            self.operand_ptr = op.ea;
+        }
+        cycles
+    }
+
+    fn load(&mut self, format: MemFormat, bus: &mut dyn FpuBus) -> f64 {
+        match format {
+            MemFormat::Real32 => f32::from_le_bytes(read_bytes::<4>(bus)) as f64,
+            MemFormat::Real64 => f64::from_le_bytes(read_bytes::<8>(bus)),
+            MemFormat::Real80 => f80_to_f64(&read_bytes::<10>(bus)),
+            MemFormat::Int16 => i16::from_le_bytes(read_bytes::<2>(bus)) as f64,
+            MemFormat::Int32 => i32::from_le_bytes(read_bytes::<4>(bus)) as f64,
+            MemFormat::Int64 => i64::from_le_bytes(read_bytes::<8>(bus)) as f64,
+            MemFormat::Bcd => bcd_to_f64(&read_bytes::<10>(bus)),
+        }
+    }
+
+    /// Store ST(0) to memory in the specified format. Returns false if the store was suppressed
+    /// by an unmasked exception.
+    fn store(&mut self, format: MemFormat, bus: &mut dyn FpuBus) -> bool {
+        let value = match self.read_st(0) {
+            Some(value) => value,
+            None => return false,
+        };
+        match format {
+            MemFormat::Real32 => {
+                let narrow = value as f32;
+                if narrow.is_infinite() && value.is_finite() && self.raise(FPU_STATUS_OE | FPU_STATUS_PE) {
+                    return false;
+                }
+                write_bytes(bus, 0, &narrow.to_le_bytes());
+            }
+            MemFormat::Real64 => write_bytes(bus, 0, &value.to_le_bytes()),
+            MemFormat::Real80 => write_bytes(bus, 0, &f64_to_f80(value)),
+            MemFormat::Int16 => match self.round_to_integer(value, 16) {
+                Some(int) => write_bytes(bus, 0, &(int as i16).to_le_bytes()),
+                None => return false,
+            },
+            MemFormat::Int32 => match self.round_to_integer(value, 32) {
+                Some(int) => write_bytes(bus, 0, &(int as i32).to_le_bytes()),
+                None => return false,
+            },
+            MemFormat::Int64 => match self.round_to_integer(value, 64) {
+                Some(int) => write_bytes(bus, 0, &int.to_le_bytes()),
+                None => return false,
+            },
+            MemFormat::Bcd => match f64_to_bcd(self.round(value)) {
+                Some(bcd) => write_bytes(bus, 0, &bcd),
+                None => {
+                    if self.raise(FPU_STATUS_IE) {
+                        return false;
+                    }
+                    write_bytes(bus, 0, &BCD_INDEFINITE);
+                }
+            },
+        }
+        true
+    }
+
+    /// Perform an arithmetic operation on ST(0) with a memory operand.
+    fn arith_memory(&mut self, reg: u8, format: MemFormat, bus: &mut dyn FpuBus) {
+        let src = self.load(format, bus);
+        if let Some(dst) = self.read_st(0) {
+            if let Some(result) = self.arith(reg, dst, src) {
+                self.write_st(0, result);
+            }
+        }
+        if reg == 3 {
+            self.pop();
+        }
+    }
+
+    /// Perform an arithmetic operation between ST(0) and ST(i). If `to_sti` is set, the result
+    /// is stored in ST(i) instead of ST(0).
+    fn arith_register(&mut self, reg: u8, i: u8, to_sti: bool) {
+        let (st0, sti) = match (self.read_st(0), self.read_st(i)) {
+            (Some(st0), Some(sti)) => (st0, sti),
+            _ => return,
+        };
+        if reg == 2 || reg == 3 {
+            self.compare(st0, sti);
+            return;
+        }
+        if to_sti {
+            if let Some(result) = self.arith(reg, sti, st0) {
+                self.write_st(i, result);
+            }
+        }
+        else if let Some(result) = self.arith(reg, st0, sti) {
+            self.write_st(0, result);
+        }
+    }
+
+    fn arith_cycles(reg: u8, base: u32) -> u32 {
+        match reg {
+            0 | 4 | 5 => base + 85,
+            1 => base + 130,
+            2 | 3 => base + 45,
+            _ => base + 198,
+        }
+    }
+
+    /// Execute a memory-form ESC instruction. Returns cycles and whether it was a control
+    /// instruction.
+    fn execute_memory(&mut self, esc: u8, reg: u8, bus: &mut dyn FpuBus) -> (u32, bool) {
+        match (esc, reg) {
+            (0, _) => {
+                self.arith_memory(reg, MemFormat::Real32, bus);
+                (Self::arith_cycles(reg, 20), false)
+            }
+            (2, _) => {
+                self.arith_memory(reg, MemFormat::Int32, bus);
+                (Self::arith_cycles(reg, 40), false)
+            }
+            (4, _) => {
+                self.arith_memory(reg, MemFormat::Real64, bus);
+                (Self::arith_cycles(reg, 25), false)
+            }
+            (6, _) => {
+                self.arith_memory(reg, MemFormat::Int16, bus);
+                (Self::arith_cycles(reg, 35), false)
+            }
+            (1, 0) | (5, 0) | (3, 5) | (3, 0) | (7, 0) | (7, 5) | (7, 4) => {
+                let (format, cycles) = match (esc, reg) {
+                    (1, _) => (MemFormat::Real32, 43),
+                    (5, _) => (MemFormat::Real64, 46),
+                    (3, 5) => (MemFormat::Real80, 57),
+                    (3, _) => (MemFormat::Int32, 56),
+                    (7, 0) => (MemFormat::Int16, 50),
+                    (7, 5) => (MemFormat::Int64, 64),
+                    _ => (MemFormat::Bcd, 300),
+                };
+                let value = self.load(format, bus);
+                self.push(value);
+                (cycles, false)
+            }
+            (1, 2) | (1, 3) | (5, 2) | (5, 3) | (3, 2) | (3, 3) | (7, 2) | (7, 3) => {
+                let (format, cycles) = match esc {
+                    1 => (MemFormat::Real32, 87),
+                    5 => (MemFormat::Real64, 100),
+                    3 => (MemFormat::Int32, 88),
+                    _ => (MemFormat::Int16, 86),
+                };
+                if self.store(format, bus) && reg == 3 {
+                    self.pop();
+                }
+                (cycles, false)
+            }
+            (3, 7) | (7, 6) | (7, 7) => {
+                let (format, cycles) = match (esc, reg) {
+                    (3, _) => (MemFormat::Real80, 55),
+                    (7, 6) => (MemFormat::Bcd, 530),
+                    _ => (MemFormat::Int64, 94),
+                };
+                if self.store(format, bus) {
+                    self.pop();
+                }
+                (cycles, false)
+            }
+            (1, 4) => {
+                // FLDENV
+                self.load_environment(bus);
+                (40, true)
+            }
+            (1, 5) => {
+                // FLDCW
+                self.control = read_u16_at(bus, 0);
+                self.update_interrupt_request();
+                (10, true)
+            }
+            (1, 6) => {
+                // FSTENV
+                self.store_environment(bus);
+                (45, true)
+            }
+            (1, 7) => {
+                // FSTCW
+                write_bytes(bus, 0, &self.control.to_le_bytes());
+                (15, true)
+            }
+            (5, 4) => {
+                // FRSTOR
+                self.load_environment(bus);
+                for i in 0..8u8 {
+                    let mut bytes = [0u8; 10];
+                    for (j, byte) in bytes.iter_mut().enumerate() {
+                        *byte = bus.fpu_read_u8(14 + (i as u16) * 10 + j as u16);
+                    }
+                    let phys = self.phys(i);
+                    self.regs[phys] = f80_to_f64(&bytes);
+                }
+                (205, true)
+            }
+            (5, 6) => {
+                // FSAVE
+                self.store_environment(bus);
+                for i in 0..8u8 {
+                    let phys = self.phys(i);
+                    write_bytes(bus, 14 + (i as u16) * 10, &f64_to_f80(self.regs[phys]));
+                }
+                self.reset();
+                (205, true)
+            }
+            (5, 7) => {
+                // FSTSW
+                write_bytes(bus, 0, &self.status_word().to_le_bytes());
+                (15, true)
+            }
+            _ => {
+                log::warn!("FPU: Reserved memory-form ESC instruction: {:02X} /{}", 0xD8 | esc, reg);
+                (0, true)
+            }
+        }
+    }
+
+    /// Execute a register-form ESC instruction. Returns cycles and whether it was a control
+    /// instruction.
+    fn execute_register(&mut self, esc: u8, reg: u8, i: u8) -> (u32, bool) {
+        match (esc, reg) {
+            (0, _) => {
+                self.arith_register(reg, i, false);
+                if reg == 3 {
+                    self.pop();
+                }
+                (Self::arith_cycles(reg, 0), false)
+            }
+            (4, _) | (6, _) => {
+                // The direction of SUB and DIV are reversed when the destination is ST(i).
+                let arith_reg = match reg {
+                    4 => 5,
+                    5 => 4,
+                    6 => 7,
+                    7 => 6,
+                    _ => reg,
+                };
+                if esc == 6 && reg == 3 {
+                    // FCOMPP. Other r/m values are reserved, but compare against ST(1) regardless.
+                    self.arith_register(3, 1, false);
+                    self.pop();
+                    self.pop();
+                    return (50, false);
+                }
+                self.arith_register(arith_reg, i, true);
+                if esc == 6 || reg == 3 {
+                    self.pop();
+                }
+                (Self::arith_cycles(reg, 5), false)
+            }
+            (1, 0) => {
+                // FLD ST(i)
+                if let Some(value) = self.read_st(i) {
+                    self.push(value);
+                }
+                (20, false)
+            }
+            (1, 1) | (5, 1) | (7, 1) => {
+                // FXCH ST(i)
+                if let (Some(st0), Some(sti)) = (self.read_st(0), self.read_st(i)) {
+                    self.write_st(0, sti);
+                    self.write_st(i, st0);
+                }
+                (12, false)
+            }
+            (1, 2) => {
+                // FNOP
+                (13, false)
+            }
+            (5, 2) | (5, 3) | (1, 3) | (7, 2) | (7, 3) => {
+                // FST / FSTP ST(i)
+                if let Some(value) = self.read_st(0) {
+                    self.write_st(i, value);
+                }
+                if reg == 3 || esc != 5 {
+                    self.pop();
+                }
+                (18, false)
+            }
+            (5, 0) | (7, 0) => {
+                // FFREE ST(i)
+                let phys = self.phys(i);
+                self.tags[phys] = FpuTag::Empty;
+                if esc == 7 {
+                    self.pop();
+                }
+                (11, false)
+            }
+            (1, 4) => {
+                self.execute_d9_e0(i);
+                let cycles = match i {
+                    0 => 15,
+                    1 => 14,
+                    4 => 42,
+                    _ => 17,
+                };
+                (cycles, false)
+            }
+            (1, 5) => {
+                // Load constant
+                let value = match i {
+                    0 => 1.0,
+                    1 => std::f64::consts::LOG2_10,
+                    2 => std::f64::consts::LOG2_E,
+                    3 => std::f64::consts::PI,
+                    4 => std::f64::consts::LOG10_2,
+                    5 => std::f64::consts::LN_2,
+                    6 => 0.0,
+                    _ => {
+                        log::warn!("FPU: Reserved instruction D9 {:02X}", 0xE8 | i);
+                        return (0, true);
+                    }
+                };
+                self.push(value);
+                (if i == 6 { 14 } else { 20 }, false)
+            }
+            (1, 6) => (self.execute_d9_f0(i), false),
+            (1, 7) => (self.execute_d9_f8(i), false),
+            (3, 4) => {
+                match i {
+                    0 => self.control &= !FPU_CONTROL_IEM, // FENI
+                    1 => self.control |= FPU_CONTROL_IEM,  // FDISI
+                    2 => self.status &= !(FPU_STATUS_EXCEPTIONS | FPU_STATUS_IR | FPU_STATUS_BUSY), // FCLEX
+                    3 => self.reset(),                     // FINIT
+                    _ => log::warn!("FPU: Reserved instruction DB {:02X}", 0xE0 | i),
+                }
+                (5, true)
+            }
+            _ => {
+                log::warn!(
+                    "FPU: Reserved instruction {:02X} {:02X}",
+                    0xD8 | esc,
+                    0xC0 | (reg << 3) | i
+                );
+                (0, true)
+            }
+        }
+    }
+
+    /// FCHS, FABS, FTST, FXAM
+    fn execute_d9_e0(&mut self, i: u8) {
+        match i {
+            0 | 1 => {
+                if let Some(value) = self.read_st(0) {
+                    self.write_st(0, if i == 0 { -value } else { value.abs() });
+                }
+            }
+            4 => {
+                if let Some(value) = self.read_st(0) {
+                    self.compare(value, 0.0);
+                }
+            }
+            5 => {
+                let negative = self.regs[self.phys(0)].is_sign_negative();
+                match self.st(0) {
+                    None => self.set_cc(true, false, negative, true),
+                    Some(v) if v.is_nan() => self.set_cc(false, false, negative, true),
+                    Some(v) if v.is_infinite() => self.set_cc(false, true, negative, true),
+                    Some(v) if v == 0.0 => self.set_cc(true, false, negative, false),
+                    Some(v) if !v.is_normal() => self.set_cc(true, true, negative, false),
+                    Some(_) => self.set_cc(false, true, negative, false),
+                }
+            }
+            _ => log::warn!("FPU: Reserved instruction D9 {:02X}", 0xE0 | i),
+        }
+    }
+
+    /// F2XM1, FYL2X, FPTAN, FPATAN, FXTRACT, FDECSTP, FINCSTP
+    fn execute_d9_f0(&mut self, i: u8) -> u32 {
+        match i {
+            0 => {
+                // F2XM1
+                if let Some(x) = self.read_st(0) {
+                    self.write_st(0, (x * std::f64::consts::LN_2).exp_m1());
+                }
+                500
+            }
+            1 | 3 => {
+                // FYL2X, FPATAN
+                if let (Some(x), Some(y)) = (self.read_st(0), self.read_st(1)) {
+                    let result = if i == 1 {
+                        if x < 0.0 || x.is_nan() {
+                            self.invalid()
+                        }
+                        else if x == 0.0 && self.raise(FPU_STATUS_ZE) {
+                            None
+                        }
+                        else {
+                            Some(y * x.log2())
+                        }
+                    }
+                    else {
+                        Some(y.atan2(x))
+                    };
+                    if let Some(result) = result {
+                        self.write_st(1, result);
+                        self.pop();
+                    }
+                }
+                if i == 1 {
+                    950
+                }
+                else {
+                    650
+                }
+            }
+            2 => {
+                // FPTAN. ST(1) / ST(0) is the tangent.
+                if let Some(x) = self.read_st(0) {
+                    if x.is_finite() {
+                        self.write_st(0, x.tan());
+                        self.push(1.0);
+                    }
+                    else if let Some(nan) = self.invalid() {
+                        self.write_st(0, nan);
+                    }
+                }
+                450
+            }
+            4 => {
+                // FXTRACT
+                if let Some(x) = self.read_st(0) {
+                    if x == 0.0 {
+                        if !self.raise(FPU_STATUS_ZE) {
+                            self.write_st(0, f64::NEG_INFINITY);
+                            self.push(x);
+                        }
+                    }
+                    else if x.is_finite() {
+                        let exponent = x.abs().log2().floor();
+                        let significand = ldexp(x, -(exponent as i32));
+                        self.write_st(0, exponent);
+                        self.push(significand);
+                    }
+                    else if let Some(nan) = self.invalid() {
+                        self.write_st(0, nan);
+                    }
+                }
+                50
+            }
+            6 => {
+                // FDECSTP
+                self.top = self.top.wrapping_sub(1) & 0x07;
+                9
+            }
+            7 => {
+                // FINCSTP
+                self.top = self.top.wrapping_add(1) & 0x07;
+                9
+            }
+            _ => {
+                log::warn!("FPU: Reserved instruction D9 {:02X}", 0xF0 | i);
+                0
+            }
+        }
+    }
+
+    /// FPREM, FYL2XP1, FSQRT, FRNDINT, FSCALE
+    fn execute_d9_f8(&mut self, i: u8) -> u32 {
+        match i {
+            0 => {
+                // FPREM. The remainder is computed fully in a single step, so C2 is always clear.
+                if let (Some(a), Some(b)) = (self.read_st(0), self.read_st(1)) {
+                    if b == 0.0 || a.is_infinite() || a.is_nan() || b.is_nan() {
+                        if let Some(nan) = self.invalid() {
+                            self.write_st(0, nan);
+                        }
+                    }
+                    else {
+                        let r = a % b;
+                        let q = ((a - r) / b).abs() as u64;
+                        self.set_cc(q & 0x02 != 0, false, q & 0x01 != 0, q & 0x04 != 0);
+                        self.write_st(0, r);
+                    }
+                }
+                125
+            }
+            1 => {
+                // FYL2XP1
+                if let (Some(x), Some(y)) = (self.read_st(0), self.read_st(1)) {
+                    self.write_st(1, y * x.ln_1p() / std::f64::consts::LN_2);
+                    self.pop();
+                }
+                850
+            }
+            2 => {
+                // FSQRT
+                if let Some(x) = self.read_st(0) {
+                    if x < 0.0 || x.is_nan() {
+                        if let Some(nan) = self.invalid() {
+                            self.write_st(0, nan);
+                        }
+                    }
+                    else {
+                        self.write_st(0, x.sqrt());
+                    }
+                }
+                183
+            }
+            4 => {
+                // FRNDINT
+                if let Some(x) = self.read_st(0) {
+                    let rounded = self.round(x);
+                    if rounded != x && x.is_finite() {
+                        self.raise(FPU_STATUS_PE);
+                    }
+                    self.write_st(0, rounded);
+                }
+                45
+            }
+            5 => {
+                // FSCALE
+                if let (Some(x), Some(scale)) = (self.read_st(0), self.read_st(1)) {
+                    let n = scale.trunc().clamp(-65536.0, 65536.0) as i32;
+                    let result = ldexp(x, n);
+                    if !self.check_result(result, x, 0.0) {
+                        self.write_st(0, result);
+                    }
+                }
+                35
+            }
+            _ => {
+                log::warn!("FPU: Reserved instruction D9 {:02X}", 0xF8 | i);
+                0
+            }
+        }
+    }
+
+    /// Recalculate the IR bit after a change to the control word's exception masks.
+    fn update_interrupt_request(&mut self) {
+        if self.status & !self.control & FPU_STATUS_EXCEPTIONS != 0 {
+            self.status |= FPU_STATUS_IR;
+        }
+        else {
+            self.status &= !FPU_STATUS_IR;
+        }
+    }
+
+    /// Store the 14-byte real mode environment: control, status and tag words followed by the
+    /// instruction and operand pointers.
+    fn store_environment(&mut self, bus: &mut dyn FpuBus) {
+        let words = [
+            self.control,
+            self.status_word(),
+            self.tag_word(),
+            self.ip as u16,
+            (((self.ip >> 16) as u16 & 0x0F) << 12) | (self.opcode & 0x07FF),
+            self.operand_ptr as u16,
+            ((self.operand_ptr >> 16) as u16 & 0x0F) << 12,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            write_bytes(bus, (i * 2) as u16, &word.to_le_bytes());
+        }
+    }
+
+    fn load_environment(&mut self, bus: &mut dyn FpuBus) {
+        let mut words = [0u16; 7];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = read_u16_at(bus, (i * 2) as u16);
+        }
+        self.control = words[0];
+        self.status = words[1] & !FPU_STATUS_TOP_MASK;
+        self.top = ((words[1] & FPU_STATUS_TOP_MASK) >> 11) as u8;
+        for (i, tag) in self.tags.iter_mut().enumerate() {
+            *tag = FpuTag::from_bits(words[2] >> (i * 2));
+        }
+        self.ip = words[3] as u32 | (((words[4] >> 12) as u32) << 16);
+        self.opcode = words[4] & 0x07FF;
+        self.operand_ptr = words[5] as u32 | (((words[6] >> 12) as u32) << 16);
+        self.update_interrupt_request();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBus(Vec<u8>);
+
+    impl FpuBus for TestBus {
+        fn fpu_read_u8(&mut self, offset: u16) -> u8 {
+            self.0[offset as usize]
+        }
+        fn fpu_write_u8(&mut self, offset: u16, data: u8) {
+            self.0[offset as usize] = data;
+        }
+    }
+
+    fn esc(fpu: &mut Fpu8087, bus: &mut TestBus, opcode: u8, modrm: u8) {
+        let op = EscOperation {
+            esc: opcode & 0x07,
+            reg: (modrm >> 3) & 0x07,
+            rm:  if modrm >= 0xC0 { Some(modrm & 0x07) } else { None },
+            ip:  0,
+            ea:  0,
+        };
+        fpu.execute(&op, bus);
+    }
+
+    #[test]
+    fn test_fpu_arithmetic() {
+        let mut fpu = Fpu8087::new();
+        let mut bus = TestBus(vec![0; 128]);
+
+        bus.0[0..2].copy_from_slice(&7i16.to_le_bytes());
+        esc(&mut fpu, &mut bus, 0xDF, 0x00); // FILD WORD [0]
+        esc(&mut fpu, &mut bus, 0xD9, 0xE8); // FLD1
+        esc(&mut fpu, &mut bus, 0xDE, 0xC1); // FADDP ST(1), ST
+        assert_eq!(fpu.st(0), Some(8.0));
+        assert_eq!(fpu.st(1), None);
+
+        esc(&mut fpu, &mut bus, 0xD9, 0xFA); // FSQRT
+        esc(&mut fpu, &mut bus, 0xD9, 0xC0); // FLD ST(0)
+        esc(&mut fpu, &mut bus, 0xDE, 0xC9); // FMULP ST(1), ST
+        esc(&mut fpu, &mut bus, 0xDF, 0x18); // FISTP WORD [0]
+        assert_eq!(i16::from_le_bytes([bus.0[0], bus.0[1]]), 8);
+        assert_eq!(fpu.tag_word(), 0xFFFF);
+
+        // Reversed subtraction when the destination is ST(i)
+        esc(&mut fpu, &mut bus, 0xD9, 0xE8); // FLD1
+        esc(&mut fpu, &mut bus, 0xD9, 0xEB); // FLDPI
+        esc(&mut fpu, &mut bus, 0xDE, 0xE9); // FSUBP ST(1), ST
+        assert_eq!(fpu.st(0), Some(1.0 - std::f64::consts::PI));
+    }
+
+    #[test]
+    fn test_fpu_exceptions() {
+        let mut fpu = Fpu8087::new();
+        let mut bus = TestBus(vec![0; 128]);
+
+        // Masked zero divide produces infinity.
+        esc(&mut fpu, &mut bus, 0xD9, 0xE8); // FLD1
+        esc(&mut fpu, &mut bus, 0xD9, 0xEE); // FLDZ
+        esc(&mut fpu, &mut bus, 0xDE, 0xF9); // FDIVP ST(1), ST
+        assert_eq!(fpu.st(0), Some(f64::INFINITY));
+        assert_ne!(fpu.status_word() & FPU_STATUS_ZE, 0);
+        assert!(!fpu.interrupt_request());
+
+        // Unmask zero divide with interrupts enabled, and repeat.
+        bus.0[0..2].copy_from_slice(&(FPU_CONTROL_DEFAULT & !(FPU_STATUS_ZE | FPU_CONTROL_IEM)).to_le_bytes());
+        esc(&mut fpu, &mut bus, 0xD9, 0x28); // FLDCW [0]
+        assert!(fpu.interrupt_request());
+        esc(&mut fpu, &mut bus, 0xDB, 0xE2); // FCLEX
+        assert!(!fpu.interrupt_request());
+
+        // Stack underflow is an invalid operation.
+        esc(&mut fpu, &mut bus, 0xDB, 0xE3); // FINIT
+        esc(&mut fpu, &mut bus, 0xD9, 0xE1); // FABS
+        assert_ne!(fpu.status_word() & FPU_STATUS_IE, 0);
+    }
+
+    #[test]
+    fn test_fpu_save_restore() {
+        let mut fpu = Fpu8087::new();
+        let mut bus = TestBus(vec![0; 128]);
+
+        esc(&mut fpu, &mut bus, 0xD9, 0xEB); // FLDPI
+        esc(&mut fpu, &mut bus, 0xD9, 0xE9); // FLDL2T
+        esc(&mut fpu, &mut bus, 0xDD, 0x30); // FSAVE [0]
+        assert_eq!(fpu.st(0), None);
+
+        esc(&mut fpu, &mut bus, 0xDD, 0x20); // FRSTOR [0]
+        assert_eq!(fpu.st(0), Some(std::f64::consts::LOG2_10));
+        assert_eq!(fpu.st(1), Some(std::f64::consts::PI));
+        assert_eq!(fpu.status_word() & FPU_STATUS_TOP_MASK, 6 << 11);
+    }
+}
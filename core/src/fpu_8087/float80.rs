@@ -0,0 +1,204 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    fpu_8087::float80.rs
+
+    Conversions between host f64 values and the 8087's memory formats:
+    80-bit temporary real and 18-digit packed BCD.
+
+*/
+
+const F80_EXP_BIAS: i32 = 16383;
+const F80_EXP_MAX: u16 = 0x7FFF;
+const F80_INTEGER_BIT: u64 = 0x8000_0000_0000_0000;
+
+/// The packed BCD 'indefinite' value stored by FBSTP on an invalid operation.
+pub const BCD_INDEFINITE: [u8; 10] = [0, 0, 0, 0, 0, 0, 0, 0xC0, 0xFF, 0xFF];
+
+/// Multiply x by 2^n without overflowing the intermediate scale factor.
+pub fn ldexp(mut x: f64, mut n: i32) -> f64 {
+    while n > 1000 {
+        x *= 2f64.powi(1000);
+        n -= 1000;
+    }
+    while n < -1000 {
+        x *= 2f64.powi(-1000);
+        n += 1000;
+    }
+    x * 2f64.powi(n)
+}
+
+/// Convert an f64 to an 80-bit temporary real, little-endian.
+pub fn f64_to_f80(value: f64) -> [u8; 10] {
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u16;
+    let exp = ((bits >> 52) & 0x7FF) as i32;
+    let frac = bits & 0x000F_FFFF_FFFF_FFFF;
+
+    let (exp80, mant80): (u16, u64) = if exp == 0 {
+        if frac == 0 {
+            (0, 0)
+        }
+        else {
+            // f64 denormal. Normalize, as all f64 denormals are representable as normals in 80 bits.
+            let lz = frac.leading_zeros() as i32;
+            let top_bit = 63 - lz;
+            ((top_bit - 1074 + F80_EXP_BIAS) as u16, frac << lz)
+        }
+    }
+    else if exp == 0x7FF {
+        if frac == 0 {
+            (F80_EXP_MAX, F80_INTEGER_BIT)
+        }
+        else {
+            (F80_EXP_MAX, F80_INTEGER_BIT | 0x4000_0000_0000_0000 | (frac << 11))
+        }
+    }
+    else {
+        ((exp - 1023 + F80_EXP_BIAS) as u16, F80_INTEGER_BIT | (frac << 11))
+    };
+
+    let mut out = [0u8; 10];
+    out[0..8].copy_from_slice(&mant80.to_le_bytes());
+    out[8..10].copy_from_slice(&((sign << 15) | exp80).to_le_bytes());
+    out
+}
+
+/// Convert an 80-bit temporary real, little-endian, to an f64. Precision beyond 53 bits is
+/// rounded away and exponents outside the f64 range overflow to infinity or flush towards zero.
+pub fn f80_to_f64(bytes: &[u8; 10]) -> f64 {
+    let mant = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let se = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let negative = se & 0x8000 != 0;
+    let exp = se & F80_EXP_MAX;
+
+    let magnitude = if exp == F80_EXP_MAX {
+        if mant << 1 == 0 {
+            f64::INFINITY
+        }
+        else {
+            f64::NAN
+        }
+    }
+    else if mant == 0 {
+        0.0
+    }
+    else {
+        // Denormals use an exponent of 1 with no implicit integer bit. Unnormals are handled
+        // the same way, as the integer bit is explicit.
+        let unbiased = if exp == 0 {
+            1 - F80_EXP_BIAS
+        }
+        else {
+            exp as i32 - F80_EXP_BIAS
+        };
+        ldexp(mant as f64, unbiased - 63)
+    };
+
+    if negative {
+        -magnitude
+    }
+    else {
+        magnitude
+    }
+}
+
+/// Convert an 18-digit packed BCD value to an f64. Invalid digits are converted as-is, as the
+/// 8087 does not validate BCD operands.
+pub fn bcd_to_f64(bytes: &[u8; 10]) -> f64 {
+    let mut value: u64 = 0;
+    for byte in bytes[0..9].iter().rev() {
+        value = value * 100 + ((byte >> 4) as u64) * 10 + (byte & 0x0F) as u64;
+    }
+    if bytes[9] & 0x80 != 0 {
+        -(value as f64)
+    }
+    else {
+        value as f64
+    }
+}
+
+/// Convert an integral f64 to an 18-digit packed BCD value, or None if it is out of range.
+pub fn f64_to_bcd(value: f64) -> Option<[u8; 10]> {
+    if !value.is_finite() || value.abs() >= 1e18 {
+        return None;
+    }
+    let mut int = value.abs() as u64;
+    let mut out = [0u8; 10];
+    for byte in out[0..9].iter_mut() {
+        let lo = (int % 10) as u8;
+        int /= 10;
+        let hi = (int % 10) as u8;
+        int /= 10;
+        *byte = (hi << 4) | lo;
+    }
+    if value.is_sign_negative() {
+        out[9] = 0x80;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f80_round_trip() {
+        for value in [
+            0.0,
+            -0.0,
+            1.0,
+            -2.5,
+            3.141592653589793,
+            1e300,
+            -1e-300,
+            5e-324,
+            f64::INFINITY,
+        ] {
+            let f80 = f64_to_f80(value);
+            let back = f80_to_f64(&f80);
+            assert_eq!(back.to_bits(), value.to_bits(), "value: {}", value);
+        }
+        assert!(f80_to_f64(&f64_to_f80(f64::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_f80_encoding() {
+        // 1.0 is stored with the explicit integer bit set and a biased exponent of 0x3FFF.
+        assert_eq!(f64_to_f80(1.0), [0, 0, 0, 0, 0, 0, 0, 0x80, 0xFF, 0x3F]);
+        // Pi as stored by FLDPI/FSTP TBYTE, rounded to f64 precision.
+        let pi80 = [0x35, 0xC2, 0x68, 0x21, 0xA2, 0xDA, 0x0F, 0xC9, 0x00, 0x40];
+        assert_eq!(f80_to_f64(&pi80), std::f64::consts::PI);
+    }
+
+    #[test]
+    fn test_bcd() {
+        let bcd = f64_to_bcd(-1234567890.0).unwrap();
+        assert_eq!(bcd, [0x90, 0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0, 0x80]);
+        assert_eq!(bcd_to_f64(&bcd), -1234567890.0);
+        assert!(f64_to_bcd(1e18).is_none());
+    }
+}
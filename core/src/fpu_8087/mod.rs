@@ -0,0 +1,493 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    fpu_8087::mod.rs
+
+    Implements an 8087 numeric coprocessor.
+
+    This emulation is not cycle-accurate. Each ESC instruction executes immediately
+    when the CPU decodes it, and the coprocessor then reports itself busy via
+    the TEST pin for approximately the number of cycles the real chip would
+    take, so that WAIT and FWAIT behave as expected.
+
+    Register values are held as host f64 values, so operations are performed
+    with 53 bits of precision instead of 64. 80-bit operands are rounded on
+    load.
+
+*/
+
+pub mod execute;
+pub mod float80;
+
+use crate::{
+    bus::BusInterface,
+    savestate::{SaveState, SaveStateError, StateReader, StateWriter},
+};
+
+// Status word bits
+pub const FPU_STATUS_IE: u16 = 0x0001; // Invalid operation
+pub const FPU_STATUS_DE: u16 = 0x0002; // Denormalized operand
+pub const FPU_STATUS_ZE: u16 = 0x0004; // Zero divide
+pub const FPU_STATUS_OE: u16 = 0x0008; // Overflow
+pub const FPU_STATUS_UE: u16 = 0x0010; // Underflow
+pub const FPU_STATUS_PE: u16 = 0x0020; // Precision
+pub const FPU_STATUS_IR: u16 = 0x0080; // Interrupt request
+pub const FPU_STATUS_C0: u16 = 0x0100;
+pub const FPU_STATUS_C1: u16 = 0x0200;
+pub const FPU_STATUS_C2: u16 = 0x0400;
+pub const FPU_STATUS_TOP_MASK: u16 = 0x3800;
+pub const FPU_STATUS_C3: u16 = 0x4000;
+pub const FPU_STATUS_BUSY: u16 = 0x8000;
+
+pub const FPU_STATUS_EXCEPTIONS: u16 = 0x003F;
+pub const FPU_STATUS_CC_MASK: u16 = FPU_STATUS_C0 | FPU_STATUS_C1 | FPU_STATUS_C2 | FPU_STATUS_C3;
+
+// Control word bits
+pub const FPU_CONTROL_IEM: u16 = 0x0080; // Interrupt enable mask
+pub const FPU_CONTROL_RC_MASK: u16 = 0x0C00;
+pub const FPU_CONTROL_DEFAULT: u16 = 0x03FF;
+
+/// The 'indefinite' quiet NaN stored as the masked response to an invalid operation.
+pub const FPU_INDEFINITE: f64 = -f64::NAN;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum FpuTag {
+    Valid,
+    Zero,
+    Special,
+    #[default]
+    Empty,
+}
+
+impl FpuTag {
+    fn from_bits(bits: u16) -> Self {
+        match bits & 0x03 {
+            0 => FpuTag::Valid,
+            1 => FpuTag::Zero,
+            2 => FpuTag::Special,
+            _ => FpuTag::Empty,
+        }
+    }
+
+    fn to_bits(self) -> u16 {
+        match self {
+            FpuTag::Valid => 0,
+            FpuTag::Zero => 1,
+            FpuTag::Special => 2,
+            FpuTag::Empty => 3,
+        }
+    }
+
+    fn for_value(value: f64) -> Self {
+        if value == 0.0 {
+            FpuTag::Zero
+        }
+        else if value.is_finite() && value.is_normal() {
+            FpuTag::Valid
+        }
+        else {
+            FpuTag::Special
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum RoundingMode {
+    Nearest,
+    Down,
+    Up,
+    Truncate,
+}
+
+/// Memory interface for coprocessor operand transfers. Offsets are relative to the effective
+/// address of the ESC instruction's memory operand.
+pub trait FpuBus {
+    fn fpu_read_u8(&mut self, offset: u16) -> u8;
+    fn fpu_write_u8(&mut self, offset: u16, data: u8);
+}
+
+/// An ESC instruction's memory operand in system memory. The 8087 accesses its operands via its
+/// own bus cycles, so these accesses are not timed on the CPU.
+pub struct MemoryOperand<'a> {
+    pub bus: &'a mut BusInterface,
+    pub segment: u16,
+    pub offset: u16,
+}
+
+impl MemoryOperand<'_> {
+    fn address(&self, offset: u16) -> usize {
+        ((((self.segment as u32) << 4) + self.offset.wrapping_add(offset) as u32) & 0xFFFFF) as usize
+    }
+}
+
+impl FpuBus for MemoryOperand<'_> {
+    fn fpu_read_u8(&mut self, offset: u16) -> u8 {
+        let address = self.address(offset);
+        self.bus.read_u8(address, 0).map(|(byte, _)| byte).unwrap_or(0xFF)
+    }
+
+    fn fpu_write_u8(&mut self, offset: u16, data: u8) {
+        let address = self.address(offset);
+        _ = self.bus.write_u8(address, data, 0);
+    }
+}
+
+#[derive(Clone)]
+pub struct Fpu8087 {
+    regs: [f64; 8],
+    tags: [FpuTag; 8],
+    top: u8,
+    control: u16,
+    status: u16,
+    ip: u32,
+    opcode: u16,
+    operand_ptr: u32,
+}
+
+impl Default for Fpu8087 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fpu8087 {
+    pub fn new() -> Self {
+        Self {
+            regs: [0.0; 8],
+            tags: [FpuTag::Empty; 8],
+            top: 0,
+            control: FPU_CONTROL_DEFAULT,
+            status: 0,
+            ip: 0,
+            opcode: 0,
+            operand_ptr: 0,
+        }
+    }
+
+    /// Reset the coprocessor, as by FINIT or the RESET line.
+    pub fn reset(&mut self) {
+        self.tags = [FpuTag::Empty; 8];
+        self.top = 0;
+        self.control = FPU_CONTROL_DEFAULT;
+        self.status = 0;
+        self.ip = 0;
+        self.opcode = 0;
+        self.operand_ptr = 0;
+    }
+
+    /// Return the state of the 8087's INT output. On the PC this is routed to NMI.
+    pub fn interrupt_request(&self) -> bool {
+        (self.status & FPU_STATUS_IR != 0) && (self.control & FPU_CONTROL_IEM == 0)
+    }
+
+    pub fn status_word(&self) -> u16 {
+        (self.status & !FPU_STATUS_TOP_MASK) | ((self.top as u16) << 11)
+    }
+
+    pub fn control_word(&self) -> u16 {
+        self.control
+    }
+
+    pub fn tag_word(&self) -> u16 {
+        self.tags
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, tag)| acc | (tag.to_bits() << (i * 2)))
+    }
+
+    /// Return the value of ST(i), or None if the register is empty.
+    pub fn st(&self, i: u8) -> Option<f64> {
+        let phys = self.phys(i);
+        match self.tags[phys] {
+            FpuTag::Empty => None,
+            _ => Some(self.regs[phys]),
+        }
+    }
+
+    fn phys(&self, i: u8) -> usize {
+        (self.top.wrapping_add(i) & 0x07) as usize
+    }
+
+    fn rounding_mode(&self) -> RoundingMode {
+        match (self.control & FPU_CONTROL_RC_MASK) >> 10 {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::Down,
+            2 => RoundingMode::Up,
+            _ => RoundingMode::Truncate,
+        }
+    }
+
+    fn round(&self, value: f64) -> f64 {
+        match self.rounding_mode() {
+            RoundingMode::Nearest => {
+                // Round half to even
+                let r = value.round();
+                if (value - value.trunc()).abs() == 0.5 {
+                    2.0 * (value / 2.0).round()
+                }
+                else {
+                    r
+                }
+            }
+            RoundingMode::Down => value.floor(),
+            RoundingMode::Up => value.ceil(),
+            RoundingMode::Truncate => value.trunc(),
+        }
+    }
+
+    /// Signal the specified exceptions. Returns true if any of them are unmasked, in which case
+    /// the instruction should not store its result.
+    fn raise(&mut self, exceptions: u16) -> bool {
+        self.status |= exceptions;
+        let unmasked = exceptions & !self.control & FPU_STATUS_EXCEPTIONS;
+        if unmasked != 0 {
+            self.status |= FPU_STATUS_IR;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn set_cc(&mut self, c3: bool, c2: bool, c1: bool, c0: bool) {
+        self.status &= !FPU_STATUS_CC_MASK;
+        if c3 {
+            self.status |= FPU_STATUS_C3;
+        }
+        if c2 {
+            self.status |= FPU_STATUS_C2;
+        }
+        if c1 {
+            self.status |= FPU_STATUS_C1;
+        }
+        if c0 {
+            self.status |= FPU_STATUS_C0;
+        }
+    }
+
+    /// Read ST(i) as a source operand. An empty register is a stack underflow, which signals an
+    /// invalid operation and reads as indefinite.
+    fn read_st(&mut self, i: u8) -> Option<f64> {
+        match self.st(i) {
+            Some(value) => Some(value),
+            None => {
+                if self.raise(FPU_STATUS_IE) {
+                    None
+                }
+                else {
+                    Some(FPU_INDEFINITE)
+                }
+            }
+        }
+    }
+
+    fn write_st(&mut self, i: u8, value: f64) {
+        let phys = self.phys(i);
+        self.regs[phys] = value;
+        self.tags[phys] = FpuTag::for_value(value);
+    }
+
+    /// Push a value onto the register stack. Pushing onto a full stack is a stack overflow, which
+    /// signals an invalid operation and pushes indefinite if masked.
+    fn push(&mut self, value: f64) {
+        let new_top = self.top.wrapping_sub(1) & 0x07;
+        if self.tags[new_top as usize] != FpuTag::Empty {
+            if self.raise(FPU_STATUS_IE) {
+                return;
+            }
+            self.top = new_top;
+            self.write_st(0, FPU_INDEFINITE);
+            return;
+        }
+        self.top = new_top;
+        self.write_st(0, value);
+    }
+
+    fn pop(&mut self) {
+        let phys = self.phys(0);
+        self.tags[phys] = FpuTag::Empty;
+        self.top = self.top.wrapping_add(1) & 0x07;
+    }
+
+    /// Check a computed result for overflow and underflow.
+    fn check_result(&mut self, result: f64, a: f64, b: f64) -> bool {
+        let mut exceptions = 0;
+        if result.is_infinite() && a.is_finite() && b.is_finite() {
+            exceptions |= FPU_STATUS_OE | FPU_STATUS_PE;
+        }
+        else if result != 0.0 && !result.is_normal() && result.is_finite() {
+            exceptions |= FPU_STATUS_UE;
+        }
+        if exceptions != 0 {
+            self.raise(exceptions)
+        }
+        else {
+            false
+        }
+    }
+
+    /// Perform one of the eight basic arithmetic operations selected by the reg field of the
+    /// modrm byte: ADD, MUL, COM, COMP, SUB, SUBR, DIV, DIVR. `dst` is the destination operand
+    /// and `src` the source. Returns the result, or None if no result should be stored.
+    fn arith(&mut self, op: u8, dst: f64, src: f64) -> Option<f64> {
+        if op == 2 || op == 3 {
+            self.compare(dst, src);
+            return None;
+        }
+
+        if dst.is_nan() || src.is_nan() {
+            if self.raise(FPU_STATUS_IE) {
+                return None;
+            }
+            return Some(if dst.is_nan() { dst } else { src });
+        }
+
+        let (a, b) = match op {
+            5 | 7 => (src, dst),
+            _ => (dst, src),
+        };
+
+        let result = match op {
+            0 => {
+                if a.is_infinite() && b.is_infinite() && a.signum() != b.signum() {
+                    return self.invalid();
+                }
+                a + b
+            }
+            1 => {
+                if (a == 0.0 && b.is_infinite()) || (a.is_infinite() && b == 0.0) {
+                    return self.invalid();
+                }
+                a * b
+            }
+            4 | 5 => {
+                if a.is_infinite() && b.is_infinite() && a.signum() == b.signum() {
+                    return self.invalid();
+                }
+                a - b
+            }
+            _ => {
+                if (a == 0.0 && b == 0.0) || (a.is_infinite() && b.is_infinite()) {
+                    return self.invalid();
+                }
+                if b == 0.0 {
+                    // Masked zero divide produces a correctly signed infinity, without overflow.
+                    if self.raise(FPU_STATUS_ZE) {
+                        return None;
+                    }
+                    return Some(a / b);
+                }
+                a / b
+            }
+        };
+
+        if self.check_result(result, a, b) {
+            return None;
+        }
+        Some(result)
+    }
+
+    fn invalid(&mut self) -> Option<f64> {
+        if self.raise(FPU_STATUS_IE) {
+            None
+        }
+        else {
+            Some(FPU_INDEFINITE)
+        }
+    }
+
+    /// Compare two values and set the condition codes. Unordered operands signal an invalid
+    /// operation.
+    fn compare(&mut self, a: f64, b: f64) {
+        if a.is_nan() || b.is_nan() {
+            self.raise(FPU_STATUS_IE);
+            self.set_cc(true, true, false, true);
+        }
+        else if a > b {
+            self.set_cc(false, false, false, false);
+        }
+        else if a < b {
+            self.set_cc(false, false, false, true);
+        }
+        else {
+            self.set_cc(true, false, false, false);
+        }
+    }
+
+    /// Convert a value to an integer of the specified bit width for storage, applying the current
+    /// rounding mode. Out of range values signal an invalid operation and produce the integer
+    /// indefinite value if masked.
+    fn round_to_integer(&mut self, value: f64, bits: u32) -> Option<i64> {
+        let min = -(2f64.powi(bits as i32 - 1));
+        let max = 2f64.powi(bits as i32 - 1) - 1.0;
+        let rounded = self.round(value);
+        if value.is_nan() || rounded < min || rounded > max {
+            if self.raise(FPU_STATUS_IE) {
+                return None;
+            }
+            return Some(i64::MIN >> (64 - bits));
+        }
+        if rounded != value {
+            self.raise(FPU_STATUS_PE);
+        }
+        Some(rounded as i64)
+    }
+}
+
+impl SaveState for Fpu8087 {
+    fn save_state(&self, w: &mut StateWriter) {
+        for reg in self.regs {
+            w.write_f64(reg);
+        }
+        w.write_u16(self.tag_word());
+        w.write_u8(self.top);
+        w.write_u16(self.control);
+        w.write_u16(self.status);
+        w.write_u32(self.ip);
+        w.write_u16(self.opcode);
+        w.write_u32(self.operand_ptr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        for reg in self.regs.iter_mut() {
+            *reg = r.read_f64()?;
+        }
+        let tag_word = r.read_u16()?;
+        for (i, tag) in self.tags.iter_mut().enumerate() {
+            *tag = FpuTag::from_bits(tag_word >> (i * 2));
+        }
+        self.top = r.read_u8()?;
+        if self.top > 7 {
+            return Err(SaveStateError::InvalidValue("FPU stack top"));
+        }
+        self.control = r.read_u16()?;
+        self.status = r.read_u16()?;
+        self.ip = r.read_u32()?;
+        self.opcode = r.read_u16()?;
+        self.operand_ptr = r.read_u32()?;
+        Ok(())
+    }
+}
@@ -33,7 +33,134 @@
 
 use log;
 
-use crate::{bus::BusInterface, cpu_808x::CpuRegisterState};
+use crate::{bus::BusInterface, cpu_808x::CpuRegisterState, cpu_common::CpuState};
+
+/// Longest string to read from guest memory when decoding interrupt parameters.
+const MAX_TRACE_STRING: usize = 128;
+
+/// Read a string from guest memory at seg:offset, stopping at `terminator` or after MAX_TRACE_STRING bytes.
+fn read_guest_string(bus: &BusInterface, seg: u16, offset: u16, terminator: u8) -> String {
+    let mut string = String::new();
+    for i in 0..MAX_TRACE_STRING {
+        let addr = (((seg as usize) << 4) + offset.wrapping_add(i as u16) as usize) & 0xFFFFF;
+        match bus.peek_u8(addr) {
+            Ok(byte) if byte != terminator => string.push(byte as char),
+            _ => break,
+        }
+    }
+    string
+}
+
+/// Produce a description of a software interrupt call from the register state at the time of the call,
+/// decoding the function number and parameters of common BIOS and DOS services.
+pub fn describe_interrupt(int: u8, regs: &CpuState, bus: &BusInterface) -> String {
+    let ah = (regs.ax >> 8) as u8;
+    let al = (regs.ax & 0xFF) as u8;
+    let asciiz = |seg: u16, offset: u16| read_guest_string(bus, seg, offset, 0);
+
+    let desc = match (int, ah) {
+        (0x10, 0x00) => format!("Set video mode: {:02X}", al),
+        (0x10, 0x01) => format!("Set cursor shape: CX:{:04X}", regs.cx),
+        (0x10, 0x02) => format!(
+            "Set cursor position: page:{:02X} row:{:02X} col:{:02X}",
+            regs.bx >> 8,
+            regs.dx >> 8,
+            regs.dx & 0xFF
+        ),
+        (0x10, 0x03) => format!("Get cursor position: page:{:02X}", regs.bx >> 8),
+        (0x10, 0x06) => format!("Scroll up: lines:{:02X}", al),
+        (0x10, 0x07) => format!("Scroll down: lines:{:02X}", al),
+        (0x10, 0x09) => format!(
+            "Write char and attribute: '{}' attr:{:02X} ct:{}",
+            al as char,
+            regs.bx & 0xFF,
+            regs.cx
+        ),
+        (0x10, 0x0E) => format!("Teletype output: '{}'", al as char),
+        (0x10, 0x0F) => "Get video mode".to_string(),
+        (0x13, 0x00) => format!("Reset disk system: drive:{:02X}", regs.dx & 0xFF),
+        (0x13, 0x02 | 0x03) => format!(
+            "{} sectors: count:{} drive:{:02X} c:{} h:{} s:{} buf:{:04X}:{:04X}",
+            if ah == 0x02 { "Read" } else { "Write" },
+            al,
+            regs.dx & 0xFF,
+            (regs.cx >> 8) | ((regs.cx & 0xC0) << 2),
+            regs.dx >> 8,
+            regs.cx & 0x3F,
+            regs.es,
+            regs.bx
+        ),
+        (0x13, 0x08) => format!("Get drive parameters: drive:{:02X}", regs.dx & 0xFF),
+        (0x16, 0x00) => "Read keystroke".to_string(),
+        (0x16, 0x01) => "Check keystroke".to_string(),
+        (0x16, 0x02) => "Get shift flags".to_string(),
+        (0x1A, 0x00) => "Get system time".to_string(),
+        (0x21, 0x02) => format!("Write character: '{}'", (regs.dx & 0xFF) as u8 as char),
+        (0x21, 0x09) => format!("Write string: \"{}\"", read_guest_string(bus, regs.ds, regs.dx, b'$')),
+        (0x21, 0x0E) => format!("Select drive: {}", b'A'.wrapping_add((regs.dx & 0xFF) as u8) as char),
+        (0x21, 0x19) => "Get current drive".to_string(),
+        (0x21, 0x25) => format!("Set interrupt vector: {:02X} -> {:04X}:{:04X}", al, regs.ds, regs.dx),
+        (0x21, 0x2A) => "Get date".to_string(),
+        (0x21, 0x2C) => "Get time".to_string(),
+        (0x21, 0x30) => "Get DOS version".to_string(),
+        (0x21, 0x35) => format!("Get interrupt vector: {:02X}", al),
+        (0x21, 0x39) => format!("Create directory: \"{}\"", asciiz(regs.ds, regs.dx)),
+        (0x21, 0x3A) => format!("Remove directory: \"{}\"", asciiz(regs.ds, regs.dx)),
+        (0x21, 0x3B) => format!("Change directory: \"{}\"", asciiz(regs.ds, regs.dx)),
+        (0x21, 0x3C) => format!("Create file: \"{}\" attr:{:04X}", asciiz(regs.ds, regs.dx), regs.cx),
+        (0x21, 0x3D) => format!("Open file: \"{}\" mode:{:02X}", asciiz(regs.ds, regs.dx), al),
+        (0x21, 0x3E) => format!("Close file: handle:{}", regs.bx),
+        (0x21, 0x3F) => format!(
+            "Read file: handle:{} len:{} buf:{:04X}:{:04X}",
+            regs.bx, regs.cx, regs.ds, regs.dx
+        ),
+        (0x21, 0x40) => format!(
+            "Write file: handle:{} len:{} buf:{:04X}:{:04X}",
+            regs.bx, regs.cx, regs.ds, regs.dx
+        ),
+        (0x21, 0x41) => format!("Delete file: \"{}\"", asciiz(regs.ds, regs.dx)),
+        (0x21, 0x42) => format!(
+            "Seek: handle:{} origin:{} offset:{:08X}",
+            regs.bx,
+            al,
+            ((regs.cx as u32) << 16) | regs.dx as u32
+        ),
+        (0x21, 0x43) => format!(
+            "Get/set file attributes: \"{}\" AL:{:02X}",
+            asciiz(regs.ds, regs.dx),
+            al
+        ),
+        (0x21, 0x44) => format!("IOCTL: function:{:02X} handle:{}", al, regs.bx),
+        (0x21, 0x47) => format!("Get current directory: drive:{}", regs.dx & 0xFF),
+        (0x21, 0x48) => format!("Allocate memory: paragraphs:{:04X}", regs.bx),
+        (0x21, 0x49) => format!("Free memory: segment:{:04X}", regs.es),
+        (0x21, 0x4A) => format!(
+            "Resize memory block: segment:{:04X} paragraphs:{:04X}",
+            regs.es, regs.bx
+        ),
+        (0x21, 0x4B) => format!(
+            "Load and execute program: \"{}\" AL:{:02X}",
+            asciiz(regs.ds, regs.dx),
+            al
+        ),
+        (0x21, 0x4C) => format!("Terminate with return code: {:02X}", al),
+        (0x21, 0x4E) => format!("Find first file: \"{}\" attr:{:04X}", asciiz(regs.ds, regs.dx), regs.cx),
+        (0x21, 0x4F) => "Find next file".to_string(),
+        (0x21, 0x56) => format!(
+            "Rename file: \"{}\" -> \"{}\"",
+            asciiz(regs.ds, regs.dx),
+            asciiz(regs.es, regs.di)
+        ),
+        _ => String::new(),
+    };
+
+    if desc.is_empty() {
+        format!("INT {:02X}h AH={:02X}h", int, ah)
+    }
+    else {
+        format!("INT {:02X}h AH={:02X}h {}", int, ah, desc)
+    }
+}
 
 /// Function to log interrupt return values - called on return from interrupt (IRET)
 pub fn log_post_interrupt(int: u8, ah: u8, regs: &CpuRegisterState, bus: &mut BusInterface) {
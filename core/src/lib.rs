@@ -38,6 +38,7 @@ pub mod breakpoints;
 pub mod bus;
 pub mod bytebuf;
 pub mod bytequeue;
+pub mod cd_image;
 pub mod coreconfig;
 pub mod cpu_808x;
 pub mod cpu_common;
@@ -45,18 +46,28 @@ pub mod cpu_vx0;
 pub mod device_traits;
 pub mod device_types;
 pub mod devices;
+pub mod disk_info;
 pub mod file_util;
+pub mod floppy_image;
+pub mod fpu_8087;
 pub mod interrupt;
 pub mod keys;
 pub mod machine;
 pub mod machine_config;
 pub mod memerror;
+pub mod network;
+pub mod replay;
+pub mod rewind;
+pub mod savestate;
 pub mod sound;
+pub mod sound_mixer;
 pub mod syntax_token;
+pub mod tape_image;
 pub mod tracelogger;
 pub mod updatable;
 pub mod util;
 pub mod vhd;
+pub mod wav;
 
 pub mod cpu_validator; // CpuValidator trait
 
@@ -42,7 +42,7 @@ use std::{
     collections::{HashMap, VecDeque},
     fs::File,
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use std::collections::BTreeMap;
 
@@ -51,23 +51,32 @@ use crate::{
     bus::{BusInterface, ClockFactor, DeviceEvent, MEM_CP_BIT},
     coreconfig::CoreConfig,
     cpu_808x::{Intel808x},
-    cpu_common::{Cpu, CpuOption, CpuError, CpuType, TraceMode},
+    cpu_common::{Cpu, CpuOption, CpuError, CpuState, CpuType, TraceMode},
     device_traits::videocard::{VideoCard, VideoCardId, VideoCardInterface, VideoCardState, VideoOption},
+    device_types::{drive_status::DriveStatus, hdc::HardDiskFormat},
     devices::{
+        atapi_cdrom::AtapiCdrom,
+        cassette::Cassette,
         dma::DMAControllerStringState,
         fdc::FloppyController,
         hdc::HardDiskController,
-        keyboard::KeyboardModifiers,
+        inport_mouse::InportMouse,
+        keyboard::{Keyboard, KeyboardModifiers},
         mouse::Mouse,
         pic::PicStringState,
         pit::{self, PitDisplayState},
         ppi::PpiStringState,
+        xtide::XtIdeController,
     },
     keys::MartyKey,
     machine_config::{get_machine_descriptor, MachineConfiguration, MachineDescriptor},
-    machine_types::MachineType,
-    sound::{SoundPlayer, BUFFER_MS, VOLUME_ADJUST},
+    machine_types::{BootDevice, MachineType},
+    replay::{state_hash, FloppyMedia, InputEvent, Playback, Recording, Replay, ReplayStatus, TimedInput},
+    savestate::{SaveStateError, StateReader, StateWriter},
+    sound::{SoundPlayer, SoundPlayerStats, SpeakerFilter, BUFFER_MS},
+    sound_mixer::{SoundMixer, SoundSource},
     tracelogger::TraceLogger,
+    vhd::VirtualHardDisk,
 };
 
 use ringbuf::{Consumer, Producer, RingBuffer};
@@ -84,6 +93,11 @@ pub const STEP_OVER_TIMEOUT: u32 = 320000;
 
 pub const MAX_MEMORY_ADDRESS: usize = 0xFFFFF;
 
+pub const BOOT_SECTOR_ADDRESS: u32 = 0x7C00; // The BIOS loads the boot sector here and jumps to it
+
+pub const DEFAULT_PASTE_DELAY_MS: f64 = 20.0; // Default delay between pasted key events
+pub const PASTE_LINE_DELAY_FACTOR: f64 = 10.0; // Additional paste delays to wait after each Enter
+
 pub struct DisassemblyListingEntry {
     pub visit_count: u32,
     pub disassembly: Disassembly,
@@ -102,6 +116,11 @@ pub enum MachineEvent {
     CheckpointHit(usize, u32),
     Halted,
     Reset,
+    /// Playback of a recording has finished. The value is true if the machine ended in the same
+    /// state as it did when the recording was made.
+    ReplayFinished(bool),
+    /// The guest wrote the specified exit code to the exit port.
+    ExitRequested(u8),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -256,6 +275,10 @@ pub struct PitData {
     logging_triggered: bool,
     fractional_part: f64,
     next_sample_size: usize,
+    sample_rate: u32,
+    capture: Option<Vec<f32>>,
+    filter: SpeakerFilter,
+    source: SoundSource,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -455,6 +478,7 @@ pub struct Machine {
     state: MachineState,
     options: MachineOptions,
     sound_player: Option<SoundPlayer>,
+    sound_mixer: SoundMixer,
     rom_manifest: MachineRomManifest,
     load_bios: bool,
     cpu: CpuDispatch,
@@ -462,6 +486,9 @@ pub struct Machine {
     pit_data: PitData,
     debug_snd_file: Option<File>,
     kb_buf: VecDeque<KeybufferEntry>,
+    paste_buf: VecDeque<KeybufferEntry>,
+    paste_delay: f64, // Delay between pasted key events, in microseconds
+    paste_us_accum: f64,
     error: bool,
     error_str: Option<String>,
     turbo_bit: bool,
@@ -476,9 +503,12 @@ pub struct Machine {
     events: Vec<MachineEvent>,
     reload_pending: bool,
     halt_behavior: OnHaltBehavior,
+    boot_device: BootDevice,
+    boot_pending: bool,
     disassembly: Disassembly,
     disassembly_listing: BTreeMap<CpuAddress, DisassemblyListingEntry>,
     disassembly_listing_file: Option<PathBuf>,
+    replay: Option<Replay>,
 }
 
 impl Machine {
@@ -536,6 +566,7 @@ impl Machine {
         //       description / configuration structs.
         let resolved_cpu_type 
             = machine_config.cpu.as_ref().and_then(|cpu| cpu.upgrade_type).unwrap_or(machine_desc.cpu_type);
+        let have_fpu = machine_config.cpu.as_ref().is_some_and(|cpu| cpu.fpu);
         
         // Build the CPU
         let mut cpu;
@@ -546,6 +577,7 @@ impl Machine {
                 .with_cpu_type(resolved_cpu_type)
                 .with_trace_mode(trace_mode)
                 .with_trace_logger(trace_logger)
+                .with_fpu(have_fpu)
                 .with_validator_type(core_config.get_validator_type().unwrap_or_default())
                 .with_validator_mode(ValidatorMode::Cycle)
                 .with_validator_logger(validator_trace)
@@ -564,6 +596,7 @@ impl Machine {
                 .with_cpu_type(resolved_cpu_type)
                 .with_trace_mode(trace_mode)
                 .with_trace_logger(trace_logger)
+                .with_fpu(have_fpu)
                 .build() {
                 Ok(cpu) => cpu,
                 Err(e) => {
@@ -589,6 +622,11 @@ impl Machine {
         }
         let pit_ticks_per_sample = (pit::PIT_MHZ * 1_000_000.0) / sample_rate as f64;
 
+        // The PC speaker is the first mixer source. It is decimated to the output rate by the PIT
+        // sampling below.
+        let mut sound_mixer = SoundMixer::new(sample_rate as f64);
+        let speaker_source = sound_mixer.add_source("PC Speaker", sample_rate as f64);
+
         let pit_data = PitData {
             buffer_consumer: speaker_buf_consumer,
             ticks_per_sample: pit_ticks_per_sample,
@@ -597,6 +635,10 @@ impl Machine {
             logging_triggered: false,
             fractional_part: pit_ticks_per_sample.fract(),
             next_sample_size: pit_ticks_per_sample.trunc() as usize,
+            sample_rate,
+            capture: None,
+            filter: SpeakerFilter::new(pit::PIT_MHZ * 1_000_000.0, sample_rate as f64),
+            source: speaker_source,
         };
 
         // open a file to write the sound to
@@ -625,6 +667,22 @@ impl Machine {
         {
             log::error!("Failed to install devices: {}", err);
         }
+        cpu.bus_mut().set_log_unmapped(core_config.get_log_unmapped());
+        cpu.bus_mut().set_exit_port(core_config.get_exit_port());
+
+        // Connect any installed sound devices to the mixer.
+        cpu.bus_mut().register_sound_sources(&mut sound_mixer);
+
+        // Floppy drive sounds are an effect rather than part of the machine, so are muted unless enabled.
+        if let Some(fdc) = cpu.bus_mut().fdc_mut() {
+            if let Some(id) = fdc.sound_mut().source_id() {
+                sound_mixer.set_source_volume(
+                    id,
+                    core_config.get_drive_sound_volume().unwrap_or(0.5),
+                    !core_config.get_drive_sounds(),
+                );
+            }
+        }
 
         // Load keyboard translation file if specified.
         if let Some(kb_translation_path) = keyboard_layout_file {
@@ -681,13 +739,14 @@ impl Machine {
             patch_map = rom_manifest.patch_map();
         }
 
-        Machine {
+        let mut machine = Machine {
             machine_type,
             machine_desc,
             machine_config,
             options: MachineOptions::default(),
             state: MachineState::On,
             sound_player,
+            sound_mixer,
             rom_manifest,
             load_bios: !core_config.get_machine_noroms(),
             cpu,
@@ -695,6 +754,9 @@ impl Machine {
             pit_data,
             debug_snd_file: None,
             kb_buf: VecDeque::new(),
+            paste_buf: VecDeque::new(),
+            paste_delay: DEFAULT_PASTE_DELAY_MS * 1000.0,
+            paste_us_accum: 0.0,
             error: false,
             error_str: None,
             turbo_bit: false,
@@ -709,10 +771,15 @@ impl Machine {
             events: Vec::new(),
             reload_pending: false,
             halt_behavior: core_config.get_halt_behavior(),
+            boot_device: core_config.get_boot_device(),
+            boot_pending: false,
             disassembly: Disassembly::default(),
             disassembly_listing: BTreeMap::new(),
-            disassembly_listing_file
-        }
+            disassembly_listing_file,
+            replay: None,
+        };
+        machine.start_boot();
+        machine
     }
 
     pub fn set_option(&mut self, opt: MachineOption) {
@@ -878,6 +945,10 @@ impl Machine {
         &self.cpu
     }
 
+    pub fn cpu_mut(&mut self) -> &mut CpuDispatch {
+        &mut self.cpu
+    }
+
     pub fn config(&self) -> &MachineConfiguration {
         &self.machine_config
     }
@@ -926,6 +997,10 @@ impl Machine {
     /// We must be careful not to update this between step() and run_devices() or devices'
     /// advance_ticks may overflow device update ticks.
     pub fn set_turbo_mode(&mut self, state: bool) {
+        self.host_input(InputEvent::TurboButton(state));
+    }
+
+    fn apply_turbo_mode(&mut self, state: bool) {
         self.turbo_button = state;
         if state {
             self.next_cpu_factor = self.machine_desc.cpu_turbo_factor;
@@ -940,6 +1015,10 @@ impl Machine {
         );
     }
 
+    pub fn turbo_mode(&self) -> bool {
+        self.turbo_button
+    }
+
     pub fn fdc(&mut self) -> &mut Option<FloppyController> {
         self.cpu.bus_mut().fdc_mut()
     }
@@ -948,7 +1027,65 @@ impl Machine {
         self.cpu.bus_mut().hdc_mut()
     }
 
+    pub fn xtide(&mut self) -> &mut Option<XtIdeController> {
+        self.cpu.bus_mut().xtide_mut()
+    }
+
+    /// Mount a VHD into the specified drive of the installed hard disk controller, whichever type it is.
+    pub fn set_vhd(&mut self, drive: usize, vhd: VirtualHardDisk) -> Result<(), Error> {
+        if let Some(hdc) = self.hdc() {
+            return hdc.set_vhd(drive, vhd).map_err(|e| anyhow!(e));
+        }
+        if let Some(xtide) = self.xtide() {
+            return xtide.set_vhd(drive, vhd).map_err(|e| anyhow!(e));
+        }
+        Err(anyhow!("No Hard Disk Controller present!"))
+    }
+
+    /// Return the VHD mounted in the specified drive of the installed hard disk controller, if any.
+    pub fn vhd_mut(&mut self, drive: usize) -> Option<&mut VirtualHardDisk> {
+        let bus = self.cpu.bus_mut();
+        if bus.hdc_mut().is_some() {
+            return bus.hdc_mut().as_mut().and_then(|hdc| hdc.vhd_mut(drive));
+        }
+        bus.xtide_mut().as_mut().and_then(|xtide| xtide.vhd_mut(drive))
+    }
+
+    /// Return the status of each floppy drive for activity indicators. Read and write activity is cleared.
+    pub fn floppy_drive_status(&mut self) -> Vec<DriveStatus> {
+        match self.fdc() {
+            Some(fdc) => (0..fdc.drive_ct()).map(|drive| fdc.take_drive_status(drive)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the status of each hard disk for activity indicators, or None for drives with no image mounted.
+    /// Read and write activity is cleared.
+    pub fn hdd_status(&mut self) -> Vec<Option<DriveStatus>> {
+        (0..self.cpu.bus().hdd_ct())
+            .map(|drive| self.vhd_mut(drive).map(|vhd| vhd.take_status()))
+            .collect()
+    }
+
+    /// Return the drive geometries supported by the installed hard disk controller, if any.
+    pub fn hdd_formats(&mut self) -> Option<Vec<HardDiskFormat>> {
+        if let Some(hdc) = self.hdc() {
+            return Some(hdc.get_supported_formats());
+        }
+        self.xtide().as_ref().map(|xtide| xtide.get_supported_formats())
+    }
+
     pub fn cart_slot(&mut self) -> &mut Option<CartridgeSlot> { self.cpu.bus_mut().cart_slot_mut() }
+
+    /// Return the cassette interface, if the machine has one.
+    pub fn cassette(&mut self) -> Option<&mut Cassette> {
+        self.cpu.bus_mut().ppi_mut().as_mut().and_then(|ppi| ppi.cassette_mut())
+    }
+
+    /// Return the ATAPI CD-ROM drive, if one is attached to the hard disk controller.
+    pub fn cdrom(&mut self) -> Option<&mut AtapiCdrom> {
+        self.xtide().as_mut().and_then(|xtide| xtide.cdrom_mut())
+    }
     
     pub fn cpu_cycles(&self) -> u64 {
         self.cpu_cycles
@@ -1039,6 +1176,9 @@ impl Machine {
 
     /// Enter a keypress keycode into the emulator keyboard buffer.
     pub fn key_press(&mut self, keycode: MartyKey, modifiers: KeyboardModifiers) {
+        if self.is_replaying() {
+            return;
+        }
         self.kb_buf.push_back(KeybufferEntry {
             keycode,
             pressed: true,
@@ -1049,6 +1189,9 @@ impl Machine {
 
     /// Enter a key release keycode into the emulator keyboard buffer.
     pub fn key_release(&mut self, keycode: MartyKey) {
+        if self.is_replaying() {
+            return;
+        }
         // HO Bit set converts a scancode into its 'release' code
         self.kb_buf.push_back(KeybufferEntry {
             keycode,
@@ -1058,9 +1201,46 @@ impl Machine {
         });
     }
 
-    #[rustfmt::skip]
-    /// Simulate the user pressing control-alt-delete.
+    /// Type the specified text into the machine. The text is converted to key events which are
+    /// delivered one at a time, separated by the paste delay.
+    pub fn paste_text(&mut self, text: &str) {
+        if self.is_replaying() {
+            return;
+        }
+        let keycodes = Keyboard::keycodes_from_text(text);
+        log::debug!("Pasting {} characters as {} key events", text.len(), keycodes.len());
+        self.paste_buf.extend(keycodes);
+    }
+
+    /// Queue a sequence of key events, such as a keyboard macro. The events are delivered at the
+    /// paste rate, after any text that is already being pasted.
+    pub fn play_keys(&mut self, keys: &[KeybufferEntry]) {
+        if self.is_replaying() {
+            return;
+        }
+        self.paste_buf.extend(keys.iter().copied());
+    }
+
+    /// Set the delay between pasted key events, in milliseconds.
+    pub fn set_paste_delay(&mut self, ms: f64) {
+        self.paste_delay = ms.max(1.0) * 1000.0;
+    }
+
+    /// Discard any pasted text that has not been typed yet.
+    pub fn cancel_paste(&mut self) {
+        self.paste_buf.clear();
+        self.paste_us_accum = 0.0;
+    }
+
+    /// Simulate the user pressing control-alt-delete. The BIOS boots again from the selected boot device.
     pub fn emit_ctrl_alt_del(&mut self) {
+        self.host_input(InputEvent::CtrlAltDel);
+    }
+
+    #[rustfmt::skip]
+    fn ctrl_alt_del(&mut self) {
+        self.start_boot();
+
         let reboot_keycodes = [
             MartyKey::ControlLeft,
             MartyKey::AltLeft,
@@ -1088,10 +1268,88 @@ impl Machine {
         }
     }
 
+    /// Send a mouse update to the serial and bus mice, if present.
+    pub fn mouse_update(&mut self, l_button: bool, r_button: bool, delta_x: f64, delta_y: f64) {
+        self.host_input(InputEvent::Mouse {
+            l_button,
+            r_button,
+            delta_x,
+            delta_y,
+        });
+    }
+
+    /// Set the state of a button on the specified game port controller.
+    pub fn joystick_button(&mut self, controller: usize, button: usize, pressed: bool) {
+        self.host_input(InputEvent::JoystickButton {
+            controller,
+            button,
+            pressed,
+        });
+    }
+
+    /// Set the position of a stick on the specified game port controller. An axis given as None
+    /// is left where it is.
+    pub fn joystick_position(&mut self, controller: usize, stick: usize, x: Option<f64>, y: Option<f64>) {
+        self.host_input(InputEvent::JoystickPosition {
+            controller,
+            stick,
+            x,
+            y,
+        });
+    }
+
+    /// Load a disk image in any supported format into the specified floppy drive.
+    pub fn load_floppy(&mut self, drive_select: usize, data: Vec<u8>, write_protect: bool) -> Result<(), Error> {
+        if self.is_replaying() {
+            return Err(anyhow!("Disks can't be changed while a recording is playing."));
+        }
+        let recording = matches!(self.replay, Some(Replay::Recording(_)));
+        let record_data = if recording { Some(data.clone()) } else { None };
+
+        self.cpu
+            .bus_mut()
+            .fdc_mut()
+            .as_mut()
+            .ok_or(anyhow!("Machine has no floppy controller"))?
+            .load_image_from(drive_select, data, write_protect)?;
+
+        if let Some(data) = record_data {
+            self.record_input(InputEvent::LoadFloppy(FloppyMedia {
+                drive: drive_select,
+                data,
+                write_protect,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Eject the disk in the specified floppy drive.
+    pub fn eject_floppy(&mut self, drive_select: usize) {
+        self.host_input(InputEvent::EjectFloppy(drive_select));
+    }
+
     pub fn mouse_mut(&mut self) -> &mut Option<Mouse> {
         self.cpu.bus_mut().mouse_mut()
     }
 
+    pub fn bus_mouse_mut(&mut self) -> &mut Option<InportMouse> {
+        self.cpu.bus_mut().bus_mouse_mut()
+    }
+
+    /// Load any floppy drive sound files present in the specified directory, replacing the default sounds.
+    pub fn load_drive_sounds(&mut self, path: &Path) {
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            fdc.sound_mut().load_samples(path);
+        }
+    }
+
+    /// Set the directory that print jobs from a virtual printer are written to.
+    pub fn set_printer_output_dir(&mut self, path: PathBuf) {
+        if let Some(printer) = self.cpu.bus_mut().printer_mut() {
+            printer.set_output_dir(path);
+        }
+    }
+
     pub fn bridge_serial_port(&mut self, port_num: usize, host_port_name: String, host_port_id: usize) -> Result<(), Error> {
         if let Some(spc) = self.cpu.bus_mut().serial_mut() {
             if let Err(e) = spc.bridge_port(port_num, host_port_name, host_port_id) {
@@ -1115,6 +1373,18 @@ impl Machine {
     }
 
     pub fn reset(&mut self) {
+        match self.replay {
+            Some(Replay::Playing(_)) => {
+                log::info!("Machine was reset, stopping playback.");
+                self.replay = None;
+            }
+            Some(Replay::Recording(_)) => self.record_input(InputEvent::Reset),
+            None => {}
+        }
+        self.reset_machine();
+    }
+
+    fn reset_machine(&mut self) {
         // TODO: Reload any program specified here?
 
         // Clear any error state.
@@ -1135,11 +1405,419 @@ impl Machine {
             //self.rom_manager.reset_patches();
         }
 
+        // Discard any pending pasted text.
+        self.cancel_paste();
+
         // Reset all installed devices.
         self.cpu.bus_mut().reset_devices();
+        self.start_boot();
         self.events.push(MachineEvent::Reset);
     }
 
+    /// Set the drive to boot from. This takes effect on the next reboot.
+    pub fn set_boot_device(&mut self, device: BootDevice) {
+        log::debug!("Boot device set to: {:?}", device);
+        self.boot_device = device;
+    }
+
+    pub fn boot_device(&self) -> BootDevice {
+        self.boot_device
+    }
+
+    /// Arrange the floppy drives so that the BIOS boots from the selected boot device.
+    fn start_boot(&mut self) {
+        let mut device = self.boot_device;
+        let bus = self.cpu.bus_mut();
+        if device == BootDevice::HardDisk && bus.hdc_mut().is_none() && bus.xtide_mut().is_none() {
+            log::warn!("Boot device is the hard disk, but no hard disk controller is installed. Booting from A:");
+            device = BootDevice::FloppyA;
+        }
+        self.boot_pending = device == BootDevice::HardDisk;
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            fdc.set_drives_swapped(device == BootDevice::FloppyB);
+            fdc.set_disks_hidden(device == BootDevice::HardDisk);
+        }
+        if self.boot_pending {
+            // Watch for the boot sector being executed, at which point the floppy disks reappear.
+            self.cpu.bus_mut().set_flags(BOOT_SECTOR_ADDRESS as usize, MEM_CP_BIT);
+        }
+    }
+
+    /// The boot sector has started executing, so the boot is complete.
+    fn finish_boot(&mut self) {
+        log::debug!("Boot sector reached, floppy disks are no longer hidden.");
+        self.boot_pending = false;
+        self.cpu.bus_mut().clear_flags(BOOT_SECTOR_ADDRESS as usize, MEM_CP_BIT);
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            fdc.set_disks_hidden(false);
+        }
+    }
+
+    /// Capture the state of the machine into a save state. The state is captured at an instruction
+    /// boundary, so this should only be called between calls to run().
+    /// Disk and tape media are not part of a save state; the images in the drives are left as they are.
+    /// Returns an error if the machine has a device installed that does not support save states.
+    pub fn save_state(&self) -> Result<Vec<u8>, SaveStateError> {
+        let mut w = StateWriter::new();
+        w.write_header(&format!("{:?}", self.machine_type), &format!("{:?}", self.cpu.get_type()));
+        w.write_state("cpu", &self.cpu.get_state());
+        if let Some(fpu) = self.cpu.fpu() {
+            w.write_state("fpu", fpu);
+        }
+        w.write_section("machine", |w| {
+            w.write_u64(self.cpu_cycles);
+            w.write_u64(self.cpu_instructions);
+            w.write_u64(self.system_ticks);
+            w.write_bool(self.turbo_button);
+            w.write_bool(self.boot_pending);
+        });
+        self.cpu.bus().save_state(&mut w)?;
+        Ok(w.into_vec())
+    }
+
+    /// Restore the machine from a save state produced by save_state(). The save state must have been
+    /// made by the same machine type and CPU. If the state cannot be restored, the machine is returned
+    /// to the state it was in before the call.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let backup = self.save_state()?;
+        if self.replay.take().is_some() {
+            log::warn!("Loading a save state stopped the active recording or playback.");
+        }
+        if let Err(e) = self.apply_state(data) {
+            log::error!("Failed to load save state: {}", e);
+            if let Err(e) = self.apply_state(&backup) {
+                log::error!("Failed to restore machine state: {}", e);
+            }
+            return Err(e);
+        }
+        self.error = false;
+        self.error_str = None;
+        Ok(())
+    }
+
+    fn apply_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut r = StateReader::new(data);
+        let header = r.read_header()?;
+
+        let machine = format!("{:?}", self.machine_type);
+        if header.machine != machine {
+            return Err(SaveStateError::MachineMismatch(header.machine, machine));
+        }
+        let cpu = format!("{:?}", self.cpu.get_type());
+        if header.cpu != cpu {
+            return Err(SaveStateError::MachineMismatch(header.cpu, cpu));
+        }
+
+        while let Some((tag, mut section)) = r.read_section()? {
+            match tag.as_str() {
+                "cpu" => {
+                    let mut state = CpuState::default();
+                    section.read_state(&mut state, "cpu")?;
+                    self.cpu.set_state(&state);
+                }
+                "fpu" => match self.cpu.fpu().cloned() {
+                    Some(mut fpu) => {
+                        section.read_state(&mut fpu, "FPU")?;
+                        self.cpu.set_fpu(Some(fpu));
+                    }
+                    None => log::warn!("Save state section 'fpu' was not restored."),
+                },
+                "machine" => {
+                    self.cpu_cycles = section.read_u64()?;
+                    self.cpu_instructions = section.read_u64()?;
+                    self.system_ticks = section.read_u64()?;
+                    let turbo = section.read_bool()?;
+                    self.apply_turbo_mode(turbo);
+                    self.boot_pending = section.read_bool()?;
+                }
+                _ => {
+                    if !self.cpu.bus_mut().load_state_section(&tag, &mut section)? {
+                        log::warn!("Save state section '{}' was not restored.", tag);
+                    }
+                }
+            }
+        }
+
+        // The floppy disks stay hidden until a pending hard disk boot reaches the boot sector.
+        let boot_pending = self.boot_pending;
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            fdc.set_disks_hidden(boot_pending);
+        }
+        if boot_pending {
+            self.cpu.bus_mut().set_flags(BOOT_SECTOR_ADDRESS as usize, MEM_CP_BIT);
+        }
+        else if !self.checkpoint_map.contains_key(&BOOT_SECTOR_ADDRESS)
+            && !self.patch_map.contains_key(&BOOT_SECTOR_ADDRESS)
+        {
+            self.cpu.bus_mut().clear_flags(BOOT_SECTOR_ADDRESS as usize, MEM_CP_BIT);
+        }
+
+        // Keystrokes queued for the old state would be delivered to the restored one.
+        self.kb_buf.clear();
+        self.cancel_paste();
+        Ok(())
+    }
+
+    /// Start recording host input. The machine is reset, and a save state of the machine and the
+    /// disks in the floppy drives are kept as the starting point of the recording.
+    pub fn start_recording(&mut self) -> Result<(), Error> {
+        if self.replay.is_some() {
+            return Err(anyhow!("A recording is already being made or played."));
+        }
+        // Check before resetting the machine, as the recording starts from a save state.
+        if let Some(device) = self.cpu.bus().unsupported_state_device() {
+            return Err(anyhow!("Input can't be recorded, the {} does not support save states.", device));
+        }
+
+        let mut media = Vec::new();
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            for drive in 0..fdc.drive_ct() {
+                if fdc.get_image(drive).is_some() {
+                    let data = fdc
+                        .get_image_data(drive)
+                        .map_err(|e| anyhow!("The disk in drive {} can't be stored in a recording: {}", drive, e))?;
+                    media.push(FloppyMedia {
+                        drive,
+                        data,
+                        write_protect: fdc.is_write_protected(drive),
+                    });
+                }
+            }
+        }
+
+        // Host connections are not part of a recording.
+        if self.cpu.bus_mut().serial_mut().as_ref().is_some_and(|spc| spc.is_bridged()) {
+            log::warn!("A serial port is bridged to the host. Its traffic will not be recorded.");
+        }
+
+        self.reset();
+        self.kb_buf.clear();
+        self.cancel_paste();
+
+        self.replay = Some(Replay::Recording(Recording {
+            machine: format!("{:?}", self.machine_type),
+            cpu: format!("{:?}", self.cpu.get_type()),
+            boot_device: self.boot_device,
+            snapshot: self.save_state()?,
+            media,
+            events: Vec::new(),
+            end_cycle: 0,
+            end_hash: 0,
+        }));
+        log::info!("Started recording input at cycle {}", self.cpu_cycles);
+        Ok(())
+    }
+
+    /// Stop recording host input. Returns the recording, or None if no recording was being made.
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        match self.replay.take() {
+            Some(Replay::Recording(mut recording)) => {
+                recording.end_cycle = self.cpu_cycles;
+                recording.end_hash = self.save_state().map_or(0, |state| state_hash(&state));
+                log::info!(
+                    "Stopped recording input at cycle {}, {} events recorded",
+                    self.cpu_cycles,
+                    recording.events.len()
+                );
+                Some(recording.to_bytes())
+            }
+            other => {
+                self.replay = other;
+                None
+            }
+        }
+    }
+
+    /// Play back a recording made by start_recording(). The machine is reset to the starting
+    /// point of the recording, after which input from the host is ignored until playback ends.
+    pub fn start_playback(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.replay.is_some() {
+            return Err(anyhow!("A recording is already being made or played."));
+        }
+        let recording = Recording::from_bytes(data)?;
+
+        let machine = format!("{:?}", self.machine_type);
+        if recording.machine != machine {
+            return Err(SaveStateError::MachineMismatch(recording.machine, machine).into());
+        }
+        let cpu = format!("{:?}", self.cpu.get_type());
+        if recording.cpu != cpu {
+            return Err(SaveStateError::MachineMismatch(recording.cpu, cpu).into());
+        }
+
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            for drive in 0..fdc.drive_ct() {
+                fdc.unload_image(drive);
+            }
+            for media in &recording.media {
+                fdc.load_image_from(media.drive, media.data.clone(), media.write_protect)?;
+            }
+        }
+
+        self.set_boot_device(recording.boot_device);
+        self.reset();
+        self.load_state(&recording.snapshot)?;
+        self.kb_buf.clear();
+        self.cancel_paste();
+        self.cpu_factor = self.next_cpu_factor;
+        let factor = self.cpu_factor;
+        self.bus_mut().set_cpu_factor(factor);
+
+        log::info!(
+            "Playing back {} input events from cycle {} to {}",
+            recording.events.len(),
+            self.cpu_cycles,
+            recording.end_cycle
+        );
+        let start_cycle = self.cpu_cycles;
+        self.replay = Some(Replay::Playing(Playback::new(recording, start_cycle)));
+        Ok(())
+    }
+
+    /// Stop playback of a recording, returning control of the machine to the host.
+    pub fn stop_playback(&mut self) {
+        if self.is_replaying() {
+            log::info!("Stopped playback at cycle {}", self.cpu_cycles);
+            self.replay = None;
+            self.kb_buf.clear();
+        }
+    }
+
+    /// Returns true if a recording is being played back. Input from the host is ignored during playback.
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.replay, Some(Replay::Playing(_)))
+    }
+
+    pub fn replay_status(&self) -> ReplayStatus {
+        match &self.replay {
+            Some(Replay::Recording(recording)) => ReplayStatus::Recording {
+                events: recording.events.len(),
+            },
+            Some(Replay::Playing(playback)) => ReplayStatus::Playing {
+                elapsed: self.cpu_cycles.saturating_sub(playback.start_cycle),
+                length:  playback.recording.end_cycle.saturating_sub(playback.start_cycle),
+            },
+            None => ReplayStatus::Idle,
+        }
+    }
+
+    /// Apply an input from the host, recording it if a recording is being made. Input from the
+    /// host is ignored during playback.
+    fn host_input(&mut self, event: InputEvent) {
+        if self.is_replaying() {
+            return;
+        }
+        self.record_input(event.clone());
+        self.apply_input(event);
+    }
+
+    fn record_input(&mut self, event: InputEvent) {
+        if let Some(Replay::Recording(recording)) = &mut self.replay {
+            recording.events.push(TimedInput {
+                cycle: self.cpu_cycles,
+                event,
+            });
+        }
+    }
+
+    fn apply_input(&mut self, event: InputEvent) {
+        match event {
+            // Key events are delivered to the keyboard by run_devices().
+            InputEvent::Key(_) => {}
+            InputEvent::CtrlAltDel => self.ctrl_alt_del(),
+            InputEvent::Mouse {
+                l_button,
+                r_button,
+                delta_x,
+                delta_y,
+            } => {
+                if let Some(mouse) = self.cpu.bus_mut().mouse_mut() {
+                    mouse.update(l_button, r_button, delta_x, delta_y);
+                }
+                if let Some(bus_mouse) = self.cpu.bus_mut().bus_mouse_mut() {
+                    bus_mouse.update(l_button, r_button, delta_x, delta_y);
+                }
+            }
+            InputEvent::JoystickButton {
+                controller,
+                button,
+                pressed,
+            } => {
+                if let Some(game_port) = self.cpu.bus_mut().game_port_mut() {
+                    game_port.set_button(controller, button, pressed);
+                }
+            }
+            InputEvent::JoystickPosition {
+                controller,
+                stick,
+                x,
+                y,
+            } => {
+                if let Some(game_port) = self.cpu.bus_mut().game_port_mut() {
+                    game_port.set_stick_pos(controller, stick, x, y);
+                }
+            }
+            InputEvent::LoadFloppy(media) => {
+                if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+                    if let Err(e) = fdc.load_image_from(media.drive, media.data, media.write_protect) {
+                        log::error!("Failed to load floppy image from recording: {}", e);
+                    }
+                }
+            }
+            InputEvent::EjectFloppy(drive) => {
+                if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+                    fdc.unload_image(drive);
+                }
+            }
+            InputEvent::TurboButton(state) => self.apply_turbo_mode(state),
+            InputEvent::ClockFactor(factor) => {
+                self.cpu_factor = factor;
+                self.bus_mut().set_cpu_factor(factor);
+            }
+            InputEvent::Reset => self.reset_machine(),
+        }
+    }
+
+    /// Apply the inputs from the recording being played back that are due at the current cycle,
+    /// and finish playback once the end of the recording is reached.
+    fn run_replay(&mut self) {
+        let cycle = self.cpu_cycles;
+        let finished = loop {
+            let (event, finished) = match &mut self.replay {
+                Some(Replay::Playing(playback)) => (playback.next_input(cycle), playback.is_finished(cycle)),
+                _ => return,
+            };
+            match event {
+                Some(event) => self.apply_input(event),
+                None => break finished,
+            }
+        };
+
+        if finished {
+            let matched = match &self.replay {
+                Some(Replay::Playing(playback)) => self
+                    .save_state()
+                    .is_ok_and(|state| playback.recording.end_hash == state_hash(&state)),
+                _ => false,
+            };
+            if matched {
+                log::info!(
+                    "Playback finished at cycle {}. The machine state matches the recording.",
+                    cycle
+                );
+            }
+            else {
+                log::warn!(
+                    "Playback finished at cycle {}. The machine state differs from the recording!",
+                    cycle
+                );
+            }
+            self.replay = None;
+            self.kb_buf.clear();
+            self.events.push(MachineEvent::ReplayFinished(matched));
+        }
+    }
+
     pub fn set_reload_pending(&mut self, state: bool) {
         self.reload_pending = state;
     }
@@ -1191,10 +1869,15 @@ impl Machine {
         let mut skip_breakpoint = false;
         let mut instr_count = 0;
 
-        // Update cpu factor.
-        let new_factor = self.next_cpu_factor;
-        self.cpu_factor = new_factor;
-        self.bus_mut().set_cpu_factor(new_factor);
+        // Update cpu factor. During playback, the cpu factor follows the recording instead.
+        if !self.is_replaying() {
+            let new_factor = self.next_cpu_factor;
+            if new_factor != self.cpu_factor {
+                self.record_input(InputEvent::ClockFactor(new_factor));
+            }
+            self.cpu_factor = new_factor;
+            self.bus_mut().set_cpu_factor(new_factor);
+        }
 
         // Don't run this iteration if we're pending a ROM reload
         if self.reload_pending {
@@ -1303,6 +1986,11 @@ impl Machine {
         while cycles_elapsed < cycle_target_adj {
             let fake_cycles: u32 = 7;
             let mut cpu_cycles;
+
+            // Apply recorded input when it is due.
+            if self.is_replaying() {
+                self.run_replay();
+            }
             
             // if self.cpu.is_error() {
             //     break;
@@ -1324,6 +2012,10 @@ impl Machine {
                         .push(MachineEvent::CheckpointHit(*cp, self.rom_manifest.checkpoints[*cp].lvl));
                 }
 
+                if self.boot_pending && flat_address == BOOT_SECTOR_ADDRESS {
+                    self.finish_boot();
+                }
+
                 if let Some(&cp) = self.patch_map.get(&flat_address) {
                     log::debug!(
                         "ROM PATCH CHECKPOINT: [{:05X}] Installing patch...",
@@ -1474,6 +2166,13 @@ impl Machine {
                     }
                 }
             }
+
+            // Stop running once the guest writes to the exit port.
+            if let Some(code) = self.cpu.bus_mut().take_exit_code() {
+                log::debug!("Exit requested with code {} at cycle {}", code, self.cpu_cycles);
+                self.events.push(MachineEvent::ExitRequested(code));
+                break;
+            }
         }
 
         //log::debug!("cycles_elapsed: {}", cycles_elapsed);
@@ -1502,10 +2201,39 @@ impl Machine {
         // If we limit keyboard events to once per frame, this avoids this problem. I'm a reasonably
         // fast typist and this method seems to work fine.
         let mut kb_event_opt: Option<KeybufferEntry> = None;
-        if !self.kb_buf.is_empty() && !*kb_event_processed {
-            kb_event_opt = self.kb_buf.pop_front();
-            if kb_event_opt.is_some() {
-                *kb_event_processed = true;
+        if let Some(Replay::Playing(playback)) = &mut self.replay {
+            // During playback, key events are delivered when the recording says they were.
+            kb_event_opt = playback.next_key(self.cpu_cycles);
+        }
+        else {
+            if !self.kb_buf.is_empty() && !*kb_event_processed {
+                kb_event_opt = self.kb_buf.pop_front();
+                if kb_event_opt.is_some() {
+                    *kb_event_processed = true;
+                }
+            }
+
+            // Deliver pasted key events at the paste rate. These are not limited to once per frame.
+            if kb_event_opt.is_none() && !self.paste_buf.is_empty() {
+                self.paste_us_accum += us;
+                if self.paste_us_accum >= self.paste_delay {
+                    self.paste_us_accum = 0.0;
+                    kb_event_opt = self.paste_buf.pop_front();
+
+                    if let Some(KeybufferEntry {
+                        keycode: MartyKey::Enter,
+                        pressed: false,
+                        ..
+                    }) = kb_event_opt
+                    {
+                        // Give the guest time to process a line of input before typing the next one.
+                        self.paste_us_accum = -self.paste_delay * PASTE_LINE_DELAY_FACTOR;
+                    }
+                }
+            }
+
+            if let Some(entry) = kb_event_opt {
+                self.record_input(InputEvent::Key(entry));
             }
         }
 
@@ -1629,6 +2357,59 @@ impl Machine {
         device_events
     }
 
+    /// Return the sample rate of the audio output, or None if no sound player is present.
+    pub fn audio_sample_rate(&self) -> Option<u32> {
+        self.sound_player.as_ref().map(|_| self.pit_data.sample_rate)
+    }
+
+    /// Start or stop capturing the audio samples sent to the sound player, ie, for video recording.
+    pub fn set_audio_capture(&mut self, state: bool) {
+        self.pit_data.capture = if state { Some(Vec::new()) } else { None };
+    }
+
+    /// Resample audio output for a machine running at `factor` times real time, so that the rate
+    /// samples are produced keeps pace with the rate the sound player consumes them.
+    pub fn set_audio_rate_factor(&mut self, factor: f64) {
+        self.pit_data.ticks_per_sample = (pit::PIT_MHZ * 1_000_000.0) / self.pit_data.sample_rate as f64 * factor;
+        let emulated_rate = self.pit_data.sample_rate as f64 / factor;
+        self.sound_mixer.set_output_rate(emulated_rate);
+        self.sound_mixer
+            .set_source_rate(self.pit_data.source.id(), emulated_rate);
+        log::debug!(
+            "Audio rate factor set to {:.4}, pit_ticks_per_sample: {}",
+            factor,
+            self.pit_data.ticks_per_sample
+        );
+    }
+
+    /// Return the sound player's buffer configuration and statistics, or None if no sound player
+    /// is present.
+    pub fn sound_player_stats(&self) -> Option<SoundPlayerStats> {
+        self.sound_player.as_ref().map(|sound_player| sound_player.stats())
+    }
+
+    pub fn reset_sound_player_stats(&self) {
+        if let Some(sound_player) = &self.sound_player {
+            sound_player.reset_stats();
+        }
+    }
+
+    pub fn sound_mixer(&self) -> &SoundMixer {
+        &self.sound_mixer
+    }
+
+    pub fn sound_mixer_mut(&mut self) -> &mut SoundMixer {
+        &mut self.sound_mixer
+    }
+
+    /// Take all audio samples captured since the last call.
+    pub fn take_audio_capture(&mut self) -> Vec<f32> {
+        match &mut self.pit_data.capture {
+            Some(capture) => std::mem::take(capture),
+            None => Vec::new(),
+        }
+    }
+
     pub fn play_sound_buffer(&self) {
         if let Some(sound_player) = &self.sound_player {
             sound_player.play();
@@ -1641,7 +2422,7 @@ impl Machine {
             return;
         }
 
-        let mut sum = 0;
+        let mut sum = 0.0;
         let mut sample;
         let mut samples_read = false;
 
@@ -1653,7 +2434,7 @@ impl Machine {
                         log::trace!("No byte in pit buffer");
                         0
                     });
-                    sum += sample;
+                    sum += self.pit_data.filter.input(sample);
 
                     let sample_f32: f32 = if sample == 0 { 0.0 } else { 1.0 };
                     file.write_all(&sample_f32.to_le_bytes())
@@ -1670,19 +2451,26 @@ impl Machine {
                     log::trace!("No byte in pit buffer");
                     0
                 });
-                sum += sample;
+                sum += self.pit_data.filter.input(sample);
             }
         }
 
-        // Averaging samples is effectively a poor lowpass filter.
-        // TODO: replace with actual lowpass filter from biquad?
-        let average: f32 = sum as f32 / nsamples as f32;
+        // The speaker signal has been band-limited at the PIT rate, so averaging the filtered
+        // samples over the output period is sufficient to decimate without aliasing.
+        let average: f32 = self.pit_data.filter.output(sum / nsamples as f64);
 
         //log::trace!("Sample: sum: {}, ticks: {}, avg: {}", sum, pit_ticks, average);
         self.pit_data.samples_produced += 1;
         //log::trace!("producer: {}", self.pit_samples_produced);
+        // The speaker sample clocks the mixer, which produces one output sample for each.
+        self.pit_data.source.push(average);
+        let sample = self.sound_mixer.mix();
+
         if let Some(sound_player) = &mut self.sound_player {
-            sound_player.queue_sample(average * VOLUME_ADJUST);
+            sound_player.queue_sample(sample);
+        }
+        if let Some(capture) = &mut self.pit_data.capture {
+            capture.push(sample);
         }
 
         // Calculate size of next audio sample in pit samples by carrying over fractional part
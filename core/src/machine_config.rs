@@ -31,14 +31,19 @@
 */
 
 use crate::machine_types::{
+    BusMouseType,
     EmsType,
     FdcType,
     FloppyDriveType,
     HardDiskControllerType,
     HardDriveFormat,
+    LptDacType,
     MachineType,
+    NetworkBackendType,
+    PrinterType,
     SerialControllerType,
     SerialMouseType,
+    SoundBlasterType,
 };
 use anyhow::{anyhow, Error};
 use lazy_static::lazy_static;
@@ -48,7 +53,7 @@ use crate::{
     bus::ClockFactor,
     cpu_common::CpuType,
     device_traits::videocard::VideoType,
-    devices::{keyboard::KeyboardType, pit::PitType},
+    devices::{keyboard::KeyboardType, pit::PitType, sn76489::SN76489_DEFAULT_IO_BASE},
     tracelogger::TraceLogger,
 };
 
@@ -120,11 +125,22 @@ pub enum BusType {
 #[derive(Clone, Debug, Deserialize)]
 pub struct CpuConfig {
     pub upgrade_type: Option<CpuType>,
+    #[serde(default)]
+    pub fpu: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct MemoryConfig {
     pub conventional: ConventionalMemoryConfig,
+    #[serde(default)]
+    pub holes: Vec<MemoryHoleConfig>,
+}
+
+/// A region of the address space with no memory installed. Reads return the open bus byte and writes are ignored.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MemoryHoleConfig {
+    pub address: u32,
+    pub size:    u32,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -159,6 +175,58 @@ pub struct SerialMouseConfig {
     pub port: u32,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct SerialModemConfig {
+    pub port: u32,
+    pub listen_port: Option<u16>,
+    pub listen_address: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BusMouseConfig {
+    #[serde(rename = "type")]
+    pub mouse_type: BusMouseType,
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LptDacConfig {
+    #[serde(rename = "type")]
+    pub dac_type: LptDacType,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SoundBlasterConfig {
+    #[serde(rename = "type")]
+    pub sb_type: SoundBlasterType,
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+    pub dma: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Mpu401Config {
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Ne2000Config {
+    pub io_base: Option<u16>,
+    pub irq: Option<u8>,
+    pub mac_address: Option<String>,
+    #[serde(default)]
+    pub backend: NetworkBackendType,
+    pub pcap_interface: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrinterConfig {
+    #[serde(rename = "type")]
+    pub printer_type: PrinterType,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct GamePortConfig {
     pub io_base: u16,
@@ -167,10 +235,11 @@ pub struct GamePortConfig {
 #[derive(Clone, Debug, Deserialize)]
 pub struct VideoCardConfig {
     #[serde(rename = "type")]
-    pub video_type:    VideoType,
+    pub video_type: VideoType,
     #[serde(rename = "subtype")]
     pub video_subtype: Option<VideoCardSubType>,
-    pub dip_switch:    Option<u8>,
+    pub dip_switch: Option<u8>,
+    pub snow: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -206,7 +275,10 @@ pub struct FloppyDriveConfig {
 pub struct HardDriveControllerConfig {
     #[serde(rename = "type")]
     pub hdc_type: HardDiskControllerType,
-    pub drive:    Option<Vec<HardDriveConfig>>,
+    pub io_base: Option<u16>, // IDE controllers only
+    pub irq: Option<u8>,      // IDE controllers only
+    pub drive: Option<Vec<HardDriveConfig>>,
+    pub cdrom: Option<u8>, // IDE controllers only. Attach an ATAPI CD-ROM drive as device 0 (master) or 1 (slave).
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -243,6 +315,13 @@ pub struct MachineConfiguration {
     pub ems: Option<EmsMemoryConfig>,
     pub keyboard: Option<KeyboardConfig>,
     pub serial_mouse: Option<SerialMouseConfig>,
+    pub bus_mouse: Option<BusMouseConfig>,
+    pub serial_modem: Option<SerialModemConfig>,
+    pub printer: Option<PrinterConfig>,
+    pub lpt_dac: Option<LptDacConfig>,
+    pub sound_blaster: Option<SoundBlasterConfig>,
+    pub mpu401: Option<Mpu401Config>,
+    pub ne2000: Option<Ne2000Config>,
     pub video: Vec<VideoCardConfig>,
     pub serial: Vec<SerialControllerConfig>,
     pub game_port: Option<GamePortConfig>,
@@ -322,6 +401,7 @@ pub struct MachineDescriptor {
     pub allow_expansion_video: bool,   // Whether the machine allows for expansion video cards.
     pub pcjr_cart_slot: bool,          // Does the system have PCJr cartridge slots?
     pub game_port: Option<u16>,        // Does the system have an onboard game port, and if so, at what address?
+    pub sn76489: Option<u16>,          // Does the system have an onboard SN76489, and if so, at what address?
 }
 
 impl Default for MachineDescriptor {
@@ -350,6 +430,7 @@ impl Default for MachineDescriptor {
             allow_expansion_video: true,
             pcjr_cart_slot: false,
             game_port: None,
+            sn76489: None,
         }
     }
 }
@@ -422,6 +503,7 @@ lazy_static! {
                     dma_type: None,
                     pcjr_cart_slot: true, // PCJr has cartridge slots!
                     game_port: Some(GAME_PORT_DEFAULT_IO),
+                    sn76489: Some(SN76489_DEFAULT_IO_BASE),
                     ..Default::default()
                 },
             ),
@@ -450,6 +532,7 @@ lazy_static! {
                     allow_expansion_video: false,
                     pcjr_cart_slot: false,
                     game_port: Some(GAME_PORT_DEFAULT_IO),
+                    sn76489: Some(SN76489_DEFAULT_IO_BASE),
                     ..Default::default()
                 },
             )
@@ -54,7 +54,7 @@ impl FromStr for MachineType {
         match s.to_lowercase().as_str() {
             "default" => Ok(MachineType::Default),
             "ibm5150v64k" => Ok(MachineType::Ibm5150v64K),
-            "ibm5150v256k" => Ok(MachineType::Ibm5150v64K),
+            "ibm5150v256k" => Ok(MachineType::Ibm5150v256K),
             "ibm5160" => Ok(MachineType::Ibm5160),
             "ibm_pcjr" => Ok(MachineType::IbmPCJr),
             "tandy1000" => Ok(MachineType::Tandy1000),
@@ -86,6 +86,31 @@ impl FromStr for OnHaltBehavior {
     }
 }
 
+/// The drive the machine boots from. The BIOS always tries floppy drive A: before the first hard disk, so
+/// other boot devices are arranged by exchanging or hiding floppy drives while the machine boots.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum BootDevice {
+    #[default]
+    FloppyA,
+    FloppyB,
+    HardDisk,
+}
+
+impl FromStr for BootDevice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "a" | "floppya" => Ok(BootDevice::FloppyA),
+            "b" | "floppyb" => Ok(BootDevice::FloppyB),
+            "c" | "harddisk" => Ok(BootDevice::HardDisk),
+            _ => Err("Bad value for BootDevice".to_string()),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FloppyDriveType {
     Floppy360K,
@@ -94,6 +119,18 @@ pub enum FloppyDriveType {
     Floppy144M,
 }
 
+impl FloppyDriveType {
+    /// Return the size in bytes of the highest capacity standard disk the drive can use.
+    pub fn max_disk_size(&self) -> usize {
+        match self {
+            FloppyDriveType::Floppy360K => 368_640,
+            FloppyDriveType::Floppy720K => 737_280,
+            FloppyDriveType::Floppy12M => 1_228_800,
+            FloppyDriveType::Floppy144M => 1_474_560,
+        }
+    }
+}
+
 impl FromStr for FloppyDriveType {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, String>
@@ -152,6 +189,8 @@ pub enum FdcType {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum HardDiskControllerType {
     IbmXebec,
+    XtIde,
+    Ata8Bit,
 }
 
 impl FromStr for HardDiskControllerType {
@@ -162,6 +201,8 @@ impl FromStr for HardDiskControllerType {
     {
         match s.to_lowercase().as_str() {
             "ibmxebec" => Ok(HardDiskControllerType::IbmXebec),
+            "xtide" => Ok(HardDiskControllerType::XtIde),
+            "ata8bit" => Ok(HardDiskControllerType::Ata8Bit),
             _ => Err("Bad value for HardDiskControllerType".to_string()),
         }
     }
@@ -176,14 +217,45 @@ pub enum HardDriveFormat {
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum SerialControllerType {
     IbmAsync,
+    Ns16550,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum SerialMouseType {
     Microsoft,
+    #[serde(alias = "Logitech")]
+    MouseSystems,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum BusMouseType {
+    InPort,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum SoundBlasterType {
+    SoundBlaster2,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum LptDacType {
+    Covox,
+    DisneySoundSource,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+pub enum PrinterType {
+    EpsonFx80,
 }
 
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 pub enum EmsType {
     LoTech2MB,
 }
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum NetworkBackendType {
+    #[default]
+    User,
+    Pcap,
+}
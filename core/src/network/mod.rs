@@ -0,0 +1,93 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    network::mod.rs
+
+    Host network backends for emulated network cards.
+
+    A backend exchanges raw Ethernet frames with the emulated card. The user
+    mode backend implements a small NAT router that proxies the guest's
+    traffic through ordinary host sockets, so it needs no special privileges.
+    The pcap backend, available with the 'pcap' feature, bridges frames
+    directly to a host network interface.
+
+*/
+
+pub mod packet;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod tcp_nat;
+pub mod user;
+
+use anyhow::{anyhow, Error};
+
+use crate::machine_types::NetworkBackendType;
+
+pub type MacAddress = [u8; 6];
+
+pub const BROADCAST_MAC: MacAddress = [0xFF; 6];
+
+pub trait NetworkBackend: Send {
+    /// Send a frame from the guest to the network.
+    fn send(&mut self, frame: &[u8]);
+    /// Run the backend for the specified number of microseconds, and return the next frame
+    /// received for the guest, if any.
+    fn poll(&mut self, us: f64) -> Option<Vec<u8>>;
+}
+
+/// Parse a MAC address in the form "52:54:00:12:34:56". Hyphens are also accepted as separators.
+pub fn parse_mac_address(mac_str: &str) -> Result<MacAddress, Error> {
+    let bytes = mac_str
+        .split([':', '-'])
+        .map(|b| u8::from_str_radix(b, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| anyhow!("Invalid MAC address: {}", mac_str))?;
+
+    let mac: MacAddress = bytes
+        .try_into()
+        .map_err(|_| anyhow!("Invalid MAC address: {}", mac_str))?;
+    Ok(mac)
+}
+
+/// Create the network backend of the specified type. `interface` names the host interface for
+/// backends that attach to one.
+pub fn create_backend(
+    backend_type: NetworkBackendType,
+    interface: Option<&str>,
+) -> Result<Box<dyn NetworkBackend>, Error> {
+    match backend_type {
+        NetworkBackendType::User => Ok(Box::new(user::UserNetwork::new())),
+        #[cfg(feature = "pcap")]
+        NetworkBackendType::Pcap => Ok(Box::new(pcap::PcapNetwork::new(interface)?)),
+        #[cfg(not(feature = "pcap"))]
+        NetworkBackendType::Pcap => {
+            let _ = interface;
+            Err(anyhow!(
+                "The pcap network backend requires MartyPC to be built with the 'pcap' feature"
+            ))
+        }
+    }
+}
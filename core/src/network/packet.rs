@@ -0,0 +1,430 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    network::packet.rs
+
+    Helpers for parsing and building Ethernet, ARP, IPv4, UDP and TCP
+    packets.
+
+*/
+
+use std::net::Ipv4Addr;
+
+use crate::network::MacAddress;
+
+pub const ETH_HEADER_LEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const IPV4_HEADER_LEN: usize = 20;
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+const IP_DEFAULT_TTL: u8 = 64;
+
+pub const UDP_HEADER_LEN: usize = 8;
+pub const TCP_HEADER_LEN: usize = 20;
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            dst: frame[0..6].try_into().unwrap(),
+            src: frame[6..12].try_into().unwrap(),
+            ethertype: read_u16(frame, 12),
+            payload: &frame[ETH_HEADER_LEN..],
+        })
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse an IPv4 packet. Fragmented packets are not supported and are rejected.
+    pub fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = ((packet[0] & 0x0F) as usize) * 4;
+        let total_len = read_u16(packet, 2) as usize;
+        let fragment = read_u16(packet, 6);
+        if header_len < IPV4_HEADER_LEN || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        // More fragments flag set, or a non-zero fragment offset
+        if fragment & 0x3FFF != 0 {
+            return None;
+        }
+        Some(Self {
+            src: read_ipv4(packet, 12),
+            dst: read_ipv4(packet, 16),
+            protocol: packet[9],
+            payload: &packet[header_len..total_len],
+        })
+    }
+}
+
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload:  &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub fn parse(datagram: &'a [u8]) -> Option<Self> {
+        if datagram.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        let len = read_u16(datagram, 4) as usize;
+        if len < UDP_HEADER_LEN || len > datagram.len() {
+            return None;
+        }
+        Some(Self {
+            src_port: read_u16(datagram, 0),
+            dst_port: read_u16(datagram, 2),
+            payload:  &datagram[UDP_HEADER_LEN..len],
+        })
+    }
+}
+
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(segment: &'a [u8]) -> Option<Self> {
+        if segment.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let header_len = ((segment[12] >> 4) as usize) * 4;
+        if header_len < TCP_HEADER_LEN || header_len > segment.len() {
+            return None;
+        }
+
+        // Look for the MSS option
+        let mut mss = None;
+        let options = &segment[TCP_HEADER_LEN..header_len];
+        let mut i = 0;
+        while i < options.len() {
+            match options[i] {
+                0 => break,
+                1 => i += 1,
+                kind => {
+                    let len = *options.get(i + 1)? as usize;
+                    if len < 2 || i + len > options.len() {
+                        break;
+                    }
+                    if kind == 2 && len == 4 {
+                        mss = Some(read_u16(options, i + 2));
+                    }
+                    i += len;
+                }
+            }
+        }
+
+        Some(Self {
+            src_port: read_u16(segment, 0),
+            dst_port: read_u16(segment, 2),
+            seq: read_u32(segment, 4),
+            ack: read_u32(segment, 8),
+            flags: segment[13],
+            window: read_u16(segment, 14),
+            mss,
+            payload: &segment[header_len..],
+        })
+    }
+}
+
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+pub fn read_ipv4(buf: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3])
+}
+
+/// Calculate the ones' complement checksum used by IP, ICMP, UDP and TCP. `sum` is the initial
+/// value, ie, the sum of a pseudo-header.
+pub fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Sum the pseudo-header used in UDP and TCP checksums.
+fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let s = src.octets();
+    let d = dst.octets();
+    u16::from_be_bytes([s[0], s[1]]) as u32
+        + u16::from_be_bytes([s[2], s[3]]) as u32
+        + u16::from_be_bytes([d[0], d[1]]) as u32
+        + u16::from_be_bytes([d[2], d[3]]) as u32
+        + protocol as u32
+        + len as u32
+}
+
+pub fn build_ethernet(dst: MacAddress, src: MacAddress, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub fn build_ipv4(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = IPV4_HEADER_LEN + payload.len();
+    let mut packet = Vec::with_capacity(total_len);
+    packet.push(0x45); // Version 4, 5 word header
+    packet.push(0); // DSCP/ECN
+    packet.extend_from_slice(&(total_len as u16).to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&0x4000u16.to_be_bytes()); // Don't fragment
+    packet.push(IP_DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]); // Checksum placeholder
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(&packet[0..IPV4_HEADER_LEN], 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+pub fn build_udp(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let len = UDP_HEADER_LEN + payload.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    let mut sum = checksum(&datagram, pseudo_header_sum(src, dst, IP_PROTO_UDP, len));
+    if sum == 0 {
+        // A zero UDP checksum means no checksum, so send all ones instead.
+        sum = 0xFFFF;
+    }
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_tcp(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_len = TCP_HEADER_LEN + if mss.is_some() { 4 } else { 0 };
+    let len = header_len + payload.len();
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(((header_len / 4) as u8) << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]); // Checksum and urgent pointer
+    if let Some(mss) = mss {
+        segment.extend_from_slice(&[2, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    let sum = checksum(&segment, pseudo_header_sum(src, dst, IP_PROTO_TCP, len));
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const DST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+
+    #[test]
+    fn test_checksum() {
+        // A sample IPv4 header with its checksum field cleared. The checksum is 0xB861.
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8,
+            0x00, 0xC7,
+        ];
+        assert_eq!(checksum(&header, 0), 0xB861);
+        // Summing over a valid checksum gives zero.
+        header[10..12].copy_from_slice(&0xB861u16.to_be_bytes());
+        assert_eq!(checksum(&header, 0), 0);
+
+        // An odd trailing byte is padded with zero.
+        assert_eq!(checksum(&[0x12, 0x34, 0x56], 0), !(0x1234u16 + 0x5600));
+        // End around carry
+        assert_eq!(checksum(&[0xFF, 0xFF, 0x00, 0x01], 0), !0x0001);
+    }
+
+    #[test]
+    fn test_ethernet_round_trip() {
+        let dst = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let src = [0x52, 0x55, 0x0A, 0x00, 0x02, 0x02];
+        let frame = build_ethernet(dst, src, ETHERTYPE_ARP, &[1, 2, 3]);
+        assert_eq!(frame.len(), ETH_HEADER_LEN + 3);
+
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, dst);
+        assert_eq!(eth.src, src);
+        assert_eq!(eth.ethertype, ETHERTYPE_ARP);
+        assert_eq!(eth.payload, &[1, 2, 3]);
+
+        assert!(EthernetFrame::parse(&frame[..ETH_HEADER_LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn test_ipv4_round_trip() {
+        let mut packet = build_ipv4(SRC, DST, IP_PROTO_UDP, 0x1234, b"payload");
+        assert_eq!(packet.len(), IPV4_HEADER_LEN + 7);
+        assert_eq!(checksum(&packet[..IPV4_HEADER_LEN], 0), 0);
+
+        // Trailing padding, as added to short Ethernet frames, is not part of the payload.
+        packet.extend_from_slice(&[0, 0, 0]);
+        let ip = Ipv4Packet::parse(&packet).unwrap();
+        assert_eq!(ip.src, SRC);
+        assert_eq!(ip.dst, DST);
+        assert_eq!(ip.protocol, IP_PROTO_UDP);
+        assert_eq!(ip.payload, b"payload");
+
+        // Truncated packets, other IP versions and fragments are rejected.
+        assert!(Ipv4Packet::parse(&packet[..IPV4_HEADER_LEN + 6]).is_none());
+        let mut v6 = packet.clone();
+        v6[0] = 0x65;
+        assert!(Ipv4Packet::parse(&v6).is_none());
+        let mut fragment = packet.clone();
+        fragment[6..8].copy_from_slice(&0x2000u16.to_be_bytes());
+        assert!(Ipv4Packet::parse(&fragment).is_none());
+        fragment[6..8].copy_from_slice(&0x0004u16.to_be_bytes());
+        assert!(Ipv4Packet::parse(&fragment).is_none());
+    }
+
+    #[test]
+    fn test_udp_round_trip() {
+        let datagram = build_udp(SRC, 1024, DST, 53, b"query");
+        assert_eq!(
+            checksum(&datagram, pseudo_header_sum(SRC, DST, IP_PROTO_UDP, datagram.len())),
+            0
+        );
+
+        let udp = UdpDatagram::parse(&datagram).unwrap();
+        assert_eq!(udp.src_port, 1024);
+        assert_eq!(udp.dst_port, 53);
+        assert_eq!(udp.payload, b"query");
+
+        // The length field must fit in the datagram.
+        assert!(UdpDatagram::parse(&datagram[..UDP_HEADER_LEN + 4]).is_none());
+    }
+
+    #[test]
+    fn test_tcp_round_trip() {
+        let segment = build_tcp(
+            SRC,
+            1025,
+            DST,
+            80,
+            0x1122_3344,
+            0x5566_7788,
+            TCP_SYN | TCP_ACK,
+            8192,
+            Some(1460),
+            b"data",
+        );
+        assert_eq!(
+            checksum(&segment, pseudo_header_sum(SRC, DST, IP_PROTO_TCP, segment.len())),
+            0
+        );
+
+        let tcp = TcpSegment::parse(&segment).unwrap();
+        assert_eq!(tcp.src_port, 1025);
+        assert_eq!(tcp.dst_port, 80);
+        assert_eq!(tcp.seq, 0x1122_3344);
+        assert_eq!(tcp.ack, 0x5566_7788);
+        assert_eq!(tcp.flags, TCP_SYN | TCP_ACK);
+        assert_eq!(tcp.window, 8192);
+        assert_eq!(tcp.mss, Some(1460));
+        assert_eq!(tcp.payload, b"data");
+
+        let segment = build_tcp(SRC, 1025, DST, 80, 1, 2, TCP_ACK, 0, None, &[]);
+        let tcp = TcpSegment::parse(&segment).unwrap();
+        assert_eq!(tcp.mss, None);
+        assert!(tcp.payload.is_empty());
+
+        // A data offset past the end of the segment is rejected.
+        let mut bad = segment.clone();
+        bad[12] = 6 << 4;
+        assert!(TcpSegment::parse(&bad).is_none());
+    }
+}
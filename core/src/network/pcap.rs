@@ -0,0 +1,107 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    network::pcap.rs
+
+    A network backend that bridges the emulated card to a host interface
+    with libpcap (or Npcap on Windows).
+
+    Frames from the guest are injected on the interface as-is, and every
+    frame seen on the interface is offered to the card, whose own address
+    filter decides what the guest receives. This usually needs elevated
+    privileges, and wireless interfaces may refuse foreign MAC addresses.
+
+*/
+
+use anyhow::{anyhow, Error};
+use pcap::{Active, Capture, Device};
+
+use crate::network::NetworkBackend;
+
+pub struct PcapNetwork {
+    capture: Capture<Active>,
+    // Frames we have sent, so they can be discarded when captured again.
+    sent:    Vec<Vec<u8>>,
+}
+
+impl PcapNetwork {
+    /// Open the named host interface, or the system's default interface if no name is given.
+    pub fn new(interface: Option<&str>) -> Result<Self, Error> {
+        let device = match interface {
+            Some(name) => Device::list()?
+                .into_iter()
+                .find(|d| d.name == name || d.desc.as_deref() == Some(name))
+                .ok_or_else(|| anyhow!("Network interface not found: {}", name))?,
+            None => Device::lookup()?.ok_or_else(|| anyhow!("No default network interface found"))?,
+        };
+        log::debug!("PcapNetwork: opening interface {}", device.name);
+
+        let capture = Capture::from_device(device)?
+            .promisc(true)
+            .immediate_mode(true)
+            .snaplen(2048)
+            .timeout(1)
+            .open()?
+            .setnonblock()?;
+
+        Ok(Self {
+            capture,
+            sent: Vec::new(),
+        })
+    }
+}
+
+impl NetworkBackend for PcapNetwork {
+    fn send(&mut self, frame: &[u8]) {
+        if let Err(e) = self.capture.sendpacket(frame) {
+            log::warn!("PcapNetwork: failed to send frame: {}", e);
+            return;
+        }
+        // Some platforms capture outgoing frames; remember a few so they are not looped back.
+        if self.sent.len() >= 16 {
+            self.sent.remove(0);
+        }
+        self.sent.push(frame.to_vec());
+    }
+
+    fn poll(&mut self, _us: f64) -> Option<Vec<u8>> {
+        loop {
+            let packet = match self.capture.next_packet() {
+                Ok(packet) => packet.data.to_vec(),
+                Err(pcap::Error::TimeoutExpired) => return None,
+                Err(e) => {
+                    log::trace!("PcapNetwork: capture error: {}", e);
+                    return None;
+                }
+            };
+            if let Some(pos) = self.sent.iter().position(|f| *f == packet) {
+                self.sent.remove(pos);
+                continue;
+            }
+            return Some(packet);
+        }
+    }
+}
@@ -0,0 +1,634 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    network::tcp_nat.rs
+
+    TCP connection proxying for the user mode network backend.
+
+    Each TCP connection opened by the guest is terminated here: a SYN starts
+    a host connection to the same destination, and the handshake is only
+    completed once that connection succeeds, so 'connection refused' appears
+    to the guest as a reset. Data is then relayed between the guest's segments
+    and the host socket.
+
+    This is a deliberately small TCP implementation. It has a fixed receive
+    window, retransmits with go-back-N on a fixed timeout, and doesn't accept
+    out of order segments. That's plenty for a virtual link where nothing is
+    lost except when the guest's own receive buffer overflows.
+
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::Duration,
+};
+
+use crate::network::{packet::*, user::UserNetwork};
+
+/// A TCP segment for the guest, as (source address, destination address, segment).
+pub type TcpOutput = (Ipv4Addr, Ipv4Addr, Vec<u8>);
+
+// Maximum segment size we advertise and send.
+const MSS: u16 = 1460;
+// Our receive window. Data from the guest is buffered until the host socket accepts it.
+const RCV_WINDOW: usize = 8192;
+// Maximum amount of data read from the host ahead of the guest acknowledging it.
+const TX_BUFFER_MAX: usize = 65536;
+// Retransmission timeout, in microseconds.
+const RTO_US: f64 = 500_000.0;
+// Give up on a connection after this many retransmissions without progress.
+const MAX_RETRIES: u32 = 10;
+// Half-closed connections are dropped after this long without traffic, in microseconds.
+const CLOSE_TIMEOUT_US: f64 = 120_000_000.0;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ConnKey = (SocketAddrV4, SocketAddrV4);
+
+/// Returns true if sequence number `a` is before `b`.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+enum TcpState {
+    Connecting(Receiver<std::io::Result<TcpStream>>),
+    SynReceived,
+    Established,
+}
+
+struct TcpConnection {
+    guest: SocketAddrV4,
+    remote: SocketAddrV4,
+    state: TcpState,
+    stream: Option<TcpStream>,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_max: u32,
+    rcv_nxt: u32,
+    guest_window: u16,
+    guest_mss: u16,
+    tx_buf: VecDeque<u8>, // Data from the host, starting at snd_una
+    to_host: Vec<u8>,     // Data from the guest not yet written to the host
+    adv_window: usize,
+    host_eof: bool,
+    host_shutdown: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+    guest_fin: bool,
+    rto_timer: f64,
+    retries: u32,
+    idle_us: f64,
+}
+
+impl TcpConnection {
+    fn segment(&mut self, seq: u32, flags: u8, mss: Option<u16>, payload: &[u8]) -> TcpOutput {
+        self.adv_window = RCV_WINDOW.saturating_sub(self.to_host.len());
+        let segment = build_tcp(
+            *self.remote.ip(),
+            self.remote.port(),
+            *self.guest.ip(),
+            self.guest.port(),
+            seq,
+            self.rcv_nxt,
+            flags,
+            self.adv_window as u16,
+            mss,
+            payload,
+        );
+        (*self.remote.ip(), *self.guest.ip(), segment)
+    }
+
+    fn ack(&mut self) -> TcpOutput {
+        self.segment(self.snd_nxt, TCP_ACK, None, &[])
+    }
+
+    fn reset(&mut self) -> TcpOutput {
+        self.segment(self.snd_nxt, TCP_RST | TCP_ACK, None, &[])
+    }
+
+    fn syn_ack(&mut self) -> TcpOutput {
+        self.segment(self.iss, TCP_SYN | TCP_ACK, Some(MSS), &[])
+    }
+
+    fn set_snd_nxt(&mut self, seq: u32) {
+        self.snd_nxt = seq;
+        if seq_lt(self.snd_max, seq) {
+            self.snd_max = seq;
+        }
+    }
+
+    /// Process a segment from the guest. Returns false if the connection should be removed.
+    fn handle_segment(&mut self, seg: &TcpSegment, out: &mut Vec<TcpOutput>) -> bool {
+        if seg.flags & TCP_RST != 0 {
+            log::debug!("TcpNat: guest reset connection to {}", self.remote);
+            return false;
+        }
+        if seg.flags & TCP_SYN != 0 {
+            // Retransmitted SYN; the guest didn't see our SYN-ACK.
+            if let TcpState::SynReceived = self.state {
+                out.push(self.syn_ack());
+            }
+            return true;
+        }
+        if let TcpState::Connecting(_) = self.state {
+            return true;
+        }
+        self.idle_us = 0.0;
+
+        if seg.flags & TCP_ACK != 0 {
+            if let TcpState::SynReceived = self.state {
+                if seg.ack != self.iss.wrapping_add(1) {
+                    out.push(self.segment(seg.ack, TCP_RST, None, &[]));
+                    return true;
+                }
+                log::debug!("TcpNat: connection {} -> {} established", self.guest, self.remote);
+                self.state = TcpState::Established;
+                self.snd_una = seg.ack;
+                self.retries = 0;
+            }
+
+            let acked = seg.ack.wrapping_sub(self.snd_una);
+            if acked > 0 && acked <= self.snd_max.wrapping_sub(self.snd_una) {
+                let data_acked = std::cmp::min(acked as usize, self.tx_buf.len());
+                self.tx_buf.drain(..data_acked);
+                // Only our FIN follows the data, so anything acknowledged beyond it is the FIN.
+                if self.host_eof && acked as usize > data_acked {
+                    self.fin_acked = true;
+                }
+                self.snd_una = seg.ack;
+                if seq_lt(self.snd_nxt, self.snd_una) {
+                    self.snd_nxt = self.snd_una;
+                }
+                self.rto_timer = 0.0;
+                self.retries = 0;
+            }
+            self.guest_window = seg.window;
+        }
+
+        if let TcpState::SynReceived = self.state {
+            return true;
+        }
+
+        let fin = seg.flags & TCP_FIN != 0;
+        if seg.payload.is_empty() && !fin {
+            return true;
+        }
+        let fits = self.to_host.len() + seg.payload.len() <= RCV_WINDOW;
+        if seg.seq == self.rcv_nxt && !self.guest_fin && fits {
+            self.to_host.extend_from_slice(seg.payload);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(seg.payload.len() as u32);
+            if fin {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.guest_fin = true;
+            }
+        }
+        // Acknowledge everything received so far; out of order or duplicate segments get a
+        // duplicate ACK.
+        out.push(self.ack());
+        true
+    }
+
+    /// Exchange data with the host socket. Returns false if the connection should be removed.
+    fn poll(&mut self, us: f64, out: &mut Vec<TcpOutput>) -> bool {
+        match &self.state {
+            TcpState::Connecting(rx) => match rx.try_recv() {
+                Ok(Ok(stream)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::warn!("TcpNat: failed to set socket nonblocking: {}", e);
+                        out.push(self.segment(0, TCP_RST | TCP_ACK, None, &[]));
+                        return false;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    self.stream = Some(stream);
+                    self.state = TcpState::SynReceived;
+                    out.push(self.syn_ack());
+                    self.set_snd_nxt(self.iss.wrapping_add(1));
+                    self.snd_una = self.iss;
+                    true
+                }
+                Ok(Err(e)) => {
+                    log::debug!("TcpNat: connection to {} failed: {}", self.remote, e);
+                    out.push(self.segment(0, TCP_RST | TCP_ACK, None, &[]));
+                    false
+                }
+                Err(TryRecvError::Empty) => true,
+                Err(TryRecvError::Disconnected) => false,
+            },
+            TcpState::SynReceived => {
+                self.rto_timer += us;
+                if self.rto_timer > RTO_US {
+                    self.rto_timer = 0.0;
+                    self.retries += 1;
+                    if self.retries > MAX_RETRIES {
+                        return false;
+                    }
+                    out.push(self.syn_ack());
+                }
+                true
+            }
+            TcpState::Established => self.poll_established(us, out),
+        }
+    }
+
+    fn poll_established(&mut self, us: f64, out: &mut Vec<TcpOutput>) -> bool {
+        // Write buffered guest data to the host.
+        let mut drained = false;
+        while !self.to_host.is_empty() {
+            match self.stream.as_mut().unwrap().write(&self.to_host) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.to_host.drain(..n);
+                    drained = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("TcpNat: write to {} failed: {}", self.remote, e);
+                    out.push(self.reset());
+                    return false;
+                }
+            }
+        }
+        if self.guest_fin && self.to_host.is_empty() && !self.host_shutdown {
+            let _ = self.stream.as_mut().unwrap().shutdown(Shutdown::Write);
+            self.host_shutdown = true;
+        }
+
+        // Read host data ahead of what the guest has acknowledged.
+        let mut buf = [0u8; 4096];
+        while !self.host_eof && self.tx_buf.len() < TX_BUFFER_MAX {
+            match self.stream.as_mut().unwrap().read(&mut buf) {
+                Ok(0) => {
+                    log::debug!("TcpNat: host closed connection to {}", self.remote);
+                    self.host_eof = true;
+                }
+                Ok(n) => self.tx_buf.extend(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("TcpNat: read from {} failed: {}", self.remote, e);
+                    out.push(self.reset());
+                    return false;
+                }
+            }
+        }
+
+        // Let the guest know if our window has reopened.
+        if drained && self.adv_window < MSS as usize {
+            out.push(self.ack());
+        }
+
+        // Retransmit from the oldest unacknowledged byte on timeout. If the guest's window is
+        // closed, this probes it with a single byte.
+        let unsent = self.tx_buf.len() > self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.snd_nxt != self.snd_una || (unsent && self.guest_window == 0) {
+            self.rto_timer += us;
+            if self.rto_timer > RTO_US {
+                self.rto_timer = 0.0;
+                self.retries += 1;
+                if self.retries > MAX_RETRIES {
+                    log::debug!("TcpNat: connection to {} timed out", self.remote);
+                    out.push(self.reset());
+                    return false;
+                }
+                self.snd_nxt = self.snd_una;
+                if !self.fin_acked {
+                    self.fin_sent = false;
+                }
+                if self.guest_window == 0 && !self.tx_buf.is_empty() {
+                    let probe = [self.tx_buf[0]];
+                    out.push(self.segment(self.snd_una, TCP_ACK, None, &probe));
+                    self.set_snd_nxt(self.snd_una.wrapping_add(1));
+                }
+            }
+        }
+        else {
+            self.rto_timer = 0.0;
+        }
+
+        // Send as much data as the guest's window allows.
+        let mss = std::cmp::min(self.guest_mss, MSS) as usize;
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let window = self.guest_window as usize;
+            if offset >= self.tx_buf.len() || offset >= window {
+                break;
+            }
+            let len = std::cmp::min(mss, std::cmp::min(self.tx_buf.len(), window) - offset);
+            let payload: Vec<u8> = self.tx_buf.range(offset..offset + len).copied().collect();
+            out.push(self.segment(self.snd_nxt, TCP_ACK | TCP_PSH, None, &payload));
+            self.set_snd_nxt(self.snd_nxt.wrapping_add(len as u32));
+        }
+
+        if self.host_eof && !self.fin_sent && self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.tx_buf.len() {
+            out.push(self.segment(self.snd_nxt, TCP_FIN | TCP_ACK, None, &[]));
+            self.set_snd_nxt(self.snd_nxt.wrapping_add(1));
+            self.fin_sent = true;
+        }
+
+        if self.fin_acked && self.guest_fin && self.to_host.is_empty() {
+            log::debug!("TcpNat: connection {} -> {} closed", self.guest, self.remote);
+            return false;
+        }
+        if self.host_eof || self.guest_fin {
+            self.idle_us += us;
+            if self.idle_us > CLOSE_TIMEOUT_US {
+                out.push(self.reset());
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub struct TcpNat {
+    conns:    HashMap<ConnKey, TcpConnection>,
+    next_iss: u32,
+}
+
+impl Default for TcpNat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TcpNat {
+    pub fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        Self {
+            conns:    HashMap::new(),
+            next_iss: seed,
+        }
+    }
+
+    /// Handle a TCP segment sent by the guest from `src` to `dst`.
+    pub fn handle_segment(&mut self, src: Ipv4Addr, dst: Ipv4Addr, seg: &TcpSegment, out: &mut Vec<TcpOutput>) {
+        let key = (
+            SocketAddrV4::new(src, seg.src_port),
+            SocketAddrV4::new(dst, seg.dst_port),
+        );
+
+        if seg.flags & (TCP_SYN | TCP_ACK) == TCP_SYN {
+            let is_retransmit = self
+                .conns
+                .get(&key)
+                .is_some_and(|conn| conn.rcv_nxt == seg.seq.wrapping_add(1));
+            if !is_retransmit {
+                self.open(key, seg, out);
+                return;
+            }
+        }
+
+        match self.conns.get_mut(&key) {
+            Some(conn) => {
+                if !conn.handle_segment(seg, out) {
+                    self.conns.remove(&key);
+                }
+            }
+            None => {
+                // No such connection; reset unless this is itself a reset.
+                if seg.flags & TCP_RST == 0 {
+                    let len = seg.payload.len() as u32 + (seg.flags & (TCP_SYN | TCP_FIN) != 0) as u32;
+                    let (seq, ack, flags) = if seg.flags & TCP_ACK != 0 {
+                        (seg.ack, 0, TCP_RST)
+                    }
+                    else {
+                        (0, seg.seq.wrapping_add(len), TCP_RST | TCP_ACK)
+                    };
+                    let segment = build_tcp(dst, seg.dst_port, src, seg.src_port, seq, ack, flags, 0, None, &[]);
+                    out.push((dst, src, segment));
+                }
+            }
+        }
+    }
+
+    /// Start a new connection for a SYN from the guest.
+    fn open(&mut self, key: ConnKey, seg: &TcpSegment, out: &mut Vec<TcpOutput>) {
+        let (guest, remote) = key;
+        let host_ip = match UserNetwork::host_addr(*remote.ip()) {
+            Some(host_ip) => host_ip,
+            None => {
+                let ack = seg.seq.wrapping_add(1);
+                let segment = build_tcp(
+                    *remote.ip(),
+                    remote.port(),
+                    *guest.ip(),
+                    guest.port(),
+                    0,
+                    ack,
+                    TCP_RST | TCP_ACK,
+                    0,
+                    None,
+                    &[],
+                );
+                out.push((*remote.ip(), *guest.ip(), segment));
+                return;
+            }
+        };
+
+        log::debug!("TcpNat: guest {} connecting to {}", guest, remote);
+        let (tx, rx) = mpsc::channel();
+        let host_addr = SocketAddr::V4(SocketAddrV4::new(host_ip, remote.port()));
+        std::thread::spawn(move || {
+            let _ = tx.send(TcpStream::connect_timeout(&host_addr, CONNECT_TIMEOUT));
+        });
+
+        let iss = self.next_iss;
+        self.next_iss = self.next_iss.wrapping_add(0x0001_0000);
+        self.conns.insert(
+            key,
+            TcpConnection {
+                guest,
+                remote,
+                state: TcpState::Connecting(rx),
+                stream: None,
+                iss,
+                snd_una: iss,
+                snd_nxt: iss,
+                snd_max: iss,
+                rcv_nxt: seg.seq.wrapping_add(1),
+                guest_window: seg.window,
+                guest_mss: seg.mss.unwrap_or(536),
+                tx_buf: VecDeque::new(),
+                to_host: Vec::new(),
+                adv_window: RCV_WINDOW,
+                host_eof: false,
+                host_shutdown: false,
+                fin_sent: false,
+                fin_acked: false,
+                guest_fin: false,
+                rto_timer: 0.0,
+                retries: 0,
+                idle_us: 0.0,
+            },
+        );
+    }
+
+    /// Run all connections for the specified number of microseconds.
+    pub fn poll(&mut self, us: f64, out: &mut Vec<TcpOutput>) {
+        self.conns.retain(|_, conn| conn.poll(us, out));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::user::{GATEWAY_ADDR, GUEST_ADDR};
+    use std::net::TcpListener;
+
+    const GUEST_PORT: u16 = 1025;
+
+    fn guest_segment(
+        nat: &mut TcpNat,
+        dst: Ipv4Addr,
+        dst_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<TcpOutput> {
+        let segment = build_tcp(
+            GUEST_ADDR,
+            GUEST_PORT,
+            dst,
+            dst_port,
+            seq,
+            ack,
+            flags,
+            4096,
+            Some(1460),
+            payload,
+        );
+        let mut out = Vec::new();
+        nat.handle_segment(GUEST_ADDR, dst, &TcpSegment::parse(&segment).unwrap(), &mut out);
+        out
+    }
+
+    /// Poll the NAT until it produces output, or give up after a few seconds.
+    fn poll_output(nat: &mut TcpNat) -> Vec<TcpOutput> {
+        let mut out = Vec::new();
+        for _ in 0..5000 {
+            nat.poll(1000.0, &mut out);
+            if !out.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        out
+    }
+
+    #[test]
+    fn test_unroutable_and_unknown_connections() {
+        let mut nat = TcpNat::new();
+
+        // A SYN to an address with no host equivalent is refused.
+        let out = guest_segment(&mut nat, Ipv4Addr::new(10, 0, 2, 50), 80, 1000, 0, TCP_SYN, &[]);
+        assert_eq!(out.len(), 1);
+        let rst = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!(rst.flags, TCP_RST | TCP_ACK);
+        assert_eq!(rst.ack, 1001);
+        assert_eq!((rst.src_port, rst.dst_port), (80, GUEST_PORT));
+
+        // A segment for an unknown connection is reset using its acknowledgement number.
+        let dst = Ipv4Addr::new(192, 168, 1, 1);
+        let out = guest_segment(&mut nat, dst, 80, 5000, 7000, TCP_ACK, b"abc");
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].0, dst);
+        let rst = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!(rst.flags, TCP_RST);
+        assert_eq!(rst.seq, 7000);
+
+        // ...but a reset is never answered.
+        assert!(guest_segment(&mut nat, dst, 80, 5000, 0, TCP_RST, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_connection_relay() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut nat = TcpNat::new();
+
+        // The handshake completes once the host connection is up.
+        assert!(guest_segment(&mut nat, GATEWAY_ADDR, port, 1000, 0, TCP_SYN, &[]).is_empty());
+        let (mut host, _) = listener.accept().unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let out = poll_output(&mut nat);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].0, out[0].1), (GATEWAY_ADDR, GUEST_ADDR));
+        let syn_ack = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!(syn_ack.flags, TCP_SYN | TCP_ACK);
+        assert_eq!(syn_ack.ack, 1001);
+        assert_eq!(syn_ack.mss, Some(MSS));
+        let mut ack = syn_ack.seq.wrapping_add(1);
+
+        // Guest to host
+        let out = guest_segment(&mut nat, GATEWAY_ADDR, port, 1001, ack, TCP_ACK | TCP_PSH, b"hello");
+        assert_eq!(out.len(), 1);
+        let seg = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!((seg.flags, seg.ack), (TCP_ACK, 1006));
+        nat.poll(1000.0, &mut Vec::new());
+        let mut buf = [0u8; 5];
+        host.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Host to guest
+        host.write_all(b"world").unwrap();
+        let out = poll_output(&mut nat);
+        assert_eq!(out.len(), 1);
+        let seg = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!(seg.seq, ack);
+        assert_eq!(seg.payload, b"world");
+        ack = ack.wrapping_add(5);
+        assert!(guest_segment(&mut nat, GATEWAY_ADDR, port, 1006, ack, TCP_ACK, &[]).is_empty());
+
+        // Host close is passed on to the guest as a FIN.
+        drop(host);
+        let out = poll_output(&mut nat);
+        assert_eq!(out.len(), 1);
+        let fin = TcpSegment::parse(&out[0].2).unwrap();
+        assert_eq!(fin.flags, TCP_FIN | TCP_ACK);
+        assert_eq!(fin.seq, ack);
+
+        // The connection is removed once both sides have closed and the FIN is acknowledged.
+        let out = guest_segment(
+            &mut nat,
+            GATEWAY_ADDR,
+            port,
+            1006,
+            ack.wrapping_add(1),
+            TCP_FIN | TCP_ACK,
+            &[],
+        );
+        assert_eq!(TcpSegment::parse(&out[0].2).unwrap().ack, 1007);
+        nat.poll(1000.0, &mut Vec::new());
+        assert!(nat.conns.is_empty());
+    }
+}
@@ -0,0 +1,793 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    network::user.rs
+
+    A user mode NAT network backend, in the style of QEMU's slirp.
+
+    The guest sits on a virtual 10.0.2.0/24 network behind a router at
+    10.0.2.2, which also answers DHCP. A DNS server at 10.0.2.3 resolves
+    names with the host's resolver. Traffic to other addresses is proxied
+    through host sockets: UDP datagrams are relayed, and TCP connections are
+    terminated here and re-created from the host (see tcp_nat.rs). The
+    router address itself maps to the host's loopback interface.
+
+    Only the gateway and DNS addresses answer pings, as relaying ICMP would
+    need raw sockets. There is no port forwarding, so the guest can't accept
+    inbound connections.
+
+*/
+
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+};
+
+use crate::network::{packet::*, tcp_nat::TcpNat, MacAddress, NetworkBackend, BROADCAST_MAC};
+
+pub const NETWORK_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 0);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+pub const GATEWAY_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+pub const DNS_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+pub const GUEST_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const GATEWAY_MAC: MacAddress = [0x52, 0x55, 0x0A, 0x00, 0x02, 0x02];
+
+// How often host sockets are polled, in microseconds.
+const SOCKET_POLL_US: f64 = 1000.0;
+// UDP mappings are closed after this long without traffic, in microseconds.
+const UDP_TIMEOUT_US: f64 = 60_000_000.0;
+// Maximum number of frames queued for the guest.
+const MAX_RX_QUEUE: usize = 256;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+const DHCP_OPTIONS_OFFSET: usize = 240;
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_INFORM: u8 = 8;
+const DHCP_LEASE_SECONDS: u32 = 86400;
+
+const DNS_PORT: u16 = 53;
+const DNS_TTL_SECONDS: u32 = 300;
+// Maximum number of DNS queries waiting on the resolver. Further queries are dropped, and the guest will retry.
+const MAX_DNS_PENDING: usize = 16;
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+struct UdpMapping {
+    socket:  UdpSocket,
+    idle_us: f64,
+}
+
+struct DnsQuery {
+    guest_port: u16,
+    query: Vec<u8>,
+    name: String,
+    qtype: u16,
+    question_end: usize,
+}
+
+/// Resolves DNS queries on a single worker thread, so that a slow lookup doesn't stall emulation
+/// and a burst of queries doesn't start a thread for each one.
+struct DnsResolver {
+    queries:   Sender<DnsQuery>,
+    responses: Receiver<(u16, Vec<u8>)>,
+    pending:   usize,
+}
+
+impl DnsResolver {
+    fn new() -> Self {
+        let (query_tx, query_rx) = mpsc::channel::<DnsQuery>();
+        let (response_tx, response_rx) = mpsc::channel();
+
+        // The thread exits when the resolver is dropped and the query channel closes.
+        std::thread::spawn(move || {
+            for query in query_rx {
+                let addrs = resolve_dns_query(&query.name, query.qtype);
+                log::debug!(
+                    "UserNetwork: DNS query for {} type {}: {:?}",
+                    query.name,
+                    query.qtype,
+                    addrs
+                );
+                let response = build_dns_response(&query.query, query.question_end, addrs.as_deref());
+                if response_tx.send((query.guest_port, response)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            queries:   query_tx,
+            responses: response_rx,
+            pending:   0,
+        }
+    }
+}
+
+pub struct UserNetwork {
+    guest_mac: MacAddress,
+    ip_id: u16,
+    poll_accum: f64,
+    rx_queue: VecDeque<Vec<u8>>,
+    udp: HashMap<u16, UdpMapping>,
+    dns: Option<DnsResolver>,
+    tcp: TcpNat,
+}
+
+impl Default for UserNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserNetwork {
+    pub fn new() -> Self {
+        Self {
+            guest_mac: BROADCAST_MAC,
+            ip_id: 0,
+            poll_accum: 0.0,
+            rx_queue: VecDeque::new(),
+            udp: HashMap::new(),
+            dns: None,
+            tcp: TcpNat::new(),
+        }
+    }
+
+    /// Translate an address on the virtual network to the host address it represents.
+    pub fn host_addr(addr: Ipv4Addr) -> Option<Ipv4Addr> {
+        if addr == GATEWAY_ADDR {
+            Some(Ipv4Addr::LOCALHOST)
+        }
+        else if Self::is_local(addr) || addr.is_broadcast() || addr.is_multicast() || addr.is_unspecified() {
+            None
+        }
+        else {
+            Some(addr)
+        }
+    }
+
+    /// Translate a host address to the address the guest sees.
+    pub fn guest_addr(addr: Ipv4Addr) -> Ipv4Addr {
+        if addr.is_loopback() {
+            GATEWAY_ADDR
+        }
+        else {
+            addr
+        }
+    }
+
+    fn is_local(addr: Ipv4Addr) -> bool {
+        u32::from(addr) & u32::from(NETMASK) == u32::from(NETWORK_ADDR)
+    }
+
+    fn queue_frame(&mut self, frame: Vec<u8>) {
+        if self.rx_queue.len() < MAX_RX_QUEUE {
+            self.rx_queue.push_back(frame);
+        }
+        else {
+            log::trace!("UserNetwork: receive queue full, dropping frame");
+        }
+    }
+
+    /// Queue an IPv4 packet for the guest.
+    fn queue_ipv4(&mut self, src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) {
+        self.ip_id = self.ip_id.wrapping_add(1);
+        let packet = build_ipv4(src, dst, protocol, self.ip_id, payload);
+        let dst_mac = if dst.is_broadcast() {
+            BROADCAST_MAC
+        }
+        else {
+            self.guest_mac
+        };
+        let frame = build_ethernet(dst_mac, GATEWAY_MAC, ETHERTYPE_IPV4, &packet);
+        self.queue_frame(frame);
+    }
+
+    fn queue_udp(&mut self, src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) {
+        let datagram = build_udp(*src.ip(), src.port(), *dst.ip(), dst.port(), payload);
+        self.queue_ipv4(*src.ip(), *dst.ip(), IP_PROTO_UDP, &datagram);
+    }
+
+    fn handle_arp(&mut self, arp: &[u8]) {
+        if arp.len() < 28 || read_u16(arp, 0) != 1 || read_u16(arp, 2) != ETHERTYPE_IPV4 {
+            return;
+        }
+        if read_u16(arp, 6) != ARP_REQUEST {
+            return;
+        }
+        let sender_mac: MacAddress = arp[8..14].try_into().unwrap();
+        let sender_ip = read_ipv4(arp, 14);
+        let target_ip = read_ipv4(arp, 24);
+
+        // Answer for every address on the virtual network except the guest's own, so that
+        // gratuitous ARPs and address conflict checks go unanswered.
+        if !Self::is_local(target_ip) || target_ip == sender_ip || target_ip == GUEST_ADDR {
+            return;
+        }
+
+        let mut reply = Vec::with_capacity(28);
+        reply.extend_from_slice(&arp[0..6]);
+        reply.extend_from_slice(&ARP_REPLY.to_be_bytes());
+        reply.extend_from_slice(&GATEWAY_MAC);
+        reply.extend_from_slice(&target_ip.octets());
+        reply.extend_from_slice(&sender_mac);
+        reply.extend_from_slice(&sender_ip.octets());
+        let frame = build_ethernet(sender_mac, GATEWAY_MAC, ETHERTYPE_ARP, &reply);
+        self.queue_frame(frame);
+    }
+
+    fn handle_ipv4(&mut self, ip: &Ipv4Packet) {
+        match ip.protocol {
+            IP_PROTO_ICMP => self.handle_icmp(ip),
+            IP_PROTO_UDP => {
+                if let Some(udp) = UdpDatagram::parse(ip.payload) {
+                    self.handle_udp(ip, &udp);
+                }
+            }
+            IP_PROTO_TCP => {
+                if let Some(tcp) = TcpSegment::parse(ip.payload) {
+                    let mut out = Vec::new();
+                    self.tcp.handle_segment(ip.src, ip.dst, &tcp, &mut out);
+                    for (src, dst, segment) in out {
+                        self.queue_ipv4(src, dst, IP_PROTO_TCP, &segment);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_icmp(&mut self, ip: &Ipv4Packet) {
+        let icmp = ip.payload;
+        if icmp.len() < 8 || icmp[0] != ICMP_ECHO_REQUEST {
+            return;
+        }
+        if ip.dst != GATEWAY_ADDR && ip.dst != DNS_ADDR {
+            return;
+        }
+        let mut reply = icmp.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(&reply, 0);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        self.queue_ipv4(ip.dst, ip.src, IP_PROTO_ICMP, &reply);
+    }
+
+    fn handle_udp(&mut self, ip: &Ipv4Packet, udp: &UdpDatagram) {
+        if udp.dst_port == DHCP_SERVER_PORT && (ip.dst.is_broadcast() || ip.dst == GATEWAY_ADDR) {
+            self.handle_dhcp(udp.payload);
+            return;
+        }
+        if ip.dst == DNS_ADDR {
+            if udp.dst_port == DNS_PORT {
+                self.handle_dns(udp.src_port, udp.payload);
+            }
+            return;
+        }
+
+        let host_ip = match Self::host_addr(ip.dst) {
+            Some(host_ip) => host_ip,
+            None => return,
+        };
+
+        let mapping = match self.udp.entry(udp.src_port) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|s| {
+                    s.set_nonblocking(true)?;
+                    Ok(s)
+                }) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        log::warn!("UserNetwork: Failed to open UDP socket: {}", e);
+                        return;
+                    }
+                };
+                entry.insert(UdpMapping { socket, idle_us: 0.0 })
+            }
+        };
+        mapping.idle_us = 0.0;
+        if let Err(e) = mapping.socket.send_to(udp.payload, (host_ip, udp.dst_port)) {
+            log::debug!("UserNetwork: UDP send to {}:{} failed: {}", host_ip, udp.dst_port, e);
+        }
+    }
+
+    fn handle_dhcp(&mut self, bootp: &[u8]) {
+        if bootp.len() < DHCP_OPTIONS_OFFSET || bootp[0] != 1 || bootp[236..240] != DHCP_MAGIC_COOKIE {
+            return;
+        }
+
+        // Find the message type option
+        let mut msg_type = None;
+        let mut i = DHCP_OPTIONS_OFFSET;
+        while i < bootp.len() {
+            match bootp[i] {
+                0 => i += 1,
+                255 => break,
+                option => {
+                    let len = match bootp.get(i + 1) {
+                        Some(len) => *len as usize,
+                        None => break,
+                    };
+                    if option == 53 && len == 1 {
+                        msg_type = bootp.get(i + 2).copied();
+                    }
+                    i += 2 + len;
+                }
+            }
+        }
+
+        let reply_type = match msg_type {
+            Some(DHCP_DISCOVER) => DHCP_OFFER,
+            Some(DHCP_REQUEST) | Some(DHCP_INFORM) => DHCP_ACK,
+            _ => return,
+        };
+        log::debug!(
+            "UserNetwork: DHCP message type {:?}, replying with {}",
+            msg_type,
+            reply_type
+        );
+
+        // Learn the guest's hardware address from the request.
+        let chaddr: MacAddress = bootp[28..34].try_into().unwrap();
+        self.guest_mac = chaddr;
+
+        let mut reply = vec![0u8; DHCP_OPTIONS_OFFSET];
+        reply[0] = 2; // BOOTREPLY
+        reply[1] = 1; // Ethernet
+        reply[2] = 6; // Hardware address length
+        reply[4..8].copy_from_slice(&bootp[4..8]); // Transaction ID
+        reply[10..12].copy_from_slice(&bootp[10..12]); // Flags
+        if reply_type != DHCP_ACK || msg_type != Some(DHCP_INFORM) {
+            reply[16..20].copy_from_slice(&GUEST_ADDR.octets());
+        }
+        reply[20..24].copy_from_slice(&GATEWAY_ADDR.octets());
+        reply[28..44].copy_from_slice(&bootp[28..44]);
+        reply[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+        reply.extend_from_slice(&[53, 1, reply_type]);
+        reply.extend_from_slice(&[54, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDR.octets());
+        reply.extend_from_slice(&[51, 4]);
+        reply.extend_from_slice(&DHCP_LEASE_SECONDS.to_be_bytes());
+        reply.extend_from_slice(&[1, 4]);
+        reply.extend_from_slice(&NETMASK.octets());
+        reply.extend_from_slice(&[3, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDR.octets());
+        reply.extend_from_slice(&[6, 4]);
+        reply.extend_from_slice(&DNS_ADDR.octets());
+        reply.push(255);
+        // Pad to the minimum BOOTP message size
+        if reply.len() < 300 {
+            reply.resize(300, 0);
+        }
+
+        self.queue_udp(
+            SocketAddrV4::new(GATEWAY_ADDR, DHCP_SERVER_PORT),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT),
+            &reply,
+        );
+    }
+
+    fn handle_dns(&mut self, guest_port: u16, query: &[u8]) {
+        let (name, qtype, question_end) = match parse_dns_question(query) {
+            Some(question) => question,
+            None => return,
+        };
+
+        let resolver = self.dns.get_or_insert_with(DnsResolver::new);
+        if resolver.pending >= MAX_DNS_PENDING {
+            log::debug!("UserNetwork: Too many DNS queries pending, dropping query for {}", name);
+            return;
+        }
+        let query = DnsQuery {
+            guest_port,
+            query: query.to_vec(),
+            name,
+            qtype,
+            question_end,
+        };
+        if resolver.queries.send(query).is_ok() {
+            resolver.pending += 1;
+        }
+        else {
+            // The resolver thread has gone away. Start a new one on the next query.
+            self.dns = None;
+        }
+    }
+
+    fn poll_sockets(&mut self, us: f64) {
+        // Relay UDP replies
+        let mut buf = [0u8; 2048];
+        let mut replies = Vec::new();
+        self.udp.retain(|guest_port, mapping| {
+            loop {
+                match mapping.socket.recv_from(&mut buf) {
+                    Ok((len, SocketAddr::V4(addr))) => {
+                        mapping.idle_us = 0.0;
+                        replies.push((*guest_port, addr, buf[..len].to_vec()));
+                    }
+                    Ok(_) => {}
+                    // Either WouldBlock, or an ICMP error reported by the host; the guest will time out.
+                    Err(_) => break,
+                }
+            }
+            mapping.idle_us += us;
+            mapping.idle_us < UDP_TIMEOUT_US
+        });
+        for (guest_port, addr, payload) in replies {
+            let src = SocketAddrV4::new(Self::guest_addr(*addr.ip()), addr.port());
+            self.queue_udp(src, SocketAddrV4::new(GUEST_ADDR, guest_port), &payload);
+        }
+
+        // Deliver DNS responses
+        let mut responses = Vec::new();
+        if let Some(resolver) = &mut self.dns {
+            loop {
+                match resolver.responses.try_recv() {
+                    Ok(response) => {
+                        resolver.pending -= 1;
+                        responses.push(response);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.dns = None;
+                        break;
+                    }
+                }
+            }
+        }
+        for (guest_port, response) in responses {
+            self.queue_udp(
+                SocketAddrV4::new(DNS_ADDR, DNS_PORT),
+                SocketAddrV4::new(GUEST_ADDR, guest_port),
+                &response,
+            );
+        }
+
+        // Run TCP connections
+        let mut out = Vec::new();
+        self.tcp.poll(us, &mut out);
+        for (src, dst, segment) in out {
+            self.queue_ipv4(src, dst, IP_PROTO_TCP, &segment);
+        }
+    }
+}
+
+impl NetworkBackend for UserNetwork {
+    fn send(&mut self, frame: &[u8]) {
+        let eth = match EthernetFrame::parse(frame) {
+            Some(eth) => eth,
+            None => return,
+        };
+        if eth.dst != GATEWAY_MAC && eth.dst != BROADCAST_MAC {
+            return;
+        }
+        match eth.ethertype {
+            ETHERTYPE_ARP => {
+                self.guest_mac = eth.src;
+                self.handle_arp(eth.payload);
+            }
+            ETHERTYPE_IPV4 => {
+                self.guest_mac = eth.src;
+                if let Some(ip) = Ipv4Packet::parse(eth.payload) {
+                    self.handle_ipv4(&ip);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(&mut self, us: f64) -> Option<Vec<u8>> {
+        self.poll_accum += us;
+        if self.poll_accum >= SOCKET_POLL_US {
+            let elapsed = self.poll_accum;
+            self.poll_accum = 0.0;
+            self.poll_sockets(elapsed);
+        }
+        self.rx_queue.pop_front()
+    }
+}
+
+/// Look up the addresses for a DNS query on the host. Returns None if the name could not be resolved.
+fn resolve_dns_query(name: &str, qtype: u16) -> Option<Vec<Ipv4Addr>> {
+    if qtype != 1 {
+        // Only A records are supported. Other queries get an empty answer.
+        return Some(Vec::new());
+    }
+    match (name, 0).to_socket_addrs() {
+        Ok(addrs) => Some(
+            addrs
+                .filter_map(|addr| match addr {
+                    SocketAddr::V4(addr) => Some(*addr.ip()),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        Err(_) => None,
+    }
+}
+
+/// Parse the question of a standard DNS query. Returns the name, query type, and the offset of
+/// the end of the question.
+fn parse_dns_question(query: &[u8]) -> Option<(String, u16, usize)> {
+    if query.len() < 12 || query[2] & 0x80 != 0 || read_u16(query, 4) != 1 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut i = 12;
+    loop {
+        let len = *query.get(i)? as usize;
+        i += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xC0 != 0 {
+            // Compression isn't used in questions
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(query.get(i..i + len)?).to_string());
+        i += len;
+    }
+    let qtype = read_u16(query.get(i..i + 4)?, 0);
+    Some((labels.join("."), qtype, i + 4))
+}
+
+/// Build a response to a DNS query from the resolved addresses. `addrs` is None if the name
+/// could not be resolved.
+fn build_dns_response(query: &[u8], question_end: usize, addrs: Option<&[Ipv4Addr]>) -> Vec<u8> {
+    let rcode = if addrs.is_some() { 0 } else { 3 }; // NXDOMAIN
+    let mut response = Vec::with_capacity(question_end + 16 * addrs.map_or(0, |a| a.len()));
+    response.extend_from_slice(&query[0..2]); // ID
+    response.push(0x80 | (query[2] & 0x79)); // Response, with the query's opcode and RD bits
+    response.push(0x80 | rcode); // Recursion available
+    response.extend_from_slice(&1u16.to_be_bytes()); // Questions
+    response.extend_from_slice(&(addrs.map_or(0, |a| a.len()) as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]); // Authority and additional records
+    response.extend_from_slice(&query[12..question_end]);
+    for addr in addrs.unwrap_or(&[]) {
+        response.extend_from_slice(&[0xC0, 0x0C]); // Pointer to the name in the question
+        response.extend_from_slice(&1u16.to_be_bytes()); // A
+        response.extend_from_slice(&1u16.to_be_bytes()); // IN
+        response.extend_from_slice(&DNS_TTL_SECONDS.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&addr.octets());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_GUEST_MAC: MacAddress = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+    fn udp_frame(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let datagram = build_udp(src, src_port, dst, dst_port, payload);
+        let packet = build_ipv4(src, dst, IP_PROTO_UDP, 1, &datagram);
+        build_ethernet(BROADCAST_MAC, TEST_GUEST_MAC, ETHERTYPE_IPV4, &packet)
+    }
+
+    fn arp_request(target_ip: Ipv4Addr) -> Vec<u8> {
+        let mut arp = Vec::new();
+        arp.extend_from_slice(&1u16.to_be_bytes()); // Ethernet
+        arp.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        arp.extend_from_slice(&[6, 4]);
+        arp.extend_from_slice(&ARP_REQUEST.to_be_bytes());
+        arp.extend_from_slice(&TEST_GUEST_MAC);
+        arp.extend_from_slice(&GUEST_ADDR.octets());
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&target_ip.octets());
+        build_ethernet(BROADCAST_MAC, TEST_GUEST_MAC, ETHERTYPE_ARP, &arp)
+    }
+
+    fn dhcp_message(msg_type: u8, xid: u32) -> Vec<u8> {
+        let mut bootp = vec![0u8; DHCP_OPTIONS_OFFSET];
+        bootp[0] = 1; // BOOTREQUEST
+        bootp[1] = 1;
+        bootp[2] = 6;
+        bootp[4..8].copy_from_slice(&xid.to_be_bytes());
+        bootp[28..34].copy_from_slice(&TEST_GUEST_MAC);
+        bootp[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        bootp.extend_from_slice(&[53, 1, msg_type, 255]);
+        bootp
+    }
+
+    /// Take the next UDP datagram queued for the guest, returning the IP source and destination,
+    /// the ports and the payload.
+    fn take_udp(net: &mut UserNetwork) -> (MacAddress, Ipv4Addr, Ipv4Addr, u16, u16, Vec<u8>) {
+        let frame = net.poll(0.0).expect("no frame for guest");
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(eth.src, GATEWAY_MAC);
+        assert_eq!(eth.ethertype, ETHERTYPE_IPV4);
+        let ip = Ipv4Packet::parse(eth.payload).unwrap();
+        assert_eq!(ip.protocol, IP_PROTO_UDP);
+        let udp = UdpDatagram::parse(ip.payload).unwrap();
+        (
+            eth.dst,
+            ip.src,
+            ip.dst,
+            udp.src_port,
+            udp.dst_port,
+            udp.payload.to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_arp_reply() {
+        let mut net = UserNetwork::new();
+
+        net.send(&arp_request(GATEWAY_ADDR));
+        let frame = net.poll(0.0).unwrap();
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, TEST_GUEST_MAC);
+        assert_eq!(eth.src, GATEWAY_MAC);
+        assert_eq!(eth.ethertype, ETHERTYPE_ARP);
+
+        let arp = eth.payload;
+        assert_eq!(read_u16(arp, 6), ARP_REPLY);
+        assert_eq!(arp[8..14], GATEWAY_MAC);
+        assert_eq!(read_ipv4(arp, 14), GATEWAY_ADDR);
+        assert_eq!(arp[18..24], TEST_GUEST_MAC);
+        assert_eq!(read_ipv4(arp, 24), GUEST_ADDR);
+
+        // The guest's own address and addresses off the virtual network go unanswered.
+        net.send(&arp_request(GUEST_ADDR));
+        net.send(&arp_request(Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(net.poll(0.0).is_none());
+    }
+
+    #[test]
+    fn test_dhcp_offer_ack() {
+        let mut net = UserNetwork::new();
+
+        for (request, reply) in [(DHCP_DISCOVER, DHCP_OFFER), (DHCP_REQUEST, DHCP_ACK)] {
+            let discover = dhcp_message(request, 0xDEADBEEF);
+            net.send(&udp_frame(
+                Ipv4Addr::UNSPECIFIED,
+                DHCP_CLIENT_PORT,
+                Ipv4Addr::BROADCAST,
+                DHCP_SERVER_PORT,
+                &discover,
+            ));
+
+            let (dst_mac, src, dst, src_port, dst_port, bootp) = take_udp(&mut net);
+            assert_eq!(dst_mac, BROADCAST_MAC);
+            assert_eq!((src, src_port), (GATEWAY_ADDR, DHCP_SERVER_PORT));
+            assert_eq!((dst, dst_port), (Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT));
+
+            assert_eq!(bootp[0], 2); // BOOTREPLY
+            assert_eq!(bootp[4..8], 0xDEADBEEFu32.to_be_bytes());
+            assert_eq!(read_ipv4(&bootp, 16), GUEST_ADDR);
+            assert_eq!(read_ipv4(&bootp, 20), GATEWAY_ADDR);
+            assert_eq!(bootp[28..34], TEST_GUEST_MAC);
+            assert_eq!(bootp[236..240], DHCP_MAGIC_COOKIE);
+            assert_eq!(bootp[240..243], [53, 1, reply]);
+
+            // Server ID, lease time, netmask, router and DNS server follow the message type.
+            let options = &bootp[243..];
+            assert_eq!(options[0..6], [54, 4, 10, 0, 2, 2]);
+            assert_eq!(options[6..8], [51, 4]);
+            assert_eq!(read_u32(options, 8), DHCP_LEASE_SECONDS);
+            assert_eq!(options[12..18], [1, 4, 255, 255, 255, 0]);
+            assert_eq!(options[18..24], [3, 4, 10, 0, 2, 2]);
+            assert_eq!(options[24..31], [6, 4, 10, 0, 2, 3, 255]);
+            assert_eq!(bootp.len(), 300);
+        }
+        assert_eq!(net.guest_mac, TEST_GUEST_MAC);
+
+        // An INFORM is acknowledged without assigning an address.
+        net.send(&udp_frame(
+            GUEST_ADDR,
+            DHCP_CLIENT_PORT,
+            GATEWAY_ADDR,
+            DHCP_SERVER_PORT,
+            &dhcp_message(DHCP_INFORM, 1),
+        ));
+        let (_, _, _, _, _, bootp) = take_udp(&mut net);
+        assert_eq!(read_ipv4(&bootp, 16), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(bootp[240..243], [53, 1, DHCP_ACK]);
+    }
+
+    #[test]
+    fn test_dns_response() {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x07example\x03com\x00");
+        query.extend_from_slice(&[0, 1, 0, 1]); // A, IN
+
+        let (name, qtype, question_end) = parse_dns_question(&query).unwrap();
+        assert_eq!(name, "example.com");
+        assert_eq!(qtype, 1);
+        assert_eq!(question_end, query.len());
+
+        let addr = Ipv4Addr::new(93, 184, 216, 34);
+        let response = build_dns_response(&query, question_end, Some(&[addr]));
+        assert_eq!(response[0..2], [0x12, 0x34]);
+        assert_eq!(response[2..4], [0x81, 0x80]); // Response, recursion desired and available
+        assert_eq!(read_u16(&response, 4), 1); // Questions
+        assert_eq!(read_u16(&response, 6), 1); // Answers
+        assert_eq!(response[12..question_end], query[12..]);
+        let answer = &response[question_end..];
+        assert_eq!(answer[0..6], [0xC0, 0x0C, 0, 1, 0, 1]);
+        assert_eq!(read_u32(answer, 6), DNS_TTL_SECONDS);
+        assert_eq!(read_u16(answer, 10), 4);
+        assert_eq!(read_ipv4(answer, 12), addr);
+        assert_eq!(answer.len(), 16);
+
+        // An unresolved name gets NXDOMAIN with no answers.
+        let response = build_dns_response(&query, question_end, None);
+        assert_eq!(response[3] & 0x0F, 3);
+        assert_eq!(read_u16(&response, 6), 0);
+        assert_eq!(response.len(), question_end);
+
+        // Responses and truncated queries are not parsed as questions.
+        let mut not_query = query.clone();
+        not_query[2] |= 0x80;
+        assert!(parse_dns_question(&not_query).is_none());
+        assert!(parse_dns_question(&query[..query.len() - 2]).is_none());
+    }
+
+    #[test]
+    fn test_ping_gateway() {
+        let mut net = UserNetwork::new();
+        let mut echo = vec![
+            ICMP_ECHO_REQUEST,
+            0,
+            0,
+            0,
+            0x00,
+            0x01,
+            0x00,
+            0x02,
+            b'p',
+            b'i',
+            b'n',
+            b'g',
+        ];
+        let sum = checksum(&echo, 0);
+        echo[2..4].copy_from_slice(&sum.to_be_bytes());
+        let packet = build_ipv4(GUEST_ADDR, GATEWAY_ADDR, IP_PROTO_ICMP, 1, &echo);
+        net.send(&build_ethernet(GATEWAY_MAC, TEST_GUEST_MAC, ETHERTYPE_IPV4, &packet));
+
+        let frame = net.poll(0.0).unwrap();
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, TEST_GUEST_MAC);
+        let ip = Ipv4Packet::parse(eth.payload).unwrap();
+        assert_eq!((ip.src, ip.dst, ip.protocol), (GATEWAY_ADDR, GUEST_ADDR, IP_PROTO_ICMP));
+        assert_eq!(ip.payload[0], ICMP_ECHO_REPLY);
+        assert_eq!(ip.payload[4..], echo[4..]);
+        assert_eq!(checksum(ip.payload, 0), 0);
+    }
+}
@@ -0,0 +1,521 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    replay.rs
+
+    Input recording and playback for deterministic replays. Given the same
+    starting state, the machine is deterministic; everything that varies from
+    run to run comes from the host. A recording captures the starting state
+    and every host input with the CPU cycle at which it reached the machine,
+    so that playback can apply each input at exactly the same point in the
+    emulation and reproduce the session bit for bit.
+
+    A recording starts with a reset followed by a full save state, so that
+    devices a save state does not cover also begin from a known state. The
+    disks in the floppy drives are stored with it. A hash of the machine
+    state at the end of the recording lets playback detect a desync.
+
+*/
+
+use std::str::FromStr;
+
+use crate::{
+    bus::ClockFactor,
+    devices::{fdc::FDC_MAX_DRIVES, keyboard::KeyboardModifiers},
+    keys::MartyKey,
+    machine::KeybufferEntry,
+    machine_types::BootDevice,
+    savestate::{SaveStateError, StateReader, StateWriter},
+};
+
+pub const REPLAY_MAGIC: &[u8; 8] = b"MARTYREC";
+/// Incremented whenever the layout of a recording changes.
+pub const REPLAY_VERSION: u16 = 1;
+
+/// A disk image in a floppy drive.
+#[derive(Clone)]
+pub struct FloppyMedia {
+    pub drive: usize,
+    pub data: Vec<u8>,
+    pub write_protect: bool,
+}
+
+/// An input from the host. Keyboard input is recorded when a key event is delivered to the
+/// keyboard, rather than when the host key is pressed, as delivery is paced by host frames.
+#[derive(Clone)]
+pub enum InputEvent {
+    Key(KeybufferEntry),
+    CtrlAltDel,
+    Mouse { l_button: bool, r_button: bool, delta_x: f64, delta_y: f64 },
+    JoystickButton { controller: usize, button: usize, pressed: bool },
+    JoystickPosition { controller: usize, stick: usize, x: Option<f64>, y: Option<f64> },
+    LoadFloppy(FloppyMedia),
+    EjectFloppy(usize),
+    TurboButton(bool),
+    ClockFactor(ClockFactor),
+    Reset,
+}
+
+/// An input and the CPU cycle count at which it was applied.
+#[derive(Clone)]
+pub struct TimedInput {
+    pub cycle: u64,
+    pub event: InputEvent,
+}
+
+pub struct Recording {
+    pub machine: String,
+    pub cpu: String,
+    pub boot_device: BootDevice,
+    /// A save state of the machine at the start of the recording.
+    pub snapshot: Vec<u8>,
+    pub media: Vec<FloppyMedia>,
+    pub events: Vec<TimedInput>,
+    pub end_cycle: u64,
+    /// A hash of the save state of the machine at the end of the recording.
+    pub end_hash: u64,
+}
+
+impl Recording {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(REPLAY_MAGIC);
+        w.write_u16(REPLAY_VERSION);
+        w.write_str(&self.machine);
+        w.write_str(&self.cpu);
+        w.write_u8(match self.boot_device {
+            BootDevice::FloppyA => 0,
+            BootDevice::FloppyB => 1,
+            BootDevice::HardDisk => 2,
+        });
+        w.write_bytes(&self.snapshot);
+
+        w.write_usize(self.media.len());
+        for media in &self.media {
+            write_media(&mut w, media);
+        }
+
+        w.write_usize(self.events.len());
+        for input in &self.events {
+            w.write_u64(input.cycle);
+            write_event(&mut w, &input.event);
+        }
+
+        w.write_u64(self.end_cycle);
+        w.write_u64(self.end_hash);
+        w.into_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SaveStateError> {
+        let mut r = StateReader::new(data);
+        if r.read_bytes().ok() != Some(&REPLAY_MAGIC[..]) {
+            return Err(SaveStateError::BadHeader);
+        }
+        let version = r.read_u16()?;
+        if version != REPLAY_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let machine = r.read_str()?;
+        let cpu = r.read_str()?;
+        let boot_device = match r.read_u8()? {
+            0 => BootDevice::FloppyA,
+            1 => BootDevice::FloppyB,
+            2 => BootDevice::HardDisk,
+            _ => return Err(SaveStateError::InvalidValue("boot device")),
+        };
+        let snapshot = r.read_bytes()?.to_vec();
+
+        let media_ct = r.read_usize()?;
+        let mut media = Vec::new();
+        for _ in 0..media_ct {
+            media.push(read_media(&mut r)?);
+        }
+
+        let event_ct = r.read_usize()?;
+        let mut events = Vec::new();
+        for _ in 0..event_ct {
+            let cycle = r.read_u64()?;
+            let event = read_event(&mut r)?;
+            events.push(TimedInput { cycle, event });
+        }
+
+        let end_cycle = r.read_u64()?;
+        let end_hash = r.read_u64()?;
+        if !r.is_empty() {
+            return Err(SaveStateError::SizeMismatch("recording"));
+        }
+
+        Ok(Recording {
+            machine,
+            cpu,
+            boot_device,
+            snapshot,
+            media,
+            events,
+            end_cycle,
+            end_hash,
+        })
+    }
+}
+
+fn write_media(w: &mut StateWriter, media: &FloppyMedia) {
+    w.write_usize(media.drive);
+    w.write_bytes(&media.data);
+    w.write_bool(media.write_protect);
+}
+
+fn read_drive(r: &mut StateReader) -> Result<usize, SaveStateError> {
+    let drive = r.read_usize()?;
+    if drive >= FDC_MAX_DRIVES {
+        return Err(SaveStateError::InvalidValue("floppy drive"));
+    }
+    Ok(drive)
+}
+
+fn read_media(r: &mut StateReader) -> Result<FloppyMedia, SaveStateError> {
+    Ok(FloppyMedia {
+        drive: read_drive(r)?,
+        data: r.read_bytes()?.to_vec(),
+        write_protect: r.read_bool()?,
+    })
+}
+
+fn write_option_f64(w: &mut StateWriter, v: Option<f64>) {
+    w.write_bool(v.is_some());
+    w.write_f64(v.unwrap_or_default());
+}
+
+fn read_option_f64(r: &mut StateReader) -> Result<Option<f64>, SaveStateError> {
+    let present = r.read_bool()?;
+    let v = r.read_f64()?;
+    Ok(present.then_some(v))
+}
+
+fn write_event(w: &mut StateWriter, event: &InputEvent) {
+    match event {
+        InputEvent::Key(entry) => {
+            w.write_u8(0);
+            // Keys are stored by name, so that recordings survive changes to the order of MartyKey.
+            w.write_str(&format!("{:?}", entry.keycode));
+            w.write_bool(entry.pressed);
+            w.write_bool(entry.modifiers.control);
+            w.write_bool(entry.modifiers.alt);
+            w.write_bool(entry.modifiers.shift);
+            w.write_bool(entry.modifiers.meta);
+            w.write_bool(entry.translate);
+        }
+        InputEvent::CtrlAltDel => w.write_u8(1),
+        InputEvent::Mouse {
+            l_button,
+            r_button,
+            delta_x,
+            delta_y,
+        } => {
+            w.write_u8(2);
+            w.write_bool(*l_button);
+            w.write_bool(*r_button);
+            w.write_f64(*delta_x);
+            w.write_f64(*delta_y);
+        }
+        InputEvent::JoystickButton {
+            controller,
+            button,
+            pressed,
+        } => {
+            w.write_u8(3);
+            w.write_usize(*controller);
+            w.write_usize(*button);
+            w.write_bool(*pressed);
+        }
+        InputEvent::JoystickPosition {
+            controller,
+            stick,
+            x,
+            y,
+        } => {
+            w.write_u8(4);
+            w.write_usize(*controller);
+            w.write_usize(*stick);
+            write_option_f64(w, *x);
+            write_option_f64(w, *y);
+        }
+        InputEvent::LoadFloppy(media) => {
+            w.write_u8(5);
+            write_media(w, media);
+        }
+        InputEvent::EjectFloppy(drive) => {
+            w.write_u8(6);
+            w.write_usize(*drive);
+        }
+        InputEvent::TurboButton(state) => {
+            w.write_u8(7);
+            w.write_bool(*state);
+        }
+        InputEvent::ClockFactor(factor) => {
+            w.write_u8(8);
+            match factor {
+                ClockFactor::Divisor(n) => {
+                    w.write_u8(0);
+                    w.write_u8(*n);
+                }
+                ClockFactor::Multiplier(n) => {
+                    w.write_u8(1);
+                    w.write_u8(*n);
+                }
+            }
+        }
+        InputEvent::Reset => w.write_u8(9),
+    }
+}
+
+fn read_event(r: &mut StateReader) -> Result<InputEvent, SaveStateError> {
+    let event = match r.read_u8()? {
+        0 => {
+            let keycode = MartyKey::from_str(&r.read_str()?).map_err(|_| SaveStateError::InvalidValue("keycode"))?;
+            let pressed = r.read_bool()?;
+            let modifiers = KeyboardModifiers {
+                control: r.read_bool()?,
+                alt: r.read_bool()?,
+                shift: r.read_bool()?,
+                meta: r.read_bool()?,
+            };
+            InputEvent::Key(KeybufferEntry {
+                keycode,
+                pressed,
+                modifiers,
+                translate: r.read_bool()?,
+            })
+        }
+        1 => InputEvent::CtrlAltDel,
+        2 => InputEvent::Mouse {
+            l_button: r.read_bool()?,
+            r_button: r.read_bool()?,
+            delta_x:  r.read_f64()?,
+            delta_y:  r.read_f64()?,
+        },
+        3 => InputEvent::JoystickButton {
+            controller: r.read_usize()?,
+            button: r.read_usize()?,
+            pressed: r.read_bool()?,
+        },
+        4 => InputEvent::JoystickPosition {
+            controller: r.read_usize()?,
+            stick: r.read_usize()?,
+            x: read_option_f64(r)?,
+            y: read_option_f64(r)?,
+        },
+        5 => InputEvent::LoadFloppy(read_media(r)?),
+        6 => InputEvent::EjectFloppy(read_drive(r)?),
+        7 => InputEvent::TurboButton(r.read_bool()?),
+        8 => {
+            let kind = r.read_u8()?;
+            let n = r.read_u8()?;
+            match kind {
+                0 => InputEvent::ClockFactor(ClockFactor::Divisor(n)),
+                1 => InputEvent::ClockFactor(ClockFactor::Multiplier(n)),
+                _ => return Err(SaveStateError::InvalidValue("clock factor")),
+            }
+        }
+        9 => InputEvent::Reset,
+        _ => return Err(SaveStateError::InvalidValue("input event")),
+    };
+    Ok(event)
+}
+
+/// A recording being played back.
+pub struct Playback {
+    pub recording: Recording,
+    /// The cycle count restored from the recording's snapshot, where playback begins.
+    pub start_cycle: u64,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(recording: Recording, start_cycle: u64) -> Self {
+        Playback {
+            recording,
+            start_cycle,
+            next: 0,
+        }
+    }
+
+    /// Take the next input due at or before the specified cycle, other than a key event.
+    pub fn next_input(&mut self, cycle: u64) -> Option<InputEvent> {
+        while let Some(input) = self.recording.events.get(self.next) {
+            if input.cycle > cycle {
+                return None;
+            }
+            self.next += 1;
+            match &input.event {
+                InputEvent::Key(_) => {
+                    // Key events are taken by next_key() as the keyboard runs, so one left here
+                    // means playback has already diverged from the recording.
+                    log::warn!("Replay desync: key event at cycle {} was not delivered.", input.cycle);
+                }
+                event => return Some(event.clone()),
+            }
+        }
+        None
+    }
+
+    /// Take the next key event, if it is due at or before the specified cycle.
+    pub fn next_key(&mut self, cycle: u64) -> Option<KeybufferEntry> {
+        match self.recording.events.get(self.next) {
+            Some(TimedInput {
+                cycle: key_cycle,
+                event: InputEvent::Key(entry),
+            }) if *key_cycle <= cycle => {
+                self.next += 1;
+                Some(*entry)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true once every input has been applied and the end of the recording is reached.
+    pub fn is_finished(&self, cycle: u64) -> bool {
+        self.next >= self.recording.events.len() && cycle >= self.recording.end_cycle
+    }
+}
+
+pub enum Replay {
+    Recording(Recording),
+    Playing(Playback),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReplayStatus {
+    Idle,
+    Recording {
+        events: usize,
+    },
+    /// Progress through a recording being played back, in cpu cycles.
+    Playing {
+        elapsed: u64,
+        length:  u64,
+    },
+}
+
+/// A 64-bit FNV-1a hash, used to compare machine states.
+pub fn state_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let recording = Recording {
+            machine: "Ibm5160".to_string(),
+            cpu: "Intel8088".to_string(),
+            boot_device: BootDevice::HardDisk,
+            snapshot: vec![1, 2, 3],
+            media: vec![FloppyMedia {
+                drive: 0,
+                data: vec![0xF6; 16],
+                write_protect: true,
+            }],
+            events: vec![
+                TimedInput {
+                    cycle: 100,
+                    event: InputEvent::Key(KeybufferEntry {
+                        keycode:   MartyKey::KeyA,
+                        pressed:   true,
+                        modifiers: KeyboardModifiers::default(),
+                        translate: true,
+                    }),
+                },
+                TimedInput {
+                    cycle: 200,
+                    event: InputEvent::JoystickPosition {
+                        controller: 0,
+                        stick: 0,
+                        x: Some(0.5),
+                        y: None,
+                    },
+                },
+                TimedInput {
+                    cycle: 300,
+                    event: InputEvent::ClockFactor(ClockFactor::Divisor(2)),
+                },
+            ],
+            end_cycle: 400,
+            end_hash: state_hash(b"end"),
+        };
+
+        let bytes = recording.to_bytes();
+        let loaded = Recording::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert_eq!(loaded.boot_device, BootDevice::HardDisk);
+        assert_eq!(loaded.events.len(), 3);
+
+        let mut playback = Playback::new(loaded, 0);
+        assert!(playback.next_key(99).is_none());
+        assert_eq!(playback.next_key(100).map(|entry| entry.keycode), Some(MartyKey::KeyA));
+        assert!(playback.next_input(250).is_some());
+        assert!(playback.next_input(250).is_none());
+        assert!(!playback.is_finished(350));
+        assert!(playback.next_input(350).is_some());
+        assert!(playback.is_finished(400));
+
+        assert!(Recording::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_recording_rejects_bad_drive() {
+        let mut recording = Recording {
+            machine: "Ibm5160".to_string(),
+            cpu: "Intel8088".to_string(),
+            boot_device: BootDevice::FloppyA,
+            snapshot: Vec::new(),
+            media: vec![FloppyMedia {
+                drive: FDC_MAX_DRIVES,
+                data: vec![0xF6; 16],
+                write_protect: false,
+            }],
+            events: Vec::new(),
+            end_cycle: 0,
+            end_hash: 0,
+        };
+        assert!(Recording::from_bytes(&recording.to_bytes()).is_err());
+
+        recording.media.clear();
+        recording.events.push(TimedInput {
+            cycle: 0,
+            event: InputEvent::EjectFloppy(FDC_MAX_DRIVES - 1),
+        });
+        assert!(Recording::from_bytes(&recording.to_bytes()).is_ok());
+
+        recording.events[0].event = InputEvent::EjectFloppy(FDC_MAX_DRIVES);
+        assert!(Recording::from_bytes(&recording.to_bytes()).is_err());
+    }
+}
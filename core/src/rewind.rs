@@ -0,0 +1,237 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    rewind.rs
+
+    A rolling window of machine snapshots for stepping the machine backwards
+    in time. Only the newest snapshot is kept whole. Each older snapshot is
+    stored as a delta against the snapshot that followed it, so the oldest
+    snapshot can be discarded without touching the rest of the window, and
+    memory that did not change between snapshots costs next to nothing.
+
+    A delta is the length of the snapshot it reconstructs, followed by pairs
+    of runs: a count of bytes unchanged from the base, then a count of
+    literal bytes and the bytes themselves. Counts are LEB128 varints.
+
+*/
+
+use std::collections::VecDeque;
+
+/// Changes separated by fewer unchanged bytes than this are merged into one literal run, since
+/// a new run costs at least two bytes of counts.
+const MIN_MATCH_RUN: usize = 4;
+
+/// Encode `target` as a delta against `base`.
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let matches = |i: usize| i < base.len() && base[i] == target[i];
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let mut i = 0;
+    while i < target.len() {
+        let match_start = i;
+        while i < target.len() && matches(i) {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < target.len() {
+            let run = (i..target.len().min(i + MIN_MATCH_RUN))
+                .take_while(|&j| matches(j))
+                .count();
+            if run == MIN_MATCH_RUN || (run > 0 && i + run == target.len()) {
+                break;
+            }
+            i += run.max(1);
+        }
+        write_varint(&mut delta, literal_start - match_start);
+        write_varint(&mut delta, i - literal_start);
+        delta.extend_from_slice(&target[literal_start..i]);
+    }
+    delta
+}
+
+/// Reconstruct a snapshot from `base` and a delta produced by encode_delta(). Returns None if the
+/// delta is malformed or was not made against a base of this length.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+
+    let mut target = Vec::with_capacity(len);
+    while target.len() < len {
+        let match_len = read_varint(delta, &mut pos)?;
+        let start = target.len();
+        target.extend_from_slice(base.get(start..start.checked_add(match_len)?)?);
+
+        let literal_len = read_varint(delta, &mut pos)?;
+        target.extend_from_slice(delta.get(pos..pos.checked_add(literal_len)?)?);
+        pos += literal_len;
+    }
+
+    (target.len() == len && pos == delta.len()).then_some(target)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+/// A bounded history of snapshots, newest last.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    /// Deltas for the snapshots older than `latest`, oldest first. Each delta is made against the
+    /// snapshot after it.
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    /// Create a buffer holding up to `capacity` snapshots.
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// Add a snapshot as the newest in the buffer, discarding the oldest if the buffer is full.
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&snapshot, &previous);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(snapshot);
+
+        while self.len() > self.capacity {
+            if let Some(oldest) = self.deltas.pop_front() {
+                self.delta_bytes -= oldest.len();
+            }
+        }
+    }
+
+    /// Remove and return the newest snapshot. The snapshot before it becomes the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            match apply_delta(&latest, &delta) {
+                Some(previous) => self.latest = Some(previous),
+                None => {
+                    // Every older delta depends on the one that failed, so the history is lost.
+                    log::error!("Rewind buffer is corrupt, discarding history.");
+                    self.clear();
+                }
+            }
+        }
+        Some(latest)
+    }
+
+    /// The number of snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of bytes of snapshot data held by the buffer.
+    pub fn size_bytes(&self) -> usize {
+        self.latest.as_ref().map_or(0, |latest| latest.len()) + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut target = base.clone();
+        target[0] ^= 0xFF;
+        target[10] ^= 0xFF;
+        target[12] ^= 0xFF;
+        target[500] ^= 0xFF;
+        target.truncate(990);
+        target.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]);
+
+        let delta = encode_delta(&base, &target);
+        assert!(delta.len() < 64);
+        assert_eq!(apply_delta(&base, &delta), Some(target));
+
+        let delta = encode_delta(&base, &[]);
+        assert_eq!(apply_delta(&base, &delta), Some(Vec::new()));
+        assert_eq!(apply_delta(&base[..10], &encode_delta(&base, &base)), None);
+    }
+
+    #[test]
+    fn test_rewind_window() {
+        let mut rb = RewindBuffer::new(3);
+        for i in 0..5u8 {
+            rb.push(vec![i; 100]);
+        }
+        assert_eq!(rb.len(), 3);
+        assert_eq!(rb.pop(), Some(vec![4; 100]));
+        assert_eq!(rb.pop(), Some(vec![3; 100]));
+        assert_eq!(rb.pop(), Some(vec![2; 100]));
+        assert_eq!(rb.pop(), None);
+        assert!(rb.is_empty());
+        assert_eq!(rb.size_bytes(), 0);
+    }
+}
@@ -0,0 +1,375 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    savestate.rs
+
+    Machine snapshots. A snapshot begins with a header identifying the file
+    format version and the machine it was taken from, followed by a list of
+    tagged sections, one per component. Each section is length-prefixed, so
+    a reader can skip sections it does not recognize and a component can
+    tell when it has been handed more or less data than it expects.
+
+    All values are stored little-endian. Snapshots are only taken at an
+    instruction boundary.
+
+*/
+
+use std::{error::Error, fmt::Display};
+
+pub const SAVESTATE_MAGIC: &[u8; 8] = b"MARTYSAV";
+/// Incremented whenever the layout of any section changes. Snapshots of a different version are
+/// rejected rather than misinterpreted.
+pub const SAVESTATE_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    BadHeader,
+    UnsupportedVersion(u16),
+    MachineMismatch(String, String),
+    UnexpectedEnd,
+    InvalidValue(&'static str),
+    SizeMismatch(&'static str),
+    UnsupportedDevice(String),
+}
+impl Error for SaveStateError {}
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveStateError::BadHeader => write!(f, "Not a MartyPC save state."),
+            SaveStateError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "Unsupported save state version {} (expected {}).",
+                    v, SAVESTATE_VERSION
+                )
+            }
+            SaveStateError::MachineMismatch(saved, current) => {
+                write!(
+                    f,
+                    "Save state was taken on {}, but the current machine is {}.",
+                    saved, current
+                )
+            }
+            SaveStateError::UnexpectedEnd => write!(f, "Save state data ended unexpectedly."),
+            SaveStateError::InvalidValue(what) => write!(f, "Invalid value for {} in save state.", what),
+            SaveStateError::SizeMismatch(what) => {
+                write!(f, "Size of {} in save state does not match the current machine.", what)
+            }
+            SaveStateError::UnsupportedDevice(device) => {
+                write!(f, "The {} does not support save states.", device)
+            }
+        }
+    }
+}
+
+/// Implemented by components whose state can be captured into, and restored from, a snapshot.
+/// Only state that affects emulation is saved; configuration, host resources and debug statistics
+/// are left as they are.
+pub trait SaveState {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+/// Header of a snapshot file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveStateHeader {
+    pub version: u16,
+    pub machine: String,
+    pub cpu: String,
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn write_header(&mut self, machine: &str, cpu: &str) {
+        self.buf.extend_from_slice(SAVESTATE_MAGIC);
+        self.write_u16(SAVESTATE_VERSION);
+        self.write_str(machine);
+        self.write_str(cpu);
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+    pub fn write_bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+    pub fn write_u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn write_u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn write_u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    pub fn write_f64(&mut self, v: f64) {
+        self.buf.extend_from_slice(&v.to_bits().to_le_bytes());
+    }
+    pub fn write_usize(&mut self, v: usize) {
+        self.write_u64(v as u64);
+    }
+
+    /// Write a length-prefixed byte slice.
+    pub fn write_bytes(&mut self, v: &[u8]) {
+        self.write_u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+    }
+    pub fn write_str(&mut self, v: &str) {
+        self.write_bytes(v.as_bytes());
+    }
+
+    /// Write a section with the specified tag. The section payload is produced by `f`.
+    pub fn write_section<F: FnOnce(&mut StateWriter)>(&mut self, tag: &str, f: F) {
+        self.write_str(tag);
+        let len_pos = self.buf.len();
+        self.write_u32(0);
+        f(self);
+        let len = (self.buf.len() - len_pos - 4) as u32;
+        self.buf[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// Write a section containing the state of the specified component.
+    pub fn write_state(&mut self, tag: &str, state: &impl SaveState) {
+        self.write_section(tag, |w| state.save_state(w));
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    buf:    &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, cursor: 0 }
+    }
+
+    pub fn read_header(&mut self) -> Result<SaveStateHeader, SaveStateError> {
+        if self
+            .take(SAVESTATE_MAGIC.len())
+            .map_err(|_| SaveStateError::BadHeader)?
+            != SAVESTATE_MAGIC
+        {
+            return Err(SaveStateError::BadHeader);
+        }
+        let version = self.read_u16()?;
+        if version != SAVESTATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        Ok(SaveStateHeader {
+            version,
+            machine: self.read_str()?,
+            cpu: self.read_str()?,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.buf.len() - self.cursor < len {
+            return Err(SaveStateError::UnexpectedEnd);
+        }
+        let slice = &self.buf[self.cursor..self.cursor + len];
+        self.cursor += len;
+        Ok(slice)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("bool")),
+        }
+    }
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+    pub fn read_f64(&mut self) -> Result<f64, SaveStateError> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+    pub fn read_usize(&mut self) -> Result<usize, SaveStateError> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    /// Read a length-prefixed byte slice.
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Read a length-prefixed byte slice into `dst`, which must be exactly the saved length.
+    pub fn read_bytes_into(&mut self, dst: &mut [u8], what: &'static str) -> Result<(), SaveStateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dst.len() {
+            return Err(SaveStateError::SizeMismatch(what));
+        }
+        dst.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn read_str(&mut self) -> Result<String, SaveStateError> {
+        String::from_utf8(self.read_bytes()?.to_vec()).map_err(|_| SaveStateError::InvalidValue("string"))
+    }
+
+    /// Read the next section, returning its tag and a reader over its payload, or None at the end
+    /// of the data.
+    pub fn read_section(&mut self) -> Result<Option<(String, StateReader<'a>)>, SaveStateError> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.read_str()?;
+        let payload = self.read_bytes()?;
+        Ok(Some((tag, StateReader::new(payload))))
+    }
+
+    /// Restore the state of a component from this reader, which must be consumed entirely.
+    pub fn read_state(&mut self, state: &mut impl SaveState, what: &'static str) -> Result<(), SaveStateError> {
+        state.load_state(self)?;
+        if !self.is_empty() {
+            return Err(SaveStateError::SizeMismatch(what));
+        }
+        Ok(())
+    }
+
+    /// Returns true if all data has been read.
+    pub fn is_empty(&self) -> bool {
+        self.cursor >= self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Widget {
+        a: u8,
+        b: u16,
+        c: bool,
+        d: Vec<u8>,
+    }
+
+    impl SaveState for Widget {
+        fn save_state(&self, w: &mut StateWriter) {
+            w.write_u8(self.a);
+            w.write_u16(self.b);
+            w.write_bool(self.c);
+            w.write_bytes(&self.d);
+        }
+        fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+            self.a = r.read_u8()?;
+            self.b = r.read_u16()?;
+            self.c = r.read_bool()?;
+            self.d = r.read_bytes()?.to_vec();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_section_roundtrip() {
+        let widget = Widget {
+            a: 0x12,
+            b: 0x3456,
+            c: true,
+            d: vec![1, 2, 3],
+        };
+        let mut w = StateWriter::new();
+        w.write_header("Ibm5150v64K", "Intel8088");
+        w.write_section("extra", |w| w.write_u64(u64::MAX));
+        w.write_state("widget", &widget);
+        let data = w.into_vec();
+
+        let mut r = StateReader::new(&data);
+        let header = r.read_header().unwrap();
+        assert_eq!(header.machine, "Ibm5150v64K");
+        assert_eq!(header.cpu, "Intel8088");
+
+        let mut restored = Widget {
+            a: 0,
+            b: 0,
+            c: false,
+            d: Vec::new(),
+        };
+        let mut tags = Vec::new();
+        while let Some((tag, mut section)) = r.read_section().unwrap() {
+            if tag == "widget" {
+                section.read_state(&mut restored, "widget").unwrap();
+            }
+            tags.push(tag);
+        }
+        assert_eq!(tags, vec!["extra", "widget"]);
+        assert_eq!(restored.a, 0x12);
+        assert_eq!(restored.b, 0x3456);
+        assert!(restored.c);
+        assert_eq!(restored.d, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut w = StateWriter::new();
+        w.write_header("Ibm5160", "Intel8088");
+        w.write_u16(0x1234);
+        let data = w.into_vec();
+
+        let mut r = StateReader::new(&data[..data.len() - 1]);
+        r.read_header().unwrap();
+        assert!(matches!(r.read_u16(), Err(SaveStateError::UnexpectedEnd)));
+
+        let mut r = StateReader::new(b"NOTMARTY");
+        assert!(matches!(r.read_header(), Err(SaveStateError::BadHeader)));
+    }
+}
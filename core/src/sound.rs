@@ -32,6 +32,11 @@
 
 #![allow(dead_code)]
 
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use ringbuf::{
     Producer,
@@ -43,12 +48,32 @@ use ringbuf::{
 
 pub const VOLUME_ADJUST: f32 = 0.10;
 
+// Cutoff of the low-pass filter applied to PC speaker output, roughly the response of the speaker cone.
+pub const SPEAKER_CUTOFF_HZ: f64 = 10_000.0;
+// Cutoff of the high-pass filter modelling the AC coupling of the speaker.
+pub const SPEAKER_DC_CUTOFF_HZ: f64 = 20.0;
+
 #[cfg(target_arch = "wasm32")]
 pub const BUFFER_MS: f32 = 100.0;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub const BUFFER_MS: f32 = 30.0;
 
+// Limits for a configured buffer size, in milliseconds.
+pub const MIN_BUFFER_MS: f32 = 5.0;
+pub const MAX_BUFFER_MS: f32 = 500.0;
+
+/// A snapshot of the sound player's configuration and buffer state.
+#[derive(Clone, Debug, Default)]
+pub struct SoundPlayerStats {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub buffer_ms: f32,
+    pub device_buffer: Option<u32>,
+    pub buffered_ms: f32,
+    pub underruns: u64,
+}
+
 pub struct SoundPlayer {
     audio_device: cpal::Device,
     //audio_config_s: cpal::SupportedStreamConfig,
@@ -56,6 +81,9 @@ pub struct SoundPlayer {
     sample_format: cpal::SampleFormat,
     sample_rate: u32,
     channels: usize,
+    buffer_ms: f32,
+    device_buffer: Option<u32>,
+    underruns: Arc<AtomicU64>,
 
     pub samples_consumed: u64,
     pub samples_produced: u64,
@@ -84,7 +112,11 @@ impl SoundPlayer {
         (audio_device, config.sample_format())
     }
 
-    pub fn new<T>(audio_device: cpal::Device) -> Self
+    /// Create a sound player for the specified device. `buffer_ms` sets the length of the sample
+    /// buffer between the emulator and the audio backend, which is how far output lags behind
+    /// emulation. `device_buffer` optionally sets the size of the backend's own buffer, in frames.
+    /// Smaller buffers reduce latency, but are more likely to underrun.
+    pub fn new<T>(audio_device: cpal::Device, buffer_ms: Option<f32>, device_buffer: Option<u32>) -> Self
     where
         T: cpal::Sample,
     {
@@ -94,9 +126,10 @@ impl SoundPlayer {
         let sample_rate = config.sample_rate().0;
         let channels = config.channels() as usize;
 
-        let min_buffer = ((BUFFER_MS / 1000.0) / (1.0 / sample_rate as f32)) as usize;
+        let buffer_ms = buffer_ms.unwrap_or(BUFFER_MS).clamp(MIN_BUFFER_MS, MAX_BUFFER_MS);
+        let min_buffer = ((buffer_ms / 1000.0) / (1.0 / sample_rate as f32)) as usize;
         //log::trace!("Minimum sample buffer size: {}", min_buffer);
-        let buffer_size = (sample_rate as f32 * (BUFFER_MS as f32 / 1000.0)) as usize;
+        let buffer_size = (sample_rate as f32 * (buffer_ms / 1000.0)) as usize;
         let buffer = RingBuffer::new(buffer_size as usize);
         let (buffer_producer, mut buffer_consumer) = buffer.split();
 
@@ -108,6 +141,9 @@ impl SoundPlayer {
 
         //let mut debug_snd_file = File::create("output2.pcm").expect("Couldn't open debug pcm file");
 
+        let underruns = Arc::new(AtomicU64::new(0));
+        let underruns_cb = underruns.clone();
+
         let mut _consumer_count: u64 = 0;
         let _last_value: f32 = 0.0;
         let mut refill_buffer: bool = true;
@@ -128,6 +164,7 @@ impl SoundPlayer {
                 Some(s) => s,
                 None => {
                     //log::trace!("Buffer underrun");
+                    underruns_cb.fetch_add(1, Ordering::Relaxed);
                     refill_buffer = true;
                     0.0
                 }
@@ -136,9 +173,26 @@ impl SoundPlayer {
             sample
         };
 
+        // Use the requested backend buffer size, if the device supports it.
+        let device_buffer = match (device_buffer, config.buffer_size()) {
+            (Some(frames), cpal::SupportedBufferSize::Range { min, max }) => Some(frames.clamp(*min, *max)),
+            (Some(frames), cpal::SupportedBufferSize::Unknown) => Some(frames),
+            (None, _) => None,
+        };
+        let mut stream_config: cpal::StreamConfig = config.into();
+        if let Some(frames) = device_buffer {
+            stream_config.buffer_size = cpal::BufferSize::Fixed(frames);
+        }
+        log::debug!(
+            "Audio buffer: {}ms ({} samples), device buffer: {:?}",
+            buffer_ms,
+            buffer_size,
+            stream_config.buffer_size
+        );
+
         let output_stream = audio_device
             .build_output_stream(
-                &config.into(),
+                &stream_config,
                 move |data: &mut [T], _: &cpal::OutputCallbackInfo| write_data(data, channels, &mut next_value),
                 err_fn,
             )
@@ -150,6 +204,9 @@ impl SoundPlayer {
             //audio_config: config.into(),
             sample_format,
             sample_rate,
+            buffer_ms,
+            device_buffer,
+            underruns,
             samples_consumed: 0,
             samples_produced: 0,
             channels,
@@ -176,6 +233,21 @@ impl SoundPlayer {
     pub fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+
+    pub fn stats(&self) -> SoundPlayerStats {
+        SoundPlayerStats {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            buffer_ms: self.buffer_ms,
+            device_buffer: self.device_buffer,
+            buffered_ms: self.buffer_producer.len() as f32 * 1000.0 / self.sample_rate as f32,
+            underruns: self.underruns.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.underruns.store(0, Ordering::Relaxed);
+    }
 }
 
 fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
@@ -190,3 +262,83 @@ where
         }
     }
 }
+
+/// A biquad filter section in transposed direct form II.
+#[derive(Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = 2.0 * std::f64::consts::PI * cutoff / sample_rate;
+        let cos_w0 = w0.cos();
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: (1.0 - cos_w0) / 2.0 / a0,
+            b1: (1.0 - cos_w0) / a0,
+            b2: (1.0 - cos_w0) / 2.0 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Band-limits the 1-bit PC speaker signal, sampled at the PIT clock, for decimation to the host
+/// audio rate. A 4th order Butterworth low-pass runs at the input rate, so that PWM carriers and
+/// ultrasonic tones are removed instead of aliasing into the audible range. A DC-blocking
+/// high-pass is then applied at the output rate.
+pub struct SpeakerFilter {
+    lowpass: [Biquad; 2],
+    dc_coeff: f32,
+    dc_last_in: f32,
+    dc_last_out: f32,
+}
+
+impl SpeakerFilter {
+    pub fn new(input_rate: f64, output_rate: f64) -> Self {
+        let cutoff = f64::min(SPEAKER_CUTOFF_HZ, output_rate * 0.4);
+        Self {
+            lowpass: [
+                Biquad::lowpass(input_rate, cutoff, 0.541_196_1),
+                Biquad::lowpass(input_rate, cutoff, 1.306_563),
+            ],
+            dc_coeff: (1.0 - 2.0 * std::f64::consts::PI * SPEAKER_DC_CUTOFF_HZ / output_rate) as f32,
+            dc_last_in: 0.0,
+            dc_last_out: 0.0,
+        }
+    }
+
+    /// Filter a single speaker sample at the input rate.
+    #[inline]
+    pub fn input(&mut self, sample: u8) -> f64 {
+        let x = if sample != 0 { 1.0 } else { 0.0 };
+        let y = self.lowpass[0].process(x);
+        self.lowpass[1].process(y)
+    }
+
+    /// Remove DC from a decimated sample at the output rate.
+    pub fn output(&mut self, sample: f64) -> f32 {
+        let x = sample as f32;
+        let y = x - self.dc_last_in + self.dc_coeff * self.dc_last_out;
+        self.dc_last_in = x;
+        self.dc_last_out = y;
+        y
+    }
+}
@@ -0,0 +1,242 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    sound_mixer.rs
+
+    Implement the central audio mixer.
+
+    Each sound device registers a source with the mixer, and pushes samples
+    into the source's ring buffer at its own fixed sample rate. The mixer
+    produces one output sample at a time at the host audio rate, linearly
+    resampling each source, applying per-source and master volume, and
+    summing the result.
+
+    If a source runs dry its last sample is held. A source that gets too far
+    ahead of the mixer has its oldest samples discarded, so that latency stays
+    low.
+
+    The mixed output and each resampled source can be captured, ie, for
+    recording to a WAV file.
+
+*/
+
+use ringbuf::{Consumer, Producer, RingBuffer};
+
+use crate::sound::VOLUME_ADJUST;
+
+// Size of each source's ring buffer, in seconds of input.
+const SOURCE_BUFFER_SECONDS: f64 = 0.25;
+// Maximum number of buffered input samples before the oldest are dropped, in seconds of input.
+const SOURCE_MAX_LATENCY_SECONDS: f64 = 0.02;
+
+/// The producing end of a mixer source, owned by the sound device that generates the samples.
+pub struct SoundSource {
+    id: usize,
+    producer: Producer<f32>,
+}
+
+impl SoundSource {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Queue a sample for mixing. If the mixer isn't consuming samples the sample is discarded.
+    #[inline]
+    pub fn push(&mut self, sample: f32) {
+        let _ = self.producer.push(sample);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SoundSourceInfo {
+    pub name: String,
+    pub sample_rate: f64,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+/// Samples captured from the mixer at the output rate. `mix` holds the final output, and `sources`
+/// holds the output of each source, in registration order, before volume is applied.
+#[derive(Default)]
+pub struct MixerCapture {
+    pub mix: Vec<f32>,
+    pub sources: Vec<Vec<f32>>,
+}
+
+struct MixerChannel {
+    info: SoundSourceInfo,
+    consumer: Consumer<f32>,
+    max_latency: usize,
+    step: f64,
+    phase: f64,
+    prev: f32,
+    next: f32,
+}
+
+impl MixerChannel {
+    /// Produce the next sample at the output rate, interpolating between input samples.
+    fn next_sample(&mut self) -> f32 {
+        // Drop samples we've fallen too far behind on.
+        let excess = self.consumer.len().saturating_sub(self.max_latency);
+        if excess > 0 {
+            self.consumer.discard(excess);
+        }
+
+        self.phase += self.step;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.prev = self.next;
+            if let Some(sample) = self.consumer.pop() {
+                self.next = sample;
+            }
+        }
+        self.prev + (self.next - self.prev) * self.phase as f32
+    }
+}
+
+pub struct SoundMixer {
+    output_rate: f64,
+    master_volume: f32,
+    master_muted: bool,
+    channels: Vec<MixerChannel>,
+    capture: Option<MixerCapture>,
+}
+
+impl SoundMixer {
+    pub fn new(output_rate: f64) -> Self {
+        Self {
+            output_rate,
+            master_volume: 1.0,
+            master_muted: false,
+            channels: Vec::new(),
+            capture: None,
+        }
+    }
+
+    /// Register a new source producing samples at `sample_rate`. The returned SoundSource should
+    /// be given to the device that produces the source's samples.
+    pub fn add_source(&mut self, name: &str, sample_rate: f64) -> SoundSource {
+        let buffer = RingBuffer::new(((sample_rate * SOURCE_BUFFER_SECONDS) as usize).max(1));
+        let (producer, consumer) = buffer.split();
+        let id = self.channels.len();
+
+        log::debug!("Added mixer source {}: {} at {}Hz", id, name, sample_rate);
+        self.channels.push(MixerChannel {
+            info: SoundSourceInfo {
+                name: name.to_string(),
+                sample_rate,
+                volume: 1.0,
+                muted: false,
+            },
+            consumer,
+            max_latency: ((sample_rate * SOURCE_MAX_LATENCY_SECONDS) as usize).max(1),
+            step: sample_rate / self.output_rate,
+            phase: 0.0,
+            prev: 0.0,
+            next: 0.0,
+        });
+        SoundSource { id, producer }
+    }
+
+    /// The rate at which output samples are produced, in emulated time.
+    pub fn output_rate(&self) -> f64 {
+        self.output_rate
+    }
+
+    /// Set the rate at which output samples are produced, in emulated time.
+    pub fn set_output_rate(&mut self, rate: f64) {
+        self.output_rate = rate;
+        for channel in &mut self.channels {
+            channel.step = channel.info.sample_rate / rate;
+        }
+    }
+
+    /// Set the rate at which the specified source produces samples.
+    pub fn set_source_rate(&mut self, id: usize, rate: f64) {
+        if let Some(channel) = self.channels.get_mut(id) {
+            channel.info.sample_rate = rate;
+            channel.step = rate / self.output_rate;
+        }
+    }
+
+    pub fn sources(&self) -> Vec<SoundSourceInfo> {
+        self.channels.iter().map(|c| c.info.clone()).collect()
+    }
+
+    pub fn set_source_volume(&mut self, id: usize, volume: f32, muted: bool) {
+        if let Some(channel) = self.channels.get_mut(id) {
+            channel.info.volume = volume.clamp(0.0, 1.0);
+            channel.info.muted = muted;
+        }
+    }
+
+    pub fn master_volume(&self) -> (f32, bool) {
+        (self.master_volume, self.master_muted)
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32, muted: bool) {
+        self.master_volume = volume.clamp(0.0, 1.0);
+        self.master_muted = muted;
+    }
+
+    /// Start or stop capturing mixer output.
+    pub fn set_capture(&mut self, state: bool) {
+        self.capture = if state { Some(MixerCapture::default()) } else { None };
+    }
+
+    /// Take all samples captured since the last call, or None if capture is not enabled.
+    pub fn take_capture(&mut self) -> Option<MixerCapture> {
+        self.capture.as_mut().map(std::mem::take)
+    }
+
+    /// Mix a single output sample from all sources.
+    pub fn mix(&mut self) -> f32 {
+        let mut sample = 0.0;
+        for (idx, channel) in self.channels.iter_mut().enumerate() {
+            // Muted sources are still consumed, so they resume in sync when unmuted.
+            let source_sample = channel.next_sample();
+            if !channel.info.muted {
+                sample += source_sample * channel.info.volume * VOLUME_ADJUST;
+            }
+            if let Some(capture) = &mut self.capture {
+                if capture.sources.len() <= idx {
+                    capture.sources.resize(idx + 1, Vec::new());
+                }
+                capture.sources[idx].push(source_sample);
+            }
+        }
+        if self.master_muted {
+            sample = 0.0;
+        }
+        else {
+            sample *= self.master_volume;
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.mix.push(sample);
+        }
+        sample
+    }
+}
@@ -0,0 +1,362 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    tape_image.rs
+
+    Cassette tape images. A tape is held as the list of intervals between
+    level transitions on the cassette data line, so that audio recordings,
+    data images and recordings made by the guest share one representation.
+
+    Data is encoded as the IBM cassette BIOS writes it: a '1' bit is a 1ms
+    cycle and a '0' bit a 0.5ms cycle, most significant bit first. Each
+    record is preceded by a leader of 256 0xFF bytes and a single '0' sync
+    bit, followed by the 0x16 sync byte, 256-byte data blocks each with a
+    CRC, and a 4-byte trailer.
+
+    CAS images hold the bytes of each record after the leader and sync bit,
+    one record after another.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+use crate::wav::{decode_wav, WavSamples};
+
+/// Half-cycle lengths of encoded bits, in microseconds.
+pub const BIT0_HALF_US: f64 = 250.0;
+pub const BIT1_HALF_US: f64 = 500.0;
+/// Cycles shorter than this are decoded as a '0' bit.
+const BIT_THRESHOLD_US: f64 = 750.0;
+/// Half-cycles longer than this are a gap between records.
+const GAP_THRESHOLD_US: f64 = 2000.0;
+/// Minimum number of leader half-cycles to recognize before a sync bit.
+const MIN_LEADER_HALVES: usize = 64;
+
+const LEADER_BITS: usize = 2048;
+const RECORD_GAP_US: f64 = 500_000.0;
+
+pub const SYNC_BYTE: u8 = 0x16;
+pub const BLOCK_SIZE: usize = 256;
+const CRC_LEN: usize = 2;
+const TRAILER_LEN: usize = 4;
+/// The CRC of a block including its (inverted) CRC bytes.
+const CRC_RESIDUE: u16 = 0x1D0F;
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+const WAV_HEADER_SIZE: usize = 44;
+
+#[derive(Clone, Debug, Default)]
+pub struct TapeImage {
+    pulses: Vec<f64>,
+    length: f64,
+}
+
+/// Calculate the CRC-CCITT used by the cassette BIOS over `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            }
+            else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Split the contents of a CAS image into records. A record ends at its trailer, which is
+/// recognized after the last data block whose CRC checks.
+fn split_records(data: &[u8]) -> Vec<&[u8]> {
+    let mut records = Vec::new();
+    let mut start = 0;
+
+    while start < data.len() {
+        let mut end = start + 1;
+        let mut blocks = 0;
+        while end + BLOCK_SIZE + CRC_LEN <= data.len() && crc16(&data[end..end + BLOCK_SIZE + CRC_LEN]) == CRC_RESIDUE {
+            end += BLOCK_SIZE + CRC_LEN;
+            blocks += 1;
+        }
+
+        if blocks == 0 {
+            // Not a well-formed record; keep the remainder of the image as is.
+            end = data.len();
+        }
+        else {
+            let trailer = data[end..].iter().take(TRAILER_LEN).take_while(|&&b| b == 0xFF).count();
+            end += trailer;
+        }
+
+        records.push(&data[start..end]);
+        start = end;
+    }
+    records
+}
+
+impl TapeImage {
+    /// Create a blank tape.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Return the intervals between level transitions, in microseconds.
+    pub fn pulses(&self) -> &[f64] {
+        &self.pulses
+    }
+
+    /// Return the length of the recorded portion of the tape, in microseconds.
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pulses.is_empty()
+    }
+
+    pub fn push(&mut self, us: f64) {
+        self.pulses.push(us);
+        self.length += us;
+    }
+
+    /// Erase the tape after the specified pulse.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.pulses.len() {
+            self.pulses.truncate(len);
+            self.length = self.pulses.iter().sum();
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        let half = if bit { BIT1_HALF_US } else { BIT0_HALF_US };
+        self.push(half);
+        self.push(half);
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        for i in (0..8).rev() {
+            self.push_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    /// Append a record to the tape, preceded by a gap, the leader and the sync bit.
+    pub fn push_record(&mut self, record: &[u8]) {
+        self.push(RECORD_GAP_US);
+        for _ in 0..LEADER_BITS {
+            self.push_bit(true);
+        }
+        self.push_bit(false);
+        for &byte in record {
+            self.push_byte(byte);
+        }
+    }
+
+    /// Create a tape from a CAS image.
+    pub fn from_cas(data: &[u8]) -> Result<Self, Error> {
+        if data.first().is_some_and(|&b| b != SYNC_BYTE) {
+            return Err(anyhow!("Not a cassette image: expected sync byte {:02X}", SYNC_BYTE));
+        }
+
+        let mut tape = TapeImage::new();
+        for record in split_records(data) {
+            tape.push_record(record);
+        }
+        if !tape.is_empty() {
+            tape.push(RECORD_GAP_US);
+        }
+        Ok(tape)
+    }
+
+    /// Decode the records on the tape.
+    pub fn records(&self) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        let pulses = &self.pulses;
+        let mut leader = 0;
+        let mut i = 0;
+
+        while i < pulses.len() {
+            let half = pulses[i];
+            if half > GAP_THRESHOLD_US {
+                leader = 0;
+                i += 1;
+                continue;
+            }
+            if half > BIT_THRESHOLD_US / 2.0 {
+                leader += 1;
+                i += 1;
+                continue;
+            }
+            if leader < MIN_LEADER_HALVES {
+                leader = 0;
+                i += 1;
+                continue;
+            }
+
+            // Found the sync bit. The cycles that follow are aligned with it.
+            i += 2;
+            leader = 0;
+            let mut record = Vec::new();
+            let mut byte = 0u8;
+            let mut bits = 0;
+
+            while i + 1 < pulses.len() && pulses[i] <= GAP_THRESHOLD_US && pulses[i + 1] <= GAP_THRESHOLD_US {
+                let bit = pulses[i] + pulses[i + 1] >= BIT_THRESHOLD_US;
+                byte = (byte << 1) | bit as u8;
+                bits += 1;
+                if bits == 8 {
+                    record.push(byte);
+                    bits = 0;
+                }
+                i += 2;
+            }
+
+            if !record.is_empty() {
+                records.push(record);
+            }
+        }
+        records
+    }
+
+    /// Encode the tape as a CAS image.
+    pub fn to_cas(&self) -> Vec<u8> {
+        self.records().concat()
+    }
+
+    /// Create a tape from a PCM WAV recording. The first channel is used.
+    pub fn from_wav(data: &[u8]) -> Result<Self, Error> {
+        let WavSamples { rate, levels } = decode_wav(data)?;
+
+        // Find transitions with some hysteresis, so that noise around zero isn't read as data.
+        let peak = levels.iter().map(|s| s.abs()).max().unwrap_or(0);
+        let hysteresis = peak / 8;
+        let us_per_sample = 1_000_000.0 / rate as f64;
+
+        let mut tape = TapeImage::new();
+        let mut high = false;
+        let mut last = 0;
+        for (i, &level) in levels.iter().enumerate() {
+            if (!high && level > hysteresis) || (high && level < -hysteresis) {
+                high = !high;
+                tape.push((i - last) as f64 * us_per_sample);
+                last = i;
+            }
+        }
+        if last < levels.len() {
+            tape.push((levels.len() - last) as f64 * us_per_sample);
+        }
+        Ok(tape)
+    }
+
+    /// Encode the tape as an 8-bit mono PCM WAV file.
+    pub fn to_wav(&self) -> Vec<u8> {
+        let us_per_sample = 1_000_000.0 / WAV_SAMPLE_RATE as f64;
+        let mut samples = Vec::with_capacity((self.length / us_per_sample) as usize + 1);
+
+        // Round transitions to the nearest sample without accumulating error.
+        let mut time = 0.0;
+        let mut high = false;
+        for &pulse in &self.pulses {
+            time += pulse;
+            let end = (time / us_per_sample).round() as usize;
+            let level = if high { 0xC0 } else { 0x40 };
+            samples.resize(end.max(samples.len()), level);
+            high = !high;
+        }
+
+        let data_size = samples.len() as u32;
+        let mut wav = Vec::with_capacity(WAV_HEADER_SIZE + samples.len());
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(WAV_HEADER_SIZE as u32 - 8 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // WAVE_FORMAT_PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // Channels
+        wav.extend_from_slice(&WAV_SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&WAV_SAMPLE_RATE.to_le_bytes()); // Bytes per second
+        wav.extend_from_slice(&1u16.to_le_bytes()); // Block align
+        wav.extend_from_slice(&8u16.to_le_bytes()); // Bits per sample
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        wav.extend_from_slice(&samples);
+        wav
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a record the way the cassette BIOS writes it.
+    fn make_record(data: &[u8]) -> Vec<u8> {
+        let mut record = vec![SYNC_BYTE];
+        for block in data.chunks(BLOCK_SIZE) {
+            let mut block = block.to_vec();
+            block.resize(BLOCK_SIZE, 0);
+            let crc = !crc16(&block);
+            record.extend_from_slice(&block);
+            record.extend_from_slice(&crc.to_be_bytes());
+        }
+        record.extend_from_slice(&[0xFF; TRAILER_LEN]);
+        record
+    }
+
+    #[test]
+    fn test_crc_residue() {
+        let record = make_record(b"HELLO");
+        assert_eq!(crc16(&record[1..1 + BLOCK_SIZE + CRC_LEN]), CRC_RESIDUE);
+    }
+
+    #[test]
+    fn test_cas_roundtrip() {
+        let header = make_record(&[0xA5, b'P', b'R', b'O', b'G']);
+        let body = make_record(&[0x55; 600]);
+        let cas = [header.clone(), body.clone()].concat();
+
+        assert_eq!(split_records(&cas), vec![&header[..], &body[..]]);
+
+        let tape = TapeImage::from_cas(&cas).unwrap();
+        assert_eq!(tape.records(), vec![header, body]);
+        assert_eq!(tape.to_cas(), cas);
+    }
+
+    #[test]
+    fn test_wav_roundtrip() {
+        let record = make_record(b"10 PRINT \"HELLO\"");
+        let tape = TapeImage::from_cas(&record).unwrap();
+        let wav = TapeImage::from_wav(&tape.to_wav()).unwrap();
+        assert_eq!(wav.records(), vec![record]);
+    }
+
+    #[test]
+    fn test_not_cas() {
+        assert!(TapeImage::from_cas(b"MZ").is_err());
+        assert!(TapeImage::from_cas(&[]).unwrap().is_empty());
+    }
+}
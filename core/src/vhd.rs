@@ -28,6 +28,23 @@
 
     Implements VHD support including reading and writing to VHD images.
 
+    Raw sector images without a VHD footer are also supported. If their size
+    matches one of the standard XT drive geometries, that geometry is used,
+    otherwise a 16 head, 63 sector geometry is assumed, which is suitable for
+    LBA access through an ATA controller.
+
+    A copy-on-write overlay file may be attached to an image. Sector writes
+    then go to the overlay instead of the image, and reads return the
+    overlay's copy of any sector written. The overlay can later be committed
+    to the image or discarded. Overlay files have the following layout:
+
+      0x00  8 bytes   Magic, "MRTYCOW1"
+      0x08  u64 (LE)  Sector count of the image the overlay belongs to
+      0x10  bitmap    One bit per sector, set if the sector is in the overlay
+      data            Sector data, 512 byte aligned, indexed by LBA. Sectors
+                      not in the overlay are left as holes on filesystems
+                      that support sparse files.
+
 */
 
 use core::fmt::Display;
@@ -45,6 +62,7 @@ use uuid::Uuid;
 
 use crate::{
     bytebuf::{ByteBuf, ByteBufWriter},
+    device_types::{drive_status::DriveStatus, hdc::xt_hard_disk_format_for_size},
     devices::hdc::SECTOR_SIZE,
 };
 
@@ -56,6 +74,13 @@ pub const VHD_FEATURE_RESERVED: u32 = 0x02;
 pub const VHD_CHECKSUM_OFFSET: usize = 64;
 pub const VHD_DISK_TYPE: u32 = 0x02;
 
+const OVERLAY_MAGIC: &[u8; 8] = b"MRTYCOW1";
+const OVERLAY_HEADER_LEN: u64 = 16;
+
+// Geometry assumed for raw images that don't match a standard XT drive type
+const RAW_IMAGE_HEADS: u32 = 16;
+const RAW_IMAGE_SECTORS: u32 = 63;
+
 #[derive(Debug)]
 pub enum VirtualHardDiskError {
     FileExists,
@@ -64,6 +89,8 @@ pub enum VirtualHardDiskError {
     InvalidVersion,
     InvalidType,
     InvalidSeek,
+    UnknownGeometry,
+    InvalidOverlay,
 }
 impl Error for VirtualHardDiskError {}
 impl Display for VirtualHardDiskError {
@@ -84,6 +111,12 @@ impl Display for VirtualHardDiskError {
             VirtualHardDiskError::InvalidSeek => {
                 write!(f, "An IO operation was requested out of bounds.")
             }
+            VirtualHardDiskError::UnknownGeometry => {
+                write!(f, "The raw image size does not match a supported drive geometry.")
+            }
+            VirtualHardDiskError::InvalidOverlay => {
+                write!(f, "The overlay file is invalid or belongs to a different image.")
+            }
         }
     }
 }
@@ -91,18 +124,23 @@ impl Display for VirtualHardDiskError {
 #[allow(dead_code)]
 pub struct VirtualHardDisk {
     vhd_file: File,
-    footer:   VHDFileFooter,
+    footer:   Option<VHDFileFooter>, // None for a raw sector image
 
-    size: u64,
+    size: u64, // Length of the sector data, excluding any footer
     checksum: u32,
 
     pub max_cylinders: u32,
     pub max_heads: u32,
     pub max_sectors: u32,
 
+    overlay: Option<VhdOverlay>,
+
     cur_cylinder: u32,
     cur_head: u32,
     cur_sector: u32,
+
+    read_activity:  bool,
+    write_activity: bool,
 }
 
 #[derive(Default)]
@@ -289,7 +327,87 @@ impl VHDFileFooter {
     }
 }
 
+/// A copy-on-write overlay holding the sectors written to an image since the overlay was created.
+struct VhdOverlay {
+    file: File,
+    bitmap: Vec<u8>,
+    data_offset: u64,
+    sector_ct: usize,
+}
+
+impl VhdOverlay {
+    /// Open an overlay file for an image of `sectors` sectors. An empty file is initialized as a new overlay.
+    fn open(mut file: File, sectors: u64) -> Result<VhdOverlay, anyhow::Error> {
+        let bitmap_len = sectors.div_ceil(8) as usize;
+        let data_offset =
+            (OVERLAY_HEADER_LEN + bitmap_len as u64).div_ceil(VHD_SECTOR_SIZE as u64) * VHD_SECTOR_SIZE as u64;
+        let mut bitmap = vec![0u8; bitmap_len];
+
+        if file.metadata()?.len() == 0 {
+            file.write_all(OVERLAY_MAGIC)?;
+            file.write_all(&sectors.to_le_bytes())?;
+            file.write_all(&bitmap)?;
+        }
+        else {
+            let mut header = [0u8; OVERLAY_HEADER_LEN as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut header)?;
+            if &header[0..8] != OVERLAY_MAGIC || header[8..16] != sectors.to_le_bytes() {
+                bail!(VirtualHardDiskError::InvalidOverlay);
+            }
+            file.read_exact(&mut bitmap)
+                .context("Error reading overlay sector map")?;
+        }
+
+        let sector_ct = bitmap.iter().map(|b| b.count_ones() as usize).sum();
+        log::debug!("Opened VHD overlay with {} modified sectors", sector_ct);
+        Ok(VhdOverlay {
+            file,
+            bitmap,
+            data_offset,
+            sector_ct,
+        })
+    }
+
+    fn contains(&self, lba: u64) -> bool {
+        self.bitmap[(lba / 8) as usize] & (1 << (lba % 8)) != 0
+    }
+
+    fn read(&mut self, buf: &mut [u8], lba: u64) -> Result<(), anyhow::Error> {
+        self.file
+            .seek(SeekFrom::Start(self.data_offset + lba * VHD_SECTOR_SIZE as u64))?;
+        self.file.read_exact(buf).context("Error reading sector from overlay")?;
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8], lba: u64) -> Result<(), anyhow::Error> {
+        self.file
+            .seek(SeekFrom::Start(self.data_offset + lba * VHD_SECTOR_SIZE as u64))?;
+        self.file.write_all(buf).context("Error writing sector to overlay")?;
+
+        if !self.contains(lba) {
+            let idx = (lba / 8) as usize;
+            self.bitmap[idx] |= 1 << (lba % 8);
+            self.sector_ct += 1;
+            self.file.seek(SeekFrom::Start(OVERLAY_HEADER_LEN + idx as u64))?;
+            self.file.write_all(&self.bitmap[idx..idx + 1])?;
+        }
+        Ok(())
+    }
+
+    /// Empty the overlay, releasing its sector data.
+    fn clear(&mut self) -> Result<(), anyhow::Error> {
+        self.bitmap.fill(0);
+        self.sector_ct = 0;
+        self.file.set_len(OVERLAY_HEADER_LEN)?;
+        self.file.seek(SeekFrom::Start(OVERLAY_HEADER_LEN))?;
+        self.file.write_all(&self.bitmap)?;
+        Ok(())
+    }
+}
+
 impl VirtualHardDisk {
+    /// Open a VHD file, or a raw sector image if the file has no VHD footer.
     pub fn from_file(mut vhd_file: File) -> Result<VirtualHardDisk, anyhow::Error> {
         let metadata = vhd_file.metadata().context("Failed to read VHD file metadata")?;
         // Check that the file is long enough to even read the footer in. Such a small file will fail
@@ -304,23 +422,74 @@ impl VirtualHardDisk {
         // Read in the entire footer
         vhd_file.read_exact(&mut trailer_buf)?;
 
+        if &trailer_buf[0..8] != "conectix".as_bytes() {
+            return VirtualHardDisk::from_raw_file(vhd_file, metadata.len());
+        }
+
         let footer = VHDFileFooter::parse_vhd_footer(&mut trailer_buf)?;
 
         Ok(VirtualHardDisk {
             vhd_file,
 
-            size: metadata.len(),
+            size: metadata.len() - VHD_FOOTER_LEN as u64,
             checksum: 0,
 
             max_cylinders: footer.geometry.c as u32,
             max_heads: footer.geometry.h as u32,
             max_sectors: footer.geometry.s as u32,
 
+            overlay: None,
+
             cur_cylinder: 0,
             cur_head: 0,
             cur_sector: 0,
 
-            footer,
+            read_activity: false,
+            write_activity: false,
+
+            footer: Some(footer),
+        })
+    }
+
+    /// Open a raw sector image of `len` bytes. The geometry is determined from the image size.
+    fn from_raw_file(vhd_file: File, len: u64) -> Result<VirtualHardDisk, anyhow::Error> {
+        let (c, h, s) = match xt_hard_disk_format_for_size(len as usize) {
+            Some(format) => (
+                format.max_cylinders as u32,
+                format.max_heads as u32,
+                format.max_sectors as u32,
+            ),
+            None => {
+                let track_size = (RAW_IMAGE_HEADS * RAW_IMAGE_SECTORS) as u64 * VHD_SECTOR_SIZE as u64;
+                if len % VHD_SECTOR_SIZE as u64 != 0 || len < track_size {
+                    bail!(VirtualHardDiskError::UnknownGeometry);
+                }
+                let c = std::cmp::min(len / track_size, u16::MAX as u64) as u32;
+                (c, RAW_IMAGE_HEADS, RAW_IMAGE_SECTORS)
+            }
+        };
+        log::info!("Raw hard disk image geometry: c: {} h: {} s: {}", c, h, s);
+
+        Ok(VirtualHardDisk {
+            vhd_file,
+
+            size: len,
+            checksum: 0,
+
+            max_cylinders: c,
+            max_heads: h,
+            max_sectors: s,
+
+            overlay: None,
+
+            cur_cylinder: 0,
+            cur_head: 0,
+            cur_sector: 0,
+
+            read_activity: false,
+            write_activity: false,
+
+            footer: None,
         })
     }
 
@@ -335,40 +504,129 @@ impl VirtualHardDisk {
         lba * SECTOR_SIZE
     }
 
+    /// Return the total number of sectors in the image. This may be more than the geometry covers.
+    pub fn sector_count(&self) -> u64 {
+        self.size / VHD_SECTOR_SIZE as u64
+    }
+
+    /// Returns true if the image is a raw sector image rather than a VHD.
+    pub fn is_raw(&self) -> bool {
+        self.footer.is_none()
+    }
+
     pub fn read_sector(&mut self, buf: &mut [u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
         let read_offset = self.get_chs_offset(cylinder, head, sector);
+        self.read_sector_lba(buf, (read_offset / SECTOR_SIZE) as u64)
+    }
 
-        let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if read_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+    pub fn write_sector(&mut self, buf: &[u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
+        let write_offset = self.get_chs_offset(cylinder, head, sector);
+        self.write_sector_lba(buf, (write_offset / SECTOR_SIZE) as u64)
+    }
+
+    /// Read the sector at the specified logical block address, independent of the image geometry.
+    pub fn read_sector_lba(&mut self, buf: &mut [u8], lba: u64) -> Result<(), anyhow::Error> {
+        if lba >= self.sector_count() {
             // Read requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
 
-        self.vhd_file.seek(SeekFrom::Start(read_offset as u64))?;
+        self.read_activity = true;
+        if let Some(overlay) = &mut self.overlay {
+            if overlay.contains(lba) {
+                return overlay.read(buf, lba);
+            }
+        }
 
+        self.vhd_file.seek(SeekFrom::Start(lba * VHD_SECTOR_SIZE as u64))?;
         self.vhd_file.read_exact(buf).context("Error reading sector from VHD")?;
 
         Ok(())
     }
 
-    pub fn write_sector(&mut self, buf: &[u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
-        let write_offset = self.get_chs_offset(cylinder, head, sector);
-
-        let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if write_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+    /// Write the sector at the specified logical block address, independent of the image geometry.
+    pub fn write_sector_lba(&mut self, buf: &[u8], lba: u64) -> Result<(), anyhow::Error> {
+        if lba >= self.sector_count() {
             // Write requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
 
-        self.vhd_file.seek(SeekFrom::Start(write_offset as u64))?;
+        self.write_activity = true;
+        if let Some(overlay) = &mut self.overlay {
+            return overlay.write(buf, lba);
+        }
+
+        self.vhd_file.seek(SeekFrom::Start(lba * VHD_SECTOR_SIZE as u64))?;
+        self.vhd_file.write_all(buf).context("Error writing sector to VHD")?;
 
-        let write_len = self.vhd_file.write(buf)?;
-        if write_len != VHD_SECTOR_SIZE {
-            log::error!("Incomplete VHD Sector Write!");
+        Ok(())
+    }
+
+    /// Return the drive status for activity indicators, clearing the read and write activity flags. A hard disk
+    /// spins whenever it is powered, so the motor is always on.
+    pub fn take_status(&mut self) -> DriveStatus {
+        DriveStatus {
+            motor: true,
+            read:  std::mem::take(&mut self.read_activity),
+            write: std::mem::take(&mut self.write_activity),
         }
+    }
 
+    /// Attach a copy-on-write overlay file. If the file is empty a new overlay is created, otherwise the sectors
+    /// already in the overlay become visible.
+    pub fn attach_overlay(&mut self, file: File) -> Result<(), anyhow::Error> {
+        self.overlay = Some(VhdOverlay::open(file, self.sector_count())?);
         Ok(())
     }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    /// Return the number of sectors held in the overlay, or None if no overlay is attached.
+    pub fn overlay_sector_ct(&self) -> Option<usize> {
+        self.overlay.as_ref().map(|overlay| overlay.sector_ct)
+    }
+
+    /// Write every sector in the overlay to the image, then empty the overlay. Returns the number of sectors
+    /// committed.
+    pub fn commit_overlay(&mut self) -> Result<usize, anyhow::Error> {
+        let mut overlay = match self.overlay.take() {
+            Some(overlay) => overlay,
+            None => return Ok(0),
+        };
+
+        let mut buf = vec![0u8; VHD_SECTOR_SIZE];
+        let mut result = Ok(overlay.sector_ct);
+        for lba in 0..self.sector_count() {
+            if !overlay.contains(lba) {
+                continue;
+            }
+            if let Err(err) = overlay
+                .read(&mut buf, lba)
+                .and_then(|_| self.write_sector_lba(&buf, lba))
+            {
+                result = Err(err);
+                break;
+            }
+        }
+
+        // Only empty the overlay once every sector is safely in the image
+        if result.is_ok() {
+            self.vhd_file.flush()?;
+            overlay.clear()?;
+        }
+        self.overlay = Some(overlay);
+        result
+    }
+
+    /// Discard every sector in the overlay, reverting the image to its state when the overlay was created.
+    pub fn discard_overlay(&mut self) -> Result<(), anyhow::Error> {
+        match &mut self.overlay {
+            Some(overlay) => overlay.clear(),
+            None => Ok(()),
+        }
+    }
 }
 
 pub fn create_vhd(filename: OsString, c: u16, h: u8, s: u8) -> Result<File, anyhow::Error> {
@@ -388,12 +646,14 @@ pub fn create_vhd(filename: OsString, c: u16, h: u8, s: u8) -> Result<File, anyh
 
     let mut write_buf = vec![0; VHD_SECTOR_SIZE];
 
-    // Write all 0's by sector buf size
-    let n_sectors = c as u32 * h as u32 * s as u32;
-
-    for _ in 0..n_sectors {
-        vhd_file.write(&write_buf).context("Error writing VHD file to disk.")?;
-    }
+    // Extend the file with 0's to the size of the disk. Large images are left sparse where the filesystem allows.
+    let n_sectors = c as u64 * h as u64 * s as u64;
+    vhd_file
+        .set_len(n_sectors * VHD_SECTOR_SIZE as u64)
+        .context("Error writing VHD file to disk.")?;
+    vhd_file
+        .seek(SeekFrom::End(0))
+        .context("Error writing VHD file to disk.")?;
 
     let footer = VHDFileFooter::new(c, h, s, uuid);
 
@@ -0,0 +1,140 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    wav.rs
+
+    Decoding of PCM WAV files, for devices that accept host audio such as
+    the cassette interface and the floppy drive sound effects.
+
+*/
+
+use anyhow::{anyhow, Error};
+
+/// Decoded WAV audio. Only the first channel of multichannel files is kept.
+pub struct WavSamples {
+    /// Sample rate in Hz.
+    pub rate:   u32,
+    /// Sample levels, scaled to the range of a 16-bit sample.
+    pub levels: Vec<i32>,
+}
+
+impl WavSamples {
+    /// Return the samples as floating point values in the range -1.0 to 1.0.
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.levels.iter().map(|&s| s as f32 / 32768.0).collect()
+    }
+
+    /// Return the samples as floating point values, resampled to the specified rate with linear interpolation.
+    pub fn resample(&self, rate: u32) -> Vec<f32> {
+        let samples = self.to_f32();
+        if rate == self.rate || samples.is_empty() {
+            return samples;
+        }
+        let step = self.rate as f64 / rate as f64;
+        let len = ((samples.len() as f64) / step) as usize;
+        (0..len)
+            .map(|i| {
+                let pos = i as f64 * step;
+                let idx = pos as usize;
+                let frac = (pos - idx as f64) as f32;
+                let a = samples[idx];
+                let b = samples.get(idx + 1).copied().unwrap_or(a);
+                a + (b - a) * frac
+            })
+            .collect()
+    }
+}
+
+/// Decode an 8 or 16-bit PCM WAV file.
+pub fn decode_wav(data: &[u8]) -> Result<WavSamples, Error> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(anyhow!("Not a WAV file"));
+    }
+
+    let mut format = None;
+    let mut samples = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let body = &data[pos + 8..(pos + 8 + size).min(data.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]) as usize;
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + size + (size & 1);
+    }
+
+    let (tag, channels, rate, bits) = format.ok_or_else(|| anyhow!("WAV file has no format chunk"))?;
+    let samples = samples.ok_or_else(|| anyhow!("WAV file has no data chunk"))?;
+    if tag != 1 || channels == 0 || rate == 0 || !matches!(bits, 8 | 16) {
+        return Err(anyhow!(
+            "Unsupported WAV format: {} channels, {} bits, format tag {}",
+            channels,
+            bits,
+            tag
+        ));
+    }
+
+    let frame = channels * bits / 8;
+    let levels = samples
+        .chunks_exact(frame)
+        .map(|f| match bits {
+            8 => (f[0] as i32 - 128) << 8,
+            _ => i16::from_le_bytes([f[0], f[1]]) as i32,
+        })
+        .collect();
+
+    Ok(WavSamples { rate, levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample() {
+        let wav = WavSamples {
+            rate:   22050,
+            levels: vec![0, 16384, 0, -16384],
+        };
+        assert_eq!(wav.resample(22050), vec![0.0, 0.5, 0.0, -0.5]);
+        assert_eq!(wav.resample(44100), vec![0.0, 0.25, 0.5, 0.25, 0.0, -0.25, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_not_wav() {
+        assert!(decode_wav(b"RIFF\0\0\0\0AVI ").is_err());
+    }
+}
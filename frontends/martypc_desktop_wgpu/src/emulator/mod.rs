@@ -31,29 +31,67 @@
 
 use crate::JoystickData;
 use display_manager_wgpu::DisplayManager;
-use std::{cell::RefCell, ffi::OsString, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
-use crate::{input::HotkeyManager, Counter, KeyboardData, MouseData};
-use anyhow::Error;
+use crate::{
+    input::{HotkeyManager, MacroManager},
+    Counter,
+    KeyboardData,
+    MouseData,
+};
+use anyhow::{anyhow, Error};
 use config_toml_bpaf::ConfigFileParams;
 use display_manager_wgpu::WgpuDisplayManager;
 use frontend_common::{
     cartridge_manager::CartridgeManager,
+    cassette_manager::CassetteManager,
+    cdrom_manager::CdromManager,
+    clip_recorder::ClipRecorder,
     display_scaler::SCALER_MODES,
-    floppy_manager::FloppyManager,
+    floppy_manager::{DirectoryFloppy, FloppyManager},
+    media_manager::MediaManager,
     resource_manager::ResourceManager,
     rom_manager::RomManager,
     timestep_manager::PerfSnapshot,
     vhd_manager::VhdManager,
+    video_recorder::VideoRecorder,
+    wav_recorder::WavRecorder,
 };
 use marty_core::{
+    breakpoints::BreakPointType,
     cpu_common::{Cpu, CpuOption},
+    device_traits::videocard::VideoCardId,
+    file_util,
     machine::{ExecutionControl, Machine, MachineEvent, MachineState},
+    replay::ReplayStatus,
+    rewind::RewindBuffer,
     vhd::VirtualHardDisk,
 };
 use marty_egui::{state::GuiState, GuiBoolean, GuiWindow};
 use videocard_renderer::AspectCorrectionMode;
 
+/// Number of save state slots available from the Machine menu.
+pub const SAVE_STATE_SLOTS: usize = 4;
+/// Default number of frames between rewind snapshots.
+pub const REWIND_INTERVAL_DEFAULT: u32 = 30;
+/// Default number of rewind snapshots to keep.
+pub const REWIND_SNAPSHOTS_DEFAULT: usize = 120;
+/// Nominal frame rate of video recordings.
+pub const VIDEO_RECORDING_FPS: u32 = 60;
+/// Capture every other frame for animated clips, for 30fps playback.
+pub const CLIP_FRAME_STEP: u32 = 2;
+/// Maximum length of an animated clip in seconds.
+pub const CLIP_MAX_SECONDS: u32 = 10;
+/// Minimum interval between writes of modified floppy images back to their image files.
+pub const FLOPPY_WRITE_BACK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Define flags to be used by emulator.
 pub struct EmuFlags {
     pub render_gui: bool,
@@ -75,15 +113,38 @@ pub struct Emulator {
     pub exec_control: Rc<RefCell<ExecutionControl>>,
     pub mouse_data: MouseData,
     pub joy_data: JoystickData,
+    #[cfg(feature = "gamepad")]
+    pub gamepad_data: crate::gamepad::GamepadData,
+    #[cfg(feature = "midi")]
+    pub midi_data: crate::midi::MidiData,
+    #[cfg(feature = "scripting")]
+    pub scripts: crate::scripting::ScriptHost,
     pub kb_data: KeyboardData,
     pub stat_counter: Counter,
     pub gui: GuiState,
     pub floppy_manager: FloppyManager,
     pub vhd_manager: VhdManager,
     pub cart_manager: CartridgeManager,
+    pub cassette_manager: CassetteManager,
+    pub cassette_path: Option<PathBuf>,
+    pub cdrom_manager: CdromManager,
+    pub media_manager: MediaManager,
     pub flags: EmuFlags,
     pub perf: PerfSnapshot,
     pub hkm: HotkeyManager,
+    pub macros: MacroManager,
+    pub recorder: Option<VideoRecorder>,
+    pub recorder_card: Option<VideoCardId>,
+    pub clip_recorder: Option<ClipRecorder>,
+    pub clip_card: Option<VideoCardId>,
+    pub audio_recorder: Option<WavRecorder>,
+    pub source_recorders: Vec<WavRecorder>,
+    pub floppy_write_back_time: Instant,
+    pub floppy_directories: HashMap<usize, DirectoryFloppy>,
+    pub state_slot: usize,
+    pub rewind: Option<RewindBuffer>,
+    pub rewind_frames: u32,
+    pub rewind_instructions: u64,
 }
 
 impl Emulator {
@@ -169,6 +230,10 @@ impl Emulator {
             self.config.machine.cpu.wait_states.unwrap_or(true),
         ));
 
+        self.machine.set_cpu_option(CpuOption::SegmentOverrun(
+            self.config.machine.cpu.segment_overrun.unwrap_or(false),
+        ));
+
         self.gui.set_option(
             GuiBoolean::CpuInstructionHistory,
             self.config.machine.cpu.instruction_history.unwrap_or(false),
@@ -177,6 +242,25 @@ impl Emulator {
         self.machine.set_cpu_option(CpuOption::InstructionHistory(
             self.config.machine.cpu.instruction_history.unwrap_or(false),
         ));
+        if let Some(len) = self.config.machine.cpu.instruction_history_len {
+            self.machine.set_cpu_option(CpuOption::InstructionHistoryLen(len));
+        }
+
+        if self.config.machine.cpu.int_trace.unwrap_or(false) {
+            self.machine.set_cpu_option(CpuOption::InterruptTrace(
+                true,
+                self.config.machine.cpu.int_trace_vectors.clone().unwrap_or_default(),
+            ));
+        }
+
+        if self.config.machine.cpu.stack_guard.unwrap_or(false) {
+            self.machine.set_cpu_option(CpuOption::StackGuard(
+                true,
+                self.config.machine.cpu.stack_guard_limit.unwrap_or(0),
+                self.config.machine.cpu.stack_guard_wrap.unwrap_or(false),
+                self.config.machine.cpu.stack_guard_break.unwrap_or(false),
+            ));
+        }
 
         self.gui
             .set_option(GuiBoolean::CpuTraceLoggingEnabled, self.config.machine.cpu.trace_on);
@@ -185,6 +269,11 @@ impl Emulator {
 
         self.gui.set_option(GuiBoolean::TurboButton, self.config.machine.turbo);
 
+        self.gui.set_option(
+            GuiBoolean::ShowDriveStatus,
+            self.config.gui.drive_status.unwrap_or(true),
+        );
+
         self.gui.set_scaler_presets(&self.config.emulator.scaler_preset);
 
         // Populate the list of display targets for each display.
@@ -300,30 +389,26 @@ impl Emulator {
             let vhd_os_name: OsString = vhd_name.into();
             match self.vhd_manager.load_vhd_file_by_name(config_drive_idx, &vhd_os_name) {
                 Ok((vhd_file, vhd_idx)) => match VirtualHardDisk::from_file(vhd_file) {
-                    Ok(vhd) => {
-                        if let Some(hdc) = self.machine.hdc() {
-                            match hdc.set_vhd(config_drive_idx, vhd) {
-                                Ok(_) => {
-                                    log::info!(
-                                        "VHD image {:?} successfully loaded into virtual drive: {}",
-                                        vhd_os_name,
-                                        config_drive_idx
-                                    );
-
-                                    if let Some(selection) = self.vhd_manager.get_vhd_path(vhd_idx) {
-                                        self.gui
-                                            .set_hdd_selection(config_drive_idx, Some(vhd_idx), Some(selection));
-                                    }
-                                }
-                                Err(err) => {
-                                    log::error!("Error mounting VHD: {}", err);
-                                }
+                    Ok(mut vhd) => match self
+                        .attach_vhd_overlay(config_drive_idx, vhd_idx, &mut vhd)
+                        .and_then(|_| self.machine.set_vhd(config_drive_idx, vhd))
+                    {
+                        Ok(_) => {
+                            log::info!(
+                                "VHD image {:?} successfully loaded into virtual drive: {}",
+                                vhd_os_name,
+                                config_drive_idx
+                            );
+
+                            if let Some(selection) = self.vhd_manager.get_vhd_path(vhd_idx) {
+                                self.gui
+                                    .set_hdd_selection(config_drive_idx, Some(vhd_idx), Some(selection));
                             }
                         }
-                        else {
-                            log::error!("Couldn't load VHD: No Hard Disk Controller present!");
+                        Err(err) => {
+                            log::error!("Couldn't load VHD: {}", err);
                         }
-                    }
+                    },
                     Err(err) => {
                         log::error!("Error loading VHD: {}", err);
                     }
@@ -337,6 +422,21 @@ impl Emulator {
         Ok(())
     }
 
+    /// Attach a copy-on-write overlay to a VHD before it is mounted, if overlays are enabled for the drive.
+    pub fn attach_vhd_overlay(&self, drive: usize, image_idx: usize, vhd: &mut VirtualHardDisk) -> Result<(), Error> {
+        if !self.gui.hdd_overlay(drive) {
+            return Ok(());
+        }
+        let overlay_file = self.vhd_manager.open_overlay_file(image_idx)?;
+        vhd.attach_overlay(overlay_file)?;
+        log::info!(
+            "Attached overlay {:?} to hard disk {}",
+            self.vhd_manager.get_overlay_path(image_idx),
+            drive
+        );
+        Ok(())
+    }
+
     pub fn post_dm_build_init(&mut self) {
         // Set all DisplayTargets to hardware aspect correction
         self.dm.for_each_target(|dtc, _idx| {
@@ -381,10 +481,31 @@ impl Emulator {
 
         // Set hard drives.
         self.gui.set_hdds(self.machine.bus().hdd_ct());
+        for drive in 0..self.machine.bus().hdd_ct() {
+            self.gui
+                .set_hdd_overlay(drive, self.config.emulator.media.hdd_overlay_default);
+        }
 
         // Set cartridge slots
         self.gui.set_cart_slots(self.machine.bus().cart_ct());
 
+        // Set cassette interface
+        self.gui.set_cassette(self.machine.cassette().is_some());
+
+        // Set CD-ROM drive
+        self.gui.set_cdrom(self.machine.cdrom().is_some());
+
+        // Set boot device
+        self.gui.set_boot_device(self.machine.boot_device());
+
+        // Show the contents of the save state slots and the available input recordings
+        self.update_state_slots();
+        self.update_replays();
+
+        // Show the scripts that can be run from the Debug menu
+        #[cfg(feature = "scripting")]
+        crate::scripting::update_script_list(self);
+
         // Request initial events from GUI.
         self.gui.initialize();
     }
@@ -392,4 +513,606 @@ impl Emulator {
     pub fn start(&mut self) {
         self.machine.play_sound_buffer();
     }
+
+    /// Resolve the file backing the specified save state slot. Slots are kept per machine
+    /// configuration, as a save state can only be loaded into the machine that made it.
+    fn state_slot_path(&self, slot: usize) -> Result<PathBuf, Error> {
+        let state_path = self
+            .rm
+            .get_resource_path("savestate")
+            .ok_or(anyhow!("No 'savestate' resource path is configured."))?;
+        Ok(state_path.join(format!("{}_slot{}.mss", self.config.machine.config_name, slot + 1)))
+    }
+
+    /// Save the state of the machine to the specified slot. The slot becomes the target of the
+    /// save and load state hotkeys.
+    pub fn save_state_slot(&mut self, slot: usize) -> Result<PathBuf, Error> {
+        let filename = self.state_slot_path(slot)?;
+        let state = self.machine.save_state()?;
+        std::fs::write(&filename, state)?;
+        log::info!("Saved state to slot {}: {}", slot + 1, filename.display());
+
+        self.state_slot = slot;
+        self.update_state_slots();
+        Ok(filename)
+    }
+
+    /// Restore the state of the machine from the specified slot. The slot becomes the target of the
+    /// save and load state hotkeys.
+    pub fn load_state_slot(&mut self, slot: usize) -> Result<PathBuf, Error> {
+        if self.machine.replay_status() != ReplayStatus::Idle {
+            return Err(anyhow!(
+                "A state can't be loaded while input is being recorded or played."
+            ));
+        }
+        let filename = self.state_slot_path(slot)?;
+        let data = std::fs::read(&filename).map_err(|e| anyhow!("Slot {} is empty: {}", slot + 1, e))?;
+        self.machine.load_state(&data)?;
+        log::info!("Loaded state from slot {}: {}", slot + 1, filename.display());
+
+        self.state_slot = slot;
+        self.gui.set_option(GuiBoolean::TurboButton, self.machine.turbo_mode());
+        Ok(filename)
+    }
+
+    /// Take a rewind snapshot once the rewind interval has elapsed. Called once per frame.
+    /// Frames in which the machine did not run, such as while paused, are not counted.
+    pub fn update_rewind(&mut self) {
+        let interval = self
+            .config
+            .emulator
+            .rewind
+            .interval
+            .unwrap_or(REWIND_INTERVAL_DEFAULT)
+            .max(1);
+
+        if let Some(rewind) = &mut self.rewind {
+            let instructions = self.machine.cpu_instructions();
+            if instructions == self.rewind_instructions {
+                return;
+            }
+            self.rewind_instructions = instructions;
+
+            self.rewind_frames += 1;
+            if self.rewind_frames >= interval {
+                self.rewind_frames = 0;
+                match self.machine.save_state() {
+                    Ok(state) => rewind.push(state),
+                    Err(e) => {
+                        // The machine can't be saved at all, so don't try again on every interval.
+                        log::warn!("Rewind disabled: {}", e);
+                        self.rewind = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Step the machine back to the newest rewind snapshot, removing it from the history.
+    /// Returns the number of snapshots remaining.
+    pub fn rewind(&mut self) -> Result<usize, Error> {
+        if self.machine.replay_status() != ReplayStatus::Idle {
+            return Err(anyhow!(
+                "The machine can't be rewound while input is being recorded or played."
+            ));
+        }
+        let rewind = self.rewind.as_mut().ok_or(anyhow!("Rewind is not enabled."))?;
+        let snapshot = rewind.pop().ok_or(anyhow!("No rewind history is available."))?;
+        let remaining = rewind.len();
+        self.machine.load_state(&snapshot)?;
+
+        self.rewind_frames = 0;
+        self.rewind_instructions = self.machine.cpu_instructions();
+        self.gui.set_option(GuiBoolean::TurboButton, self.machine.turbo_mode());
+        Ok(remaining)
+    }
+
+    /// Start recording input to the machine. The machine is reset, and everything that happens
+    /// from then on can be played back with play_input_recording().
+    pub fn start_input_recording(&mut self) -> Result<(), Error> {
+        self.machine.start_recording()?;
+        self.gui.set_option(GuiBoolean::TurboButton, self.machine.turbo_mode());
+        Ok(())
+    }
+
+    /// Finish recording input and write the recording to a new file in the 'replay' resource
+    /// directory. Returns the path to the file that was written.
+    pub fn stop_input_recording(&mut self) -> Result<PathBuf, Error> {
+        let replay_path = self
+            .rm
+            .get_resource_path("replay")
+            .ok_or(anyhow!("No 'replay' resource path is configured."))?;
+        let data = self
+            .machine
+            .stop_recording()
+            .ok_or(anyhow!("No input recording is in progress."))?;
+
+        let filename = file_util::find_unique_timestamped_filename(&replay_path, "replay", "mrp");
+        std::fs::write(&filename, data)?;
+        log::info!("Saved input recording: {}", filename.display());
+
+        self.update_replays();
+        Ok(filename)
+    }
+
+    /// Play back the specified input recording from the 'replay' resource directory.
+    pub fn play_input_recording(&mut self, name: &str) -> Result<PathBuf, Error> {
+        let replay_path = self
+            .rm
+            .get_resource_path("replay")
+            .ok_or(anyhow!("No 'replay' resource path is configured."))?;
+        let filename = replay_path.join(name);
+        let data = std::fs::read(&filename)?;
+        self.machine.start_playback(&data)?;
+
+        // The recording brings its own disks and boot device.
+        self.floppy_directories.clear();
+        if let Some(fdc) = self.machine.fdc() {
+            for drive in 0..fdc.drive_ct() {
+                let name = fdc.get_image(drive).map(|_| PathBuf::from("Recorded disk"));
+                self.gui.set_floppy_selection(drive, None, name);
+            }
+        }
+        self.gui.set_boot_device(self.machine.boot_device());
+        self.gui.set_option(GuiBoolean::TurboButton, self.machine.turbo_mode());
+        Ok(filename)
+    }
+
+    /// Update the GUI with the input recordings available in the 'replay' resource directory.
+    pub fn update_replays(&mut self) {
+        let mut replays: Vec<String> = self
+            .rm
+            .get_resource_path("replay")
+            .and_then(|path| std::fs::read_dir(path).ok())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mrp")))
+                    .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        replays.sort();
+        self.gui.set_replays(replays);
+    }
+
+    /// Update the GUI with the time each save state slot was last saved.
+    pub fn update_state_slots(&mut self) {
+        let slots = (0..SAVE_STATE_SLOTS)
+            .map(|slot| {
+                let modified = self
+                    .state_slot_path(slot)
+                    .ok()
+                    .and_then(|path| std::fs::metadata(path).ok())
+                    .and_then(|meta| meta.modified().ok())?;
+                let (year, month, day, hour, minute, second) = file_util::utc_date_time(modified);
+                Some(format!(
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                    year, month, day, hour, minute, second
+                ))
+            })
+            .collect();
+        self.gui.set_state_slots(slots);
+    }
+
+    /// Set the machine's breakpoints from the breakpoints entered in the GUI, along with any
+    /// breakpoints set by scripts.
+    pub fn update_breakpoints(&mut self) {
+        // Get breakpoints from GUI
+        let bp_set = self.gui.get_breakpoints();
+
+        let mut breakpoints = Vec::new();
+
+        // Push exec breakpoint to list if valid expression
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.breakpoint) {
+            let flat_addr = u32::from(addr);
+            if flat_addr > 0 && flat_addr < 0x100000 {
+                breakpoints.push(BreakPointType::ExecuteFlat(flat_addr));
+            }
+        };
+
+        // Push mem breakpoint to list if valid expression
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.mem_breakpoint) {
+            let flat_addr = u32::from(addr);
+            if flat_addr > 0 && flat_addr < 0x100000 {
+                breakpoints.push(BreakPointType::MemAccessFlat(flat_addr));
+            }
+        }
+
+        // Push int breakpoint to list
+        if let Ok(iv) = u32::from_str_radix(bp_set.int_breakpoint, 10) {
+            if iv < 256 {
+                breakpoints.push(BreakPointType::Interrupt(iv as u8));
+            }
+        }
+
+        // Push io breakpoint to list
+        if let Ok(addr) = u32::from_str_radix(bp_set.io_breakpoint, 16) {
+            let port = addr as u16;
+            log::debug!("Adding I/O breakpoint: {:04X}", port);
+            breakpoints.push(BreakPointType::IoAccess(port));
+        }
+
+        // Push watchpoint to list if valid expression. Size defaults to one byte.
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.watch_addr) {
+            let start = u32::from(addr);
+            let size = u32::from_str_radix(bp_set.watch_size, 16).unwrap_or(1).max(1);
+            let end = start.saturating_add(size).min(0x100000);
+            if start < 0x100000 {
+                log::debug!("Adding {:?} watchpoint: [{:05X}-{:05X})", bp_set.watch_mode, start, end);
+                breakpoints.push(BreakPointType::MemWatchFlat(start, end, bp_set.watch_mode));
+            }
+        }
+
+        // Push stopwatches to list
+        if let Some(addr) = self.machine.cpu().eval_address(bp_set.sw_start) {
+            let start_flat_addr = u32::from(addr);
+            if start_flat_addr > 0 && start_flat_addr < 0x100000 {
+                if let Some(addr) = self.machine.cpu().eval_address(bp_set.sw_stop) {
+                    let stop_flat_addr = u32::from(addr);
+                    if stop_flat_addr > 0 && stop_flat_addr < 0x100000 {
+                        breakpoints.push(BreakPointType::StartWatch(start_flat_addr));
+                        breakpoints.push(BreakPointType::StopWatch(stop_flat_addr));
+                        self.machine.set_stopwatch(0, start_flat_addr, stop_flat_addr);
+                    }
+                }
+            }
+        }
+
+        // Push breakpoints set by scripts
+        #[cfg(feature = "scripting")]
+        breakpoints.extend(self.scripts.breakpoints().into_iter().map(BreakPointType::ExecuteFlat));
+
+        self.machine.set_breakpoints(breakpoints);
+    }
+
+    /// Start recording the output of the specified display target to a new AVI file in the
+    /// 'video' resource directory. Audio is recorded if a sound player is available.
+    pub fn start_video_recording(&mut self, dt_idx: usize) -> Result<PathBuf, Error> {
+        if self.recorder.is_some() {
+            return Err(anyhow!("A recording is already in progress."));
+        }
+
+        let (vid, w, h) = self.recording_target(dt_idx)?;
+
+        let video_path = self
+            .rm
+            .get_resource_path("video")
+            .ok_or(anyhow!("No 'video' resource path is configured."))?;
+        let filename = file_util::find_unique_timestamped_filename(&video_path, "recording", "avi");
+
+        let recorder = VideoRecorder::new(&filename, w, h, VIDEO_RECORDING_FPS, self.machine.audio_sample_rate())?;
+        log::info!(
+            "Started recording display {} ({}x{}) to {}",
+            dt_idx,
+            w,
+            h,
+            filename.display()
+        );
+
+        self.machine.set_audio_capture(true);
+        self.recorder = Some(recorder);
+        self.recorder_card = Some(vid);
+        Ok(filename)
+    }
+
+    /// Start capturing a short animated PNG clip of the specified display target to the
+    /// 'screenshot' resource directory. Capture finishes automatically after CLIP_MAX_SECONDS.
+    pub fn start_clip_capture(&mut self, dt_idx: usize) -> Result<PathBuf, Error> {
+        if self.clip_recorder.is_some() {
+            return Err(anyhow!("A clip capture is already in progress."));
+        }
+
+        let (vid, w, h) = self.recording_target(dt_idx)?;
+
+        let clip_path = self
+            .rm
+            .get_resource_path("screenshot")
+            .ok_or(anyhow!("No 'screenshot' resource path is configured."))?;
+        let filename = file_util::find_unique_timestamped_filename(&clip_path, "clip", "png");
+
+        let recorder = ClipRecorder::new(&filename, w, h, VIDEO_RECORDING_FPS, CLIP_FRAME_STEP, CLIP_MAX_SECONDS)?;
+        log::info!("Started clip capture of display {} ({}x{})", dt_idx, w, h);
+
+        self.clip_recorder = Some(recorder);
+        self.clip_card = Some(vid);
+        Ok(filename)
+    }
+
+    /// Finish the current clip capture, if any, and save it. Returns the path to the file
+    /// that was written.
+    pub fn finish_clip_capture(&mut self) -> Result<Option<PathBuf>, Error> {
+        self.clip_card = None;
+        match self.clip_recorder.take() {
+            Some(recorder) => Ok(Some(recorder.finish()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolve the video card and backend buffer dimensions of a display target to record.
+    fn recording_target(&mut self, dt_idx: usize) -> Result<(VideoCardId, u32, u32), Error> {
+        let vid = self
+            .dm
+            .get_display_info(&self.machine)
+            .get(dt_idx)
+            .and_then(|info| info.vid)
+            .ok_or(anyhow!("Display {} has no video card to record.", dt_idx))?;
+
+        match self.dm.get_renderer(dt_idx) {
+            Some(renderer) => Ok((vid, renderer.get_params().backend.w, renderer.get_params().backend.h)),
+            None => Err(anyhow!("Display {} has no renderer.", dt_idx)),
+        }
+    }
+
+    /// Start recording the mixed audio output to a new WAV file in the 'audio' resource directory.
+    /// If `per_source` is set, the output of each sound source is also recorded to its own file.
+    pub fn start_audio_recording(&mut self, per_source: bool) -> Result<PathBuf, Error> {
+        if self.audio_recorder.is_some() {
+            return Err(anyhow!("An audio recording is already in progress."));
+        }
+
+        let audio_path = self
+            .rm
+            .get_resource_path("audio")
+            .ok_or(anyhow!("No 'audio' resource path is configured."))?;
+        let filename = file_util::find_unique_timestamped_filename(&audio_path, "audio", "wav");
+
+        let mixer = self.machine.sound_mixer_mut();
+        let sample_rate = mixer.output_rate().round() as u32;
+        let recorder = WavRecorder::new(&filename, sample_rate)?;
+
+        let mut source_recorders = Vec::new();
+        if per_source {
+            let stem = filename.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            for (idx, source) in mixer.sources().iter().enumerate() {
+                let name: String = source
+                    .name
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() {
+                            c.to_ascii_lowercase()
+                        }
+                        else {
+                            '_'
+                        }
+                    })
+                    .collect();
+                let source_filename = filename.with_file_name(format!("{}_{}_{}.wav", stem, idx, name));
+                source_recorders.push(WavRecorder::new(&source_filename, sample_rate)?);
+            }
+        }
+        log::info!("Started recording audio at {}Hz to {}", sample_rate, filename.display());
+
+        mixer.set_capture(true);
+        self.audio_recorder = Some(recorder);
+        self.source_recorders = source_recorders;
+        Ok(filename)
+    }
+
+    /// Write any audio produced since the last call to the current audio recording.
+    pub fn update_audio_recording(&mut self) -> Result<(), Error> {
+        let recorder = match &mut self.audio_recorder {
+            Some(recorder) => recorder,
+            None => return Ok(()),
+        };
+        if let Some(capture) = self.machine.sound_mixer_mut().take_capture() {
+            recorder.push_samples(&capture.mix)?;
+            for (source_recorder, samples) in self.source_recorders.iter_mut().zip(capture.sources.iter()) {
+                source_recorder.push_samples(samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the current audio recording, if any, and finalize its files. Returns the path to the
+    /// mixed output file.
+    pub fn stop_audio_recording(&mut self) -> Result<Option<PathBuf>, Error> {
+        let flush_result = self.update_audio_recording();
+        self.machine.sound_mixer_mut().set_capture(false);
+        for recorder in self.source_recorders.drain(..) {
+            recorder.finish()?;
+        }
+        match self.audio_recorder.take() {
+            Some(recorder) => {
+                log::info!("Stopped audio recording: {:.2} seconds", recorder.duration());
+                let path = recorder.finish()?;
+                flush_result.map(|_| Some(path))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Stop the current recording, if any, and finalize the file. Returns the path to the file
+    /// that was written.
+    pub fn stop_video_recording(&mut self) -> Result<Option<PathBuf>, Error> {
+        self.machine.set_audio_capture(false);
+        self.recorder_card = None;
+        match self.recorder.take() {
+            Some(recorder) => {
+                log::info!(
+                    "Stopped recording: {} frames ({:.2} seconds)",
+                    recorder.frames(),
+                    recorder.duration()
+                );
+                Ok(Some(recorder.finish()?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Save the disk in the specified floppy drive to the image file it was loaded from.
+    pub fn save_floppy(&mut self, drive: usize) -> Result<PathBuf, Error> {
+        let fdc = self
+            .machine
+            .fdc()
+            .as_mut()
+            .ok_or_else(|| anyhow!("Machine has no floppy controller"))?;
+
+        // A disk built from a host directory is saved by writing its files back to the directory.
+        let path = if let Some(dir) = self.floppy_directories.get_mut(&drive) {
+            let written = dir.sync(&fdc.get_image_data(drive)?)?;
+            log::debug!(
+                "Wrote {} files from drive {} to {}",
+                written,
+                drive,
+                dir.path().display()
+            );
+            dir.path().to_path_buf()
+        }
+        else {
+            let image_idx = self
+                .gui
+                .get_floppy_selection(drive)
+                .ok_or_else(|| anyhow!("No image file is associated with drive {}", drive))?;
+            let data = fdc.get_image_data(drive)?;
+            self.floppy_manager.save_floppy_data(&data, image_idx, &self.rm)?
+        };
+        fdc.clear_image_modified(drive);
+        self.gui.set_floppy_modified(drive, false);
+        Ok(path)
+    }
+
+    /// Write changes to the disk in the specified drive that haven't been written back yet, as write-back is
+    /// rate-limited. Call before the disk is ejected or replaced, or the emulator exits. Returns false if the
+    /// disk has changes that were discarded because write-back is disabled for the drive.
+    pub fn flush_floppy(&mut self, drive: usize) -> Result<bool, Error> {
+        let modified = self
+            .machine
+            .fdc()
+            .as_ref()
+            .is_some_and(|fdc| fdc.image_modified(drive));
+        if !modified {
+            return Ok(true);
+        }
+        if !self.gui.floppy_write_back(drive) {
+            log::warn!("Discarding unsaved changes to floppy in drive: {}", drive);
+            return Ok(false);
+        }
+        let path = self.save_floppy(drive)?;
+        log::debug!("Wrote floppy image in drive {} to {}", drive, path.display());
+        Ok(true)
+    }
+
+    /// Insert the specified tape from the cassette manager into the cassette deck. Returns the path of the
+    /// tape image.
+    pub fn load_cassette(&mut self, image_idx: usize) -> Result<PathBuf, Error> {
+        let path = self
+            .cassette_manager
+            .get_cassette_path(image_idx)
+            .ok_or_else(|| anyhow!("Tape not found in cassette manager"))?;
+        let tape = self.cassette_manager.load_cassette(image_idx, &self.rm)?;
+        let cassette = self
+            .machine
+            .cassette()
+            .ok_or_else(|| anyhow!("Machine has no cassette interface"))?;
+        cassette.insert(tape);
+
+        self.cassette_path = Some(path.clone());
+        self.gui.set_cassette_selection(Some(image_idx), Some(path.clone()));
+        Ok(path)
+    }
+
+    /// Insert the specified disc from the CD-ROM manager into the CD-ROM drive. Returns the path of the
+    /// disc image.
+    pub fn load_cdrom(&mut self, image_idx: usize) -> Result<PathBuf, Error> {
+        let path = self
+            .cdrom_manager
+            .get_cdrom_path(image_idx)
+            .ok_or_else(|| anyhow!("Disc not found in CD-ROM manager"))?;
+        let image = self.cdrom_manager.load_cdrom(image_idx)?;
+        let cdrom = self
+            .machine
+            .cdrom()
+            .ok_or_else(|| anyhow!("Machine has no CD-ROM drive"))?;
+        cdrom.insert(image);
+
+        self.gui.set_cdrom_selection(Some(image_idx), Some(path.clone()));
+        Ok(path)
+    }
+
+    /// Save the tape in the cassette deck to the image file it was loaded from.
+    pub fn save_cassette(&mut self) -> Result<PathBuf, Error> {
+        let path = self
+            .cassette_path
+            .clone()
+            .ok_or_else(|| anyhow!("No tape is inserted"))?;
+        let cassette = self
+            .machine
+            .cassette()
+            .ok_or_else(|| anyhow!("Machine has no cassette interface"))?;
+        let tape = cassette.tape().ok_or_else(|| anyhow!("No tape is inserted"))?;
+
+        self.cassette_manager.save_cassette(&path, tape)?;
+        cassette.clear_modified();
+        Ok(path)
+    }
+
+    /// Mount the specified directory from the floppy manager in a floppy drive, as a disk of the largest
+    /// size the drive supports. Returns the path of the directory.
+    pub fn mount_floppy_directory(&mut self, drive: usize, dir_idx: usize) -> Result<PathBuf, Error> {
+        let path = self
+            .floppy_manager
+            .get_directory_path(dir_idx)
+            .ok_or_else(|| anyhow!("Directory not found in floppy manager"))?;
+        let size = self
+            .machine
+            .fdc()
+            .as_ref()
+            .ok_or_else(|| anyhow!("Machine has no floppy controller"))?
+            .drive_type(drive)
+            .ok_or_else(|| anyhow!("Invalid floppy drive: {}", drive))?
+            .max_disk_size();
+
+        let (dir, image) = DirectoryFloppy::build(&path, size)?;
+        let write_protect = self.config.emulator.media.write_protect_default;
+        self.machine.load_floppy(drive, image, write_protect)?;
+        self.floppy_directories.insert(drive, dir);
+
+        let name = PathBuf::from(path.file_name().unwrap_or_default());
+        self.gui.set_floppy_directory_selection(drive, name);
+        self.gui.set_floppy_write_protected(drive, write_protect);
+        self.gui
+            .set_floppy_write_back(drive, self.config.emulator.media.write_back_default);
+        Ok(path)
+    }
+
+    /// Update the modified state of floppy images shown in the GUI, and write modified images in
+    /// write-back mode to their image files. Writes are rate-limited so that a burst of sector writes
+    /// results in a single file write. Returns the drive and error for any write that failed; write-back
+    /// is disabled for that drive so its contents are kept in memory instead.
+    pub fn update_floppy_write_back(&mut self) -> Vec<(usize, Error)> {
+        let mut errors = Vec::new();
+        let drive_ct = match self.machine.fdc() {
+            Some(fdc) => fdc.drive_ct(),
+            None => return errors,
+        };
+
+        let flush = self.floppy_write_back_time.elapsed() >= FLOPPY_WRITE_BACK_INTERVAL;
+        for drive in 0..drive_ct {
+            let modified = self
+                .machine
+                .fdc()
+                .as_ref()
+                .is_some_and(|fdc| fdc.image_modified(drive));
+            if !modified {
+                continue;
+            }
+            self.gui.set_floppy_modified(drive, true);
+
+            if flush && self.gui.floppy_write_back(drive) {
+                match self.save_floppy(drive) {
+                    Ok(path) => log::debug!("Wrote floppy image in drive {} to {}", drive, path.display()),
+                    Err(e) => {
+                        self.gui.set_floppy_write_back(drive, false);
+                        errors.push((drive, e));
+                    }
+                }
+            }
+        }
+        if flush {
+            self.floppy_write_back_time = Instant::now();
+        }
+        errors
+    }
 }
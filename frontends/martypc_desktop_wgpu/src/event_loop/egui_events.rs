@@ -29,13 +29,16 @@
     Process received egui events.
 */
 
-use crate::Emulator;
+use crate::{emulator::CLIP_MAX_SECONDS, event_loop::fullscreen::toggle_fullscreen, Emulator};
+use anyhow::anyhow;
 use display_manager_wgpu::DisplayManager;
 use marty_core::{
-    breakpoints::BreakPointType,
+    breakpoints::WatchMode,
     cpu_common,
     cpu_common::{Cpu, CpuOption},
     device_traits::videocard::ClockingMode,
+    device_types::fdc::DISK_FORMATS,
+    floppy_image::{FloppyImage, FORMAT_FILL_BYTE},
     machine::MachineState,
     vhd,
 };
@@ -48,9 +51,12 @@ use marty_egui::{
     GuiVariableContext,
     InputFieldChangeSource,
 };
-use std::{mem::discriminant, time::Duration};
+use std::{fs::File, io::BufWriter, mem::discriminant, time::Duration};
 
-use frontend_common::constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME};
+use frontend_common::{
+    constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME},
+    debugger_command::{DebuggerCommand, PROFILE_SHOW_DEFAULT},
+};
 use marty_core::{cpu_common::Register16, machine::MachineOption, vhd::VirtualHardDisk};
 use videocard_renderer::AspectCorrectionMode;
 use winit::event_loop::EventLoopWindowTarget;
@@ -85,6 +91,9 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 (GuiBoolean::TurboButton, state) => {
                     emu.machine.set_turbo_mode(state);
                 }
+                (GuiBoolean::LightPen, _) => {
+                    super::light_pen::update_light_pen(emu);
+                }
                 _ => {}
             },
             GuiVariable::Enum(op) => match ctx {
@@ -161,31 +170,27 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
 
             match emu.vhd_manager.load_vhd_file(*drive_idx, *image_idx) {
                 Ok(vhd_file) => match VirtualHardDisk::from_file(vhd_file) {
-                    Ok(vhd) => {
-                        if let Some(hdc) = emu.machine.hdc() {
-                            match hdc.set_vhd(*drive_idx, vhd) {
-                                Ok(_) => {
-                                    let vhd_name = emu.vhd_manager.get_vhd_name(*image_idx).unwrap();
-                                    log::info!(
-                                        "VHD image {:?} successfully loaded into virtual drive: {}",
-                                        vhd_name,
-                                        *drive_idx
-                                    );
-
-                                    emu.gui
-                                        .toasts()
-                                        .info(format!("VHD loaded: {:?}", vhd_name))
-                                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                                }
-                                Err(err) => {
-                                    error_str = Some(format!("Error mounting VHD: {}", err));
-                                }
-                            }
+                    Ok(mut vhd) => match emu
+                        .attach_vhd_overlay(*drive_idx, *image_idx, &mut vhd)
+                        .and_then(|_| emu.machine.set_vhd(*drive_idx, vhd))
+                    {
+                        Ok(_) => {
+                            let vhd_name = emu.vhd_manager.get_vhd_name(*image_idx).unwrap();
+                            log::info!(
+                                "VHD image {:?} successfully loaded into virtual drive: {}",
+                                vhd_name,
+                                *drive_idx
+                            );
+
+                            emu.gui
+                                .toasts()
+                                .info(format!("VHD loaded: {:?}", vhd_name))
+                                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
                         }
-                        else {
-                            error_str = Some("No Hard Disk Controller present!".to_string());
+                        Err(err) => {
+                            error_str = Some(format!("Error mounting VHD: {}", err));
                         }
-                    }
+                    },
                     Err(err) => {
                         error_str = Some(format!("Error loading VHD: {}", err));
                     }
@@ -204,6 +209,54 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
         }
+        GuiEvent::SetHddOverlay(drive_idx, state) => {
+            log::info!("Setting copy-on-write overlay for hard disk {}: {}", drive_idx, state);
+            emu.gui.set_hdd_overlay(*drive_idx, *state);
+        }
+        GuiEvent::CommitHddOverlay(drive_idx) => {
+            let result = match emu.machine.vhd_mut(*drive_idx) {
+                Some(vhd) => vhd.commit_overlay(),
+                None => Err(anyhow!("No image loaded in hard disk {}", drive_idx)),
+            };
+            match result {
+                Ok(sectors) => {
+                    log::info!("Committed {} overlay sectors to hard disk {}", sectors, drive_idx);
+                    emu.gui
+                        .toasts()
+                        .info(format!("Committed {} sectors to image", sectors))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to commit overlay: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Failed to commit overlay: {}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::DiscardHddOverlay(drive_idx) => {
+            let result = match emu.machine.vhd_mut(*drive_idx) {
+                Some(vhd) => vhd.discard_overlay(),
+                None => Err(anyhow!("No image loaded in hard disk {}", drive_idx)),
+            };
+            match result {
+                Ok(_) => {
+                    log::info!("Discarded overlay for hard disk {}", drive_idx);
+                    emu.gui
+                        .toasts()
+                        .info("Overlay discarded".to_string())
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to discard overlay: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Failed to discard overlay: {}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
         GuiEvent::CreateVHD(filename, fmt) => {
             log::info!("Got CreateVHD event: {:?}, {:?}", filename, fmt);
 
@@ -228,6 +281,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     if let Err(e) = emu.vhd_manager.scan_resource(&emu.rm) {
                         log::error!("Error scanning hdd directory: {}", e);
                     };
+                    emu.media_manager.invalidate();
                 }
                 Err(err) => {
                     log::error!("Error creating VHD: {}", err);
@@ -248,10 +302,18 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             if let Err(e) = emu.cart_manager.scan_resource(&emu.rm) {
                 log::error!("Error scanning cartridge directory: {}", e);
             }
+            if let Err(e) = emu.cassette_manager.scan_resource(&emu.rm) {
+                log::error!("Error scanning cassette directory: {}", e);
+            }
+            if let Err(e) = emu.cdrom_manager.scan_resource(&emu.rm) {
+                log::error!("Error scanning cdrom directory: {}", e);
+            }
+            emu.media_manager.invalidate();
             // Update Floppy Disk Image tree
             if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
                 emu.gui.set_floppy_tree(floppy_tree);
             }
+            emu.gui.set_floppy_directories(emu.floppy_manager.get_directory_names());
             // Update VHD Image tree
             if let Ok(hdd_tree) = emu.vhd_manager.make_tree(&emu.rm) {
                 emu.gui.set_hdd_tree(hdd_tree);
@@ -260,6 +322,17 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             if let Ok(cart_tree) = emu.cart_manager.make_tree(&emu.rm) {
                 emu.gui.set_cart_tree(cart_tree);
             }
+            // Update Cassette Image tree
+            if let Ok(tape_tree) = emu.cassette_manager.make_tree(&emu.rm) {
+                emu.gui.set_cassette_tree(tape_tree);
+            }
+            // Update CD-ROM Image tree
+            if let Ok(cdrom_tree) = emu.cdrom_manager.make_tree(&emu.rm) {
+                emu.gui.set_cdrom_tree(cdrom_tree);
+            }
+            // Update the list of scripts
+            #[cfg(feature = "scripting")]
+            crate::scripting::update_script_list(emu);
         }
         GuiEvent::InsertCartridge(slot_select, item_idx) => {
             log::debug!("Insert Cart image: {:?} into drive: {}", item_idx, slot_select);
@@ -326,51 +399,243 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 emu.machine.change_state(MachineState::Rebooting);
             }
         }
-        GuiEvent::LoadFloppy(drive_select, item_idx) => {
-            log::debug!("Load floppy image: {:?} into drive: {}", item_idx, drive_select);
+        GuiEvent::LoadCassette(item_idx) => match emu.load_cassette(*item_idx) {
+            Ok(path) => {
+                log::info!("Tape inserted: {:?}", path);
+                emu.gui
+                    .toasts()
+                    .info(format!("Tape inserted: {:?}", path.file_name().unwrap_or_default()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to load tape image: {:?} Error: {}", item_idx, err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Tape load failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::CreateCassette => {
+            let created = emu
+                .cassette_manager
+                .create_cassette_image(&emu.rm)
+                .and_then(|item_idx| emu.load_cassette(item_idx));
 
-            if let Some(fdc) = emu.machine.fdc() {
-                emu.floppy_manager.get_floppy_name(*item_idx).map(|name| {
-                    log::info!("Loading floppy image: {:?} into drive: {}", name, drive_select);
-
-                    match emu.floppy_manager.load_floppy_data(*item_idx, &emu.rm) {
-                        Ok(floppy_image) => match fdc.load_image_from(
-                            *drive_select,
-                            floppy_image,
-                            emu.config.emulator.media.write_protect_default,
-                        ) {
-                            Ok(()) => {
-                                log::info!("Floppy image successfully loaded into virtual drive.");
-                                emu.gui
-                                    .set_floppy_selection(*drive_select, Some(*item_idx), Some(name.clone().into()));
+            match created {
+                Ok(path) => {
+                    log::info!("Blank tape inserted: {:?}", path);
+                    if let Ok(tape_tree) = emu.cassette_manager.make_tree(&emu.rm) {
+                        emu.gui.set_cassette_tree(tape_tree);
+                    }
+                    emu.gui
+                        .toasts()
+                        .info(format!(
+                            "Blank tape inserted: {:?}",
+                            path.file_name().unwrap_or_default()
+                        ))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to create tape image: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Tape creation failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::SaveCassette => match emu.save_cassette() {
+            Ok(path) => {
+                log::info!("Tape successfully saved: {:?}", path);
+                emu.gui
+                    .toasts()
+                    .info(format!("Tape saved: {:?}", path.file_name().unwrap_or_default()))
+                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::warn!("Tape failed to save: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Tape save failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::EjectCassette => {
+            // Keep anything recorded on the tape.
+            let modified = emu
+                .machine
+                .cassette()
+                .is_some_and(|cassette| cassette.status().modified);
+            if modified {
+                if let Err(err) = emu.save_cassette() {
+                    log::warn!("Tape failed to save: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Tape save failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+            }
+
+            if let Some(cassette) = emu.machine.cassette() {
+                cassette.eject();
+            }
+            emu.cassette_path = None;
+            emu.gui.set_cassette_selection(None, None);
+        }
+        GuiEvent::CassetteControl(command) => {
+            if let Some(cassette) = emu.machine.cassette() {
+                log::debug!("Cassette command: {:?}", command);
+                cassette.command(*command);
+            }
+        }
+        GuiEvent::LoadCdrom(item_idx) => match emu.load_cdrom(*item_idx) {
+            Ok(path) => {
+                log::info!("Disc inserted: {:?}", path);
+                emu.gui
+                    .toasts()
+                    .info(format!("Disc inserted: {:?}", path.file_name().unwrap_or_default()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to load CD-ROM image: {:?} Error: {}", item_idx, err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Disc load failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::EjectCdrom => {
+            if let Some(cdrom) = emu.machine.cdrom() {
+                cdrom.eject();
+            }
+            emu.gui.set_cdrom_selection(None, None);
+        }
+        GuiEvent::SetBootDevice(device) => {
+            emu.machine.set_boot_device(*device);
+            emu.gui.set_boot_device(*device);
+        }
+        GuiEvent::SaveState(slot) => {
+            save_state(emu, *slot);
+        }
+        GuiEvent::LoadState(slot) => {
+            load_state(emu, *slot);
+        }
+        GuiEvent::StartReplayRecording => match emu.start_input_recording() {
+            Ok(()) => {
+                emu.gui
+                    .toasts()
+                    .info("Recording input. The machine has been reset.".to_string())
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to start input recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Input recording failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::StopReplayRecording => match emu.stop_input_recording() {
+            Ok(path) => {
+                emu.gui
+                    .toasts()
+                    .info(format!(
+                        "Input recording saved: {:?}",
+                        path.file_name().unwrap_or_default()
+                    ))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to save input recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Input recording failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::PlayReplay(name) => match emu.play_input_recording(name) {
+            Ok(path) => {
+                log::info!("Playing input recording: {}", path.display());
+                emu.gui
+                    .toasts()
+                    .info(format!("Playing recording: {}", name))
+                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to play input recording {}: {}", name, err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Playback failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::StopReplayPlayback => {
+            emu.machine.stop_playback();
+        }
+        #[cfg(feature = "scripting")]
+        GuiEvent::RunScript(name) => {
+            if let Err(err) = crate::scripting::run_script(emu, std::path::Path::new(name)) {
+                log::error!("Failed to run script {}: {}", name, err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Script {} failed: {}", name, err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+        #[cfg(feature = "scripting")]
+        GuiEvent::StopScripts => {
+            crate::scripting::stop_scripts(emu);
+        }
+        GuiEvent::LoadFloppy(drive_select, item_idx) => {
+            let write_protect = emu.config.emulator.media.write_protect_default;
+            load_floppy(emu, *drive_select, *item_idx, write_protect);
+        }
+        GuiEvent::CreateFloppy(drive_select, size) => {
+            log::info!("Creating new {} byte floppy image for drive: {}", size, drive_select);
 
-                                emu.gui.set_floppy_write_protected(
-                                    *drive_select,
-                                    emu.config.emulator.media.write_protect_default,
-                                );
+            let created = DISK_FORMATS
+                .get(size)
+                .ok_or_else(|| anyhow!("Unsupported floppy size: {}", size))
+                .and_then(|fmt| FloppyImage::formatted(fmt.chs, FORMAT_FILL_BYTE).to_raw())
+                .and_then(|data| emu.floppy_manager.create_floppy_image(&data, &emu.rm));
 
-                                emu.gui
-                                    .toasts()
-                                    .info(format!("Floppy loaded: {:?}", name.clone()))
-                                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                            }
-                            Err(err) => {
-                                log::error!("Floppy image failed to load into virtual drive: {}", err);
-                                emu.gui
-                                    .toasts()
-                                    .error(format!("Floppy load failed: {}", err))
-                                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                            }
-                        },
-                        Err(err) => {
-                            log::error!("Failed to load floppy image: {:?} Error: {}", item_idx, err);
-                            emu.gui
-                                .toasts()
-                                .error(format!("Floppy load failed: {}", err))
-                                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
-                        }
+            match created {
+                Ok(item_idx) => {
+                    // Show the new image in the image list, then insert it. The disk is formatted at the
+                    // track level but contains no file system, so DOS will need to FORMAT it before use.
+                    if let Ok(floppy_tree) = emu.floppy_manager.make_tree(&emu.rm) {
+                        emu.gui.set_floppy_tree(floppy_tree);
                     }
-                });
+                    emu.media_manager.invalidate();
+                    load_floppy(emu, *drive_select, item_idx, false);
+                }
+                Err(err) => {
+                    log::error!("Failed to create floppy image: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Floppy creation failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+            }
+        }
+        GuiEvent::MountFloppyDirectory(drive_select, dir_idx) => {
+            log::info!("Mounting floppy directory {} in drive: {}", dir_idx, drive_select);
+            flush_floppy(emu, *drive_select);
+            match emu.mount_floppy_directory(*drive_select, *dir_idx) {
+                Ok(path) => {
+                    log::info!("Mounted directory {} in drive {}", path.display(), drive_select);
+                    emu.gui
+                        .toasts()
+                        .info(format!("Directory mounted: {:?}", path.file_name().unwrap_or_default()))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::error!("Failed to mount floppy directory: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Directory mount failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
             }
         }
         /*
@@ -405,29 +670,56 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 drive_select
             );
 
-            if let Some(fdc) = emu.machine.fdc() {
-                let floppy = fdc.get_image_data(*drive_select);
-                if let Some(floppy_image) = floppy {
-                    match emu.floppy_manager.save_floppy_data(floppy_image, *image_idx, &emu.rm) {
-                        Ok(path) => {
-                            log::info!("Floppy image successfully saved: {:?}", path);
+            match emu.save_floppy(*drive_select) {
+                Ok(path) => {
+                    log::info!("Floppy image successfully saved: {:?}", path);
+                    emu.media_manager.invalidate();
 
-                            emu.gui
-                                .toasts()
-                                .info(format!("Floppy saved: {:?}", path.file_name()))
-                                .set_duration(Some(SHORT_NOTIFICATION_TIME));
-                        }
-                        Err(err) => {
-                            log::warn!("Floppy image failed to save: {}", err);
-                        }
-                    }
+                    emu.gui
+                        .toasts()
+                        .info(format!("Floppy saved: {:?}", path.file_name()))
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+                Err(err) => {
+                    log::warn!("Floppy image failed to save: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Floppy save failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
                 }
             }
         }
+        GuiEvent::SyncFloppyDirectory(drive_select) => match emu.save_floppy(*drive_select) {
+            Ok(path) => {
+                log::info!("Floppy files successfully written to directory: {:?}", path);
+                emu.gui
+                    .toasts()
+                    .info(format!("Files written to: {:?}", path.file_name().unwrap_or_default()))
+                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::warn!("Floppy files failed to write to directory: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("Directory write failed: {}", err))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
         GuiEvent::EjectFloppy(drive_select) => {
+            if emu.machine.is_replaying() {
+                emu.gui
+                    .toasts()
+                    .warning("Disks can't be changed while a recording is playing.".to_string())
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                return;
+            }
+
             log::info!("Ejecting floppy in drive: {}", drive_select);
-            if let Some(fdc) = emu.machine.fdc() {
-                fdc.unload_image(*drive_select);
+            flush_floppy(emu, *drive_select);
+
+            emu.floppy_directories.remove(drive_select);
+            if emu.machine.fdc().is_some() {
+                emu.machine.eject_floppy(*drive_select);
                 emu.gui.set_floppy_selection(*drive_select, None, None);
                 emu.gui
                     .toasts()
@@ -441,6 +733,11 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 fdc.write_protect(*drive_select, *state);
             }
         }
+        GuiEvent::SetFloppyWriteBack(drive_select, state) => {
+            // Any changes already made are written on the next update if write-back is enabled.
+            log::info!("Setting floppy write-back for drive {}: {}", drive_select, state);
+            emu.gui.set_floppy_write_back(*drive_select, *state);
+        }
         GuiEvent::BridgeSerialPort(guest_port_id, host_port_name, host_port_id) => {
             log::info!("Bridging serial port: {}, id: {}", host_port_name, host_port_id);
             if let Err(err) = emu
@@ -517,57 +814,7 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                 });
         }
         GuiEvent::EditBreakpoint => {
-            // Get breakpoints from GUI
-            let bp_set = emu.gui.get_breakpoints();
-
-            let mut breakpoints = Vec::new();
-
-            // Push exec breakpoint to list if valid expression
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.breakpoint) {
-                let flat_addr = u32::from(addr);
-                if flat_addr > 0 && flat_addr < 0x100000 {
-                    breakpoints.push(BreakPointType::ExecuteFlat(flat_addr));
-                }
-            };
-
-            // Push mem breakpoint to list if valid expression
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.mem_breakpoint) {
-                let flat_addr = u32::from(addr);
-                if flat_addr > 0 && flat_addr < 0x100000 {
-                    breakpoints.push(BreakPointType::MemAccessFlat(flat_addr));
-                }
-            }
-
-            // Push int breakpoint to list
-            if let Ok(iv) = u32::from_str_radix(bp_set.int_breakpoint, 10) {
-                if iv < 256 {
-                    breakpoints.push(BreakPointType::Interrupt(iv as u8));
-                }
-            }
-
-            // Push io breakpoint to list
-            if let Ok(addr) = u32::from_str_radix(bp_set.io_breakpoint, 16) {
-                let port = addr as u16;
-                log::debug!("Adding I/O breakpoint: {:04X}", port);
-                breakpoints.push(BreakPointType::IoAccess(port));
-            }
-
-            // Push stopwatches to list
-            if let Some(addr) = emu.machine.cpu().eval_address(bp_set.sw_start) {
-                let start_flat_addr = u32::from(addr);
-                if start_flat_addr > 0 && start_flat_addr < 0x100000 {
-                    if let Some(addr) = emu.machine.cpu().eval_address(bp_set.sw_stop) {
-                        let stop_flat_addr = u32::from(addr);
-                        if stop_flat_addr > 0 && stop_flat_addr < 0x100000 {
-                            breakpoints.push(BreakPointType::StartWatch(start_flat_addr));
-                            breakpoints.push(BreakPointType::StopWatch(stop_flat_addr));
-                            emu.machine.set_stopwatch(0, start_flat_addr, stop_flat_addr);
-                        }
-                    }
-                }
-            }
-
-            emu.machine.set_breakpoints(breakpoints);
+            emu.update_breakpoints();
         }
         GuiEvent::MemoryUpdate => {
             // The address bar for the memory viewer was updated. We need to
@@ -608,6 +855,15 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             emu.machine
                 .set_cpu_option(CpuOption::HaltResumeDelay(delay_params.halt_resume_delay));
         }
+        GuiEvent::SetMasterVolume(volume, muted) => {
+            emu.machine.sound_mixer_mut().set_master_volume(*volume, *muted);
+        }
+        GuiEvent::SetSoundSourceVolume(idx, volume, muted) => {
+            emu.machine.sound_mixer_mut().set_source_volume(*idx, *volume, *muted);
+        }
+        GuiEvent::ResetAudioStats => {
+            emu.machine.reset_sound_player_stats();
+        }
         GuiEvent::TickDevice(dev, ticks) => {
             match dev {
                 DeviceSelection::Timer(_t) => {}
@@ -641,10 +897,10 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
             }
             emu.machine.change_state(*state);
         }
-        GuiEvent::TakeScreenshot(dt_idx) => {
+        GuiEvent::TakeScreenshot(dt_idx, stype) => {
             let screenshot_path = emu.rm.get_resource_path("screenshot").unwrap();
 
-            if let Err(err) = emu.dm.save_screenshot(*dt_idx, screenshot_path) {
+            if let Err(err) = emu.dm.save_screenshot(*dt_idx, screenshot_path, *stype) {
                 log::error!("Failed to save screenshot: {}", err);
                 emu.gui
                     .toasts()
@@ -652,23 +908,119 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
                     .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
         }
+        GuiEvent::StartRecordingVideo(dt_idx) => match emu.start_video_recording(*dt_idx) {
+            Ok(path) => {
+                emu.gui
+                    .toasts()
+                    .info(format!("Recording to {}", path.display()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to start recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("{}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::StopRecordingVideo => match emu.stop_video_recording() {
+            Ok(Some(path)) => {
+                emu.gui
+                    .toasts()
+                    .info(format!("Saved recording to {}", path.display()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("Failed to finish recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("{}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::StartRecordingAudio(per_source) => match emu.start_audio_recording(*per_source) {
+            Ok(path) => {
+                emu.gui
+                    .toasts()
+                    .info(format!("Recording audio to {}", path.display()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Err(err) => {
+                log::error!("Failed to start audio recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("{}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::StopRecordingAudio => match emu.stop_audio_recording() {
+            Ok(Some(path)) => {
+                emu.gui
+                    .toasts()
+                    .info(format!("Saved audio recording to {}", path.display()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("Failed to finish audio recording: {}", err);
+                emu.gui
+                    .toasts()
+                    .error(format!("{}", err))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        },
+        GuiEvent::CaptureClip(dt_idx) => {
+            // Selecting the option again while capturing ends the clip early.
+            let result = if emu.clip_recorder.is_some() {
+                emu.finish_clip_capture()
+                    .map(|path| path.map(|path| format!("Saved clip to {}", path.display())))
+            }
+            else {
+                emu.start_clip_capture(*dt_idx)
+                    .map(|_| Some(format!("Capturing clip of up to {} seconds", CLIP_MAX_SECONDS)))
+            };
+
+            match result {
+                Ok(Some(msg)) => {
+                    emu.gui.toasts().info(msg).set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    log::error!("Clip capture failed: {}", err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("{}", err))
+                        .set_duration(Some(LONG_NOTIFICATION_TIME));
+                }
+            }
+        }
         GuiEvent::ToggleFullscreen(dt_idx) => {
             if let Some(window) = emu.dm.get_window(*dt_idx) {
-                match window.fullscreen() {
-                    Some(_) => {
-                        log::debug!("ToggleFullscreen: Resetting fullscreen state.");
-                        window.set_fullscreen(None);
-                    }
-                    None => {
-                        log::debug!("ToggleFullscreen: Entering fullscreen state.");
-                        window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    }
-                }
+                toggle_fullscreen(window, emu.config.emulator.backend.fullscreen_exclusive);
             }
         }
         GuiEvent::CtrlAltDel => {
             emu.machine.emit_ctrl_alt_del();
         }
+        GuiEvent::PasteClipboard => {
+            let clipboard_text = emu.dm.get_main_gui_mut().and_then(|gui_ctx| gui_ctx.clipboard_text());
+            match clipboard_text {
+                Some(text) if !text.is_empty() => {
+                    emu.machine.paste_text(&text);
+                    emu.gui
+                        .toasts()
+                        .info(format!("Pasting {} characters", text.chars().count()))
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+                _ => {
+                    emu.gui
+                        .toasts()
+                        .warning("Clipboard does not contain any text".to_string())
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+            }
+        }
         GuiEvent::CompositeAdjust(dt_idx, params) => {
             //log::warn!("got composite params: {:?}", params);
             emu.dm.with_renderer(*dt_idx, |renderer| {
@@ -695,8 +1047,194 @@ pub fn handle_egui_event(emu: &mut Emulator, elwt: &EventLoopWindowTarget<()>, g
         GuiEvent::StopRecordingDisassembly => {
             emu.machine.set_option(MachineOption::RecordListing(false));
         }
+        GuiEvent::StartOpcodeProfiling => {
+            emu.machine.set_cpu_option(CpuOption::OpcodeProfiling(true));
+        }
+        GuiEvent::StopOpcodeProfiling => {
+            stop_opcode_profiling(emu);
+        }
+        GuiEvent::DebuggerCommand(line) => match line.parse::<DebuggerCommand>() {
+            Ok(command) => debugger_command(emu, command),
+            Err(err) => {
+                log::error!("{}", err);
+                emu.gui.toasts().error(err).set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+        },
         _ => {
             log::warn!("Unhandled GUI event: {:?}", discriminant(gui_event));
         }
     }
 }
+
+/// Save the machine state to the specified save state slot.
+pub(crate) fn save_state(emu: &mut Emulator, slot: usize) {
+    match emu.save_state_slot(slot) {
+        Ok(_) => {
+            emu.gui
+                .toasts()
+                .info(format!("State saved to slot {}", slot + 1))
+                .set_duration(Some(SHORT_NOTIFICATION_TIME));
+        }
+        Err(err) => {
+            log::error!("Failed to save state to slot {}: {}", slot + 1, err);
+            emu.gui
+                .toasts()
+                .error(format!("Save state failed: {}", err))
+                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+        }
+    }
+}
+
+/// Restore the machine state from the specified save state slot.
+pub(crate) fn load_state(emu: &mut Emulator, slot: usize) {
+    match emu.load_state_slot(slot) {
+        Ok(_) => {
+            emu.gui
+                .toasts()
+                .info(format!("State loaded from slot {}", slot + 1))
+                .set_duration(Some(SHORT_NOTIFICATION_TIME));
+        }
+        Err(err) => {
+            log::error!("Failed to load state from slot {}: {}", slot + 1, err);
+            emu.gui
+                .toasts()
+                .error(format!("Load state failed: {}", err))
+                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+        }
+    }
+}
+
+/// Flush any pending changes to a write-back image before the disk in a drive is removed or replaced.
+fn flush_floppy(emu: &mut Emulator, drive_select: usize) {
+    match emu.flush_floppy(drive_select) {
+        Ok(true) => {}
+        Ok(false) => {
+            emu.gui
+                .toasts()
+                .warning("Unsaved floppy changes were discarded.".to_string())
+                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+        }
+        Err(err) => {
+            log::error!("Failed to write floppy image in drive {}: {}", drive_select, err);
+            emu.gui
+                .toasts()
+                .error(format!("Floppy changes could not be saved: {}", err))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+    }
+}
+
+/// Load the specified image from the floppy manager into a floppy drive.
+fn load_floppy(emu: &mut Emulator, drive_select: usize, item_idx: usize, write_protect: bool) {
+    log::debug!("Load floppy image: {:?} into drive: {}", item_idx, drive_select);
+
+    if emu.machine.fdc().is_some() {
+        flush_floppy(emu, drive_select);
+        emu.floppy_manager.get_floppy_name(item_idx).map(|name| {
+            log::info!("Loading floppy image: {:?} into drive: {}", name, drive_select);
+
+            match emu.floppy_manager.load_floppy_data(item_idx, &emu.rm) {
+                Ok(floppy_image) => match emu.machine.load_floppy(drive_select, floppy_image, write_protect) {
+                    Ok(()) => {
+                        log::info!("Floppy image successfully loaded into virtual drive.");
+                        emu.floppy_directories.remove(&drive_select);
+                        emu.gui
+                            .set_floppy_selection(drive_select, Some(item_idx), Some(name.clone().into()));
+
+                        emu.gui.set_floppy_write_protected(drive_select, write_protect);
+                        emu.gui
+                            .set_floppy_write_back(drive_select, emu.config.emulator.media.write_back_default);
+
+                        emu.gui
+                            .toasts()
+                            .info(format!("Floppy loaded: {:?}", name.clone()))
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                    }
+                    Err(err) => {
+                        log::error!("Floppy image failed to load into virtual drive: {}", err);
+                        emu.gui
+                            .toasts()
+                            .error(format!("Floppy load failed: {}", err))
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                    }
+                },
+                Err(err) => {
+                    log::error!("Failed to load floppy image: {:?} Error: {}", item_idx, err);
+                    emu.gui
+                        .toasts()
+                        .error(format!("Floppy load failed: {}", err))
+                        .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                }
+            }
+        });
+    }
+}
+
+/// Log the `count` code blocks that have used the most cycles since profiling started.
+fn log_hot_spots(emu: &Emulator, count: usize) {
+    match emu.machine.cpu().opcode_profiler() {
+        Some(profiler) => {
+            for (addr, counter) in profiler.hot_spots(count) {
+                log::info!(
+                    "Hot spot: {:05X}: {} instructions, {} cycles",
+                    addr,
+                    counter.count,
+                    counter.cycles
+                );
+            }
+        }
+        None => log::warn!("The opcode profiler is not running."),
+    }
+}
+
+/// Stop the opcode profiler, logging the hot spots and writing all counters to a CSV file in the dump directory.
+fn stop_opcode_profiling(emu: &mut Emulator) {
+    if let Some(profiler) = emu.machine.cpu().opcode_profiler() {
+        log_hot_spots(emu, PROFILE_SHOW_DEFAULT);
+
+        match emu.rm.get_available_filename("dump", "opcode_profile", Some("csv")) {
+            Ok(path) => {
+                log::info!("Writing opcode profile to: {:?}", path);
+                let result = File::create(&path).and_then(|file| profiler.write_csv(&mut BufWriter::new(file)));
+                if let Err(e) = result {
+                    log::error!("Failed to write opcode profile: {}", e);
+                }
+            }
+            Err(_) => {
+                log::error!("Failed to get available filename for opcode profile!");
+            }
+        }
+    }
+    emu.machine.set_cpu_option(CpuOption::OpcodeProfiling(false));
+}
+
+/// Run a command entered on the debugger command line.
+fn debugger_command(emu: &mut Emulator, command: DebuggerCommand) {
+    log::debug!("Debugger command: {:?}", command);
+    match command {
+        DebuggerCommand::ProfileStart => {
+            emu.machine.set_cpu_option(CpuOption::OpcodeProfiling(true));
+            log::info!("Opcode profiler started.");
+        }
+        DebuggerCommand::ProfileStop => stop_opcode_profiling(emu),
+        DebuggerCommand::ProfileShow(count) => log_hot_spots(emu, count),
+        DebuggerCommand::Watch(address, size, mode) => {
+            if emu.machine.cpu().eval_address(&address).is_none() {
+                log::error!("Invalid watchpoint address: {}", address);
+                emu.gui
+                    .toasts()
+                    .error(format!("Invalid watchpoint address: {}", address))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                return;
+            }
+            emu.gui.cpu_control.set_watchpoint(&address, size, mode);
+            emu.update_breakpoints();
+            log::info!("Watching {:X} byte(s) at {} for {:?}", size, address, mode);
+        }
+        DebuggerCommand::Unwatch => {
+            emu.gui.cpu_control.set_watchpoint("", 1, WatchMode::Access);
+            emu.update_breakpoints();
+            log::info!("Watchpoint cleared.");
+        }
+    }
+}
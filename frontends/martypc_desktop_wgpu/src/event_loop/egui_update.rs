@@ -73,10 +73,48 @@ pub fn update_egui(emu: &mut Emulator, tm: &TimestepManager, elwt: &EventLoopWin
     // -- Update serial ports
     emu.gui.set_serial_ports(emu.machine.bus().enumerate_serial_ports());
 
+    // -- Update Sound Mixer window
+    if emu.gui.is_window_open(GuiWindow::SoundMixer) {
+        let mixer = emu.machine.sound_mixer();
+        emu.gui
+            .sound_mixer
+            .update_sources(mixer.sources(), mixer.master_volume());
+        emu.gui
+            .sound_mixer
+            .update_player_stats(emu.machine.sound_player_stats());
+    }
+
+    // -- Update cassette status
+    if let Some(cassette) = emu.machine.cassette() {
+        let status = cassette.status();
+        emu.gui.set_cassette_status(status);
+    }
+
+    // -- Update drive activity indicators
+    for (drive, status) in emu.machine.floppy_drive_status().into_iter().enumerate() {
+        emu.gui.set_floppy_drive_status(drive, status);
+    }
+    for (drive, status) in emu.machine.hdd_status().into_iter().enumerate() {
+        emu.gui.set_hdd_status(drive, status);
+    }
+
+    // -- Update hard disk overlay status
+    for drive in 0..emu.machine.bus().hdd_ct() {
+        let sectors = emu.machine.vhd_mut(drive).and_then(|vhd| vhd.overlay_sector_ct());
+        emu.gui.set_hdd_overlay_sectors(drive, sectors);
+    }
+
+    // -- Update Media Browser window. Identifying images means reading them, so only rescan when the
+    //    media folders have changed.
+    if emu.gui.is_window_open(GuiWindow::MediaBrowser) && emu.media_manager.is_stale() {
+        emu.media_manager.scan(&emu.floppy_manager, &emu.vhd_manager, &emu.rm);
+        emu.gui.media_browser.set_entries(emu.media_manager.entries().to_vec());
+    }
+
     // -- Update VHD Creator window
     if emu.gui.is_window_open(GuiWindow::VHDCreator) {
-        if let Some(hdc) = emu.machine.hdc() {
-            emu.gui.vhd_creator.set_formats(hdc.get_supported_formats());
+        if let Some(formats) = emu.machine.hdd_formats() {
+            emu.gui.vhd_creator.set_formats(formats);
         }
         else {
             log::error!("Couldn't query available formats: No Hard Disk Controller present!");
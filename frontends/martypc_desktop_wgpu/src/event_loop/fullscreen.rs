@@ -0,0 +1,75 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    event_loop/fullscreen.rs
+
+    Toggle display windows in and out of fullscreen.
+
+*/
+
+use winit::window::{Fullscreen, Window};
+
+/// Toggle the fullscreen state of the specified window. If `exclusive` is set, an exclusive
+/// fullscreen video mode is used instead of a borderless window, which lets the host present
+/// at the monitor's native refresh rate. Falls back to borderless fullscreen if no suitable
+/// video mode can be found.
+pub fn toggle_fullscreen(window: &Window, exclusive: bool) {
+    if window.fullscreen().is_some() {
+        log::debug!("ToggleFullscreen: Resetting fullscreen state.");
+        window.set_fullscreen(None);
+        return;
+    }
+
+    if exclusive {
+        // Keep the monitor's current resolution, but pick its highest available refresh rate.
+        let mode = window.current_monitor().and_then(|monitor| {
+            let size = monitor.size();
+            monitor
+                .video_modes()
+                .filter(|mode| mode.size() == size)
+                .max_by_key(|mode| (mode.refresh_rate_millihertz(), mode.bit_depth()))
+        });
+
+        match mode {
+            Some(mode) => {
+                log::debug!(
+                    "ToggleFullscreen: Entering exclusive fullscreen state: {}x{} @ {:.2}Hz",
+                    mode.size().width,
+                    mode.size().height,
+                    mode.refresh_rate_millihertz() as f64 / 1000.0
+                );
+                window.set_fullscreen(Some(Fullscreen::Exclusive(mode)));
+                return;
+            }
+            None => {
+                log::warn!("ToggleFullscreen: No exclusive video mode available. Using borderless fullscreen.");
+            }
+        }
+    }
+
+    log::debug!("ToggleFullscreen: Entering fullscreen state.");
+    window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+}
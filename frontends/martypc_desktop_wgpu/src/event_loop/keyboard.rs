@@ -36,11 +36,23 @@ use winit::{
     window::WindowId,
 };
 
-use display_manager_wgpu::DisplayManager;
-use frontend_common::{constants::LONG_NOTIFICATION_TIME, types::joykeys::JoyKeyInput, HotkeyEvent};
+use display_manager_wgpu::{DisplayManager, ScreenshotType};
+use frontend_common::{
+    constants::{LONG_NOTIFICATION_TIME, SHORT_NOTIFICATION_TIME},
+    types::joykeys::JoyKeyInput,
+    HotkeyEvent,
+};
 use marty_core::machine::{ExecutionOperation, MachineState};
-
-use crate::{input::TranslateKey, Emulator};
+use marty_egui::GuiBoolean;
+
+use crate::{
+    event_loop::{
+        egui_events::{load_state, save_state},
+        fullscreen::toggle_fullscreen,
+    },
+    input::TranslateKey,
+    Emulator,
+};
 
 pub fn handle_modifiers(emu: &mut Emulator, wid: WindowId, event: &WindowEvent, modifiers: &Modifiers) {
     let state = modifiers.state();
@@ -124,6 +136,7 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
                     if !repeat {
                         match state {
                             ElementState::Pressed => {
+                                emu.macros.record(keycode.to_internal(), true, emu.kb_data.modifiers);
                                 emu.machine.key_press(keycode.to_internal(), emu.kb_data.modifiers);
                                 if emu.flags.debug_keyboard {
                                     println!("Window: {:?} Key pressed: {:?}", window_id, keycode);
@@ -132,6 +145,7 @@ pub fn handle_key_event(emu: &mut Emulator, window_id: WindowId, key_event: &Key
                                 return true;
                             }
                             ElementState::Released => {
+                                emu.macros.record(keycode.to_internal(), false, emu.kb_data.modifiers);
                                 emu.machine.key_release(keycode.to_internal());
                                 if emu.flags.debug_keyboard {
                                     println!("Window: {:?} Key released: {:?}", window_id, keycode);
@@ -159,9 +173,15 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
         event_opt = emu
             .hkm
             .keydown(keycode.to_internal(), gui_focus, emu.mouse_data.is_captured);
+        emu.macros
+            .keydown(keycode.to_internal(), gui_focus, emu.mouse_data.is_captured);
     }
     else {
-        emu.hkm.keyup(keycode.to_internal())
+        emu.hkm.keyup(keycode.to_internal());
+        let macro_events = emu.macros.keyup(keycode.to_internal());
+        if !macro_events.is_empty() {
+            emu.machine.play_keys(&macro_events);
+        }
     }
 
     for hotkey in event_opt.unwrap_or_default().iter() {
@@ -229,24 +249,30 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
                     .get_window_by_id(window_id)
                     .expect(&format!("Couldn't resolve window id {:?} to window.", window_id));
 
-                match event_window.fullscreen() {
-                    Some(_) => {
-                        log::debug!("ToggleFullscreen: Resetting fullscreen state.");
-                        event_window.set_fullscreen(None);
-                    }
-                    None => {
-                        log::debug!("ToggleFullscreen: Entering fullscreen state.");
-                        event_window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    }
-                }
+                toggle_fullscreen(event_window, emu.config.emulator.backend.fullscreen_exclusive);
             }
-            HotkeyEvent::Screenshot => {
+            HotkeyEvent::ToggleTurbo => {
+                let state = !emu.gui.get_option(GuiBoolean::TurboButton).unwrap_or(false);
+                log::debug!("ToggleTurbo hotkey triggered. Setting turbo button to: {}", state);
+                emu.gui.set_option(GuiBoolean::TurboButton, state);
+                emu.machine.set_turbo_mode(state);
+                emu.gui
+                    .toasts()
+                    .info(format!("Turbo {}", if state { "on" } else { "off" }))
+                    .set_duration(Some(SHORT_NOTIFICATION_TIME));
+            }
+            HotkeyEvent::Screenshot | HotkeyEvent::ScreenshotRaw | HotkeyEvent::ScreenshotDisplay => {
                 log::debug!("Screenshot hotkey triggered. Capturing screenshot.");
 
                 let screenshot_path = emu.rm.get_resource_path("screenshot").unwrap();
+                let stype = match hotkey {
+                    HotkeyEvent::ScreenshotRaw => ScreenshotType::Raw,
+                    HotkeyEvent::ScreenshotDisplay => ScreenshotType::Display,
+                    _ => ScreenshotType::Rendered,
+                };
 
                 // Take as screenshot of the primary display target.
-                if let Err(err) = emu.dm.save_screenshot(0, screenshot_path) {
+                if let Err(err) = emu.dm.save_screenshot(0, screenshot_path, stype) {
                     log::error!("Failed to save screenshot: {}", err);
                     emu.gui
                         .toasts()
@@ -264,6 +290,49 @@ pub fn process_hotkeys(emu: &mut Emulator, keycode: KeyCode, pressed: bool, wind
                 log::debug!("JoyToggle hotkey triggered. Toggling joystick keyboard emulation.");
                 emu.joy_data.enabled = !emu.joy_data.enabled;
             }
+            HotkeyEvent::RecordMacro => {
+                if emu.macros.is_recording() {
+                    let event_ct = emu.macros.stop_recording();
+                    emu.gui
+                        .toasts()
+                        .info(format!("Macro recorded ({} key events)", event_ct))
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+                else {
+                    log::debug!("RecordMacro hotkey triggered. Recording keyboard macro.");
+                    emu.macros.start_recording(emu.hkm.held_keys(HotkeyEvent::RecordMacro));
+                    emu.gui
+                        .toasts()
+                        .info("Recording macro...".to_string())
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+            }
+            HotkeyEvent::PlayRecordedMacro => {
+                log::debug!("PlayRecordedMacro hotkey triggered. Playing recorded macro.");
+                let events = emu.macros.recorded().to_vec();
+                emu.machine.play_keys(&events);
+            }
+            HotkeyEvent::SaveState => {
+                let slot = emu.state_slot;
+                log::debug!("SaveState hotkey triggered. Saving state to slot {}.", slot + 1);
+                save_state(emu, slot);
+            }
+            HotkeyEvent::LoadState => {
+                let slot = emu.state_slot;
+                log::debug!("LoadState hotkey triggered. Loading state from slot {}.", slot + 1);
+                load_state(emu, slot);
+            }
+            HotkeyEvent::Rewind => match emu.rewind() {
+                Ok(remaining) => {
+                    log::debug!("Rewind hotkey triggered. {} rewind snapshots remaining.", remaining);
+                }
+                Err(err) => {
+                    emu.gui
+                        .toasts()
+                        .error(format!("Rewind failed: {}", err))
+                        .set_duration(Some(SHORT_NOTIFICATION_TIME));
+                }
+            },
             _ => {
                 log::debug!("Unhandled Hotkey triggered: {:?}", hotkey);
             }
@@ -296,19 +365,17 @@ pub fn process_joykeys(
     });
 
     if let Some(key) = joykey {
-        if let Some(gameport) = emu.machine.bus_mut().game_port_mut() {
-            match key {
-                JoyKeyInput::JoyButton1 => {
-                    gameport.set_button(0, 0, pressed);
-                }
-                JoyKeyInput::JoyButton2 => {
-                    gameport.set_button(0, 1, pressed);
-                }
-                _ => {
-                    // Update the stick position
-                    let (x, y) = emu.joy_data.get_xy();
-                    gameport.set_stick_pos(0, 0, Some(x), Some(y));
-                }
+        match key {
+            JoyKeyInput::JoyButton1 => {
+                emu.machine.joystick_button(0, 0, pressed);
+            }
+            JoyKeyInput::JoyButton2 => {
+                emu.machine.joystick_button(0, 1, pressed);
+            }
+            _ => {
+                // Update the stick position
+                let (x, y) = emu.joy_data.get_xy();
+                emu.machine.joystick_position(0, 0, Some(x), Some(y));
             }
         }
     }
@@ -0,0 +1,90 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    event_loop/light_pen.rs
+
+    Drive the emulated light pen from the host mouse cursor.
+
+*/
+
+use winit::window::WindowId;
+
+use display_manager_wgpu::DisplayManager;
+use marty_egui::GuiBoolean;
+
+use crate::Emulator;
+
+/// Update the light pen position from the cursor position within the specified window.
+/// A position of None indicates the cursor has left the window.
+pub fn handle_light_pen_cursor(emu: &mut Emulator, wid: WindowId, pos: Option<(f32, f32)>) {
+    if !emu.gui.get_option(GuiBoolean::LightPen).unwrap_or(false) {
+        return;
+    }
+    emu.mouse_data.light_pen = pos.and_then(|(x, y)| emu.dm.window_pos_to_display_pos(wid, x, y));
+    update_light_pen(emu);
+}
+
+/// Update the state of the light pen switch.
+pub fn handle_light_pen_button(emu: &mut Emulator, pressed: bool) {
+    if !emu.gui.get_option(GuiBoolean::LightPen).unwrap_or(false) {
+        return;
+    }
+    emu.mouse_data.light_pen_down = pressed;
+    update_light_pen(emu);
+}
+
+/// Send the current light pen state to each video card. Only the card displayed under the cursor
+/// sees the pen; to every other card it is pointed away from the screen.
+pub fn update_light_pen(emu: &mut Emulator) {
+    let pen = if emu.gui.get_option(GuiBoolean::LightPen).unwrap_or(false) {
+        emu.mouse_data.light_pen
+    }
+    else {
+        None
+    };
+    let switch = pen.is_some() && emu.mouse_data.light_pen_down;
+
+    // The cursor position is relative to the displayed aperture, so resolve the aperture selected
+    // in the renderer to translate it into video field coordinates.
+    let aperture = pen.and_then(|(vid, _, _)| {
+        emu.dm
+            .get_renderer_by_card_id(vid)
+            .map(|renderer| renderer.get_params().aperture as usize)
+    });
+
+    emu.machine.for_each_videocard(|vci| match (pen, aperture) {
+        (Some((vid, x, y)), Some(aperture)) if vid == vci.id => {
+            let field_pos = vci
+                .card
+                .get_display_extents()
+                .apertures
+                .get(aperture)
+                .map(|ap| (ap.x + (x * ap.w as f32) as u32, ap.y + (y * ap.h as f32) as u32));
+            vci.card.set_light_pen(field_pos, switch);
+        }
+        _ => vci.card.set_light_pen(None, false),
+    });
+}
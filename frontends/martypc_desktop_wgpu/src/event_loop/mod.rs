@@ -33,11 +33,14 @@
 
 mod egui_events;
 mod egui_update;
+mod fullscreen;
 mod keyboard;
+mod light_pen;
 mod render_frame;
 mod update;
 
 use keyboard::handle_modifiers;
+use light_pen::{handle_light_pen_button, handle_light_pen_cursor};
 
 use std::time::Instant;
 use winit::{
@@ -178,6 +181,22 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                 } => {
                     pass_to_egui = !handle_key_event(emu, window_id, key_event);
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    handle_light_pen_cursor(emu, window_id, Some((position.x as f32, position.y as f32)));
+                    pass_to_egui = true;
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    handle_light_pen_cursor(emu, window_id, None);
+                    pass_to_egui = true;
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: winit::event::MouseButton::Left,
+                    ..
+                } => {
+                    handle_light_pen_button(emu, state == ElementState::Pressed);
+                    pass_to_egui = true;
+                }
                 WindowEvent::RedrawRequested => {
                     process_update(emu, tm, elwt);
                 }
@@ -228,6 +247,22 @@ pub fn handle_event(emu: &mut Emulator, tm: &mut TimestepManager, event: Event<(
                 None
             });
         }
+        Event::LoopExiting => {
+            // Finalize any recording in progress so that the file is playable.
+            if let Err(e) = emu.stop_video_recording() {
+                log::error!("Failed to finish recording: {}", e);
+            }
+            if let Err(e) = emu.stop_audio_recording() {
+                log::error!("Failed to finish audio recording: {}", e);
+            }
+            // Write-back is rate-limited, so the latest changes to a disk may not have been written yet.
+            let drive_ct = emu.machine.fdc().as_ref().map_or(0, |fdc| fdc.drive_ct());
+            for drive in 0..drive_ct {
+                if let Err(e) = emu.flush_floppy(drive) {
+                    log::error!("Failed to write floppy image in drive {}: {}", drive, e);
+                }
+            }
+        }
         _ => (),
     }
 }
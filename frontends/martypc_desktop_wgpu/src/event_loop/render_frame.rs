@@ -32,10 +32,14 @@
 use crate::Emulator;
 use display_backend_pixels::DisplayBackend;
 use display_manager_wgpu::DisplayManager;
+use frontend_common::constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME};
 use marty_core::{device_traits::videocard::BufferSelect, machine::ExecutionState};
 use marty_egui::GuiBoolean;
 
 pub fn render_frame(emu: &mut Emulator) {
+    let mut record_error = None;
+    let mut clip_full = false;
+
     // First, run each renderer to resolve all videocard views.
     // Every renderer will have an associated card and backend.
     emu.dm.for_each_renderer(|renderer, vid, backend_buf| {
@@ -75,10 +79,62 @@ pub fn render_frame(emu: &mut Emulator) {
                 backend_buf,
                 extents,
                 beam_pos,
-            )
+            );
+
+            // If we are recording this card, add the rendered frame to the recording.
+            if emu.recorder_card == Some(vid) {
+                if let Some(recorder) = &mut emu.recorder {
+                    let audio = emu.machine.take_audio_capture();
+                    let dim = renderer.get_params().backend;
+                    if let Err(e) = recorder
+                        .push_audio(&audio)
+                        .and_then(|_| recorder.push_frame(backend_buf, dim.w, dim.h))
+                    {
+                        record_error = Some(e);
+                    }
+                }
+            }
+
+            if emu.clip_card == Some(vid) {
+                if let Some(clip_recorder) = &mut emu.clip_recorder {
+                    let dim = renderer.get_params().backend;
+                    clip_recorder.push_frame(backend_buf, dim.w, dim.h);
+                    clip_full = clip_recorder.is_full();
+                }
+            }
         }
     });
 
+    if let Some(e) = record_error {
+        log::error!("Error recording video: {}", e);
+        emu.gui
+            .toasts()
+            .error(format!("Recording stopped: {}", e))
+            .set_duration(Some(LONG_NOTIFICATION_TIME));
+        if let Err(e) = emu.stop_video_recording() {
+            log::error!("Failed to finish recording: {}", e);
+        }
+    }
+
+    if clip_full {
+        match emu.finish_clip_capture() {
+            Ok(Some(path)) => {
+                emu.gui
+                    .toasts()
+                    .info(format!("Saved clip to {}", path.display()))
+                    .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Failed to save clip: {}", e);
+                emu.gui
+                    .toasts()
+                    .error(format!("Failed to save clip: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+            }
+        }
+    }
+
     // Prepare guis for rendering.
     emu.dm.for_each_gui(|gui, window| gui.prepare(window, &mut emu.gui));
 
@@ -66,41 +66,79 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
             emuc.perf = perf;
 
             // Per frame freq
-            if let Some(mouse) = emuc.machine.mouse_mut() {
-                // Send any pending mouse update to machine if mouse is captured
-                if emuc.mouse_data.is_captured && emuc.mouse_data.have_update {
-                    mouse.update(
-                        emuc.mouse_data.l_button_was_pressed,
-                        emuc.mouse_data.r_button_was_pressed,
-                        emuc.mouse_data.frame_delta_x,
-                        emuc.mouse_data.frame_delta_y,
-                    );
-
-                    // Handle release event
-                    let l_release_state = if emuc.mouse_data.l_button_was_released {
-                        false
-                    }
-                    else {
-                        emuc.mouse_data.l_button_was_pressed
-                    };
+            // Send any pending mouse update to machine if mouse is captured
+            if emuc.mouse_data.is_captured && emuc.mouse_data.have_update {
+                // Handle release event
+                let l_release_state = if emuc.mouse_data.l_button_was_released {
+                    false
+                }
+                else {
+                    emuc.mouse_data.l_button_was_pressed
+                };
 
-                    let r_release_state = if emuc.mouse_data.r_button_was_released {
-                        false
-                    }
-                    else {
-                        emuc.mouse_data.r_button_was_pressed
-                    };
+                let r_release_state = if emuc.mouse_data.r_button_was_released {
+                    false
+                }
+                else {
+                    emuc.mouse_data.r_button_was_pressed
+                };
 
-                    if emuc.mouse_data.l_button_was_released || emuc.mouse_data.r_button_was_released {
-                        // Send release event
-                        mouse.update(l_release_state, r_release_state, 0.0, 0.0);
-                    }
+                let have_release = emuc.mouse_data.l_button_was_released || emuc.mouse_data.r_button_was_released;
+
+                emuc.machine.mouse_update(
+                    emuc.mouse_data.l_button_was_pressed,
+                    emuc.mouse_data.r_button_was_pressed,
+                    emuc.mouse_data.frame_delta_x,
+                    emuc.mouse_data.frame_delta_y,
+                );
 
-                    // Reset mouse for next frame
-                    emuc.mouse_data.reset();
+                if have_release {
+                    // Send release event
+                    emuc.machine.mouse_update(l_release_state, r_release_state, 0.0, 0.0);
                 }
+
+                // Reset mouse for next frame
+                emuc.mouse_data.reset();
+            }
+
+            // Apply any pending host gamepad input to the game port
+            #[cfg(feature = "gamepad")]
+            emuc.gamepad_data.poll(&mut emuc.machine);
+
+            // Send any MIDI output to the host MIDI device
+            #[cfg(feature = "midi")]
+            emuc.midi_data.poll(emuc.machine.bus_mut().mpu401_mut().as_mut());
+
+            // Call the handlers of any running scripts
+            #[cfg(feature = "scripting")]
+            crate::scripting::update_scripts(emuc);
+
+            // Write any new audio to the current audio recording
+            if let Err(e) = emuc.update_audio_recording() {
+                log::error!("Error recording audio: {}", e);
+                emuc.gui
+                    .toasts()
+                    .error(format!("Audio recording stopped: {}", e))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
+                if let Err(e) = emuc.stop_audio_recording() {
+                    log::error!("Failed to finish audio recording: {}", e);
+                }
+            }
+
+            // Write modified floppy images back to their image files
+            for (drive, e) in emuc.update_floppy_write_back() {
+                log::error!("Error writing floppy image in drive {}: {}", drive, e);
+                emuc.gui
+                    .toasts()
+                    .error(format!(
+                        "Floppy write-back disabled for drive {}: {}. Changes are kept in memory.",
+                        drive, e
+                    ))
+                    .set_duration(Some(LONG_NOTIFICATION_TIME));
             }
 
+            emuc.gui.set_replay_status(emuc.machine.replay_status());
+
             // Drain machine events
             while let Some(event) = emuc.machine.get_event() {
                 match event {
@@ -169,6 +207,25 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                             .error("CPU permanently halted!".to_string())
                             .set_duration(Some(LONG_NOTIFICATION_TIME));
                     }
+                    MachineEvent::ExitRequested(code) => {
+                        log::info!("Guest wrote exit code {} to the exit port.", code);
+                        emuc.gui
+                            .toasts()
+                            .info(format!("Guest requested exit with code {}", code))
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                    }
+                    MachineEvent::ReplayFinished(true) => {
+                        emuc.gui
+                            .toasts()
+                            .info("Playback finished.".to_string())
+                            .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+                    }
+                    MachineEvent::ReplayFinished(false) => {
+                        emuc.gui
+                            .toasts()
+                            .warning("Playback finished, but the machine state differs from the recording.".to_string())
+                            .set_duration(Some(LONG_NOTIFICATION_TIME));
+                    }
                 }
             }
 
@@ -195,6 +252,9 @@ pub fn process_update(emu: &mut Emulator, tm: &mut TimestepManager, elwt: &Event
                 }
             }
 
+            // Take a rewind snapshot, if due
+            emuc.update_rewind();
+
             // Resize windows
             if let Err(err) = emuc.dm.resize_windows() {
                 log::error!("Error resizing windows: {}", err);
@@ -0,0 +1,196 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    gamepad.rs
+
+    Map host gamepads to the emulated game port via gilrs.
+    The first two connected gamepads are assigned to game port controllers
+    0 and 1, in connection order.
+*/
+
+use std::collections::HashMap;
+
+use frontend_common::{types::gamepad::GamePortAxis, GamepadConfig};
+use gilrs::{EventType, GamepadId, Gilrs};
+use marty_core::machine::Machine;
+
+pub const MAX_GAMEPADS: usize = 2;
+
+struct AxisMapping {
+    axis:   GamePortAxis,
+    invert: bool,
+    center: f64,
+    scale:  f64,
+}
+
+pub struct GamepadData {
+    gilrs: Option<Gilrs>,
+    deadzone: f64,
+    axes: HashMap<String, AxisMapping>,
+    buttons: HashMap<String, usize>,
+    slots: [Option<GamepadId>; MAX_GAMEPADS],
+}
+
+impl GamepadData {
+    pub fn new(config: &GamepadConfig) -> Self {
+        let gilrs = if config.enabled {
+            match Gilrs::new() {
+                Ok(gilrs) => Some(gilrs),
+                Err(e) => {
+                    log::error!("Failed to initialize gamepad support: {}", e);
+                    None
+                }
+            }
+        }
+        else {
+            None
+        };
+
+        let axes = config
+            .axes
+            .iter()
+            .map(|entry| {
+                (
+                    entry.host_axis.clone(),
+                    AxisMapping {
+                        axis:   entry.axis,
+                        invert: entry.invert,
+                        center: entry.center.unwrap_or(0.0),
+                        scale:  entry.scale.unwrap_or(1.0),
+                    },
+                )
+            })
+            .collect();
+
+        let buttons = config
+            .buttons
+            .iter()
+            .map(|entry| (entry.host_button.clone(), entry.button))
+            .collect();
+
+        let mut gd = GamepadData {
+            gilrs,
+            deadzone: config.deadzone.clamp(0.0, 1.0),
+            axes,
+            buttons,
+            slots: [None; MAX_GAMEPADS],
+        };
+
+        // Gamepads that were connected before startup do not produce a Connected event.
+        let ids: Vec<GamepadId> = gd
+            .gilrs
+            .as_ref()
+            .map(|gilrs| gilrs.gamepads().map(|(id, _)| id).collect())
+            .unwrap_or_default();
+        for id in ids {
+            gd.connect(id);
+        }
+        gd
+    }
+
+    fn connect(&mut self, id: GamepadId) -> Option<usize> {
+        if let Some(slot) = self.slot(id) {
+            return Some(slot);
+        }
+        let slot = self.slots.iter().position(|s| s.is_none())?;
+        self.slots[slot] = Some(id);
+        if let Some(gilrs) = &self.gilrs {
+            log::info!(
+                "Gamepad \"{}\" assigned to game port controller {}",
+                gilrs.gamepad(id).name(),
+                slot
+            );
+        }
+        Some(slot)
+    }
+
+    fn slot(&self, id: GamepadId) -> Option<usize> {
+        self.slots.iter().position(|s| *s == Some(id))
+    }
+
+    fn calibrate(&self, mapping: &AxisMapping, value: f32) -> f64 {
+        let mut value = value as f64;
+        if value.abs() < self.deadzone {
+            value = 0.0;
+        }
+        let pos = ((value - mapping.center) * mapping.scale).clamp(-1.0, 1.0);
+        if mapping.invert {
+            -pos
+        }
+        else {
+            pos
+        }
+    }
+
+    /// Drain pending gamepad events and apply them to the game port. Should be called once per frame.
+    pub fn poll(&mut self, machine: &mut Machine) {
+        let mut events = Vec::new();
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+                events.push((id, event));
+            }
+        }
+        if events.is_empty() || machine.bus_mut().game_port_mut().is_none() {
+            return;
+        }
+
+        for (id, event) in events {
+            match event {
+                EventType::Connected => {
+                    self.connect(id);
+                }
+                EventType::Disconnected => {
+                    if let Some(slot) = self.slot(id) {
+                        log::info!("Gamepad disconnected from game port controller {}", slot);
+                        self.slots[slot] = None;
+                        // Center the stick and release buttons so the guest doesn't see a stuck input.
+                        machine.joystick_position(slot, 0, Some(0.0), Some(0.0));
+                        machine.joystick_button(slot, 0, false);
+                        machine.joystick_button(slot, 1, false);
+                    }
+                }
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    let pressed = matches!(event, EventType::ButtonPressed(..));
+                    let slot = self.connect(id);
+                    if let (Some(slot), Some(&gp_button)) = (slot, self.buttons.get(&format!("{:?}", button))) {
+                        machine.joystick_button(slot, gp_button, pressed);
+                    }
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    let slot = self.connect(id);
+                    if let (Some(slot), Some(mapping)) = (slot, self.axes.get(&format!("{:?}", axis))) {
+                        let pos = self.calibrate(mapping, value);
+                        match mapping.axis {
+                            GamePortAxis::X => machine.joystick_position(slot, 0, Some(pos), None),
+                            GamePortAxis::Y => machine.joystick_position(slot, 0, None, Some(pos)),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
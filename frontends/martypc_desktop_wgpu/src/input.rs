@@ -33,8 +33,12 @@
     https://w3c.github.io/uievents-code/#code-value-tables
 */
 
-use frontend_common::{HotkeyConfigEntry, HotkeyEvent, HotkeyScope};
-use marty_core::keys::MartyKey;
+use frontend_common::{HotkeyConfigEntry, HotkeyEvent, HotkeyScope, KeyMacroEntry};
+use marty_core::{
+    devices::keyboard::{Keyboard, KeyboardModifiers},
+    keys::MartyKey,
+    machine::KeybufferEntry,
+};
 use std::{
     collections::{HashMap, HashSet},
     env::consts::OS,
@@ -68,6 +72,44 @@ impl Default for HotkeyState {
     }
 }
 
+impl HotkeyState {
+    pub fn new(keyvec: Vec<MartyKey>, scope: HotkeyScope, capture_disable: bool) -> Self {
+        HotkeyState {
+            len: keyvec.len(),
+            keyset: HashSet::from_iter(keyvec.iter().cloned()),
+            pressed: HashSet::new(),
+            scope,
+            capture_disable,
+        }
+    }
+
+    /// Register a key press. Returns true if the press completes this hotkey's key combination.
+    pub fn keydown(&mut self, key: MartyKey, gui_focus: bool, input_captured: bool) -> bool {
+        let mut process_key = match self.scope {
+            HotkeyScope::Any => true,
+            HotkeyScope::Gui => gui_focus,
+            HotkeyScope::Machine => !gui_focus,
+            HotkeyScope::Captured => input_captured,
+        };
+
+        if self.capture_disable && input_captured {
+            process_key = false;
+        }
+
+        if process_key && self.keyset.contains(&key) {
+            self.pressed.insert(key);
+            return self.pressed.len() == self.len;
+        }
+        false
+    }
+
+    pub fn keyup(&mut self, key: MartyKey) {
+        if self.keyset.contains(&key) {
+            self.pressed.remove(&key);
+        }
+    }
+}
+
 pub struct HotkeyManager {
     pub hotkeys: HashMap<HotkeyEvent, HotkeyState>,
 }
@@ -100,39 +142,24 @@ impl HotkeyManager {
         scope: HotkeyScope,
         capture_disable: bool,
     ) {
-        let len = keyvec.len();
-        self.hotkeys.insert(
-            hotkey,
-            HotkeyState {
-                keyset: HashSet::from_iter(keyvec.iter().cloned()),
-                pressed: HashSet::new(),
-                scope,
-                capture_disable,
-                len,
-            },
-        );
+        self.hotkeys
+            .insert(hotkey, HotkeyState::new(keyvec, scope, capture_disable));
+    }
+
+    /// Return the keys of the specified hotkey that are currently held down.
+    pub fn held_keys(&self, hotkey: HotkeyEvent) -> HashSet<MartyKey> {
+        self.hotkeys
+            .get(&hotkey)
+            .map(|state| state.pressed.clone())
+            .unwrap_or_default()
     }
 
     pub fn keydown(&mut self, key: MartyKey, gui_focus: bool, input_captured: bool) -> Option<Vec<HotkeyEvent>> {
         let mut events = Vec::new();
         for (hotkey, state) in self.hotkeys.iter_mut() {
-            let mut process_key = match state.scope {
-                HotkeyScope::Any => true,
-                HotkeyScope::Gui => gui_focus,
-                HotkeyScope::Machine => !gui_focus,
-                HotkeyScope::Captured => input_captured,
-            };
-
-            if state.capture_disable && input_captured {
-                process_key = false;
-            }
-
-            if process_key && state.keyset.contains(&key) {
-                state.pressed.insert(key);
-                if state.pressed.len() == state.len {
-                    log::debug!("Hotkey matched: {:?}, len: {}", hotkey, state.len);
-                    events.push(*hotkey);
-                }
+            if state.keydown(key, gui_focus, input_captured) {
+                log::debug!("Hotkey matched: {:?}, len: {}", hotkey, state.len);
+                events.push(*hotkey);
             }
         }
 
@@ -146,11 +173,148 @@ impl HotkeyManager {
 
     pub fn keyup(&mut self, key: MartyKey) {
         for state in self.hotkeys.values_mut() {
-            if state.keyset.contains(&key) {
-                state.pressed.remove(&key);
+            state.keyup(key);
+        }
+    }
+}
+
+pub struct KeyMacro {
+    pub name:   String,
+    pub events: Vec<KeybufferEntry>,
+    pub hotkey: HotkeyState,
+    pub armed:  bool,
+}
+
+/// Manages keyboard macros defined in the configuration, and a single macro that can be recorded
+/// live from the host keyboard.
+///
+/// A macro is played once all keys of its hotkey have been released, so that the hotkey's own
+/// modifier keys do not affect the keys typed into the machine.
+#[derive(Default)]
+pub struct MacroManager {
+    macros: Vec<KeyMacro>,
+    recording: Option<Vec<KeybufferEntry>>,
+    recording_ignore: HashSet<MartyKey>,
+    recorded: Vec<KeybufferEntry>,
+}
+
+impl MacroManager {
+    pub fn new() -> Self {
+        MacroManager::default()
+    }
+
+    pub fn add_macros(&mut self, macro_list: Vec<KeyMacroEntry>) {
+        for entry in macro_list {
+            let mut events = match Keyboard::keycodes_from_strings(&entry.keys, false) {
+                Ok(events) => events,
+                Err(e) => {
+                    log::error!("Invalid key list in macro '{}': {}", entry.name, e);
+                    continue;
+                }
+            };
+            if let Some(text) = &entry.text {
+                events.extend(Keyboard::keycodes_from_text(text));
             }
+
+            log::debug!("Adding macro '{}' with {} key events", entry.name, events.len());
+            self.macros.push(KeyMacro {
+                name: entry.name,
+                events,
+                hotkey: HotkeyState::new(entry.hotkey, entry.scope.unwrap_or(HotkeyScope::Machine), false),
+                armed: false,
+            });
         }
     }
+
+    pub fn keydown(&mut self, key: MartyKey, gui_focus: bool, input_captured: bool) {
+        for key_macro in self.macros.iter_mut() {
+            if key_macro.hotkey.keydown(key, gui_focus, input_captured) {
+                log::debug!("Macro hotkey matched: {}", key_macro.name);
+                key_macro.armed = true;
+            }
+        }
+    }
+
+    /// Register a key release. Returns the key events of any macro whose hotkey has been fully
+    /// released.
+    pub fn keyup(&mut self, key: MartyKey) -> Vec<KeybufferEntry> {
+        let mut events = Vec::new();
+        for key_macro in self.macros.iter_mut() {
+            key_macro.hotkey.keyup(key);
+            if key_macro.armed && key_macro.hotkey.pressed.is_empty() {
+                key_macro.armed = false;
+                log::debug!("Playing macro: {}", key_macro.name);
+                events.extend(key_macro.events.iter().copied());
+            }
+        }
+        events
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Begin recording a macro. Keys in `held` are currently held down (typically the hotkey that
+    /// started recording) and are ignored until they are released.
+    pub fn start_recording(&mut self, held: HashSet<MartyKey>) {
+        self.recording = Some(Vec::new());
+        self.recording_ignore = held;
+    }
+
+    /// Stop recording and keep the recorded macro. Key presses that were never released and
+    /// releases of keys that were pressed before recording began are discarded. Returns the
+    /// number of recorded key events.
+    pub fn stop_recording(&mut self) -> usize {
+        let recording = self.recording.take().unwrap_or_default();
+
+        let mut recorded = Vec::new();
+        for (i, event) in recording.iter().enumerate() {
+            let keep = if event.pressed {
+                recording[i + 1..]
+                    .iter()
+                    .any(|later| later.keycode == event.keycode && !later.pressed)
+            }
+            else {
+                recording[..i]
+                    .iter()
+                    .any(|earlier| earlier.keycode == event.keycode && earlier.pressed)
+            };
+            if keep {
+                recorded.push(*event);
+            }
+        }
+
+        let keys: Vec<String> = recorded
+            .iter()
+            .map(|event| format!("{}{:?}", if event.pressed { "+" } else { "-" }, event.keycode))
+            .collect();
+        log::info!("Recorded macro: keys = {:?}", keys);
+
+        self.recorded = recorded;
+        self.recorded.len()
+    }
+
+    /// Record a key event sent to the machine, if recording.
+    pub fn record(&mut self, keycode: MartyKey, pressed: bool, modifiers: KeyboardModifiers) {
+        if let Some(recording) = &mut self.recording {
+            if self.recording_ignore.contains(&keycode) {
+                if !pressed {
+                    self.recording_ignore.remove(&keycode);
+                }
+                return;
+            }
+            recording.push(KeybufferEntry {
+                keycode,
+                pressed,
+                modifiers,
+                translate: true,
+            });
+        }
+    }
+
+    pub fn recorded(&self) -> &[KeybufferEntry] {
+        &self.recorded
+    }
 }
 
 pub trait TranslateKey {
@@ -39,9 +39,16 @@
 mod cpu_test;
 mod emulator;
 mod event_loop;
+#[cfg(feature = "gamepad")]
+mod gamepad;
 mod input;
+#[cfg(feature = "midi")]
+mod midi;
 mod run_benchmark;
+mod run_convert;
 mod run_headless;
+#[cfg(feature = "scripting")]
+mod scripting;
 
 #[cfg(feature = "arduino_validator")]
 mod run_fuzzer;
@@ -67,15 +74,20 @@ use marty_core::cpu_validator::ValidatorType;
 use config_toml_bpaf::TestMode;
 
 use marty_core::{
+    device_traits::videocard::VideoCardId,
     devices::keyboard::KeyboardModifiers,
     machine::{ExecutionControl, ExecutionState, MachineBuilder},
+    rewind::RewindBuffer,
     sound::SoundPlayer,
 };
 
 use display_manager_wgpu::{DisplayBackend, DisplayManager, DisplayManagerGuiOptions, WgpuDisplayManagerBuilder};
 use frontend_common::{
     cartridge_manager::CartridgeManager,
+    cassette_manager::CassetteManager,
+    cdrom_manager::CdromManager,
     floppy_manager::FloppyManager,
+    media_manager::MediaManager,
     resource_manager::ResourceManager,
     timestep_manager::TimestepManager,
     types::joykeys::JoyKeyInput,
@@ -86,9 +98,9 @@ use marty_core::keys::MartyKey;
 use marty_egui::state::GuiState;
 
 use crate::{
-    emulator::{EmuFlags, Emulator},
+    emulator::{EmuFlags, Emulator, REWIND_SNAPSHOTS_DEFAULT},
     event_loop::handle_event,
-    input::HotkeyManager,
+    input::{HotkeyManager, MacroManager},
 };
 
 pub const FPS_TARGET: f64 = 60.0;
@@ -182,6 +194,8 @@ pub struct MouseData {
     pub r_button_is_pressed: bool,
     pub frame_delta_x: f64,
     pub frame_delta_y: f64,
+    pub light_pen: Option<(VideoCardId, f32, f32)>,
+    pub light_pen_down: bool,
 }
 
 impl MouseData {
@@ -200,6 +214,8 @@ impl MouseData {
             r_button_is_pressed: false,
             frame_delta_x: 0.0,
             frame_delta_y: 0.0,
+            light_pen: None,
+            light_pen_down: false,
         }
     }
     pub fn reset(&mut self) {
@@ -317,6 +333,11 @@ pub fn run() {
         },
     };
 
+    // If a floppy image conversion was requested, perform it now. It doesn't need any resources.
+    if let Some(input) = &config.emulator.convert_floppy {
+        return run_convert::run_convert(&config, input);
+    }
+
     // Now that we have our configuration, we can instantiate a ResourceManager.
     let mut resource_manager = ResourceManager::from_config(config.emulator.basedir.clone(), &config.emulator.paths)
         .unwrap_or_else(|e| {
@@ -530,6 +551,18 @@ pub fn run() {
         std::process::exit(1);
     }
 
+    // Instantiate the cassette manager. Older configurations may not define a cassette path.
+    let mut cassette_manager = CassetteManager::new();
+    if let Err(e) = cassette_manager.scan_resource(&resource_manager) {
+        log::warn!("Failed to read cassette path: {:?}", e);
+    }
+
+    // Instantiate the CD-ROM manager. Older configurations may not define a cdrom path.
+    let mut cdrom_manager = CdromManager::new();
+    if let Err(e) = cdrom_manager.scan_resource(&resource_manager) {
+        log::warn!("Failed to read cdrom path: {:?}", e);
+    }
+
     // Enumerate host serial ports
     let serial_ports = serialport::available_ports().unwrap_or_else(|e| {
         log::warn!("Didn't find any serial ports: {:?}", e);
@@ -581,7 +614,13 @@ pub fn run() {
 
     // If headless mode was specified, run the emulator in headless mode now
     if config.emulator.headless {
-        //return run_headless::run_headless(&config, rom_manager, floppy_manager);
+        return run_headless::run_headless(
+            &config,
+            machine_config_file,
+            rom_manifest,
+            resource_manager,
+            vhd_manager,
+        );
     }
 
     // ----------------------------------------------------------------------------
@@ -591,6 +630,9 @@ pub fn run() {
     let mut hotkey_manager = HotkeyManager::new();
     hotkey_manager.add_hotkeys(config.emulator.input.hotkeys.clone());
 
+    let mut macro_manager = MacroManager::new();
+    macro_manager.add_macros(config.emulator.input.macros.clone());
+
     // ExecutionControl is shared via RefCell with GUI so that state can be updated by control widget
     let exec_control = Rc::new(RefCell::new(ExecutionControl::new()));
 
@@ -617,10 +659,12 @@ pub fn run() {
             // The cpal sound library uses generics to initialize depending on the SampleFormat type.
             // On Windows at least a sample type of f32 is typical, but just in case...
             let (audio_device, sample_fmt) = SoundPlayer::get_device();
+            let buffer_ms = config.emulator.audio.buffer_ms;
+            let device_buffer = config.emulator.audio.device_buffer;
             let sp = match sample_fmt {
-                cpal::SampleFormat::F32 => SoundPlayer::new::<f32>(audio_device),
-                cpal::SampleFormat::I16 => SoundPlayer::new::<i16>(audio_device),
-                cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(audio_device),
+                cpal::SampleFormat::F32 => SoundPlayer::new::<f32>(audio_device, buffer_ms, device_buffer),
+                cpal::SampleFormat::I16 => SoundPlayer::new::<i16>(audio_device, buffer_ms, device_buffer),
+                cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(audio_device, buffer_ms, device_buffer),
             };
             Some(sp)
         }
@@ -679,11 +723,25 @@ pub fn run() {
         .with_keyboard_layout(kb_layout_file_path)
         .with_listing_file(disassembly_file_path);
 
-    let machine = machine_builder.build().unwrap_or_else(|e| {
+    let mut machine = machine_builder.build().unwrap_or_else(|e| {
         log::error!("Failed to build machine: {:?}", e);
         std::process::exit(1);
     });
 
+    if let Some(paste_delay) = config.emulator.input.paste_delay {
+        machine.set_paste_delay(paste_delay);
+    }
+
+    // Direct print jobs to the print output directory.
+    if let Some(print_path) = resource_manager.get_resource_path("print") {
+        machine.set_printer_output_dir(print_path);
+    }
+
+    // Replace the default floppy drive sounds with any found in the sound directory.
+    if let Some(sound_path) = resource_manager.get_resource_path("sound") {
+        machine.load_drive_sounds(&sound_path);
+    }
+
     // Get a list of video devices from machine.
     let cardlist = machine.bus().enumerate_videocards();
 
@@ -730,6 +788,14 @@ pub fn run() {
         config.emulator.input.keyboard_joystick,
     );
 
+    // Create host gamepad state
+    #[cfg(feature = "gamepad")]
+    let gamepad_data = gamepad::GamepadData::new(&config.emulator.input.gamepad);
+
+    // Open host MIDI output
+    #[cfg(feature = "midi")]
+    let midi_data = midi::MidiData::new(&config.emulator.midi);
+
     // Create GUI state
     let render_egui = true;
     let gui = GuiState::new(exec_control.clone());
@@ -741,6 +807,12 @@ pub fn run() {
 
     let machine_events = Vec::new();
 
+    let rewind = config
+        .emulator
+        .rewind
+        .enabled
+        .then(|| RewindBuffer::new(config.emulator.rewind.snapshots.unwrap_or(REWIND_SNAPSHOTS_DEFAULT)));
+
     // Put everything we want to handle in event loop into an Emulator struct
     let mut emu = Emulator {
         rm: resource_manager,
@@ -754,22 +826,69 @@ pub fn run() {
         mouse_data,
         kb_data,
         joy_data,
+        #[cfg(feature = "gamepad")]
+        gamepad_data,
+        #[cfg(feature = "midi")]
+        midi_data,
+        #[cfg(feature = "scripting")]
+        scripts: scripting::ScriptHost::new(),
         stat_counter,
         gui,
         floppy_manager,
         vhd_manager,
         cart_manager,
+        cassette_manager,
+        cassette_path: None,
+        cdrom_manager,
+        media_manager: MediaManager::default(),
         perf: Default::default(),
         flags: EmuFlags {
             render_gui: render_egui,
             debug_keyboard: false,
         },
         hkm: hotkey_manager,
+        macros: macro_manager,
+        recorder: None,
+        recorder_card: None,
+        clip_recorder: None,
+        clip_card: None,
+        audio_recorder: None,
+        source_recorders: Vec::new(),
+        floppy_write_back_time: Instant::now(),
+        floppy_directories: HashMap::new(),
+        state_slot: 0,
+        rewind,
+        rewind_frames: 0,
+        rewind_instructions: 0,
     };
 
     // Resize video cards
     emu.post_dm_build_init();
 
+    // Lock emulated frames to the host refresh rate, if requested.
+    if emu.config.emulator.backend.frame_pacing {
+        let host_rate = emu
+            .dm
+            .get_main_window()
+            .and_then(|window| window.current_monitor())
+            .and_then(|monitor| monitor.refresh_rate_millihertz());
+
+        match host_rate {
+            Some(millihertz) if emu.config.emulator.backend.vsync => {
+                let host_rate = (millihertz + 500) / 1000;
+                timestep_manager.set_frame_pacing(host_rate, highest_rate);
+                emu.machine
+                    .set_audio_rate_factor(millihertz as f64 / 1000.0 / highest_rate as f64);
+            }
+            Some(_) => {
+                log::warn!("Frame pacing requires vsync to be enabled. Frame pacing disabled.");
+            }
+            None => {
+                log::warn!("Couldn't determine host refresh rate. Frame pacing disabled.");
+            }
+        }
+    }
+
     // Set list of host serial ports
     emu.gui.set_host_serial_ports(serial_ports);
 
@@ -811,6 +930,10 @@ pub fn run() {
         std::process::exit(1);
     }
 
+    // Run any startup scripts
+    #[cfg(feature = "scripting")]
+    scripting::run_startup_scripts(&mut emu);
+
     // Start emulator
     emu.start();
 
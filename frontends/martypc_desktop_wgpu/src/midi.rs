@@ -0,0 +1,256 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    midi.rs
+
+    Forward MIDI output from the emulated MPU-401 to a host MIDI device via
+    midir. The MPU-401 produces a raw byte stream, which is assembled into
+    complete messages (expanding running status) before sending, as some
+    host MIDI APIs only accept whole messages.
+*/
+
+use config_toml_bpaf::Midi;
+use marty_core::devices::mpu401::Mpu401;
+use midir::{MidiOutput, MidiOutputConnection};
+
+const MIDI_CLIENT_NAME: &str = "MartyPC";
+const MIDI_CC_ALL_NOTES_OFF: u8 = 123;
+
+/// Assembles a raw MIDI byte stream into complete messages.
+#[derive(Default)]
+struct MidiParser {
+    message: Vec<u8>,
+    running_status: Option<u8>,
+    expected: usize,
+    in_sysex: bool,
+}
+
+impl MidiParser {
+    /// Return the number of data bytes following the specified status byte.
+    fn data_len(status: u8) -> usize {
+        match status {
+            0x80..=0xBF | 0xE0..=0xEF => 2,
+            0xC0..=0xDF => 1,
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            _ => 0,
+        }
+    }
+
+    /// Feed a byte to the parser, calling `send` with each complete message.
+    fn feed(&mut self, byte: u8, mut send: impl FnMut(&[u8])) {
+        match byte {
+            // Real-time messages may appear anywhere, even within other messages.
+            0xF8..=0xFF => send(&[byte]),
+            0xF0 => {
+                self.message.clear();
+                self.message.push(byte);
+                self.in_sysex = true;
+                self.running_status = None;
+            }
+            0xF7 => {
+                if self.in_sysex {
+                    self.message.push(byte);
+                    send(&self.message);
+                }
+                self.message.clear();
+                self.in_sysex = false;
+            }
+            0x80..=0xF6 => {
+                self.in_sysex = false;
+                self.message.clear();
+                self.message.push(byte);
+                self.expected = Self::data_len(byte);
+                // System common messages cancel running status.
+                self.running_status = if byte < 0xF0 { Some(byte) } else { None };
+                if self.expected == 0 {
+                    send(&self.message);
+                    self.message.clear();
+                }
+            }
+            _ => {
+                if self.in_sysex {
+                    self.message.push(byte);
+                    return;
+                }
+                if self.message.is_empty() {
+                    match self.running_status {
+                        Some(status) => {
+                            self.message.push(status);
+                            self.expected = Self::data_len(status);
+                        }
+                        // Data byte without a status byte; discard it.
+                        None => return,
+                    }
+                }
+                self.message.push(byte);
+                if self.message.len() > self.expected {
+                    send(&self.message);
+                    self.message.clear();
+                }
+            }
+        }
+    }
+}
+
+pub struct MidiData {
+    connection: Option<MidiOutputConnection>,
+    parser: MidiParser,
+}
+
+impl MidiData {
+    pub fn new(config: &Midi) -> Self {
+        let connection = if config.enabled {
+            match Self::connect(config.port.as_deref()) {
+                Ok(connection) => Some(connection),
+                Err(e) => {
+                    log::error!("Failed to open MIDI output: {}", e);
+                    None
+                }
+            }
+        }
+        else {
+            None
+        };
+
+        Self {
+            connection,
+            parser: MidiParser::default(),
+        }
+    }
+
+    /// Connect to the first host MIDI output port whose name contains `port_name`, or the first
+    /// available port if no name is given.
+    fn connect(port_name: Option<&str>) -> Result<MidiOutputConnection, anyhow::Error> {
+        let output = MidiOutput::new(MIDI_CLIENT_NAME)?;
+        let ports = output.ports();
+        for port in ports.iter() {
+            let name = output.port_name(port).unwrap_or_default();
+            log::debug!("Found MIDI output port: {}", name);
+        }
+
+        let port = ports
+            .iter()
+            .find(|port| match port_name {
+                Some(port_name) => output
+                    .port_name(port)
+                    .map(|name| name.contains(port_name))
+                    .unwrap_or(false),
+                None => true,
+            })
+            .ok_or_else(|| anyhow::anyhow!("No matching MIDI output port found"))?
+            .clone();
+
+        let name = output.port_name(&port).unwrap_or_default();
+        let connection = output
+            .connect(&port, MIDI_CLIENT_NAME)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        log::info!("Opened MIDI output port: {}", name);
+        Ok(connection)
+    }
+
+    /// Send any MIDI output from the MPU-401 to the host MIDI device.
+    pub fn poll(&mut self, mpu401: Option<&mut Mpu401>) {
+        let bytes = match mpu401 {
+            Some(mpu401) => mpu401.take_midi_output(),
+            None => return,
+        };
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        for byte in bytes {
+            self.parser.feed(byte, |message| {
+                if let Err(e) = connection.send(message) {
+                    log::warn!("Failed to send MIDI message: {}", e);
+                }
+            });
+        }
+    }
+}
+
+impl Drop for MidiData {
+    fn drop(&mut self) {
+        // Don't leave notes hanging on the host synth when exiting.
+        if let Some(connection) = &mut self.connection {
+            for channel in 0..16 {
+                _ = connection.send(&[0xB0 | channel, MIDI_CC_ALL_NOTES_OFF, 0]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut parser = MidiParser::default();
+        let mut messages = Vec::new();
+        for byte in bytes {
+            parser.feed(*byte, |message| messages.push(message.to_vec()));
+        }
+        messages
+    }
+
+    #[test]
+    fn test_running_status() {
+        assert_eq!(
+            parse(&[0x90, 0x3C, 0x40, 0x3E, 0x40, 0xC0, 0x05, 0x06]),
+            vec![
+                vec![0x90, 0x3C, 0x40],
+                vec![0x90, 0x3E, 0x40],
+                vec![0xC0, 0x05],
+                vec![0xC0, 0x06]
+            ]
+        );
+
+        // Data bytes with no status byte are discarded, and system common messages cancel running status.
+        assert_eq!(
+            parse(&[0x40, 0x90, 0x3C, 0x40, 0xF2, 0x01, 0x02, 0x3C, 0x40]),
+            vec![vec![0x90, 0x3C, 0x40], vec![0xF2, 0x01, 0x02]]
+        );
+    }
+
+    #[test]
+    fn test_realtime_and_sysex() {
+        // Real-time messages are sent immediately without interrupting the message in progress.
+        assert_eq!(
+            parse(&[0x90, 0x3C, 0xF8, 0x40]),
+            vec![vec![0xF8], vec![0x90, 0x3C, 0x40]]
+        );
+
+        assert_eq!(
+            parse(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7, 0x80, 0x3C, 0x00]),
+            vec![vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7], vec![0x80, 0x3C, 0x00]]
+        );
+
+        // An unterminated SysEx is dropped when another status byte arrives.
+        assert_eq!(parse(&[0xF0, 0x7E, 0xC0, 0x05]), vec![vec![0xC0, 0x05]]);
+    }
+}
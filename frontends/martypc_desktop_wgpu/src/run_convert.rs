@@ -0,0 +1,84 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+
+    run_convert.rs - Implement the main procedure for floppy image conversion.
+
+    Any floppy image format that can be loaded can be converted to a PCE
+    sector (.psi) or bitstream (.pri) image. Disks with a standard layout
+    can also be converted to a raw sector image.
+
+*/
+
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+
+use config_toml_bpaf::ConfigFileParams;
+use marty_core::floppy_image::{pri, psi, FloppyImage};
+
+pub fn run_convert(config: &ConfigFileParams, input: &Path) {
+    // Default to a sector image alongside the input file
+    let output = config
+        .emulator
+        .convert_output
+        .clone()
+        .unwrap_or_else(|| input.with_extension("psi"));
+
+    match convert_floppy(config, input, &output) {
+        Ok(()) => {
+            println!("Converted {} to {}", input.display(), output.display());
+        }
+        Err(e) => {
+            eprintln!("Failed to convert {}: {}", input.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn convert_floppy(config: &ConfigFileParams, input: &Path, output: &Path) -> Result<(), Error> {
+    let image = FloppyImage::load(&std::fs::read(input)?)?;
+
+    let extension = output
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let raw_extensions = config
+        .emulator
+        .media
+        .raw_sector_image_extensions
+        .clone()
+        .unwrap_or_default();
+
+    let data = match extension.as_str() {
+        "psi" => psi::save(&image),
+        "pri" => pri::save(&image),
+        ext if raw_extensions.iter().any(|raw| raw.eq_ignore_ascii_case(ext)) => image.to_raw()?,
+        _ => return Err(anyhow!("Can't write images with the extension '{}'", extension)),
+    };
+    std::fs::write(output, data)?;
+    Ok(())
+}
@@ -28,110 +28,315 @@
 
 */
 
+use std::{ffi::OsString, path::Path};
+
 use config_toml_bpaf::ConfigFileParams;
-use frontend_common::floppy_manager::FloppyManager;
+use frontend_common::{
+    machine_manager::MachineConfigFileEntry,
+    resource_manager::ResourceManager,
+    vhd_manager::VhdManager,
+};
+use marty_core::{
+    breakpoints::BreakPointType,
+    cpu_common::Cpu,
+    machine::{ExecutionControl, ExecutionState, Machine, MachineBuilder, MachineEvent, MachineRomManifest},
+    vhd::VirtualHardDisk,
+};
+use videocard_renderer::VideoRenderer;
 
-#[allow(dead_code)]
-pub fn run_headless(_config: &ConfigFileParams, _floppy_manager: FloppyManager) {
+const HEADLESS_CYCLE_BATCH: u64 = 100_000;
+/// Number of virtual seconds to run if neither a cycle count or timeout is given.
+const HEADLESS_TIMEOUT_DEFAULT: u32 = 60;
 
-    /*
+/// Exit code used when the machine could not be set up, or the CPU halted or failed.
+const EXIT_CODE_ERROR: i32 = 1;
+/// Exit code used when an exit port is configured but the run limit was reached first.
+const EXIT_CODE_TIMEOUT: i32 = 2;
 
-    // Init sound
-    // The cpal sound library uses generics to initialize depending on the SampleFormat type.
-    // On Windows at least a sample type of f32 is typical, but just in case...
-    let sample_fmt = SoundPlayer::get_sample_format();
-    let sp = match sample_fmt {
-        cpal::SampleFormat::F32 => SoundPlayer::new::<f32>(),
-        cpal::SampleFormat::I16 => SoundPlayer::new::<i16>(),
-        cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(),
-    };
+/// Why a headless run ended.
+enum RunEnd {
+    ExitPort(u8),
+    Breakpoint,
+    Limit,
+    Halted,
+}
 
-    // Look up the machine description given the machine type in the configuration file
-    let machine_desc_opt = MACHINE_DESCS.get(&config.machine.model);
-    if let Some(machine_desc) = machine_desc_opt {
-        log::debug!(
-            "Given machine type {:?} got machine description: {:?}",
-            config.machine.model,
-            machine_desc
-        );
-    }
-    else {
-        log::error!("Couldn't get machine description for {:?}", config.machine.model);
-
-        eprintln!(
-            "Couldn't get machine description for machine type {:?}. \
-             Check that you have a valid machine type specified in configuration file.",
-            config.machine.model
-        );
-        std::process::exit(1);
-    }
+/// Run a machine without any windows until one of the configured exit conditions is met, then
+/// write the requested screen and memory dumps and exit with a status code for the run.
+pub fn run_headless(
+    config: &ConfigFileParams,
+    machine_config_file: &MachineConfigFileEntry,
+    rom_manifest: MachineRomManifest,
+    _rm: ResourceManager,
+    mut vhdm: VhdManager,
+) {
+    let machine_config = machine_config_file.to_machine_config();
+    let run = &config.emulator.headless_run;
 
-    let (video_type, _clock_mode, _video_debug) = {
-        let mut video_type: Option<VideoType> = None;
-        let mut clock_mode: Option<ClockingMode> = None;
-        let video_cards = config.get_video_cards();
-        if video_cards.len() > 0 {
-            clock_mode = video_cards[0].clocking_mode;
-            video_type = Some(video_cards[0].video_type); // Videotype is not optional
-        }
-        (
-            video_type.unwrap_or(VideoType::CGA),
-            clock_mode.unwrap_or_default(),
-            video_cards[0].debug.unwrap_or(false),
-        )
-    };
+    let machine_builder = MachineBuilder::new()
+        .with_core_config(Box::new(config))
+        .with_machine_config(&machine_config)
+        .with_roms(rom_manifest)
+        .with_trace_mode(config.machine.cpu.trace_mode.unwrap_or_default())
+        .with_sound_override(false);
 
-    // Instantiate the main Machine data struct
-    // Machine coordinates all the parts of the emulated computer
-    let mut machine = Machine::new(
-        config,
-        config.machine.model,
-        *machine_desc_opt.unwrap(),
-        config.machine.cpu.trace_mode.unwrap_or_default(),
-        video_type,
-        sp,
-        rom_manager,
-    );
+    let mut machine = machine_builder.build().unwrap_or_else(|e| {
+        eprintln!("Failed to build machine: {:?}", e);
+        std::process::exit(EXIT_CODE_ERROR);
+    });
+
+    // Load floppy images into drives in order, starting with A:
+    for (drive, path) in run.floppy.iter().enumerate() {
+        let data = std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Error reading floppy image {:?}: {}", path, e);
+            std::process::exit(EXIT_CODE_ERROR);
+        });
+        if let Err(e) = machine.load_floppy(drive, data, false) {
+            eprintln!("Error loading floppy image {:?} into drive {}: {}", path, drive, e);
+            std::process::exit(EXIT_CODE_ERROR);
+        }
+    }
+
+    let overlays = mount_vhds(config, &mut machine, &mut vhdm);
 
     // Load program binary if one was specified in config options
     if let Some(prog_bin) = &config.emulator.run_bin {
-        if let Some(prog_seg) = config.emulator.run_bin_seg {
-            if let Some(prog_ofs) = config.emulator.run_bin_ofs {
-                let prog_vec = match std::fs::read(prog_bin.clone()) {
-                    Ok(vec) => vec,
-                    Err(e) => {
-                        eprintln!("Error opening filename {:?}: {}", prog_bin, e);
-                        std::process::exit(1);
-                    }
-                };
-
-                if let Err(_) = machine.load_program(&prog_vec, prog_seg, prog_ofs) {
+        match (config.emulator.run_bin_seg, config.emulator.run_bin_ofs) {
+            (Some(prog_seg), Some(prog_ofs)) => {
+                let prog_vec = std::fs::read(prog_bin).unwrap_or_else(|e| {
+                    eprintln!("Error opening filename {:?}: {}", prog_bin, e);
+                    std::process::exit(EXIT_CODE_ERROR);
+                });
+                if machine.load_program(&prog_vec, prog_seg, prog_ofs).is_err() {
                     eprintln!(
                         "Error loading program into memory at {:04X}:{:04X}.",
                         prog_seg, prog_ofs
                     );
-                    std::process::exit(1);
-                };
+                    std::process::exit(EXIT_CODE_ERROR);
+                }
             }
-            else {
-                eprintln!("Must specifiy program load offset.");
-                std::process::exit(1);
+            _ => {
+                eprintln!("Must specify program load segment and offset.");
+                std::process::exit(EXIT_CODE_ERROR);
             }
         }
-        else {
-            eprintln!("Must specifiy program load segment.");
-            std::process::exit(1);
+    }
+
+    if let Some(expr) = &run.breakpoint {
+        match machine.cpu().eval_address(expr) {
+            Some(addr) => machine.set_breakpoints(vec![BreakPointType::ExecuteFlat(u32::from(addr))]),
+            None => {
+                eprintln!("Invalid breakpoint address: {}", expr);
+                std::process::exit(EXIT_CODE_ERROR);
+            }
         }
     }
 
+    let cycle_total = match (run.cycles, run.timeout) {
+        (Some(cycles), _) => cycles,
+        (None, timeout) => {
+            let timeout_secs = timeout.unwrap_or(HEADLESS_TIMEOUT_DEFAULT);
+            (machine.get_cpu_mhz() * 1_000_000.0 * timeout_secs as f64) as u64
+        }
+    };
+    println!("Running headless for up to {} cycles", cycle_total);
+
     let mut exec_control = ExecutionControl::new();
     exec_control.set_state(ExecutionState::Running);
 
-    loop {
-        // This should really return a Result
-        machine.run(1000, &mut exec_control);
+    let start_cycles = machine.cpu_cycles();
+    let end = loop {
+        let elapsed = machine.cpu_cycles() - start_cycles;
+        if elapsed >= cycle_total {
+            break RunEnd::Limit;
+        }
+        let cycle_batch = std::cmp::min(cycle_total - elapsed, HEADLESS_CYCLE_BATCH);
+        machine.run(cycle_batch as u32, &mut exec_control);
+
+        let mut exit_code = None;
+        while let Some(event) = machine.get_event() {
+            if let MachineEvent::ExitRequested(code) = event {
+                exit_code = Some(code);
+            }
+        }
+        if let Some(code) = exit_code {
+            break RunEnd::ExitPort(code);
+        }
+
+        match exec_control.get_state() {
+            ExecutionState::BreakpointHit => break RunEnd::Breakpoint,
+            ExecutionState::Halted => break RunEnd::Halted,
+            _ => {}
+        }
+    };
+
+    let cycles = machine.cpu_cycles() - start_cycles;
+    let status = match end {
+        RunEnd::ExitPort(code) => {
+            println!("Guest wrote exit code {} after {} cycles.", code, cycles);
+            code as i32
+        }
+        RunEnd::Breakpoint => {
+            println!("Breakpoint hit after {} cycles.", cycles);
+            0
+        }
+        RunEnd::Limit if config.machine.exit_port.is_some() => {
+            eprintln!(
+                "Run limit of {} cycles reached without a write to the exit port.",
+                cycles
+            );
+            EXIT_CODE_TIMEOUT
+        }
+        RunEnd::Limit => {
+            println!("Run limit of {} cycles reached.", cycles);
+            0
+        }
+        RunEnd::Halted => {
+            match machine.get_error_str() {
+                Some(err) => eprintln!("Machine halted after {} cycles: {}", cycles, err),
+                None => eprintln!("Machine halted after {} cycles.", cycles),
+            }
+            EXIT_CODE_ERROR
+        }
+    };
+
+    let mut dumps_ok = true;
+    if let Some(path) = &run.screen_dump {
+        dumps_ok &= dump_screen(&mut machine, path);
+    }
+    for dump in &run.memory_dump {
+        dumps_ok &= dump_memory(&machine, &dump.address, dump.size, &dump.file);
     }
-    */
 
-    //std::process::exit(0);
+    for overlay in overlays {
+        _ = std::fs::remove_file(overlay);
+    }
+
+    if !dumps_ok && status == 0 {
+        std::process::exit(EXIT_CODE_ERROR);
+    }
+    std::process::exit(status);
+}
+
+/// Mount the VHD images given in the machine and main configuration, in the same order as the GUI
+/// frontend does. Each image gets a temporary copy-on-write overlay, so that a run never modifies
+/// the image itself. Returns the paths of the overlays, to be removed after the run.
+fn mount_vhds(config: &ConfigFileParams, machine: &mut Machine, vhdm: &mut VhdManager) -> Vec<std::path::PathBuf> {
+    let mut vhd_names: Vec<Option<String>> = machine
+        .config()
+        .hdc
+        .as_ref()
+        .and_then(|controller| controller.drive.as_ref())
+        .map(|drives| drives.iter().map(|drive| drive.vhd.clone()).collect())
+        .unwrap_or_default();
+
+    for (drive_i, vhd) in config.emulator.media.vhd.iter().flatten().enumerate() {
+        if drive_i >= vhd_names.len() {
+            vhd_names.push(Some(vhd.filename.clone()));
+        }
+        else {
+            vhd_names[drive_i] = Some(vhd.filename.clone());
+        }
+    }
+
+    let mut overlays = Vec::new();
+    // Keep each image on its own drive number; a drive with no VHD configured leaves a gap.
+    let vhd_drives = vhd_names
+        .into_iter()
+        .enumerate()
+        .filter_map(|(drive, name)| name.map(|name| (drive, name)));
+
+    for (drive, vhd_name) in vhd_drives {
+        let vhd_os_name: OsString = vhd_name.into();
+        let overlay_path = std::env::temp_dir().join(format!("martypc_headless_{}_{}.cow", std::process::id(), drive));
+
+        let result = vhdm
+            .load_vhd_file_by_name(drive, &vhd_os_name)
+            .map_err(anyhow::Error::from)
+            .and_then(|(vhd_file, _)| VirtualHardDisk::from_file(vhd_file))
+            .and_then(|mut vhd| {
+                let overlay_file = std::fs::File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&overlay_path)?;
+                overlays.push(overlay_path.clone());
+                vhd.attach_overlay(overlay_file)?;
+                machine.set_vhd(drive, vhd)
+            });
+
+        if let Err(e) = result {
+            eprintln!("Error loading VHD image {:?} into drive {}: {}", vhd_os_name, drive, e);
+            std::process::exit(EXIT_CODE_ERROR);
+        }
+        log::info!("VHD image {:?} loaded into drive {}", vhd_os_name, drive);
+    }
+    overlays
+}
+
+/// Save the screen of the primary video card. A path with a 'txt' extension receives the text mode
+/// contents of the screen, and any other path receives the raw framebuffer as a PNG.
+fn dump_screen(machine: &mut Machine, path: &Path) -> bool {
+    let card = match machine.primary_videocard() {
+        Some(card) => card,
+        None => {
+            eprintln!("Can't dump screen: the machine has no video card.");
+            return false;
+        }
+    };
+
+    let result = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt")) {
+        let mut text = card.get_text_mode_strings().join("\n");
+        text.push('\n');
+        std::fs::write(path, text).map_err(anyhow::Error::from)
+    }
+    else {
+        VideoRenderer::save_raw_buf(
+            card.get_video_type(),
+            card.get_display_buf(),
+            card.get_display_extents(),
+            path,
+        )
+        .map_err(anyhow::Error::from)
+    };
+
+    match result {
+        Ok(_) => {
+            println!("Saved screen: {}", path.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Error writing screen dump {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Save a region of memory. The address may be flat or segmented.
+fn dump_memory(machine: &Machine, address: &str, size: u32, path: &Path) -> bool {
+    let start = match machine.cpu().eval_address(address) {
+        Some(addr) => u32::from(addr) as usize,
+        None => {
+            eprintln!("Can't dump memory: invalid address {}", address);
+            return false;
+        }
+    };
+
+    match machine.bus().peek_range(start, size as usize) {
+        Ok(data) => match std::fs::write(path, data) {
+            Ok(_) => {
+                println!("Saved {} bytes at {:05X}: {}", size, start, path.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("Error writing memory dump {}: {}", path.display(), e);
+                false
+            }
+        },
+        Err(e) => {
+            eprintln!("Can't dump {} bytes of memory at {:05X}: {:?}", size, start, e);
+            false
+        }
+    }
 }
@@ -0,0 +1,789 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    scripting.rs
+
+    Run Rhai scripts to automate the emulator. Scripts can inspect and modify
+    registers and memory, set breakpoints, inject keys, change disks and take
+    screenshots, and may define on_frame() and on_breakpoint(address) handlers
+    that are called as the machine runs.
+
+    Handlers can keep state between calls in `this`, an object map that
+    belongs to each script.
+*/
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Error};
+use display_manager_wgpu::{DisplayManager, ScreenshotType};
+use frontend_common::constants::{LONG_NOTIFICATION_TIME, NORMAL_NOTIFICATION_TIME};
+use marty_core::{
+    cpu_common::{Cpu, HookRegisters, Register16},
+    devices::keyboard::KeyboardModifiers,
+    keys::MartyKey,
+    machine::{ExecutionOperation, ExecutionState, Machine},
+};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+
+use crate::Emulator;
+
+/// Maximum number of operations a script may perform in a single call, so that a runaway script
+/// can't hang the emulator.
+pub const SCRIPT_MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Actions requested by scripts that are carried out by the frontend after the script returns.
+pub enum ScriptCommand {
+    MountFloppy(usize, PathBuf),
+    EjectFloppy(usize),
+    Screenshot,
+    Pause,
+    Resume,
+}
+
+/// Keyboard input from scripts, delivered to the machine in order after the script returns.
+enum ScriptKey {
+    Down(MartyKey),
+    Up(MartyKey),
+    Text(String),
+}
+
+/// The state of the machine presented to scripts. Script functions can't borrow the machine, so
+/// the view is taken before each call into the script engine, and any changes made by the script
+/// are written back to the machine when it returns.
+#[derive(Default)]
+struct MachineView {
+    regs:   HookRegisters,
+    memory: Vec<u8>,
+    writes: Vec<(usize, u8)>,
+    keys:   Vec<ScriptKey>,
+    cycles: u64,
+    frame:  u64,
+    screen: Vec<String>,
+}
+
+impl MachineView {
+    fn update(&mut self, machine: &mut Machine) {
+        let cpu = machine.cpu();
+        let cs = cpu.get_register16(Register16::CS);
+        self.regs = HookRegisters {
+            ax: cpu.get_register16(Register16::AX),
+            bx: cpu.get_register16(Register16::BX),
+            cx: cpu.get_register16(Register16::CX),
+            dx: cpu.get_register16(Register16::DX),
+            sp: cpu.get_register16(Register16::SP),
+            bp: cpu.get_register16(Register16::BP),
+            si: cpu.get_register16(Register16::SI),
+            di: cpu.get_register16(Register16::DI),
+            cs,
+            ds: cpu.get_register16(Register16::DS),
+            ss: cpu.get_register16(Register16::SS),
+            es: cpu.get_register16(Register16::ES),
+            ip: cpu.flat_ip().wrapping_sub((cs as u32) << 4) as u16,
+            flags: cpu.get_flags(),
+        };
+        machine.bus().peek_address_space(&mut self.memory);
+        self.cycles = machine.cpu_cycles();
+        if let Some(card) = machine.primary_videocard() {
+            self.frame = card.get_frame_count();
+            self.screen = card.get_text_mode_strings();
+        }
+    }
+
+    /// Write back any changes made by scripts. As with instruction hooks, CS and IP are read-only.
+    fn apply(&mut self, machine: &mut Machine, original: HookRegisters) {
+        let regs = self.regs;
+        if regs != original {
+            let cpu = machine.cpu_mut();
+            cpu.set_register16(Register16::AX, regs.ax);
+            cpu.set_register16(Register16::BX, regs.bx);
+            cpu.set_register16(Register16::CX, regs.cx);
+            cpu.set_register16(Register16::DX, regs.dx);
+            cpu.set_register16(Register16::SP, regs.sp);
+            cpu.set_register16(Register16::BP, regs.bp);
+            cpu.set_register16(Register16::SI, regs.si);
+            cpu.set_register16(Register16::DI, regs.di);
+            cpu.set_register16(Register16::DS, regs.ds);
+            cpu.set_register16(Register16::SS, regs.ss);
+            cpu.set_register16(Register16::ES, regs.es);
+            cpu.set_flags(regs.flags);
+        }
+
+        for (address, data) in self.writes.drain(..) {
+            if let Err(e) = machine.bus_mut().write_u8(address, data, 0) {
+                log::warn!("Script write to {:05X} failed: {}", address, e);
+            }
+        }
+
+        for key in self.keys.drain(..) {
+            match key {
+                ScriptKey::Down(key) => machine.key_press(key, KeyboardModifiers::default()),
+                ScriptKey::Up(key) => machine.key_release(key),
+                ScriptKey::Text(text) => machine.paste_text(&text),
+            }
+        }
+    }
+
+    fn reg(&self, name: &str) -> Option<u16> {
+        let r = &self.regs;
+        let value = match name {
+            "ax" => r.ax,
+            "bx" => r.bx,
+            "cx" => r.cx,
+            "dx" => r.dx,
+            "sp" => r.sp,
+            "bp" => r.bp,
+            "si" => r.si,
+            "di" => r.di,
+            "cs" => r.cs,
+            "ds" => r.ds,
+            "ss" => r.ss,
+            "es" => r.es,
+            "ip" => r.ip,
+            "flags" => r.flags,
+            "al" => r.ax & 0xFF,
+            "bl" => r.bx & 0xFF,
+            "cl" => r.cx & 0xFF,
+            "dl" => r.dx & 0xFF,
+            "ah" => r.ax >> 8,
+            "bh" => r.bx >> 8,
+            "ch" => r.cx >> 8,
+            "dh" => r.dx >> 8,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn set_reg(&mut self, name: &str, value: u16) -> bool {
+        let r = &mut self.regs;
+        match name {
+            "ax" => r.ax = value,
+            "bx" => r.bx = value,
+            "cx" => r.cx = value,
+            "dx" => r.dx = value,
+            "sp" => r.sp = value,
+            "bp" => r.bp = value,
+            "si" => r.si = value,
+            "di" => r.di = value,
+            "ds" => r.ds = value,
+            "ss" => r.ss = value,
+            "es" => r.es = value,
+            "flags" => r.flags = value,
+            "al" => r.ax = (r.ax & 0xFF00) | (value & 0xFF),
+            "bl" => r.bx = (r.bx & 0xFF00) | (value & 0xFF),
+            "cl" => r.cx = (r.cx & 0xFF00) | (value & 0xFF),
+            "dl" => r.dx = (r.dx & 0xFF00) | (value & 0xFF),
+            "ah" => r.ax = (r.ax & 0x00FF) | (value << 8),
+            "bh" => r.bx = (r.bx & 0x00FF) | (value << 8),
+            "ch" => r.cx = (r.cx & 0x00FF) | (value << 8),
+            "dh" => r.dx = (r.dx & 0x00FF) | (value << 8),
+            _ => return false,
+        }
+        true
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self.memory.get(address & 0xFFFFF).copied().unwrap_or(0xFF)
+    }
+
+    fn poke(&mut self, address: usize, data: u8) {
+        let address = address & 0xFFFFF;
+        if let Some(byte) = self.memory.get_mut(address) {
+            *byte = data;
+        }
+        self.writes.push((address, data));
+    }
+}
+
+/// State shared between the script host and the functions registered with the script engine.
+#[derive(Default)]
+struct ScriptShared {
+    view: MachineView,
+    commands: Vec<ScriptCommand>,
+    breakpoints: Vec<u32>,
+    breakpoints_changed: bool,
+}
+
+impl ScriptShared {
+    fn add_breakpoint(&mut self, address: u32) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+            self.breakpoints_changed = true;
+        }
+    }
+}
+
+struct Script {
+    name:  String,
+    ast:   AST,
+    scope: Scope<'static>,
+    this:  Dynamic,
+}
+
+impl Script {
+    fn has_fn(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    shared: Rc<RefCell<ScriptShared>>,
+    scripts: Vec<Script>,
+    errors: Vec<(String, Error)>,
+    at_breakpoint: bool,
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptHost {
+    pub fn new() -> Self {
+        let shared = Rc::new(RefCell::new(ScriptShared::default()));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+        engine.on_print(|text| log::info!("[script] {}", text));
+        engine.on_debug(|text, source, pos| log::debug!("[script] {} {}: {}", source.unwrap_or(""), pos, text));
+        register_api(&mut engine, &shared);
+
+        Self {
+            engine,
+            shared,
+            scripts: Vec::new(),
+            errors: Vec::new(),
+            at_breakpoint: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Return the names of the running scripts.
+    pub fn names(&self) -> Vec<String> {
+        self.scripts.iter().map(|script| script.name.clone()).collect()
+    }
+
+    /// Run `f` with an up-to-date view of the machine, then write back any changes made to it.
+    fn with_view<R>(&mut self, machine: &mut Machine, f: impl FnOnce(&mut Self) -> R) -> R {
+        let original = {
+            let mut shared = self.shared.borrow_mut();
+            shared.view.update(machine);
+            shared.view.regs
+        };
+        let result = f(self);
+        self.shared.borrow_mut().view.apply(machine, original);
+        result
+    }
+
+    /// Compile the specified script and run its top-level statements. The script remains loaded
+    /// to handle events if it defines any handlers.
+    pub fn load(&mut self, path: &Path, machine: &mut Machine) -> Result<(), Error> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let ast = self
+            .engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| anyhow!("{}", e))?;
+
+        let mut script = Script {
+            name,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+        };
+
+        self.with_view(machine, |host| {
+            host.engine.run_ast_with_scope(&mut script.scope, &script.ast)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+        if script.has_fn("on_frame", 0) || script.has_fn("on_breakpoint", 1) {
+            self.scripts.push(script);
+        }
+        else {
+            log::debug!("Script {} has no handlers, unloading.", script.name);
+        }
+        Ok(())
+    }
+
+    /// Unload all scripts and remove their breakpoints.
+    pub fn stop_all(&mut self) {
+        self.scripts.clear();
+        let mut shared = self.shared.borrow_mut();
+        shared.commands.clear();
+        shared.breakpoints.clear();
+        shared.breakpoints_changed = true;
+    }
+
+    /// Call the on_frame() handler of each script.
+    pub fn on_frame(&mut self, machine: &mut Machine) {
+        if self.scripts.iter().any(|script| script.has_fn("on_frame", 0)) {
+            self.call_handler(machine, "on_frame", 0, ());
+        }
+    }
+
+    /// Call the on_breakpoint() handler of each script if the machine has stopped at one of the
+    /// breakpoints set by scripts. Returns true if any handler asked for the machine to keep running.
+    pub fn on_breakpoint(&mut self, machine: &mut Machine, state: ExecutionState) -> bool {
+        if !matches!(state, ExecutionState::BreakpointHit) {
+            self.at_breakpoint = false;
+            return false;
+        }
+        if self.at_breakpoint {
+            // Only notify scripts once per stop.
+            return false;
+        }
+        self.at_breakpoint = true;
+
+        let address = machine.cpu().flat_ip();
+        if !self.shared.borrow().breakpoints.contains(&address) {
+            return false;
+        }
+        let results = self.call_handler(machine, "on_breakpoint", 1, (address as INT,));
+        let resume = results.iter().any(|result| result.as_bool().unwrap_or(false));
+        if resume {
+            self.at_breakpoint = false;
+        }
+        resume
+    }
+
+    /// Call the named handler in each script that defines it with the given number of parameters,
+    /// returning the results. A script that fails is unloaded and its error recorded.
+    fn call_handler(
+        &mut self,
+        machine: &mut Machine,
+        name: &str,
+        params: usize,
+        args: impl FuncArgs + Clone,
+    ) -> Vec<Dynamic> {
+        self.with_view(machine, |host| {
+            let engine = &host.engine;
+            let errors = &mut host.errors;
+            let mut results = Vec::new();
+
+            host.scripts.retain_mut(|script| {
+                if !script.has_fn(name, params) {
+                    return true;
+                }
+                let options = CallFnOptions::new()
+                    .eval_ast(false)
+                    .rewind_scope(false)
+                    .bind_this_ptr(&mut script.this);
+                match engine.call_fn_with_options::<Dynamic>(
+                    options,
+                    &mut script.scope,
+                    &script.ast,
+                    name,
+                    args.clone(),
+                ) {
+                    Ok(result) => {
+                        results.push(result);
+                        true
+                    }
+                    Err(e) => {
+                        errors.push((script.name.clone(), anyhow!("{}", e)));
+                        false
+                    }
+                }
+            });
+            results
+        })
+    }
+
+    /// Return the errors of any scripts that were unloaded since the last call.
+    pub fn take_errors(&mut self) -> Vec<(String, Error)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Return the actions requested by scripts since the last call.
+    pub fn take_commands(&mut self) -> Vec<ScriptCommand> {
+        std::mem::take(&mut self.shared.borrow_mut().commands)
+    }
+
+    /// Return true if scripts have changed their breakpoints since the last call.
+    pub fn take_breakpoints_changed(&mut self) -> bool {
+        std::mem::take(&mut self.shared.borrow_mut().breakpoints_changed)
+    }
+
+    /// Return the flat addresses of the execute breakpoints set by scripts.
+    pub fn breakpoints(&self) -> Vec<u32> {
+        self.shared.borrow().breakpoints.clone()
+    }
+}
+
+fn parse_key(name: &str) -> Result<MartyKey, Box<EvalAltResult>> {
+    MartyKey::from_str(name).map_err(|_| format!("Unknown key: {}", name).into())
+}
+
+fn check_address(address: INT) -> Result<usize, Box<EvalAltResult>> {
+    if (0..0x100000).contains(&address) {
+        Ok(address as usize)
+    }
+    else {
+        Err(format!("Address out of range: {:X}", address).into())
+    }
+}
+
+/// Register the emulator API with the script engine.
+fn register_api(engine: &mut Engine, shared: &Rc<RefCell<ScriptShared>>) {
+    // Registers
+    let s = shared.clone();
+    engine.register_fn("reg", move |name: &str| -> Result<INT, Box<EvalAltResult>> {
+        s.borrow()
+            .view
+            .reg(&name.to_ascii_lowercase())
+            .map(|value| value as INT)
+            .ok_or_else(|| format!("Unknown register: {}", name).into())
+    });
+    let s = shared.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: INT| -> Result<(), Box<EvalAltResult>> {
+            s.borrow_mut()
+                .view
+                .set_reg(&name.to_ascii_lowercase(), value as u16)
+                .then_some(())
+                .ok_or_else(|| format!("Can't set register: {}", name).into())
+        },
+    );
+
+    // Memory
+    let s = shared.clone();
+    engine.register_fn("peek", move |address: INT| -> Result<INT, Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        Ok(s.borrow().view.peek(address) as INT)
+    });
+    let s = shared.clone();
+    engine.register_fn("peek16", move |address: INT| -> Result<INT, Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        let shared = s.borrow();
+        Ok(((shared.view.peek(address + 1) as INT) << 8) | shared.view.peek(address) as INT)
+    });
+    let s = shared.clone();
+    engine.register_fn(
+        "poke",
+        move |address: INT, value: INT| -> Result<(), Box<EvalAltResult>> {
+            let address = check_address(address)?;
+            s.borrow_mut().view.poke(address, value as u8);
+            Ok(())
+        },
+    );
+    let s = shared.clone();
+    engine.register_fn(
+        "poke16",
+        move |address: INT, value: INT| -> Result<(), Box<EvalAltResult>> {
+            let address = check_address(address)?;
+            let mut shared = s.borrow_mut();
+            shared.view.poke(address, value as u8);
+            shared.view.poke(address + 1, (value >> 8) as u8);
+            Ok(())
+        },
+    );
+    engine.register_fn("addr", |segment: INT, offset: INT| -> INT {
+        (((segment & 0xFFFF) << 4) + (offset & 0xFFFF)) & 0xFFFFF
+    });
+
+    // Breakpoints
+    let s = shared.clone();
+    engine.register_fn("break_at", move |address: INT| -> Result<(), Box<EvalAltResult>> {
+        let address = check_address(address)?;
+        s.borrow_mut().add_breakpoint(address as u32);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("clear_breakpoints", move || {
+        let mut shared = s.borrow_mut();
+        shared.breakpoints.clear();
+        shared.breakpoints_changed = true;
+    });
+
+    // Keyboard
+    let s = shared.clone();
+    engine.register_fn("key_down", move |name: &str| -> Result<(), Box<EvalAltResult>> {
+        let key = parse_key(name)?;
+        s.borrow_mut().view.keys.push(ScriptKey::Down(key));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("key_up", move |name: &str| -> Result<(), Box<EvalAltResult>> {
+        let key = parse_key(name)?;
+        s.borrow_mut().view.keys.push(ScriptKey::Up(key));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("type_text", move |text: &str| {
+        s.borrow_mut().view.keys.push(ScriptKey::Text(text.to_string()));
+    });
+
+    // Machine state
+    let s = shared.clone();
+    engine.register_fn("cycles", move || s.borrow().view.cycles as INT);
+    let s = shared.clone();
+    engine.register_fn("frame", move || s.borrow().view.frame as INT);
+    let s = shared.clone();
+    engine.register_fn("screen_text", move || -> Array {
+        s.borrow().view.screen.iter().cloned().map(Dynamic::from).collect()
+    });
+
+    // Actions carried out by the frontend
+    let s = shared.clone();
+    engine.register_fn("mount_floppy", move |drive: INT, path: &str| {
+        s.borrow_mut()
+            .commands
+            .push(ScriptCommand::MountFloppy(drive as usize, PathBuf::from(path)));
+    });
+    let s = shared.clone();
+    engine.register_fn("eject_floppy", move |drive: INT| {
+        s.borrow_mut().commands.push(ScriptCommand::EjectFloppy(drive as usize));
+    });
+    let s = shared.clone();
+    engine.register_fn("screenshot", move || {
+        s.borrow_mut().commands.push(ScriptCommand::Screenshot);
+    });
+    let s = shared.clone();
+    engine.register_fn("pause", move || {
+        s.borrow_mut().commands.push(ScriptCommand::Pause);
+    });
+    let s = shared.clone();
+    engine.register_fn("resume", move || {
+        s.borrow_mut().commands.push(ScriptCommand::Resume);
+    });
+}
+
+/// Resolve a script path. Relative paths that don't exist in the working directory are looked up
+/// in the 'script' resource directory.
+fn script_path(emu: &Emulator, path: &Path) -> PathBuf {
+    match emu.rm.get_resource_path("script") {
+        Some(dir) if path.is_relative() && !path.exists() => dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Load and run the specified script.
+pub fn run_script(emu: &mut Emulator, path: &Path) -> Result<(), Error> {
+    let path = script_path(emu, path);
+    log::info!("Running script: {}", path.display());
+    emu.scripts.load(&path, &mut emu.machine)?;
+    process_script_requests(emu);
+    emu.gui.set_running_scripts(emu.scripts.names());
+    Ok(())
+}
+
+/// Run the scripts given in the configuration and on the command line.
+pub fn run_startup_scripts(emu: &mut Emulator) {
+    for path in emu.config.emulator.scripting.startup.clone() {
+        if let Err(e) = run_script(emu, &path) {
+            log::error!("Failed to run script {}: {}", path.display(), e);
+            emu.gui
+                .toasts()
+                .error(format!("Script {} failed: {}", path.display(), e))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+    }
+}
+
+/// Unload all scripts.
+pub fn stop_scripts(emu: &mut Emulator) {
+    emu.scripts.stop_all();
+    process_script_requests(emu);
+    emu.gui.set_running_scripts(Vec::new());
+}
+
+/// Call the handlers of running scripts. Should be called once per frame.
+pub fn update_scripts(emu: &mut Emulator) {
+    if emu.scripts.is_empty() {
+        return;
+    }
+
+    emu.scripts.on_frame(&mut emu.machine);
+
+    let state = emu.exec_control.borrow().get_state();
+    if emu.scripts.on_breakpoint(&mut emu.machine, state) {
+        emu.exec_control.borrow_mut().set_op(ExecutionOperation::Run);
+    }
+
+    process_script_requests(emu);
+
+    let errors = emu.scripts.take_errors();
+    if !errors.is_empty() {
+        for (name, e) in errors {
+            log::error!("Script {} stopped: {}", name, e);
+            emu.gui
+                .toasts()
+                .error(format!("Script {} stopped: {}", name, e))
+                .set_duration(Some(LONG_NOTIFICATION_TIME));
+        }
+        emu.gui.set_running_scripts(emu.scripts.names());
+    }
+}
+
+/// Carry out the actions requested by scripts, and apply any changes to their breakpoints.
+fn process_script_requests(emu: &mut Emulator) {
+    if emu.scripts.take_breakpoints_changed() {
+        emu.update_breakpoints();
+    }
+
+    for command in emu.scripts.take_commands() {
+        let result = match command {
+            ScriptCommand::MountFloppy(drive, path) => mount_floppy(emu, drive, &path),
+            ScriptCommand::EjectFloppy(drive) => emu.flush_floppy(drive).map(|_| {
+                emu.machine.eject_floppy(drive);
+                emu.floppy_directories.remove(&drive);
+                emu.gui.set_floppy_selection(drive, None, None);
+            }),
+            ScriptCommand::Screenshot => emu
+                .rm
+                .get_resource_path("screenshot")
+                .ok_or_else(|| anyhow!("No 'screenshot' resource path is configured."))
+                .and_then(|path| emu.dm.save_screenshot(0, path, ScreenshotType::Rendered)),
+            ScriptCommand::Pause => {
+                emu.exec_control.borrow_mut().set_op(ExecutionOperation::Pause);
+                Ok(())
+            }
+            ScriptCommand::Resume => {
+                emu.exec_control.borrow_mut().set_op(ExecutionOperation::Run);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            log::error!("Script request failed: {}", e);
+            emu.gui
+                .toasts()
+                .error(format!("Script request failed: {}", e))
+                .set_duration(Some(NORMAL_NOTIFICATION_TIME));
+        }
+    }
+}
+
+/// Load a floppy image on behalf of a script. Relative paths are in the 'floppy' resource directory.
+fn mount_floppy(emu: &mut Emulator, drive: usize, path: &Path) -> Result<(), Error> {
+    let path = match emu.rm.get_resource_path("floppy") {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    };
+    let data = std::fs::read(&path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let write_protect = emu.config.emulator.media.write_protect_default;
+    emu.flush_floppy(drive)?;
+    emu.machine.load_floppy(drive, data, write_protect)?;
+    log::info!("Script loaded floppy image {} into drive {}", path.display(), drive);
+
+    emu.floppy_directories.remove(&drive);
+    emu.gui
+        .set_floppy_selection(drive, None, Some(PathBuf::from(path.file_name().unwrap_or_default())));
+    emu.gui.set_floppy_write_protected(drive, write_protect);
+    // The image isn't in the floppy manager, so there is no image file to write back to.
+    emu.gui.set_floppy_write_back(drive, false);
+    Ok(())
+}
+
+/// Update the GUI with the scripts available in the 'script' resource directory.
+pub fn update_script_list(emu: &mut Emulator) {
+    let mut scripts: Vec<String> = emu
+        .rm
+        .get_resource_path("script")
+        .and_then(|path| std::fs::read_dir(path).ok())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rhai")))
+                .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    scripts.sort();
+    emu.gui.set_scripts(Some(scripts));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut view = MachineView::default();
+        assert!(view.set_reg("ax", 0x1234));
+        assert_eq!(view.reg("al"), Some(0x34));
+        assert_eq!(view.reg("ah"), Some(0x12));
+
+        assert!(view.set_reg("al", 0xAB));
+        assert!(view.set_reg("ah", 0xCD));
+        assert_eq!(view.reg("ax"), Some(0xCDAB));
+
+        // Byte registers only take the low byte of the value.
+        assert!(view.set_reg("dl", 0x1FF));
+        assert_eq!(view.reg("dx"), Some(0x00FF));
+
+        // CS and IP are read-only.
+        assert!(!view.set_reg("cs", 0xF000));
+        assert!(!view.set_reg("ip", 0xFFF0));
+        assert_eq!(view.reg("cs"), Some(0));
+        assert_eq!(view.reg("ip"), Some(0));
+
+        assert!(!view.set_reg("eax", 0));
+        assert_eq!(view.reg("eax"), None);
+    }
+
+    #[test]
+    fn test_poke() {
+        let mut view = MachineView {
+            memory: vec![0; 0x100000],
+            ..Default::default()
+        };
+        view.poke(0x00400, 0x55);
+        assert_eq!(view.peek(0x00400), 0x55);
+
+        // Addresses wrap at 1MB.
+        view.poke(0x100401, 0xAA);
+        assert_eq!(view.peek(0x00401), 0xAA);
+        assert_eq!(view.writes, vec![(0x00400, 0x55), (0x00401, 0xAA)]);
+
+        // Without a memory snapshot, reads return open bus but writes are still recorded.
+        let mut view = MachineView::default();
+        view.poke(0x00400, 0x55);
+        assert_eq!(view.peek(0x00400), 0xFF);
+        assert_eq!(view.writes, vec![(0x00400, 0x55)]);
+    }
+
+    #[test]
+    fn test_check_address() {
+        assert_eq!(check_address(0).unwrap(), 0);
+        assert_eq!(check_address(0xFFFFF).unwrap(), 0xFFFFF);
+        assert!(check_address(0x100000).is_err());
+        assert!(check_address(-1).is_err());
+    }
+}
@@ -30,7 +30,7 @@
 */
 
 pub use pixels::{
-    wgpu::{CommandEncoder, PowerPreference, RequestAdapterOptions, TextureView},
+    wgpu::{self, CommandEncoder, PowerPreference, RequestAdapterOptions, TextureView},
     Pixels,
     PixelsBuilder,
     SurfaceTexture,
@@ -59,7 +59,7 @@ pub struct PixelsBackend {
 }
 
 impl PixelsBackend {
-    pub fn new(w: u32, h: u32, window: &Window) -> Result<PixelsBackend, Error> {
+    pub fn new(w: u32, h: u32, vsync: bool, window: &Window) -> Result<PixelsBackend, Error> {
         let window_size = window.inner_size();
 
         // Create a surface the size of the window's client area.
@@ -72,7 +72,7 @@ impl PixelsBackend {
                 force_fallback_adapter: false,
                 compatible_surface: None,
             })
-            .enable_vsync(false)
+            .enable_vsync(vsync)
             .build()?;
 
         Ok(PixelsBackend {
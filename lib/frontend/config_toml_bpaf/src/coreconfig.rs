@@ -40,7 +40,7 @@ use marty_core::{
     coreconfig::CoreConfig,
     cpu_common::TraceMode,
     cpu_validator::ValidatorType,
-    machine_types::{MachineType, OnHaltBehavior},
+    machine_types::{BootDevice, MachineType, OnHaltBehavior},
 };
 
 /*
@@ -65,12 +65,21 @@ impl CoreConfig for ConfigFileParams {
     fn get_audio_enabled(&self) -> bool {
         self.emulator.audio.enabled
     }
+    fn get_drive_sounds(&self) -> bool {
+        self.emulator.audio.drive_sounds
+    }
+    fn get_drive_sound_volume(&self) -> Option<f32> {
+        self.emulator.audio.drive_sound_volume
+    }
     fn get_machine_noroms(&self) -> bool {
         self.machine.no_roms
     }
     fn get_machine_turbo(&self) -> bool {
         self.machine.turbo
     }
+    fn get_boot_device(&self) -> BootDevice {
+        self.machine.boot_device.unwrap_or_default()
+    }
     //fn get_keyboard_type(&self) -> Option<KeyboardType> { self.machine.keyboard_type }
     fn get_keyboard_layout(&self) -> Option<String> {
         self.machine.input.keyboard_layout.clone()
@@ -114,4 +123,10 @@ impl CoreConfig for ConfigFileParams {
     fn get_terminal_port(&self) -> Option<u16> {
         self.machine.terminal_port
     }
+    fn get_exit_port(&self) -> Option<u16> {
+        self.machine.exit_port
+    }
+    fn get_log_unmapped(&self) -> bool {
+        self.machine.log_unmapped
+    }
 }
@@ -41,15 +41,17 @@ use frontend_common::{
     display_scaler::ScalerPreset,
     resource_manager::PathConfigItem,
     BenchmarkEndCondition,
+    GamepadConfig,
     HotkeyConfigEntry,
     JoyKeyEntry,
+    KeyMacroEntry,
     MartyGuiTheme,
 };
 use marty_common::VideoDimensions;
 use marty_core::{
     cpu_common::{CpuSubType, CpuType, TraceMode},
     cpu_validator::ValidatorType,
-    machine_types::OnHaltBehavior,
+    machine_types::{BootDevice, OnHaltBehavior},
 };
 
 use bpaf::Bpaf;
@@ -88,6 +90,7 @@ impl FromStr for TestMode {
         match s.to_lowercase().as_str() {
             "none" => Ok(TestMode::None),
             "generate" => Ok(TestMode::Generate),
+            "run" => Ok(TestMode::Run),
             "validate" => Ok(TestMode::Validate),
             "process" => Ok(TestMode::Process),
             _ => Err("Bad value for testmode".to_string()),
@@ -106,6 +109,10 @@ pub struct Media {
     pub raw_sector_image_extensions: Option<Vec<String>>,
     #[serde(default)]
     pub write_protect_default: bool,
+    #[serde(default)]
+    pub write_back_default: bool,
+    #[serde(default)]
+    pub hdd_overlay_default: bool,
     pub vhd: Option<Vec<VhdConfigEntry>>,
 }
 
@@ -113,6 +120,51 @@ pub struct Media {
 pub struct Audio {
     #[serde(default = "_default_true")]
     pub enabled: bool,
+    pub buffer_ms: Option<f32>,
+    pub device_buffer: Option<u32>,
+    #[serde(default)]
+    pub drive_sounds: bool,
+    pub drive_sound_volume: Option<f32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Midi {
+    #[serde(default)]
+    pub enabled: bool,
+    pub port:    Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Rewind {
+    #[serde(default)]
+    pub enabled:   bool,
+    pub interval:  Option<u32>,
+    pub snapshots: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemoryDump {
+    pub address: String,
+    pub size:    u32,
+    pub file:    PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HeadlessRun {
+    pub cycles: Option<u64>,
+    pub timeout: Option<u32>,
+    pub breakpoint: Option<String>,
+    #[serde(default)]
+    pub floppy: Vec<PathBuf>,
+    pub screen_dump: Option<PathBuf>,
+    #[serde(default)]
+    pub memory_dump: Vec<MemoryDump>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Scripting {
+    #[serde(default)]
+    pub startup: Vec<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -128,6 +180,10 @@ pub struct Backend {
     pub vsync: bool,
     #[serde(default)]
     pub macos_stripe_fix: bool,
+    #[serde(default)]
+    pub fullscreen_exclusive: bool,
+    #[serde(default)]
+    pub frame_pacing: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -149,6 +205,10 @@ pub struct Emulator {
     #[serde(default)]
     pub fuzzer: bool,
     #[serde(default)]
+    pub convert_floppy: Option<PathBuf>,
+    #[serde(default)]
+    pub convert_output: Option<PathBuf>,
+    #[serde(default)]
     pub warpspeed: bool,
     #[serde(default)]
     pub title_hacks: bool,
@@ -159,6 +219,14 @@ pub struct Emulator {
     pub media: Media,
     pub debugger: Debugger,
     pub audio: Audio,
+    #[serde(default)]
+    pub midi: Midi,
+    #[serde(default)]
+    pub rewind: Rewind,
+    #[serde(default)]
+    pub headless_run: HeadlessRun,
+    #[serde(default)]
+    pub scripting: Scripting,
     pub run_bin: Option<String>,
     pub run_bin_seg: Option<u16>,
     pub run_bin_ofs: Option<u16>,
@@ -174,6 +242,8 @@ pub struct Emulator {
     pub pit_output_int_trigger: bool,
 
     pub window: Vec<WindowDefinition>,
+    #[serde(default = "_default_true")]
+    pub auto_card_windows: bool,
     pub scaler_preset: Vec<ScalerPreset>,
     pub input: EmulatorInput,
     pub benchmark: Benchmark,
@@ -186,6 +256,7 @@ pub struct Gui {
     pub theme: Option<MartyGuiTheme>,
     pub menu_theme: Option<MartyGuiTheme>,
     pub zoom: Option<f32>,
+    pub drive_status: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -244,9 +315,17 @@ pub struct Tests {
 pub struct Cpu {
     pub wait_states: Option<bool>,
     pub off_rails_detection: Option<bool>,
+    pub segment_overrun: Option<bool>,
     pub on_halt: Option<OnHaltBehavior>,
     pub instruction_history: Option<bool>,
+    pub instruction_history_len: Option<u32>,
     pub service_interrupt: Option<bool>,
+    pub int_trace: Option<bool>,
+    pub int_trace_vectors: Option<Vec<u8>>,
+    pub stack_guard: Option<bool>,
+    pub stack_guard_limit: Option<u16>,
+    pub stack_guard_wrap: Option<bool>,
+    pub stack_guard_break: Option<bool>,
     #[serde(default)]
     pub trace_on: bool,
     pub trace_mode: Option<TraceMode>,
@@ -275,12 +354,16 @@ pub struct Machine {
     pub raw_rom: bool,
     #[serde(default)]
     pub turbo: bool,
+    pub boot_device: Option<BootDevice>,
     pub cpu: Cpu,
     pub pit_phase: Option<u32>,
     pub input: MachineInput,
     pub disassembly_recording: Option<bool>,
     pub disassembly_file: Option<PathBuf>,
     pub terminal_port: Option<u16>,
+    pub exit_port: Option<u16>,
+    #[serde(default)]
+    pub log_unmapped: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -292,6 +375,11 @@ pub struct EmulatorInput {
     #[serde(default)]
     pub keyboard_joystick: bool,
     #[serde(default)]
+    pub gamepad: GamepadConfig,
+    pub paste_delay: Option<f64>,
+    #[serde(default)]
+    pub macros: Vec<KeyMacroEntry>,
+    #[serde(default)]
     pub debug_keyboard: bool,
 }
 
@@ -338,9 +426,30 @@ pub struct CmdLineArgs {
     #[bpaf(long, switch)]
     pub headless: bool,
 
+    // Headless run and exit conditions
+    #[bpaf(long)]
+    pub run_cycles: Option<u64>,
+    #[bpaf(long)]
+    pub run_timeout: Option<u32>,
+    #[bpaf(long)]
+    pub break_at: Option<String>,
+    #[bpaf(long)]
+    pub exit_port: Option<u16>,
+    #[bpaf(long)]
+    pub floppy: Vec<PathBuf>,
+    #[bpaf(long)]
+    pub dump_screen: Option<PathBuf>,
+    #[bpaf(long)]
+    pub dump_memory: Vec<String>,
+
     #[bpaf(long, switch)]
     pub fuzzer: bool,
 
+    #[bpaf(long)]
+    pub convert_floppy: Option<PathBuf>,
+    #[bpaf(long)]
+    pub convert_output: Option<PathBuf>,
+
     // Emulator options
     #[bpaf(long, switch)]
     pub romscan: bool,
@@ -373,6 +482,9 @@ pub struct CmdLineArgs {
     #[bpaf(long)]
     pub turbo: bool,
 
+    #[bpaf(long)]
+    pub boot_device: Option<BootDevice>,
+
     #[bpaf(long)]
     pub validator: Option<ValidatorType>,
 
@@ -397,6 +509,9 @@ pub struct CmdLineArgs {
     #[bpaf(long)]
     pub run_bin_ofs: Option<u16>,
 
+    #[bpaf(long)]
+    pub script: Vec<PathBuf>,
+
     // Test stuff
     #[bpaf(long)]
     pub test_cpu_type: Option<CpuType>,
@@ -415,6 +530,10 @@ impl ConfigFileParams {
             self.machine.config_overlays = Some(config_overlays);
         }
 
+        if let Some(boot_device) = shell_args.boot_device {
+            self.machine.boot_device = Some(boot_device);
+        }
+
         if let Some(validator) = shell_args.validator {
             self.validator.vtype = Some(validator);
         }
@@ -423,6 +542,49 @@ impl ConfigFileParams {
             self.emulator.basedir = basedir;
         }
 
+        if let Some(convert_floppy) = shell_args.convert_floppy {
+            self.emulator.convert_floppy = Some(convert_floppy);
+        }
+        if let Some(convert_output) = shell_args.convert_output {
+            self.emulator.convert_output = Some(convert_output);
+        }
+
+        // Headless run options. Memory dumps are given as 'address,size,file'.
+        let headless_run = &mut self.emulator.headless_run;
+        if let Some(cycles) = shell_args.run_cycles {
+            headless_run.cycles = Some(cycles);
+        }
+        if let Some(timeout) = shell_args.run_timeout {
+            headless_run.timeout = Some(timeout);
+        }
+        if let Some(breakpoint) = shell_args.break_at {
+            headless_run.breakpoint = Some(breakpoint);
+        }
+        if !shell_args.floppy.is_empty() {
+            headless_run.floppy = shell_args.floppy;
+        }
+        if let Some(screen_dump) = shell_args.dump_screen {
+            headless_run.screen_dump = Some(screen_dump);
+        }
+        for dump in shell_args.dump_memory {
+            let mut parts = dump.splitn(3, ',');
+            match (
+                parts.next(),
+                parts.next().map(|s| s.trim().parse::<u32>()),
+                parts.next(),
+            ) {
+                (Some(address), Some(Ok(size)), Some(file)) => headless_run.memory_dump.push(MemoryDump {
+                    address: address.trim().to_string(),
+                    size,
+                    file: PathBuf::from(file),
+                }),
+                _ => eprintln!("Ignoring invalid memory dump '{}'. Expected 'address,size,file'.", dump),
+            }
+        }
+        if let Some(exit_port) = shell_args.exit_port {
+            self.machine.exit_port = Some(exit_port);
+        }
+
         self.emulator.benchmark_mode |= shell_args.benchmark_mode;
         self.emulator.headless |= shell_args.headless;
         self.emulator.fuzzer |= shell_args.fuzzer;
@@ -443,6 +605,9 @@ impl ConfigFileParams {
         }
          */
 
+        // Scripts given on the command line run after any configured startup scripts.
+        self.emulator.scripting.startup.extend(shell_args.script);
+
         if let Some(run_bin) = shell_args.run_bin {
             self.emulator.run_bin = Some(run_bin);
         }
@@ -38,8 +38,13 @@
    - A file (for screenshots)
 */
 
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use display_backend_pixels::wgpu;
 pub use display_backend_pixels::{
     BufferDimensions,
     CommandEncoder,
@@ -67,6 +72,7 @@ pub use frontend_common::{
         DisplayManagerWindowOptions,
         DisplayTargetDimensions,
         DisplayTargetType,
+        ScreenshotType,
     },
 };
 use frontend_common::{constants::*, display_manager::DisplayInfo};
@@ -85,7 +91,7 @@ use marty_core::{
 };
 use marty_egui::context::GuiRenderContext;
 use marty_pixels_scaler::{DisplayScaler, MartyScaler, ScalerMode};
-use videocard_renderer::{AspectCorrectionMode, AspectRatio, VideoRenderer};
+use videocard_renderer::{AspectCorrectionMode, AspectRatio, RendererEvent, VideoRenderer};
 
 const EGUI_MENU_BAR: u32 = 24;
 
@@ -178,6 +184,7 @@ pub struct WgpuDisplayManager {
     card_id_map: HashMap<VideoCardId, Vec<usize>>, // Card id maps to a Vec<usize> as a single card can have multiple targets.
     primary_idx: Option<usize>,
     scaler_presets: HashMap<String, ScalerPreset>,
+    vsync: bool,
 }
 
 impl Default for WgpuDisplayManager {
@@ -190,6 +197,7 @@ impl Default for WgpuDisplayManager {
             card_id_map: HashMap::new(),
             primary_idx: None,
             scaler_presets: HashMap::new(),
+            vsync: false,
         }
     }
 }
@@ -307,6 +315,7 @@ impl WgpuDisplayManagerBuilder {
         };
 
         let mut dm = WgpuDisplayManager::new();
+        dm.vsync = config.emulator.backend.vsync;
 
         // Install scaler presets
         for preset in scaler_presets.iter() {
@@ -334,6 +343,45 @@ impl WgpuDisplayManagerBuilder {
                         .expect("FATAL: Failed to create a window target");
                 }
             }
+
+            // Give any secondary video card not displayed by a configured window its own window, so that
+            // dual-monitor configurations show both displays.
+            if config.emulator.auto_card_windows {
+                for card_idx in 1..cards.len() {
+                    let displayed = config
+                        .emulator
+                        .window
+                        .iter()
+                        .enumerate()
+                        .any(|(i, w)| (i == 0 || w.enabled) && w.card_id == Some(card_idx));
+
+                    if !displayed {
+                        log::debug!("Creating secondary window for undisplayed card: {:?}", cards[card_idx]);
+                        let window_def = WindowDefinition {
+                            enabled: true,
+                            name: format!("Display {}", card_idx),
+                            size: Some(VideoDimensions {
+                                w: DEFAULT_RESOLUTION_W,
+                                h: DEFAULT_RESOLUTION_H,
+                            }),
+                            resizable: false,
+                            card_id: Some(card_idx),
+                            card_scale: Some(1.0),
+                            always_on_top: false,
+                            scaler_preset: None,
+                        };
+                        Self::create_target_from_window_def(
+                            &mut dm,
+                            false,
+                            &window_def,
+                            &cards,
+                            gui_options,
+                            icon.clone(),
+                        )
+                        .expect("FATAL: Failed to create a window target");
+                    }
+                }
+            }
         }
 
         Ok(dm)
@@ -559,6 +607,15 @@ impl DisplayTargetContext<PixelsBackend> {
             });
         }
 
+        scaler_update.push(ScalerOption::Mask {
+            mask: params.crt_mask,
+            intensity: params.crt_mask_intensity,
+        });
+
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_phosphor_persistence(params.crt_phosphor_persistence);
+        }
+
         match params.crt_phosphor_type {
             PhosphorType::Color => scaler_update.push(ScalerOption::Mono {
                 enabled: false,
@@ -596,6 +653,102 @@ impl DisplayTargetContext<PixelsBackend> {
             .set_options(self.backend.as_mut().unwrap().get_backend_raw().unwrap(), scaler_update);
     }
 
+    /// Render the scaler output into an offscreen texture and save it as a PNG. This captures the
+    /// display as it appears in the window, including shader effects, but without the GUI.
+    pub fn save_display_output(&mut self, path: &Path) -> Result<(), Error> {
+        let (backend, scaler) = match (&mut self.backend, &self.scaler) {
+            (Some(backend), Some(scaler)) => (backend, scaler),
+            _ => return Err(anyhow!("Display target has no scaler output to capture!")),
+        };
+
+        let surface = backend.surface_dimensions();
+        let (w, h) = (surface.w.max(1), surface.h.max(1));
+
+        let pixels = backend.get_backend_raw().unwrap();
+        let device = pixels.device();
+        let format = pixels.render_texture_format();
+
+        let extent = wgpu::Extent3d {
+            width: w,
+            height: h,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("marty_display_capture_texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows copied out of a texture must be padded to COPY_BYTES_PER_ROW_ALIGNMENT.
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (w * 4).div_ceil(align) * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("marty_display_capture_buffer"),
+            size: padded_row as u64 * h as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("marty_display_capture_encoder"),
+        });
+        scaler.render(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture:   &texture,
+                mip_level: 0,
+                origin:    wgpu::Origin3d::ZERO,
+                aspect:    wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(h),
+                },
+            },
+            extent,
+        );
+        pixels.queue().submit(Some(encoder.finish()));
+
+        // Wait for the copy to complete.
+        let slice = buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let bgra = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut packed = Vec::with_capacity((w * h * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_row as usize).take(h as usize) {
+                for px in row[0..(w * 4) as usize].chunks_exact(4) {
+                    match bgra {
+                        true => packed.extend_from_slice(&[px[2], px[1], px[0], 0xFF]),
+                        false => packed.extend_from_slice(&[px[0], px[1], px[2], 0xFF]),
+                    }
+                }
+            }
+        }
+        buffer.unmap();
+
+        image::save_buffer(path, &packed, w, h, image::ColorType::Rgba8)?;
+        Ok(())
+    }
+
     pub fn request_params(&mut self, params: DisplayTargetParams) {
         if self.requested_params.is_some() {
             log::warn!("Requesting param change with unresolved param request pending.");
@@ -728,7 +881,7 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
                 };
 
                 // Create the backend.
-                let mut pb = PixelsBackend::new(w, h, &window)?;
+                let mut pb = PixelsBackend::new(w, h, self.vsync, &window)?;
 
                 // Create the scaler.
                 let _scale_mode = match main_window {
@@ -1361,6 +1514,13 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
         }
     }
 
+    fn window_pos_to_display_pos(&mut self, wid: WindowId, x: f32, y: f32) -> Option<(VideoCardId, f32, f32)> {
+        let dtc = &self.targets[*self.window_id_map.get(&wid)?];
+        let card_id = dtc.card_id?;
+        let (u, v) = dtc.scaler.as_ref()?.surface_to_texture(x, y)?;
+        Some((card_id, u, v))
+    }
+
     fn with_gui_by_wid<F>(&mut self, wid: WindowId, mut f: F)
     where
         F: FnMut(&mut GuiRenderContext, &Window),
@@ -1540,18 +1700,39 @@ impl DisplayManager<PixelsBackend, GuiRenderContext, WindowId, Window> for WgpuD
         Ok(())
     }
 
-    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf) -> Result<(), Error> {
+    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf, stype: ScreenshotType) -> Result<(), Error> {
         if dt_idx >= self.targets.len() {
             return Err(anyhow!("Display target out of range!"));
         }
 
-        let filename = file_util::find_unique_filename(&path, "screenshot", "png");
+        let base = match stype {
+            ScreenshotType::Raw => "raw",
+            ScreenshotType::Rendered => "screenshot",
+            ScreenshotType::Display => "display",
+        };
+        let filename = file_util::find_unique_timestamped_filename(&path, base, "png");
 
-        if let Some(renderer) = &mut self.targets[dt_idx].renderer {
-            renderer.request_screenshot(&filename);
-        }
-        else {
-            return Err(anyhow!("No renderer for display target!"));
+        let dt = &mut self.targets[dt_idx];
+        match stype {
+            ScreenshotType::Display => {
+                // The display output can be captured immediately.
+                dt.save_display_output(&filename)?;
+                log::info!("Saved display output: {}", filename.display());
+                if let Some(renderer) = &mut dt.renderer {
+                    renderer.send_event(RendererEvent::ScreenshotSaved);
+                }
+            }
+            _ => {
+                if let Some(renderer) = &mut dt.renderer {
+                    match stype {
+                        ScreenshotType::Raw => renderer.request_raw_capture(&filename),
+                        _ => renderer.request_screenshot(&filename),
+                    }
+                }
+                else {
+                    return Err(anyhow!("No renderer for display target!"));
+                }
+            }
         }
 
         Ok(())
@@ -0,0 +1,153 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::cassette_manager.rs
+
+    Discover cassette tape images in the 'cassette' resource and provide an
+    interface for enumerating, loading and saving them.
+
+    Tapes may be WAV recordings or CAS data images. A tape is saved in the
+    format given by its file extension.
+
+*/
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+
+use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
+use marty_core::tape_image::TapeImage;
+
+pub struct CassetteImage {
+    idx:  usize,
+    name: OsString,
+    path: PathBuf,
+}
+
+pub struct CassetteManager {
+    files: Vec<ResourceItem>,
+    image_vec: Vec<CassetteImage>,
+    image_map: HashMap<OsString, usize>,
+    extensions: Vec<OsString>,
+}
+
+impl Default for CassetteManager {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            image_vec: Vec::new(),
+            image_map: HashMap::new(),
+            extensions: vec![OsString::from("cas"), OsString::from("wav")],
+        }
+    }
+}
+
+fn is_wav(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+}
+
+impl CassetteManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        // Clear and rebuild image lists.
+        self.image_vec.clear();
+        self.image_map.clear();
+
+        let items = rm.enumerate_items("cassette", true, true, Some(self.extensions.clone()))?;
+
+        // Index mapping between 'files' vec and 'image_vec' should be maintained.
+        for item in items.iter() {
+            let idx = self.image_vec.len();
+            let name = item.full_path.file_name().unwrap().to_os_string();
+            self.image_vec.push(CassetteImage {
+                idx,
+                name: name.clone(),
+                path: item.full_path.clone(),
+            });
+            self.image_map.insert(name, idx);
+        }
+
+        self.files = items;
+        Ok(true)
+    }
+
+    pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
+        let tree = rm.items_to_tree("cassette", &self.files)?;
+        Ok(tree)
+    }
+
+    pub fn get_cassette_name(&self, idx: usize) -> Option<OsString> {
+        self.image_vec.get(idx).map(|image| image.name.clone())
+    }
+
+    pub fn get_cassette_path(&self, idx: usize) -> Option<PathBuf> {
+        self.image_vec.get(idx).map(|image| image.path.clone())
+    }
+
+    pub fn load_cassette(&self, idx: usize, rm: &ResourceManager) -> Result<TapeImage, Error> {
+        let image = self
+            .image_vec
+            .get(idx)
+            .ok_or_else(|| anyhow!("Cassette image index not found: {}", idx))?;
+        log::debug!("Loading cassette image {}: {:?}", image.idx, image.path);
+
+        let data = rm.read_resource_from_path(&image.path)?;
+        if is_wav(&image.path) {
+            TapeImage::from_wav(&data)
+        }
+        else {
+            TapeImage::from_cas(&data)
+        }
+    }
+
+    /// Write a tape to the specified path, in the format given by its extension.
+    pub fn save_cassette(&self, path: &Path, tape: &TapeImage) -> Result<(), Error> {
+        let data = if is_wav(path) { tape.to_wav() } else { tape.to_cas() };
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Create an empty CAS image to record on, returning its index.
+    pub fn create_cassette_image(&mut self, rm: &ResourceManager) -> Result<usize, Error> {
+        let path = rm.get_available_filename("cassette", "tape", Some("cas"))?;
+        fs::write(&path, b"")?;
+        log::debug!("Created cassette image: {:?}", path);
+
+        self.scan_resource(rm)?;
+        path.file_name()
+            .and_then(|name| self.image_map.get(name))
+            .copied()
+            .ok_or_else(|| anyhow!("Created cassette image not found: {:?}", path))
+    }
+}
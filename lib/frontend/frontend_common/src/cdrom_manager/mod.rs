@@ -0,0 +1,118 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::cdrom_manager.rs
+
+    Discover CD-ROM images in the 'cdrom' resource and provide an interface
+    for enumerating and loading them.
+
+    Images may be ISO files or CUE sheets. The files referenced by a CUE
+    sheet are opened from the directory containing it.
+
+*/
+
+use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+
+use anyhow::{anyhow, Error};
+
+use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
+use marty_core::cd_image::CdImage;
+
+pub struct CdromImage {
+    idx:  usize,
+    name: OsString,
+    path: PathBuf,
+}
+
+pub struct CdromManager {
+    files: Vec<ResourceItem>,
+    image_vec: Vec<CdromImage>,
+    image_map: HashMap<OsString, usize>,
+    extensions: Vec<OsString>,
+}
+
+impl Default for CdromManager {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            image_vec: Vec::new(),
+            image_map: HashMap::new(),
+            extensions: vec![OsString::from("iso"), OsString::from("cue")],
+        }
+    }
+}
+
+impl CdromManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
+        // Clear and rebuild image lists.
+        self.image_vec.clear();
+        self.image_map.clear();
+
+        let items = rm.enumerate_items("cdrom", true, true, Some(self.extensions.clone()))?;
+
+        // Index mapping between 'files' vec and 'image_vec' should be maintained.
+        for item in items.iter() {
+            let idx = self.image_vec.len();
+            let name = item.full_path.file_name().unwrap().to_os_string();
+            self.image_vec.push(CdromImage {
+                idx,
+                name: name.clone(),
+                path: item.full_path.clone(),
+            });
+            self.image_map.insert(name, idx);
+        }
+
+        self.files = items;
+        Ok(true)
+    }
+
+    pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
+        let tree = rm.items_to_tree("cdrom", &self.files)?;
+        Ok(tree)
+    }
+
+    pub fn get_cdrom_name(&self, idx: usize) -> Option<OsString> {
+        self.image_vec.get(idx).map(|image| image.name.clone())
+    }
+
+    pub fn get_cdrom_path(&self, idx: usize) -> Option<PathBuf> {
+        self.image_vec.get(idx).map(|image| image.path.clone())
+    }
+
+    pub fn load_cdrom(&self, idx: usize) -> Result<CdImage, Error> {
+        let image = self
+            .image_vec
+            .get(idx)
+            .ok_or_else(|| anyhow!("CD-ROM image index not found: {}", idx))?;
+        log::debug!("Loading CD-ROM image {}: {:?}", image.idx, image.path);
+
+        CdImage::open(&image.path)
+    }
+}
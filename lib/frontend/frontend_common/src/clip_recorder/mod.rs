@@ -0,0 +1,196 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::clip_recorder::mod.rs
+
+    Captures short, bounded-length clips of display output to an animated PNG.
+
+    Frames are converted to an 8-bit palette as they are captured. The first
+    256 unique colors seen are stored exactly, which covers any of the direct
+    RGBI or VGA modes; later colors are mapped to the nearest palette entry.
+    Runs of identical frames are merged into a single frame with a longer
+    delay, so static screens cost almost nothing.
+
+*/
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+
+struct ClipFrame {
+    data:  Vec<u8>,
+    delay: u16, // Frame duration in units of 1/fps seconds
+}
+
+pub struct ClipRecorder {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    fps: u32,
+    frame_step: u32,
+    frame_ct: u32,
+    max_frames: u32,
+    captured: u32,
+    palette: Vec<[u8; 3]>,
+    color_map: HashMap<[u8; 3], u8>,
+    frames: Vec<ClipFrame>,
+}
+
+impl ClipRecorder {
+    /// Create a new clip recorder that will save an APNG to `path` when finished.
+    /// Only every `frame_step`th frame pushed is captured, so the clip plays back at
+    /// `source_fps / frame_step` frames per second. Capture stops after `max_seconds`.
+    pub fn new(
+        path: &Path,
+        width: u32,
+        height: u32,
+        source_fps: u32,
+        frame_step: u32,
+        max_seconds: u32,
+    ) -> Result<Self, Error> {
+        if width == 0 || height == 0 || source_fps == 0 || frame_step == 0 {
+            return Err(anyhow!("Invalid clip parameters"));
+        }
+        let fps = std::cmp::max(1, source_fps / frame_step);
+        Ok(Self {
+            path: path.to_path_buf(),
+            width,
+            height,
+            fps,
+            frame_step,
+            frame_ct: 0,
+            max_frames: fps * max_seconds,
+            captured: 0,
+            palette: Vec::new(),
+            color_map: HashMap::new(),
+            frames: Vec::new(),
+        })
+    }
+
+    /// Returns true once the maximum clip duration has been captured.
+    pub fn is_full(&self) -> bool {
+        self.captured >= self.max_frames
+    }
+
+    /// Add a frame from an RGBA buffer of `w` x `h` pixels. If the frame size differs from the
+    /// clip size, the frame is cropped or padded with black.
+    pub fn push_frame(&mut self, buf: &[u8], w: u32, h: u32) {
+        if self.is_full() {
+            return;
+        }
+        self.frame_ct += 1;
+        if (self.frame_ct - 1) % self.frame_step != 0 {
+            return;
+        }
+
+        let black = self.color_index([0, 0, 0]);
+        let mut data = vec![black; (self.width * self.height) as usize];
+
+        let copy_w = std::cmp::min(w, self.width) as usize;
+        let copy_h = std::cmp::min(h, self.height) as usize;
+        for y in 0..copy_h {
+            let src_start = y * w as usize * 4;
+            if src_start + copy_w * 4 > buf.len() {
+                break;
+            }
+            let dst_start = y * self.width as usize;
+            for x in 0..copy_w {
+                let s = &buf[src_start + x * 4..src_start + x * 4 + 3];
+                data[dst_start + x] = self.color_index([s[0], s[1], s[2]]);
+            }
+        }
+
+        self.captured += 1;
+        match self.frames.last_mut() {
+            Some(last) if last.data == data && last.delay < u16::MAX => last.delay += 1,
+            _ => self.frames.push(ClipFrame { data, delay: 1 }),
+        }
+    }
+
+    /// Write the captured clip to disk as an animated PNG.
+    pub fn finish(self) -> Result<PathBuf, Error> {
+        if self.frames.is_empty() {
+            return Err(anyhow!("No frames were captured"));
+        }
+
+        let file = File::create(&self.path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(self.palette.iter().flatten().copied().collect::<Vec<u8>>());
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+
+        let mut writer = encoder.write_header()?;
+        for frame in &self.frames {
+            writer.set_frame_delay(frame.delay, self.fps as u16)?;
+            writer.write_image_data(&frame.data)?;
+        }
+        writer.finish()?;
+
+        log::debug!(
+            "Saved clip of {} frames ({} unique) with {} colors to {}",
+            self.captured,
+            self.frames.len(),
+            self.palette.len(),
+            self.path.display()
+        );
+        Ok(self.path)
+    }
+
+    /// Return the palette index for the specified color, adding it to the palette if there is room,
+    /// or mapping it to the nearest existing entry if not.
+    fn color_index(&mut self, color: [u8; 3]) -> u8 {
+        if let Some(idx) = self.color_map.get(&color) {
+            return *idx;
+        }
+
+        let idx = if self.palette.len() < 256 {
+            self.palette.push(color);
+            (self.palette.len() - 1) as u8
+        }
+        else {
+            let mut best = 0;
+            let mut best_dist = u32::MAX;
+            for (i, entry) in self.palette.iter().enumerate() {
+                let dist = (0..3)
+                    .map(|c| (entry[c] as i32 - color[c] as i32).pow(2) as u32)
+                    .sum::<u32>();
+                if dist < best_dist {
+                    best = i;
+                    best_dist = dist;
+                }
+            }
+            best as u8
+        };
+        self.color_map.insert(color, idx);
+        idx
+    }
+}
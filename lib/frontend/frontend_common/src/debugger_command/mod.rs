@@ -0,0 +1,162 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::debugger_command::mod.rs
+
+    Parses commands entered on the debugger command line. Commands are a
+    keyword followed by whitespace separated arguments. Address arguments
+    are left as strings, to be evaluated against the CPU by the front end.
+
+*/
+
+use marty_core::breakpoints::WatchMode;
+use std::str::FromStr;
+
+pub const DEBUGGER_COMMAND_HELP: &str =
+    "profile start | profile stop | profile show [count] | watch <address> [size] [r|w|rw] | unwatch";
+
+/// Number of hot spots listed by 'profile show' when no count is given.
+pub const PROFILE_SHOW_DEFAULT: usize = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebuggerCommand {
+    /// Start counting executed opcodes and code addresses.
+    ProfileStart,
+    /// Stop profiling and save the counters to a CSV file.
+    ProfileStop,
+    /// Log the specified number of hot spots found so far, without stopping the profiler.
+    ProfileShow(usize),
+    /// Set the watchpoint to the specified address expression, size in bytes and access mode.
+    Watch(String, u32, WatchMode),
+    /// Clear the watchpoint.
+    Unwatch,
+}
+
+impl FromStr for DebuggerCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args: Vec<&str> = s.split_whitespace().collect();
+        let keyword = args.first().map(|k| k.to_lowercase()).unwrap_or_default();
+
+        let command = match keyword.as_str() {
+            "profile" => parse_profile(&args[1..]),
+            "watch" => parse_watch(&args[1..]),
+            "unwatch" if args.len() == 1 => Some(DebuggerCommand::Unwatch),
+            "" => return Err(format!("No command given. Commands: {}", DEBUGGER_COMMAND_HELP)),
+            _ => None,
+        };
+        command.ok_or(format!(
+            "Invalid command: '{}'. Commands: {}",
+            s.trim(),
+            DEBUGGER_COMMAND_HELP
+        ))
+    }
+}
+
+fn parse_profile(args: &[&str]) -> Option<DebuggerCommand> {
+    match args {
+        [sub] if sub.eq_ignore_ascii_case("start") => Some(DebuggerCommand::ProfileStart),
+        [sub] if sub.eq_ignore_ascii_case("stop") => Some(DebuggerCommand::ProfileStop),
+        [sub] if sub.eq_ignore_ascii_case("show") => Some(DebuggerCommand::ProfileShow(PROFILE_SHOW_DEFAULT)),
+        [sub, count] if sub.eq_ignore_ascii_case("show") => count.parse().ok().map(DebuggerCommand::ProfileShow),
+        _ => None,
+    }
+}
+
+/// Parse the arguments of a watch command. The size is in hex, like the Watch Size field of the CPU Control window,
+/// and defaults to one byte. The mode defaults to any access.
+fn parse_watch(args: &[&str]) -> Option<DebuggerCommand> {
+    let (address, rest) = args.split_first()?;
+    let mut size = 1;
+    let mut mode = WatchMode::Access;
+    let mut rest = rest.iter();
+    let mut next = rest.next();
+
+    if let Some(arg) = next {
+        if let Ok(s) = u32::from_str_radix(arg, 16) {
+            size = s.max(1);
+            next = rest.next();
+        }
+    }
+    if let Some(arg) = next {
+        mode = match arg.to_lowercase().as_str() {
+            "r" => WatchMode::Read,
+            "w" => WatchMode::Write,
+            "rw" => WatchMode::Access,
+            _ => return None,
+        };
+        next = rest.next();
+    }
+    match next {
+        Some(_) => None,
+        None => Some(DebuggerCommand::Watch(address.to_string(), size, mode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile() {
+        assert_eq!("profile start".parse(), Ok(DebuggerCommand::ProfileStart));
+        assert_eq!("  PROFILE Stop ".parse(), Ok(DebuggerCommand::ProfileStop));
+        assert_eq!(
+            "profile show".parse(),
+            Ok(DebuggerCommand::ProfileShow(PROFILE_SHOW_DEFAULT))
+        );
+        assert_eq!("profile show 25".parse(), Ok(DebuggerCommand::ProfileShow(25)));
+
+        assert!("profile show x".parse::<DebuggerCommand>().is_err());
+        assert!("profile show 5 6".parse::<DebuggerCommand>().is_err());
+        assert!("profile start now".parse::<DebuggerCommand>().is_err());
+        assert!("".parse::<DebuggerCommand>().is_err());
+        assert!("frobnicate".parse::<DebuggerCommand>().is_err());
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        assert_eq!(
+            "watch 1234:0010".parse(),
+            Ok(DebuggerCommand::Watch("1234:0010".to_string(), 1, WatchMode::Access))
+        );
+        assert_eq!(
+            "watch B8000 FA0 w".parse(),
+            Ok(DebuggerCommand::Watch("B8000".to_string(), 0xFA0, WatchMode::Write))
+        );
+        assert_eq!(
+            "watch ds:si R".parse(),
+            Ok(DebuggerCommand::Watch("ds:si".to_string(), 1, WatchMode::Read))
+        );
+        assert_eq!("unwatch".parse(), Ok(DebuggerCommand::Unwatch));
+
+        assert!("watch".parse::<DebuggerCommand>().is_err());
+        assert!("watch 100 10 x".parse::<DebuggerCommand>().is_err());
+        assert!("watch 100 10 r extra".parse::<DebuggerCommand>().is_err());
+        assert!("unwatch 100".parse::<DebuggerCommand>().is_err());
+    }
+}
@@ -49,6 +49,18 @@ use crate::{
 use marty_core::device_traits::videocard::{DisplayApertureType, DisplayExtents, VideoCardId, VideoType};
 use videocard_renderer::{RendererConfigParams, VideoRenderer};
 
+/// The stage of the display pipeline captured by a screenshot.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ScreenshotType {
+    /// The video card's raw framebuffer, before any palette lookup or rendering.
+    Raw,
+    /// The RGBA frame produced by the renderer, after composite conversion and aspect correction.
+    #[default]
+    Rendered,
+    /// The final display output after scaling and shader effects, without the GUI.
+    Display,
+}
+
 #[derive(Copy, Clone)]
 pub enum DisplayTargetType {
     WindowBackground { main_window: bool, has_gui: bool, has_menu: bool },
@@ -197,6 +209,12 @@ pub trait DisplayManager<B, G, Wi, W> {
     /// Return the associated VideoRenderer, if Some, given a card id
     fn get_renderer_by_card_id(&mut self, id: VideoCardId) -> Option<&mut VideoRenderer>;
 
+    /// Translate a position within the client area of the specified window, in physical pixels, to
+    /// a normalized (0.0-1.0) position within the video card output displayed in that window.
+    /// Returns the displayed card's VideoCardId and the position, or None if the window displays no
+    /// card or the position is outside of the display.
+    fn window_pos_to_display_pos(&mut self, wid: Wi, x: f32, y: f32) -> Option<(VideoCardId, f32, f32)>;
+
     /// Returns the associated VideoRenderer for the primary video card. If no primary card
     /// is present, returns None.
     fn get_primary_renderer(&mut self) -> Option<&mut VideoRenderer>;
@@ -305,7 +323,7 @@ pub trait DisplayManager<B, G, Wi, W> {
     fn set_scaler_mode(&mut self, dt_idx: usize, mode: ScalerMode) -> Result<(), Error>;
 
     /// Save a screenshot of the specified display target to the specified path.
-    /// A unique filename will be generated assuming the path is a directory.
+    /// A unique, timestamped filename will be generated assuming the path is a directory.
     /// No operational error is returned as screenshot operation may be deferred.
-    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf) -> Result<(), Error>;
+    fn save_screenshot(&mut self, dt_idx: usize, path: PathBuf, stype: ScreenshotType) -> Result<(), Error>;
 }
@@ -85,6 +85,7 @@ pub enum ScalerOption {
     Mono { enabled: bool, r: f32, g: f32, b: f32, a: f32 },
     Geometry { h_curvature: f32, v_curvature: f32, corner_radius: f32 },
     Scanlines { enabled: Option<bool>, lines: Option<u32>, intensity: Option<f32> },
+    Mask { mask: CrtMaskType, intensity: f32 },
     Effect(ScalerEffect),
 }
 
@@ -96,6 +97,22 @@ pub enum PhosphorType {
     Amber,
 }
 
+/// Default strength of the phosphor mask. 0.0 leaves the image unchanged, 1.0 fully darkens the masked subpixels.
+pub const CRT_MASK_INTENSITY_DEFAULT: f32 = 0.5;
+
+const fn _default_mask_intensity() -> f32 {
+    CRT_MASK_INTENSITY_DEFAULT
+}
+
+/// The type of phosphor mask to simulate over the display.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+pub enum CrtMaskType {
+    #[default]
+    None,
+    ApertureGrille,
+    ShadowMask,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ScalerPreset {
     pub name: String,
@@ -108,6 +125,12 @@ pub struct ScalerPreset {
     pub crt_corner_radius: f32,
     pub crt_scanlines: bool,
     pub crt_phosphor_type: PhosphorType,
+    #[serde(default)]
+    pub crt_phosphor_persistence: f32,
+    #[serde(default)]
+    pub crt_mask: CrtMaskType,
+    #[serde(default = "_default_mask_intensity")]
+    pub crt_mask_intensity: f32,
     pub gamma: f32,
     // Options for associated renderer
     pub renderer: RendererConfigParams,
@@ -121,6 +144,9 @@ pub struct ScalerParams {
     pub crt_corner_radius: f32,
     pub crt_scanlines: bool,
     pub crt_phosphor_type: PhosphorType,
+    pub crt_phosphor_persistence: f32,
+    pub crt_mask: CrtMaskType,
+    pub crt_mask_intensity: f32,
     pub gamma: f32,
 }
 
@@ -132,6 +158,9 @@ impl From<ScalerPreset> for ScalerParams {
             crt_barrel_distortion: value.crt_barrel_distortion,
             crt_scanlines: value.crt_scanlines,
             crt_phosphor_type: value.crt_phosphor_type,
+            crt_phosphor_persistence: value.crt_phosphor_persistence,
+            crt_mask: value.crt_mask,
+            crt_mask_intensity: value.crt_mask_intensity,
             crt_corner_radius: value.crt_corner_radius,
             gamma: value.gamma,
         }
@@ -147,6 +176,9 @@ impl Default for ScalerParams {
             crt_corner_radius: 0.0,
             crt_scanlines: false,
             crt_phosphor_type: PhosphorType::Color,
+            crt_phosphor_persistence: 0.0,
+            crt_mask: CrtMaskType::None,
+            crt_mask_intensity: CRT_MASK_INTENSITY_DEFAULT,
             gamma: 1.0,
         }
     }
@@ -192,4 +224,7 @@ pub trait DisplayScaler<B>: Send + Sync {
     fn set_fill_color(&mut self, fill: MartyColor);
     fn set_option(&mut self, pixels: &B, opt: ScalerOption, update: bool) -> bool;
     fn set_options(&mut self, pixels: &B, opts: Vec<ScalerOption>);
+    /// Convert a position on the destination surface, in pixels, to a normalized (0.0-1.0) position
+    /// within the source texture. Returns None if the position is outside the scaled texture.
+    fn surface_to_texture(&self, x: f32, y: f32) -> Option<(f32, f32)>;
 }
@@ -0,0 +1,213 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    frontend_common::floppy_manager::directory.rs
+
+    Mount a host directory as a floppy disk.
+
+    The directory's files and subdirectories are copied into a freshly built
+    FAT12 volume, with long host names shortened to DOS 8.3 names. Changes
+    made by the guest can be synced back: new and modified files are written
+    to the directory, while files deleted by the guest are left in place on
+    the host.
+
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Error;
+
+use marty_core::floppy_image::fat12::{self, FatEntry, FatNode};
+
+pub struct DirectoryFloppy {
+    path: PathBuf,
+    // The host path each file and directory on the disk was built from, keyed by DOS path.
+    host_paths: HashMap<String, PathBuf>,
+}
+
+fn dos_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    }
+    else {
+        format!("{}\\{}", parent, name)
+    }
+}
+
+impl DirectoryFloppy {
+    /// Build a raw sector image of the given size holding the contents of the specified directory.
+    pub fn build(path: &Path, size: usize) -> Result<(Self, Vec<u8>), Error> {
+        let mut dir = Self {
+            path: path.to_path_buf(),
+            host_paths: HashMap::new(),
+        };
+        let entries = dir.read_host_dir(path, "")?;
+
+        let label: String = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_uppercase())
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+            .take(11)
+            .collect();
+        let label = if label.is_empty() { None } else { Some(label.as_str()) };
+
+        let image = fat12::build_volume(size, label, &entries)?;
+        log::debug!(
+            "Built {} byte floppy image from directory {} with {} files",
+            size,
+            path.display(),
+            dir.host_paths.len()
+        );
+        Ok((dir, image))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write files created or modified by the guest back to the directory. Returns the number of files written.
+    pub fn sync(&mut self, image: &[u8]) -> Result<usize, Error> {
+        let entries = fat12::read_volume(image)?;
+        let path = self.path.clone();
+        self.write_host_dir(&entries, "", &path)
+    }
+
+    fn read_host_dir(&mut self, host_dir: &Path, parent: &str) -> Result<Vec<FatEntry>, Error> {
+        let mut items = fs::read_dir(host_dir)?.collect::<Result<Vec<_>, _>>()?;
+        items.sort_by_key(|item| item.file_name());
+
+        let mut names = Vec::new();
+        let mut entries = Vec::new();
+        for item in items {
+            let host_name = item.file_name().to_string_lossy().to_string();
+            let file_type = item.file_type()?;
+            // Skip hidden files and symbolic links, which could lead outside the directory.
+            if host_name.starts_with('.') || file_type.is_symlink() {
+                continue;
+            }
+
+            let name = fat12::short_name(&host_name, &names);
+            let path = dos_path(parent, &name);
+            let entry = if file_type.is_dir() {
+                FatEntry::directory(&name, self.read_host_dir(&item.path(), &path)?)
+            }
+            else if file_type.is_file() {
+                FatEntry::file(&name, fs::read(item.path())?)
+            }
+            else {
+                continue;
+            };
+            let modified = item.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+
+            names.push(name);
+            self.host_paths.insert(path, item.path());
+            entries.push(entry.with_time(modified));
+        }
+        Ok(entries)
+    }
+
+    fn write_host_dir(&mut self, entries: &[FatEntry], parent: &str, host_dir: &Path) -> Result<usize, Error> {
+        let mut written = 0;
+        for entry in entries {
+            // Names come from the guest's disk. Anything that isn't a plain DOS name could lead outside the directory.
+            if !fat12::is_valid_short_name(&entry.name) {
+                log::warn!("Skipping file with invalid name on floppy: {:?}", entry.name);
+                continue;
+            }
+            let path = dos_path(parent, &entry.name);
+            let host_path = match self.host_paths.get(&path) {
+                Some(host_path) => host_path.clone(),
+                None => host_dir.join(&entry.name),
+            };
+            if !host_path.starts_with(&self.path) {
+                log::warn!("Skipping file outside of mounted directory: {}", host_path.display());
+                continue;
+            }
+
+            match &entry.node {
+                FatNode::Directory(children) => {
+                    if !host_path.is_dir() {
+                        log::debug!("Creating directory from floppy: {}", host_path.display());
+                        fs::create_dir_all(&host_path)?;
+                    }
+                    self.host_paths.insert(path.clone(), host_path.clone());
+                    written += self.write_host_dir(children, &path, &host_path)?;
+                }
+                FatNode::File(data) => {
+                    let unchanged = fs::read(&host_path).is_ok_and(|host_data| host_data == *data);
+                    if !unchanged {
+                        log::debug!("Writing file from floppy: {}", host_path.display());
+                        fs::write(&host_path, data)?;
+                        written += 1;
+                    }
+                    self.host_paths.insert(path, host_path);
+                }
+            }
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_rejects_unsafe_names() {
+        let base = std::env::temp_dir().join(format!("marty_directory_floppy_{}", std::process::id()));
+        let host = base.join("host");
+        fs::create_dir_all(&host).unwrap();
+
+        let mut dir = DirectoryFloppy {
+            path: host.clone(),
+            host_paths: HashMap::new(),
+        };
+        let entries = vec![
+            FatEntry::file("../EVIL.TXT", vec![1]),
+            FatEntry::file("..\\EVIL.TXT", vec![2]),
+            FatEntry::file(&base.join("EVIL.TXT").to_string_lossy(), vec![3]),
+            FatEntry::file("C:EVIL.TXT", vec![4]),
+            FatEntry::directory("..", vec![FatEntry::file("EVIL.TXT", vec![5])]),
+            FatEntry::file("GOOD.TXT", vec![6]),
+        ];
+        let written = dir.write_host_dir(&entries, "", &host).unwrap();
+
+        assert_eq!(written, 1);
+        assert_eq!(fs::read(host.join("GOOD.TXT")).unwrap(), vec![6]);
+        assert!(!base.join("EVIL.TXT").exists());
+        assert_eq!(fs::read_dir(&host).unwrap().count(), 1);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -29,8 +29,19 @@
     Discover floppy images in the 'floppy' resource and provide an interface
     for enumerating and loading them.
 
+    Raw sector images are identified by the configured list of extensions.
+    Bitstream, flux and archival images are always recognized, but can't be
+    saved, as the core only writes raw sector images.
+
+    Each subdirectory of the 'floppy_dir' resource can also be mounted as a
+    floppy disk; see directory.rs.
+
 */
 
+mod directory;
+
+pub use directory::DirectoryFloppy;
+
 use crate::resource_manager::{PathTreeNode, ResourceItem, ResourceManager};
 use std::{
     collections::HashMap,
@@ -40,7 +51,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+
+/// Extensions of image formats other than raw sector images, which are loaded but not saved.
+pub const IMAGE_FORMAT_EXTENSIONS: [&str; 6] = ["86f", "scp", "imd", "td0", "psi", "pri"];
 
 #[derive(Debug)]
 pub enum FloppyError {
@@ -48,6 +62,7 @@ pub enum FloppyError {
     ImageNotFound,
     FileReadError,
     FileWriteError,
+    UnsupportedFormat,
 }
 impl std::error::Error for FloppyError {}
 impl Display for FloppyError {
@@ -57,6 +72,7 @@ impl Display for FloppyError {
             FloppyError::ImageNotFound => write!(f, "Specified image name could not be found in floppy manager."),
             FloppyError::FileReadError => write!(f, "A file read error occurred."),
             FloppyError::FileWriteError => write!(f, "A file write error occurred."),
+            FloppyError::UnsupportedFormat => write!(f, "Images in this format can't be saved."),
         }
     }
 }
@@ -74,6 +90,7 @@ pub struct FloppyManager {
     image_vec: Vec<FloppyImage>,
     image_map: HashMap<OsString, usize>,
     extensions: Vec<OsString>,
+    directories: Vec<PathBuf>,
 }
 
 impl FloppyManager {
@@ -83,6 +100,7 @@ impl FloppyManager {
             image_vec: Vec::new(),
             image_map: HashMap::new(),
             extensions: vec![OsString::from("img"), OsString::from("ima")],
+            directories: Vec::new(),
         }
     }
 
@@ -95,13 +113,26 @@ impl FloppyManager {
         }
     }
 
+    /// Return the extensions of all image files the floppy manager can load.
+    fn image_extensions(&self) -> Vec<OsString> {
+        let mut extensions = self.extensions.clone();
+        extensions.extend(IMAGE_FORMAT_EXTENSIONS.iter().map(OsString::from));
+        extensions
+    }
+
+    /// Returns true if the specified path is a raw sector image, which can be saved.
+    fn is_raw_image(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions.contains(&ext.to_ascii_lowercase()))
+    }
+
     pub fn scan_resource(&mut self, rm: &ResourceManager) -> Result<bool, Error> {
         // Clear and rebuild image lists.
         self.image_vec.clear();
         self.image_map.clear();
 
         // Retrieve all items from the floppy resource paths.
-        let floppy_items = rm.enumerate_items("floppy", true, true, Some(self.extensions.clone()))?;
+        let floppy_items = rm.enumerate_items("floppy", true, true, Some(self.image_extensions()))?;
 
         // Index mapping between 'files' vec and 'image_vec' should be maintained.
         for item in floppy_items.iter() {
@@ -119,9 +150,64 @@ impl FloppyManager {
 
         self.files = floppy_items;
 
+        self.scan_directories(rm);
         Ok(true)
     }
 
+    /// Rebuild the list of directories that can be mounted as floppy disks. The 'floppy_dir' resource is
+    /// optional, so a missing or unreadable directory only results in an empty list.
+    fn scan_directories(&mut self, rm: &ResourceManager) {
+        self.directories.clear();
+        let dir_path = match rm.get_resource_path("floppy_dir") {
+            Some(path) => path,
+            None => return,
+        };
+        match fs::read_dir(&dir_path) {
+            Ok(dir) => {
+                for entry in dir.flatten() {
+                    if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        self.directories.push(entry.path());
+                    }
+                }
+                self.directories.sort();
+            }
+            Err(e) => {
+                log::warn!("Couldn't read floppy directory path {}: {}", dir_path.display(), e);
+            }
+        }
+    }
+
+    /// Return the names of the directories that can be mounted as floppy disks.
+    pub fn get_directory_names(&self) -> Vec<String> {
+        self.directories
+            .iter()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect()
+    }
+
+    pub fn get_directory_path(&self, idx: usize) -> Option<PathBuf> {
+        self.directories.get(idx).cloned()
+    }
+
+    /// Create a new raw sector image file containing `data` in the floppy resource directory and add it to the
+    /// list of images. Returns the index of the new image.
+    pub fn create_floppy_image(&mut self, data: &[u8], rm: &ResourceManager) -> Result<usize, Error> {
+        // Use a raw image extension so that changes to the image can be saved.
+        let extension = self
+            .extensions
+            .first()
+            .map_or("img".to_string(), |ext| ext.to_string_lossy().to_string());
+        let path = rm.get_available_filename("floppy", "floppy", Some(&extension))?;
+        fs::write(&path, data)?;
+        log::debug!("Created floppy image: {:?}", path);
+
+        self.scan_resource(rm)?;
+        path.file_name()
+            .and_then(|name| self.image_map.get(name))
+            .copied()
+            .ok_or_else(|| anyhow!(FloppyError::ImageNotFound))
+    }
+
     pub fn make_tree(&mut self, rm: &ResourceManager) -> Result<PathTreeNode, Error> {
         let tree = rm.items_to_tree("floppy", &self.files)?;
         Ok(tree)
@@ -131,12 +217,13 @@ impl FloppyManager {
         // Clear and rebuild image lists.
         self.image_vec.clear();
         self.image_map.clear();
+        let extensions = self.image_extensions();
 
         // Scan through all entries in the directory and find all files with matching extension
         for path in paths {
             if path.is_file() {
                 if let Some(extension) = path.extension() {
-                    if self.extensions.contains(&extension.to_ascii_lowercase()) {
+                    if extensions.contains(&extension.to_ascii_lowercase()) {
                         println!(
                             "Found floppy image: {:?} size: {}",
                             path,
@@ -169,13 +256,14 @@ impl FloppyManager {
         // Clear and rebuild image lists.
         self.image_vec.clear();
         self.image_map.clear();
+        let extensions = self.image_extensions();
 
         // Scan through all entries in the directory and find all files with matching extension
         for entry in dir {
             if let Ok(entry) = entry {
                 if entry.path().is_file() {
                     if let Some(extension) = entry.path().extension() {
-                        if self.extensions.contains(&extension.to_ascii_lowercase()) {
+                        if extensions.contains(&extension.to_ascii_lowercase()) {
                             println!(
                                 "Found floppy image: {:?} size: {}",
                                 entry.path(),
@@ -208,6 +296,14 @@ impl FloppyManager {
         vec
     }
 
+    pub fn image_ct(&self) -> usize {
+        self.image_vec.len()
+    }
+
+    pub fn get_floppy_path(&self, idx: usize) -> Option<PathBuf> {
+        self.image_vec.get(idx).map(|image| image.path.clone())
+    }
+
     pub fn get_floppy_name(&self, idx: usize) -> Option<OsString> {
         if idx >= self.image_vec.len() {
             return None;
@@ -273,6 +369,9 @@ impl FloppyManager {
         }
 
         let floppy_path = self.image_vec[idx].path.clone();
+        if !self.is_raw_image(&floppy_path) {
+            return Err(FloppyError::UnsupportedFormat);
+        }
         // TODO: Implement write through resource manager instead of direct file access.
         match std::fs::write(&floppy_path, data) {
             Ok(_) => Ok(floppy_path.clone()),
@@ -34,25 +34,34 @@
 use serde_derive::Deserialize;
 
 pub mod cartridge_manager;
+pub mod cassette_manager;
+pub mod cdrom_manager;
+pub mod clip_recorder;
 pub mod color;
 pub mod constants;
+pub mod debugger_command;
 pub mod display_manager;
 #[cfg(feature = "use_wgpu")]
 pub mod display_scaler;
 pub mod floppy_manager;
 pub mod machine_manager;
+pub mod media_manager;
 pub mod resource_manager;
 pub mod rom_manager;
 pub mod timestep_manager;
 pub mod types;
 pub mod vhd_manager;
+pub mod video_recorder;
+pub mod wav_recorder;
 
 pub type FileTreeNode = resource_manager::tree::TreeNode;
 pub type MartyGuiTheme = types::gui::MartyGuiTheme;
 pub type HotkeyEvent = types::hotkeys::HotkeyEvent;
 pub type HotkeyScope = types::hotkeys::HotkeyScope;
 pub type HotkeyConfigEntry = types::hotkeys::HotkeyConfigEntry;
+pub type GamepadConfig = types::gamepad::GamepadConfig;
 pub type JoyKeyEntry = types::joykeys::JoyKeyEntry;
+pub type KeyMacroEntry = types::keymacros::KeyMacroEntry;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
 pub enum BenchmarkEndCondition {
@@ -34,17 +34,24 @@ use anyhow::Error;
 use marty_core::{
     device_traits::videocard::VideoType,
     machine_config::{
+        BusMouseConfig,
         CpuConfig,
         EmsMemoryConfig,
         FloppyControllerConfig,
         GamePortConfig,
         HardDriveControllerConfig,
         KeyboardConfig,
+        LptDacConfig,
         MachineConfiguration,
         MediaConfig,
         MemoryConfig,
+        Mpu401Config,
+        Ne2000Config,
+        PrinterConfig,
         SerialControllerConfig,
+        SerialModemConfig,
         SerialMouseConfig,
+        SoundBlasterConfig,
         VideoCardConfig,
     },
     machine_types::{HardDiskControllerType, MachineType},
@@ -87,8 +94,16 @@ pub struct MachineConfigFileEntry {
     video: Option<Vec<VideoCardConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    bus_mouse: Option<BusMouseConfig>,
+    serial_modem: Option<SerialModemConfig>,
+    printer: Option<PrinterConfig>,
+    lpt_dac: Option<LptDacConfig>,
+    sound_blaster: Option<SoundBlasterConfig>,
+    mpu401: Option<Mpu401Config>,
+    ne2000: Option<Ne2000Config>,
     game_port: Option<GamePortConfig>,
     media: Option<MediaConfig>,
+    option_roms: Option<Vec<String>>, // ROM features of option ROMs to load, ie, "xtide"
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -103,8 +118,16 @@ pub struct MachineConfigFileOverlayEntry {
     video: Option<Vec<VideoCardConfig>>,
     keyboard: Option<KeyboardConfig>,
     serial_mouse: Option<SerialMouseConfig>,
+    bus_mouse: Option<BusMouseConfig>,
+    serial_modem: Option<SerialModemConfig>,
+    printer: Option<PrinterConfig>,
+    lpt_dac: Option<LptDacConfig>,
+    sound_blaster: Option<SoundBlasterConfig>,
+    mpu401: Option<Mpu401Config>,
+    ne2000: Option<Ne2000Config>,
     game_port: Option<GamePortConfig>,
     media: Option<MediaConfig>,
+    option_roms: Option<Vec<String>>, // ROM features of option ROMs to load, ie, "xtide"
 }
 
 /*
@@ -316,6 +339,14 @@ impl MachineConfigFileEntry {
                         req_vec.push(String::from("ibm_xebec"));
                     }
                 }
+                HardDiskControllerType::XtIde | HardDiskControllerType::Ata8Bit => {
+                    if req_set.insert(String::from("expansion")) {
+                        req_vec.push(String::from("expansion"));
+                    }
+                    if req_set.insert(String::from("xtide")) {
+                        req_vec.push(String::from("xtide"));
+                    }
+                }
             }
         }
 
@@ -350,6 +381,19 @@ impl MachineConfigFileEntry {
             log::warn!("Config has no video cards specified. Skipping video ROM requirements.");
         }
 
+        // Option ROMs are found by the BIOS's expansion ROM scan, so they require a BIOS that performs one.
+        if let Some(option_roms) = &self.option_roms {
+            for feature in option_roms {
+                log::debug!("Adding option ROM requirement: {}", feature);
+                if req_set.insert(String::from("expansion")) {
+                    req_vec.push(String::from("expansion"));
+                }
+                if req_set.insert(feature.clone()) {
+                    req_vec.push(feature.clone());
+                }
+            }
+        }
+
         Ok((req_vec, opt_vec))
     }
 
@@ -392,10 +436,42 @@ impl MachineConfigFileEntry {
             log::debug!("Applying serial mouse overlay: {:?}", serial_mouse);
             self.serial_mouse = Some(serial_mouse);
         }
+        if let Some(bus_mouse) = overlay.bus_mouse {
+            log::debug!("Applying bus mouse overlay: {:?}", bus_mouse);
+            self.bus_mouse = Some(bus_mouse);
+        }
+        if let Some(serial_modem) = overlay.serial_modem {
+            log::debug!("Applying serial modem overlay: {:?}", serial_modem);
+            self.serial_modem = Some(serial_modem);
+        }
+        if let Some(printer) = overlay.printer {
+            log::debug!("Applying printer overlay: {:?}", printer);
+            self.printer = Some(printer);
+        }
+        if let Some(lpt_dac) = overlay.lpt_dac {
+            log::debug!("Applying LPT DAC overlay: {:?}", lpt_dac);
+            self.lpt_dac = Some(lpt_dac);
+        }
+        if let Some(sound_blaster) = overlay.sound_blaster {
+            log::debug!("Applying Sound Blaster overlay: {:?}", sound_blaster);
+            self.sound_blaster = Some(sound_blaster);
+        }
+        if let Some(mpu401) = overlay.mpu401 {
+            log::debug!("Applying MPU-401 overlay: {:?}", mpu401);
+            self.mpu401 = Some(mpu401);
+        }
+        if let Some(ne2000) = overlay.ne2000 {
+            log::debug!("Applying NE2000 overlay: {:?}", ne2000);
+            self.ne2000 = Some(ne2000);
+        }
         if let Some(game_port) = overlay.game_port {
             log::debug!("Applying game port overlay: {:?}", game_port);
             self.game_port = Some(game_port);
         }
+        if let Some(option_roms) = overlay.option_roms {
+            log::debug!("Applying option ROM overlay: {:?}", option_roms);
+            self.option_roms = Some(option_roms);
+        }
     }
 
     pub fn to_machine_config(&self) -> MachineConfiguration {
@@ -412,6 +488,13 @@ impl MachineConfigFileEntry {
             video: self.video.clone().unwrap_or_default(),
             keyboard: self.keyboard.clone(),
             serial_mouse: self.serial_mouse.clone(),
+            bus_mouse: self.bus_mouse.clone(),
+            serial_modem: self.serial_modem.clone(),
+            printer: self.printer.clone(),
+            lpt_dac: self.lpt_dac.clone(),
+            sound_blaster: self.sound_blaster.clone(),
+            mpu401: self.mpu401.clone(),
+            ne2000: self.ne2000.clone(),
             game_port: self.game_port.clone(),
             media: self.media.clone(),
         }
@@ -0,0 +1,172 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    frontend_common::media_manager.rs
+
+    Identify the floppy and hard disk images found by the floppy and VHD
+    managers, for display in the media browser.
+
+    Identifying an image means reading all of it, so results are cached by
+    path and only recomputed for images whose size or modification time has
+    changed since the last scan.
+
+*/
+
+use std::{collections::HashMap, fs::File, path::PathBuf, time::SystemTime};
+
+use marty_core::{
+    disk_info::{self, DiskInfo},
+    vhd::VirtualHardDisk,
+};
+
+use crate::{floppy_manager::FloppyManager, resource_manager::ResourceManager, vhd_manager::VhdManager};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Floppy,
+    HardDisk,
+}
+
+#[derive(Clone, Debug)]
+pub struct MediaEntry {
+    pub kind: MediaKind,
+    pub idx:  usize, // Index of the image in the floppy or VHD manager
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub info: Result<DiskInfo, String>,
+}
+
+struct CachedInfo {
+    size: u64,
+    modified: Option<SystemTime>,
+    info: Result<DiskInfo, String>,
+}
+
+pub struct MediaManager {
+    entries: Vec<MediaEntry>,
+    cache:   HashMap<PathBuf, CachedInfo>,
+    stale:   bool,
+}
+
+impl Default for MediaManager {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            cache:   HashMap::new(),
+            stale:   true,
+        }
+    }
+}
+
+impl MediaManager {
+    /// Mark the list of images as out of date, so that it is rebuilt by the next scan.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn entries(&self) -> &[MediaEntry] {
+        &self.entries
+    }
+
+    /// Rebuild the list of images from the floppy and VHD managers, identifying any new or changed images.
+    pub fn scan(&mut self, fm: &FloppyManager, vm: &VhdManager, rm: &ResourceManager) {
+        let mut entries = Vec::new();
+        for idx in 0..fm.image_ct() {
+            if let Some(path) = fm.get_floppy_path(idx) {
+                let entry = self.make_entry(MediaKind::Floppy, idx, path, |_| {
+                    let data = fm.load_floppy_data(idx, rm).map_err(|e| e.to_string())?;
+                    disk_info::identify_floppy(&data).map_err(|e| e.to_string())
+                });
+                entries.push(entry);
+            }
+        }
+        for idx in 0..vm.image_ct() {
+            if let Some(path) = vm.get_vhd_path(idx) {
+                let entry = self.make_entry(MediaKind::HardDisk, idx, path, |path| {
+                    // The image may be mounted, so only open it for reading.
+                    let file = File::open(path).map_err(|e| e.to_string())?;
+                    let mut vhd = VirtualHardDisk::from_file(file).map_err(|e| e.to_string())?;
+                    Ok(disk_info::identify_hard_disk(&mut vhd))
+                });
+                entries.push(entry);
+            }
+        }
+
+        // Forget images that have gone away.
+        self.cache.retain(|path, _| entries.iter().any(|e| e.path == *path));
+        self.entries = entries;
+        self.stale = false;
+    }
+
+    fn make_entry(
+        &mut self,
+        kind: MediaKind,
+        idx: usize,
+        path: PathBuf,
+        identify: impl FnOnce(&PathBuf) -> Result<DiskInfo, String>,
+    ) -> MediaEntry {
+        let metadata = path.metadata().ok();
+        let size = metadata.as_ref().map_or(0, |m| m.len());
+        let modified = metadata.and_then(|m| m.modified().ok());
+
+        let cached = self
+            .cache
+            .get(&path)
+            .filter(|c| c.size == size && c.modified == modified)
+            .map(|c| c.info.clone());
+        let info = match cached {
+            Some(info) => info,
+            None => {
+                log::debug!("Identifying image: {}", path.display());
+                let info = identify(&path);
+                self.cache.insert(
+                    path.clone(),
+                    CachedInfo {
+                        size,
+                        modified,
+                        info: info.clone(),
+                    },
+                );
+                info
+            }
+        };
+
+        MediaEntry {
+            kind,
+            idx,
+            name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            path,
+            size,
+            info,
+        }
+    }
+}
@@ -195,6 +195,7 @@ pub struct TimestepManager {
     cpu_cycle_update_target: u32, // Number of CPU cycles to execute per emulator update
     frame_target: Duration,       // Target frame time in microseconds
     throttle_factor: f64,         // Factor to adjust CPU cycle target by to keep up with emu_render_rate
+    paced_rate: Option<u32>,      // Emulated frame rate, if frame pacing to the host refresh rate
 
     frame_history: HistoryBuffer<FrameEntry>,
     perf_stats: PerfStats,
@@ -220,6 +221,7 @@ impl Default for TimestepManager {
             cpu_cycle_update_target: 1_000_000 / DEFAULT_EMU_FPS_TARGET,
             frame_target: Duration::from_micros(1_000_000 / DEFAULT_EMU_FPS_TARGET as u64),
             throttle_factor: 1.0,
+            paced_rate: None,
 
             frame_history: HistoryBuffer::new(FRAME_HISTORY_LEN),
             total_running_time: Duration::from_secs(0),
@@ -341,7 +343,9 @@ impl TimestepManager {
     }
 
     pub fn set_cpu_mhz(&mut self, mhz: f64) {
-        self.cpu_cycle_update_target = (mhz * 1_000_000.0 / self.emu_update_rate.get() as f64) as u32;
+        // When frame pacing, each update runs one emulated frame regardless of the host update rate.
+        let frame_rate = self.paced_rate.unwrap_or(self.emu_update_rate.get());
+        self.cpu_cycle_update_target = (mhz * 1_000_000.0 / frame_rate as f64) as u32;
         log::info!(
             "CPU clock has changed to {:.4}Mhz, new cycle target: {}",
             mhz,
@@ -354,6 +358,21 @@ impl TimestepManager {
         self.emu_update_rate.set(fps);
     }
 
+    /// Lock emulator updates and rendering to the host refresh rate, running one emulated frame of
+    /// `emu_rate` Hz per host frame. The emulated machine will run slightly faster or slower than
+    /// real time by the ratio of the two rates.
+    pub fn set_frame_pacing(&mut self, host_rate: u32, emu_rate: u32) {
+        self.paced_rate = Some(emu_rate);
+        self.set_emu_update_rate(host_rate);
+        self.set_emu_render_rate(host_rate);
+        self.set_cpu_mhz(self.cpu_mhz);
+        log::info!(
+            "Frame pacing enabled: {}Hz emulated frames presented at {}Hz",
+            emu_rate,
+            host_rate
+        );
+    }
+
     pub fn set_gui_render_rate(&mut self, fps: u32) {
         self.gui_render_rate.set(fps);
     }
@@ -0,0 +1,73 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::gamepad.rs
+
+   Define frontend types for mapping host gamepads to the game port.
+
+*/
+
+use serde_derive::Deserialize;
+
+/// A game port stick axis.
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Eq, Deserialize)]
+pub enum GamePortAxis {
+    X,
+    Y,
+}
+
+/// Map a host gamepad axis to a game port stick axis, with calibration.
+/// `host_axis` is the name of the host axis as reported by the controller input library,
+/// e.g. "LeftStickX". The calibrated position is `((value - center) * scale)`, clamped to
+/// -1.0..=1.0 and optionally inverted.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadAxisEntry {
+    pub host_axis: String,
+    pub axis: GamePortAxis,
+    #[serde(default)]
+    pub invert: bool,
+    pub center: Option<f64>,
+    pub scale: Option<f64>,
+}
+
+/// Map a host gamepad button to a game port button (0 or 1).
+#[derive(Clone, Debug, Deserialize)]
+pub struct GamepadButtonEntry {
+    pub host_button: String,
+    pub button: usize,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GamepadConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub deadzone: f64,
+    #[serde(default)]
+    pub axes: Vec<GamepadAxisEntry>,
+    #[serde(default)]
+    pub buttons: Vec<GamepadButtonEntry>,
+}
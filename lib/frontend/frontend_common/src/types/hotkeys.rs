@@ -40,11 +40,19 @@ pub enum HotkeyEvent {
     CtrlAltDel,
     Reboot,
     Screenshot,
+    ScreenshotRaw,
+    ScreenshotDisplay,
     ToggleGui,
     ToggleFullscreen,
+    ToggleTurbo,
     DebugStep,
     DebugStepOver,
     JoyToggle,
+    RecordMacro,
+    PlayRecordedMacro,
+    SaveState,
+    LoadState,
+    Rewind,
     JoyButton1,
     JoyButton2,
     JoyUp,
@@ -0,0 +1,49 @@
+/*
+   MartyPC
+   https://github.com/dbalsom/martypc
+
+   Copyright 2022-2024 Daniel Balsom
+
+   Permission is hereby granted, free of charge, to any person obtaining a
+   copy of this software and associated documentation files (the “Software”),
+   to deal in the Software without restriction, including without limitation
+   the rights to use, copy, modify, merge, publish, distribute, sublicense,
+   and/or sell copies of the Software, and to permit persons to whom the
+   Software is furnished to do so, subject to the following conditions:
+
+   The above copyright notice and this permission notice shall be included in
+   all copies or substantial portions of the Software.
+
+   THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+   FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+   DEALINGS IN THE SOFTWARE.
+
+
+   ---------------------------------------------------------------------------
+
+   frontend_common::types::keymacros.rs
+
+   Define frontend types for keyboard macros.
+
+*/
+
+use crate::HotkeyScope;
+use marty_core::keys::MartyKey;
+use serde_derive::Deserialize;
+
+/// A named keyboard macro bound to a host hotkey.
+/// A macro may specify a list of key events in the same '+Key' (press) and '-Key' (release)
+/// notation used by keyboard layout macros, and/or text to type. Key events are sent before text.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyMacroEntry {
+    pub name:   String,
+    pub hotkey: Vec<MartyKey>,
+    pub scope:  Option<HotkeyScope>,
+    #[serde(default)]
+    pub keys:   Vec<String>,
+    pub text:   Option<String>,
+}
@@ -32,6 +32,8 @@
 
 pub mod display_target_dimensions;
 pub mod display_target_margins;
+pub mod gamepad;
 pub mod gui;
 pub mod hotkeys;
 pub mod joykeys;
+pub mod keymacros;
@@ -107,7 +107,7 @@ impl VhdManager {
             image_map: HashMap::new(),
             drives_loaded: BTreeMap::new(),
             images_loaded: BTreeSet::new(),
-            extensions: vec![OsString::from("vhd")],
+            extensions: vec![OsString::from("vhd"), OsString::from("img")],
         }
     }
 
@@ -162,6 +162,10 @@ impl VhdManager {
         vec
     }
 
+    pub fn image_ct(&self) -> usize {
+        self.image_vec.len()
+    }
+
     pub fn get_vhd_name(&self, idx: usize) -> Option<OsString> {
         if idx >= self.image_vec.len() {
             return None;
@@ -176,6 +180,28 @@ impl VhdManager {
         Some(self.image_vec[idx].path.clone())
     }
 
+    /// Return the path of the copy-on-write overlay file for the specified image. The overlay is kept next to
+    /// the image, with '.cow' appended to its name.
+    pub fn get_overlay_path(&self, idx: usize) -> Option<PathBuf> {
+        self.get_vhd_path(idx).map(|path| {
+            let mut name = path.into_os_string();
+            name.push(".cow");
+            PathBuf::from(name)
+        })
+    }
+
+    /// Open the copy-on-write overlay file for the specified image, creating it if it doesn't exist.
+    pub fn open_overlay_file(&self, idx: usize) -> Result<File, VhdManagerError> {
+        let path = self.get_overlay_path(idx).ok_or(VhdManagerError::IndexNotFound)?;
+        File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|_| VhdManagerError::FileReadError)
+    }
+
     pub fn is_vhd_available(&self, name: &PathBuf) -> bool {
         if let Some(entry) = self.image_map.get(name).and_then(|idx| self.image_vec.get(*idx)) {
             log::debug!("is_vhd_loaded(): confirming entry {}", entry.name.to_string_lossy());
@@ -0,0 +1,444 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::video_recorder::mod.rs
+
+    Records display output and audio to an uncompressed AVI file.
+
+    The file is a plain AVI 1.0 (RIFF) container holding a 24-bit RGB video
+    stream and, if a sample rate is provided, a 16-bit mono PCM audio stream.
+    Since no codec is required the output is large, but it can be read by
+    most players and converted to MP4 or any other format with ffmpeg.
+
+*/
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+
+// AVI 1.0 files are limited to 2GB. Stop a little short of that to leave room for the index.
+const MAX_FILE_SIZE: u64 = 0x7F00_0000;
+
+const AVIF_HASINDEX: u32 = 0x0000_0010;
+const AVIIF_KEYFRAME: u32 = 0x0000_0010;
+
+const VIDEO_CHUNK_ID: &[u8; 4] = b"00db";
+const AUDIO_CHUNK_ID: &[u8; 4] = b"01wb";
+
+// Size of the header written by make_header(), up to and including the 'movi' fourcc.
+const HEADER_SIZE_VIDEO: u64 = 12 + 12 + 64 + 12 + 64 + 48 + 12;
+const HEADER_SIZE_AUDIO: u64 = 12 + 64 + 24;
+
+struct IndexEntry {
+    id: [u8; 4],
+    offset: u32,
+    size: u32,
+}
+
+pub struct VideoRecorder {
+    path: PathBuf,
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    fps: u32,
+    sample_rate: Option<u32>,
+    index: Vec<IndexEntry>,
+    pos: u64,
+    movi_end: u64,
+    frames: u64,
+    audio_samples: u64,
+    max_chunk: u32,
+    frame_buf: Vec<u8>,
+}
+
+impl VideoRecorder {
+    /// Create a new AVI file at `path`. The video stream will be `width` x `height` pixels at `fps`
+    /// frames per second. If `sample_rate` is None, no audio stream is created.
+    pub fn new(path: &Path, width: u32, height: u32, fps: u32, sample_rate: Option<u32>) -> Result<Self, Error> {
+        if width == 0 || height == 0 || fps == 0 {
+            return Err(anyhow!("Invalid video dimensions: {}x{}@{}", width, height, fps));
+        }
+
+        let file = File::create(path)?;
+        let mut recorder = Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            width,
+            height,
+            fps,
+            sample_rate,
+            index: Vec::new(),
+            pos: 0,
+            movi_end: 0,
+            frames: 0,
+            audio_samples: 0,
+            max_chunk: 0,
+            frame_buf: Vec::new(),
+        };
+
+        // Write a placeholder header. It is rewritten with the final stream lengths by finish().
+        let header = recorder.make_header();
+        recorder.file.write_all(&header)?;
+        recorder.pos = header.len() as u64;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Return the length of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames as f64 / self.fps as f64
+    }
+
+    /// Add audio samples to the recording. Samples are expected to be in the range -1.0 to 1.0.
+    /// Audio is ignored if the recorder was created without a sample rate.
+    pub fn push_audio(&mut self, samples: &[f32]) -> Result<(), Error> {
+        if self.sample_rate.is_none() || samples.is_empty() {
+            return Ok(());
+        }
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let sample_i16 = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            data.extend_from_slice(&sample_i16.to_le_bytes());
+        }
+        self.write_chunk(*AUDIO_CHUNK_ID, &data)?;
+        self.audio_samples += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Add a frame to the recording from an RGBA buffer of `w` x `h` pixels. If the frame size
+    /// differs from the size of the video stream, the frame is cropped or padded with black.
+    ///
+    /// When recording audio, the frame is duplicated or dropped as needed to keep the video
+    /// in sync with the audio pushed so far; otherwise each call produces one frame.
+    pub fn push_frame(&mut self, buf: &[u8], w: u32, h: u32) -> Result<(), Error> {
+        let target_frames = match self.sample_rate {
+            Some(rate) => std::cmp::max(1, self.audio_samples * self.fps as u64 / rate as u64),
+            None => self.frames + 1,
+        };
+        if self.frames >= target_frames {
+            return Ok(());
+        }
+
+        self.convert_frame(buf, w, h);
+        let frame = std::mem::take(&mut self.frame_buf);
+        let mut result = Ok(());
+        while self.frames < target_frames {
+            result = self.write_chunk(*VIDEO_CHUNK_ID, &frame);
+            if result.is_err() {
+                break;
+            }
+            self.frames += 1;
+        }
+        self.frame_buf = frame;
+        result
+    }
+
+    /// Write the index and the final header, and close the file.
+    pub fn finish(mut self) -> Result<PathBuf, Error> {
+        self.movi_end = self.pos;
+        let mut idx = Vec::with_capacity(self.index.len() * 16);
+        for entry in &self.index {
+            idx.extend_from_slice(&entry.id);
+            idx.extend_from_slice(&AVIIF_KEYFRAME.to_le_bytes());
+            idx.extend_from_slice(&entry.offset.to_le_bytes());
+            idx.extend_from_slice(&entry.size.to_le_bytes());
+        }
+        self.file.write_all(b"idx1")?;
+        self.file.write_all(&(idx.len() as u32).to_le_bytes())?;
+        self.file.write_all(&idx)?;
+        self.pos += 8 + idx.len() as u64;
+
+        let header = self.make_header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()?;
+        Ok(self.path)
+    }
+
+    fn row_size(&self) -> usize {
+        (self.width as usize * 3 + 3) & !3
+    }
+
+    /// Convert an RGBA buffer into a bottom-up, 24-bit BGR DIB in frame_buf.
+    fn convert_frame(&mut self, buf: &[u8], w: u32, h: u32) {
+        let row_size = self.row_size();
+        self.frame_buf.clear();
+        self.frame_buf.resize(row_size * self.height as usize, 0);
+
+        let copy_w = std::cmp::min(w, self.width) as usize;
+        let copy_h = std::cmp::min(h, self.height) as usize;
+        for y in 0..copy_h {
+            let src_start = y * w as usize * 4;
+            let dst_start = (self.height as usize - 1 - y) * row_size;
+            if src_start + copy_w * 4 > buf.len() {
+                break;
+            }
+            let src = &buf[src_start..src_start + copy_w * 4];
+            let dst = &mut self.frame_buf[dst_start..dst_start + copy_w * 3];
+            for (d, s) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                d[0] = s[2];
+                d[1] = s[1];
+                d[2] = s[0];
+            }
+        }
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], data: &[u8]) -> Result<(), Error> {
+        let padded_len = data.len() as u64 + (data.len() as u64 & 1);
+        if self.pos + 8 + padded_len + (self.index.len() as u64 + 1) * 16 > MAX_FILE_SIZE {
+            return Err(anyhow!("Maximum AVI file size reached"));
+        }
+
+        let movi_start = self.header_size() - 4;
+        self.index.push(IndexEntry {
+            id,
+            offset: (self.pos - movi_start) as u32,
+            size: data.len() as u32,
+        });
+
+        self.file.write_all(&id)?;
+        self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.file.write_all(data)?;
+        if data.len() & 1 != 0 {
+            self.file.write_all(&[0])?;
+        }
+        self.pos += 8 + padded_len;
+        self.max_chunk = std::cmp::max(self.max_chunk, data.len() as u32);
+        Ok(())
+    }
+
+    fn header_size(&self) -> u64 {
+        match self.sample_rate {
+            Some(_) => HEADER_SIZE_VIDEO + HEADER_SIZE_AUDIO,
+            None => HEADER_SIZE_VIDEO,
+        }
+    }
+
+    fn make_header(&self) -> Vec<u8> {
+        let frame_size = self.row_size() as u32 * self.height;
+        let header_size = self.header_size();
+        let streams = if self.sample_rate.is_some() { 2 } else { 1 };
+        let mut h = Vec::with_capacity(header_size as usize);
+
+        // For a file that has not been finished yet, the sizes here are just placeholders.
+        let riff_size = std::cmp::max(self.pos, header_size) - 8;
+        let movi_size = std::cmp::max(self.movi_end, header_size) - (header_size - 4);
+
+        put_fourcc(&mut h, b"RIFF");
+        put_u32(&mut h, riff_size as u32);
+        put_fourcc(&mut h, b"AVI ");
+
+        let hdrl_size = header_size - 12 - 8 - 12;
+        put_fourcc(&mut h, b"LIST");
+        put_u32(&mut h, hdrl_size as u32);
+        put_fourcc(&mut h, b"hdrl");
+
+        // Main AVI header
+        put_fourcc(&mut h, b"avih");
+        put_u32(&mut h, 56);
+        put_u32(&mut h, 1_000_000 / self.fps); // microseconds per frame
+        put_u32(&mut h, frame_size * self.fps + self.sample_rate.unwrap_or(0) * 2); // max bytes per second
+        put_u32(&mut h, 0); // padding granularity
+        put_u32(&mut h, AVIF_HASINDEX);
+        put_u32(&mut h, self.frames as u32);
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, streams);
+        put_u32(&mut h, self.max_chunk);
+        put_u32(&mut h, self.width);
+        put_u32(&mut h, self.height);
+        for _ in 0..4 {
+            put_u32(&mut h, 0);
+        }
+
+        // Video stream
+        put_fourcc(&mut h, b"LIST");
+        put_u32(&mut h, 4 + 64 + 48);
+        put_fourcc(&mut h, b"strl");
+        put_fourcc(&mut h, b"strh");
+        put_u32(&mut h, 56);
+        put_fourcc(&mut h, b"vids");
+        put_fourcc(&mut h, b"DIB ");
+        put_u32(&mut h, 0); // flags
+        put_u32(&mut h, 0); // priority & language
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, 1); // scale
+        put_u32(&mut h, self.fps); // rate
+        put_u32(&mut h, 0); // start
+        put_u32(&mut h, self.frames as u32); // length
+        put_u32(&mut h, frame_size); // suggested buffer size
+        put_u32(&mut h, u32::MAX); // quality
+        put_u32(&mut h, 0); // sample size
+        put_u16(&mut h, 0); // rcFrame
+        put_u16(&mut h, 0);
+        put_u16(&mut h, self.width as u16);
+        put_u16(&mut h, self.height as u16);
+
+        put_fourcc(&mut h, b"strf");
+        put_u32(&mut h, 40);
+        put_u32(&mut h, 40); // BITMAPINFOHEADER size
+        put_u32(&mut h, self.width);
+        put_u32(&mut h, self.height); // positive height: bottom-up DIB
+        put_u16(&mut h, 1); // planes
+        put_u16(&mut h, 24); // bits per pixel
+        put_u32(&mut h, 0); // BI_RGB
+        put_u32(&mut h, frame_size);
+        for _ in 0..4 {
+            put_u32(&mut h, 0);
+        }
+
+        // Audio stream
+        if let Some(rate) = self.sample_rate {
+            put_fourcc(&mut h, b"LIST");
+            put_u32(&mut h, 4 + 64 + 24);
+            put_fourcc(&mut h, b"strl");
+            put_fourcc(&mut h, b"strh");
+            put_u32(&mut h, 56);
+            put_fourcc(&mut h, b"auds");
+            put_u32(&mut h, 0); // handler
+            put_u32(&mut h, 0); // flags
+            put_u32(&mut h, 0); // priority & language
+            put_u32(&mut h, 0); // initial frames
+            put_u32(&mut h, 1); // scale
+            put_u32(&mut h, rate); // rate
+            put_u32(&mut h, 0); // start
+            put_u32(&mut h, self.audio_samples as u32); // length
+            put_u32(&mut h, rate * 2); // suggested buffer size
+            put_u32(&mut h, u32::MAX); // quality
+            put_u32(&mut h, 2); // sample size
+            for _ in 0..4 {
+                put_u16(&mut h, 0);
+            }
+
+            put_fourcc(&mut h, b"strf");
+            put_u32(&mut h, 16);
+            put_u16(&mut h, 1); // WAVE_FORMAT_PCM
+            put_u16(&mut h, 1); // channels
+            put_u32(&mut h, rate);
+            put_u32(&mut h, rate * 2); // average bytes per second
+            put_u16(&mut h, 2); // block align
+            put_u16(&mut h, 16); // bits per sample
+        }
+
+        put_fourcc(&mut h, b"LIST");
+        put_u32(&mut h, movi_size as u32);
+        put_fourcc(&mut h, b"movi");
+
+        debug_assert_eq!(h.len() as u64, header_size);
+        h
+    }
+}
+
+fn put_fourcc(buf: &mut Vec<u8>, fourcc: &[u8; 4]) {
+    buf.extend_from_slice(fourcc);
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_avi_layout() {
+        let path = std::env::temp_dir().join(format!("marty_video_recorder_{}.avi", std::process::id()));
+
+        // An odd width exercises the DIB row padding. At 10 fps and 100 Hz, each frame is 10 samples.
+        let mut recorder = VideoRecorder::new(&path, 3, 2, 10, Some(100)).unwrap();
+        let rgba = vec![0xFF; 3 * 2 * 4];
+        recorder.push_audio(&[0.0; 10]).unwrap();
+        recorder.push_frame(&rgba, 3, 2).unwrap();
+        recorder.push_audio(&[0.5; 20]).unwrap();
+        recorder.push_frame(&rgba, 3, 2).unwrap();
+        assert_eq!(recorder.frames(), 3);
+        recorder.finish().unwrap();
+
+        let avi = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&avi[0..4], b"RIFF");
+        assert_eq!(get_u32(&avi, 4) as usize, avi.len() - 8);
+        assert_eq!(&avi[8..12], b"AVI ");
+
+        // avih total frames, and the video and audio strh lengths.
+        assert_eq!(&avi[24..28], b"avih");
+        assert_eq!(get_u32(&avi, 24 + 8 + 16), 3);
+        let video_strl = 24 + 64;
+        assert_eq!(&avi[video_strl + 20..video_strl + 24], b"vids");
+        assert_eq!(get_u32(&avi, video_strl + 12 + 8 + 32), 3);
+        let audio_strl = HEADER_SIZE_VIDEO as usize - 12;
+        assert_eq!(&avi[audio_strl + 20..audio_strl + 24], b"auds");
+        assert_eq!(get_u32(&avi, audio_strl + 12 + 8 + 32), 30);
+
+        let movi_start = (HEADER_SIZE_VIDEO + HEADER_SIZE_AUDIO) as usize - 4;
+        assert_eq!(&avi[movi_start - 8..movi_start - 4], b"LIST");
+        assert_eq!(&avi[movi_start..movi_start + 4], b"movi");
+        let movi_end = movi_start + get_u32(&avi, movi_start - 4) as usize;
+
+        assert_eq!(&avi[movi_end..movi_end + 4], b"idx1");
+        let idx_len = get_u32(&avi, movi_end + 4) as usize;
+        assert_eq!(movi_end + 8 + idx_len, avi.len());
+
+        // Every index entry must point at a chunk with a matching id and size.
+        let expected: [(&[u8; 4], u32); 5] = [
+            (AUDIO_CHUNK_ID, 20),
+            (VIDEO_CHUNK_ID, 24),
+            (AUDIO_CHUNK_ID, 40),
+            (VIDEO_CHUNK_ID, 24),
+            (VIDEO_CHUNK_ID, 24),
+        ];
+        let idx = &avi[movi_end + 8..];
+        assert_eq!(idx.len(), expected.len() * 16);
+        for (entry, (id, size)) in idx.chunks_exact(16).zip(expected) {
+            assert_eq!(&entry[0..4], id);
+            assert_eq!(get_u32(entry, 4), AVIIF_KEYFRAME);
+            let chunk = movi_start + get_u32(entry, 8) as usize;
+            assert_eq!(&avi[chunk..chunk + 4], id);
+            assert_eq!(get_u32(&avi, chunk + 4), size);
+            assert_eq!(get_u32(entry, 12), size);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    frontend_common::wav_recorder::mod.rs
+
+    Records audio samples to a 16-bit mono PCM WAV file.
+
+*/
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+
+const WAV_HEADER_SIZE: u32 = 44;
+// The RIFF size fields are 32 bits. Stop a little short of 4GB.
+const MAX_DATA_SIZE: u64 = 0xFFFF_0000;
+
+pub struct WavRecorder {
+    path: PathBuf,
+    file: BufWriter<File>,
+    sample_rate: u32,
+    samples: u64,
+}
+
+impl WavRecorder {
+    /// Create a new WAV file at `path` with the specified sample rate.
+    pub fn new(path: &Path, sample_rate: u32) -> Result<Self, Error> {
+        if sample_rate == 0 {
+            return Err(anyhow!("Invalid sample rate: {}", sample_rate));
+        }
+
+        let file = File::create(path)?;
+        let mut recorder = Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            sample_rate,
+            samples: 0,
+        };
+
+        // Write a placeholder header. It is rewritten with the final data length by finish().
+        let header = recorder.make_header();
+        recorder.file.write_all(&header)?;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the length of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.samples as f64 / self.sample_rate as f64
+    }
+
+    /// Add samples to the recording. Samples are expected to be in the range -1.0 to 1.0.
+    pub fn push_samples(&mut self, samples: &[f32]) -> Result<(), Error> {
+        if (self.samples + samples.len() as u64) * 2 > MAX_DATA_SIZE {
+            return Err(anyhow!("Maximum WAV file size reached"));
+        }
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let sample_i16 = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            data.extend_from_slice(&sample_i16.to_le_bytes());
+        }
+        self.file.write_all(&data)?;
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Write the final header and close the file.
+    pub fn finish(mut self) -> Result<PathBuf, Error> {
+        let header = self.make_header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()?;
+        Ok(self.path)
+    }
+
+    fn make_header(&self) -> Vec<u8> {
+        let data_size = (self.samples * 2) as u32;
+        let mut h = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+
+        h.extend_from_slice(b"RIFF");
+        h.extend_from_slice(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes());
+        h.extend_from_slice(b"WAVE");
+
+        h.extend_from_slice(b"fmt ");
+        h.extend_from_slice(&16u32.to_le_bytes());
+        h.extend_from_slice(&1u16.to_le_bytes()); // WAVE_FORMAT_PCM
+        h.extend_from_slice(&1u16.to_le_bytes()); // Channels
+        h.extend_from_slice(&self.sample_rate.to_le_bytes());
+        h.extend_from_slice(&(self.sample_rate * 2).to_le_bytes()); // Bytes per second
+        h.extend_from_slice(&2u16.to_le_bytes()); // Block align
+        h.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+
+        h.extend_from_slice(b"data");
+        h.extend_from_slice(&data_size.to_le_bytes());
+
+        debug_assert_eq!(h.len() as u32, WAV_HEADER_SIZE);
+        h
+    }
+}
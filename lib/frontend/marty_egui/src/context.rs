@@ -57,6 +57,12 @@ pub struct GuiRenderContext {
 }
 
 impl GuiRenderContext {
+    /// Return the text contents of the host clipboard, if any.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn clipboard_text(&mut self) -> Option<String> {
+        self.egui_state.clipboard_text()
+    }
+
     /// Create egui.
     pub fn new(
         dt_idx: usize,
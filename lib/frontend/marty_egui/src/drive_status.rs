@@ -0,0 +1,126 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    egui::drive_status.rs
+
+    Draw the drive status overlay: activity lights and mounted image names
+    for each floppy and hard disk drive.
+
+*/
+
+use std::path::Path;
+
+use marty_core::device_types::drive_status::DriveStatus;
+
+use crate::state::GuiState;
+
+// Time an activity light stays lit after a read or write, in seconds, so that single sector accesses are visible.
+const ACTIVITY_HOLD_SECS: f64 = 0.15;
+
+const LIGHT_OFF: egui::Color32 = egui::Color32::from_rgb(48, 48, 48);
+const LIGHT_MOTOR: egui::Color32 = egui::Color32::from_rgb(40, 200, 40);
+const LIGHT_READ: egui::Color32 = egui::Color32::from_rgb(240, 180, 0);
+const LIGHT_WRITE: egui::Color32 = egui::Color32::from_rgb(230, 40, 40);
+
+/// The state of a drive's indicator lights. Activity reported by the machine is held until the overlay is next drawn.
+#[derive(Default)]
+pub struct DriveIndicator {
+    status: DriveStatus,
+    last_read: Option<f64>,
+    last_write: Option<f64>,
+}
+
+impl DriveIndicator {
+    pub fn update(&mut self, status: DriveStatus) {
+        self.status.motor = status.motor;
+        self.status.read |= status.read;
+        self.status.write |= status.write;
+    }
+
+    /// Return the color of the activity light at the specified time.
+    fn activity_color(&mut self, now: f64) -> egui::Color32 {
+        if std::mem::take(&mut self.status.read) {
+            self.last_read = Some(now);
+        }
+        if std::mem::take(&mut self.status.write) {
+            self.last_write = Some(now);
+        }
+        let lit = |last: Option<f64>| last.is_some_and(|t| now - t < ACTIVITY_HOLD_SECS);
+        if lit(self.last_write) {
+            LIGHT_WRITE
+        }
+        else if lit(self.last_read) {
+            LIGHT_READ
+        }
+        else {
+            LIGHT_OFF
+        }
+    }
+}
+
+fn draw_light(ui: &mut egui::Ui, color: egui::Color32) {
+    let size = ui.spacing().interact_size.y * 0.5;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+    ui.painter().circle_filled(rect.center(), size * 0.5, color);
+}
+
+/// Draw the lights and image name of a drive. The first light shows the motor, the second read and write activity.
+fn draw_drive(ui: &mut egui::Ui, letter: char, indicator: &mut DriveIndicator, path: Option<&Path>, now: f64) {
+    ui.horizontal(|ui| {
+        draw_light(ui, if indicator.status.motor { LIGHT_MOTOR } else { LIGHT_OFF });
+        draw_light(ui, indicator.activity_color(now));
+        let name = match path.and_then(|p| p.file_name()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => "Empty".to_string(),
+        };
+        ui.label(format!("{}: {}", letter, name));
+    });
+}
+
+impl GuiState {
+    pub fn draw_drive_status(&mut self, ctx: &egui::Context) {
+        if self.floppy_drives.is_empty() && self.hdds.is_empty() {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+
+        egui::Area::new(egui::Id::new("drive_status"))
+            .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(8.0, -8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for drive in self.floppy_drives.iter_mut() {
+                        let letter = (b'A' + drive.idx as u8) as char;
+                        draw_drive(ui, letter, &mut drive.indicator, drive.selected_path.as_deref(), now);
+                    }
+                    for drive in self.hdds.iter_mut() {
+                        let letter = (b'C' + drive.idx as u8) as char;
+                        draw_drive(ui, letter, &mut drive.indicator, drive.selected_path.as_deref(), now);
+                    }
+                });
+            });
+    }
+}
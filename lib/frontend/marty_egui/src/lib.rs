@@ -44,12 +44,13 @@ use egui::{Color32, Context, Visuals};
 use lazy_static::lazy_static;
 
 use frontend_common::{
-    display_manager::DisplayInfo,
+    display_manager::{DisplayInfo, ScreenshotType},
     display_scaler::{ScalerMode, ScalerParams},
 };
 
 mod color;
 mod constants;
+mod drive_status;
 mod image;
 
 pub mod context;
@@ -66,8 +67,9 @@ mod workspace;
 use marty_core::{
     device_traits::videocard::DisplayApertureType,
     device_types::hdc::HardDiskFormat,
-    devices::pic::PicStringState,
+    devices::{cassette::CassetteCommand, pic::PicStringState},
     machine::MachineState,
+    machine_types::BootDevice,
 };
 
 use serde::{Deserialize, Serialize};
@@ -100,6 +102,8 @@ pub enum GuiWindow {
     VHDCreator,
     CycleTraceViewer,
     TextModeViewer,
+    SoundMixer,
+    MediaBrowser,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -123,6 +127,8 @@ pub enum GuiBoolean {
     TurboButton,
     ShowBackBuffer,
     ShowRasterPosition,
+    LightPen,
+    ShowDriveStatus,
 }
 
 // Enums are hashed with a tuple of GuiEnumContext and their base discriminant.
@@ -155,7 +161,7 @@ fn create_default_variant(ge: GuiEnum) -> GuiEnum {
     match ge {
         GuiEnum::DisplayAspectCorrect(_) => GuiEnum::DisplayAspectCorrect(Default::default()),
         GuiEnum::DisplayAperture(_) => GuiEnum::DisplayAperture(Default::default()),
-        GuiEnum::DisplayScalerMode(_) => GuiEnum::DisplayAperture(Default::default()),
+        GuiEnum::DisplayScalerMode(_) => GuiEnum::DisplayScalerMode(Default::default()),
         GuiEnum::DisplayScalerPreset(_) => GuiEnum::DisplayScalerPreset(String::new()),
         GuiEnum::DisplayComposite(_) => GuiEnum::DisplayComposite(Default::default()),
         GuiEnum::SerialPortBridge(_) => GuiEnum::SerialPortBridge(Default::default()),
@@ -168,11 +174,18 @@ type GuiEnumMap = HashMap<(GuiVariableContext, Discriminant<GuiEnum>), GuiEnum>;
 pub enum GuiEvent {
     LoadVHD(usize, usize),
     DetachVHD(usize),
+    SetHddOverlay(usize, bool),
+    CommitHddOverlay(usize),
+    DiscardHddOverlay(usize),
     CreateVHD(OsString, HardDiskFormat),
     LoadFloppy(usize, usize),
+    CreateFloppy(usize, usize),
+    MountFloppyDirectory(usize, usize),
     SaveFloppy(usize, usize),
+    SyncFloppyDirectory(usize),
     EjectFloppy(usize),
     SetFloppyWriteProtect(usize, bool),
+    SetFloppyWriteBack(usize, bool),
     BridgeSerialPort(usize, String, usize),
     DumpVRAM,
     DumpCS,
@@ -187,19 +200,47 @@ pub enum GuiEvent {
     DelayAdjust,
     TickDevice(DeviceSelection, u32),
     MachineStateChange(MachineState),
-    TakeScreenshot(usize),
+    TakeScreenshot(usize, ScreenshotType),
+    StartRecordingVideo(usize),
+    StopRecordingVideo,
+    StartRecordingAudio(bool),
+    StopRecordingAudio,
+    CaptureClip(usize),
     ToggleFullscreen(usize),
     Exit,
     SetNMI(bool),
     TriggerParity,
     RescanMediaFolders,
     CtrlAltDel,
+    PasteClipboard,
     ZoomChanged(f32),
     ResetIOStats,
     StartRecordingDisassembly,
     StopRecordingDisassembly,
+    StartOpcodeProfiling,
+    StopOpcodeProfiling,
+    DebuggerCommand(String),
     InsertCartridge(usize, usize),
     RemoveCartridge(usize),
+    LoadCassette(usize),
+    CreateCassette,
+    SaveCassette,
+    EjectCassette,
+    CassetteControl(CassetteCommand),
+    LoadCdrom(usize),
+    EjectCdrom,
+    SetBootDevice(BootDevice),
+    SaveState(usize),
+    LoadState(usize),
+    StartReplayRecording,
+    StopReplayRecording,
+    PlayReplay(String),
+    StopReplayPlayback,
+    RunScript(String),
+    StopScripts,
+    SetMasterVolume(f32, bool),
+    SetSoundSourceVolume(usize, f32, bool),
+    ResetAudioStats,
 }
 
 pub enum DeviceSelection {
@@ -499,6 +540,26 @@ lazy_static! {
                 resizable: false,
             },
         ),
+        (
+            GuiWindow::SoundMixer,
+            WorkspaceWindowDef {
+                id: GuiWindow::SoundMixer,
+                title: "Sound Mixer",
+                menu: "🔊 Sound Mixer",
+                width: 400.0,
+                resizable: false,
+            },
+        ),
+        (
+            GuiWindow::MediaBrowser,
+            WorkspaceWindowDef {
+                id: GuiWindow::MediaBrowser,
+                title: "Media Browser",
+                menu: "📂 Media Browser",
+                width: 700.0,
+                resizable: true,
+            },
+        ),
     ]
     .into();
 }
@@ -32,9 +32,20 @@
 
 use crate::{state::GuiState, GuiBoolean, GuiEnum, GuiEvent, GuiVariable, GuiVariableContext, GuiWindow};
 
-use marty_core::{device_traits::videocard::VideoType, devices::serial::SerialPortDescriptor};
+use marty_core::{
+    device_traits::videocard::VideoType,
+    device_types::fdc::DISK_FORMATS,
+    devices::{
+        cassette::{CassetteCommand, CassetteState},
+        serial::SerialPortDescriptor,
+    },
+    replay::ReplayStatus,
+};
 
-use marty_core::machine::MachineState;
+use std::path::PathBuf;
+
+use frontend_common::display_manager::ScreenshotType;
+use marty_core::{machine::MachineState, machine_types::BootDevice};
 
 impl GuiState {
     pub fn draw_menu(&mut self, ui: &mut egui::Ui) {
@@ -56,6 +67,8 @@ impl GuiState {
                 }
             });
             ui.menu_button("Machine", |ui| {
+                self.workspace_window_open_button(ui, GuiWindow::SoundMixer, true);
+
                 ui.menu_button("Input/Output", |ui| {
                     // Create a vector of ports that are currently bridged. We will use this to disable
                     // those ports from selection in the menu.
@@ -98,6 +111,20 @@ impl GuiState {
                             }
                         });
                     }
+
+                    if ui
+                        .checkbox(&mut self.get_option_mut(GuiBoolean::LightPen), "Light Pen (Mouse)")
+                        .on_hover_text("Use the host mouse as a light pen. The left button presses the pen switch.")
+                        .clicked()
+                    {
+                        let new_opt = self.get_option(GuiBoolean::LightPen).unwrap();
+
+                        self.event_queue.send(GuiEvent::VariableChanged(
+                            GuiVariableContext::Global,
+                            GuiVariable::Bool(GuiBoolean::LightPen, new_opt),
+                        ));
+                        ui.close_menu();
+                    }
                 });
 
                 ui.separator();
@@ -153,6 +180,18 @@ impl GuiState {
                     }
                 });
 
+                ui.add_enabled_ui(is_on, |ui| {
+                    self.draw_boot_menu(ui);
+                });
+
+                ui.add_enabled_ui(is_on, |ui| {
+                    self.draw_state_menu(ui);
+                });
+
+                ui.add_enabled_ui(is_on, |ui| {
+                    self.draw_replay_menu(ui);
+                });
+
                 ui.add_enabled_ui(is_on, |ui| {
                     if ui.button("⟲ CTRL-ALT-DEL").clicked() {
                         self.event_queue.send(GuiEvent::CtrlAltDel);
@@ -160,6 +199,13 @@ impl GuiState {
                     }
                 });
 
+                ui.add_enabled_ui(is_on, |ui| {
+                    if ui.button("📋 Paste Text").clicked() {
+                        self.event_queue.send(GuiEvent::PasteClipboard);
+                        ui.close_menu();
+                    }
+                });
+
                 ui.add_enabled_ui(is_on, |ui| {
                     if ui.button("🔌 Power off").clicked() {
                         self.event_queue.send(GuiEvent::MachineStateChange(MachineState::Off));
@@ -176,6 +222,24 @@ impl GuiState {
                 if ui.button("⟲ Rescan Media Folders").clicked() {
                     self.event_queue.send(GuiEvent::RescanMediaFolders);
                 }
+                self.workspace_window_open_button(ui, GuiWindow::MediaBrowser, true);
+
+                if ui
+                    .checkbox(
+                        &mut self.get_option_mut(GuiBoolean::ShowDriveStatus),
+                        "Show Drive Status",
+                    )
+                    .on_hover_text("Show activity lights and mounted images for each drive.")
+                    .clicked()
+                {
+                    let new_opt = self.get_option(GuiBoolean::ShowDriveStatus).unwrap();
+
+                    self.event_queue.send(GuiEvent::VariableChanged(
+                        GuiVariableContext::Global,
+                        GuiVariable::Bool(GuiBoolean::ShowDriveStatus, new_opt),
+                    ));
+                    ui.close_menu();
+                }
 
                 for i in 0..self.floppy_drives.len() {
                     self.draw_floppy_menu(ui, i);
@@ -189,6 +253,14 @@ impl GuiState {
                     self.draw_cart_menu(ui, i);
                 }
 
+                if self.cassette.is_some() {
+                    self.draw_cassette_menu(ui);
+                }
+
+                if self.cdrom.is_some() {
+                    self.draw_cdrom_menu(ui);
+                }
+
                 if ui.button("🖹 Create new VHD...").clicked() {
                     *self.window_flag(GuiWindow::VHDCreator) = true;
                     ui.close_menu();
@@ -295,6 +367,17 @@ impl GuiState {
                             ui.close_menu();
                         }
                     });
+
+                    ui.menu_button("Opcode Profiler", |ui| {
+                        if ui.button("⏺ Start Profiling").clicked() {
+                            self.event_queue.send(GuiEvent::StartOpcodeProfiling);
+                            ui.close_menu();
+                        }
+                        if ui.button("⏹ Stop Profiling and Save").clicked() {
+                            self.event_queue.send(GuiEvent::StopOpcodeProfiling);
+                            ui.close_menu();
+                        }
+                    });
                 });
 
                 ui.menu_button("Memory", |ui| {
@@ -350,6 +433,12 @@ impl GuiState {
                      */
                 });
 
+                if self.scripts.is_some() {
+                    ui.menu_button("Scripts", |ui| {
+                        self.draw_script_menu(ui);
+                    });
+                }
+
                 if ui
                     .checkbox(&mut self.get_option_mut(GuiBoolean::ShowBackBuffer), "Show Back Buffer")
                     .clicked()
@@ -403,6 +492,39 @@ impl GuiState {
                 });
             });
 
+            ui.menu_button("Create new image", |ui| {
+                let mut sizes: Vec<usize> = DISK_FORMATS.keys().copied().collect();
+                sizes.sort();
+                for size in sizes {
+                    let fmt = &DISK_FORMATS[&size];
+                    let label = format!(
+                        "{} ({} cylinders, {} heads, {} sectors)",
+                        fmt.name,
+                        fmt.chs.c(),
+                        fmt.chs.h(),
+                        fmt.chs.s()
+                    );
+                    if ui.button(label).clicked() {
+                        self.event_queue.send(GuiEvent::CreateFloppy(drive_idx, size));
+                        ui.close_menu();
+                    }
+                }
+            });
+
+            ui.menu_button("Mount directory", |ui| {
+                if self.floppy_directories.is_empty() {
+                    ui.add_enabled(false, egui::Button::new("<No directories>"))
+                        .on_disabled_hover_text("Add directories to the 'floppy_dir' path to mount them.");
+                }
+                for (dir_idx, name) in self.floppy_directories.iter().enumerate() {
+                    if ui.button(format!("📁 {}", name)).clicked() {
+                        self.event_queue
+                            .send(GuiEvent::MountFloppyDirectory(drive_idx, dir_idx));
+                        ui.close_menu();
+                    }
+                }
+            });
+
             ui.horizontal(|ui| {
                 if let Some(floppy_name) = &self.floppy_drives[drive_idx].filename() {
                     if ui.button(format!("Eject image: {}", floppy_name)).clicked() {
@@ -416,8 +538,22 @@ impl GuiState {
 
             ui.horizontal(|ui| {
                 if let Some(floppy_name) = &self.floppy_drives[drive_idx].filename() {
+                    let modified = if self.floppy_drives[drive_idx].modified {
+                        "*"
+                    }
+                    else {
+                        ""
+                    };
                     ui.add_enabled_ui(!self.floppy_drives[drive_idx].write_protected, |ui| {
-                        if ui.button(format!("Save image: {}", floppy_name)).clicked() {
+                        if self.floppy_drives[drive_idx].directory {
+                            if ui
+                                .button(format!("Write files to directory: {}{}", floppy_name, modified))
+                                .clicked()
+                            {
+                                self.event_queue.send(GuiEvent::SyncFloppyDirectory(drive_idx));
+                            }
+                        }
+                        else if ui.button(format!("Save image: {}{}", floppy_name, modified)).clicked() {
                             if let Some(floppy_idx) = self.floppy_drives[drive_idx].selected_idx {
                                 self.event_queue.send(GuiEvent::SaveFloppy(drive_idx, floppy_idx));
                             }
@@ -438,6 +574,23 @@ impl GuiState {
                     self.floppy_drives[drive_idx].write_protected,
                 ));
             }
+
+            let write_back_label = if self.floppy_drives[drive_idx].directory {
+                "Write Changes to Directory"
+            }
+            else {
+                "Write Changes to Image File"
+            };
+            if ui
+                .checkbox(&mut self.floppy_drives[drive_idx].write_back, write_back_label)
+                .on_hover_text("When unchecked, changes are kept in memory until the image is saved.")
+                .changed()
+            {
+                self.event_queue.send(GuiEvent::SetFloppyWriteBack(
+                    drive_idx,
+                    self.floppy_drives[drive_idx].write_back,
+                ));
+            }
         });
         ui.end_row();
     }
@@ -473,6 +626,36 @@ impl GuiState {
                         self.event_queue.send(GuiEvent::DetachVHD(drive_idx));
                     }
                 });
+
+                ui.separator();
+
+                if ui
+                    .checkbox(&mut self.hdds[drive_idx].overlay, "Copy-on-Write Overlay")
+                    .on_hover_text(
+                        "Keep changes in a separate .cow file next to the image, leaving the image untouched.\n\
+                        Takes effect when an image is loaded.",
+                    )
+                    .changed()
+                {
+                    self.event_queue
+                        .send(GuiEvent::SetHddOverlay(drive_idx, self.hdds[drive_idx].overlay));
+                }
+
+                if let Some(sectors) = self.hdds[drive_idx].overlay_sectors {
+                    ui.add_enabled_ui(sectors > 0, |ui| {
+                        if ui
+                            .button(format!("Commit overlay to image ({} sectors)", sectors))
+                            .clicked()
+                        {
+                            self.event_queue.send(GuiEvent::CommitHddOverlay(drive_idx));
+                            ui.close_menu();
+                        }
+                        if ui.button("Discard overlay").clicked() {
+                            self.event_queue.send(GuiEvent::DiscardHddOverlay(drive_idx));
+                            ui.close_menu();
+                        }
+                    });
+                }
             });
         });
     }
@@ -500,6 +683,218 @@ impl GuiState {
         });
     }
 
+    pub fn draw_cassette_menu(&mut self, ui: &mut egui::Ui) {
+        let (filename, status) = match &self.cassette {
+            Some(cassette) => (cassette.filename(), cassette.status.clone()),
+            None => return,
+        };
+        let format_time = |secs: f64| format!("{}:{:02}", secs as u32 / 60, secs as u32 % 60);
+
+        ui.menu_button("📼 Cassette", |ui| {
+            ui.menu_button("Insert tape", |ui| {
+                self.tape_tree_menu.draw(ui, 0, &mut |image_idx| {
+                    self.event_queue.send(GuiEvent::LoadCassette(image_idx));
+                });
+            });
+            if ui.button("Insert blank tape").clicked() {
+                self.event_queue.send(GuiEvent::CreateCassette);
+                ui.close_menu();
+            }
+
+            ui.separator();
+            match &filename {
+                Some(name) => {
+                    ui.label(format!("Tape: {}", name));
+                    ui.label(format!(
+                        "{:?}{} {} / {}",
+                        status.state,
+                        if status.motor { " (motor on)" } else { "" },
+                        format_time(status.position),
+                        format_time(status.length)
+                    ));
+                }
+                None => {
+                    ui.label("Tape: <No Tape>");
+                }
+            }
+
+            ui.add_enabled_ui(filename.is_some(), |ui| {
+                ui.horizontal(|ui| {
+                    for (label, command, state) in [
+                        ("▶ Play", CassetteCommand::Play, Some(CassetteState::Playing)),
+                        ("⏺ Record", CassetteCommand::Record, Some(CassetteState::Recording)),
+                        ("⏹ Stop", CassetteCommand::Stop, None),
+                        ("⏪ Rewind", CassetteCommand::Rewind, None),
+                    ] {
+                        let button = egui::Button::new(label).selected(state == Some(status.state));
+                        if ui.add(button).clicked() {
+                            self.event_queue.send(GuiEvent::CassetteControl(command));
+                        }
+                    }
+                });
+
+                let modified = if status.modified { "*" } else { "" };
+                if ui.button(format!("💾 Save tape{}", modified)).clicked() {
+                    self.event_queue.send(GuiEvent::SaveCassette);
+                    ui.close_menu();
+                }
+                if ui.button("Eject tape").clicked() {
+                    self.event_queue.send(GuiEvent::EjectCassette);
+                    ui.close_menu();
+                }
+            });
+        });
+    }
+
+    /// Draw a menu to choose the boot device and reboot from it. The name of the image in each drive is shown,
+    /// so that a mounted image can be booted without moving it to drive A:.
+    pub fn draw_boot_menu(&mut self, ui: &mut egui::Ui) {
+        let image_name = |path: &Option<PathBuf>| match path.as_ref().and_then(|path| path.file_name()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => "<empty>".to_string(),
+        };
+
+        let mut devices = Vec::new();
+        if let Some(drive) = self.floppy_drives.first() {
+            devices.push((BootDevice::FloppyA, format!("A: {}", image_name(&drive.selected_path))));
+        }
+        if let Some(drive) = self.floppy_drives.get(1) {
+            devices.push((BootDevice::FloppyB, format!("B: {}", image_name(&drive.selected_path))));
+        }
+        if let Some(hdd) = self.hdds.first() {
+            devices.push((BootDevice::HardDisk, format!("C: {}", image_name(&hdd.selected_path))));
+        }
+
+        ui.menu_button("⟲ Reboot from", |ui| {
+            for (device, label) in devices {
+                if ui.radio(self.boot_device == device, label).clicked() {
+                    self.event_queue.send(GuiEvent::SetBootDevice(device));
+                    self.event_queue
+                        .send(GuiEvent::MachineStateChange(MachineState::Rebooting));
+                    ui.close_menu();
+                }
+            }
+        });
+    }
+
+    pub fn draw_state_menu(&mut self, ui: &mut egui::Ui) {
+        let slot_label = |slot: usize, saved: &Option<String>| match saved {
+            Some(time) => format!("Slot {}: {}", slot + 1, time),
+            None => format!("Slot {}: <empty>", slot + 1),
+        };
+
+        ui.menu_button("💾 Save State", |ui| {
+            for (slot, saved) in self.state_slots.iter().enumerate() {
+                if ui.button(slot_label(slot, saved)).clicked() {
+                    self.event_queue.send(GuiEvent::SaveState(slot));
+                    ui.close_menu();
+                }
+            }
+        });
+
+        ui.menu_button("📂 Load State", |ui| {
+            for (slot, saved) in self.state_slots.iter().enumerate() {
+                ui.add_enabled_ui(saved.is_some(), |ui| {
+                    if ui.button(slot_label(slot, saved)).clicked() {
+                        self.event_queue.send(GuiEvent::LoadState(slot));
+                        ui.close_menu();
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn draw_replay_menu(&mut self, ui: &mut egui::Ui) {
+        match self.replay_status {
+            ReplayStatus::Idle => {
+                if ui.button("⏺ Record Input").clicked() {
+                    self.event_queue.send(GuiEvent::StartReplayRecording);
+                    ui.close_menu();
+                }
+                ui.add_enabled_ui(!self.replays.is_empty(), |ui| {
+                    ui.menu_button("▶ Play Recording", |ui| {
+                        for name in &self.replays {
+                            if ui.button(name).clicked() {
+                                self.event_queue.send(GuiEvent::PlayReplay(name.clone()));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+            }
+            ReplayStatus::Recording { events } => {
+                if ui.button(format!("⏹ Stop Recording ({} events)", events)).clicked() {
+                    self.event_queue.send(GuiEvent::StopReplayRecording);
+                    ui.close_menu();
+                }
+            }
+            ReplayStatus::Playing { elapsed, length } => {
+                let percent = elapsed.saturating_mul(100) / length.max(1);
+                if ui.button(format!("⏹ Stop Playback ({}%)", percent.min(100))).clicked() {
+                    self.event_queue.send(GuiEvent::StopReplayPlayback);
+                    ui.close_menu();
+                }
+            }
+        }
+    }
+
+    pub fn draw_script_menu(&mut self, ui: &mut egui::Ui) {
+        let scripts = match &self.scripts {
+            Some(scripts) => scripts,
+            None => return,
+        };
+
+        if scripts.is_empty() {
+            ui.add_enabled(false, egui::Button::new("<No scripts>"))
+                .on_disabled_hover_text("Add .rhai scripts to the 'script' path to run them.");
+        }
+        for name in scripts {
+            if ui.button(format!("▶ {}", name)).clicked() {
+                self.event_queue.send(GuiEvent::RunScript(name.clone()));
+                ui.close_menu();
+            }
+        }
+
+        ui.separator();
+        for name in &self.running_scripts {
+            ui.label(format!("⚙ {}", name));
+        }
+        ui.add_enabled_ui(!self.running_scripts.is_empty(), |ui| {
+            if ui.button("⏹ Stop All Scripts").clicked() {
+                self.event_queue.send(GuiEvent::StopScripts);
+                ui.close_menu();
+            }
+        });
+    }
+
+    pub fn draw_cdrom_menu(&mut self, ui: &mut egui::Ui) {
+        let filename = match &self.cdrom {
+            Some(cdrom) => cdrom.filename(),
+            None => return,
+        };
+
+        ui.menu_button("💿 CD-ROM", |ui| {
+            ui.menu_button("Insert disc", |ui| {
+                self.cdrom_tree_menu.draw(ui, 0, &mut |image_idx| {
+                    self.event_queue.send(GuiEvent::LoadCdrom(image_idx));
+                });
+            });
+
+            ui.separator();
+            match &filename {
+                Some(name) => ui.label(format!("Disc: {}", name)),
+                None => ui.label("Disc: <No Disc>"),
+            };
+
+            ui.add_enabled_ui(filename.is_some(), |ui| {
+                if ui.button("Eject disc").clicked() {
+                    self.event_queue.send(GuiEvent::EjectCdrom);
+                    ui.close_menu();
+                }
+            });
+        });
+    }
+
     pub fn draw_display_menu(&mut self, ui: &mut egui::Ui, display_idx: usize) {
         let ctx = GuiVariableContext::Display(display_idx);
 
@@ -638,7 +1033,35 @@ impl GuiState {
         ui.separator();
 
         if ui.button("🖼 Take Screenshot").clicked() {
-            self.event_queue.send(GuiEvent::TakeScreenshot(display_idx));
+            self.event_queue
+                .send(GuiEvent::TakeScreenshot(display_idx, ScreenshotType::Rendered));
+            ui.close_menu();
+        };
+        ui.menu_button("🖼 Other Screenshots", |ui| {
+            if ui.button("Raw Framebuffer").clicked() {
+                self.event_queue
+                    .send(GuiEvent::TakeScreenshot(display_idx, ScreenshotType::Raw));
+                ui.close_menu();
+            };
+            if ui.button("Display Output (with shaders)").clicked() {
+                self.event_queue
+                    .send(GuiEvent::TakeScreenshot(display_idx, ScreenshotType::Display));
+                ui.close_menu();
+            };
+        });
+
+        ui.separator();
+
+        if ui.button("⏺ Start Recording Video").clicked() {
+            self.event_queue.send(GuiEvent::StartRecordingVideo(display_idx));
+            ui.close_menu();
+        };
+        if ui.button("⏹ Stop Recording Video").clicked() {
+            self.event_queue.send(GuiEvent::StopRecordingVideo);
+            ui.close_menu();
+        };
+        if ui.button("🎞 Capture or Finish Clip (APNG)").clicked() {
+            self.event_queue.send(GuiEvent::CaptureClip(display_idx));
             ui.close_menu();
         };
     }
@@ -49,8 +49,11 @@ use frontend_common::{
 };
 use marty_core::{
     device_traits::videocard::{DisplayApertureDesc, VideoCardState, VideoCardStateEntry},
-    devices::{pit::PitDisplayState, serial::SerialPortDescriptor},
+    device_types::drive_status::DriveStatus,
+    devices::{cassette::CassetteStatus, pit::PitDisplayState, serial::SerialPortDescriptor},
     machine::{ExecutionControl, MachineState},
+    machine_types::BootDevice,
+    replay::ReplayStatus,
 };
 use serde::{Deserialize, Serialize};
 use serialport::SerialPortInfo;
@@ -65,6 +68,7 @@ use std::{
 use strum::IntoEnumIterator;
 
 use crate::{
+    drive_status::DriveIndicator,
     widgets::file_tree_menu::FileTreeMenu,
     windows::{
         about::AboutDialog,
@@ -80,6 +84,7 @@ use crate::{
         instruction_history_viewer::InstructionHistoryControl,
         io_stats_viewer::IoStatsViewerControl,
         ivt_viewer::IvtViewerControl,
+        media_browser::MediaBrowser,
         memory_viewer::MemoryViewerControl,
         performance_viewer::PerformanceViewerControl,
         pic_viewer::PicViewerControl,
@@ -87,6 +92,7 @@ use crate::{
         ppi_viewer::PpiViewerControl,
         scaler_adjust::ScalerAdjustControl,
         serial_viewer::SerialViewerControl,
+        sound_mixer::SoundMixerControl,
         text_mode_viewer::TextModeViewer,
         vhd_creator::VhdCreator,
     },
@@ -97,6 +103,10 @@ pub struct GuiFloppyDriveInfo {
     pub(crate) selected_idx: Option<usize>,
     pub(crate) selected_path: Option<PathBuf>,
     pub(crate) write_protected: bool,
+    pub(crate) write_back: bool,
+    pub(crate) modified: bool,
+    pub(crate) directory: bool, // A host directory is mounted instead of an image
+    pub(crate) indicator: DriveIndicator,
 }
 
 impl GuiFloppyDriveInfo {
@@ -113,6 +123,9 @@ pub struct GuiHddInfo {
     pub(crate) selected_idx: Option<usize>,
     pub(crate) selected_path: Option<PathBuf>,
     pub(crate) write_protected: bool,
+    pub(crate) overlay: bool,
+    pub(crate) overlay_sectors: Option<usize>,
+    pub(crate) indicator: DriveIndicator,
 }
 
 impl GuiHddInfo {
@@ -139,6 +152,33 @@ impl GuiCartInfo {
     }
 }
 
+pub struct GuiCassetteInfo {
+    pub(crate) selected_idx: Option<usize>,
+    pub(crate) selected_path: Option<PathBuf>,
+    pub(crate) status: CassetteStatus,
+}
+
+impl GuiCassetteInfo {
+    pub fn filename(&self) -> Option<String> {
+        self.selected_path
+            .as_ref()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+    }
+}
+
+pub struct GuiCdromInfo {
+    pub(crate) selected_idx:  Option<usize>,
+    pub(crate) selected_path: Option<PathBuf>,
+}
+
+impl GuiCdromInfo {
+    pub fn filename(&self) -> Option<String> {
+        self.selected_path
+            .as_ref()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct WorkspaceWindowState {
     pub open: bool,
@@ -189,8 +229,24 @@ pub struct GuiState {
 
     // Media Images
     pub(crate) floppy_drives: Vec<GuiFloppyDriveInfo>,
+    pub(crate) floppy_directories: Vec<String>,
     pub(crate) hdds: Vec<GuiHddInfo>,
     pub(crate) carts: Vec<GuiCartInfo>,
+    pub(crate) cassette: Option<GuiCassetteInfo>,
+    pub(crate) cdrom: Option<GuiCdromInfo>,
+    pub(crate) boot_device: BootDevice,
+
+    // Save state slots. Each entry holds the time the slot was saved, if it is in use.
+    pub(crate) state_slots: Vec<Option<String>>,
+
+    // Input recordings. The list holds the names of the recordings that can be played back.
+    pub(crate) replay_status: ReplayStatus,
+    pub(crate) replays: Vec<String>,
+
+    // Scripts. The list holds the names of the scripts that can be run, or None if scripting is
+    // not available.
+    pub(crate) scripts: Option<Vec<String>>,
+    pub(crate) running_scripts: Vec<String>,
 
     // VHD Images
     pub(crate) vhd_names: Vec<OsString>,
@@ -233,10 +289,14 @@ pub struct GuiState {
     pub vhd_creator: VhdCreator,
     pub text_mode_viewer: TextModeViewer,
     pub call_stack_viewer: CallStackViewer,
+    pub sound_mixer: SoundMixerControl,
+    pub media_browser: MediaBrowser,
 
     pub floppy_tree_menu: FileTreeMenu,
     pub hdd_tree_menu:    FileTreeMenu,
     pub cart_tree_menu:   FileTreeMenu,
+    pub tape_tree_menu:   FileTreeMenu,
+    pub cdrom_tree_menu:  FileTreeMenu,
     //pub(crate) global_zoom: f32,
 }
 
@@ -290,6 +350,8 @@ impl GuiState {
             (GuiBoolean::TurboButton, false),
             (GuiBoolean::ShowBackBuffer, false),
             (GuiBoolean::ShowRasterPosition, true),
+            (GuiBoolean::LightPen, false),
+            (GuiBoolean::ShowDriveStatus, true),
             //(GuiBoolean::EnableSnow, true),
         ]
         .into();
@@ -319,8 +381,17 @@ impl GuiState {
             scaler_presets: Vec::new(),
 
             floppy_drives: Vec::new(),
+            floppy_directories: Vec::new(),
             hdds: Vec::new(),
             carts: Vec::new(),
+            cassette: None,
+            cdrom: None,
+            boot_device: BootDevice::default(),
+            state_slots: Vec::new(),
+            replay_status: ReplayStatus::Idle,
+            replays: Vec::new(),
+            scripts: None,
+            running_scripts: Vec::new(),
             vhd_names: Vec::new(),
 
             serial_ports: Vec::new(),
@@ -357,11 +428,15 @@ impl GuiState {
             device_control: DeviceControl::new(),
             vhd_creator: VhdCreator::new(),
             text_mode_viewer: TextModeViewer::new(),
+            sound_mixer: SoundMixerControl::new(),
+            media_browser: MediaBrowser::new(),
             call_stack_viewer: CallStackViewer::new(),
 
             floppy_tree_menu: FileTreeMenu::new(),
             hdd_tree_menu: FileTreeMenu::new(),
             cart_tree_menu: FileTreeMenu::new(),
+            tape_tree_menu: FileTreeMenu::new(),
+            cdrom_tree_menu: FileTreeMenu::new(),
             //global_zoom: 1.0,
         }
     }
@@ -451,6 +526,10 @@ impl GuiState {
                 selected_idx: None,
                 selected_path: None,
                 write_protected: true,
+                write_back: false,
+                modified: false,
+                directory: false,
+                indicator: Default::default(),
             });
         }
     }
@@ -459,6 +538,21 @@ impl GuiState {
         self.floppy_drives[drive].write_protected = state;
     }
 
+    pub fn set_floppy_write_back(&mut self, drive: usize, state: bool) {
+        self.floppy_drives[drive].write_back = state;
+    }
+
+    pub fn floppy_write_back(&self, drive: usize) -> bool {
+        self.floppy_drives.get(drive).is_some_and(|d| d.write_back)
+    }
+
+    /// Set whether the disk in the specified drive has unsaved changes.
+    pub fn set_floppy_modified(&mut self, drive: usize, state: bool) {
+        if let Some(d) = self.floppy_drives.get_mut(drive) {
+            d.modified = state;
+        }
+    }
+
     pub fn set_floppy_tree(&mut self, tree: PathTreeNode) {
         self.floppy_tree_menu.set_root(tree);
     }
@@ -466,6 +560,23 @@ impl GuiState {
     pub fn set_floppy_selection(&mut self, drive: usize, idx: Option<usize>, name: Option<PathBuf>) {
         self.floppy_drives[drive].selected_idx = idx;
         self.floppy_drives[drive].selected_path = name;
+        self.floppy_drives[drive].modified = false;
+        self.floppy_drives[drive].directory = false;
+    }
+
+    /// Show the specified drive as holding a disk built from the named host directory.
+    pub fn set_floppy_directory_selection(&mut self, drive: usize, name: PathBuf) {
+        self.set_floppy_selection(drive, None, Some(name));
+        self.floppy_drives[drive].directory = true;
+    }
+
+    /// Set the list of host directories that can be mounted as floppy disks.
+    pub fn set_floppy_directories(&mut self, names: Vec<String>) {
+        self.floppy_directories = names;
+    }
+
+    pub fn get_floppy_selection(&self, drive: usize) -> Option<usize> {
+        self.floppy_drives.get(drive).and_then(|d| d.selected_idx)
     }
 
     pub fn set_hdds(&mut self, drivect: usize) {
@@ -476,6 +587,9 @@ impl GuiState {
                 selected_idx: None,
                 selected_path: None,
                 write_protected: true,
+                overlay: false,
+                overlay_sectors: None,
+                indicator: Default::default(),
             });
         }
     }
@@ -489,6 +603,37 @@ impl GuiState {
         self.hdds[drive].selected_path = name;
     }
 
+    pub fn set_hdd_overlay(&mut self, drive: usize, state: bool) {
+        if let Some(d) = self.hdds.get_mut(drive) {
+            d.overlay = state;
+        }
+    }
+
+    pub fn hdd_overlay(&self, drive: usize) -> bool {
+        self.hdds.get(drive).is_some_and(|d| d.overlay)
+    }
+
+    /// Set the number of sectors in the overlay attached to the drive's image, or None if it has no overlay.
+    pub fn set_hdd_overlay_sectors(&mut self, drive: usize, sectors: Option<usize>) {
+        if let Some(d) = self.hdds.get_mut(drive) {
+            d.overlay_sectors = sectors;
+        }
+    }
+
+    /// Update the activity indicator of the specified floppy drive.
+    pub fn set_floppy_drive_status(&mut self, drive: usize, status: DriveStatus) {
+        if let Some(d) = self.floppy_drives.get_mut(drive) {
+            d.indicator.update(status);
+        }
+    }
+
+    /// Update the activity indicator of the specified hard disk. A status of None means no image is mounted.
+    pub fn set_hdd_status(&mut self, drive: usize, status: Option<DriveStatus>) {
+        if let Some(d) = self.hdds.get_mut(drive) {
+            d.indicator.update(status.unwrap_or_default());
+        }
+    }
+
     pub fn set_cart_slots(&mut self, slotct: usize) {
         self.carts.clear();
         for idx in 0..slotct {
@@ -509,6 +654,82 @@ impl GuiState {
         self.cart_tree_menu.set_root(tree);
     }
 
+    /// Set whether the machine has a cassette interface.
+    pub fn set_cassette(&mut self, present: bool) {
+        self.cassette = present.then(|| GuiCassetteInfo {
+            selected_idx: None,
+            selected_path: None,
+            status: CassetteStatus::default(),
+        });
+    }
+
+    pub fn set_cassette_selection(&mut self, idx: Option<usize>, name: Option<PathBuf>) {
+        if let Some(cassette) = &mut self.cassette {
+            cassette.selected_idx = idx;
+            cassette.selected_path = name;
+        }
+    }
+
+    pub fn set_cassette_status(&mut self, status: CassetteStatus) {
+        if let Some(cassette) = &mut self.cassette {
+            cassette.status = status;
+        }
+    }
+
+    pub fn set_cassette_tree(&mut self, tree: PathTreeNode) {
+        self.tape_tree_menu.set_root(tree);
+    }
+
+    pub fn set_boot_device(&mut self, device: BootDevice) {
+        self.boot_device = device;
+    }
+
+    /// Set the save state slots shown in the Machine menu. Each entry is a description of when the
+    /// slot was saved, or None if the slot is empty.
+    pub fn set_state_slots(&mut self, slots: Vec<Option<String>>) {
+        self.state_slots = slots;
+    }
+
+    /// Set the status of input recording or playback, shown in the Machine menu.
+    pub fn set_replay_status(&mut self, status: ReplayStatus) {
+        self.replay_status = status;
+    }
+
+    /// Set the names of the input recordings that can be played back from the Machine menu.
+    pub fn set_replays(&mut self, replays: Vec<String>) {
+        self.replays = replays;
+    }
+
+    /// Set the names of the scripts that can be run from the Debug menu. The menu is only shown if
+    /// a list has been set.
+    pub fn set_scripts(&mut self, scripts: Option<Vec<String>>) {
+        self.scripts = scripts;
+    }
+
+    /// Set the names of the scripts that are currently running.
+    pub fn set_running_scripts(&mut self, scripts: Vec<String>) {
+        self.running_scripts = scripts;
+    }
+
+    /// Set whether the machine has a CD-ROM drive.
+    pub fn set_cdrom(&mut self, present: bool) {
+        self.cdrom = present.then_some(GuiCdromInfo {
+            selected_idx:  None,
+            selected_path: None,
+        });
+    }
+
+    pub fn set_cdrom_selection(&mut self, idx: Option<usize>, name: Option<PathBuf>) {
+        if let Some(cdrom) = &mut self.cdrom {
+            cdrom.selected_idx = idx;
+            cdrom.selected_path = name;
+        }
+    }
+
+    pub fn set_cdrom_tree(&mut self, tree: PathTreeNode) {
+        self.cdrom_tree_menu.set_root(tree);
+    }
+
     /// Set display apertures for the specified display. Should be called in a loop for each display
     /// target.
     pub fn set_display_apertures(&mut self, display: usize, apertures: Vec<DisplayApertureDesc>) {
@@ -29,7 +29,7 @@
     Main UI drawing code for EGUI.
 */
 
-use crate::{state::GuiState};
+use crate::{state::GuiState, GuiBoolean};
 use egui::Context;
 
 impl GuiState {
@@ -72,6 +72,10 @@ impl GuiState {
 
         self.draw_workspace(ctx);
 
+        if self.get_option(GuiBoolean::ShowDriveStatus).unwrap_or(false) {
+            self.draw_drive_status(ctx);
+        }
+
         /*        egui::Window::new("About")
             .open(self.window_open_flags.get_mut(&GuiWindow::About).unwrap())
             .show(ctx, |ui| {
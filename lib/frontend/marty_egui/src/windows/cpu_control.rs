@@ -32,7 +32,7 @@
 */
 use crate::*;
 use marty_core::{
-    breakpoints::StopWatchData,
+    breakpoints::{StopWatchData, WatchMode},
     cpu_common::CpuAddress,
     machine::{ExecutionControl, ExecutionOperation, ExecutionState},
 };
@@ -43,6 +43,9 @@ pub struct BreakpointSet<'a> {
     pub mem_breakpoint: &'a str,
     pub int_breakpoint: &'a str,
     pub io_breakpoint: &'a str,
+    pub watch_addr: &'a str,
+    pub watch_size: &'a str,
+    pub watch_mode: WatchMode,
     pub sw_start: &'a str,
     pub sw_stop: &'a str,
 }
@@ -53,6 +56,9 @@ pub struct CpuControl {
     mem_breakpoint: String,
     int_breakpoint: String,
     io_breakpoint: String,
+    watch_addr: String,
+    watch_size: String,
+    watch_mode: WatchMode,
     sw_start: String,
     sw_stop: String,
     sw_measurements: String,
@@ -61,6 +67,7 @@ pub struct CpuControl {
     sw_last_duration: String,
     sw_total_duration: String,
     step_over_target: Option<CpuAddress>,
+    command: String,
 }
 
 impl CpuControl {
@@ -71,6 +78,9 @@ impl CpuControl {
             mem_breakpoint: String::new(),
             int_breakpoint: String::new(),
             io_breakpoint: String::new(),
+            watch_addr: String::new(),
+            watch_size: String::new(),
+            watch_mode: WatchMode::default(),
             sw_start: String::new(),
             sw_stop: String::new(),
             sw_measurements: String::new(),
@@ -79,6 +89,7 @@ impl CpuControl {
             sw_last_duration: String::new(),
             sw_total_duration: String::new(),
             step_over_target: None,
+            command: String::new(),
         }
     }
 
@@ -255,8 +266,43 @@ impl CpuControl {
                     events.send(GuiEvent::EditBreakpoint);
                 }
                 ui.end_row();
+
+                ui.label("Watchpoint: ");
+                if ui.text_edit_singleline(&mut self.watch_addr).changed() {
+                    events.send(GuiEvent::EditBreakpoint);
+                }
+                ui.end_row();
+
+                ui.label("Watch Size (hex): ");
+                if ui.text_edit_singleline(&mut self.watch_size).changed() {
+                    events.send(GuiEvent::EditBreakpoint);
+                }
+                ui.end_row();
+
+                ui.label("Watch Mode: ");
+                let old_mode = self.watch_mode;
+                egui::ComboBox::from_id_source("cpu-control-watch-mode")
+                    .selected_text(format!("{:?}", self.watch_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in [WatchMode::Read, WatchMode::Write, WatchMode::Access] {
+                            ui.selectable_value(&mut self.watch_mode, mode, format!("{:?}", mode));
+                        }
+                    });
+                if self.watch_mode != old_mode {
+                    events.send(GuiEvent::EditBreakpoint);
+                }
+                ui.end_row();
             });
 
+        ui.horizontal(|ui| {
+            ui.label("Command: ");
+            let response = ui.text_edit_singleline(&mut self.command);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                events.send(GuiEvent::DebuggerCommand(std::mem::take(&mut self.command)));
+                response.request_focus();
+            }
+        });
+
         egui::CollapsingHeader::new("StopWatch")
             .default_open(true)
             .show(ui, |ui| {
@@ -318,11 +364,21 @@ impl CpuControl {
             mem_breakpoint: &self.mem_breakpoint,
             int_breakpoint: &self.int_breakpoint,
             io_breakpoint: &self.io_breakpoint,
+            watch_addr: &self.watch_addr,
+            watch_size: &self.watch_size,
+            watch_mode: self.watch_mode,
             sw_start: &self.sw_start,
             sw_stop: &self.sw_stop,
         }
     }
 
+    /// Set the watchpoint fields, as if entered by the user. An empty address clears the watchpoint.
+    pub fn set_watchpoint(&mut self, address: &str, size: u32, mode: WatchMode) {
+        self.watch_addr = address.to_string();
+        self.watch_size = format!("{:X}", size);
+        self.watch_mode = mode;
+    }
+
     pub fn set_stopwatch_data(&mut self, data: Vec<StopWatchData>) {
         // Eventually support multiple stopwatches; but for now just use the first one.
         if let Some(data) = data.first() {
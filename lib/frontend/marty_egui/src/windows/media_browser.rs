@@ -0,0 +1,285 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    -------------------------------------------------------------------------
+
+    egui::media_browser.rs
+
+    Browse the floppy and hard disk images in the media folders, showing
+    what was identified on each, and mount them with a single click.
+
+*/
+
+use crate::{
+    state::{GuiFloppyDriveInfo, GuiHddInfo},
+    *,
+};
+use egui_extras::{Column, TableBuilder};
+use frontend_common::media_manager::{MediaEntry, MediaKind};
+use marty_core::disk_info::DiskInfo;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum KindFilter {
+    All,
+    Floppy,
+    HardDisk,
+}
+
+pub struct MediaBrowser {
+    entries: Vec<MediaEntry>,
+    filter: String,
+    kind_filter: KindFilter,
+    bootable_only: bool,
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+    else {
+        format!("{}K", bytes / 1024)
+    }
+}
+
+fn drive_letter(drive: usize) -> String {
+    match drive {
+        0 => "A:".to_string(),
+        1 => "B:".to_string(),
+        _ => format!("{}:", drive),
+    }
+}
+
+impl MediaBrowser {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            filter: String::new(),
+            kind_filter: KindFilter::All,
+            bootable_only: false,
+        }
+    }
+
+    pub fn set_entries(&mut self, entries: Vec<MediaEntry>) {
+        self.entries = entries;
+    }
+
+    pub fn draw(
+        &mut self,
+        ui: &mut egui::Ui,
+        events: &mut GuiEventQueue,
+        floppy_drives: &[GuiFloppyDriveInfo],
+        hdds: &[GuiHddInfo],
+        machine_on: bool,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.add(egui::TextEdit::singleline(&mut self.filter).desired_width(150.0));
+            ui.selectable_value(&mut self.kind_filter, KindFilter::All, "All");
+            ui.selectable_value(&mut self.kind_filter, KindFilter::Floppy, "💾 Floppy");
+            ui.selectable_value(&mut self.kind_filter, KindFilter::HardDisk, "🖴 Hard Disk");
+            ui.checkbox(&mut self.bootable_only, "Bootable only");
+            if ui.button("⟲ Rescan").clicked() {
+                events.send(GuiEvent::RescanMediaFolders);
+            }
+        });
+        ui.separator();
+
+        let filter = self.filter.to_lowercase();
+        let visible: Vec<&MediaEntry> = self
+            .entries
+            .iter()
+            .filter(|e| match self.kind_filter {
+                KindFilter::All => true,
+                KindFilter::Floppy => e.kind == MediaKind::Floppy,
+                KindFilter::HardDisk => e.kind == MediaKind::HardDisk,
+            })
+            .filter(|e| !self.bootable_only || Self::is_bootable(e))
+            .filter(|e| filter.is_empty() || Self::search_text(e).contains(&filter))
+            .collect();
+
+        TableBuilder::new(ui)
+            .striped(true)
+            .auto_shrink([false, true])
+            .max_scroll_height(400.0)
+            .column(Column::initial(180.0).clip(true).resizable(true))
+            .column(Column::auto().resizable(true))
+            .column(Column::auto().resizable(true))
+            .column(Column::initial(100.0).clip(true).resizable(true))
+            .column(Column::initial(120.0).clip(true).resizable(true))
+            .column(Column::remainder())
+            .header(20.0, |mut header| {
+                for title in ["Name", "Format", "Size", "Label", "Operating System", "Mount"] {
+                    header.col(|ui| {
+                        ui.strong(title);
+                    });
+                }
+            })
+            .body(|mut body| {
+                for entry in visible {
+                    body.row(20.0, |mut row| {
+                        row.col(|ui| {
+                            let icon = match entry.kind {
+                                MediaKind::Floppy => "💾",
+                                MediaKind::HardDisk => "🖴",
+                            };
+                            ui.label(format!("{} {}", icon, entry.name))
+                                .on_hover_text(Self::details(entry));
+                        });
+
+                        match &entry.info {
+                            Ok(info) => {
+                                let volume = info.volume.as_ref();
+                                row.col(|ui| {
+                                    ui.label(info.format.to_string());
+                                });
+                                row.col(|ui| {
+                                    ui.label(format_size(info.capacity));
+                                });
+                                row.col(|ui| {
+                                    ui.label(volume.and_then(|v| v.label.clone()).unwrap_or_default());
+                                });
+                                row.col(|ui| {
+                                    ui.label(Self::os_description(info));
+                                });
+                            }
+                            Err(err) => {
+                                row.col(|ui| {
+                                    ui.label(egui::RichText::new("Unreadable").color(ui.visuals().error_fg_color))
+                                        .on_hover_text(err.as_str());
+                                });
+                                row.col(|ui| {
+                                    ui.label(format_size(entry.size));
+                                });
+                                row.col(|_| {});
+                                row.col(|_| {});
+                            }
+                        }
+
+                        row.col(|ui| match entry.kind {
+                            MediaKind::Floppy => {
+                                for (drive, info) in floppy_drives.iter().enumerate() {
+                                    let inserted = !info.directory && info.selected_idx == Some(entry.idx);
+                                    let button = egui::Button::new(drive_letter(drive)).selected(inserted);
+                                    if ui
+                                        .add(button)
+                                        .on_hover_text(format!("Insert into floppy drive {}", drive_letter(drive)))
+                                        .clicked()
+                                        && !inserted
+                                    {
+                                        events.send(GuiEvent::LoadFloppy(drive, entry.idx));
+                                    }
+                                }
+                            }
+                            MediaKind::HardDisk => {
+                                for (drive, info) in hdds.iter().enumerate() {
+                                    let mounted = info.selected_idx == Some(entry.idx);
+                                    let button = egui::Button::new(format!("HD{}", drive)).selected(mounted);
+                                    if ui
+                                        .add_enabled(!machine_on, button)
+                                        .on_hover_text(format!("Mount as hard disk {}", drive))
+                                        .on_disabled_hover_text(
+                                            "Hard disks can only be mounted while the machine is off.",
+                                        )
+                                        .clicked()
+                                        && !mounted
+                                    {
+                                        events.send(GuiEvent::LoadVHD(drive, entry.idx));
+                                    }
+                                }
+                            }
+                        });
+                    });
+                }
+            });
+
+        if self.entries.is_empty() {
+            ui.label("No images found in the media folders.");
+        }
+    }
+
+    fn is_bootable(entry: &MediaEntry) -> bool {
+        entry
+            .info
+            .as_ref()
+            .is_ok_and(|info| info.volume.as_ref().is_some_and(|v| v.bootable))
+    }
+
+    fn search_text(entry: &MediaEntry) -> String {
+        let mut text = entry.name.to_lowercase();
+        if let Ok(info) = &entry.info {
+            if let Some(volume) = &info.volume {
+                for s in [&volume.label, &volume.os].into_iter().flatten() {
+                    text.push(' ');
+                    text.push_str(&s.to_lowercase());
+                }
+            }
+        }
+        text
+    }
+
+    fn os_description(info: &DiskInfo) -> String {
+        match &info.volume {
+            Some(volume) => match (&volume.os, volume.bootable, volume.file_system) {
+                (Some(os), _, _) => os.clone(),
+                (None, true, _) => "Bootable (unknown)".to_string(),
+                (None, false, Some(_)) => "Data disk".to_string(),
+                (None, false, None) => "Unknown".to_string(),
+            },
+            None => "Unformatted".to_string(),
+        }
+    }
+
+    fn details(entry: &MediaEntry) -> String {
+        let mut lines = vec![entry.path.display().to_string()];
+        match &entry.info {
+            Ok(info) => {
+                lines.push(format!("Format: {}", info.format));
+                if let Some((c, h, s)) = info.geometry {
+                    lines.push(format!("Geometry: {} cylinders, {} heads, {} sectors", c, h, s));
+                }
+                lines.push(format!("Capacity: {} bytes", info.capacity));
+                for (i, partition) in info.partitions.iter().enumerate() {
+                    lines.push(format!(
+                        "Partition {}: {}{}, {}",
+                        i + 1,
+                        partition.type_name(),
+                        if partition.active { " (active)" } else { "" },
+                        format_size(partition.sectors as u64 * 512)
+                    ));
+                }
+                if let Some(volume) = &info.volume {
+                    if let Some(oem_name) = &volume.oem_name {
+                        lines.push(format!("OEM name: {}", oem_name));
+                    }
+                    if let Some(file_system) = volume.file_system {
+                        lines.push(format!("File system: {}", file_system));
+                    }
+                }
+            }
+            Err(err) => lines.push(format!("Error: {}", err)),
+        }
+        lines.join("\n")
+    }
+}
@@ -43,6 +43,7 @@ pub mod dma_viewer;
 pub mod instruction_history_viewer;
 pub mod io_stats_viewer;
 pub mod ivt_viewer;
+pub mod media_browser;
 pub mod memory_viewer;
 pub mod performance_viewer;
 pub mod pic_viewer;
@@ -50,6 +51,7 @@ pub mod pit_viewer;
 pub mod ppi_viewer;
 pub mod scaler_adjust;
 pub mod serial_viewer;
+pub mod sound_mixer;
 pub mod text_mode_viewer;
 pub mod vhd_creator;
 pub mod videocard_viewer;
@@ -72,10 +72,14 @@ impl PicViewerControl {
 
                 ui.label(egui::RichText::new("IRR Register").text_style(egui::TextStyle::Monospace));
                 ui.add(egui::TextEdit::singleline(&mut self.state.irr).font(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Priority").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut self.state.priority).font(egui::TextStyle::Monospace));
                 ui.end_row();
 
                 ui.label(egui::RichText::new("IR Lines").text_style(egui::TextStyle::Monospace));
                 ui.add(egui::TextEdit::singleline(&mut self.state.ir).font(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Special Mask").text_style(egui::TextStyle::Monospace));
+                ui.add(egui::TextEdit::singleline(&mut self.state.special_mask).font(egui::TextStyle::Monospace));
                 ui.end_row();
 
                 // Add table header
@@ -31,7 +31,7 @@
 */
 
 use crate::{layouts::MartyLayout, *};
-use frontend_common::display_scaler::{PhosphorType, ScalerFilter, ScalerParams};
+use frontend_common::display_scaler::{CrtMaskType, PhosphorType, ScalerFilter, ScalerParams};
 
 pub struct ScalerAdjustControl {
     params:   Vec<ScalerParams>,
@@ -126,6 +126,18 @@ impl ScalerAdjustControl {
                 }
                 ui.end_row();
 
+                ui.label(egui::RichText::new("Persistence:").text_style(egui::TextStyle::Monospace));
+                if ui
+                    .add(egui::Slider::new(
+                        &mut self.params[self.dt_idx].crt_phosphor_persistence,
+                        0.0..=0.95,
+                    ))
+                    .changed()
+                {
+                    update = true;
+                }
+                ui.end_row();
+
                 ui.label(egui::RichText::new("Gamma:").text_style(egui::TextStyle::Monospace));
                 if ui
                     .add(egui::Slider::new(&mut self.params[self.dt_idx].gamma, 0.0..=2.0))
@@ -144,6 +156,42 @@ impl ScalerAdjustControl {
                 }
                 ui.end_row();
 
+                ui.label(egui::RichText::new("Phosphor Mask:").text_style(egui::TextStyle::Monospace));
+                let previous_mask_selection = self.params[self.dt_idx].crt_mask;
+
+                egui::ComboBox::from_id_source("scaler_mask_select")
+                    .selected_text(format!("{:?}", self.params[self.dt_idx].crt_mask))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.params[self.dt_idx].crt_mask, CrtMaskType::None, "None");
+                        ui.selectable_value(
+                            &mut self.params[self.dt_idx].crt_mask,
+                            CrtMaskType::ApertureGrille,
+                            "Aperture Grille",
+                        );
+                        ui.selectable_value(
+                            &mut self.params[self.dt_idx].crt_mask,
+                            CrtMaskType::ShadowMask,
+                            "Shadow Mask",
+                        );
+                    });
+
+                if self.params[self.dt_idx].crt_mask != previous_mask_selection {
+                    update = true;
+                }
+                ui.end_row();
+
+                ui.label(egui::RichText::new("Mask Intensity:").text_style(egui::TextStyle::Monospace));
+                if ui
+                    .add(egui::Slider::new(
+                        &mut self.params[self.dt_idx].crt_mask_intensity,
+                        0.0..=1.0,
+                    ))
+                    .changed()
+                {
+                    update = true;
+                }
+                ui.end_row();
+
                 ui.label(egui::RichText::new("Barrel Distortion:").text_style(egui::TextStyle::Monospace));
                 if ui
                     .add(egui::Slider::new(
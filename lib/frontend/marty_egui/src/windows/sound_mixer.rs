@@ -0,0 +1,163 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2024 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::sound_mixer.rs
+
+    Implement volume and mute controls for the sound mixer, and display the
+    state of the host audio buffer.
+
+*/
+
+use crate::*;
+use marty_core::{sound::SoundPlayerStats, sound_mixer::SoundSourceInfo};
+
+pub struct SoundMixerControl {
+    sources: Vec<SoundSourceInfo>,
+    master_volume: f32,
+    master_muted: bool,
+    record_per_source: bool,
+    player_stats: Option<SoundPlayerStats>,
+}
+
+impl SoundMixerControl {
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            master_volume: 1.0,
+            master_muted: false,
+            record_per_source: false,
+            player_stats: None,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut GuiEventQueue) {
+        egui::Grid::new("sound_mixer")
+            .num_columns(3)
+            .striped(false)
+            .min_col_width(100.0)
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new("Master").strong());
+                if Self::volume_controls(ui, &mut self.master_volume, &mut self.master_muted) {
+                    events.send(GuiEvent::SetMasterVolume(self.master_volume, self.master_muted));
+                }
+                ui.end_row();
+
+                for (idx, source) in self.sources.iter_mut().enumerate() {
+                    ui.label(&source.name)
+                        .on_hover_text(format!("{}Hz", source.sample_rate as u32));
+                    if Self::volume_controls(ui, &mut source.volume, &mut source.muted) {
+                        events.send(GuiEvent::SetSoundSourceVolume(idx, source.volume, source.muted));
+                    }
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("⏺ Start Recording Audio").clicked() {
+                events.send(GuiEvent::StartRecordingAudio(self.record_per_source));
+            }
+            if ui.button("⏹ Stop Recording Audio").clicked() {
+                events.send(GuiEvent::StopRecordingAudio);
+            }
+        });
+        ui.checkbox(
+            &mut self.record_per_source,
+            "Also record each source to a separate file",
+        );
+
+        ui.separator();
+        match &self.player_stats {
+            Some(stats) => {
+                egui::Grid::new("sound_player_stats")
+                    .num_columns(2)
+                    .striped(true)
+                    .min_col_width(100.0)
+                    .show(ui, |ui| {
+                        ui.label("Output:");
+                        ui.label(format!("{}Hz, {} channel(s)", stats.sample_rate, stats.channels));
+                        ui.end_row();
+
+                        ui.label("Buffer size:");
+                        ui.label(format!("{:.0}ms", stats.buffer_ms));
+                        ui.end_row();
+
+                        ui.label("Device buffer:");
+                        match stats.device_buffer {
+                            Some(frames) => ui.label(format!("{} frames", frames)),
+                            None => ui.label("Default"),
+                        };
+                        ui.end_row();
+
+                        ui.label("Buffered:");
+                        ui.label(format!("{:.1}ms", stats.buffered_ms));
+                        ui.end_row();
+
+                        ui.label("Underruns:");
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{}", stats.underruns));
+                            if ui.button("Reset").clicked() {
+                                events.send(GuiEvent::ResetAudioStats);
+                            }
+                        });
+                        ui.end_row();
+                    });
+            }
+            None => {
+                ui.label("No audio device.");
+            }
+        }
+    }
+
+    /// Draw a volume slider and mute checkbox. Returns true if either was changed.
+    fn volume_controls(ui: &mut egui::Ui, volume: &mut f32, muted: &mut bool) -> bool {
+        let mut percent = *volume * 100.0;
+        let mut changed = false;
+        if ui
+            .add(
+                egui::Slider::new(&mut percent, 0.0..=100.0)
+                    .suffix("%")
+                    .fixed_decimals(0),
+            )
+            .changed()
+        {
+            *volume = percent / 100.0;
+            changed = true;
+        }
+        changed |= ui.checkbox(muted, "Mute").changed();
+        changed
+    }
+
+    pub fn update_player_stats(&mut self, stats: Option<SoundPlayerStats>) {
+        self.player_stats = stats;
+    }
+
+    pub fn update_sources(&mut self, sources: Vec<SoundSourceInfo>, master_volume: (f32, bool)) {
+        self.sources = sources;
+        self.master_volume = master_volume.0;
+        self.master_muted = master_volume.1;
+    }
+}
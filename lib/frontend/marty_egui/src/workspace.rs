@@ -193,6 +193,18 @@ impl GuiState {
                 GuiWindow::TextModeViewer => {
                     self.text_mode_viewer.draw(ui, &mut self.event_queue);
                 }
+                GuiWindow::SoundMixer => {
+                    self.sound_mixer.draw(ui, &mut self.event_queue);
+                }
+                GuiWindow::MediaBrowser => {
+                    self.media_browser.draw(
+                        ui,
+                        &mut self.event_queue,
+                        &self.floppy_drives,
+                        &self.hdds,
+                        self.machine_state.is_on(),
+                    );
+                }
             });
 
             match inner_response_opt {
@@ -40,7 +40,7 @@ use bytemuck::{Pod, Zeroable};
 // Reexport trait items
 pub use frontend_common::{
     color::MartyColor,
-    display_scaler::{DisplayScaler, ScalerEffect, ScalerFilter, ScalerMode, ScalerOption},
+    display_scaler::{CrtMaskType, DisplayScaler, ScalerEffect, ScalerFilter, ScalerMode, ScalerOption},
 };
 
 use ultraviolet::Mat4;
@@ -65,6 +65,10 @@ struct CrtParamUniform {
     contrast: f32,
     mono: u32,
     mono_color: [f32; 4],
+    mask: u32,
+    mask_intensity: f32,
+    pad0: u32,
+    pad1: u32,
 }
 
 #[repr(C)]
@@ -95,6 +99,10 @@ impl Default for CrtParamUniform {
             contrast: 1.0,
             mono: 0,
             mono_color: [1.0, 1.0, 1.0, 1.0],
+            mask: 0,
+            mask_intensity: 0.0,
+            pad0: 0,
+            pad1: 0,
         }
     }
 }
@@ -207,6 +215,8 @@ pub struct MartyScaler {
     corner_radius: f32,
     mono: bool,
     mono_color: wgpu::Color,
+    mask: CrtMaskType,
+    mask_intensity: f32,
     #[allow(dead_code)]
     effect: ScalerEffect,
     #[allow(dead_code)]
@@ -480,6 +490,8 @@ impl MartyScaler {
                 b: 1.0,
                 a: 1.0,
             },
+            mask: CrtMaskType::None,
+            mask_intensity: 0.0,
             crt_params: Default::default(),
         }
     }
@@ -555,6 +567,10 @@ impl MartyScaler {
             contrast: self.contrast,
             mono: self.mono as u32,
             mono_color: MartyColor::from(self.mono_color).into(),
+            mask: self.mask as u32,
+            mask_intensity: self.mask_intensity,
+            pad0: 0,
+            pad1: 0,
         };
 
         let uniform_struct = ScalerOptionsUniform {
@@ -818,6 +834,11 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
                 self.do_scanlines = enabled.unwrap_or(self.do_scanlines);
                 update_uniform = true;
             }
+            ScalerOption::Mask { mask, intensity } => {
+                self.mask = mask;
+                self.mask_intensity = intensity;
+                update_uniform = true;
+            }
             ScalerOption::Effect(_) => {}
         }
 
@@ -877,6 +898,36 @@ impl DisplayScaler<pixels::Pixels> for MartyScaler {
             self.update_uniforms(pixels);
         }
     }
+
+    fn surface_to_texture(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        if self.screen_width == 0 || self.screen_height == 0 {
+            return None;
+        }
+        let matrix = ScalingMatrix::new(
+            self.mode,
+            (self.texture_width as f32, self.texture_height as f32),
+            (self.target_width as f32, self.target_height as f32),
+            (self.screen_width as f32, self.screen_height as f32),
+            self.screen_margin_y as f32,
+        );
+        let m = matrix.transform;
+
+        // Convert to normalized device coordinates and undo the scaling transform to get the
+        // position on the quad, then map to texture coordinates as the vertex shader does.
+        let ndc_x = x / self.screen_width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - y / self.screen_height as f32 * 2.0;
+        let quad_x = (ndc_x - m.cols[3].x) / m.cols[0].x;
+        let quad_y = (ndc_y - m.cols[3].y) / m.cols[1].y;
+        let u = quad_x * 0.5 + 0.5;
+        let v = quad_y * -0.5 + 0.5;
+
+        if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {
+            Some((u, v))
+        }
+        else {
+            None
+        }
+    }
 }
 
 impl ScalingMatrix {
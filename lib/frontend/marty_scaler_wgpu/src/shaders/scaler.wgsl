@@ -56,6 +56,10 @@ struct CrtParamUniform {
     contrast: f32,
     mono: u32,
     mono_color: vec4<f32>,
+    mask: u32,
+    mask_intensity: f32,
+    pad0: u32,
+    pad1: u32,
 };
 
 struct ScalerOptionsUniform {
@@ -185,12 +189,40 @@ fn do_scanlines(color: vec4<f32>, y_coord: f32, lines: u32, intensity: f32) -> v
     return newColor;
 }
 
+// Simulate the phosphor mask of a color CRT. The mask is applied in screen space, with each
+// phosphor triad spanning three pixels.
+// Mask type 1 is an aperture grille (vertical stripes), type 2 is a shadow mask (staggered dots).
+fn do_mask(color: vec4<f32>, frag_pos: vec2<f32>, mask_type: u32, intensity: f32) -> vec4<f32> {
+    let x = u32(frag_pos.x);
+    let y = u32(frag_pos.y);
+
+    var idx = x % 3u;
+    if (mask_type == 2u) {
+        // Offset every other row of triads by half a triad.
+        idx = (x + select(0u, 2u, (y / 2u) % 2u == 1u)) % 3u;
+    }
+
+    let dim = 1.0 - intensity;
+    var mask = vec3<f32>(dim, dim, dim);
+    if (idx == 0u) {
+        mask.r = 1.0;
+    } else if (idx == 1u) {
+        mask.g = 1.0;
+    } else {
+        mask.b = 1.0;
+    }
+
+    // Compensate for the light lost to the mask.
+    let boost = 3.0 / (1.0 + 2.0 * dim);
+    return vec4<f32>(min(color.rgb * mask * boost, vec3<f32>(1.0, 1.0, 1.0)), color.a);
+}
+
 // Fragment shader bindings
 @group(0) @binding(0) var r_tex_color: texture_2d<f32>;
 @group(0) @binding(1) var r_tex_sampler: sampler;
 
 @fragment
-fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+fn fs_main(@location(0) tex_coord: vec2<f32>, @builtin(position) frag_pos: vec4<f32>) -> @location(0) vec4<f32> {
     let curved_tex_coord = apply_crt_curvature(tex_coord);
 
     let is_outside = any(curved_tex_coord < vec2<f32>(0.0, 0.0)) || any(curved_tex_coord > vec2<f32>(1.0, 1.0));
@@ -214,6 +246,9 @@ fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
 
         if (mono != 0u) {
             color = do_monochrome(color, gamma);
+        } else if (scaler_opts.crt_params.mask > 0u) {
+            // Monochrome monitors have no phosphor mask.
+            color = do_mask(color, frag_pos.xy, scaler_opts.crt_params.mask, scaler_opts.crt_params.mask_intensity);
         }
 
         return color;
@@ -52,6 +52,18 @@ impl VideoRenderer {
     ) {
         let render_start = Instant::now();
 
+        if let Some(path) = self.raw_capture_path.take() {
+            match VideoRenderer::save_raw_buf(self.video_type, input_buf, extents, &path) {
+                Ok(_) => {
+                    log::info!("Saved raw framebuffer: {}", path.display());
+                    self.send_event(RendererEvent::ScreenshotSaved);
+                }
+                Err(e) => {
+                    log::error!("Error writing raw framebuffer: {}: {}", path.display(), e)
+                }
+            }
+        }
+
         let do_software_aspect = matches!(self.params.aspect_correction, AspectCorrectionMode::Software);
         let mut screenshot_taken = false;
 
@@ -114,6 +126,25 @@ impl VideoRenderer {
                 extents,
                 RenderBpp::Six,
             ),
+            #[cfg(feature = "vga")]
+            VideoType::VGA => VideoRenderer::draw_vga_direct_u32(
+                first_pass_buf,
+                self.params.render.w,
+                self.params.render.h,
+                input_buf,
+                self.params.aperture,
+                extents,
+            ),
+        }
+
+        if self.phosphor_decay > 0.0 {
+            VideoRenderer::apply_persistence(
+                first_pass_buf,
+                &mut self.persistence_buf,
+                self.params.render.w,
+                self.params.render.h,
+                self.phosphor_decay,
+            );
         }
 
         // Draw raster beam position if provided
@@ -700,4 +731,87 @@ impl VideoRenderer {
             }
         }
     }
+
+    /// Save the entire video field of a card's framebuffer as a PNG. Indexed framebuffers are saved
+    /// as 8-bit grayscale images of the raw color indices. The VGA framebuffer is already RGBA.
+    pub fn save_raw_buf(
+        video_type: VideoType,
+        dbuf: &[u8],
+        extents: &DisplayExtents,
+        path: &Path,
+    ) -> Result<(), image::ImageError> {
+        let bpp = match video_type {
+            #[cfg(feature = "vga")]
+            VideoType::VGA => 4,
+            _ => 1,
+        };
+
+        let w = extents.field_w as usize;
+        let h = std::cmp::min(extents.field_h as usize, dbuf.len() / (extents.row_stride * bpp).max(1));
+
+        let mut packed = Vec::with_capacity(w * h * bpp);
+        for y in 0..h {
+            let row = y * extents.row_stride * bpp;
+            packed.extend_from_slice(&dbuf[row..row + w * bpp]);
+        }
+
+        let color_type = match bpp {
+            4 => image::ColorType::Rgba8,
+            _ => image::ColorType::L8,
+        };
+        image::save_buffer(path, &packed, w as u32, h as u32, color_type)
+    }
+
+    /// Blend a decayed copy of the previous frame into the current frame, to emulate the afterglow
+    /// of long-persistence phosphors. The alpha channel is left untouched.
+    pub fn apply_persistence(frame: &mut [u8], prev: &mut Vec<u8>, w: u32, h: u32, decay: f32) {
+        let len = std::cmp::min(w as usize * h as usize * 4, frame.len());
+
+        if prev.len() != len {
+            // Resolution changed, or first frame. Start over with the current frame.
+            *prev = frame[0..len].to_vec();
+            return;
+        }
+
+        for (i, (f, p)) in frame[0..len].iter_mut().zip(prev.iter_mut()).enumerate() {
+            if i & 0x03 != 3 {
+                let decayed = (*p as f32 * decay) as u8;
+                *f = std::cmp::max(*f, decayed);
+            }
+            *p = *f;
+        }
+    }
+
+    /// Draw the VGA card in Direct Mode.
+    /// The VGA generates a 32-bit RGBA framebuffer of the visible display area, so it only needs to
+    /// be copied into the output buffer. The row stride of the VGA framebuffer is given in pixels.
+    pub fn draw_vga_direct_u32(
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        aperture: DisplayApertureType,
+        extents: &DisplayExtents,
+    ) {
+        let aperture = &extents.apertures[aperture as usize];
+
+        let max_x = std::cmp::min(w, aperture.w) as usize;
+        let max_y = std::cmp::min(h, aperture.h) as usize;
+
+        if ((aperture.y as usize + max_y) * extents.row_stride + aperture.x as usize) * 4 > dbuf.len() {
+            log::warn!(
+                "draw_vga_direct_u32(): extents {}x{} greater than buffer: {}",
+                max_x,
+                max_y,
+                dbuf.len()
+            );
+            return;
+        }
+
+        for y in 0..max_y {
+            let src = ((y + aperture.y as usize) * extents.row_stride + aperture.x as usize) * 4;
+            let dst = y * w as usize * 4;
+            frame[dst..dst + max_x * 4].copy_from_slice(&dbuf[src..src + max_x * 4]);
+        }
+    }
 }
@@ -96,6 +96,8 @@ pub struct RendererConfigParams {
     pub display_aperture: Option<DisplayApertureType>,
     #[serde(default)]
     pub composite: bool,
+    #[serde(default)]
+    pub new_cga: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -223,6 +225,11 @@ pub struct VideoRenderer {
     screenshot_buf: Vec<u8>,
     screenshot_path: Option<std::path::PathBuf>,
     screenshot_requested: bool,
+    raw_capture_path: Option<std::path::PathBuf>,
+
+    // Phosphor persistence
+    phosphor_decay:  f32,
+    persistence_buf: Vec<u8>,
 
     last_render_time: Duration,
     event_queue: VecDeque<RendererEvent>,
@@ -272,6 +279,10 @@ impl VideoRenderer {
             screenshot_buf: Vec::new(),
             screenshot_path: None,
             screenshot_requested: false,
+            raw_capture_path: None,
+
+            phosphor_decay: 0.0,
+            persistence_buf: Vec::new(),
 
             last_render_time: Duration::from_secs(0),
             event_queue: VecDeque::new(),
@@ -292,6 +303,11 @@ impl VideoRenderer {
 
     pub fn set_config_params(&mut self, cfg: &RendererConfigParams) {
         self.composite_enabled = cfg.composite;
+        if self.composite_params.new_cga != cfg.new_cga {
+            let mut composite_params = self.composite_params;
+            composite_params.new_cga = cfg.new_cga;
+            self.cga_direct_param_update(&composite_params);
+        }
 
         if cfg.aspect_correction {
             self.set_aspect_ratio(cfg.aspect_ratio, Some(AspectCorrectionMode::Hardware));
@@ -309,6 +325,7 @@ impl VideoRenderer {
             aspect_ratio: self.aspect_ratio,
             display_aperture: Some(self.params.aperture),
             composite: self.composite_enabled,
+            new_cga: self.composite_params.new_cga,
         }
     }
     pub fn get_params(&self) -> &VideoParams {
@@ -347,6 +364,15 @@ impl VideoRenderer {
         self.params.line_double = state;
     }
 
+    /// Set the fraction of each pixel's brightness retained from one frame to the next, emulating
+    /// long-persistence phosphors. A value of 0.0 disables persistence.
+    pub fn set_phosphor_persistence(&mut self, decay: f32) {
+        self.phosphor_decay = decay.clamp(0.0, 0.95);
+        if self.phosphor_decay == 0.0 {
+            self.persistence_buf = Vec::new();
+        }
+    }
+
     /// Resizes the internal rendering buffer to the specified dimensions, before aspect correction.
     pub fn resize(&mut self, new_dims: VideoDimensions) {
         self.initialized = true;
@@ -543,6 +569,12 @@ impl VideoRenderer {
         self.screenshot_requested = true;
     }
 
+    /// Request that the video card's raw framebuffer be saved to the specified path on the next
+    /// rendering pass.
+    pub fn request_raw_capture(&mut self, path: &Path) {
+        self.raw_capture_path = Some(path.to_path_buf());
+    }
+
     pub fn render_screenshot(&self, frame: &[u8], path: &Path) {
         let frame_slice =
             &frame[0..(self.params.backend.w as usize * self.params.backend.h as usize * std::mem::size_of::<u32>())];